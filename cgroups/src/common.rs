@@ -1,16 +1,29 @@
 use std::{
+    convert::TryFrom,
     fmt::{Debug, Display},
     fs::{self, File},
-    io::{BufRead, BufReader, Write},
-    path::{Path, PathBuf},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::Receiver,
+    thread,
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
 use nix::{
-    sys::statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
+    errno::Errno,
+    sys::{
+        signal::{self, Signal},
+        stat::{self, SFlag},
+        statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
+    },
     unistd::Pid,
 };
-use oci_spec::{FreezerState, LinuxDevice, LinuxDeviceCgroup, LinuxDeviceType, LinuxResources};
+use oci_spec::{
+    FreezerState, LinuxCpu, LinuxDevice, LinuxDeviceCgroup, LinuxDeviceType, LinuxResources,
+};
+use once_cell::sync::OnceCell;
+use procfs::CpuInfo;
 #[cfg(feature = "systemd_cgroups")]
 use systemd::daemon::booted;
 #[cfg(not(feature = "systemd_cgroups"))]
@@ -18,30 +31,250 @@ fn booted() -> Result<bool> {
     bail!("This build does not include the systemd cgroups feature")
 }
 
+use super::error::CgroupError;
+use super::mounts;
 use super::v1;
 use super::v2;
 
 use super::stats::Stats;
+use super::systemd_unit::UnitProperty;
 
 pub const CGROUP_PROCS: &str = "cgroup.procs";
+/// Threads of a cgroup on cgroup v2
+pub const CGROUP_THREADS: &str = "cgroup.threads";
+/// Threads of a cgroup on cgroup v1
+pub const CGROUP_TASKS: &str = "tasks";
+const CGROUP_TYPE: &str = "cgroup.type";
 pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// Number of times the removal of a busy cgroup is attempted
+const REMOVE_ATTEMPTS: u32 = 7;
+// Block devices known to the kernel, named by their device number
+const SYS_DEV_BLOCK: &str = "/sys/dev/block";
+const UID_MAP_PATH: &str = "/proc/self/uid_map";
+
+// The cgroup setup of the host does not change while youki is running
+static CGROUP_ROOT: OnceCell<PathBuf> = OnceCell::new();
+static CGROUP_SETUP: OnceCell<CgroupSetup> = OnceCell::new();
+static IN_USER_NAMESPACE: OnceCell<bool> = OnceCell::new();
 
+/// Manages the cgroup of a container. The methods return a [CgroupError], so
+/// callers can tell invalid resources from missing permissions or kernel
+/// features.
 pub trait CgroupManager {
     /// Adds a task specified by its pid to the cgroup
-    fn add_task(&self, pid: Pid) -> Result<()>;
-    /// Applies resource restrictions to the cgroup
-    fn apply(&self, linux_resources: &LinuxResources) -> Result<()>;
+    fn add_task(&self, pid: Pid) -> Result<(), CgroupError>;
+    /// Creates the cgroup and opens its directory, so processes can be
+    /// created in the cgroup with clone3 instead of being moved into it. None
+    /// is returned if processes have to be added with add_task, which is the
+    /// case on cgroup v1 and for cgroups which are managed by systemd.
+    fn open_cgroup_dir(&self) -> Result<Option<File>, CgroupError> {
+        Ok(None)
+    }
+    /// Applies resource restrictions to the cgroup. It may be applied again to
+    /// change the restrictions of a running container.
+    fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError>;
+    /// Applies changed resource restrictions to the cgroup of a running
+    /// container. If they can not be applied, the previous restrictions are
+    /// applied again, so the cgroup is not left partially updated.
+    fn update(
+        &self,
+        linux_resources: &LinuxResources,
+        previous: &LinuxResources,
+    ) -> Result<(), CgroupError> {
+        if let Err(err) = self.apply(linux_resources) {
+            if let Err(rollback_err) = self.apply(previous) {
+                log::warn!(
+                    "failed to restore the previous resource restrictions: {:?}",
+                    rollback_err
+                );
+            }
+            return Err(err);
+        }
+
+        Ok(())
+    }
     /// Removes the cgroup
-    fn remove(&self) -> Result<()>;
-    // Sets the freezer cgroup to the specified state
-    fn freeze(&self, state: FreezerState) -> Result<()>;
+    fn remove(&self) -> Result<(), CgroupError>;
+    /// Sets the freezer cgroup to the specified state
+    fn freeze(&self, state: FreezerState) -> Result<(), CgroupError>;
+    /// Freezes the processes in the cgroup, so they are not scheduled until
+    /// the cgroup is resumed
+    fn pause(&self) -> Result<(), CgroupError> {
+        self.freeze(FreezerState::Frozen)
+    }
+    /// Thaws the processes of a paused cgroup
+    fn resume(&self) -> Result<(), CgroupError> {
+        self.freeze(FreezerState::Thawed)
+    }
     /// Retrieve statistics for the cgroup
-    fn stats(&self) -> Result<Stats>;
-    // Gets the PIDs inside the cgroup
-    fn get_all_pids(&self) -> Result<Vec<Pid>>;
+    fn stats(&self) -> Result<Stats, CgroupError>;
+    /// Gets the PIDs inside the cgroup
+    fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError>;
+    /// Gets the ids of all threads inside the cgroup
+    fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError>;
+    /// Kills all processes of the cgroup and its descendants at once through
+    /// cgroup.kill. Returns false if the cgroup does not support it, in which
+    /// case the processes have to be killed one by one.
+    fn kill(&self) -> Result<bool, CgroupError> {
+        Ok(false)
+    }
+    /// Checks if the cgroup has been created and not been removed yet
+    fn exists(&self) -> bool;
+    /// Checks if no processes are left in the cgroup. A cgroup which does not
+    /// exist is considered to be empty.
+    fn is_empty(&self) -> Result<bool, CgroupError> {
+        if !self.exists() {
+            return Ok(true);
+        }
+
+        Ok(self.get_all_pids()?.is_empty())
+    }
+    /// Lowers the cpu quota of the cgroup gradually towards the quota of the
+    /// given cpu resources
+    fn ramp_down_cpu(&self, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<(), CgroupError>;
+    /// Returns the directories of the cgroup in the hierarchies it is part of
+    fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError>;
+    /// Watches the cgroup for processes killed by the OOM killer. A message is
+    /// received for every OOM event, the channel is closed when the cgroup is
+    /// removed.
+    fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError>;
 }
 
-#[derive(Debug)]
+/// Directory of a cgroup in one of the mounted hierarchies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupHierarchy {
+    /// Name of the hierarchy below the cgroup root, e.g. "cpu,cpuacct" for a
+    /// v1 hierarchy. The unified hierarchy is mounted at the cgroup root
+    /// itself and has no name.
+    pub name: Option<String>,
+    /// Directory of the cgroup on the host
+    pub path: PathBuf,
+}
+
+/// Lowers a cpu quota in steps over a period of time instead of at once, so
+/// latency sensitive processes are not throttled abruptly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuRampdown {
+    /// Time over which the quota is lowered
+    pub duration: Duration,
+    /// Number of steps in which the quota is lowered
+    pub steps: u32,
+}
+
+impl CpuRampdown {
+    /// Calculates the quotas which are applied one after another to lower the
+    /// current quota to the target quota. Both quotas have to be relative to
+    /// the same period. The last quota is always the target quota.
+    pub fn quotas(&self, current: i64, target: i64) -> Vec<i64> {
+        if target >= current || self.steps <= 1 {
+            return vec![target];
+        }
+
+        let steps = self.steps as i64;
+        (1..=steps)
+            .map(|step| current - (current - target) * step / steps)
+            .collect()
+    }
+
+    /// Applies the quotas with an even delay between them
+    pub(crate) fn run<F>(&self, current: i64, target: i64, mut apply_quota: F) -> Result<()>
+    where
+        F: FnMut(i64) -> Result<()>,
+    {
+        let quotas = self.quotas(current, target);
+        let interval = self.duration / quotas.len() as u32;
+        for (i, quota) in quotas.into_iter().enumerate() {
+            if i > 0 {
+                thread::sleep(interval);
+            }
+
+            log::debug!("ramp down cpu quota to {}", quota);
+            apply_quota(quota)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings of the resources which a controller could not apply, because the
+/// kernel or the cgroup setup of the host does not support them. They are
+/// reported after all other settings of the controller have been applied, so
+/// they can be ignored unless the resources are handled strictly.
+#[derive(Debug, Default)]
+pub struct UnsupportedSettings {
+    settings: Vec<String>,
+}
+
+impl UnsupportedSettings {
+    /// Records a setting which is not supported, together with the reason
+    pub fn add<S: Into<String>>(&mut self, setting: S) {
+        self.settings.push(setting.into());
+    }
+
+    pub fn settings(&self) -> &[String] {
+        &self.settings
+    }
+
+    /// Fails with the unsupported settings, if there are any
+    pub fn check(self) -> Result<()> {
+        if self.settings.is_empty() {
+            return Ok(());
+        }
+
+        Err(self.into())
+    }
+}
+
+impl Display for UnsupportedSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unsupported resource settings: {}",
+            self.settings.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedSettings {}
+
+/// Decides if the result of a controller aborts applying the resources. By
+/// default unsupported settings are only logged, in strict mode they are an
+/// error like any other.
+pub(crate) fn check_controller_result(result: Result<()>, strict: bool) -> Result<()> {
+    match result {
+        Err(err) if !strict => match err.downcast_ref::<UnsupportedSettings>() {
+            Some(unsupported) => {
+                for setting in unsupported.settings() {
+                    log::warn!("{} is not applied", setting);
+                }
+                Ok(())
+            }
+            None => Err(err),
+        },
+        result => result,
+    }
+}
+
+/// Cpu time of all online cpus within the given period, which corresponds to
+/// an unrestricted quota
+pub(crate) fn unrestricted_quota(period: u64) -> i64 {
+    let cpus = CpuInfo::new().map(|info| info.num_cores()).unwrap_or(1);
+    cpus as i64 * period as i64
+}
+
+/// Converts a quota for the given period into the quota for the current
+/// period. Both are given by the user, so an overflow is reported as an error.
+pub(crate) fn scale_quota(quota: i64, period: u64, current_period: u64) -> Result<i64> {
+    let scaled = i128::from(quota) * i128::from(current_period) / i128::from(period);
+    i64::try_from(scaled).with_context(|| {
+        format!(
+            "cpu quota {} for period {} is out of range for period {}",
+            quota, period, current_period
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CgroupSetup {
     Hybrid,
     Legacy,
@@ -102,83 +335,257 @@ pub fn read_cgroup_file<P: AsRef<Path>>(path: P) -> Result<String> {
 ///   an additional unified hierarchy which doesn't have any
 ///   controllers attached. Resource control can purely be achieved
 ///   through the cgroup v1 hierarchy, not through the cgroup v2 hierarchy.
+///
+/// The setup is only determined on the first call.
 pub fn get_cgroup_setup() -> Result<CgroupSetup> {
-    let default_root = Path::new(DEFAULT_CGROUP_ROOT);
-    match default_root.exists() {
-        true => {
-            // If the filesystem is of type cgroup2, the system is in unified mode.
-            // If the filesystem is tmpfs instead the system is either in legacy or
-            // hybrid mode. If a cgroup2 filesystem has been mounted under the "unified"
-            // folder we are in hybrid mode, otherwise we are in legacy mode.
-            let stat = statfs(default_root).with_context(|| {
-                format!(
-                    "failed to stat default cgroup root {}",
-                    &default_root.display()
-                )
-            })?;
-            if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
-                return Ok(CgroupSetup::Unified);
-            }
+    CGROUP_SETUP
+        .get_or_try_init(|| get_cgroup_setup_with_root(&detect_cgroup_root()?))
+        .map(|setup| *setup)
+}
 
-            if stat.filesystem_type() == TMPFS_MAGIC {
-                let unified = Path::new("/sys/fs/cgroup/unified");
-                if Path::new(unified).exists() {
-                    let stat = statfs(unified)
-                        .with_context(|| format!("failed to stat {}", unified.display()))?;
-                    if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
-                        return Ok(CgroupSetup::Hybrid);
-                    }
-                }
+/// Determines the cgroup setup of the system for the cgroup root mounted at
+/// the given path. See [get_cgroup_setup].
+pub fn get_cgroup_setup_with_root(cgroup_root: &Path) -> Result<CgroupSetup> {
+    if !cgroup_root.exists() {
+        bail!("cgroup root {} does not exist", cgroup_root.display());
+    }
+
+    // If the filesystem is of type cgroup2, the system is in unified mode.
+    // If the filesystem is tmpfs instead the system is either in legacy or
+    // hybrid mode. If a cgroup2 filesystem has been mounted under the "unified"
+    // folder we are in hybrid mode, otherwise we are in legacy mode.
+    let stat = statfs(cgroup_root)
+        .with_context(|| format!("failed to stat cgroup root {}", cgroup_root.display()))?;
+    if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
+        return Ok(CgroupSetup::Unified);
+    }
 
-                return Ok(CgroupSetup::Legacy);
+    if stat.filesystem_type() == TMPFS_MAGIC {
+        let unified = cgroup_root.join("unified");
+        if unified.exists() {
+            let stat = statfs(&unified)
+                .with_context(|| format!("failed to stat {}", unified.display()))?;
+            if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
+                return Ok(CgroupSetup::Hybrid);
             }
         }
-        false => bail!("non default cgroup root not supported"),
+
+        return Ok(CgroupSetup::Legacy);
     }
 
     bail!("failed to detect cgroup setup");
 }
 
+/// Finds the mount point of the cgroup root. This is [DEFAULT_CGROUP_ROOT] on
+/// most systems, but some distributions and guests mount the cgroup
+/// filesystems elsewhere, e.g. under /mnt/cgroup2. The root is only detected
+/// on the first call.
+pub fn detect_cgroup_root() -> Result<PathBuf> {
+    CGROUP_ROOT.get_or_try_init(find_cgroup_root).cloned()
+}
+
+fn find_cgroup_root() -> Result<PathBuf> {
+    let default_root = Path::new(DEFAULT_CGROUP_ROOT);
+    if default_root.exists() {
+        return Ok(default_root.to_path_buf());
+    }
+
+    if let Some(mount) = mounts::unified_mount(None)? {
+        return Ok(mount.mount_point.clone());
+    }
+
+    // cgroup v1 hierarchies are mounted below a common directory
+    mounts::v1_mounts(None)?
+        .first()
+        .and_then(|m| m.mount_point.parent())
+        .map(|p| p.to_path_buf())
+        .context("failed to find the cgroup root, no cgroup filesystem is mounted")
+}
+
+/// Checks if the process runs in a user namespace other than the initial user
+/// namespace of the host, e.g. under podman unshare or inside of a rootless
+/// container
+pub fn in_user_namespace() -> bool {
+    *IN_USER_NAMESPACE.get_or_init(|| match fs::read_to_string(UID_MAP_PATH) {
+        Ok(uid_map) => !is_initial_uid_map(&uid_map),
+        Err(err) => {
+            log::debug!("failed to read {}: {}", UID_MAP_PATH, err);
+            false
+        }
+    })
+}
+
+// The initial user namespace maps the whole range of ids to itself
+fn is_initial_uid_map(uid_map: &str) -> bool {
+    let mappings: Vec<Vec<&str>> = uid_map
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .filter(|m: &Vec<&str>| !m.is_empty())
+        .collect();
+    matches!(mappings.as_slice(), [m] if m.as_slice() == ["0", "0", "4294967295"])
+}
+
 pub fn create_cgroup_manager<P: Into<PathBuf>>(
     cgroup_path: P,
     systemd_cgroup: bool,
-) -> Result<Box<dyn CgroupManager>> {
-    let cgroup_setup = get_cgroup_setup()?;
+) -> Result<Box<dyn CgroupManager>, CgroupError> {
+    create_cgroup_manager_with_root(None, cgroup_path, systemd_cgroup)
+}
+
+/// Creates a cgroup manager for a cgroup root which is mounted somewhere else
+/// than [DEFAULT_CGROUP_ROOT]. If no cgroup root is given, it is detected.
+pub fn create_cgroup_manager_with_root<P: Into<PathBuf>>(
+    cgroup_root: Option<&Path>,
+    cgroup_path: P,
+    systemd_cgroup: bool,
+) -> Result<Box<dyn CgroupManager>, CgroupError> {
+    create_cgroup_manager_with_options(
+        cgroup_root,
+        cgroup_path,
+        systemd_cgroup,
+        ManagerOptions::default(),
+    )
+}
+
+/// Options of the cgroup manager of a container, which are not part of the
+/// resources in the spec
+#[derive(Debug, Clone, Default)]
+pub struct ManagerOptions {
+    /// Properties which are set on the systemd unit of the container. They are
+    /// ignored if systemd does not manage the cgroups.
+    pub unit_properties: Vec<UnitProperty>,
+    /// Settings which are not supported by the host fail instead of being
+    /// skipped with a warning
+    pub strict_resources: bool,
+    /// The device rules are not applied at all, e.g. if youki runs in an
+    /// unprivileged container which can not manage the devices controller
+    pub skip_devices: bool,
+}
+
+/// Creates a cgroup manager for a container with options beyond the resources
+/// of the spec
+pub fn create_cgroup_manager_with_options<P: Into<PathBuf>>(
+    cgroup_root: Option<&Path>,
+    cgroup_path: P,
+    systemd_cgroup: bool,
+    options: ManagerOptions,
+) -> Result<Box<dyn CgroupManager>, CgroupError> {
+    // an explicit root restricts the v1 hierarchies to the ones mounted below it
+    let v1_root = cgroup_root.map(Path::to_path_buf);
+    let (cgroup_root, cgroup_setup) = match cgroup_root {
+        Some(root) => (root.to_path_buf(), get_cgroup_setup_with_root(root)?),
+        None => (detect_cgroup_root()?, get_cgroup_setup()?),
+    };
+
+    if systemd_cgroup && !booted().context("failed to check if systemd is running")? {
+        return Err(CgroupError::kernel_feature_missing(
+            "systemd cgroup flag passed, but systemd support for managing cgroups is not available",
+        ));
+    }
 
     match cgroup_setup {
         CgroupSetup::Legacy | CgroupSetup::Hybrid => {
+            if systemd_cgroup {
+                log::info!("systemd cgroup manager V1 will be used");
+                let manager = v1::SystemDCGroupManager::new(cgroup_path.into())?
+                    .with_strict_resources(options.strict_resources)
+                    .with_skip_devices(options.skip_devices);
+                return Ok(Box::new(
+                    manager.with_unit_properties(options.unit_properties),
+                ));
+            }
             log::info!("cgroup manager V1 will be used");
-            Ok(Box::new(v1::manager::Manager::new(cgroup_path.into())?))
+            let manager = v1::manager::Manager::with_root(v1_root.as_deref(), cgroup_path.into())?
+                .with_strict_resources(options.strict_resources)
+                .with_skip_devices(options.skip_devices);
+            Ok(Box::new(manager))
         }
         CgroupSetup::Unified => {
             if systemd_cgroup {
-                if !booted()? {
-                    bail!("systemd cgroup flag passed, but systemd support for managing cgroups is not available");
-                }
                 log::info!("systemd cgroup manager will be used");
-                return Ok(Box::new(v2::SystemDCGroupManager::new(
-                    DEFAULT_CGROUP_ROOT.into(),
-                    cgroup_path.into(),
-                )?));
+                let manager = v2::SystemDCGroupManager::new(cgroup_root, cgroup_path.into())?
+                    .with_strict_resources(options.strict_resources)
+                    .with_skip_devices(options.skip_devices);
+                return Ok(Box::new(
+                    manager.with_unit_properties(options.unit_properties),
+                ));
             }
             log::info!("cgroup manager V2 will be used");
-            Ok(Box::new(v2::manager::Manager::new(
-                DEFAULT_CGROUP_ROOT.into(),
-                cgroup_path.into(),
-            )?))
+            let manager = v2::manager::Manager::new(cgroup_root, cgroup_path.into())?
+                .with_strict_resources(options.strict_resources)
+                .with_skip_devices(options.skip_devices);
+            Ok(Box::new(manager))
         }
     }
 }
 
+/// Creates a cgroup manager for the cgroup filesystems in the content of a
+/// mountinfo file, see proc(5), instead of the mount table of the calling
+/// process, e.g. to manage the cgroups which are visible in another mount
+/// namespace. The cgroup setup is derived from the mounts. The cgroups are
+/// always managed through the filesystem, the unit properties of the options
+/// are ignored.
+pub fn create_cgroup_manager_from_mountinfo<P: Into<PathBuf>>(
+    mountinfo: &str,
+    cgroup_path: P,
+    options: ManagerOptions,
+) -> Result<Box<dyn CgroupManager>, CgroupError> {
+    let mounts = mounts::parse_mountinfo(mountinfo)?;
+    // on a hybrid setup the unified hierarchy has no controllers
+    if mounts.iter().any(|m| !m.controllers().is_empty()) {
+        log::info!("cgroup manager V1 will be used");
+        let manager = v1::Manager::from_mounts(&mounts, cgroup_path.into())?
+            .with_strict_resources(options.strict_resources)
+            .with_skip_devices(options.skip_devices);
+        return Ok(Box::new(manager));
+    }
+
+    match mounts.iter().find(|m| m.is_unified()) {
+        Some(unified) => {
+            log::info!("cgroup manager V2 will be used");
+            let manager =
+                v2::manager::Manager::new(unified.mount_point.clone(), cgroup_path.into())?
+                    .with_strict_resources(options.strict_resources)
+                    .with_skip_devices(options.skip_devices);
+            Ok(Box::new(manager))
+        }
+        None => Err(CgroupError::kernel_feature_missing(
+            "no cgroup filesystem is mounted",
+        )),
+    }
+}
+
 pub fn get_all_pids(path: &Path) -> Result<Vec<Pid>> {
     log::debug!("scan pids in folder: {:?}", path);
+    get_all_tasks(path, CGROUP_PROCS)
+}
+
+/// Gets the ids of the threads in the cgroup and its nested cgroups, which
+/// are listed in the given file, i.e. CGROUP_THREADS or CGROUP_TASKS
+pub fn get_all_threads(path: &Path, threads_file: &str) -> Result<Vec<Pid>> {
+    log::debug!("scan threads in folder: {:?}", path);
+    get_all_tasks(path, threads_file)
+}
+
+// The threads of a process can be spread over the cgroups of a threaded
+// subtree, so a process may be listed by several of them
+fn get_all_tasks(path: &Path, tasks_file: &str) -> Result<Vec<Pid>> {
     let mut result = vec![];
     walk_dir(path, &mut |p| {
-        let file_path = p.join(CGROUP_PROCS);
+        // the processes of a threaded cgroup belong to the domain of its
+        // subtree, the kernel refuses to list them with EOPNOTSUPP
+        if tasks_file == CGROUP_PROCS && is_threaded(p) {
+            return Ok(());
+        }
+
+        let file_path = p.join(tasks_file);
         if file_path.exists() {
             let file = File::open(file_path)?;
             for line in BufReader::new(file).lines().flatten() {
-                result.push(Pid::from_raw(line.parse::<i32>()?))
+                let pid = Pid::from_raw(line.parse::<i32>()?);
+                if !result.contains(&pid) {
+                    result.push(pid);
+                }
             }
         }
         Ok(())
@@ -186,6 +593,84 @@ pub fn get_all_pids(path: &Path) -> Result<Vec<Pid>> {
     Ok(result)
 }
 
+fn is_threaded(path: &Path) -> bool {
+    fs::read_to_string(path.join(CGROUP_TYPE)).map_or(false, |t| t.trim() == "threaded")
+}
+
+/// Removes a cgroup together with the cgroups nested in it, e.g. the ones
+/// systemd creates when it runs inside of the container. The processes which
+/// are left in them are killed and, as it takes a moment until killed
+/// processes have left the cgroup, the removal is retried while it is busy.
+pub fn remove_cgroup_dir(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    kill_all(path);
+    remove_nested_cgroups(path)
+}
+
+// Nested cgroups have to be removed bottom-up, as rmdir fails with EBUSY as
+// long as a cgroup has children
+fn remove_nested_cgroups(path: &Path) -> Result<()> {
+    for entry in fs::read_dir(path).with_context(|| format!("failed to read {:?}", path))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_nested_cgroups(&entry.path())?;
+        }
+    }
+
+    let mut delay = Duration::from_millis(10);
+    let mut attempts = 1;
+    loop {
+        match fs::remove_dir(path) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err)
+                if err.raw_os_error() == Some(Errno::EBUSY as i32)
+                    && attempts < REMOVE_ATTEMPTS =>
+            {
+                log::debug!("cgroup {:?} is busy, retry in {:?}", path, delay);
+                // processes may have been forked in the meantime
+                kill_all(path);
+                thread::sleep(delay);
+                delay *= 2;
+                attempts += 1;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to remove cgroup {:?}", path))
+            }
+        }
+    }
+}
+
+/// Kills the processes of a cgroup v2 through its cgroup.kill file, which
+/// exists since linux 5.14. Returns false if the file does not exist.
+pub fn write_cgroup_kill(path: &Path) -> Result<bool> {
+    let cgroup_kill = path.join("cgroup.kill");
+    if !cgroup_kill.exists() {
+        return Ok(false);
+    }
+
+    write_cgroup_file_str(cgroup_kill, "1")?;
+    Ok(true)
+}
+
+fn kill_all(path: &Path) {
+    match get_all_pids(path) {
+        Ok(pids) => {
+            for pid in pids {
+                let _ = signal::kill(pid, Signal::SIGKILL);
+            }
+        }
+        Err(err) => log::warn!(
+            "failed to get the processes of cgroup {:?}: {:?}",
+            path,
+            err
+        ),
+    }
+}
+
 fn walk_dir<F>(path: &Path, c: &mut F) -> Result<()>
 where
     F: FnMut(&Path) -> Result<()>,
@@ -207,17 +692,72 @@ pub(crate) trait PathBufExt {
 }
 
 impl PathBufExt for PathBuf {
+    /// Joins a cgroup path to a directory. Absolute and relative paths are both
+    /// interpreted relative to the directory. The path is normalized lexically,
+    /// so that ".." can not be used to escape from the directory.
     fn join_safely(&self, p: &Path) -> Result<PathBuf> {
-        if !p.is_absolute() && !p.as_os_str().is_empty() {
-            bail!(
-                "cannot join {:?} because it is not the absolute path.",
-                p.display()
-            )
+        let mut joined = self.clone();
+        let mut depth = 0;
+        for component in p.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::Normal(name) => {
+                    joined.push(name);
+                    depth += 1;
+                }
+                Component::ParentDir if depth > 0 => {
+                    joined.pop();
+                    depth -= 1;
+                }
+                Component::ParentDir | Component::Prefix(_) => bail!(
+                    "cannot join {:?} because it escapes from {:?}",
+                    p.display(),
+                    self.display()
+                ),
+            }
         }
-        Ok(PathBuf::from(format!("{}{}", self.display(), p.display())))
+
+        Ok(joined)
     }
 }
 
+/// Resolves the path of a block device, e.g. /dev/sda, to its major and minor
+/// device number
+pub fn block_device_number(path: &Path) -> Result<(i64, i64)> {
+    let stat =
+        stat::stat(path).with_context(|| format!("block device {:?} does not exist", path))?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+        bail!("{:?} is not a block device", path);
+    }
+
+    Ok((
+        stat::major(stat.st_rdev) as i64,
+        stat::minor(stat.st_rdev) as i64,
+    ))
+}
+
+/// Writes a setting of a block device to a cgroup file. The kernel rejects
+/// devices which do not exist with a generic error, so it is checked whether
+/// the device exists if the write fails.
+pub(crate) fn write_block_device_setting<P: AsRef<Path>, T: ToString>(
+    path: P,
+    major: i64,
+    minor: i64,
+    value: T,
+) -> Result<()> {
+    let data = format!("{}:{} {}", major, minor, value.to_string());
+    write_cgroup_file_str(path, &data).map_err(|err| {
+        if Path::new(SYS_DEV_BLOCK)
+            .join(format!("{}:{}", major, minor))
+            .exists()
+        {
+            err
+        } else {
+            err.context(format!("block device {}:{} does not exist", major, minor))
+        }
+    })
+}
+
 pub(crate) fn default_allow_devices() -> Vec<LinuxDeviceCgroup> {
     vec![
         LinuxDeviceCgroup {
@@ -326,3 +866,248 @@ pub(crate) fn default_devices() -> Vec<LinuxDevice> {
         },
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use oci_spec::LinuxPids;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_scale_quota() -> Result<()> {
+        assert_eq!(scale_quota(50000, 100000, 200000)?, 100000);
+        assert_eq!(scale_quota(50000, 100000, 100000)?, 50000);
+        assert!(scale_quota(i64::MAX, 1, 1_000_000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_cgroup_kill() -> Result<()> {
+        let tmp = create_temp_dir("test_write_cgroup_kill")?;
+        assert!(!write_cgroup_kill(&tmp)?);
+
+        let cgroup_kill = set_fixture(&tmp, "cgroup.kill", "")?;
+        assert!(write_cgroup_kill(&tmp)?);
+        assert_eq!(fs::read_to_string(cgroup_kill)?, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_initial_uid_map() {
+        assert!(is_initial_uid_map("         0          0 4294967295\n"));
+        assert!(!is_initial_uid_map("         0       1000          1\n"));
+        assert!(!is_initial_uid_map(
+            "         0       1000          1\n         1     100000      65536\n"
+        ));
+        assert!(!is_initial_uid_map(""));
+    }
+
+    // Records the pids limits it is applied with and fails for negative ones
+    #[derive(Default)]
+    struct RecordingManager {
+        applied: RefCell<Vec<i64>>,
+    }
+
+    impl CgroupManager for RecordingManager {
+        fn add_task(&self, _pid: Pid) -> Result<(), CgroupError> {
+            unimplemented!()
+        }
+
+        fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError> {
+            let limit = linux_resources.pids.as_ref().map_or(0, |pids| pids.limit);
+            self.applied.borrow_mut().push(limit);
+            if limit < 0 {
+                return Err(CgroupError::spec(format!("invalid pids limit {}", limit)));
+            }
+            Ok(())
+        }
+
+        fn remove(&self) -> Result<(), CgroupError> {
+            unimplemented!()
+        }
+
+        fn freeze(&self, _state: FreezerState) -> Result<(), CgroupError> {
+            unimplemented!()
+        }
+
+        fn stats(&self) -> Result<Stats, CgroupError> {
+            unimplemented!()
+        }
+
+        fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError> {
+            unimplemented!()
+        }
+
+        fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError> {
+            unimplemented!()
+        }
+
+        fn exists(&self) -> bool {
+            unimplemented!()
+        }
+
+        fn ramp_down_cpu(
+            &self,
+            _cpu: &LinuxCpu,
+            _rampdown: &CpuRampdown,
+        ) -> Result<(), CgroupError> {
+            unimplemented!()
+        }
+
+        fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError> {
+            unimplemented!()
+        }
+
+        fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError> {
+            unimplemented!()
+        }
+    }
+
+    fn pids_limit(limit: i64) -> LinuxResources {
+        LinuxResources {
+            pids: Some(LinuxPids { limit }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_update_restores_previous_resources() {
+        let manager = RecordingManager::default();
+        manager
+            .update(&pids_limit(20), &pids_limit(10))
+            .expect("update resources");
+        assert_eq!(*manager.applied.borrow(), vec![20]);
+
+        manager.applied.borrow_mut().clear();
+        assert!(manager.update(&pids_limit(-5), &pids_limit(10)).is_err());
+        assert_eq!(*manager.applied.borrow(), vec![-5, 10]);
+    }
+
+    #[test]
+    fn test_create_cgroup_manager_from_mountinfo() -> Result<()> {
+        let tmp = create_temp_dir("test_create_cgroup_manager_from_mountinfo")?;
+        let unified = format!(
+            "26 25 0:24 / {} rw shared:9 - cgroup2 cgroup2 rw,nsdelegate\n",
+            tmp.display()
+        );
+        let manager = create_cgroup_manager_from_mountinfo(
+            &unified,
+            "/youki/test",
+            ManagerOptions::default(),
+        )?;
+        assert_eq!(
+            manager.hierarchies()?,
+            vec![CgroupHierarchy {
+                name: None,
+                path: tmp.join("youki/test"),
+            }]
+        );
+
+        let hybrid = format!(
+            "{}30 25 0:28 / {}/pids rw shared:13 - cgroup cgroup rw,pids\n",
+            unified,
+            tmp.display()
+        );
+        let manager = create_cgroup_manager_from_mountinfo(
+            &hybrid,
+            "/youki/test",
+            ManagerOptions::default(),
+        )?;
+        assert_eq!(
+            manager.hierarchies()?,
+            vec![CgroupHierarchy {
+                name: Some("pids".to_owned()),
+                path: tmp.join("pids/youki/test"),
+            }]
+        );
+
+        let err =
+            create_cgroup_manager_from_mountinfo("", "/youki/test", ManagerOptions::default())
+                .err()
+                .unwrap();
+        assert!(matches!(err, CgroupError::KernelFeatureMissing(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_safely() -> Result<()> {
+        let root = PathBuf::from("/sys/fs/cgroup");
+        assert_eq!(
+            root.join_safely(Path::new("/youki/abc"))?,
+            PathBuf::from("/sys/fs/cgroup/youki/abc")
+        );
+        assert_eq!(
+            root.join_safely(Path::new("youki/abc"))?,
+            PathBuf::from("/sys/fs/cgroup/youki/abc")
+        );
+        assert_eq!(
+            root.join_safely(Path::new("/youki/./abc/../def"))?,
+            PathBuf::from("/sys/fs/cgroup/youki/def")
+        );
+        assert_eq!(root.join_safely(Path::new(""))?, root);
+
+        assert!(root.join_safely(Path::new("/../etc")).is_err());
+        assert!(root.join_safely(Path::new("youki/../../etc")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_device_number() {
+        assert!(block_device_number(Path::new("/dev/does-not-exist")).is_err());
+        // a character device
+        let err = block_device_number(Path::new("/dev/null")).unwrap_err();
+        assert!(err.to_string().contains("is not a block device"));
+    }
+
+    #[test]
+    fn test_check_controller_result() {
+        let mut unsupported = UnsupportedSettings::default();
+        unsupported.add("blkio leaf weight (requires the cfq scheduler)");
+        let result = unsupported.check().context("failed to apply blkio");
+        assert!(check_controller_result(result, false).is_ok());
+
+        let mut unsupported = UnsupportedSettings::default();
+        unsupported.add("blkio leaf weight (requires the cfq scheduler)");
+        let err = check_controller_result(unsupported.check(), true).unwrap_err();
+        assert!(err.to_string().contains("blkio leaf weight"));
+
+        let result = Err(anyhow::anyhow!("invalid memory value"));
+        assert!(check_controller_result(result, false).is_err());
+        assert!(UnsupportedSettings::default().check().is_ok());
+    }
+
+    #[test]
+    fn test_get_all_pids_of_threaded_subtree() {
+        let tmp = create_temp_dir("test_get_all_pids_of_threaded_subtree")
+            .expect("create temp directory");
+        set_fixture(&tmp, CGROUP_PROCS, "10\n").unwrap();
+        set_fixture(&tmp, CGROUP_THREADS, "10\n").unwrap();
+        let threaded = tmp.join("threaded");
+        fs::create_dir(&threaded).unwrap();
+        set_fixture(&threaded, CGROUP_TYPE, "threaded\n").unwrap();
+        set_fixture(&threaded, CGROUP_PROCS, "invalid").unwrap();
+        set_fixture(&threaded, CGROUP_THREADS, "11\n12\n").unwrap();
+
+        let pids = get_all_pids(&tmp).expect("get pids");
+        assert_eq!(pids, vec![Pid::from_raw(10)]);
+        let threads = get_all_threads(&tmp, CGROUP_THREADS).expect("get threads");
+        assert_eq!(
+            threads,
+            vec![Pid::from_raw(10), Pid::from_raw(11), Pid::from_raw(12)]
+        );
+    }
+
+    #[test]
+    fn test_remove_nested_cgroups() {
+        let tmp = create_temp_dir("test_remove_nested_cgroups").expect("create temp directory");
+        let cgroup = tmp.join("youki");
+        fs::create_dir_all(cgroup.join("init.scope/nested")).unwrap();
+        fs::create_dir_all(cgroup.join("system.slice")).unwrap();
+
+        remove_cgroup_dir(&cgroup).expect("remove cgroup");
+        assert!(!cgroup.exists());
+        // a cgroup which has already been removed is fine
+        remove_cgroup_dir(&cgroup).expect("remove removed cgroup");
+    }
+}
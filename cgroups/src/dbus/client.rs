@@ -0,0 +1,180 @@
+use anyhow::{bail, Context, Result};
+use dbus::arg::{Iter, ReadAll, RefArg, TypeMismatchError, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+use dbus::message::MatchRule;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+const OBJECT_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const NO_SUCH_UNIT: &str = "org.freedesktop.systemd1.NoSuchUnit";
+const METHOD_TIMEOUT: Duration = Duration::from_secs(5);
+// same timeout as runc uses for the jobs of a unit
+const JOB_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Property of a unit and its value
+pub type Property = (String, Variant<Box<dyn RefArg>>);
+
+/// Creates a property of a unit from any value which can be sent over dbus
+pub fn property<T: RefArg + 'static>(name: &str, value: T) -> Property {
+    (name.to_owned(), Variant(Box::new(value)))
+}
+
+// Signal which is sent by systemd when a job has finished
+struct JobRemoved {
+    job: String,
+    result: String,
+}
+
+impl ReadAll for JobRemoved {
+    fn read(i: &mut Iter) -> std::result::Result<Self, TypeMismatchError> {
+        let _id: u32 = i.read()?;
+        let job: dbus::Path = i.read()?;
+        let _unit: String = i.read()?;
+        let result: String = i.read()?;
+        Ok(JobRemoved {
+            job: job.to_string(),
+            result,
+        })
+    }
+}
+
+/// Client is a wrapper providing higher level API and abatraction around dbus.
+/// For more information see https://www.freedesktop.org/wiki/Software/systemd/dbus/
+pub struct Client {
+    conn: Connection,
+}
+
+impl Client {
+    /// Connects to the system instance of systemd
+    pub fn new_system() -> Result<Self> {
+        let conn = Connection::new_system().context("failed to connect to the system bus")?;
+        Ok(Client { conn })
+    }
+
+    /// Connects to the systemd instance of the user, which manages the cgroups
+    /// delegated to the user
+    pub fn new_session() -> Result<Self> {
+        let conn = Connection::new_session().context("failed to connect to the session bus")?;
+        Ok(Client { conn })
+    }
+
+    fn proxy(&self) -> Proxy<'_, &Connection> {
+        self.conn
+            .with_proxy(DESTINATION, OBJECT_PATH, METHOD_TIMEOUT)
+    }
+
+    /// start_transient_unit creates and starts a unit which only exists until
+    /// it is stopped and waits until systemd has finished the job. See
+    /// https://www.freedesktop.org/wiki/Software/systemd/dbus for more details.
+    pub fn start_transient_unit(&self, unit_name: &str, properties: Vec<Property>) -> Result<()> {
+        log::debug!("start transient unit {}", unit_name);
+        // properties of auxiliary units, which are not used
+        let aux: Vec<(String, Vec<Property>)> = Vec::new();
+        self.run_job(unit_name, "start", |proxy| {
+            let (job,): (dbus::Path<'static>,) = proxy.method_call(
+                MANAGER_INTERFACE,
+                "StartTransientUnit",
+                (unit_name, "replace", properties, aux),
+            )?;
+            Ok(Some(job))
+        })
+    }
+
+    /// stop_unit stops a unit and waits until systemd has finished the job. A
+    /// unit which does not exist is considered to be stopped.
+    pub fn stop_unit(&self, unit_name: &str) -> Result<()> {
+        log::debug!("stop unit {}", unit_name);
+        self.run_job(unit_name, "stop", |proxy| {
+            let result: std::result::Result<(dbus::Path<'static>,), dbus::Error> =
+                proxy.method_call(MANAGER_INTERFACE, "StopUnit", (unit_name, "replace"));
+            match result {
+                Ok((job,)) => Ok(Some(job)),
+                Err(err) if err.name() == Some(NO_SUCH_UNIT) => Ok(None),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    /// set_unit_properties changes properties of a running unit. The changes
+    /// are lost when the unit is stopped.
+    pub fn set_unit_properties(&self, unit_name: &str, properties: Vec<Property>) -> Result<()> {
+        log::debug!("set properties of unit {}", unit_name);
+        self.proxy()
+            .method_call(
+                MANAGER_INTERFACE,
+                "SetUnitProperties",
+                (unit_name, true, properties),
+            )
+            .with_context(|| format!("failed to set properties of unit {}", unit_name))
+    }
+
+    /// control_group returns the cgroup of the systemd instance relative to
+    /// the cgroup root. A user instance creates its units below this cgroup.
+    pub fn control_group(&self) -> Result<String> {
+        self.proxy()
+            .get(MANAGER_INTERFACE, "ControlGroup")
+            .context("failed to get the cgroup of the systemd manager")
+    }
+
+    // Runs a method which creates a job and waits for the job to be removed,
+    // which happens when it has finished
+    fn run_job<F>(&self, unit_name: &str, action: &str, call: F) -> Result<()>
+    where
+        F: FnOnce(
+            &Proxy<'_, &Connection>,
+        ) -> std::result::Result<Option<dbus::Path<'static>>, dbus::Error>,
+    {
+        // the signal is subscribed before the job is created, otherwise it
+        // could finish before the signal is subscribed
+        let removed: Arc<Mutex<Vec<JobRemoved>>> = Arc::default();
+        let sink = removed.clone();
+        let token = self.conn.add_match(
+            MatchRule::new_signal(MANAGER_INTERFACE, "JobRemoved"),
+            move |signal: JobRemoved, _: &Connection, _: &dbus::Message| {
+                sink.lock().unwrap().push(signal);
+                true
+            },
+        )?;
+
+        let result = self
+            .call_and_wait(call, &removed)
+            .with_context(|| format!("failed to {} unit {}", action, unit_name));
+        let _ = self.conn.remove_match(token);
+        result
+    }
+
+    fn call_and_wait<F>(&self, call: F, removed: &Mutex<Vec<JobRemoved>>) -> Result<()>
+    where
+        F: FnOnce(
+            &Proxy<'_, &Connection>,
+        ) -> std::result::Result<Option<dbus::Path<'static>>, dbus::Error>,
+    {
+        if let Some(job) = call(&self.proxy())? {
+            self.wait_for_job(&job, removed)?;
+        }
+
+        Ok(())
+    }
+
+    fn wait_for_job(&self, job: &str, removed: &Mutex<Vec<JobRemoved>>) -> Result<()> {
+        let deadline = Instant::now() + JOB_TIMEOUT;
+        loop {
+            if let Some(signal) = removed.lock().unwrap().iter().find(|s| s.job == job) {
+                if signal.result != "done" {
+                    bail!("job {} finished with result {}", job, signal.result);
+                }
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                bail!("timed out after {:?} waiting for job {}", JOB_TIMEOUT, job);
+            }
+            self.conn.process(deadline - now)?;
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Errors which are returned by the cgroup managers. The managers report
+//! failures with anyhow internally, the errors are classified when they are
+//! returned to the caller, so it can decide how to react to them, e.g. if a
+//! retry makes sense.
+use std::fmt::{Debug, Display};
+use std::io;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::common::UnsupportedSettings;
+
+#[derive(Debug, Error)]
+pub enum CgroupError {
+    /// The resources or the cgroups path in the spec are invalid
+    #[error(transparent)]
+    Spec(anyhow::Error),
+    /// The cgroup can not be managed by the caller, e.g. because it has not
+    /// been delegated to a rootless user
+    #[error(transparent)]
+    Permission(anyhow::Error),
+    /// A controller or an interface which is needed to fulfill the request is
+    /// not provided by the kernel
+    #[error(transparent)]
+    KernelFeatureMissing(anyhow::Error),
+    /// Any other failure, e.g. a cgroup which has been removed in the meantime
+    #[error(transparent)]
+    System(anyhow::Error),
+}
+
+impl CgroupError {
+    pub fn spec<M>(message: M) -> Self
+    where
+        M: Display + Debug + Send + Sync + 'static,
+    {
+        Self::Spec(anyhow::Error::msg(message))
+    }
+
+    pub fn kernel_feature_missing<M>(message: M) -> Self
+    where
+        M: Display + Debug + Send + Sync + 'static,
+    {
+        Self::KernelFeatureMissing(anyhow::Error::msg(message))
+    }
+
+    // Constructor of the variant of the error, so another error can be
+    // classified the same way
+    fn variant(&self) -> Variant {
+        match self {
+            Self::Spec(_) => Self::Spec,
+            Self::Permission(_) => Self::Permission,
+            Self::KernelFeatureMissing(_) => Self::KernelFeatureMissing,
+            Self::System(_) => Self::System,
+        }
+    }
+}
+
+type Variant = fn(anyhow::Error) -> CgroupError;
+
+/// Classifies an error by the first cause which is known, which is either a
+/// cgroup error that has been raised deeper down, unsupported settings or the
+/// error of a syscall
+impl From<anyhow::Error> for CgroupError {
+    fn from(err: anyhow::Error) -> Self {
+        let variant = err.chain().find_map(classify).unwrap_or(Self::System);
+        variant(err)
+    }
+}
+
+fn classify(cause: &(dyn std::error::Error + 'static)) -> Option<Variant> {
+    if let Some(cgroup_err) = cause.downcast_ref::<CgroupError>() {
+        return Some(cgroup_err.variant());
+    }
+    if cause.is::<UnsupportedSettings>() {
+        return Some(CgroupError::KernelFeatureMissing);
+    }
+
+    let errno = match cause.downcast_ref::<io::Error>() {
+        Some(io_err) => io_err.raw_os_error().map(Errno::from_i32),
+        None => cause.downcast_ref::<Errno>().copied(),
+    };
+    errno.map(|errno| -> Variant {
+        match errno {
+            Errno::EPERM | Errno::EACCES | Errno::EROFS => CgroupError::Permission,
+            Errno::ENOSYS | Errno::EOPNOTSUPP | Errno::ENODEV => CgroupError::KernelFeatureMissing,
+            _ => CgroupError::System,
+        }
+    })
+}
+
+impl From<io::Error> for CgroupError {
+    fn from(err: io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<Errno> for CgroupError {
+    fn from(err: Errno) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_classify_errno() {
+        let err: CgroupError = io::Error::from_raw_os_error(Errno::EACCES as i32).into();
+        assert!(matches!(err, CgroupError::Permission(_)));
+
+        let err: CgroupError = Err::<(), _>(Errno::EOPNOTSUPP)
+            .context("failed to write memory.swap.max")
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, CgroupError::KernelFeatureMissing(_)));
+        assert_eq!(err.to_string(), "failed to write memory.swap.max");
+
+        let err: CgroupError = anyhow!("no such cgroup").into();
+        assert!(matches!(err, CgroupError::System(_)));
+    }
+
+    #[test]
+    fn test_classify_nested_cgroup_error() {
+        let err: CgroupError = Err::<(), _>(CgroupError::spec("invalid memory value: -2"))
+            .context("failed to apply memory resource restrictions")
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, CgroupError::Spec(_)));
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to apply memory resource restrictions: invalid memory value: -2"
+        );
+    }
+}
@@ -1,12 +1,55 @@
 //! Control groups provide a way of controlling groups of processes.
 //! Examples: controlling resource limits, execution priority, measuring resource usage,
 //! freezing, checkpointing and restarting groups of processes.
+//!
+//! This crate manages cgroups on cgroup v1, v2 and hybrid hosts, either through
+//! the cgroup filesystem or through systemd. A [CgroupManager] is created for
+//! a cgroup path and applies resources in the format of the OCI runtime spec,
+//! collects [Stats] and freezes the processes in the cgroup. The types of the
+//! spec which are part of the API are re-exported, so the spec does not have to
+//! be a dependency of its own. The cgroup setup is detected from the mount
+//! table of the process, or taken from the mountinfo of another mount namespace
+//! with [create_cgroup_manager_from_mountinfo].
+//!
+//! The items which are re-exported here make up the stable API of the crate.
+//! The modules are public as well, but their contents may change with the
+//! needs of youki.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use cgroups::{create_cgroup_manager, LinuxResources};
+//! use nix::unistd::Pid;
+//!
+//! let manager = create_cgroup_manager("/youki/example", false)?;
+//! manager.add_task(Pid::from_raw(4242))?;
+//! manager.apply(&LinuxResources::default())?;
+//! manager.pause()?;
+//! println!("{:?}", manager.stats()?.memory);
+//! manager.resume()?;
+//! manager.remove()?;
+//! # Ok::<(), cgroups::CgroupError>(())
+//! ```
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 
 pub mod common;
+pub mod dbus;
+pub mod error;
+pub mod mounts;
 pub mod stats;
+pub mod systemd_unit;
 mod test;
 pub mod v1;
 pub mod v2;
+
+pub use common::{
+    create_cgroup_manager, create_cgroup_manager_from_mountinfo,
+    create_cgroup_manager_with_options, create_cgroup_manager_with_root, get_cgroup_setup,
+    CgroupHierarchy, CgroupManager, CgroupSetup, ManagerOptions,
+};
+pub use error::CgroupError;
+pub use mounts::CgroupMount;
+pub use oci_spec::{FreezerState, LinuxResources};
+pub use stats::Stats;
@@ -0,0 +1,216 @@
+//! Mounts of the cgroup filesystems. Finding the cgroup root and the
+//! hierarchies of cgroup v1 requires the mount table of the process, which is
+//! read from /proc/self/mountinfo once and kept for the lifetime of the
+//! process. youki does not mount cgroup filesystems itself, so the table only
+//! changes if the host is reconfigured while youki is running.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::OnceCell;
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const CGROUP_FS: &str = "cgroup";
+const CGROUP2_FS: &str = "cgroup2";
+// Options of a cgroup v1 filesystem which do not name a controller
+const V1_GENERIC_OPTIONS: &[&str] = &[
+    "rw",
+    "ro",
+    "all",
+    "none",
+    "noprefix",
+    "xattr",
+    "clone_children",
+    "cpuset_v2_mode",
+];
+
+static CGROUP_MOUNTS: OnceCell<Vec<CgroupMount>> = OnceCell::new();
+
+/// A cgroup filesystem which is mounted in the mount namespace of youki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupMount {
+    /// Directory the filesystem is mounted at
+    pub mount_point: PathBuf,
+    /// Cgroup of the hierarchy which is mounted, "/" unless the mount belongs
+    /// to a cgroup namespace or is a bind mount of a nested cgroup
+    pub root: PathBuf,
+    /// Either cgroup or cgroup2
+    pub fs_type: String,
+    /// Options of the filesystem, for cgroup v1 this includes the controllers
+    /// of the hierarchy
+    pub super_options: Vec<String>,
+}
+
+impl CgroupMount {
+    /// Returns if this is the unified hierarchy of cgroup v2
+    pub fn is_unified(&self) -> bool {
+        self.fs_type == CGROUP2_FS
+    }
+
+    /// Returns the controllers which are attached to a cgroup v1 hierarchy in
+    /// the order the kernel lists them, e.g. ["cpu", "cpuacct"] for a
+    /// co-mounted hierarchy. Named hierarchies may have no controller at all.
+    pub fn controllers(&self) -> Vec<&str> {
+        if self.is_unified() {
+            return Vec::new();
+        }
+
+        self.super_options
+            .iter()
+            .map(String::as_str)
+            .filter(|o| !o.contains('=') && !V1_GENERIC_OPTIONS.contains(o))
+            .collect()
+    }
+
+    /// Returns if the controller is attached to the hierarchy
+    pub fn has_controller(&self, controller: &str) -> bool {
+        self.controllers().contains(&controller)
+    }
+
+    /// Returns the name of a named cgroup v1 hierarchy, e.g. "systemd" for a
+    /// hierarchy which is mounted with name=systemd
+    pub fn name(&self) -> Option<&str> {
+        self.super_options
+            .iter()
+            .find_map(|o| o.strip_prefix("name="))
+    }
+
+    /// Returns if the mount is located below the directory
+    pub fn is_below(&self, dir: &Path) -> bool {
+        self.mount_point.starts_with(dir)
+    }
+}
+
+/// Returns the cgroup filesystems which are mounted in the mount namespace of
+/// the process, in the order of the mount table
+pub fn cgroup_mounts() -> Result<&'static [CgroupMount]> {
+    let mounts = CGROUP_MOUNTS.get_or_try_init(|| {
+        let content = fs::read_to_string(MOUNTINFO_PATH)
+            .with_context(|| format!("failed to read {}", MOUNTINFO_PATH))?;
+        let mounts = parse_mountinfo(&content)?;
+        log::debug!("found cgroup mounts: {:?}", mounts);
+        Ok::<_, anyhow::Error>(mounts)
+    })?;
+
+    Ok(mounts)
+}
+
+/// Returns the mount of the unified hierarchy. If a cgroup root is given, only
+/// mounts below it are considered.
+pub fn unified_mount(cgroup_root: Option<&Path>) -> Result<Option<&'static CgroupMount>> {
+    Ok(cgroup_mounts()?
+        .iter()
+        .filter(|m| cgroup_root.map_or(true, |root| m.is_below(root)))
+        .find(|m| m.is_unified()))
+}
+
+/// Returns the mounts of the cgroup v1 hierarchies. If a cgroup root is given,
+/// only mounts below it are considered.
+pub fn v1_mounts(cgroup_root: Option<&Path>) -> Result<Vec<&'static CgroupMount>> {
+    Ok(cgroup_mounts()?
+        .iter()
+        .filter(|m| cgroup_root.map_or(true, |root| m.is_below(root)))
+        .filter(|m| !m.is_unified())
+        .collect())
+}
+
+/// Parses the cgroup filesystems from the content of a mountinfo file, see
+/// proc(5) for the format. Other filesystems are skipped.
+pub fn parse_mountinfo(content: &str) -> Result<Vec<CgroupMount>> {
+    let mut mounts = Vec::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        // the optional fields before the separator have a variable length
+        let (mount, fs) = match line.split_once(" - ") {
+            Some(parts) => parts,
+            None => bail!("invalid mountinfo entry {:?}", line),
+        };
+        let mount: Vec<&str> = mount.split_whitespace().collect();
+        let fs: Vec<&str> = fs.split_whitespace().collect();
+        if mount.len() < 6 || fs.len() < 3 {
+            bail!("invalid mountinfo entry {:?}", line);
+        }
+
+        if fs[0] != CGROUP_FS && fs[0] != CGROUP2_FS {
+            continue;
+        }
+
+        mounts.push(CgroupMount {
+            mount_point: PathBuf::from(unescape(mount[4])),
+            root: PathBuf::from(unescape(mount[3])),
+            fs_type: fs[0].to_owned(),
+            super_options: fs[2].split(',').map(str::to_owned).collect(),
+        });
+    }
+
+    Ok(mounts)
+}
+
+// Whitespace and backslashes in paths are escaped as octal numbers, e.g. \040
+// for a space
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(pos) = rest.find('\\') {
+        unescaped.push_str(&rest[..pos]);
+        let code = rest
+            .get(pos + 1..pos + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HYBRID_MOUNTINFO: &str = "\
+24 31 0:22 / /sys rw,nosuid,nodev,noexec,relatime shared:7 - sysfs sysfs rw
+25 24 0:23 / /sys/fs/cgroup ro,nosuid,nodev,noexec shared:8 - tmpfs tmpfs ro,mode=755
+26 25 0:24 / /sys/fs/cgroup/unified rw,nosuid,nodev,noexec,relatime shared:9 - cgroup2 cgroup2 rw,nsdelegate
+27 25 0:25 / /sys/fs/cgroup/systemd rw,nosuid,nodev,noexec,relatime shared:10 - cgroup cgroup rw,xattr,name=systemd
+30 25 0:28 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:13 - cgroup cgroup rw,cpu,cpuacct
+";
+
+    #[test]
+    fn test_parse_mountinfo() -> Result<()> {
+        let mounts = parse_mountinfo(HYBRID_MOUNTINFO)?;
+        assert_eq!(mounts.len(), 3);
+        assert!(mounts[0].is_unified());
+        assert_eq!(
+            mounts[2].mount_point,
+            PathBuf::from("/sys/fs/cgroup/cpu,cpuacct")
+        );
+        assert_eq!(mounts[2].root, PathBuf::from("/"));
+        assert_eq!(mounts[2].super_options, vec!["rw", "cpu", "cpuacct"]);
+        assert_eq!(mounts[2].controllers(), vec!["cpu", "cpuacct"]);
+        assert!(mounts[2].has_controller("cpuacct"));
+        assert_eq!(mounts[2].name(), None);
+
+        assert!(mounts[1].controllers().is_empty());
+        assert_eq!(mounts[1].name(), Some("systemd"));
+        assert!(mounts[0].controllers().is_empty());
+
+        assert!(parse_mountinfo("26 25 0:24 / /sys/fs/cgroup").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/mnt/cgroup\\040v2"), "/mnt/cgroup v2");
+        assert_eq!(unescape("/mnt/back\\134slash"), "/mnt/back\\slash");
+        assert_eq!(unescape("/mnt/plain"), "/mnt/plain");
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{bail, Context, Result};
-use serde::Serialize;
-use std::{collections::HashMap, fmt::Display, fs, path::Path};
+use nix::errno::Errno;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{collections::HashMap, fmt::Display, fs, io::ErrorKind, path::Path};
 
 use super::common;
 
@@ -10,8 +11,11 @@ pub trait StatsProvider {
     fn stats(cgroup_path: &Path) -> Result<Self::Stats>;
 }
 
-/// Reports the statistics for a cgroup
-#[derive(Debug, Serialize)]
+/// Reports the statistics for a cgroup. They are serialized in the layout of
+/// the data of the stats events of runc, so that tools which consume the events
+/// of runc can read them as well. Values which runc omits when they are empty
+/// are omitted as well, stats which runc does not know about are added.
+#[derive(Debug)]
 pub struct Stats {
     /// Cpu statistics for the cgroup
     pub cpu: CpuStats,
@@ -23,6 +27,8 @@ pub struct Stats {
     pub blkio: BlkioStats,
     /// Memory statistics for the cgroup
     pub memory: MemoryStats,
+    /// Pressure stall information for the cgroup
+    pub pressure: PressureStats,
 }
 
 impl Default for Stats {
@@ -33,12 +39,51 @@ impl Default for Stats {
             hugetlb: HashMap::new(),
             blkio: BlkioStats::default(),
             memory: MemoryStats::default(),
+            pressure: PressureStats::default(),
+        }
+    }
+}
+
+// runc reports the pressure stall information as part of the stats of the
+// resource, e.g. as "psi" of the cpu stats
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut stats = serializer.serialize_struct("Stats", 5)?;
+        stats.serialize_field("cpu", &WithPressure::new(&self.cpu, &self.pressure.cpu))?;
+        stats.serialize_field(
+            "memory",
+            &WithPressure::new(&self.memory, &self.pressure.memory),
+        )?;
+        stats.serialize_field("pids", &self.pids)?;
+        stats.serialize_field("blkio", &WithPressure::new(&self.blkio, &self.pressure.io))?;
+        stats.serialize_field("hugetlb", &self.hugetlb)?;
+        stats.end()
+    }
+}
+
+#[derive(Serialize)]
+struct WithPressure<'a, T> {
+    #[serde(flatten)]
+    stats: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    psi: Option<&'a PsiStats>,
+}
+
+impl<'a, T> WithPressure<'a, T> {
+    fn new(stats: &'a T, psi: &'a Option<PsiStats>) -> Self {
+        Self {
+            stats,
+            psi: psi.as_ref(),
         }
     }
 }
 
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
 /// Reports the cpu statistics for a cgroup
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct CpuStats {
     /// Cpu usage statistics for the cgroup
     pub usage: CpuUsage,
@@ -59,16 +104,22 @@ impl Default for CpuStats {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct CpuUsage {
     /// Cpu time consumed by tasks in total
+    #[serde(rename = "total", skip_serializing_if = "is_zero")]
     pub usage_total: u64,
     /// Cpu time consumed by tasks in user mode
+    #[serde(rename = "user")]
     pub usage_user: u64,
     /// Cpu time consumed by tasks in kernel mode
+    #[serde(rename = "kernel")]
     pub usage_kernel: u64,
     /// Cpu time consumed by tasks itemized per core
+    #[serde(rename = "percpu", skip_serializing_if = "Vec::is_empty")]
     pub per_core_usage_total: Vec<u64>,
     /// Cpu time consumed by tasks in user mode itemized per core
+    #[serde(rename = "percpu_user", skip_serializing_if = "Vec::is_empty")]
     pub per_core_usage_user: Vec<u64>,
     /// Cpu time consumed by tasks in kernel mode itemized per core
+    #[serde(rename = "percpu_kernel", skip_serializing_if = "Vec::is_empty")]
     pub per_core_usage_kernel: Vec<u64>,
 }
 
@@ -89,10 +140,13 @@ impl Default for CpuUsage {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct CpuThrottling {
     /// Number of period intervals (as specified in cpu.cfs_period_us) that have elapsed
+    #[serde(skip_serializing_if = "is_zero")]
     pub periods: u64,
     /// Number of period intervals where tasks have been throttled because they exhausted their quota
+    #[serde(rename = "throttledPeriods", skip_serializing_if = "is_zero")]
     pub throttled_periods: u64,
     /// Total time duration for which tasks have been throttled
+    #[serde(rename = "throttledTime", skip_serializing_if = "is_zero")]
     pub throttled_time: u64,
 }
 
@@ -110,21 +164,60 @@ impl Default for CpuThrottling {
 #[derive(Debug, Serialize)]
 pub struct MemoryStats {
     /// Usage of memory
+    #[serde(rename = "usage")]
     pub memory: MemoryData,
     /// Usage of memory and swap
+    #[serde(rename = "swap")]
     pub memswap: MemoryData,
     /// Usage of kernel memory
     pub kernel: MemoryData,
     /// Usage of kernel tcp memory
+    #[serde(rename = "kernelTCP")]
     pub kernel_tcp: MemoryData,
     /// Page cache in bytes
+    #[serde(skip_serializing_if = "is_zero")]
     pub cache: u64,
+    /// Anonymous memory in bytes. The counters below are part of the raw
+    /// statistics as well and are not serialized twice.
+    #[serde(skip)]
+    pub rss: u64,
+    /// Page cache of memory mapped files in bytes
+    #[serde(skip)]
+    pub mapped_file: u64,
+    /// Number of page faults
+    #[serde(skip)]
+    pub page_faults: u64,
+    /// Number of page faults which required disk access
+    #[serde(skip)]
+    pub major_page_faults: u64,
+    /// Memory limit of the cgroup including the limits of its ancestors. Only
+    /// reported on cgroup v1.
+    #[serde(skip)]
+    pub hierarchical_memory_limit: u64,
+    /// Memory and swap limit of the cgroup including the limits of its
+    /// ancestors. Only reported on cgroup v1.
+    #[serde(skip)]
+    pub hierarchical_memsw_limit: u64,
     /// Returns true if hierarchical accounting is enabled
     pub hierarchy: bool,
+    /// Number of processes which have been killed by the OOM killer
+    pub oom_kill: u64,
     /// Various memory statistics
+    #[serde(rename = "raw", skip_serializing_if = "HashMap::is_empty")]
     pub stats: HashMap<String, u64>,
 }
 
+impl MemoryStats {
+    /// Returns a counter of the raw statistics including the usage of the
+    /// nested cgroups, which cgroup v1 reports with a total_ prefix. The
+    /// counter of the cgroup itself is not a substitute, so None is returned
+    /// if it is missing. The raw counters of cgroup v2 always include the
+    /// nested cgroups and can be used as they are.
+    pub fn total_stat(&self, name: &str) -> Option<u64> {
+        self.stats.get(&format!("total_{}", name)).copied()
+    }
+}
+
 impl Default for MemoryStats {
     fn default() -> Self {
         Self {
@@ -133,7 +226,14 @@ impl Default for MemoryStats {
             kernel: MemoryData::default(),
             kernel_tcp: MemoryData::default(),
             cache: 0,
+            rss: 0,
+            mapped_file: 0,
+            page_faults: 0,
+            major_page_faults: 0,
+            hierarchical_memory_limit: 0,
+            hierarchical_memsw_limit: 0,
             hierarchy: false,
+            oom_kill: 0,
             stats: HashMap::default(),
         }
     }
@@ -143,10 +243,13 @@ impl Default for MemoryStats {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct MemoryData {
     /// Usage in bytes
+    #[serde(skip_serializing_if = "is_zero")]
     pub usage: u64,
     /// Maximum recorded usage in bytes
+    #[serde(rename = "max", skip_serializing_if = "is_zero")]
     pub max_usage: u64,
     /// Number of times memory usage hit limits
+    #[serde(rename = "failcnt")]
     pub fail_count: u64,
     /// Memory usage limit
     pub limit: u64,
@@ -167,9 +270,13 @@ impl Default for MemoryData {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct PidStats {
     /// Current number of active pids
+    #[serde(skip_serializing_if = "is_zero")]
     pub current: u64,
     /// Allowed number of active pids (0 means no limit)
+    #[serde(skip_serializing_if = "is_zero")]
     pub limit: u64,
+    /// Number of times a fork failed because the limit has been reached
+    pub fail_count: u64,
 }
 
 impl Default for PidStats {
@@ -177,6 +284,7 @@ impl Default for PidStats {
         Self {
             current: 0,
             limit: 0,
+            fail_count: 0,
         }
     }
 }
@@ -185,21 +293,50 @@ impl Default for PidStats {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct BlkioStats {
     // Number of bytes transfered to/from a device by the cgroup
+    #[serde(
+        rename = "ioServiceBytesRecursive",
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub service_bytes: Vec<BlkioDeviceStat>,
     // Number of I/O operations performed on a device by the cgroup
+    #[serde(rename = "ioServicedRecursive", skip_serializing_if = "Vec::is_empty")]
     pub serviced: Vec<BlkioDeviceStat>,
     // Time in milliseconds that the cgroup had access to a device
+    #[serde(rename = "ioTimeRecursive", skip_serializing_if = "Vec::is_empty")]
     pub time: Vec<BlkioDeviceStat>,
     // Number of sectors transferred to/from a device by the cgroup
+    #[serde(rename = "sectorsRecursive", skip_serializing_if = "Vec::is_empty")]
     pub sectors: Vec<BlkioDeviceStat>,
     // Total time between request dispatch and request completion
+    #[serde(
+        rename = "ioServiceTimeRecursive",
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub service_time: Vec<BlkioDeviceStat>,
     // Total time spend waiting in the scheduler queues for service
+    #[serde(rename = "ioWaitTimeRecursive", skip_serializing_if = "Vec::is_empty")]
     pub wait_time: Vec<BlkioDeviceStat>,
     // Number of requests queued for I/O operations
+    #[serde(rename = "ioQueueRecursive", skip_serializing_if = "Vec::is_empty")]
     pub queued: Vec<BlkioDeviceStat>,
     // Number of requests merged into requests for I/O operations
+    #[serde(rename = "ioMergedRecursive", skip_serializing_if = "Vec::is_empty")]
     pub merged: Vec<BlkioDeviceStat>,
+    // Configured io.latency target in microseconds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub latency_target: Vec<BlkioDeviceStat>,
+    // Device time used by the cgroup in microseconds, as accounted by io.cost
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cost_usage: Vec<BlkioDeviceStat>,
+    // Time the cgroup spent waiting due to io.cost throttling in microseconds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cost_wait: Vec<BlkioDeviceStat>,
+    // Time the cgroup has been in debt to io.cost in microseconds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cost_indebt: Vec<BlkioDeviceStat>,
+    // Time the cgroup has been delayed by io.cost due to debt in microseconds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cost_indelay: Vec<BlkioDeviceStat>,
 }
 
 impl Default for BlkioStats {
@@ -213,6 +350,11 @@ impl Default for BlkioStats {
             wait_time: Vec::new(),
             queued: Vec::new(),
             merged: Vec::new(),
+            latency_target: Vec::new(),
+            cost_usage: Vec::new(),
+            cost_wait: Vec::new(),
+            cost_indebt: Vec::new(),
+            cost_indelay: Vec::new(),
         }
     }
 }
@@ -221,12 +363,16 @@ impl Default for BlkioStats {
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, PartialOrd, Ord)]
 pub struct BlkioDeviceStat {
     /// Major device number
+    #[serde(skip_serializing_if = "is_zero")]
     pub major: u64,
     /// Minor device number
+    #[serde(skip_serializing_if = "is_zero")]
     pub minor: u64,
     /// Operation type
+    #[serde(rename = "op", skip_serializing_if = "Option::is_none")]
     pub op_type: Option<String>,
     /// Stat value
+    #[serde(skip_serializing_if = "is_zero")]
     pub value: u64,
 }
 
@@ -248,10 +394,13 @@ impl Display for BlkioDeviceStat {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct HugeTlbStats {
     /// Current usage in bytes
+    #[serde(skip_serializing_if = "is_zero")]
     pub usage: u64,
     /// Maximum recorded usage in bytes
+    #[serde(rename = "max", skip_serializing_if = "is_zero")]
     pub max_usage: u64,
     /// Number of allocation failures due to HugeTlb usage limit
+    #[serde(rename = "failcnt")]
     pub fail_count: u64,
 }
 
@@ -265,6 +414,70 @@ impl Default for HugeTlbStats {
     }
 }
 
+/// Reports the pressure stall information of a cgroup, which is only
+/// available on cgroup v2 with a kernel that has been built with PSI
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PressureStats {
+    /// Pressure caused by waiting for cpu time
+    pub cpu: Option<PsiStats>,
+    /// Pressure caused by waiting for memory to be reclaimed or swapped in
+    pub memory: Option<PsiStats>,
+    /// Pressure caused by waiting for io
+    pub io: Option<PsiStats>,
+}
+
+impl Default for PressureStats {
+    fn default() -> Self {
+        Self {
+            cpu: None,
+            memory: None,
+            io: None,
+        }
+    }
+}
+
+/// Reports the pressure stall information of a single resource
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PsiStats {
+    /// Time in which at least some tasks were stalled on the resource
+    pub some: PsiData,
+    /// Time in which all non-idle tasks were stalled on the resource at once
+    pub full: PsiData,
+}
+
+impl Default for PsiStats {
+    fn default() -> Self {
+        Self {
+            some: PsiData::default(),
+            full: PsiData::default(),
+        }
+    }
+}
+
+/// Reports the share of time tasks were stalled
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PsiData {
+    /// Stalled time in percent over the last 10 seconds
+    pub avg10: f64,
+    /// Stalled time in percent over the last 60 seconds
+    pub avg60: f64,
+    /// Stalled time in percent over the last 300 seconds
+    pub avg300: f64,
+    /// Total stalled time in microseconds
+    pub total: u64,
+}
+
+impl Default for PsiData {
+    fn default() -> Self {
+        Self {
+            avg10: 0.0,
+            avg60: 0.0,
+            avg300: 0.0,
+            total: 0,
+        }
+    }
+}
+
 /// Reports which hugepage sizes are supported by the system
 pub fn supported_page_sizes() -> Result<Vec<String>> {
     let mut sizes = Vec::new();
@@ -426,9 +639,63 @@ pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats> {
         stats.limit = limit.parse().context("failed to parse pids limit")?;
     }
 
+    // pids.events only exists since linux 4.16 for cgroup v1
+    let events_path = cgroup_path.join("pids.events");
+    if events_path.exists() {
+        let events = parse_flat_keyed_data(&events_path)?;
+        stats.fail_count = events.get("max").copied().unwrap_or_default();
+    }
+
     Ok(stats)
 }
 
+/// Parses a pressure file of cgroup v2, which has the format
+/// "some avg10=0.00 avg60=0.00 avg300=0.00 total=0" followed by a line for
+/// full. Returns None if the kernel does not provide pressure stall information.
+pub fn psi_stats(psi_path: &Path) -> Result<Option<PsiStats>> {
+    let content = match fs::read_to_string(psi_path) {
+        Ok(content) => content,
+        // the files exist but can not be read if psi has been disabled on boot
+        Err(err)
+            if err.kind() == ErrorKind::NotFound
+                || err.raw_os_error() == Some(Errno::EOPNOTSUPP as i32) =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err).with_context(|| format!("failed to open {:?}", psi_path)),
+    };
+
+    let mut stats = PsiStats::default();
+    for line in content.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        let data = match fields.next() {
+            Some("some") => &mut stats.some,
+            Some("full") => &mut stats.full,
+            _ => continue,
+        };
+
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .with_context(|| format!("invalid entry {:?} in {:?}", field, psi_path))?;
+            let parse_avg = |value: &str| {
+                value
+                    .parse::<f64>()
+                    .with_context(|| format!("failed to parse {} of {:?}", key, psi_path))
+            };
+            match key {
+                "avg10" => data.avg10 = parse_avg(value)?,
+                "avg60" => data.avg60 = parse_avg(value)?,
+                "avg300" => data.avg300 = parse_avg(value)?,
+                "total" => data.total = parse_value(value)?,
+                _ => continue,
+            }
+        }
+    }
+
+    Ok(Some(stats))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test::{create_temp_dir, set_fixture};
@@ -622,4 +889,106 @@ mod tests {
         let result = parse_device_number("a:b");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_pid_stats() {
+        let tmp = create_temp_dir("test_pid_stats").expect("create temp dir");
+        set_fixture(&tmp, "pids.current", "5\n").unwrap();
+        set_fixture(&tmp, "pids.max", "max\n").unwrap();
+
+        let stats = pid_stats(&tmp).expect("get pid stats");
+        assert_eq!(
+            stats,
+            PidStats {
+                current: 5,
+                limit: 0,
+                fail_count: 0,
+            }
+        );
+
+        set_fixture(&tmp, "pids.max", "10\n").unwrap();
+        set_fixture(&tmp, "pids.events", "max 3\n").unwrap();
+        let stats = pid_stats(&tmp).expect("get pid stats");
+        assert_eq!(
+            stats,
+            PidStats {
+                current: 5,
+                limit: 10,
+                fail_count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_psi_stats() {
+        let tmp = create_temp_dir("test_psi_stats").expect("create temp dir");
+        let content = [
+            "some avg10=1.50 avg60=0.75 avg300=0.10 total=120345",
+            "full avg10=0.00 avg60=0.25 avg300=0.05 total=4567",
+        ]
+        .join("\n");
+        set_fixture(&tmp, "memory.pressure", &content).unwrap();
+
+        let stats = psi_stats(&tmp.join("memory.pressure")).expect("get psi stats");
+        assert_eq!(
+            stats,
+            Some(PsiStats {
+                some: PsiData {
+                    avg10: 1.5,
+                    avg60: 0.75,
+                    avg300: 0.1,
+                    total: 120345,
+                },
+                full: PsiData {
+                    avg10: 0.0,
+                    avg60: 0.25,
+                    avg300: 0.05,
+                    total: 4567,
+                },
+            })
+        );
+
+        let stats = psi_stats(&tmp.join("io.pressure")).expect("get psi stats");
+        assert_eq!(stats, None);
+    }
+
+    #[test]
+    fn test_serialize_stats_like_runc() {
+        let mut stats = Stats::default();
+        stats.cpu.usage.usage_total = 2000;
+        stats.cpu.throttling.throttled_time = 100;
+        stats.memory.memory = MemoryData {
+            usage: 4096,
+            max_usage: 8192,
+            fail_count: 1,
+            limit: 16384,
+        };
+        stats.pids.current = 3;
+        stats.blkio.service_bytes = vec![BlkioDeviceStat {
+            major: 8,
+            minor: 0,
+            op_type: Some("Read".to_owned()),
+            value: 512,
+        }];
+        stats.pressure.cpu = Some(PsiStats::default());
+
+        let json = serde_json::to_value(&stats).expect("serialize stats");
+        assert_eq!(json["cpu"]["usage"]["total"], 2000);
+        assert_eq!(json["cpu"]["usage"]["kernel"], 0);
+        assert!(json["cpu"]["usage"].get("percpu").is_none());
+        assert_eq!(json["cpu"]["throttling"]["throttledTime"], 100);
+        assert_eq!(json["cpu"]["psi"]["some"]["total"], 0);
+        assert_eq!(
+            json["memory"]["usage"],
+            serde_json::json!({ "usage": 4096, "max": 8192, "failcnt": 1, "limit": 16384 })
+        );
+        assert!(json["memory"].get("psi").is_none());
+        assert_eq!(json["pids"]["current"], 3);
+        assert!(json["pids"].get("limit").is_none());
+        assert_eq!(
+            json["blkio"]["ioServiceBytesRecursive"],
+            serde_json::json!([{ "major": 8, "op": "Read", "value": 512 }])
+        );
+        assert!(json["blkio"].get("ioServicedRecursive").is_none());
+    }
 }
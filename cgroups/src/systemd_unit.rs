@@ -0,0 +1,728 @@
+//! Transient units of systemd, which own the cgroup of a container when the
+//! systemd cgroup driver is used. The unit is created over dbus and systemd
+//! creates its cgroup in every hierarchy it manages, so the same unit is used
+//! by the drivers of cgroup v1 and v2.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::unistd::{self, Pid};
+use oci_spec::LinuxResources;
+
+use crate::dbus::{property, Client, Property};
+use crate::error::CgroupError;
+
+// slice the units are created in, if the cgroups path does not name one
+const DEFAULT_SLICE: &str = "machine.slice";
+// period of the cpu quota if none is given, which is the default of the kernel
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Annotations with this prefix set a property of the unit of the container,
+/// e.g. "org.systemd.property.TimeoutStopUSec": "uint64 5000000"
+pub const PROPERTY_ANNOTATION_PREFIX: &str = "org.systemd.property.";
+
+/// Value of a unit property in the text format of GVariant, which is used by
+/// runc and systemctl as well. Numbers without a type are 32 bit integers or
+/// doubles, other types have to be given as a prefix, e.g. "uint64 100".
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Double(f64),
+    Str(String),
+    StrArray(Vec<String>),
+}
+
+impl PropertyValue {
+    fn into_property(self, name: &str) -> Property {
+        match self {
+            PropertyValue::Bool(value) => property(name, value),
+            PropertyValue::I32(value) => property(name, value),
+            PropertyValue::U32(value) => property(name, value),
+            PropertyValue::I64(value) => property(name, value),
+            PropertyValue::U64(value) => property(name, value),
+            PropertyValue::Double(value) => property(name, value),
+            PropertyValue::Str(value) => property(name, value),
+            PropertyValue::StrArray(value) => property(name, value),
+        }
+    }
+
+    // Time spans in seconds, as floating point numbers are allowed as well
+    fn as_seconds(&self) -> Option<f64> {
+        match *self {
+            PropertyValue::I32(value) => Some(value as f64),
+            PropertyValue::U32(value) => Some(value as f64),
+            PropertyValue::I64(value) => Some(value as f64),
+            PropertyValue::U64(value) => Some(value as f64),
+            PropertyValue::Double(value) => Some(value),
+            _ => None,
+        }
+        .filter(|seconds| *seconds >= 0.0)
+    }
+}
+
+impl FromStr for PropertyValue {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        match value {
+            "true" => return Ok(PropertyValue::Bool(true)),
+            "false" => return Ok(PropertyValue::Bool(false)),
+            _ => {}
+        }
+
+        if let Some((ty, number)) = value.split_once(' ') {
+            let number = number.trim();
+            let parsed = match ty {
+                "int32" => number.parse().map(PropertyValue::I32).ok(),
+                "uint32" => number.parse().map(PropertyValue::U32).ok(),
+                "int64" => number.parse().map(PropertyValue::I64).ok(),
+                "uint64" => number.parse().map(PropertyValue::U64).ok(),
+                "double" => number.parse().map(PropertyValue::Double).ok(),
+                _ => None,
+            };
+            if let Some(parsed) = parsed {
+                return Ok(parsed);
+            }
+        }
+
+        if let Some(string) = unquote(value) {
+            return Ok(PropertyValue::Str(string.to_owned()));
+        }
+        if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            return parse_string_array(items)
+                .map(PropertyValue::StrArray)
+                .with_context(|| format!("invalid array {}, only strings are supported", value));
+        }
+        if let Ok(number) = value.parse() {
+            return Ok(PropertyValue::I32(number));
+        }
+        if let Ok(number) = value.parse() {
+            return Ok(PropertyValue::Double(number));
+        }
+
+        bail!(CgroupError::spec(format!(
+            "invalid value {:?} of a systemd property",
+            value
+        )))
+    }
+}
+
+/// Property which is set on the unit in addition to the ones set by youki
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitProperty {
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+impl UnitProperty {
+    /// Creates a property. Like systemctl, youki takes time spans in seconds
+    /// for the properties ending with "Sec" and passes them to systemd in
+    /// microseconds, as systemd only knows the properties ending with "USec".
+    pub fn new(name: &str, value: PropertyValue) -> Result<Self> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!(CgroupError::spec(format!(
+                "invalid name {:?} of a systemd property",
+                name
+            )));
+        }
+        // the process of the container is always the only one of the unit
+        if name == "PIDs" {
+            bail!("systemd property PIDs is set by youki");
+        }
+
+        if let Some(base) = name.strip_suffix("Sec").filter(|b| !b.ends_with('U')) {
+            let seconds = value.as_seconds().with_context(|| {
+                format!(
+                    "systemd property {} must be a positive number of seconds",
+                    name
+                )
+            })?;
+            return Ok(UnitProperty {
+                name: format!("{}USec", base),
+                value: PropertyValue::U64((seconds * 1_000_000.0) as u64),
+            });
+        }
+
+        Ok(UnitProperty {
+            name: name.to_owned(),
+            value,
+        })
+    }
+
+    /// Reads the properties of the unit from the annotations of the spec
+    pub fn from_annotations(annotations: Option<&HashMap<String, String>>) -> Result<Vec<Self>> {
+        let mut properties = Vec::new();
+        for (key, value) in annotations.into_iter().flatten() {
+            if let Some(name) = key.strip_prefix(PROPERTY_ANNOTATION_PREFIX) {
+                let property = value
+                    .parse()
+                    .and_then(|value| UnitProperty::new(name, value))
+                    .with_context(|| format!("invalid annotation {}", key))?;
+                properties.push(property);
+            }
+        }
+
+        properties.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(properties)
+    }
+}
+
+/// Properties of the limits which systemd knows in the same form for cgroup
+/// v1 and v2. They have to match the limits youki applies to the cgroup.
+pub fn resource_properties(linux_resources: &LinuxResources) -> Vec<UnitProperty> {
+    let mut properties = Vec::new();
+    if let Some(pids) = &linux_resources.pids {
+        properties.push(UnitProperty {
+            name: "TasksMax".to_owned(),
+            // like the pids controller, youki takes limits below one as unlimited
+            value: PropertyValue::U64(if pids.limit > 0 {
+                pids.limit as u64
+            } else {
+                u64::MAX
+            }),
+        });
+    }
+
+    if let Some(quota) = linux_resources.cpu.as_ref().and_then(|cpu| cpu.quota) {
+        let period = linux_resources
+            .cpu
+            .as_ref()
+            .and_then(|cpu| cpu.period)
+            .filter(|period| *period > 0)
+            .unwrap_or(DEFAULT_CPU_PERIOD);
+        let quota_per_sec = if quota > 0 {
+            // systemd rounds the quota up to the next 10ms
+            let usec = (quota as u64).saturating_mul(1_000_000) / period;
+            (usec + 9_999) / 10_000 * 10_000
+        } else {
+            u64::MAX
+        };
+        properties.push(UnitProperty {
+            name: "CPUQuotaPerSecUSec".to_owned(),
+            value: PropertyValue::U64(quota_per_sec),
+        });
+    }
+
+    properties
+}
+
+/// Converts a limit of the spec, where negative values are unlimited, into
+/// a limit of systemd, where "infinity" is u64::MAX
+pub fn unit_limit(limit: i64) -> u64 {
+    if limit < 0 {
+        u64::MAX
+    } else {
+        limit as u64
+    }
+}
+
+/// Cgroup of the systemd instance of the current user relative to the cgroup
+/// root, e.g. "/user.slice/user-1000.slice/user@1000.service". The cgroups of
+/// the units which are created by rootless containers are located below it.
+pub fn user_cgroup() -> Result<PathBuf> {
+    let control_group = Client::new_session()?.control_group()?;
+    Ok(PathBuf::from(control_group))
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    ['\'', '"'].iter().find_map(|quote| {
+        value
+            .strip_prefix(*quote)
+            .and_then(|v| v.strip_suffix(*quote))
+            .filter(|v| !v.contains(*quote))
+    })
+}
+
+// Parses the items of an array of quoted strings, e.g. "'a', 'b'"
+fn parse_string_array(items: &str) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut rest = items.trim_start();
+    while !rest.is_empty() {
+        let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"');
+        let quote = quote.with_context(|| format!("expected a quoted string at {:?}", rest))?;
+        let end = rest[1..]
+            .find(quote)
+            .with_context(|| format!("unterminated string at {:?}", rest))?;
+        strings.push(rest[1..end + 1].to_owned());
+
+        rest = rest[end + 2..].trim_start();
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next.trim_start();
+            if rest.is_empty() {
+                bail!("trailing comma in array");
+            }
+        } else if !rest.is_empty() {
+            bail!("expected a comma at {:?}", rest);
+        }
+    }
+
+    Ok(strings)
+}
+
+/// Represents the systemd cgroups path:
+/// It should be of the form [slice]:[scope_prefix]:[name].
+/// The slice is the "parent" and should be expanded properly,
+/// see expand_slice below.
+#[derive(Clone)]
+struct CgroupsPath {
+    parent: String,
+    scope: String,
+    name: String,
+}
+
+/// Unit of the container, which is created on demand and removed by systemd
+/// once it has been stopped
+pub struct TransientUnit {
+    name: String,
+    slice: String,
+    cgroups_path: PathBuf,
+    properties: Vec<UnitProperty>,
+}
+
+impl TransientUnit {
+    pub fn new(cgroups_path: PathBuf) -> Result<Self> {
+        let destructured_path = Self::destructure_cgroups_path(cgroups_path)?;
+        let slice = if destructured_path.parent.is_empty() {
+            DEFAULT_SLICE.to_owned()
+        } else {
+            destructured_path.parent.clone()
+        };
+        let name = Self::get_unit_name(destructured_path.clone());
+        let cgroups_path = Self::construct_cgroups_path(destructured_path)?;
+
+        Ok(TransientUnit {
+            name,
+            slice,
+            cgroups_path,
+            properties: Vec::new(),
+        })
+    }
+
+    /// Sets additional properties on the unit when it is created. They take
+    /// precedence over the properties youki sets by default.
+    pub fn with_properties(mut self, properties: Vec<UnitProperty>) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Name of the unit, e.g. "docker-foo.scope"
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Path of the cgroup of the unit relative to the root of a hierarchy,
+    /// e.g. "/machine.slice/docker-foo.scope"
+    pub fn cgroups_path(&self) -> &Path {
+        &self.cgroups_path
+    }
+
+    /// Processes can only be passed to a scope when it is created. The
+    /// processes of other units have to be moved into their cgroup.
+    pub fn is_scope(&self) -> bool {
+        self.name.ends_with(".scope")
+    }
+
+    /// start creates the unit for the process. The cgroup of a scope is
+    /// delegated, so that youki can manage the controllers in it while systemd
+    /// owns its lifecycle.
+    pub fn start(&self, pid: Pid) -> Result<()> {
+        let mut properties: Vec<Property> = vec![
+            property("Description", format!("youki container {}", self.name)),
+            property("DefaultDependencies", false),
+            property("MemoryAccounting", true),
+            property("CPUAccounting", true),
+            property("IOAccounting", true),
+            property("TasksAccounting", true),
+        ];
+
+        // the parent of a slice is given by its name
+        if self.is_scope() {
+            properties.push(property("Slice", self.slice.clone()));
+            properties.push(property("Delegate", true));
+            properties.push(property("PIDs", vec![pid.as_raw() as u32]));
+        }
+
+        for extra in &self.properties {
+            properties.retain(|(name, _)| *name != extra.name);
+            properties.push(extra.value.clone().into_property(&extra.name));
+        }
+
+        Self::client()?
+            .start_transient_unit(&self.name, properties)
+            .with_context(|| format!("failed to create systemd unit {}", self.name))
+    }
+
+    /// set_properties changes the properties of the running unit
+    pub fn set_properties(&self, properties: &[UnitProperty]) -> Result<()> {
+        let properties = properties
+            .iter()
+            .map(|p| p.value.clone().into_property(&p.name))
+            .collect();
+        Self::client()?.set_unit_properties(&self.name, properties)
+    }
+
+    /// update_resources sets the properties of the limits on the running unit.
+    /// systemd writes the limits of a unit into its cgroup again when it is
+    /// reloaded, which would revert the limits youki has applied otherwise.
+    /// The limits are in effect either way, so a failure is only logged.
+    pub fn update_resources(&self, properties: &[UnitProperty]) {
+        if properties.is_empty() {
+            return;
+        }
+        if let Err(err) = self.set_properties(properties) {
+            log::warn!(
+                "failed to set the resources of systemd unit {}, they may be reverted by systemd: {:?}",
+                self.name,
+                err
+            );
+        }
+    }
+
+    /// stop stops the unit, after which systemd removes its cgroup
+    pub fn stop(&self) -> Result<()> {
+        Self::client()?
+            .stop_unit(&self.name)
+            .with_context(|| format!("failed to stop systemd unit {}", self.name))
+    }
+
+    // Connects to the systemd instance which manages the cgroups of the user.
+    // Rootless containers can only create units in the instance of the user.
+    fn client() -> Result<Client> {
+        if unistd::geteuid().is_root() {
+            Client::new_system()
+        } else {
+            Client::new_session()
+        }
+    }
+
+    // The cgroups path follows the convention of runc and is of the form
+    // [slice]:[scope_prefix]:[name], for example "system.slice:crio:1234". The
+    // slice is optional and defaults to 'machine.slice'. The cgroups path may
+    // never be empty as it is defaulted to `:youki:<id>`, see 'get_cgroup_path'
+    // under utils.rs. `/youki/<id>` is still accepted, as it has been the
+    // default of earlier versions.
+    fn destructure_cgroups_path(cgroups_path: PathBuf) -> Result<CgroupsPath> {
+        let path = cgroups_path
+            .to_str()
+            .ok_or_else(|| anyhow!("cgroups path {:?} is not valid utf-8", cgroups_path))?;
+
+        let (parent, scope, name) = if let Some(name) = path.strip_prefix("/youki/") {
+            ("", "youki", name)
+        } else {
+            match path.split(':').collect::<Vec<&str>>().as_slice() {
+                [parent, scope, name] => (*parent, *scope, *name),
+                _ => bail!(
+                    "cgroups path {:?} must be of the form [slice]:[scope_prefix]:[name] for the systemd cgroup driver",
+                    path
+                ),
+            }
+        };
+
+        if name.is_empty() || name.contains('/') {
+            bail!(CgroupError::spec(format!(
+                "invalid unit name {:?} in cgroups path {:?}",
+                name, path
+            )));
+        }
+        if !parent.is_empty() {
+            // the slice is validated here, so that an invalid one is reported
+            // before systemd is asked to create the unit
+            Self::expand_slice(parent)?;
+        }
+
+        Ok(CgroupsPath {
+            parent: parent.to_owned(),
+            scope: scope.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+
+    /// get_unit_name returns the unit (scope) name from the path provided by the user
+    /// for example: foo:docker:bar returns in 'docker-bar.scope'
+    fn get_unit_name(cgroups_path: CgroupsPath) -> String {
+        // By default we create a scope unless specified explicitly.
+        if cgroups_path.name.ends_with(".slice") {
+            return cgroups_path.name;
+        }
+        if cgroups_path.scope.is_empty() {
+            return format!("{}.scope", cgroups_path.name);
+        }
+        format!("{}-{}.scope", cgroups_path.scope, cgroups_path.name)
+    }
+
+    // systemd represents slice hierarchy using `-`, so we need to follow suit when
+    // generating the path of slice. For example, 'test-a-b.slice' becomes
+    // '/test.slice/test-a.slice/test-a-b.slice'.
+    fn expand_slice(slice: &str) -> Result<PathBuf> {
+        let suffix = ".slice";
+        if slice.len() <= suffix.len() || !slice.ends_with(suffix) || slice.contains('/') {
+            bail!(CgroupError::spec(format!("invalid slice name: {}", slice)));
+        }
+        let slice_name = &slice[..slice.len() - suffix.len()];
+        // if input was -.slice, we should just return root now
+        if slice_name == "-" {
+            return Ok(PathBuf::from("/"));
+        }
+
+        let mut path = PathBuf::from("/");
+        let mut prefix = "".to_owned();
+        for component in slice_name.split('-') {
+            // a slice name may neither start nor end with '-' and may not
+            // contain '--', as its parents would have no name
+            if component.is_empty() {
+                bail!(CgroupError::spec(format!("invalid slice name: {}", slice)));
+            }
+            // Append the component to the path and to the prefix.
+            path.push(format!("{}{}{}", prefix, component, suffix));
+            prefix = format!("{}{}-", prefix, component);
+        }
+        Ok(path)
+    }
+
+    // get_cgroups_path generates a cgroups path from the one provided by the user via cgroupsPath.
+    // an example of the final path: "/machine.slice/docker-foo.scope"
+    fn construct_cgroups_path(cgroups_path: CgroupsPath) -> Result<PathBuf> {
+        let unit_name = Self::get_unit_name(cgroups_path.clone());
+        // systemd places a slice according to its name, regardless of the
+        // parent
+        if unit_name.ends_with(".slice") {
+            return Self::expand_slice(&unit_name);
+        }
+
+        // the root slice is under 'machine.slice'.
+        let mut slice = PathBuf::from("/").join(DEFAULT_SLICE);
+        // if the user provided a '.slice' (as in a branch of a tree)
+        // we need to "unpack it".
+        if !cgroups_path.parent.is_empty() {
+            slice = Self::expand_slice(&cgroups_path.parent)?;
+        }
+        Ok(slice.join(unit_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::LinuxCpuBuilder;
+    use oci_spec::LinuxPids;
+
+    #[test]
+    fn expand_slice_works() -> Result<()> {
+        assert_eq!(
+            TransientUnit::expand_slice("test-a-b.slice")?,
+            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice"),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_cgroups_path_works_with_a_complex_slice() -> Result<()> {
+        let cgroups_path =
+            TransientUnit::destructure_cgroups_path(PathBuf::from("test-a-b.slice:docker:foo"))
+                .expect("");
+
+        assert_eq!(
+            TransientUnit::construct_cgroups_path(cgroups_path)?,
+            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice/docker-foo.scope"),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_cgroups_path_works_with_a_simple_slice() -> Result<()> {
+        let cgroups_path =
+            TransientUnit::destructure_cgroups_path(PathBuf::from("machine.slice:libpod:foo"))
+                .expect("");
+
+        assert_eq!(
+            TransientUnit::construct_cgroups_path(cgroups_path)?,
+            PathBuf::from("/machine.slice/libpod-foo.scope"),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_cgroups_path_works_with_scope() -> Result<()> {
+        let cgroups_path =
+            TransientUnit::destructure_cgroups_path(PathBuf::from(":docker:foo")).expect("");
+
+        assert_eq!(
+            TransientUnit::construct_cgroups_path(cgroups_path)?,
+            PathBuf::from("/machine.slice/docker-foo.scope"),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn expand_slice_rejects_invalid_names() {
+        for slice in &[
+            "",
+            ".slice",
+            "test",
+            "a/b.slice",
+            "-a.slice",
+            "a-.slice",
+            "a--b.slice",
+        ] {
+            assert!(
+                TransientUnit::expand_slice(slice).is_err(),
+                "{} should be invalid",
+                slice
+            );
+        }
+        assert_eq!(
+            TransientUnit::expand_slice("-.slice").unwrap(),
+            PathBuf::from("/")
+        );
+    }
+
+    #[test]
+    fn destructure_cgroups_path_rejects_invalid_paths() {
+        for path in &[
+            "machine.slice:docker",
+            "a:b:c:d",
+            "machine.slice:docker:",
+            "machine:docker:foo",
+            "machine.slice:docker:a/b",
+        ] {
+            assert!(
+                TransientUnit::destructure_cgroups_path(PathBuf::from(path)).is_err(),
+                "{} should be invalid",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn transient_unit_works_with_the_default_path() -> Result<()> {
+        let unit = TransientUnit::new(PathBuf::from("/youki/1234"))?;
+
+        assert_eq!(unit.name(), "youki-1234.scope");
+        assert!(unit.is_scope());
+        assert_eq!(unit.slice, DEFAULT_SLICE);
+        assert_eq!(
+            unit.cgroups_path(),
+            Path::new("/machine.slice/youki-1234.scope")
+        );
+
+        let unit = TransientUnit::new(PathBuf::from("system.slice::1234"))?;
+        assert_eq!(unit.name(), "1234.scope");
+        assert_eq!(unit.cgroups_path(), Path::new("/system.slice/1234.scope"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transient_unit_works_with_a_slice() -> Result<()> {
+        let unit = TransientUnit::new(PathBuf::from("test-a.slice:docker:test-a-b.slice"))?;
+
+        assert_eq!(unit.name(), "test-a-b.slice");
+        assert!(!unit.is_scope());
+        assert_eq!(
+            unit.cgroups_path(),
+            Path::new("/test.slice/test-a.slice/test-a-b.slice")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_property_values() -> Result<()> {
+        let cases = [
+            ("true", PropertyValue::Bool(true)),
+            ("uint64 5000000", PropertyValue::U64(5_000_000)),
+            ("int64 -1", PropertyValue::I64(-1)),
+            ("uint32 100", PropertyValue::U32(100)),
+            ("42", PropertyValue::I32(42)),
+            ("1.5", PropertyValue::Double(1.5)),
+            ("'infinity'", PropertyValue::Str("infinity".to_owned())),
+            (
+                "['a.service', \"b, c.service\"]",
+                PropertyValue::StrArray(vec!["a.service".to_owned(), "b, c.service".to_owned()]),
+            ),
+            ("[]", PropertyValue::StrArray(Vec::new())),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(&value.parse::<PropertyValue>()?, expected, "{}", value);
+        }
+
+        for value in &["", "uint64 -1", "yes", "'unterminated", "[1, 2]", "['a',]"] {
+            assert!(value.parse::<PropertyValue>().is_err(), "{}", value);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unit_properties_from_annotations() -> Result<()> {
+        let mut annotations = HashMap::new();
+        annotations.insert(
+            "org.systemd.property.TimeoutStopSec".to_owned(),
+            "1.5".to_owned(),
+        );
+        annotations.insert(
+            "org.systemd.property.CollectMode".to_owned(),
+            "'inactive-or-failed'".to_owned(),
+        );
+        annotations.insert("org.other".to_owned(), "true".to_owned());
+
+        assert_eq!(
+            UnitProperty::from_annotations(Some(&annotations))?,
+            vec![
+                UnitProperty {
+                    name: "CollectMode".to_owned(),
+                    value: PropertyValue::Str("inactive-or-failed".to_owned()),
+                },
+                UnitProperty {
+                    name: "TimeoutStopUSec".to_owned(),
+                    value: PropertyValue::U64(1_500_000),
+                },
+            ]
+        );
+
+        annotations.insert(
+            "org.systemd.property.RuntimeMaxSec".to_owned(),
+            "'forever'".to_owned(),
+        );
+        assert!(UnitProperty::from_annotations(Some(&annotations)).is_err());
+        assert!(UnitProperty::new("PIDs", PropertyValue::U32(1)).is_err());
+        assert!(UnitProperty::new("Bad-Name", PropertyValue::Bool(true)).is_err());
+        assert!(UnitProperty::from_annotations(None)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resource_properties_match_the_cgroup() {
+        let resources = LinuxResources {
+            pids: Some(LinuxPids { limit: 0 }),
+            cpu: Some(LinuxCpuBuilder::new().with_quota(33_333).build()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            resource_properties(&resources),
+            vec![
+                UnitProperty {
+                    name: "TasksMax".to_owned(),
+                    value: PropertyValue::U64(u64::MAX),
+                },
+                UnitProperty {
+                    name: "CPUQuotaPerSecUSec".to_owned(),
+                    value: PropertyValue::U64(340_000),
+                },
+            ]
+        );
+        assert!(resource_properties(&LinuxResources::default()).is_empty());
+    }
+}
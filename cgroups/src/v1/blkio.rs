@@ -1,7 +1,7 @@
 use std::path::Path;
 
 use crate::{
-    common,
+    common::{self, UnsupportedSettings},
     stats::{self, BlkioDeviceStat, BlkioStats, StatsProvider},
     v1::Controller,
 };
@@ -38,36 +38,50 @@ const BLKIO_WEIGHT: &str = "blkio.weight";
 // Specifies the relative proportion of block I/O access for specific devices available
 // to the cgroup. This overrides the the blkio.weight value for the specified device
 // Format: Major:Minor weight (weight can range from 100 to 1000)
-#[allow(dead_code)]
 const BLKIO_WEIGHT_DEVICE: &str = "blkio.weight_device";
-
-// Common parameters which may be used for either policy but seem to be used only for
-// proportional weight division policy in practice
+// Weight of the cgroup in relation to its child cgroups, only with CFQ
+// Format: weight (weight can range from 10 to 1000)
+const BLKIO_LEAF_WEIGHT: &str = "blkio.leaf_weight";
+// Weight of the cgroup for a specific device in relation to its child cgroups
+// Format: Major:Minor weight (weight can range from 10 to 1000)
+const BLKIO_LEAF_WEIGHT_DEVICE: &str = "blkio.leaf_weight_device";
+// CFQ has been removed in linux 5.0, BFQ provides the weights in its own files
+// Format: weight (weight can range from 1 to 1000)
+const BLKIO_BFQ_WEIGHT: &str = "blkio.bfq.weight";
+// Format: Major:Minor weight (weight can range from 1 to 1000)
+const BLKIO_BFQ_WEIGHT_DEVICE: &str = "blkio.bfq.weight_device";
+
+// Statistics of the io schedulers, which include the nested cgroups. CFQ
+// reports them in files with the blkio. prefix and BFQ in files with the
+// blkio.bfq. prefix. BFQ only reports the bytes and operations, unless the
+// kernel is built with CONFIG_BFQ_CGROUP_DEBUG.
 // ---------------------------------------
+const BLKIO_CFQ_PREFIX: &str = "blkio.";
+const BLKIO_BFQ_PREFIX: &str = "blkio.bfq.";
 // Time in milliseconds that the cgroup had access to a device
 // Format: Major:Minor Time(ms)
-const BLKIO_TIME: &str = "blkio.time_recursive";
+const BLKIO_TIME: &str = "time_recursive";
 // Number of sectors transferred to/from a device by the cgroup
 // Format: Major:Minor Sectors
-const BLKIO_SECTORS: &str = "blkio.sectors_recursive";
+const BLKIO_SECTORS: &str = "sectors_recursive";
 // Number of bytes transfered to/from a device by the cgroup
 /// Format: Major:Minor Type Bytes
-const BLKIO_IO_SERVICE_BYTES: &str = "blkio.io_service_bytes_recursive";
+const BLKIO_IO_SERVICE_BYTES: &str = "io_service_bytes_recursive";
 // Number of I/O operations performed on a device by the cgroup
 // Format: Major:Minor Type Ops
-const BLKIO_IO_SERVICED: &str = "blkio.io_serviced_recursive";
+const BLKIO_IO_SERVICED: &str = "io_serviced_recursive";
 // Total time between request dispatch and request completion
 //// Format: Major:Minor Type Time(ns)
-const BLKIO_IO_SERVICE_TIME: &str = "blkio.io_service_time_recursive";
+const BLKIO_IO_SERVICE_TIME: &str = "io_service_time_recursive";
 // Total time spend waiting in the scheduler queues for service
 // Format: Major:Minor Type Time(ns)
-const BLKIO_WAIT_TIME: &str = "blkio.io_wait_time_recursive";
+const BLKIO_WAIT_TIME: &str = "io_wait_time_recursive";
 // Number of requests queued for I/O operations
 // Format: Requests Type
-const BLKIO_QUEUED: &str = "blkio.io_queued_recursive";
+const BLKIO_QUEUED: &str = "io_queued_recursive";
 // Number of requests merged into requests for I/O operations
 // Format: Requests Type
-const BLKIO_MERGED: &str = "blkio.io_merged_recursive";
+const BLKIO_MERGED: &str = "io_merged_recursive";
 
 pub struct Blkio {}
 
@@ -97,8 +111,13 @@ impl StatsProvider for Blkio {
     type Stats = BlkioStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        if cgroup_path.join(BLKIO_WEIGHT).exists() {
-            return Self::get_weight_division_policy_stats(cgroup_path);
+        // the files of a scheduler exist even if none of the devices uses it,
+        // in which case only the throttling policy accounts the io
+        for prefix in &[BLKIO_CFQ_PREFIX, BLKIO_BFQ_PREFIX] {
+            let stats = Self::get_weight_division_policy_stats(cgroup_path, prefix)?;
+            if !stats.serviced.is_empty() {
+                return Ok(stats);
+            }
         }
 
         Self::get_throttling_policy_stats(cgroup_path)
@@ -107,39 +126,92 @@ impl StatsProvider for Blkio {
 
 impl Blkio {
     fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> Result<()> {
-        if let Some(throttle_read_bps_device) = blkio.throttle_read_bps_device.as_ref() {
-            for trbd in throttle_read_bps_device {
+        let mut unsupported = UnsupportedSettings::default();
+        Self::apply_weights(root_path, blkio, &mut unsupported)?;
+
+        let throttles = [
+            (BLKIO_THROTTLE_READ_BPS, &blkio.throttle_read_bps_device),
+            (BLKIO_THROTTLE_WRITE_BPS, &blkio.throttle_write_bps_device),
+            (BLKIO_THROTTLE_READ_IOPS, &blkio.throttle_read_iops_device),
+            (BLKIO_THROTTLE_WRITE_IOPS, &blkio.throttle_write_iops_device),
+        ];
+        for (file, devices) in throttles.iter() {
+            // the file takes one device per write
+            for device in devices.iter().flatten() {
                 common::write_cgroup_file_str(
-                    &root_path.join(BLKIO_THROTTLE_READ_BPS),
-                    &format!("{}:{} {}", trbd.major, trbd.minor, trbd.rate),
+                    &root_path.join(file),
+                    &format!("{}:{} {}", device.major, device.minor, device.rate),
                 )?;
             }
         }
 
-        if let Some(throttle_write_bps_device) = blkio.throttle_write_bps_device.as_ref() {
-            for twbd in throttle_write_bps_device {
-                common::write_cgroup_file_str(
-                    &root_path.join(BLKIO_THROTTLE_WRITE_BPS),
-                    &format!("{}:{} {}", twbd.major, twbd.minor, twbd.rate),
-                )?;
-            }
+        unsupported.check()
+    }
+
+    // A weight of 0 is not valid and means that the weight is not set. The
+    // leaf weights are only supported by the cfq scheduler.
+    fn apply_weights(
+        root_path: &Path,
+        blkio: &LinuxBlockIo,
+        unsupported: &mut UnsupportedSettings,
+    ) -> Result<()> {
+        let (weight_file, weight_device_file) = if root_path.join(BLKIO_WEIGHT).exists() {
+            (BLKIO_WEIGHT, BLKIO_WEIGHT_DEVICE)
+        } else {
+            (BLKIO_BFQ_WEIGHT, BLKIO_BFQ_WEIGHT_DEVICE)
+        };
+
+        if let Some(weight) = blkio.weight.filter(|w| *w != 0) {
+            common::write_cgroup_file(root_path.join(weight_file), weight)
+                .context("failed to set blkio weight")?;
         }
 
-        if let Some(throttle_read_iops_device) = blkio.throttle_read_iops_device.as_ref() {
-            for trid in throttle_read_iops_device {
-                common::write_cgroup_file_str(
-                    &root_path.join(BLKIO_THROTTLE_READ_IOPS),
-                    &format!("{}:{} {}", trid.major, trid.minor, trid.rate),
-                )?;
+        let cfq = root_path.join(BLKIO_LEAF_WEIGHT).exists();
+        if let Some(leaf_weight) = blkio.leaf_weight.filter(|w| *w != 0) {
+            if cfq {
+                common::write_cgroup_file(root_path.join(BLKIO_LEAF_WEIGHT), leaf_weight)
+                    .context("failed to set blkio leaf weight")?;
+            } else {
+                unsupported.add("blkio leaf weight (requires the cfq scheduler)");
             }
         }
 
-        if let Some(throttle_write_iops_device) = blkio.throttle_write_iops_device.as_ref() {
-            for twid in throttle_write_iops_device {
-                common::write_cgroup_file_str(
-                    &root_path.join(BLKIO_THROTTLE_WRITE_IOPS),
-                    &format!("{}:{} {}", twid.major, twid.minor, twid.rate),
-                )?;
+        for device in blkio.weight_device.iter().flatten() {
+            if let Some(weight) = device.weight.filter(|w| *w != 0) {
+                common::write_block_device_setting(
+                    root_path.join(weight_device_file),
+                    device.major,
+                    device.minor,
+                    weight,
+                )
+                .with_context(|| {
+                    format!(
+                        "failed to set blkio weight of device {}:{}",
+                        device.major, device.minor
+                    )
+                })?;
+            }
+
+            if let Some(leaf_weight) = device.leaf_weight.filter(|w| *w != 0) {
+                if !cfq {
+                    unsupported.add(format!(
+                        "blkio leaf weight of device {}:{} (requires the cfq scheduler)",
+                        device.major, device.minor
+                    ));
+                    continue;
+                }
+                common::write_block_device_setting(
+                    root_path.join(BLKIO_LEAF_WEIGHT_DEVICE),
+                    device.major,
+                    device.minor,
+                    leaf_weight,
+                )
+                .with_context(|| {
+                    format!(
+                        "failed to set blkio leaf weight of device {}:{}",
+                        device.major, device.minor
+                    )
+                })?;
             }
         }
 
@@ -158,16 +230,27 @@ impl Blkio {
         Ok(stats)
     }
 
-    fn get_weight_division_policy_stats(cgroup_path: &Path) -> Result<BlkioStats> {
+    fn get_weight_division_policy_stats(cgroup_path: &Path, prefix: &str) -> Result<BlkioStats> {
+        // files which are not reported by the scheduler are left empty
+        let parse = |file: &str| {
+            let path = cgroup_path.join(format!("{}{}", prefix, file));
+            if !path.exists() {
+                return Ok(Vec::new());
+            }
+
+            Self::parse_blkio_file(&path)
+        };
+
         let stats = BlkioStats {
-            time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_TIME))?,
-            sectors: Self::parse_blkio_file(&cgroup_path.join(BLKIO_SECTORS))?,
-            service_bytes: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICE_BYTES))?,
-            serviced: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICED))?,
-            service_time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICE_TIME))?,
-            wait_time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_WAIT_TIME))?,
-            queued: Self::parse_blkio_file(&cgroup_path.join(BLKIO_QUEUED))?,
-            merged: Self::parse_blkio_file(&cgroup_path.join(BLKIO_MERGED))?,
+            time: parse(BLKIO_TIME)?,
+            sectors: parse(BLKIO_SECTORS)?,
+            service_bytes: parse(BLKIO_IO_SERVICE_BYTES)?,
+            serviced: parse(BLKIO_IO_SERVICED)?,
+            service_time: parse(BLKIO_IO_SERVICE_TIME)?,
+            wait_time: parse(BLKIO_WAIT_TIME)?,
+            queued: parse(BLKIO_QUEUED)?,
+            merged: parse(BLKIO_MERGED)?,
+            ..Default::default()
         };
 
         Ok(stats)
@@ -228,7 +311,7 @@ mod tests {
     use crate::test::{create_temp_dir, set_fixture, setup};
 
     use anyhow::Result;
-    use oci_spec::{LinuxBlockIo, LinuxThrottleDevice};
+    use oci_spec::{LinuxBlockIo, LinuxThrottleDevice, LinuxWeightDevice};
 
     struct BlockIoBuilder {
         block_io: LinuxBlockIo,
@@ -249,6 +332,16 @@ mod tests {
             Self { block_io }
         }
 
+        fn with_weight(mut self, weight: u16) -> Self {
+            self.block_io.weight = Some(weight);
+            self
+        }
+
+        fn with_weight_device(mut self, devices: Vec<LinuxWeightDevice>) -> Self {
+            self.block_io.weight_device = devices.into();
+            self
+        }
+
         fn with_read_bps(mut self, throttle: Vec<LinuxThrottleDevice>) -> Self {
             self.block_io.throttle_read_bps_device = throttle.into();
             self
@@ -350,6 +443,51 @@ mod tests {
         assert_eq!("8:0 102400", content);
     }
 
+    #[test]
+    fn test_set_blkio_weight() {
+        let (tmp, weight) = setup("test_set_blkio_weight", BLKIO_WEIGHT);
+        let weight_device = set_fixture(&tmp, BLKIO_WEIGHT_DEVICE, "").unwrap();
+
+        let blkio = BlockIoBuilder::new()
+            .with_weight(500)
+            .with_weight_device(vec![LinuxWeightDevice {
+                major: 8,
+                minor: 0,
+                weight: Some(200),
+                leaf_weight: None,
+            }])
+            .build();
+
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content =
+            fs::read_to_string(weight).unwrap_or_else(|_| panic!("read {} content", BLKIO_WEIGHT));
+        assert_eq!("500", content);
+        let content = fs::read_to_string(weight_device)
+            .unwrap_or_else(|_| panic!("read {} content", BLKIO_WEIGHT_DEVICE));
+        assert_eq!("8:0 200", content);
+    }
+
+    #[test]
+    fn test_set_blkio_bfq_weight() {
+        let (tmp, weight) = setup("test_set_blkio_bfq_weight", BLKIO_BFQ_WEIGHT);
+
+        let blkio = BlockIoBuilder::new().with_weight(500).build();
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content = fs::read_to_string(weight)
+            .unwrap_or_else(|_| panic!("read {} content", BLKIO_BFQ_WEIGHT));
+        assert_eq!("500", content);
+
+        // the leaf weight only exists with cfq
+        let mut blkio = BlockIoBuilder::new().with_weight(600).build();
+        blkio.leaf_weight = Some(300);
+        let err = Blkio::apply(&tmp, &blkio).unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedSettings>().is_some());
+        // the other settings are still applied
+        let content = fs::read_to_string(tmp.join(BLKIO_BFQ_WEIGHT))
+            .unwrap_or_else(|_| panic!("read {} content", BLKIO_BFQ_WEIGHT));
+        assert_eq!("600", content);
+    }
+
     #[test]
     fn test_stat_throttling_policy() -> Result<()> {
         let tmp = create_temp_dir("test_stat_throttling_policy").expect("create test directory");
@@ -385,4 +523,39 @@ mod tests {
         assert_eq!(expected, actual);
         Ok(())
     }
+
+    #[test]
+    fn test_stat_bfq_policy() -> Result<()> {
+        let tmp = create_temp_dir("test_stat_bfq_policy").expect("create test directory");
+        // cfq is not used by any device
+        set_fixture(&tmp, "blkio.io_serviced_recursive", "Total 0\n")?;
+        set_fixture(
+            &tmp,
+            "blkio.bfq.io_serviced_recursive",
+            "8:0 Read 3\nTotal 3\n",
+        )?;
+        set_fixture(
+            &tmp,
+            "blkio.bfq.io_service_bytes_recursive",
+            "8:0 Read 4096\n",
+        )?;
+        set_fixture(&tmp, "blkio.bfq.io_merged_recursive", "8:0 Read 1\n")?;
+        set_fixture(&tmp, BLKIO_THROTTLE_IO_SERVICED, "8:0 Read 5\n")?;
+
+        let actual = Blkio::stats(&tmp).expect("get cgroup stats");
+        let stat = |value| {
+            vec![BlkioDeviceStat {
+                major: 8,
+                minor: 0,
+                op_type: Some("Read".to_owned()),
+                value,
+            }]
+        };
+        assert_eq!(actual.serviced, stat(3));
+        assert_eq!(actual.service_bytes, stat(4096));
+        assert_eq!(actual.merged, stat(1));
+        // only reported with CONFIG_BFQ_CGROUP_DEBUG
+        assert!(actual.time.is_empty());
+        Ok(())
+    }
 }
@@ -1,10 +1,10 @@
 use std::path::Path;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use oci_spec::{LinuxCpu, LinuxResources};
 
 use crate::{
-    common,
+    common::{self, CpuRampdown},
     stats::{CpuThrottling, StatsProvider},
 };
 
@@ -56,44 +56,59 @@ impl StatsProvider for Cpu {
         let stat_path = cgroup_path.join(CGROUP_CPU_STAT);
         let stat_content = common::read_cgroup_file(&stat_path)?;
 
-        let parts: Vec<&str> = stat_content.split_ascii_whitespace().collect();
-        if parts.len() < 6 {
-            bail!(
-                "{} contains less than the expected number of entries",
-                stat_path.display()
-            );
-        }
-
-        if parts[0] != "nr_periods" {
-            bail!(
-                "{} does not contain the number of elapsed periods",
-                stat_path.display()
-            );
-        }
-
-        if parts[2] != "nr_throttled" {
-            bail!(
-                "{} does not contain the number of throttled periods",
-                stat_path.display()
-            );
-        }
+        // newer kernels report additional entries, e.g. for the cpu burst
+        for entry in stat_content.lines() {
+            let parts: Vec<&str> = entry.split_ascii_whitespace().collect();
+            if parts.len() != 2 {
+                continue;
+            }
 
-        if parts[4] != "throttled_time" {
-            bail!(
-                "{} does not contain the total time tasks have spent throttled",
-                stat_path.display()
-            );
+            let field = match parts[0] {
+                "nr_periods" => &mut stats.periods,
+                "nr_throttled" => &mut stats.throttled_periods,
+                "throttled_time" => &mut stats.throttled_time,
+                _ => continue,
+            };
+            *field = parts[1].parse().with_context(|| {
+                format!("failed to parse {} of {}", parts[0], stat_path.display())
+            })?;
         }
 
-        stats.periods = parts[1].parse().context("failed to parse nr_periods")?;
-        stats.throttled_periods = parts[3].parse().context("failed to parse nr_throttled")?;
-        stats.throttled_time = parts[5].parse().context("failed to parse throttled time")?;
-
         Ok(stats)
     }
 }
 
 impl Cpu {
+    /// Lowers the cpu quota gradually to the quota of the given cpu resources.
+    /// The quota is lowered within the current period, a new period is set
+    /// when the resources are applied afterwards.
+    pub fn ramp_down(root_path: &Path, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<()> {
+        let target = match cpu.quota {
+            Some(quota) if quota > 0 => quota,
+            _ => return Ok(()),
+        };
+
+        let current_period: u64 = common::read_cgroup_file(root_path.join(CGROUP_CPU_PERIOD))?
+            .trim()
+            .parse()
+            .context("failed to parse cpu period")?;
+        let current_quota: i64 = common::read_cgroup_file(root_path.join(CGROUP_CPU_QUOTA))?
+            .trim()
+            .parse()
+            .context("failed to parse cpu quota")?;
+        let current = if current_quota < 0 {
+            common::unrestricted_quota(current_period)
+        } else {
+            current_quota
+        };
+
+        let period = cpu.period.filter(|p| *p > 0).unwrap_or(current_period);
+        let target = common::scale_quota(target, period, current_period)?;
+        rampdown.run(current, target, |quota| {
+            common::write_cgroup_file(root_path.join(CGROUP_CPU_QUOTA), quota)
+        })
+    }
+
     fn apply(root_path: &Path, cpu: &LinuxCpu) -> Result<()> {
         if let Some(cpu_shares) = cpu.shares {
             if cpu_shares != 0 {
@@ -225,6 +240,8 @@ mod tests {
             "nr_periods 165000",
             "nr_throttled 27",
             "throttled_time 1080",
+            "nr_bursts 0",
+            "burst_time 0",
         ]
         .join("\n");
         set_fixture(&tmp, CGROUP_CPU_STAT, stat_content).expect("create stat file");
@@ -237,4 +254,27 @@ mod tests {
         };
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_ramp_down_quota() {
+        // arrange
+        let (tmp, quota) = setup("test_ramp_down_quota", CGROUP_CPU_QUOTA);
+        set_fixture(&tmp, CGROUP_CPU_QUOTA, "200000")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_QUOTA));
+        set_fixture(&tmp, CGROUP_CPU_PERIOD, "100000")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_PERIOD));
+        let cpu = LinuxCpuBuilder::new().with_quota(100000).build();
+        let rampdown = CpuRampdown {
+            duration: std::time::Duration::from_millis(0),
+            steps: 2,
+        };
+
+        // act
+        Cpu::ramp_down(&tmp, &cpu, &rampdown).expect("ramp down cpu");
+
+        // assert
+        let content = fs::read_to_string(quota)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_QUOTA));
+        assert_eq!(content, "100000");
+    }
 }
@@ -76,12 +76,17 @@ impl CpuAcct {
             );
         }
 
-        stats.usage_user = parts[1]
+        // the usage is reported in clock ticks, but in nanoseconds everywhere else
+        let ticks_per_second =
+            procfs::ticks_per_second().context("failed to get the clock ticks per second")? as u64;
+        let user: u64 = parts[1]
             .parse()
             .context("failed to parse user mode cpu usage")?;
-        stats.usage_kernel = parts[3]
+        let kernel: u64 = parts[3]
             .parse()
             .context("failed to parse kernel mode cpu usage")?;
+        stats.usage_user = ticks_to_nanos(user, ticks_per_second);
+        stats.usage_kernel = ticks_to_nanos(kernel, ticks_per_second);
 
         let total = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_USAGE))?;
         stats.usage_total = total
@@ -93,7 +98,20 @@ impl CpuAcct {
     }
 
     fn get_per_core_usage(cgroup_path: &Path, stats: &mut CpuUsage) -> Result<()> {
-        let all_content = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_USAGE_ALL))?;
+        let percpu_content = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_PERCPU))?;
+        stats.per_core_usage_total = percpu_content
+            .split_ascii_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to parse per core cpu usage")?;
+
+        // the usage by mode is only itemized per core since linux 4.16
+        let usage_all = cgroup_path.join(CGROUP_CPUACCT_USAGE_ALL);
+        if !usage_all.exists() {
+            return Ok(());
+        }
+
+        let all_content = common::read_cgroup_file(usage_all)?;
         // first line is header, skip it
         for entry in all_content.lines().skip(1) {
             let entry_parts: Vec<&str> = entry.split_ascii_whitespace().collect();
@@ -113,17 +131,15 @@ impl CpuAcct {
             );
         }
 
-        let percpu_content = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_PERCPU))?;
-        stats.per_core_usage_total = percpu_content
-            .split_ascii_whitespace()
-            .map(|v| v.parse())
-            .collect::<Result<Vec<_>, _>>()
-            .context("failed to parse per core cpu usage")?;
-
         Ok(())
     }
 }
 
+fn ticks_to_nanos(ticks: u64, ticks_per_second: u64) -> u64 {
+    const NANOS_PER_SECOND: u64 = 1_000_000_000;
+    ticks * (NANOS_PER_SECOND / ticks_per_second)
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -180,8 +196,15 @@ mod tests {
         let mut stats = CpuUsage::default();
         CpuAcct::get_total_cpu_usage(tmp.path(), &mut stats).expect("get cgroup stats");
 
-        assert_eq!(stats.usage_user, 1300888);
-        assert_eq!(stats.usage_kernel, 364592);
+        let ticks_per_second = procfs::ticks_per_second().expect("get clock ticks") as u64;
+        assert_eq!(
+            stats.usage_user,
+            1300888 * (1_000_000_000 / ticks_per_second)
+        );
+        assert_eq!(
+            stats.usage_kernel,
+            364592 * (1_000_000_000 / ticks_per_second)
+        );
         assert_eq!(stats.usage_total, 18198092369681);
     }
 
@@ -220,4 +243,19 @@ mod tests {
             &[989683000640, 4409567860144, 4439880333849, 4273328034121]
         );
     }
+
+    #[test]
+    fn test_stat_per_cpu_usage_without_modes() {
+        let tmp = create_temp_dir("test_stat_per_cpu_usage_without_modes")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CPUACCT_PERCPU, "989683000640 4409567860144")
+            .unwrap_or_else(|_| panic!("create {} file", CGROUP_CPUACCT_PERCPU));
+
+        let mut stats = CpuUsage::default();
+        CpuAcct::get_per_core_usage(tmp.path(), &mut stats).expect("get cgroup stats");
+
+        assert_eq!(stats.per_core_usage_total, &[989683000640, 4409567860144]);
+        assert!(stats.per_core_usage_user.is_empty());
+        assert!(stats.per_core_usage_kernel.is_empty());
+    }
 }
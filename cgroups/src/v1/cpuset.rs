@@ -1,4 +1,4 @@
-use std::{fs, path::Path};
+use std::{fs, io::ErrorKind, path::Path};
 
 use anyhow::{bail, Context, Result};
 use nix::unistd;
@@ -18,10 +18,8 @@ impl Controller for CpuSet {
     type Resource = LinuxCpu;
 
     fn add_task(pid: Pid, cgroup_path: &Path) -> Result<()> {
-        fs::create_dir_all(cgroup_path)?;
-
-        Self::ensure_not_empty(cgroup_path, CGROUP_CPUSET_CPUS)?;
-        Self::ensure_not_empty(cgroup_path, CGROUP_CPUSET_MEMS)?;
+        let root = util::get_subsystem_mount_point(&ControllerType::CpuSet)?;
+        Self::create_cgroup(&root, cgroup_path)?;
 
         common::write_cgroup_file(cgroup_path.join(CGROUP_PROCS), pid)?;
         Ok(())
@@ -51,37 +49,67 @@ impl Controller for CpuSet {
 
 impl CpuSet {
     fn apply(cgroup_path: &Path, cpuset: &LinuxCpu) -> Result<()> {
-        if let Some(cpus) = &cpuset.cpus {
+        // an empty value would leave the cgroup without cpus or memory nodes,
+        // so it is treated as unset and the value of the parent is kept
+        if let Some(cpus) = cpuset.cpus.as_deref().filter(|cpus| !cpus.is_empty()) {
             common::write_cgroup_file_str(cgroup_path.join(CGROUP_CPUSET_CPUS), cpus)?;
         }
 
-        if let Some(mems) = &cpuset.mems {
+        if let Some(mems) = cpuset.mems.as_deref().filter(|mems| !mems.is_empty()) {
             common::write_cgroup_file_str(cgroup_path.join(CGROUP_CPUSET_MEMS), mems)?;
         }
 
         Ok(())
     }
 
-    // if a task is moved into the cgroup and a value has not been set for cpus and mems
-    // Errno 28 (no space left on device) will be returned. Therefore we set the value from the parent if required.
-    fn ensure_not_empty(cgroup_path: &Path, interface_file: &str) -> Result<()> {
-        let mut current = util::get_subsystem_mount_point(&ControllerType::CpuSet)?;
-        let relative_cgroup_path = cgroup_path.strip_prefix(&current)?;
-
+    // A task can not be moved into a cgroup whose cpus or mems are empty, which
+    // fails with Errno 28 (no space left on device). The values of a new cgroup are
+    // empty, therefore every directory on the way to the cgroup is created one by
+    // one and gets the values of its parent before its children are created.
+    fn create_cgroup(root: &Path, cgroup_path: &Path) -> Result<()> {
+        let relative_cgroup_path = cgroup_path.strip_prefix(root).with_context(|| {
+            format!(
+                "cgroup {:?} is not below the cpuset root {:?}",
+                cgroup_path, root
+            )
+        })?;
+
+        let mut current = root.to_path_buf();
         for component in relative_cgroup_path.components() {
-            let parent_value = fs::read_to_string(current.join(interface_file))?;
-            if parent_value.trim().is_empty() {
-                bail!("cpuset parent value is empty")
+            let parent = current.clone();
+            current.push(component);
+            match fs::create_dir(&current) {
+                Err(err) if err.kind() != ErrorKind::AlreadyExists => {
+                    return Err(err).with_context(|| format!("failed to create {:?}", current))
+                }
+                _ => {}
             }
 
-            current.push(component);
-            let child_path = current.join(interface_file);
-            let child_value = fs::read_to_string(&child_path)?;
+            Self::copy_from_parent(&parent, &current)?;
+        }
+
+        Ok(())
+    }
+
+    fn copy_from_parent(parent: &Path, child: &Path) -> Result<()> {
+        for interface_file in &[CGROUP_CPUSET_CPUS, CGROUP_CPUSET_MEMS] {
             // the file can contain a newline character. Need to trim it away,
             // otherwise it is not considered empty and value will not be written
-            if child_value.trim().is_empty() {
-                common::write_cgroup_file_str(&child_path, &parent_value)?;
+            let child_value = common::read_cgroup_file(child.join(interface_file))?;
+            if !child_value.trim().is_empty() {
+                continue;
             }
+
+            let parent_value = common::read_cgroup_file(parent.join(interface_file))?;
+            if parent_value.trim().is_empty() {
+                bail!(
+                    "{} of the parent cgroup {:?} is empty",
+                    interface_file,
+                    parent
+                )
+            }
+
+            common::write_cgroup_file_str(child.join(interface_file), parent_value.trim())?;
         }
 
         Ok(())
@@ -93,7 +121,7 @@ mod tests {
     use std::fs;
 
     use super::*;
-    use crate::test::{setup, LinuxCpuBuilder};
+    use crate::test::{create_temp_dir, set_fixture, setup, LinuxCpuBuilder};
 
     #[test]
     fn test_set_cpus() {
@@ -124,4 +152,44 @@ mod tests {
             .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_MEMS));
         assert_eq!(content, "1-3");
     }
+
+    #[test]
+    fn test_set_empty_cpus() {
+        let (tmp, cpus) = setup("test_set_empty_cpus", CGROUP_CPUSET_CPUS);
+        fs::write(&cpus, "0-3").expect("set cpus");
+        let cpuset = LinuxCpuBuilder::new().with_cpus("".to_owned()).build();
+
+        CpuSet::apply(&tmp, &cpuset).expect("apply cpuset");
+
+        let content = fs::read_to_string(&cpus)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_CPUS));
+        assert_eq!(content, "0-3");
+    }
+
+    #[test]
+    fn test_create_cgroup() {
+        let tmp = create_temp_dir("test_create_cgroup").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CPUSET_CPUS, "0-3\n").unwrap();
+        set_fixture(&tmp, CGROUP_CPUSET_MEMS, "0\n").unwrap();
+        // the kernel creates the interface files together with the directories
+        let parent = tmp.join("youki");
+        let cgroup = parent.join("container");
+        for dir in &[&parent, &cgroup] {
+            fs::create_dir_all(dir).unwrap();
+            set_fixture(dir, CGROUP_CPUSET_CPUS, "\n").unwrap();
+            set_fixture(dir, CGROUP_CPUSET_MEMS, "").unwrap();
+        }
+        set_fixture(&parent, CGROUP_CPUSET_CPUS, "1").unwrap();
+
+        CpuSet::create_cgroup(&tmp, &cgroup).expect("create cgroup");
+
+        for (dir, cpus) in &[(&parent, "1"), (&cgroup, "1")] {
+            let content = fs::read_to_string(dir.join(CGROUP_CPUSET_CPUS)).unwrap();
+            assert_eq!(content, *cpus);
+            let content = fs::read_to_string(dir.join(CGROUP_CPUSET_MEMS)).unwrap();
+            assert_eq!(content, "0");
+        }
+
+        assert!(CpuSet::create_cgroup(&tmp, Path::new("/sys/fs/cgroup/cpuset")).is_err());
+    }
 }
@@ -4,6 +4,7 @@ use anyhow::{bail, Context, Result};
 
 use crate::{
     common,
+    error::CgroupError,
     stats::{supported_page_sizes, HugeTlbStats, StatsProvider},
 };
 
@@ -64,32 +65,58 @@ impl HugeTlb {
             .collect();
         let page_size: u64 = page_size.parse()?;
         if !Self::is_power_of_two(page_size) {
-            bail!("page size must be in the format of 2^(integer)");
+            bail!(CgroupError::spec(
+                "page size must be in the format of 2^(integer)"
+            ));
         }
 
         common::write_cgroup_file(
             root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size)),
             hugetlb.limit,
         )?;
+
+        // since linux 5.7 reserved pages have a limit of their own, which gets
+        // the same value as the limit of the used pages, like runc does
+        let rsvd_path =
+            root_path.join(format!("hugetlb.{}.rsvd.limit_in_bytes", hugetlb.page_size));
+        if rsvd_path.exists() {
+            common::write_cgroup_file(rsvd_path, hugetlb.limit)?;
+        }
+
         Ok(())
     }
 
+    // The files of the reservations account the pages which are reserved as
+    // well as the ones which are in use, so they are preferred if they exist
+    fn file_prefix(cgroup_path: &Path, page_size: &str) -> String {
+        let rsvd = format!("hugetlb.{}.rsvd", page_size);
+        if cgroup_path
+            .join(format!("{}.usage_in_bytes", rsvd))
+            .exists()
+        {
+            rsvd
+        } else {
+            format!("hugetlb.{}", page_size)
+        }
+    }
+
     fn is_power_of_two(number: u64) -> bool {
         (number != 0) && (number & (number - 1)) == 0
     }
 
     fn stats_for_page_size(cgroup_path: &Path, page_size: &str) -> Result<HugeTlbStats> {
         let mut stats = HugeTlbStats::default();
+        let prefix = Self::file_prefix(cgroup_path, page_size);
 
-        let usage_file = format!("hugetlb.{}.usage_in_bytes", page_size);
+        let usage_file = format!("{}.usage_in_bytes", prefix);
         let usage_content = common::read_cgroup_file(cgroup_path.join(usage_file))?;
         stats.usage = usage_content.trim().parse()?;
 
-        let max_file = format!("hugetlb.{}.max_usage_in_bytes", page_size);
+        let max_file = format!("{}.max_usage_in_bytes", prefix);
         let max_content = common::read_cgroup_file(cgroup_path.join(max_file))?;
         stats.max_usage = max_content.trim().parse()?;
 
-        let failcnt_file = format!("hugetlb.{}.failcnt", page_size);
+        let failcnt_file = format!("{}.failcnt", prefix);
         let failcnt_content = common::read_cgroup_file(cgroup_path.join(failcnt_file))?;
         stats.fail_count = failcnt_content.trim().parse()?;
 
@@ -178,4 +205,45 @@ mod tests {
         };
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_set_hugetlb_rsvd() {
+        let tmp = create_temp_dir("test_set_hugetlb_rsvd").expect("create temp directory for test");
+        set_fixture(&tmp, "hugetlb.2MB.limit_in_bytes", "0").expect("set hugetlb limit");
+        set_fixture(&tmp, "hugetlb.2MB.rsvd.limit_in_bytes", "0").expect("set hugetlb rsvd limit");
+
+        let hugetlb = LinuxHugepageLimit {
+            page_size: "2MB".to_owned(),
+            limit: 16384,
+        };
+        HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+        for file in &[
+            "hugetlb.2MB.limit_in_bytes",
+            "hugetlb.2MB.rsvd.limit_in_bytes",
+        ] {
+            let content = read_to_string(tmp.join(file)).expect("Read hugetlb file content");
+            assert_eq!(content, "16384");
+        }
+    }
+
+    #[test]
+    fn test_stat_hugetlb_rsvd() {
+        let tmp =
+            create_temp_dir("test_stat_hugetlb_rsvd").expect("create temp directory for test");
+        set_fixture(&tmp, "hugetlb.2MB.usage_in_bytes", "1024\n").expect("set hugetlb usage");
+        set_fixture(&tmp, "hugetlb.2MB.rsvd.usage_in_bytes", "2048\n")
+            .expect("set hugetlb rsvd usage");
+        set_fixture(&tmp, "hugetlb.2MB.rsvd.max_usage_in_bytes", "8192\n")
+            .expect("set hugetlb rsvd max usage");
+        set_fixture(&tmp, "hugetlb.2MB.rsvd.failcnt", "2").expect("set hugetlb rsvd fail count");
+
+        let actual = HugeTlb::stats_for_page_size(&tmp, "2MB").expect("get cgroup stats");
+
+        let expected = HugeTlbStats {
+            usage: 2048,
+            max_usage: 8192,
+            fail_count: 2,
+        };
+        assert_eq!(actual, expected);
+    }
 }
@@ -1,10 +1,13 @@
-use std::fs;
 use std::path::Path;
-use std::{collections::HashMap, path::PathBuf};
+use std::sync::mpsc::Receiver;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use anyhow::bail;
-use anyhow::Result;
-use nix::unistd::Pid;
+use anyhow::{anyhow, bail};
+use anyhow::{Context, Result};
+use nix::unistd::{self, AccessFlags, Pid};
 
 use procfs::process::Process;
 
@@ -13,47 +16,126 @@ use super::{
     blkio::Blkio, controller_type::CONTROLLERS, cpu::Cpu, cpuacct::CpuAcct, cpuset::CpuSet,
     devices::Devices, freezer::Freezer, hugetlb::HugeTlb, memory::Memory,
     network_classifier::NetworkClassifier, network_priority::NetworkPriority,
-    perf_event::PerfEvent, pids::Pids, util, Controller,
+    perf_event::PerfEvent, pids::Pids, rdma::Rdma, Controller,
 };
 
-use crate::common::{self, CgroupManager, PathBufExt, CGROUP_PROCS};
+use crate::common::{self, CgroupHierarchy, CgroupManager, CpuRampdown, PathBufExt, CGROUP_TASKS};
+use crate::error::CgroupError;
+use crate::mounts::{self, CgroupMount};
 use crate::stats::{Stats, StatsProvider};
-use oci_spec::{FreezerState, LinuxResources};
+use oci_spec::{FreezerState, LinuxCpu, LinuxResources};
 pub struct Manager {
     subsystems: HashMap<CtrlType, PathBuf>,
+    // Controllers of the hierarchy each subsystem is attached to, e.g.
+    // "cpu,cpuacct" for the cpu and the cpuacct subsystem
+    hierarchy_names: HashMap<CtrlType, String>,
+    strict: bool,
+    skip_devices: bool,
+    unwritable: HashSet<CtrlType>,
 }
 
 impl Manager {
     /// Constructs a new cgroup manager with cgroups_path being relative to the root of the subsystem
     pub fn new(cgroup_path: PathBuf) -> Result<Self> {
+        Self::with_root(None, cgroup_path)
+    }
+
+    /// Constructs a new cgroup manager for the hierarchies which are mounted
+    /// below the cgroup root. Without a root, the hierarchies are used
+    /// wherever they are mounted.
+    pub fn with_root(cgroup_root: Option<&Path>, cgroup_path: PathBuf) -> Result<Self> {
+        let mounts: Vec<CgroupMount> = mounts::v1_mounts(cgroup_root)?
+            .into_iter()
+            .cloned()
+            .collect();
+        Self::from_mounts(&mounts, cgroup_path)
+    }
+
+    /// Constructs a new cgroup manager for the given hierarchies instead of
+    /// the ones in the mount table of the process, e.g. the hierarchies which
+    /// are parsed from the mountinfo of another mount namespace with
+    /// [mounts::parse_mountinfo]. The unified hierarchy is ignored.
+    pub fn from_mounts(mounts: &[CgroupMount], cgroup_path: PathBuf) -> Result<Self> {
         let mut subsystems = HashMap::<CtrlType, PathBuf>::new();
+        let mut hierarchy_names = HashMap::<CtrlType, String>::new();
         for subsystem in CONTROLLERS {
-            if let Ok(subsystem_path) = Self::get_subsystem_path(&cgroup_path, subsystem) {
+            let controller = subsystem.to_string();
+            let subsystem_path = mounts
+                .iter()
+                .find(|m| !m.is_unified() && m.has_controller(&controller))
+                .map(|mount| {
+                    (
+                        mount,
+                        Self::get_subsystem_path(mount, &cgroup_path, subsystem),
+                    )
+                });
+            if let Some((mount, Ok(subsystem_path))) = subsystem_path {
                 subsystems.insert(subsystem.clone(), subsystem_path);
+                hierarchy_names.insert(subsystem.clone(), mount.controllers().join(","));
             } else {
                 log::warn!("Cgroup {} not supported on this system", subsystem);
             }
         }
 
-        Ok(Manager { subsystems })
+        // like the rootless cgroupfs driver of runc, an unprivileged user or
+        // root of a user namespace can only use the hierarchies in which a
+        // cgroup has been made writable for it by the administrator. Root of
+        // the host may use all of them, e.g. if the permission bits of a
+        // hierarchy deny the access which root is granted anyway.
+        let unwritable = if unistd::geteuid().is_root() && !common::in_user_namespace() {
+            HashSet::new()
+        } else {
+            subsystems
+                .iter()
+                .filter(|(_, path)| !is_writable(path))
+                .map(|(subsystem, _)| subsystem.clone())
+                .collect()
+        };
+        if !unwritable.is_empty() {
+            log::debug!("cgroups are not writable by the user: {:?}", unwritable);
+        }
+
+        Ok(Manager {
+            subsystems,
+            hierarchy_names,
+            strict: false,
+            skip_devices: false,
+            unwritable,
+        })
     }
 
-    fn get_subsystem_path(cgroup_path: &Path, subsystem: &CtrlType) -> Result<PathBuf> {
-        log::debug!("Get path for subsystem: {}", subsystem);
-        let mount_point = util::get_subsystem_mount_point(subsystem)?;
+    /// Fails to apply the resources if a setting is not supported by the host,
+    /// instead of skipping it with a warning
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        let cgroup = Process::myself()?
-            .cgroups()?
-            .into_iter()
-            .find(|c| c.controllers.contains(&subsystem.to_string()))
-            .unwrap();
+    /// Does not apply any device rules, e.g. if the devices controller is
+    /// managed by an outer container
+    pub fn with_skip_devices(mut self, skip_devices: bool) -> Self {
+        self.skip_devices = skip_devices;
+        self
+    }
 
-        let p = if cgroup_path.to_string_lossy().into_owned().is_empty() {
-            mount_point.join_safely(Path::new(&cgroup.pathname))?
-        } else if cgroup_path.is_absolute() {
-            mount_point.join_safely(&cgroup_path)?
+    fn get_subsystem_path(
+        mount: &CgroupMount,
+        cgroup_path: &Path,
+        subsystem: &CtrlType,
+    ) -> Result<PathBuf> {
+        log::debug!("Get path for subsystem: {}", subsystem);
+        let p = if cgroup_path.as_os_str().is_empty() {
+            let cgroup = Process::myself()?
+                .cgroups()?
+                .into_iter()
+                .find(|c| c.controllers.contains(&subsystem.to_string()))
+                .with_context(|| format!("process is not in a cgroup of {}", subsystem))?;
+            // only the subtree below the root of the mount is visible
+            let pathname = Path::new(&cgroup.pathname);
+            let relative = pathname.strip_prefix(&mount.root).unwrap_or(pathname);
+            mount.mount_point.join_safely(relative)?
         } else {
-            mount_point.join(cgroup_path)
+            mount.mount_point.join_safely(cgroup_path)?
         };
 
         Ok(p)
@@ -83,13 +165,14 @@ impl Manager {
                     NetworkClassifier::needs_to_handle(linux_resources).is_some()
                 }
                 CtrlType::Freezer => Freezer::needs_to_handle(linux_resources).is_some(),
+                CtrlType::Rdma => Rdma::needs_to_handle(linux_resources).is_some(),
             };
 
             if required {
                 if let Some(subsystem_path) = self.subsystems.get(controller) {
                     required_controllers.insert(controller, subsystem_path);
                 } else {
-                    bail!("Cgroup {} is required to fullfill the request, but is not supported by this system", controller);
+                    bail!(CgroupError::kernel_feature_missing(format!("Cgroup {} is required to fullfill the request, but is not supported by this system", controller)));
                 }
             }
         }
@@ -99,16 +182,33 @@ impl Manager {
 }
 
 impl CgroupManager for Manager {
-    fn get_all_pids(&self) -> Result<Vec<Pid>> {
+    fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError> {
         let devices = self.subsystems.get(&CtrlType::Devices);
         if let Some(p) = devices {
-            common::get_all_pids(p)
+            Ok(common::get_all_pids(p)?)
         } else {
-            bail!("subsystem does not exist")
+            Err(CgroupError::kernel_feature_missing(
+                "subsystem does not exist",
+            ))
         }
     }
-    fn add_task(&self, pid: Pid) -> Result<()> {
+    fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError> {
+        match self.subsystems.get(&CtrlType::Devices) {
+            Some(path) => Ok(common::get_all_threads(path, CGROUP_TASKS)?),
+            None => Err(CgroupError::kernel_feature_missing(
+                "subsystem does not exist",
+            )),
+        }
+    }
+    fn exists(&self) -> bool {
+        self.subsystems.values().any(|path| path.exists())
+    }
+    fn add_task(&self, pid: Pid) -> Result<(), CgroupError> {
         for subsys in &self.subsystems {
+            if self.unwritable.contains(subsys.0) {
+                continue;
+            }
+
             match subsys.0 {
                 CtrlType::Cpu => Cpu::add_task(pid, subsys.1)?,
                 CtrlType::CpuAcct => CpuAcct::add_task(pid, subsys.1)?,
@@ -122,64 +222,110 @@ impl CgroupManager for Manager {
                 CtrlType::NetworkPriority => NetworkPriority::add_task(pid, subsys.1)?,
                 CtrlType::NetworkClassifier => NetworkClassifier::add_task(pid, subsys.1)?,
                 CtrlType::Freezer => Freezer::add_task(pid, subsys.1)?,
+                CtrlType::Rdma => Rdma::add_task(pid, subsys.1)?,
             }
         }
 
         Ok(())
     }
 
-    fn apply(&self, linux_resources: &LinuxResources) -> Result<()> {
-        for subsys in self.get_required_controllers(linux_resources)? {
-            match subsys.0 {
-                CtrlType::Cpu => Cpu::apply(linux_resources, subsys.1)?,
-                CtrlType::CpuAcct => CpuAcct::apply(linux_resources, subsys.1)?,
-                CtrlType::CpuSet => CpuSet::apply(linux_resources, subsys.1)?,
-                CtrlType::Devices => Devices::apply(linux_resources, subsys.1)?,
-                CtrlType::HugeTlb => HugeTlb::apply(linux_resources, subsys.1)?,
-                CtrlType::Memory => Memory::apply(linux_resources, subsys.1)?,
-                CtrlType::Pids => Pids::apply(linux_resources, subsys.1)?,
-                CtrlType::PerfEvent => PerfEvent::apply(linux_resources, subsys.1)?,
-                CtrlType::Blkio => Blkio::apply(linux_resources, subsys.1)?,
-                CtrlType::NetworkPriority => NetworkPriority::apply(linux_resources, subsys.1)?,
-                CtrlType::NetworkClassifier => NetworkClassifier::apply(linux_resources, subsys.1)?,
-                CtrlType::Freezer => Freezer::apply(linux_resources, subsys.1)?,
+    fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError> {
+        let mut required = self.get_required_controllers(linux_resources)?;
+        let mut unwritable: Vec<String> = required
+            .keys()
+            .filter(|c| self.unwritable.contains(**c))
+            .map(|c| c.to_string())
+            .collect();
+        if !unwritable.is_empty() {
+            unwritable.sort();
+            let unwritable = unwritable.join(", ");
+            if self.strict {
+                return Err(CgroupError::Permission(anyhow!(
+                    "resource limits can not be applied, cgroups {} are not writable by the user",
+                    unwritable
+                )));
             }
+            log::warn!(
+                "resource limits of cgroups {} are not applied, they are not writable by the user",
+                unwritable
+            );
+            required.retain(|c, _| !self.unwritable.contains(*c));
         }
 
-        Ok(())
-    }
+        for subsys in required {
+            if self.skip_devices && *subsys.0 == CtrlType::Devices {
+                log::debug!("skip the device rules of the container");
+                continue;
+            }
 
-    fn remove(&self) -> Result<()> {
-        for cgroup_path in &self.subsystems {
-            if cgroup_path.1.exists() {
-                log::debug!("remove cgroup {:?}", cgroup_path.1);
-                let procs_path = cgroup_path.1.join(CGROUP_PROCS);
-                let procs = fs::read_to_string(&procs_path)?;
+            let result = match subsys.0 {
+                CtrlType::Cpu => Cpu::apply(linux_resources, subsys.1),
+                CtrlType::CpuAcct => CpuAcct::apply(linux_resources, subsys.1),
+                CtrlType::CpuSet => CpuSet::apply(linux_resources, subsys.1),
+                CtrlType::Devices => Devices::apply(linux_resources, subsys.1),
+                CtrlType::HugeTlb => HugeTlb::apply(linux_resources, subsys.1),
+                CtrlType::Memory => Memory::apply(linux_resources, subsys.1),
+                CtrlType::Pids => Pids::apply(linux_resources, subsys.1),
+                CtrlType::PerfEvent => PerfEvent::apply(linux_resources, subsys.1),
+                CtrlType::Blkio => Blkio::apply(linux_resources, subsys.1),
+                CtrlType::NetworkPriority => NetworkPriority::apply(linux_resources, subsys.1),
+                CtrlType::NetworkClassifier => NetworkClassifier::apply(linux_resources, subsys.1),
+                CtrlType::Freezer => Freezer::apply(linux_resources, subsys.1),
+                CtrlType::Rdma => Rdma::apply(linux_resources, subsys.1),
+            };
+            common::check_controller_result(result, self.strict)?;
+        }
 
-                for line in procs.lines() {
-                    let pid: i32 = line.parse()?;
-                    let _ = nix::sys::signal::kill(Pid::from_raw(pid), nix::sys::signal::SIGKILL);
-                }
+        Ok(())
+    }
 
-                util::delete_with_retry(cgroup_path.1)?;
-            }
+    fn remove(&self) -> Result<(), CgroupError> {
+        for cgroup_path in self.subsystems.values() {
+            log::debug!("remove cgroup {:?}", cgroup_path);
+            common::remove_cgroup_dir(cgroup_path)?;
         }
 
         Ok(())
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<()> {
+    fn freeze(&self, state: FreezerState) -> Result<(), CgroupError> {
         let linux_resources = LinuxResources {
             freezer: Some(state),
             ..Default::default()
         };
-        Freezer::apply(
+        Ok(Freezer::apply(
             &linux_resources,
             self.subsystems.get(&CtrlType::Freezer).unwrap(),
-        )
+        )?)
+    }
+
+    fn ramp_down_cpu(&self, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<(), CgroupError> {
+        match self.subsystems.get(&CtrlType::Cpu) {
+            Some(path) => Ok(Cpu::ramp_down(path, cpu, rampdown)?),
+            None => Err(CgroupError::kernel_feature_missing("Cgroup cpu is required to fullfill the request, but is not supported by this system")),
+        }
+    }
+
+    fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError> {
+        // controllers which are mounted together share a hierarchy
+        let mut hierarchies: Vec<CgroupHierarchy> = Vec::new();
+        for (subsystem, path) in &self.subsystems {
+            // the layout of the host is recreated in the container, where
+            // the hierarchies are named after their controllers
+            let name = self.hierarchy_names.get(subsystem).cloned();
+            if !hierarchies.iter().any(|h| h.name == name) {
+                hierarchies.push(CgroupHierarchy {
+                    name,
+                    path: path.clone(),
+                });
+            }
+        }
+
+        hierarchies.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(hierarchies)
     }
 
-    fn stats(&self) -> Result<Stats> {
+    fn stats(&self) -> Result<Stats, CgroupError> {
         let mut stats = Stats::default();
 
         for subsystem in &self.subsystems {
@@ -196,4 +342,82 @@ impl CgroupManager for Manager {
 
         Ok(stats)
     }
+
+    fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError> {
+        match self.subsystems.get(&CtrlType::Memory) {
+            Some(path) => Ok(Memory::notify_on_oom(path)?),
+            None => Err(CgroupError::kernel_feature_missing("Cgroup memory is required to fullfill the request, but is not supported by this system")),
+        }
+    }
+}
+
+// The cgroup is writable if it exists and is owned by the user, or if it can
+// be created in the closest existing ancestor
+fn is_writable(path: &Path) -> bool {
+    path.ancestors()
+        .find(|p| p.exists())
+        .map_or(false, |p| unistd::access(p, AccessFlags::W_OK).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::create_temp_dir;
+    use oci_spec::LinuxPids;
+
+    #[test]
+    fn test_apply_skips_unwritable_cgroups() -> Result<()> {
+        let tmp = create_temp_dir("test_apply_skips_unwritable_cgroups")?;
+        let mut manager = Manager {
+            subsystems: vec![(CtrlType::Pids, tmp.to_path_buf())]
+                .into_iter()
+                .collect(),
+            hierarchy_names: HashMap::new(),
+            strict: false,
+            skip_devices: false,
+            unwritable: vec![CtrlType::Pids].into_iter().collect(),
+        };
+        let resources = LinuxResources {
+            pids: Some(LinuxPids { limit: 10 }),
+            ..Default::default()
+        };
+
+        manager.apply(&resources)?;
+        manager.add_task(Pid::from_raw(1000))?;
+        assert!(!tmp.join("pids.max").exists());
+        assert!(!tmp.join(common::CGROUP_PROCS).exists());
+
+        manager.strict = true;
+        assert!(manager.apply(&resources).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_mounts() -> Result<()> {
+        let tmp = create_temp_dir("test_from_mounts")?;
+        let mountinfo = format!(
+            "26 25 0:24 / {root}/unified rw shared:9 - cgroup2 cgroup2 rw\n\
+             30 25 0:28 / {root}/cpu,cpuacct rw shared:13 - cgroup cgroup rw,cpu,cpuacct\n\
+             31 25 0:29 / {root}/pids rw shared:14 - cgroup cgroup rw,pids\n",
+            root = tmp.display()
+        );
+        let mounts = mounts::parse_mountinfo(&mountinfo)?;
+
+        let manager = Manager::from_mounts(&mounts, PathBuf::from("/youki/test"))?;
+        assert_eq!(
+            manager.hierarchies()?,
+            vec![
+                CgroupHierarchy {
+                    name: Some("cpu,cpuacct".to_owned()),
+                    path: tmp.join("cpu,cpuacct/youki/test"),
+                },
+                CgroupHierarchy {
+                    name: Some("pids".to_owned()),
+                    path: tmp.join("pids/youki/test"),
+                },
+            ]
+        );
+        assert!(!manager.subsystems.contains_key(&CtrlType::Memory));
+        Ok(())
+    }
 }
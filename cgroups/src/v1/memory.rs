@@ -1,12 +1,19 @@
 use std::collections::HashMap;
 use std::io::{prelude::*, Write};
-use std::{fs::OpenOptions, path::Path};
-
-use anyhow::{anyhow, bail, Result};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
 use nix::errno::Errno;
+use nix::sys::eventfd::{eventfd, EfdFlags};
 
 use super::Controller;
-use crate::common::{self};
+use crate::common::{self, UnsupportedSettings};
 use crate::stats::{self, parse_single_value, MemoryData, MemoryStats, StatsProvider};
 
 use oci_spec::{LinuxMemory, LinuxResources};
@@ -18,6 +25,8 @@ const CGROUP_MEMORY_MAX_USAGE: &str = "memory.max_usage_in_bytes";
 const CGROUP_MEMORY_SWAPPINESS: &str = "memory.swappiness";
 const CGROUP_MEMORY_RESERVATION: &str = "memory.soft_limit_in_bytes";
 const CGROUP_MEMORY_OOM_CONTROL: &str = "memory.oom_control";
+// Registers an eventfd for the notifications of a cgroup file
+const CGROUP_EVENT_CONTROL: &str = "cgroup.event_control";
 
 const CGROUP_KERNEL_MEMORY_LIMIT: &str = "memory.kmem.limit_in_bytes";
 const CGROUP_KERNEL_TCP_MEMORY_LIMIT: &str = "memory.kmem.tcp.limit_in_bytes";
@@ -53,6 +62,16 @@ impl Controller for Memory {
 
         if let Some(memory) = Self::needs_to_handle(linux_resources) {
             let reservation = memory.reservation.unwrap_or(0);
+            // validated before anything is written, so that an invalid value
+            // does not leave the cgroup partially updated
+            if let Some(swappiness) = memory.swappiness {
+                if swappiness > 100 {
+                    bail!(
+                        "Invalid swappiness value: {}. Valid range is 0-100",
+                        swappiness
+                    );
+                }
+            }
 
             Self::apply(memory, cgroup_root)?;
 
@@ -70,32 +89,25 @@ impl Controller for Memory {
             }
 
             if let Some(swappiness) = memory.swappiness {
-                if swappiness <= 100 {
-                    common::write_cgroup_file(
-                        cgroup_root.join(CGROUP_MEMORY_SWAPPINESS),
-                        swappiness,
-                    )?;
-                } else {
-                    // invalid swappiness value
-                    return Err(anyhow!(
-                        "Invalid swappiness value: {}. Valid range is 0-100",
-                        swappiness
-                    ));
-                }
+                common::write_cgroup_file(cgroup_root.join(CGROUP_MEMORY_SWAPPINESS), swappiness)?;
             }
 
-            // NOTE: Seems as though kernel and kernelTCP are both deprecated
-            // neither are implemented by runc. Tests pass without this, but
-            // kept in per the spec.
+            let mut unsupported = UnsupportedSettings::default();
             if let Some(kmem) = memory.kernel {
-                common::write_cgroup_file(cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
+                Self::set_kernel_limit(
+                    kmem,
+                    &cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT),
+                    &mut unsupported,
+                )?;
             }
             if let Some(tcp_mem) = memory.kernel_tcp {
-                common::write_cgroup_file(
-                    cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
+                Self::set_kernel_limit(
                     tcp_mem,
+                    &cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
+                    &mut unsupported,
                 )?;
             }
+            unsupported.check()?;
         }
 
         Ok(())
@@ -115,19 +127,30 @@ impl StatsProvider for Memory {
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
         let memory = Self::get_memory_data(cgroup_path, MEMORY_PREFIX)?;
-        let memswap = Self::get_memory_data(cgroup_path, MEMORY_AND_SWAP_PREFIX)?;
-        let kernel = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_PREFIX)?;
-        let kernel_tcp = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_TCP_PREFIX)?;
+        // the files of swap and kernel memory do not exist if their accounting
+        // is disabled or not supported by the kernel
+        let memswap = Self::get_optional_memory_data(cgroup_path, MEMORY_AND_SWAP_PREFIX)?;
+        let kernel = Self::get_optional_memory_data(cgroup_path, MEMORY_KERNEL_PREFIX)?;
+        let kernel_tcp = Self::get_optional_memory_data(cgroup_path, MEMORY_KERNEL_TCP_PREFIX)?;
         let hierarchy = Self::hierarchy_enabled(cgroup_path)?;
         let stats = Self::get_stat_data(cgroup_path)?;
+        let oom_kill = Self::get_oom_kill_count(cgroup_path)?;
 
+        let stat = |name: &str| stats.get(name).copied().unwrap_or_default();
         Ok(MemoryStats {
             memory,
             memswap,
             kernel,
             kernel_tcp,
-            cache: stats["cache"],
+            cache: stat("cache"),
+            rss: stat("rss"),
+            mapped_file: stat("mapped_file"),
+            page_faults: stat("pgfault"),
+            major_page_faults: stat("pgmajfault"),
+            hierarchical_memory_limit: stat("hierarchical_memory_limit"),
+            hierarchical_memsw_limit: stat("hierarchical_memsw_limit"),
             hierarchy,
+            oom_kill,
             stats,
         })
     }
@@ -153,6 +176,15 @@ impl Memory {
         Ok(memory_data)
     }
 
+    fn get_optional_memory_data(cgroup_path: &Path, file_prefix: &str) -> Result<MemoryData> {
+        let usage = cgroup_path.join(format!("{}{}", file_prefix, MEMORY_USAGE_IN_BYTES));
+        if !usage.exists() {
+            return Ok(MemoryData::default());
+        }
+
+        Self::get_memory_data(cgroup_path, file_prefix)
+    }
+
     fn hierarchy_enabled(cgroup_path: &Path) -> Result<bool> {
         let hierarchy_path = cgroup_path.join(MEMORY_USE_HIERARCHY);
         let hierarchy = common::read_cgroup_file(hierarchy_path)?;
@@ -165,6 +197,49 @@ impl Memory {
         stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))
     }
 
+    // memory.oom_control reports the number of killed processes since linux 4.13
+    fn get_oom_kill_count(cgroup_path: &Path) -> Result<u64> {
+        let oom_control = cgroup_path.join(CGROUP_MEMORY_OOM_CONTROL);
+        if !oom_control.exists() {
+            return Ok(0);
+        }
+
+        let data = stats::parse_flat_keyed_data(&oom_control)?;
+        Ok(data.get("oom_kill").copied().unwrap_or_default())
+    }
+
+    /// Registers an eventfd for the OOM events of the cgroup. The kernel
+    /// signals the eventfd on every OOM event and when the cgroup is removed,
+    /// which is told apart by the removed cgroup.event_control.
+    pub fn notify_on_oom(cgroup_path: &Path) -> Result<Receiver<()>> {
+        let oom_control_path = cgroup_path.join(CGROUP_MEMORY_OOM_CONTROL);
+        let oom_control = File::open(&oom_control_path)
+            .with_context(|| format!("failed to open {:?}", oom_control_path))?;
+        let event_fd = eventfd(0, EfdFlags::EFD_CLOEXEC).context("failed to create eventfd")?;
+        // closes the eventfd when the notifications end
+        let mut event = unsafe { File::from_raw_fd(event_fd) };
+
+        let event_control = cgroup_path.join(CGROUP_EVENT_CONTROL);
+        common::write_cgroup_file_str(
+            &event_control,
+            &format!("{} {}", event_fd, oom_control.as_raw_fd()),
+        )?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // the registration ends when memory.oom_control is closed
+            let _oom_control = oom_control;
+            let mut count = [0u8; 8];
+            while event.read_exact(&mut count).is_ok() {
+                if !event_control.exists() || sender.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
     fn get_memory_usage(cgroup_root: &Path) -> Result<u64> {
         let path = cgroup_root.join(CGROUP_MEMORY_USAGE);
         let mut contents = String::new();
@@ -262,6 +337,39 @@ impl Memory {
         }
     }
 
+    // Kernel memory accounting is deprecated and newer kernels either reject
+    // the limit with EOPNOTSUPP (since 5.16) or do not have the file at all,
+    // in which case the limit is reported as unsupported instead of failing.
+    fn set_kernel_limit(
+        limit: i64,
+        path: &Path,
+        unsupported: &mut UnsupportedSettings,
+    ) -> Result<()> {
+        if limit == 0 {
+            return Ok(());
+        }
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+        if !path.exists() {
+            unsupported.add(format!(
+                "{} (kernel memory accounting is not enabled)",
+                file
+            ));
+            return Ok(());
+        }
+
+        match Self::set(limit, path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(Errno::EOPNOTSUPP as i32) => {
+                unsupported.add(format!(
+                    "{} (kernel memory limits are not supported by the kernel)",
+                    file
+                ));
+                Ok(())
+            }
+            Err(e) => Err(e).with_context(|| format!("failed to set {:?} to {}", path, limit)),
+        }
+    }
+
     fn set_swap(swap: i64, cgroup_root: &Path) -> Result<()> {
         if swap == 0 {
             return Ok(());
@@ -271,52 +379,33 @@ impl Memory {
         Ok(())
     }
 
-    fn set_memory_and_swap(
-        limit: i64,
-        swap: i64,
-        is_updated: bool,
-        cgroup_root: &Path,
-    ) -> Result<()> {
-        // According to runc we need to change the write sequence of
-        // limit and swap so it won't fail, because the new and old
-        // values don't fit the kernel's validation
-        // see:
+    fn apply(resource: &LinuxMemory, cgroup_root: &Path) -> Result<()> {
+        let limit = resource.limit.unwrap_or(0);
+        // unlimited memory implies unlimited swap, unless swap is set
+        let swap = match resource.swap {
+            Some(swap) => swap,
+            None if limit == -1 => -1,
+            None => 0,
+        };
+
+        // The kernel refuses a memory limit above the memory+swap limit, so
+        // the order of the writes depends on the current limit when both are
+        // updated, e.g. the memory+swap limit has to be raised first if it is
+        // above the current memory limit. Same as runc, see:
         // https://github.com/opencontainers/runc/blob/3f6594675675d4e88901c782462f56497260b1d2/libcontainer/cgroups/fs/memory.go#L89
-        if is_updated {
-            Self::set_swap(swap, cgroup_root)?;
-            Self::set_memory(limit, cgroup_root)?;
+        if limit != 0 && swap != 0 {
+            let current_limit = Self::get_memory_limit(cgroup_root)?;
+            if swap == -1 || current_limit < swap {
+                Self::set_swap(swap, cgroup_root)?;
+                Self::set_memory(limit, cgroup_root)?;
+                return Ok(());
+            }
         }
+
         Self::set_memory(limit, cgroup_root)?;
         Self::set_swap(swap, cgroup_root)?;
         Ok(())
     }
-
-    fn apply(resource: &LinuxMemory, cgroup_root: &Path) -> Result<()> {
-        match resource.limit {
-            Some(limit) => {
-                let current_limit = Self::get_memory_limit(cgroup_root)?;
-                match resource.swap {
-                    Some(swap) => {
-                        let is_updated = swap == -1 || current_limit < swap;
-                        Self::set_memory_and_swap(limit, swap, is_updated, cgroup_root)?;
-                    }
-                    None => {
-                        if limit == -1 {
-                            Self::set_memory_and_swap(limit, -1, true, cgroup_root)?;
-                        } else {
-                            let is_updated = current_limit < 0;
-                            Self::set_memory_and_swap(limit, 0, is_updated, cgroup_root)?;
-                        }
-                    }
-                }
-            }
-            None => match resource.swap {
-                Some(swap) => Self::set_memory_and_swap(0, swap, false, cgroup_root)?,
-                None => Self::set_memory_and_swap(0, 0, false, cgroup_root)?,
-            },
-        }
-        Ok(())
-    }
 }
 
 #[cfg(test)]
@@ -363,6 +452,33 @@ mod tests {
         assert_eq!(limit.to_string(), content)
     }
 
+    #[test]
+    fn test_set_kernel_limit_without_kmem_accounting() {
+        let tmp = create_temp_dir("test_set_kernel_limit_without_kmem_accounting")
+            .expect("create temp directory for test");
+        let mut unsupported = UnsupportedSettings::default();
+        Memory::set_kernel_limit(
+            1024,
+            &tmp.join(CGROUP_KERNEL_MEMORY_LIMIT),
+            &mut unsupported,
+        )
+        .expect("skip kernel memory limit");
+        assert!(!tmp.join(CGROUP_KERNEL_MEMORY_LIMIT).exists());
+        assert_eq!(unsupported.settings().len(), 1);
+
+        set_fixture(&tmp, CGROUP_KERNEL_TCP_MEMORY_LIMIT, "0").expect("set fixture for kmem tcp");
+        Memory::set_kernel_limit(
+            2048,
+            &tmp.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
+            &mut unsupported,
+        )
+        .expect("set kernel tcp memory limit");
+        assert_eq!(unsupported.settings().len(), 1);
+        let content = std::fs::read_to_string(tmp.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT))
+            .expect("Read to string");
+        assert_eq!(content, "2048");
+    }
+
     #[test]
     fn test_set_memory_and_swap() {
         let tmp =
@@ -594,6 +710,43 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_stat_memory_without_swap_accounting() {
+        let tmp = create_temp_dir("test_stat_memory_without_swap_accounting")
+            .expect("create test directory");
+        for (suffix, value) in &[
+            (MEMORY_USAGE_IN_BYTES, "1024"),
+            (MEMORY_MAX_USAGE_IN_BYTES, "2048"),
+            (MEMORY_LIMIT_IN_BYTES, "4096"),
+            (MEMORY_FAIL_COUNT, "5"),
+        ] {
+            set_fixture(&tmp, &format!("{}{}", MEMORY_PREFIX, suffix), value).unwrap();
+        }
+        set_fixture(&tmp, MEMORY_USE_HIERARCHY, "1").unwrap();
+        let content = [
+            "cache 512",
+            "rss 256",
+            "mapped_file 128",
+            "pgfault 10",
+            "pgmajfault 2",
+            "hierarchical_memory_limit 4096",
+            "total_cache 1024",
+        ]
+        .join("\n");
+        set_fixture(&tmp, MEMORY_STAT, &content).unwrap();
+
+        let stats = Memory::stats(&tmp).expect("get cgroup stats");
+        assert_eq!(stats.memory.fail_count, 5);
+        assert_eq!(stats.memswap, MemoryData::default());
+        assert_eq!(stats.cache, 512);
+        assert_eq!(stats.rss, 256);
+        assert_eq!(stats.mapped_file, 128);
+        assert_eq!(stats.major_page_faults, 2);
+        assert_eq!(stats.hierarchical_memory_limit, 4096);
+        assert_eq!(stats.total_stat("cache"), Some(1024));
+        assert_eq!(stats.total_stat("rss"), None);
+    }
+
     #[test]
     fn test_stat_hierarchy_enabled() {
         let tmp = create_temp_dir("test_stat_hierarchy_enabled").expect("create test directory");
@@ -645,4 +798,14 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_stat_oom_kill_count() {
+        let tmp = create_temp_dir("test_stat_oom_kill_count").expect("create test directory");
+        assert_eq!(Memory::get_oom_kill_count(&tmp).expect("get oom kills"), 0);
+
+        let content = ["oom_kill_disable 0", "under_oom 0", "oom_kill 4"].join("\n");
+        set_fixture(&tmp, CGROUP_MEMORY_OOM_CONTROL, &content).unwrap();
+        assert_eq!(Memory::get_oom_kill_count(&tmp).expect("get oom kills"), 4);
+    }
 }
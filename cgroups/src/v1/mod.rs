@@ -13,7 +13,10 @@ mod network_classifier;
 mod network_priority;
 pub mod perf_event;
 mod pids;
+mod rdma;
+pub mod systemd_manager;
 pub mod util;
 pub use controller::Controller;
 pub use controller_type::ControllerType;
 pub use manager::Manager;
+pub use systemd_manager::SystemDCGroupManager;
@@ -6,6 +6,8 @@ use super::Controller;
 use crate::common;
 use oci_spec::{LinuxNetwork, LinuxResources};
 
+const CGROUP_NET_CLS_CLASSID: &str = "net_cls.classid";
+
 pub struct NetworkClassifier {}
 
 impl Controller for NetworkClassifier {
@@ -24,7 +26,9 @@ impl Controller for NetworkClassifier {
 
     fn needs_to_handle(linux_resources: &LinuxResources) -> Option<&Self::Resource> {
         if let Some(network) = linux_resources.network.as_ref() {
-            return Some(network);
+            if network.class_id.is_some() {
+                return Some(network);
+            }
         }
 
         None
@@ -34,7 +38,7 @@ impl Controller for NetworkClassifier {
 impl NetworkClassifier {
     fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<()> {
         if let Some(class_id) = network.class_id {
-            common::write_cgroup_file(root_path.join("net_cls.classid"), class_id)?;
+            common::write_cgroup_file(root_path.join(CGROUP_NET_CLS_CLASSID), class_id)?;
         }
 
         Ok(())
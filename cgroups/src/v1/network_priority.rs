@@ -6,6 +6,8 @@ use super::Controller;
 use crate::common;
 use oci_spec::{LinuxNetwork, LinuxResources};
 
+const CGROUP_NET_PRIO_IFPRIOMAP: &str = "net_prio.ifpriomap";
+
 pub struct NetworkPriority {}
 
 impl Controller for NetworkPriority {
@@ -24,7 +26,9 @@ impl Controller for NetworkPriority {
 
     fn needs_to_handle(linux_resources: &LinuxResources) -> Option<&Self::Resource> {
         if let Some(network) = &linux_resources.network {
-            return Some(network);
+            if network.priorities.as_ref().map_or(false, |p| !p.is_empty()) {
+                return Some(network);
+            }
         }
 
         None
@@ -33,9 +37,13 @@ impl Controller for NetworkPriority {
 
 impl NetworkPriority {
     fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<()> {
-        if let Some(ni_priorities) = network.priorities.as_ref() {
-            let priorities: String = ni_priorities.iter().map(|p| p.to_string()).collect();
-            common::write_cgroup_file_str(root_path.join("net_prio.ifpriomap"), priorities.trim())?;
+        // the kernel only takes the priority of a single interface per write
+        for priority in network.priorities.iter().flatten() {
+            common::write_cgroup_file_str(
+                root_path.join(CGROUP_NET_PRIO_IFPRIOMAP),
+                &format!("{} {}", priority.name, priority.priority),
+            )
+            .with_context(|| format!("failed to set priority of interface {}", priority.name))?;
         }
 
         Ok(())
@@ -63,7 +71,6 @@ mod tests {
                 priority: 2,
             },
         ];
-        let priorities_string = priorities.iter().map(|p| p.to_string()).collect::<String>();
         let network = LinuxNetwork {
             class_id: None,
             priorities: priorities.into(),
@@ -71,8 +78,28 @@ mod tests {
 
         NetworkPriority::apply(&tmp, &network).expect("apply network priorities");
 
-        let content =
-            std::fs::read_to_string(tmp.join("net_prio.ifpriomap")).expect("Read classID contents");
-        assert_eq!(priorities_string.trim(), content);
+        // every write replaces the content of the fixture, so only the last
+        // interface remains
+        let content = std::fs::read_to_string(tmp.join(CGROUP_NET_PRIO_IFPRIOMAP))
+            .expect("Read priority map contents");
+        assert_eq!("b 2", content);
+    }
+
+    #[test]
+    fn test_needs_to_handle() {
+        let mut resources = LinuxResources {
+            network: Some(LinuxNetwork {
+                class_id: Some(0x100001),
+                priorities: Some(vec![]),
+            }),
+            ..Default::default()
+        };
+        assert!(NetworkPriority::needs_to_handle(&resources).is_none());
+
+        resources.network.as_mut().unwrap().priorities = Some(vec![LinuxInterfacePriority {
+            name: "eth0".to_owned(),
+            priority: 1,
+        }]);
+        assert!(NetworkPriority::needs_to_handle(&resources).is_some());
     }
 }
@@ -0,0 +1,117 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use oci_spec::{LinuxRdma, LinuxResources};
+
+use super::Controller;
+use crate::common;
+
+// Limits of the rdma resources per device
+// Format: Device hca_handle=Handles hca_object=Objects
+const CGROUP_RDMA_MAX: &str = "rdma.max";
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    type Resource = HashMap<String, LinuxRdma>;
+
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path) -> Result<()> {
+        log::debug!("Apply rdma cgroup config");
+
+        if let Some(rdma) = Self::needs_to_handle(linux_resources) {
+            Self::apply(cgroup_root, rdma).context("failed to apply rdma resource restrictions")?;
+        }
+
+        Ok(())
+    }
+
+    fn needs_to_handle(linux_resources: &LinuxResources) -> Option<&Self::Resource> {
+        if let Some(rdma) = &linux_resources.rdma {
+            if !rdma.is_empty() {
+                return Some(rdma);
+            }
+        }
+
+        None
+    }
+}
+
+impl Rdma {
+    fn apply(root_path: &Path, rdma: &HashMap<String, LinuxRdma>) -> Result<()> {
+        let mut devices: Vec<_> = rdma.iter().collect();
+        devices.sort_by(|a, b| a.0.cmp(b.0));
+
+        // the kernel takes the limits of a single device per write, limits
+        // which are not part of the write keep their value
+        for (device, limits) in devices {
+            if let Some(limit) = Self::limit(device, limits) {
+                common::write_cgroup_file_str(root_path.join(CGROUP_RDMA_MAX), &limit)
+                    .with_context(|| format!("failed to set rdma limits of {}", device))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn limit(device: &str, limits: &LinuxRdma) -> Option<String> {
+        let mut limit = device.to_owned();
+        if let Some(handles) = limits.hca_handles {
+            limit.push_str(&format!(" hca_handle={}", handles));
+        }
+        if let Some(objects) = limits.hca_objects {
+            limit.push_str(&format!(" hca_object={}", objects));
+        }
+
+        if limit.len() == device.len() {
+            return None;
+        }
+
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test::setup;
+
+    #[test]
+    fn test_set_rdma() {
+        let (tmp, max) = setup("test_set_rdma", CGROUP_RDMA_MAX);
+        let mut rdma = HashMap::new();
+        rdma.insert(
+            "mlx5_1".to_owned(),
+            LinuxRdma {
+                hca_handles: None,
+                hca_objects: None,
+            },
+        );
+        rdma.insert(
+            "mlx5_0".to_owned(),
+            LinuxRdma {
+                hca_handles: Some(3),
+                hca_objects: Some(10000),
+            },
+        );
+
+        Rdma::apply(&tmp, &rdma).expect("apply rdma");
+
+        let content = fs::read_to_string(&max)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_RDMA_MAX));
+        assert_eq!(content, "mlx5_0 hca_handle=3 hca_object=10000");
+    }
+
+    #[test]
+    fn test_rdma_limit() {
+        let limits = LinuxRdma {
+            hca_handles: None,
+            hca_objects: Some(500),
+        };
+        assert_eq!(
+            Rdma::limit("mlx4_0", &limits).as_deref(),
+            Some("mlx4_0 hca_object=500")
+        );
+    }
+}
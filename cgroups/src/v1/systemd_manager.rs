@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+use anyhow::Result;
+use nix::unistd::Pid;
+use oci_spec::{FreezerState, LinuxCpu, LinuxResources};
+
+use super::Manager;
+use crate::common::{CgroupHierarchy, CgroupManager, CpuRampdown};
+use crate::error::CgroupError;
+use crate::stats::Stats;
+use crate::systemd_unit::{self, PropertyValue, TransientUnit, UnitProperty};
+
+/// SystemDCGroupManager is a driver for managing cgroups via systemd on hosts
+/// with the legacy or hybrid hierarchy. systemd only creates the cgroup of the
+/// unit in the hierarchies it manages, the cgroups in the other hierarchies
+/// are created and all restrictions are applied like the fs driver does.
+pub struct SystemDCGroupManager {
+    unit: TransientUnit,
+    manager: Manager,
+}
+
+impl SystemDCGroupManager {
+    pub fn new(cgroups_path: PathBuf) -> Result<Self> {
+        let unit = TransientUnit::new(cgroups_path)?;
+        let manager = Manager::new(unit.cgroups_path().to_path_buf())?;
+
+        Ok(SystemDCGroupManager { unit, manager })
+    }
+
+    // The unit gets the same limits as the cgroup, see update_resources
+    fn set_unit_resources(&self, linux_resources: &LinuxResources) {
+        let mut properties = systemd_unit::resource_properties(linux_resources);
+        if let Some(limit) = linux_resources.memory.as_ref().and_then(|m| m.limit) {
+            properties.push(UnitProperty {
+                name: "MemoryLimit".to_owned(),
+                value: PropertyValue::U64(systemd_unit::unit_limit(limit)),
+            });
+        }
+        if let Some(shares) = linux_resources.cpu.as_ref().and_then(|cpu| cpu.shares) {
+            properties.push(UnitProperty {
+                name: "CPUShares".to_owned(),
+                value: PropertyValue::U64(shares),
+            });
+        }
+
+        self.unit.update_resources(&properties);
+    }
+
+    /// Fails to apply the resources if a setting is not supported by the host,
+    /// instead of skipping it with a warning
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.manager = self.manager.with_strict_resources(strict);
+        self
+    }
+
+    /// Does not apply any device rules, e.g. if the devices controller is
+    /// managed by an outer container
+    pub fn with_skip_devices(mut self, skip_devices: bool) -> Self {
+        self.manager = self.manager.with_skip_devices(skip_devices);
+        self
+    }
+
+    /// Sets additional properties on the systemd unit of the container
+    pub fn with_unit_properties(mut self, properties: Vec<UnitProperty>) -> Self {
+        self.unit = self.unit.with_properties(properties);
+        self
+    }
+}
+
+impl CgroupManager for SystemDCGroupManager {
+    fn add_task(&self, pid: Pid) -> Result<(), CgroupError> {
+        // Dont attach any pid to the cgroup if -1 is specified as a pid
+        if pid.as_raw() == -1 {
+            return Ok(());
+        }
+
+        self.unit.start(pid)?;
+        // joins the cgroups of the hierarchies which are not managed by systemd
+        self.manager.add_task(pid)
+    }
+
+    fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError> {
+        self.manager.apply(linux_resources)?;
+        self.set_unit_resources(linux_resources);
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<(), CgroupError> {
+        self.unit.stop()?;
+        // systemd only removes the cgroups in the hierarchies it manages
+        self.manager.remove()
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<(), CgroupError> {
+        self.manager.freeze(state)
+    }
+
+    fn stats(&self) -> Result<Stats, CgroupError> {
+        self.manager.stats()
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError> {
+        self.manager.get_all_pids()
+    }
+
+    fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError> {
+        self.manager.get_all_threads()
+    }
+
+    fn exists(&self) -> bool {
+        self.manager.exists()
+    }
+
+    fn ramp_down_cpu(&self, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<(), CgroupError> {
+        self.manager.ramp_down_cpu(cpu, rampdown)
+    }
+
+    fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError> {
+        self.manager.hierarchies()
+    }
+
+    fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError> {
+        self.manager.notify_on_oom()
+    }
+}
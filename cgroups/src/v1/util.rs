@@ -1,14 +1,12 @@
 use std::{
     collections::HashMap,
-    fs,
     path::{Path, PathBuf},
-    time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
-use procfs::process::Process;
+use anyhow::{anyhow, Result};
 
 use super::{controller_type::CONTROLLERS, ControllerType};
+use crate::mounts::{self, CgroupMount};
 
 pub fn list_subsystem_mount_points() -> Result<HashMap<ControllerType, PathBuf>> {
     let mut mount_paths = HashMap::with_capacity(CONTROLLERS.len());
@@ -23,54 +21,41 @@ pub fn list_subsystem_mount_points() -> Result<HashMap<ControllerType, PathBuf>>
 }
 
 pub fn get_subsystem_mount_point(subsystem: &ControllerType) -> Result<PathBuf> {
+    get_subsystem_mount_point_with_root(subsystem, None)
+}
+
+/// Finds the mount point of a subsystem. If a cgroup root is given, only the
+/// hierarchies which are mounted below it are considered.
+pub fn get_subsystem_mount_point_with_root(
+    subsystem: &ControllerType,
+    cgroup_root: Option<&Path>,
+) -> Result<PathBuf> {
+    get_subsystem_mount(subsystem, cgroup_root).map(|m| m.mount_point.clone())
+}
+
+/// Finds the hierarchy a subsystem is attached to. The hierarchies are
+/// identified by the controllers in their mount options, so they can be
+/// mounted anywhere and under any name, e.g. cpu and cpuacct together at
+/// /sys/fs/cgroup/cpu,cpuacct.
+pub fn get_subsystem_mount(
+    subsystem: &ControllerType,
+    cgroup_root: Option<&Path>,
+) -> Result<&'static CgroupMount> {
     let subsystem = subsystem.to_string();
-    Process::myself()?
-        .mountinfo()?
+    mounts::v1_mounts(cgroup_root)?
         .into_iter()
-        .find(|m| {
-            if m.fs_type == "cgroup" {
-                // Some systems mount net_prio and net_cls in the same directory
-                // other systems mount them in their own diretories. This
-                // should handle both cases.
-                if subsystem == "net_cls" {
-                    return m.mount_point.ends_with("net_cls,net_prio")
-                        || m.mount_point.ends_with("net_prio,net_cls")
-                        || m.mount_point.ends_with("net_cls");
-                } else if subsystem == "net_prio" {
-                    return m.mount_point.ends_with("net_cls,net_prio")
-                        || m.mount_point.ends_with("net_prio,net_cls")
-                        || m.mount_point.ends_with("net_prio");
-                }
-
-                if subsystem == "cpu" {
-                    return m.mount_point.ends_with("cpu,cpuacct")
-                        || m.mount_point.ends_with("cpu");
-                }
-                if subsystem == "cpuacct" {
-                    return m.mount_point.ends_with("cpu,cpuacct")
-                        || m.mount_point.ends_with("cpuacct");
-                }
-            }
-            m.mount_point.ends_with(&subsystem)
-        })
-        .map(|m| m.mount_point)
+        .find(|m| m.has_controller(&subsystem))
         .ok_or_else(|| anyhow!("could not find mountpoint for {}", subsystem))
 }
 
-pub(crate) fn delete_with_retry<P: AsRef<Path>>(path: P) -> Result<()> {
-    let mut attempts = 0;
-    let mut delay = Duration::from_millis(10);
-    let path = path.as_ref();
-
-    while attempts < 5 {
-        if fs::remove_dir(path).is_ok() {
-            return Ok(());
-        }
-
-        std::thread::sleep(delay);
-        attempts += attempts;
-        delay *= attempts;
-    }
-
-    bail!("could not delete {:?}", path)
+/// Lists the mount points of the named hierarchies, which have no controller
+/// attached and are only used to track processes, e.g. name=systemd
+pub fn list_named_mount_points() -> Result<HashMap<String, PathBuf>> {
+    Ok(mounts::v1_mounts(None)?
+        .into_iter()
+        .filter_map(|m| {
+            m.name()
+                .map(|name| (name.to_owned(), m.mount_point.clone()))
+        })
+        .collect())
 }
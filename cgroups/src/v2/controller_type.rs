@@ -8,6 +8,7 @@ pub enum ControllerType {
     HugeTlb,
     Pids,
     Freezer,
+    Misc,
 }
 
 impl Display for ControllerType {
@@ -20,6 +21,7 @@ impl Display for ControllerType {
             Self::HugeTlb => "hugetlb",
             Self::Pids => "pids",
             Self::Freezer => "freezer",
+            Self::Misc => "misc",
         };
 
         write!(f, "{}", print)
@@ -34,6 +36,7 @@ pub const CONTROLLER_TYPES: &[ControllerType] = &[
     ControllerType::Memory,
     ControllerType::Pids,
     ControllerType::Freezer,
+    ControllerType::Misc,
 ];
 
 pub enum PseudoControllerType {
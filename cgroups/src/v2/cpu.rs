@@ -2,8 +2,9 @@ use anyhow::{bail, Context, Result};
 use std::path::Path;
 
 use crate::{
-    common,
-    stats::{CpuUsage, StatsProvider},
+    common::{self, CpuRampdown},
+    error::CgroupError,
+    stats::{CpuStats, StatsProvider},
 };
 
 use oci_spec::{LinuxCpu, LinuxResources};
@@ -12,11 +13,18 @@ use super::controller::Controller;
 
 const CGROUP_CPU_WEIGHT: &str = "cpu.weight";
 const CGROUP_CPU_MAX: &str = "cpu.max";
+const CGROUP_CPU_BURST: &str = "cpu.max.burst";
+const CGROUP_CPU_IDLE: &str = "cpu.idle";
 const DEFAULT_PERIOD: &str = "100000";
 const UNRESTRICTED_QUOTA: &str = "max";
 
 const CPU_STAT: &str = "cpu.stat";
 
+/// Cpu files of newer versions of the spec, which are passed through the unified
+/// map. They are applied by the cpu controller after the quota has been set,
+/// because the burst can not exceed the quota.
+pub const CPU_UNIFIED_FILES: &[&str] = &[CGROUP_CPU_BURST, CGROUP_CPU_IDLE];
+
 pub struct Cpu {}
 
 impl Controller for Cpu {
@@ -25,16 +33,25 @@ impl Controller for Cpu {
             Self::apply(path, cpu).context("failed to apply cpu resource restrictions")?;
         }
 
+        if let Some(unified) = &linux_resources.unified {
+            for &file in CPU_UNIFIED_FILES {
+                if let Some(value) = unified.get(file) {
+                    Self::apply_unified(path, file, value)?;
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
 impl StatsProvider for Cpu {
-    type Stats = CpuUsage;
+    type Stats = CpuStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        let mut stats = CpuUsage::default();
+        let mut stats = CpuStats::default();
 
+        // times are reported in microseconds, but in nanoseconds on cgroup v1
         let stat_content = common::read_cgroup_file(cgroup_path.join(CPU_STAT))?;
         for entry in stat_content.lines() {
             let parts: Vec<&str> = entry.split_ascii_whitespace().collect();
@@ -42,11 +59,17 @@ impl StatsProvider for Cpu {
                 continue;
             }
 
-            let value = parts[1].parse()?;
+            let value: u64 = parts[1]
+                .parse()
+                .with_context(|| format!("failed to parse {} of {}", parts[0], CPU_STAT))?;
             match parts[0] {
-                "usage_usec" => stats.usage_total = value,
-                "user_usec" => stats.usage_user = value,
-                "system_usec" => stats.usage_kernel = value,
+                "usage_usec" => stats.usage.usage_total = value * 1000,
+                "user_usec" => stats.usage.usage_user = value * 1000,
+                "system_usec" => stats.usage.usage_kernel = value * 1000,
+                // the throttling is only reported if the cpu controller is enabled
+                "nr_periods" => stats.throttling.periods = value,
+                "nr_throttled" => stats.throttling.throttled_periods = value,
+                "throttled_usec" => stats.throttling.throttled_time = value * 1000,
                 _ => continue,
             }
         }
@@ -56,9 +79,45 @@ impl StatsProvider for Cpu {
 }
 
 impl Cpu {
+    /// Lowers the cpu quota gradually to the quota of the given cpu resources.
+    /// The quota is lowered within the current period, a new period is set
+    /// when the resources are applied afterwards.
+    pub fn ramp_down(path: &Path, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<()> {
+        let target = match cpu.quota {
+            Some(quota) if quota > 0 => quota,
+            _ => return Ok(()),
+        };
+
+        // format is 'quota period', where quota may be 'max'
+        let max = common::read_cgroup_file(path.join(CGROUP_CPU_MAX))?;
+        let mut parts = max.split_whitespace();
+        let current_quota = parts.next().unwrap_or(UNRESTRICTED_QUOTA);
+        let current_period: u64 = parts
+            .next()
+            .unwrap_or(DEFAULT_PERIOD)
+            .parse()
+            .context("failed to parse cpu period")?;
+        let current = if current_quota == UNRESTRICTED_QUOTA {
+            common::unrestricted_quota(current_period)
+        } else {
+            current_quota.parse().context("failed to parse cpu quota")?
+        };
+
+        let period = cpu.period.filter(|p| *p > 0).unwrap_or(current_period);
+        let target = common::scale_quota(target, period, current_period)?;
+        rampdown.run(current, target, |quota| {
+            common::write_cgroup_file_str(
+                path.join(CGROUP_CPU_MAX),
+                &format!("{} {}", quota, current_period),
+            )
+        })
+    }
+
     fn apply(path: &Path, cpu: &LinuxCpu) -> Result<()> {
         if Self::is_realtime_requested(cpu) {
-            bail!("realtime is not supported on cgroup v2 yet");
+            bail!(CgroupError::kernel_feature_missing(
+                "realtime is not supported on cgroup v2 yet"
+            ));
         }
 
         if let Some(mut shares) = cpu.shares {
@@ -69,6 +128,12 @@ impl Cpu {
             }
         }
 
+        // leave the current limit untouched if neither quota nor period are
+        // given, which is the case if only other cpu resources are updated
+        if cpu.quota.is_none() && cpu.period.is_none() {
+            return Ok(());
+        }
+
         // if quota is unrestricted set to 'max'
         let mut quota_string = UNRESTRICTED_QUOTA.to_owned();
         if let Some(quota) = cpu.quota {
@@ -94,7 +159,26 @@ impl Cpu {
         Ok(())
     }
 
-    fn convert_shares_to_cgroup2(shares: u64) -> u64 {
+    fn apply_unified(path: &Path, file: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        let valid = match file {
+            CGROUP_CPU_IDLE => value == "0" || value == "1",
+            // burst in microseconds, available since linux 5.14
+            _ => value.parse::<u64>().is_ok(),
+        };
+        if !valid {
+            bail!(CgroupError::spec(format!(
+                "invalid value {:?} for {}",
+                value, file
+            )));
+        }
+
+        log::debug!("Apply {} {}", file, value);
+        common::write_cgroup_file_str(path.join(file), value)
+            .with_context(|| format!("failed to set {} to {}", file, value))
+    }
+
+    pub(super) fn convert_shares_to_cgroup2(shares: u64) -> u64 {
         if shares == 0 {
             return 0;
         }
@@ -118,7 +202,10 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test::{create_temp_dir, set_fixture, setup, LinuxCpuBuilder};
+    use crate::{
+        stats::{CpuThrottling, CpuUsage},
+        test::{create_temp_dir, set_fixture, setup, LinuxCpuBuilder},
+    };
     use std::fs;
 
     #[test]
@@ -267,13 +354,102 @@ mod tests {
         set_fixture(&tmp, CPU_STAT, &content).expect("create stat file");
 
         let actual = Cpu::stats(&tmp).expect("get cgroup stats");
-        let expected = CpuUsage {
-            usage_total: 7730,
-            usage_user: 4387,
-            usage_kernel: 3498,
+        let expected = CpuStats {
+            usage: CpuUsage {
+                usage_total: 7730000,
+                usage_user: 4387000,
+                usage_kernel: 3498000,
+                ..Default::default()
+            },
             ..Default::default()
         };
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_stat_throttling() {
+        let tmp = create_temp_dir("test_stat_throttling").expect("create temp directory for test");
+        let content = [
+            "usage_usec 7730",
+            "user_usec 4387",
+            "system_usec 3498",
+            "nr_periods 165000",
+            "nr_throttled 27",
+            "throttled_usec 1080",
+        ]
+        .join("\n");
+        set_fixture(&tmp, CPU_STAT, &content).expect("create stat file");
+
+        let actual = Cpu::stats(&tmp).expect("get cgroup stats");
+        let expected = CpuThrottling {
+            periods: 165000,
+            throttled_periods: 27,
+            throttled_time: 1080000,
+        };
+
+        assert_eq!(actual.throttling, expected);
+    }
+
+    #[test]
+    fn test_ramp_down_quota() {
+        // arrange
+        let tmp = create_temp_dir("test_ramp_down_quota").expect("create temp directory for test");
+        let max = set_fixture(&tmp, CGROUP_CPU_MAX, "200000 100000")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_MAX));
+        let cpu = LinuxCpuBuilder::new().with_quota(100000).build();
+        let rampdown = CpuRampdown {
+            duration: std::time::Duration::from_millis(0),
+            steps: 2,
+        };
+
+        // act
+        Cpu::ramp_down(&tmp, &cpu, &rampdown).expect("ramp down cpu");
+
+        // assert
+        let content = fs::read_to_string(max)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_MAX));
+        assert_eq!(content, "100000 100000");
+    }
+
+    #[test]
+    fn test_rampdown_quotas() {
+        let rampdown = CpuRampdown {
+            duration: std::time::Duration::from_millis(0),
+            steps: 4,
+        };
+
+        assert_eq!(
+            rampdown.quotas(400000, 100000),
+            vec![325000, 250000, 175000, 100000]
+        );
+        // raising the quota is not ramped
+        assert_eq!(rampdown.quotas(100000, 400000), vec![400000]);
+    }
+
+    #[test]
+    fn test_set_burst_and_idle() {
+        let (tmp, burst) = setup("test_set_burst_and_idle", CGROUP_CPU_BURST);
+        let idle = set_fixture(&tmp, CGROUP_CPU_IDLE, "0")
+            .unwrap_or_else(|_| panic!("set test fixture for {}", CGROUP_CPU_IDLE));
+        let resources = LinuxResources {
+            unified: Some(
+                [
+                    (CGROUP_CPU_BURST.to_owned(), "50000".to_owned()),
+                    (CGROUP_CPU_IDLE.to_owned(), "1".to_owned()),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            ..Default::default()
+        };
+
+        <Cpu as Controller>::apply(&resources, &tmp).expect("apply cpu");
+
+        assert_eq!(fs::read_to_string(burst).expect("read burst"), "50000");
+        assert_eq!(fs::read_to_string(idle).expect("read idle"), "1");
+        assert!(Cpu::apply_unified(&tmp, CGROUP_CPU_IDLE, "2").is_err());
+        assert!(Cpu::apply_unified(&tmp, CGROUP_CPU_BURST, "-1").is_err());
+    }
 }
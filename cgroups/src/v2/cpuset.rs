@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::path::Path;
 
 use crate::common;
@@ -8,6 +8,10 @@ use super::controller::Controller;
 
 const CGROUP_CPUSET_CPUS: &str = "cpuset.cpus";
 const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
+/// Partition type of the cpuset, which can be requested through the unified map.
+/// It is applied by the cpuset controller after the cpus have been set.
+pub const CGROUP_CPUSET_PARTITION: &str = "cpuset.cpus.partition";
+const PARTITION_TYPES: &[&str] = &["member", "root", "isolated"];
 
 pub struct CpuSet {}
 
@@ -18,6 +22,14 @@ impl Controller for CpuSet {
                 .context("failed to apply cpuset resource restrictions")?;
         }
 
+        if let Some(partition) = linux_resources
+            .unified
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_CPUSET_PARTITION))
+        {
+            Self::apply_partition(cgroup_path, partition)?;
+        }
+
         Ok(())
     }
 }
@@ -25,11 +37,53 @@ impl Controller for CpuSet {
 impl CpuSet {
     fn apply(path: &Path, cpuset: &LinuxCpu) -> Result<()> {
         if let Some(cpus) = &cpuset.cpus {
-            common::write_cgroup_file_str(path.join(CGROUP_CPUSET_CPUS), cpus)?;
+            common::write_cgroup_file_str(path.join(CGROUP_CPUSET_CPUS), Self::cpuset_value(cpus))?;
         }
 
         if let Some(mems) = &cpuset.mems {
-            common::write_cgroup_file_str(path.join(CGROUP_CPUSET_MEMS), mems)?;
+            common::write_cgroup_file_str(path.join(CGROUP_CPUSET_MEMS), Self::cpuset_value(mems))?;
+        }
+
+        Ok(())
+    }
+
+    // An empty cpuset makes the cgroup use the effective cpus or mems of its
+    // parent. An empty write does not reach the kernel, so a newline is written
+    // to reset a previously set value.
+    fn cpuset_value(value: &str) -> &str {
+        if value.trim().is_empty() {
+            "\n"
+        } else {
+            value
+        }
+    }
+
+    fn apply_partition(path: &Path, partition: &str) -> Result<()> {
+        let partition = partition.trim();
+        if !PARTITION_TYPES.contains(&partition) {
+            bail!(
+                "invalid value {:?} for {}, expected one of {}",
+                partition,
+                CGROUP_CPUSET_PARTITION,
+                PARTITION_TYPES.join(", ")
+            );
+        }
+
+        let partition_path = path.join(CGROUP_CPUSET_PARTITION);
+        log::debug!("Apply {} {}", CGROUP_CPUSET_PARTITION, partition);
+        common::write_cgroup_file_str(&partition_path, partition).with_context(|| {
+            format!("failed to set {} to {}", CGROUP_CPUSET_PARTITION, partition)
+        })?;
+
+        // the kernel accepts a partition which can not be created, but marks it
+        // as invalid, e.g. "root invalid (cpu list is not exclusive)"
+        let current = common::read_cgroup_file(&partition_path)?;
+        if current.contains("invalid") {
+            bail!(
+                "{} is {}, the cpus of the cgroup must be exclusive to it",
+                CGROUP_CPUSET_PARTITION,
+                current.trim()
+            );
         }
 
         Ok(())
@@ -72,4 +126,30 @@ mod tests {
             .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_MEMS));
         assert_eq!(content, "1-3");
     }
+
+    #[test]
+    fn test_reset_cpus() {
+        let (tmp, cpus) = setup("test_reset_cpus", CGROUP_CPUSET_CPUS);
+        let cpuset = LinuxCpuBuilder::new().with_cpus("".to_owned()).build();
+
+        CpuSet::apply(&tmp, &cpuset).expect("apply cpuset");
+
+        let content = fs::read_to_string(&cpus)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_CPUS));
+        assert_eq!(content, "\n");
+    }
+
+    #[test]
+    fn test_set_partition() {
+        let (tmp, partition) = setup("test_set_partition", CGROUP_CPUSET_PARTITION);
+
+        CpuSet::apply_partition(&tmp, "isolated").expect("apply partition");
+        let content = fs::read_to_string(&partition)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_PARTITION));
+        assert_eq!(content, "isolated");
+
+        assert!(CpuSet::apply_partition(&tmp, "exclusive").is_err());
+        fs::write(&partition, "root invalid (cpu list is not exclusive)").unwrap();
+        assert!(CpuSet::apply_partition(&tmp, "root").is_err());
+    }
 }
@@ -30,6 +30,12 @@ pub struct ProgramInfo {
     pub fd: i32,
 }
 
+impl Drop for ProgramInfo {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.fd);
+    }
+}
+
 pub fn prog_query(cgroup_fd: RawFd) -> Result<Vec<ProgramInfo>> {
     let mut prog_ids: Vec<u32> = vec![0_u32; 64];
     let mut attach_flags = 0_u32;
@@ -102,6 +108,25 @@ pub fn prog_attach(prog_fd: RawFd, cgroup_fd: RawFd) -> Result<()> {
     Ok(())
 }
 
+/// Attaches the program in place of an attached program in a single step, so
+/// there is no window in which both or none of the programs are attached.
+/// Requires linux 5.6, older kernels fail with EINVAL.
+pub fn prog_replace(prog_fd: RawFd, cgroup_fd: RawFd, old_prog_fd: RawFd) -> Result<()> {
+    let opts = libbpf_sys::bpf_prog_attach_opts {
+        sz: std::mem::size_of::<libbpf_sys::bpf_prog_attach_opts>() as _,
+        flags: libbpf_sys::BPF_F_ALLOW_MULTI | libbpf_sys::BPF_F_REPLACE,
+        replace_prog_fd: old_prog_fd,
+    };
+    let ret = unsafe {
+        libbpf_sys::bpf_prog_attach_xattr(prog_fd, cgroup_fd, libbpf_sys::BPF_CGROUP_DEVICE, &opts)
+    };
+
+    if ret != 0 {
+        return Err(errno::errno().into());
+    }
+    Ok(())
+}
+
 pub fn bump_memlock_rlimit() -> Result<()> {
     let rlimit = libc::rlimit {
         rlim_cur: 128 << 20,
@@ -1,9 +1,10 @@
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use super::*;
+use nix::dir::Dir;
 use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 use oci_spec::{LinuxDeviceCgroup, LinuxResources};
@@ -31,11 +32,18 @@ impl Devices {
         linux_devices: &Option<Vec<LinuxDeviceCgroup>>,
     ) -> Result<()> {
         log::debug!("Apply Devices cgroup config");
+        let cgroup_fd = Self::open_cgroup(cgroup_root)?;
+        let old_progs = bpf::prog_query(cgroup_fd.as_raw_fd())?;
 
-        // FIXME: should we start as "deny all"?
+        // an update without device rules keeps the rules of the container
+        if linux_devices.is_none() && !old_progs.is_empty() {
+            log::debug!("no device rules given, keep the attached program");
+            return Ok(());
+        }
+
+        // access to all devices is denied unless a rule allows it, same as runc
         let mut emulator = emulator::Emulator::with_default_allow(false);
 
-        // FIXME: apply user-defined and default rules in which order?
         if let Some(devices) = linux_devices {
             for d in devices {
                 log::debug!("apply user defined rule: {:?}", d);
@@ -43,6 +51,8 @@ impl Devices {
             }
         }
 
+        // the default devices are created in every container, so they are
+        // allowed after the rules of the user like runc does
         for d in [
             default_devices().iter().map(|d| d.into()).collect(),
             default_allow_devices(),
@@ -59,34 +69,52 @@ impl Devices {
         // This limit is not inherited into the container.
         bpf::bump_memlock_rlimit()?;
         let prog_fd = bpf::prog_load(LICENSE, prog.bytecodes())?;
+        let result = Self::attach(prog_fd, cgroup_fd.as_raw_fd(), &old_progs);
+        // the attached program is referenced by the cgroup
+        let _ = nix::unistd::close(prog_fd);
+        result
+    }
 
-        // FIXME: simple way to attach BPF program
-        //  1. get list of existing attached programs
-        //  2. attach this program (not use BPF_F_REPLACE, see below)
-        //  3. detach all programs of 1
-        //
-        // runc will use BPF_F_REPLACE to replace currently attached progam if:
-        //   1. BPF_F_REPLACE is supported by kernel
-        //   2. there is exactly one attached program
-        // https://github.com/opencontainers/runc/blob/8e6871a3b14bb74e0ef358aca3b9f8f9cb80f041/libcontainer/cgroups/ebpf/ebpf_linux.go#L165
-        //
-        // IMHO, this is too complicated, and in most cases, we just attach program once without
-        // already attached programs.
-
-        let fd = nix::dir::Dir::open(
-            cgroup_root.as_os_str(),
-            OFlag::O_RDONLY | OFlag::O_DIRECTORY,
-            Mode::from_bits(0o600).unwrap(),
-        )?;
+    // Same as runc, the program replaces the attached program atomically if
+    // there is exactly one. Otherwise the new program is attached next to
+    // the old ones, which are detached afterwards. Access is denied if any of
+    // the programs denies it, so no device is accessible in between which
+    // would not be accessible with either of the programs.
+    fn attach(prog_fd: RawFd, cgroup_fd: RawFd, old_progs: &[bpf::ProgramInfo]) -> Result<()> {
+        if let [old_prog] = old_progs {
+            match bpf::prog_replace(prog_fd, cgroup_fd, old_prog.fd) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log::debug!("failed to replace device program {}: {}", old_prog.id, err)
+                }
+            }
+        }
 
-        let old_progs = bpf::prog_query(fd.as_raw_fd())?;
-        bpf::prog_attach(prog_fd, fd.as_raw_fd())?;
+        bpf::prog_attach(prog_fd, cgroup_fd)?;
         for old_prog in old_progs {
-            bpf::prog_detach2(old_prog.fd, fd.as_raw_fd())?;
+            bpf::prog_detach2(old_prog.fd, cgroup_fd)?;
         }
 
         Ok(())
     }
-}
 
-// FIXME: add tests, but how to?
+    /// Detaches all device programs from the cgroup
+    pub fn remove(cgroup_root: &Path) -> Result<()> {
+        let cgroup_fd = Self::open_cgroup(cgroup_root)?;
+        for prog in bpf::prog_query(cgroup_fd.as_raw_fd())? {
+            log::debug!("detach device program {}", prog.id);
+            bpf::prog_detach2(prog.fd, cgroup_fd.as_raw_fd())?;
+        }
+
+        Ok(())
+    }
+
+    fn open_cgroup(cgroup_root: &Path) -> Result<Dir> {
+        Dir::open(
+            cgroup_root.as_os_str(),
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .with_context(|| format!("failed to open cgroup {:?}", cgroup_root))
+    }
+}
@@ -1,18 +1,28 @@
 use anyhow::{bail, Context, Result};
 use std::{
-    fs::OpenOptions,
-    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
     path::Path,
-    str, thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
 use oci_spec::{FreezerState, LinuxResources};
 
 use super::controller::Controller;
+use crate::common;
+use crate::error::CgroupError;
 
 const CGROUP_FREEZE: &str = "cgroup.freeze";
 const CGROUP_EVENTS: &str = "cgroup.events";
+// Freezing waits for all tasks to reach a point where they can be stopped,
+// which may take a while for tasks in uninterruptible sleep
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct Freezer {}
 
@@ -41,7 +51,10 @@ impl Freezer {
         {
             Err(e) => {
                 if let FreezerState::Frozen = freezer_state {
-                    bail!("freezer not supported {}", e);
+                    bail!(CgroupError::kernel_feature_missing(format!(
+                        "freezer not supported {}",
+                        e
+                    )));
                 }
                 return Ok(());
             }
@@ -49,73 +62,67 @@ impl Freezer {
         };
 
         // confirm that the cgroup did actually change states.
-        let actual_state = Self::read_freezer_state(path)?;
-        if !actual_state.eq(&freezer_state) {
-            bail!(
-                "expected \"cgroup.freeze\" to be in state {:?} but was in {:?}",
-                freezer_state,
-                actual_state
-            );
+        if let Err(err) = Self::wait_state(path, state_str, FREEZE_TIMEOUT) {
+            // a partially frozen cgroup would be stuck, so it is thawed again
+            if let FreezerState::Frozen = freezer_state {
+                let _ = common::write_cgroup_file_str(path.join(CGROUP_FREEZE), "0");
+            }
+            return Err(err);
         }
 
         Ok(())
     }
 
-    fn read_freezer_state(path: &Path) -> Result<FreezerState> {
-        let mut buf = [0; 1];
-        OpenOptions::new()
-            .create(false)
-            .read(true)
-            .open(path.join(CGROUP_FREEZE))?
-            .read_exact(&mut buf)?;
-
-        let state = str::from_utf8(&buf)?;
-        match state {
-            "0" => Ok(FreezerState::Thawed),
-            "1" => Self::wait_frozen(path),
-            _ => bail!("unknown \"cgroup.freeze\" state: {}", state),
-        }
-    }
-
-    // wait_frozen polls cgroup.events until it sees "frozen 1" in it.
-    fn wait_frozen(path: &Path) -> Result<FreezerState> {
-        let f = OpenOptions::new()
-            .create(false)
-            .read(true)
-            .open(path.join(CGROUP_EVENTS))?;
-        let mut f = BufReader::new(f);
-
-        let wait_time = Duration::from_millis(10);
-        let max_iter = 1000;
-        let mut iter = 0;
-        let mut line = String::new();
+    // Waits until cgroup.events reports the frozen state. The kernel notifies
+    // pollers of cgroup.events about changes with POLLPRI, the file is read
+    // again after an interval in any case.
+    fn wait_state(path: &Path, frozen: &str, timeout: Duration) -> Result<()> {
+        let mut events = File::open(path.join(CGROUP_EVENTS))
+            .with_context(|| format!("failed to open {}", CGROUP_EVENTS))?;
+        let start = Instant::now();
+        let mut retries = 0;
 
         loop {
-            if iter == max_iter {
+            match Self::read_frozen(&mut events)?.as_deref() {
+                Some(state) if state == frozen => {
+                    if retries > 1 {
+                        log::debug!("frozen state {} reached after {} retries", frozen, retries);
+                    }
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => bail!(
+                    "freezer not supported, {} does not contain the frozen state",
+                    CGROUP_EVENTS
+                ),
+            }
+
+            if start.elapsed() >= timeout {
                 bail!(
-                    "timeout of {} ms reached waiting for the cgroup to freeze",
-                    wait_time.as_millis() * max_iter
+                    "timeout of {} ms reached waiting for the cgroup to {}",
+                    timeout.as_millis(),
+                    if frozen == "1" { "freeze" } else { "thaw" }
                 );
             }
-            line.clear();
-            let num_bytes = f.read_line(&mut line)?;
-            if num_bytes == 0 {
-                break;
-            }
-            if line.starts_with("frozen ") {
-                if line.starts_with("frozen 1") {
-                    if iter > 1 {
-                        log::debug!("frozen after {} retries", iter)
-                    }
-                    return Ok(FreezerState::Frozen);
-                }
-                iter += 1;
-                thread::sleep(wait_time);
-                f.seek(SeekFrom::Start(0))?;
+
+            let mut fds = [PollFd::new(events.as_raw_fd(), PollFlags::POLLPRI)];
+            match poll(&mut fds, POLL_INTERVAL.as_millis() as i32) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(err) => return Err(err).context("failed to poll cgroup.events"),
             }
+            retries += 1;
         }
+    }
 
-        Ok(FreezerState::Undefined)
+    fn read_frozen(events: &mut File) -> Result<Option<String>> {
+        let mut content = String::new();
+        events.seek(SeekFrom::Start(0))?;
+        events.read_to_string(&mut content)?;
+
+        Ok(content
+            .lines()
+            .find_map(|line| line.strip_prefix("frozen "))
+            .map(|state| state.trim().to_owned()))
     }
 }
 
@@ -125,6 +132,7 @@ mod tests {
     use crate::test::{create_temp_dir, set_fixture};
     use oci_spec::FreezerState;
     use std::sync::Arc;
+    use std::thread;
 
     #[test]
     fn test_set_freezer_state() {
@@ -154,6 +162,12 @@ mod tests {
 
         // set Thawed state.
         {
+            let p = Arc::clone(&tmp);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                set_fixture(&p, CGROUP_EVENTS, "populated 0\nfrozen 0")
+                    .expect("Set fixure for freezer state");
+            });
             let freezer_state = FreezerState::Thawed;
             Freezer::apply(freezer_state, &tmp).expect("Set freezer state");
 
@@ -189,4 +203,17 @@ mod tests {
             assert!(r.is_err());
         }
     }
+
+    #[test]
+    fn test_wait_state_timeout() {
+        let tmp =
+            create_temp_dir("test_wait_state_timeout").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_FREEZE, "").expect("Set fixure for freezer state");
+        set_fixture(&tmp, CGROUP_EVENTS, "populated 1\nfrozen 0")
+            .expect("Set fixure for freezer state");
+
+        let r = Freezer::wait_state(&tmp, "1", Duration::from_millis(50));
+        assert!(r.is_err());
+        assert!(Freezer::wait_state(&tmp, "0", Duration::from_millis(50)).is_ok());
+    }
 }
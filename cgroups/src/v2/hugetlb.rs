@@ -4,6 +4,7 @@ use std::{collections::HashMap, path::Path};
 use super::controller::Controller;
 use crate::{
     common,
+    error::CgroupError,
     stats::{parse_single_value, supported_page_sizes, HugeTlbStats, StatsProvider},
 };
 
@@ -44,23 +45,70 @@ impl StatsProvider for HugeTlb {
 
 impl HugeTlb {
     fn apply(root_path: &Path, hugetlb: &LinuxHugepageLimit) -> Result<()> {
-        let page_size: String = hugetlb
-            .page_size
-            .chars()
-            .take_while(|c| c.is_digit(10))
-            .collect();
-        let page_size: u64 = page_size.parse()?;
-        if !Self::is_power_of_two(page_size) {
-            bail!("page size must be in the format of 2^(integer)");
-        }
+        let page_size = Self::normalize_page_size(&hugetlb.page_size)?;
+        let limit = if hugetlb.limit < 0 {
+            "max".to_owned()
+        } else {
+            hugetlb.limit.to_string()
+        };
 
-        common::write_cgroup_file(
-            root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size)),
-            hugetlb.limit,
+        common::write_cgroup_file_str(
+            root_path.join(format!("hugetlb.{}.max", page_size)),
+            &limit,
         )?;
+
+        // reservations of hugepages are limited separately since linux 5.7, the
+        // same limit applies so that a reservation can not bypass the limit
+        let rsvd_path = root_path.join(format!("hugetlb.{}.rsvd.max", page_size));
+        if rsvd_path.exists() {
+            common::write_cgroup_file_str(rsvd_path, &limit)?;
+        }
+
         Ok(())
     }
 
+    /// Normalizes a page size like "2M", "2mb" or "2048kB" into the format of
+    /// the kernel used in the names of the hugetlb files, e.g. "2MB"
+    pub(super) fn normalize_page_size(page_size: &str) -> Result<String> {
+        let trimmed = page_size.trim();
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let number: u64 = number
+            .parse()
+            .with_context(|| format!("invalid hugepage size {:?}", page_size))?;
+        let shift = match unit.to_ascii_lowercase().as_str() {
+            "" | "b" => 0,
+            "k" | "kb" | "kib" => 10,
+            "m" | "mb" | "mib" => 20,
+            "g" | "gb" | "gib" => 30,
+            _ => bail!(CgroupError::spec(format!(
+                "invalid unit of hugepage size {:?}",
+                page_size
+            ))),
+        };
+        let bytes = number
+            .checked_mul(1 << shift)
+            .with_context(|| format!("hugepage size {:?} is too large", page_size))?;
+
+        if !Self::is_power_of_two(bytes) || bytes < (1 << 10) {
+            bail!(CgroupError::spec(
+                "page size must be in the format of 2^(integer)"
+            ));
+        }
+
+        let normalized = if bytes >= (1 << 30) {
+            format!("{}GB", bytes >> 30)
+        } else if bytes >= (1 << 20) {
+            format!("{}MB", bytes >> 20)
+        } else {
+            format!("{}KB", bytes >> 10)
+        };
+
+        Ok(normalized)
+    }
+
     fn is_power_of_two(number: u64) -> bool {
         (number != 0) && (number & (number - 1)) == 0
     }
@@ -76,8 +124,14 @@ impl HugeTlb {
             .with_context(|| format!("failed to parse max value for {}", events_file))?
             .unwrap_or_default();
 
+        // the usage including the reserved pages is preferred, like runc does
+        let mut usage_path = cgroup_path.join(format!("hugetlb.{}.rsvd.current", page_size));
+        if !usage_path.exists() {
+            usage_path = cgroup_path.join(format!("hugetlb.{}.current", page_size));
+        }
+
         Ok(HugeTlbStats {
-            usage: parse_single_value(&cgroup_path.join(format!("hugetlb.{}.current", page_size)))?,
+            usage: parse_single_value(&usage_path)?,
             fail_count,
             ..Default::default()
         })
@@ -93,7 +147,7 @@ mod tests {
 
     #[test]
     fn test_set_hugetlb() {
-        let page_file_name = "hugetlb.2MB.limit_in_bytes";
+        let page_file_name = "hugetlb.2MB.max";
         let tmp = create_temp_dir("test_set_hugetlbv2").expect("create temp directory for test");
         set_fixture(&tmp, page_file_name, "0").expect("Set fixture for 2 MB page size");
 
@@ -106,6 +160,46 @@ mod tests {
         assert_eq!(hugetlb.limit.to_string(), content);
     }
 
+    #[test]
+    fn test_set_hugetlb_rsvd() {
+        let tmp =
+            create_temp_dir("test_set_hugetlbv2_rsvd").expect("create temp directory for test");
+        set_fixture(&tmp, "hugetlb.1GB.max", "").expect("Set fixture for 1 GB page size");
+        set_fixture(&tmp, "hugetlb.1GB.rsvd.max", "").expect("Set fixture for 1 GB rsvd");
+
+        let hugetlb = LinuxHugepageLimit {
+            page_size: "1024M".to_owned(),
+            limit: -1,
+        };
+        HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+        for file in &["hugetlb.1GB.max", "hugetlb.1GB.rsvd.max"] {
+            let content = read_to_string(tmp.join(file)).expect("Read hugetlb file content");
+            assert_eq!(content, "max");
+        }
+    }
+
+    #[test]
+    fn test_normalize_page_size() {
+        for (page_size, expected) in &[
+            ("2MB", "2MB"),
+            ("2M", "2MB"),
+            ("2mb", "2MB"),
+            ("2048kB", "2MB"),
+            ("64KB", "64KB"),
+            ("1GB", "1GB"),
+            ("1048576K", "1GB"),
+        ] {
+            assert_eq!(
+                HugeTlb::normalize_page_size(page_size).expect("normalize page size"),
+                *expected
+            );
+        }
+
+        for page_size in &["", "MB", "3MB", "2XB", "1"] {
+            assert!(HugeTlb::normalize_page_size(page_size).is_err());
+        }
+    }
+
     #[test]
     fn test_set_hugetlb_with_invalid_page_size() {
         let tmp = create_temp_dir("test_set_hugetlbv2_with_invalid_page_size")
@@ -125,24 +219,22 @@ mod tests {
 
     quickcheck! {
         fn property_test_set_hugetlb(hugetlb: LinuxHugepageLimit) -> bool {
-            let page_file_name = format!("hugetlb.{:?}.limit_in_bytes", hugetlb.page_size);
             let tmp = create_temp_dir("property_test_set_hugetlbv2").expect("create temp directory for test");
-            set_fixture(&tmp, &page_file_name, "0").expect("Set fixture for page size");
-            let result = HugeTlb::apply(&tmp, &hugetlb);
-
-            let page_size: String = hugetlb
-            .page_size
-            .chars()
-            .take_while(|c| c.is_digit(10))
-            .collect();
-            let page_size: u64 = page_size.parse().expect("parse page size");
-
-            if HugeTlb::is_power_of_two(page_size) && page_size != 1 {
-                let content =
-                    read_to_string(tmp.join(page_file_name)).expect("Read hugetlb file content");
-                hugetlb.limit.to_string() == content
+            let page_size = match HugeTlb::normalize_page_size(&hugetlb.page_size) {
+                Ok(page_size) => page_size,
+                Err(_) => return HugeTlb::apply(&tmp, &hugetlb).is_err(),
+            };
+
+            let page_file_name = format!("hugetlb.{}.max", page_size);
+            set_fixture(&tmp, &page_file_name, "").expect("Set fixture for page size");
+            HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+
+            let content =
+                read_to_string(tmp.join(page_file_name)).expect("Read hugetlb file content");
+            if hugetlb.limit < 0 {
+                content == "max"
             } else {
-                result.is_err()
+                hugetlb.limit.to_string() == content
             }
         }
     }
@@ -162,4 +254,16 @@ mod tests {
         };
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_stat_hugetlb_rsvd() {
+        let tmp =
+            create_temp_dir("test_stat_hugetlbv2_rsvd").expect("create temp directory for test");
+        set_fixture(&tmp, "hugetlb.2MB.current", "1024\n").expect("set hugetlb current");
+        set_fixture(&tmp, "hugetlb.2MB.rsvd.current", "4096\n").expect("set hugetlb rsvd current");
+        set_fixture(&tmp, "hugetlb.2MB.events", "max 0\n").expect("set hugetlb events");
+
+        let actual = HugeTlb::stats_for_page_size(&tmp, "2MB").expect("get cgroup stats");
+        assert_eq!(actual.usage, 4096);
+    }
 }
@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 
 use crate::{
-    common,
+    common::{self, UnsupportedSettings},
+    error::CgroupError,
     stats::{self, BlkioDeviceStat, BlkioStats, StatsProvider},
 };
 
@@ -13,6 +15,13 @@ use oci_spec::{LinuxBlockIo, LinuxResources};
 const CGROUP_BFQ_IO_WEIGHT: &str = "io.bfq.weight";
 const CGROUP_IO_WEIGHT: &str = "io.weight";
 const CGROUP_IO_STAT: &str = "io.stat";
+const CGROUP_IO_LATENCY: &str = "io.latency";
+const CGROUP_IO_COST_QOS: &str = "io.cost.qos";
+const CGROUP_IO_COST_MODEL: &str = "io.cost.model";
+
+/// Io quality of service files that can be set through the unified map. They are
+/// validated and applied by the io controller instead of being passed through as is.
+pub const IO_QOS_FILES: &[&str] = &[CGROUP_IO_LATENCY, CGROUP_IO_COST_QOS, CGROUP_IO_COST_MODEL];
 
 pub struct Io {}
 
@@ -33,6 +42,10 @@ impl StatsProvider for Io {
         let keyed_data = stats::parse_nested_keyed_data(&cgroup_path.join(CGROUP_IO_STAT))?;
         let mut service_bytes = Vec::with_capacity(keyed_data.len());
         let mut serviced = Vec::with_capacity(keyed_data.len());
+        let mut cost_usage = Vec::new();
+        let mut cost_wait = Vec::new();
+        let mut cost_indebt = Vec::new();
+        let mut cost_indelay = Vec::new();
         for entry in keyed_data {
             let (major, minor) = stats::parse_device_number(&entry.0)?;
             for value in &entry.1 {
@@ -40,37 +53,57 @@ impl StatsProvider for Io {
                     service_bytes.push(BlkioDeviceStat {
                         major,
                         minor,
-                        op_type: Some("read".to_owned()),
+                        op_type: Some("Read".to_owned()),
                         value: stats::parse_value(&value[7..])?,
                     });
                 } else if value.starts_with("wbytes") {
                     service_bytes.push(BlkioDeviceStat {
                         major,
                         minor,
-                        op_type: Some("write".to_owned()),
+                        op_type: Some("Write".to_owned()),
                         value: stats::parse_value(&value[7..])?,
                     });
                 } else if value.starts_with("rios") {
                     serviced.push(BlkioDeviceStat {
                         major,
                         minor,
-                        op_type: Some("read".to_owned()),
+                        op_type: Some("Read".to_owned()),
                         value: stats::parse_value(&value[5..])?,
                     });
                 } else if value.starts_with("wios") {
                     serviced.push(BlkioDeviceStat {
                         major,
                         minor,
-                        op_type: Some("write".to_owned()),
+                        op_type: Some("Write".to_owned()),
                         value: stats::parse_value(&value[5..])?,
                     });
+                } else if value.starts_with("cost.usage") {
+                    cost_usage.push(Self::device_stat(major, minor, &value[11..])?);
+                } else if value.starts_with("cost.wait") {
+                    cost_wait.push(Self::device_stat(major, minor, &value[10..])?);
+                } else if value.starts_with("cost.indebt") {
+                    cost_indebt.push(Self::device_stat(major, minor, &value[12..])?);
+                } else if value.starts_with("cost.indelay") {
+                    cost_indelay.push(Self::device_stat(major, minor, &value[13..])?);
                 }
             }
         }
 
+        let latency_path = cgroup_path.join(CGROUP_IO_LATENCY);
+        let latency_target = if latency_path.exists() {
+            Self::latency_targets(&latency_path)?
+        } else {
+            Vec::new()
+        };
+
         let stats = BlkioStats {
             service_bytes,
             serviced,
+            latency_target,
+            cost_usage,
+            cost_wait,
+            cost_indebt,
+            cost_indelay,
             ..Default::default()
         };
 
@@ -83,67 +116,260 @@ impl Io {
         path.join("io.max")
     }
 
-    // linux kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#io
-    fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> Result<()> {
-        if let Some(weight_device) = blkio.weight_device.as_ref() {
-            for wd in weight_device {
-                common::write_cgroup_file(
-                    root_path.join(CGROUP_BFQ_IO_WEIGHT),
-                    &format!("{}:{} {}", wd.major, wd.minor, wd.weight.unwrap()),
-                )?;
+    fn device_stat(major: u64, minor: u64, value: &str) -> Result<BlkioDeviceStat> {
+        Ok(BlkioDeviceStat {
+            major,
+            minor,
+            op_type: None,
+            value: stats::parse_value(value)?,
+        })
+    }
+
+    // io.latency contains one "MAJ:MIN target=<usec>" entry per configured device
+    fn latency_targets(latency_path: &Path) -> Result<Vec<BlkioDeviceStat>> {
+        let mut targets = Vec::new();
+        for (device, values) in stats::parse_nested_keyed_data(latency_path)? {
+            let (major, minor) = stats::parse_device_number(&device)?;
+            for value in values {
+                if let Some(target) = value.strip_prefix("target=") {
+                    let target = match target {
+                        "max" => u64::MAX,
+                        target => stats::parse_value(target)?,
+                    };
+                    targets.push(BlkioDeviceStat {
+                        major,
+                        minor,
+                        op_type: None,
+                        value: target,
+                    });
+                }
             }
         }
-        if let Some(leaf_weight) = blkio.leaf_weight {
-            if leaf_weight > 0 {
-                bail!("cannot set leaf_weight with cgroupv2");
+
+        Ok(targets)
+    }
+
+    /// Applies the io.latency entry of the unified map to the cgroup of the
+    /// container. io.cost.qos and io.cost.model only exist in the root cgroup,
+    /// where they configure the device for all cgroups of the host, so they are
+    /// validated but reported as unsupported instead of being written.
+    pub fn apply_qos(linux_resources: &LinuxResources, cgroup_path: &Path) -> Result<()> {
+        let unified = match &linux_resources.unified {
+            Some(unified) => unified,
+            None => return Ok(()),
+        };
+
+        let mut unsupported = UnsupportedSettings::default();
+        for &qos_file in IO_QOS_FILES {
+            if let Some(value) = unified.get(qos_file) {
+                let entries = Self::validate_qos(qos_file, value)?;
+                if qos_file != CGROUP_IO_LATENCY {
+                    unsupported.add(format!(
+                        "{} (only available in the root cgroup of the host)",
+                        qos_file
+                    ));
+                    continue;
+                }
+
+                log::debug!("Apply {} with {:?}", qos_file, entries);
+                let path = cgroup_path.join(qos_file);
+                for entry in entries {
+                    common::write_cgroup_file_str(&path, &entry)
+                        .with_context(|| format!("failed to set {} to {}", qos_file, entry))?;
+                }
             }
         }
-        if let Some(io_weight) = blkio.weight {
-            if io_weight > 0 {
-                common::write_cgroup_file(
-                    root_path.join(CGROUP_IO_WEIGHT),
-                    format!("{}", io_weight),
-                )?;
+
+        unsupported.check()
+    }
+
+    // Validates the entries of an io qos file. Every entry has the form
+    // "MAJ:MIN key=value [key=value...]", multiple entries are separated by newlines.
+    fn validate_qos(qos_file: &str, value: &str) -> Result<Vec<String>> {
+        let mut entries = Vec::new();
+        for entry in value.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let mut fields = entry.split_ascii_whitespace();
+            let device = fields.next().unwrap();
+            stats::parse_device_number(device)
+                .with_context(|| format!("invalid device {:?} in {}", device, qos_file))?;
+
+            let mut has_settings = false;
+            for field in fields {
+                let (key, val) = field.split_once('=').with_context(|| {
+                    format!("{:?} in {} is not in key=value format", field, qos_file)
+                })?;
+                Self::validate_qos_setting(qos_file, key, val)
+                    .with_context(|| format!("invalid setting {:?} in {}", field, qos_file))?;
+                has_settings = true;
             }
-        }
 
-        if let Some(throttle_read_bps_device) = blkio.throttle_read_bps_device.as_ref() {
-            for trbd in throttle_read_bps_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    &format!("{}:{} rbps={}", trbd.major, trbd.minor, trbd.rate),
-                )?;
+            if !has_settings {
+                bail!(
+                    "entry {:?} in {} does not contain any settings",
+                    entry,
+                    qos_file
+                );
             }
+
+            entries.push(entry.to_owned());
         }
 
-        if let Some(throttle_write_bps_device) = blkio.throttle_write_bps_device.as_ref() {
-            for twbd in throttle_write_bps_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} wbps={}", twbd.major, twbd.minor, twbd.rate),
-                )?;
+        Ok(entries)
+    }
+
+    fn validate_qos_setting(qos_file: &str, key: &str, value: &str) -> Result<()> {
+        match (qos_file, key) {
+            (CGROUP_IO_LATENCY, "target") => {
+                if value != "max" {
+                    value.parse::<u64>()?;
+                }
+            }
+            (CGROUP_IO_COST_QOS, "enable") => {
+                if value != "0" && value != "1" {
+                    bail!(CgroupError::spec("enable must be 0 or 1"));
+                }
+            }
+            (CGROUP_IO_COST_QOS, "ctrl") | (CGROUP_IO_COST_MODEL, "ctrl") => {
+                if value != "auto" && value != "user" {
+                    bail!(CgroupError::spec("ctrl must be auto or user"));
+                }
+            }
+            (CGROUP_IO_COST_QOS, "rpct") | (CGROUP_IO_COST_QOS, "wpct") => {
+                let pct: f64 = value.parse()?;
+                if !(0.0..=100.0).contains(&pct) {
+                    bail!(CgroupError::spec(format!(
+                        "{} must be a percentage between 0 and 100",
+                        key
+                    )));
+                }
+            }
+            (CGROUP_IO_COST_QOS, "min") | (CGROUP_IO_COST_QOS, "max") => {
+                let pct: f64 = value.parse()?;
+                if !(1.0..=10000.0).contains(&pct) {
+                    bail!(CgroupError::spec(format!(
+                        "{} must be a percentage between 1 and 10000",
+                        key
+                    )));
+                }
+            }
+            (CGROUP_IO_COST_QOS, "rlat") | (CGROUP_IO_COST_QOS, "wlat") => {
+                value.parse::<u64>()?;
+            }
+            (CGROUP_IO_COST_MODEL, "model") => {
+                if value != "linear" {
+                    bail!(CgroupError::spec("only the linear cost model is supported"));
+                }
             }
+            (CGROUP_IO_COST_MODEL, "rbps")
+            | (CGROUP_IO_COST_MODEL, "rseqiops")
+            | (CGROUP_IO_COST_MODEL, "rrandiops")
+            | (CGROUP_IO_COST_MODEL, "wbps")
+            | (CGROUP_IO_COST_MODEL, "wseqiops")
+            | (CGROUP_IO_COST_MODEL, "wrandiops") => {
+                value.parse::<u64>()?;
+            }
+            _ => bail!("unknown key {}", key),
+        }
+
+        Ok(())
+    }
+
+    // linux kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#io
+    fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> Result<()> {
+        // cgroup v2 has no leaf weights
+        let mut unsupported = UnsupportedSettings::default();
+        if blkio.leaf_weight.map_or(false, |w| w > 0) {
+            unsupported.add("blkio leaf weight (not available on cgroup v2)");
         }
 
-        if let Some(throttle_read_iops_device) = blkio.throttle_read_iops_device.as_ref() {
-            for trid in throttle_read_iops_device {
+        // the weights of the spec have the range of blkio (10-1000). The bfq
+        // scheduler uses the same range, but io.weight ranges from 1 to 10000.
+        let bfq = root_path.join(CGROUP_BFQ_IO_WEIGHT).exists();
+        if let Some(weight) = blkio.weight.filter(|w| *w > 0) {
+            if bfq {
+                common::write_cgroup_file(root_path.join(CGROUP_BFQ_IO_WEIGHT), weight)?;
+            } else {
                 common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} riops={}", trid.major, trid.minor, trid.rate),
+                    root_path.join(CGROUP_IO_WEIGHT),
+                    Self::convert_weight_to_cgroup2(weight),
                 )?;
             }
         }
 
-        if let Some(throttle_write_iops_device) = blkio.throttle_write_iops_device.as_ref() {
-            for twid in throttle_write_iops_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} wiops={}", twid.major, twid.minor, twid.rate),
-                )?;
+        for wd in blkio.weight_device.iter().flatten() {
+            let weight = match wd.weight {
+                Some(weight) => weight,
+                None if wd.leaf_weight.map_or(false, |w| w > 0) => {
+                    unsupported.add(format!(
+                        "blkio leaf weight of device {}:{} (not available on cgroup v2)",
+                        wd.major, wd.minor
+                    ));
+                    continue;
+                }
+                None => continue,
+            };
+
+            let result = if bfq {
+                common::write_block_device_setting(
+                    root_path.join(CGROUP_BFQ_IO_WEIGHT),
+                    wd.major,
+                    wd.minor,
+                    weight,
+                )
+            } else {
+                common::write_block_device_setting(
+                    root_path.join(CGROUP_IO_WEIGHT),
+                    wd.major,
+                    wd.minor,
+                    Self::convert_weight_to_cgroup2(weight),
+                )
+            };
+            result.with_context(|| {
+                format!(
+                    "failed to set io weight of device {}:{}",
+                    wd.major, wd.minor
+                )
+            })?;
+        }
+
+        // all limits of a device are written in a single line, a limit which
+        // is not part of the line keeps its current value
+        let mut limits: BTreeMap<(i64, i64), Vec<String>> = BTreeMap::new();
+        let throttles = [
+            ("rbps", &blkio.throttle_read_bps_device),
+            ("wbps", &blkio.throttle_write_bps_device),
+            ("riops", &blkio.throttle_read_iops_device),
+            ("wiops", &blkio.throttle_write_iops_device),
+        ];
+        for (key, devices) in throttles.iter() {
+            for device in devices.iter().flatten() {
+                limits
+                    .entry((device.major, device.minor))
+                    .or_default()
+                    .push(format!("{}={}", key, device.rate));
             }
         }
 
-        Ok(())
+        for ((major, minor), limits) in limits {
+            common::write_cgroup_file(
+                Self::io_max_path(root_path),
+                format!("{} {}", Self::device_key(major, minor), limits.join(" ")),
+            )?;
+        }
+
+        unsupported.check()
+    }
+
+    fn device_key(major: i64, minor: i64) -> String {
+        format!("{}:{}", major, minor)
+    }
+
+    fn convert_weight_to_cgroup2(weight: u16) -> u64 {
+        if weight == 0 {
+            return 0;
+        }
+
+        1 + (weight.saturating_sub(10) as u64 * 9999) / 990
     }
 }
 #[cfg(test)]
@@ -300,9 +526,150 @@ mod test {
         let blkio = BlockIoBuilder::new().with_write_io_weight(100).build();
         Io::apply(&tmp, &blkio).expect("apply blkio");
         let content =
-            fs::read_to_string(throttle).unwrap_or_else(|_| panic!("read bfq_io_weight content"));
+            fs::read_to_string(throttle).unwrap_or_else(|_| panic!("read io_weight content"));
+
+        // converted from the range of blkio to the range of io.weight
+        assert_eq!("910", content);
+    }
+
+    #[test]
+    fn test_set_ioweight_bfq() {
+        let (tmp, bfq) = setup("test_set_io_weight_bfq", CGROUP_BFQ_IO_WEIGHT);
+        let io_weight = set_fixture(&tmp, CGROUP_IO_WEIGHT, "").expect("set fixture for io.weight");
+        let blkio = BlockIoBuilder::new().with_write_io_weight(100).build();
+        Io::apply(&tmp, &blkio).expect("apply blkio");
+
+        assert_eq!(
+            "100",
+            fs::read_to_string(bfq).expect("read bfq_io_weight content")
+        );
+        assert_eq!(
+            "",
+            fs::read_to_string(io_weight).expect("read io_weight content")
+        );
+    }
+
+    #[test]
+    fn test_set_ioweight_device_without_bfq() {
+        let (tmp, io_weight) = setup("test_set_io_weight_device_without_bfq", CGROUP_IO_WEIGHT);
+        let blkio = BlockIoBuilder::new()
+            .with_write_weight_device(vec![LinuxWeightDevice {
+                major: 8,
+                minor: 0,
+                weight: Some(1000),
+                leaf_weight: None,
+            }])
+            .build();
+        Io::apply(&tmp, &blkio).expect("apply blkio");
+        let content = fs::read_to_string(io_weight).expect("read io_weight content");
+
+        assert_eq!("8:0 10000", content);
+    }
+
+    #[test]
+    fn test_set_io_max_per_device() {
+        let (tmp, throttle) = setup("test_set_io_max_per_device", "io.max");
+        let throttle_device = |rate| {
+            vec![LinuxThrottleDevice {
+                major: 8,
+                minor: 0,
+                rate,
+            }]
+        };
+        let blkio = BlockIoBuilder::new()
+            .with_read_bps(throttle_device(102400))
+            .with_write_bps(throttle_device(51200))
+            .with_write_iops(throttle_device(100))
+            .build();
+        Io::apply(&tmp, &blkio).expect("apply blkio");
+        let content = fs::read_to_string(throttle).expect("read io.max content");
+
+        assert_eq!("8:0 rbps=102400 wbps=51200 wiops=100", content);
+    }
+
+    #[test]
+    fn test_apply_io_latency() {
+        let (tmp, latency) = setup("test_apply_io_latency", CGROUP_IO_LATENCY);
+        let resources = LinuxResources {
+            unified: Some(
+                [(CGROUP_IO_LATENCY.to_owned(), "8:0 target=10000".to_owned())]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        Io::apply_qos(&resources, &tmp).expect("apply io qos");
+        let content = fs::read_to_string(latency).expect("read io.latency content");
+
+        assert_eq!("8:0 target=10000", content);
+    }
+
+    #[test]
+    fn test_apply_io_cost_is_unsupported() {
+        let (tmp, qos) = setup("test_apply_io_cost_is_unsupported", CGROUP_IO_COST_QOS);
+        let resources = LinuxResources {
+            unified: Some(
+                [(
+                    CGROUP_IO_COST_QOS.to_owned(),
+                    "8:0 enable=1 ctrl=user rpct=95.00 rlat=10000 wpct=95.00 wlat=20000".to_owned(),
+                )]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            ..Default::default()
+        };
+
+        let err = Io::apply_qos(&resources, &tmp).unwrap_err();
+        assert!(err.is::<UnsupportedSettings>());
+        let content = fs::read_to_string(qos).expect("read io.cost.qos content");
+        assert_eq!("", content);
+    }
+
+    #[test]
+    fn test_validate_io_qos() {
+        assert!(Io::validate_qos(CGROUP_IO_LATENCY, "8:0 target=max").is_ok());
+        assert!(Io::validate_qos(
+            CGROUP_IO_COST_MODEL,
+            "8:16 ctrl=user model=linear rbps=2706339840 rseqiops=89698 rrandiops=110036"
+        )
+        .is_ok());
+
+        // invalid device number
+        assert!(Io::validate_qos(CGROUP_IO_LATENCY, "sda target=100").is_err());
+        // missing settings
+        assert!(Io::validate_qos(CGROUP_IO_LATENCY, "8:0").is_err());
+        // unknown key
+        assert!(Io::validate_qos(CGROUP_IO_LATENCY, "8:0 latency=100").is_err());
+        // out of range
+        assert!(Io::validate_qos(CGROUP_IO_COST_QOS, "8:0 rpct=101").is_err());
+        assert!(Io::validate_qos(CGROUP_IO_COST_QOS, "8:0 enable=2").is_err());
+    }
+
+    #[test]
+    fn test_stat_io_qos() {
+        let tmp = create_temp_dir("test_stat_io_qos").expect("create test directory");
+        let stat_content = "8:0 rbytes=0 wbytes=0 rios=0 wios=0 dbytes=0 dios=0 cost.vrate=135.00 cost.usage=7 cost.wait=3 cost.indebt=2 cost.indelay=1";
+        set_fixture(&tmp, CGROUP_IO_STAT, stat_content).unwrap();
+        set_fixture(&tmp, CGROUP_IO_LATENCY, "8:0 target=10000\n").unwrap();
+
+        let actual = Io::stats(&tmp).expect("get cgroup stats");
+        let stat = |value| {
+            vec![BlkioDeviceStat {
+                major: 8,
+                minor: 0,
+                op_type: None,
+                value,
+            }]
+        };
 
-        assert_eq!("100", content);
+        assert_eq!(actual.latency_target, stat(10000));
+        assert_eq!(actual.cost_usage, stat(7));
+        assert_eq!(actual.cost_wait, stat(3));
+        assert_eq!(actual.cost_indebt, stat(2));
+        assert_eq!(actual.cost_indelay, stat(1));
     }
 
     #[test]
@@ -321,25 +688,25 @@ mod test {
                 BlkioDeviceStat {
                     major: 7,
                     minor: 9,
-                    op_type: Some("read".to_owned()),
+                    op_type: Some("Read".to_owned()),
                     value: 34629632,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 9,
-                    op_type: Some("write".to_owned()),
+                    op_type: Some("Write".to_owned()),
                     value: 274965,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 10,
-                    op_type: Some("read".to_owned()),
+                    op_type: Some("Read".to_owned()),
                     value: 18432,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 10,
-                    op_type: Some("write".to_owned()),
+                    op_type: Some("Write".to_owned()),
                     value: 16842,
                 },
             ],
@@ -347,25 +714,25 @@ mod test {
                 BlkioDeviceStat {
                     major: 7,
                     minor: 9,
-                    op_type: Some("read".to_owned()),
+                    op_type: Some("Read".to_owned()),
                     value: 1066,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 9,
-                    op_type: Some("write".to_owned()),
+                    op_type: Some("Write".to_owned()),
                     value: 319,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 10,
-                    op_type: Some("read".to_owned()),
+                    op_type: Some("Read".to_owned()),
                     value: 12,
                 },
                 BlkioDeviceStat {
                     major: 7,
                     minor: 10,
-                    op_type: Some("write".to_owned()),
+                    op_type: Some("Write".to_owned()),
                     value: 0,
                 },
             ],
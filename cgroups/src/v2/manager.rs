@@ -1,13 +1,14 @@
 use std::{
-    fs::{self},
+    fs::{self, File},
     os::unix::fs::PermissionsExt,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::Receiver,
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
 use nix::unistd::Pid;
-use oci_spec::{FreezerState, LinuxResources};
+use oci_spec::{FreezerState, LinuxCpu, LinuxResources};
 
 #[cfg(feature = "cgroupsv2_devices")]
 use super::devices::Devices;
@@ -22,12 +23,15 @@ use super::{
     hugetlb::HugeTlb,
     io::Io,
     memory::Memory,
+    misc::Misc,
     pids::Pids,
     unified::Unified,
+    util,
 };
 use crate::{
-    common::{self, CgroupManager, PathBufExt, CGROUP_PROCS},
-    stats::{Stats, StatsProvider},
+    common::{self, CgroupHierarchy, CgroupManager, CpuRampdown, PathBufExt, CGROUP_PROCS},
+    error::CgroupError,
+    stats::Stats,
 };
 
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
@@ -37,6 +41,8 @@ pub struct Manager {
     root_path: PathBuf,
     cgroup_path: PathBuf,
     full_path: PathBuf,
+    strict: bool,
+    skip_devices: bool,
 }
 
 impl Manager {
@@ -49,38 +55,110 @@ impl Manager {
             root_path,
             cgroup_path,
             full_path,
+            strict: false,
+            skip_devices: false,
         })
     }
 
-    fn create_unified_cgroup(&self, pid: Pid) -> Result<()> {
-        let controllers: Vec<String> = self
-            .get_available_controllers()?
-            .iter()
-            .map(|c| format!("{}{}", "+", c.to_string()))
-            .collect();
+    /// Fails to apply the resources if a setting is not supported by the host,
+    /// instead of skipping it with a warning
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Does not apply any device rules, e.g. if the devices controller is
+    /// managed by an outer container
+    pub fn with_skip_devices(mut self, skip_devices: bool) -> Self {
+        self.skip_devices = skip_devices;
+        self
+    }
 
-        Self::write_controllers(&self.root_path, &controllers)?;
+    fn create_unified_cgroup(&self, pid: Pid) -> Result<()> {
+        self.create_cgroup_dirs()?;
+        common::write_cgroup_file(&self.full_path.join(CGROUP_PROCS), pid)?;
+        Ok(())
+    }
 
+    fn create_cgroup_dirs(&self) -> Result<()> {
         let mut current_path = self.root_path.clone();
-        let mut components = self.cgroup_path.components().skip(1).peekable();
-        while let Some(component) = components.next() {
+        for component in self.components() {
+            // the controllers of the parent are enabled for the new cgroup on a
+            // best effort basis, the controllers which are required by the
+            // resources are enabled when they are applied
+            Self::write_controllers(&current_path, &Self::read_controllers(&current_path)?);
+
             current_path = current_path.join(component);
             if !current_path.exists() {
                 fs::create_dir(&current_path)?;
                 fs::metadata(&current_path)?.permissions().set_mode(0o755);
             }
+        }
+
+        Ok(())
+    }
+
+    // Normal components of the cgroup path. The last component cannot have the
+    // subtree_control enabled due to the internal process constraint. If this
+    // were set, writing to the cgroups.procs file would fail with Errno 16
+    // (device or resource busy).
+    fn components(&self) -> impl Iterator<Item = Component<'_>> {
+        self.cgroup_path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+    }
+
+    /// Enables the controllers in the cgroup.subtree_control of every ancestor
+    /// of the cgroup, starting at the root. A controller can only be enabled
+    /// for the children of a cgroup if it is listed in its cgroup.controllers,
+    /// i.e. if it has been enabled or delegated by its own parent.
+    fn enable_controllers(&self, controllers: &[String]) -> Result<()> {
+        if controllers.is_empty() {
+            return Ok(());
+        }
+
+        let mut current_path = self.root_path.clone();
+        for component in self.components() {
+            let available = Self::read_controllers(&current_path)?;
+            let enabled = common::read_cgroup_file(current_path.join(CGROUP_SUBTREE_CONTROL))?;
+            let enabled: Vec<&str> = enabled.split_whitespace().collect();
+            for controller in controllers {
+                if enabled.contains(&controller.as_str()) {
+                    continue;
+                }
+
+                if !available.contains(controller) {
+                    bail!(CgroupError::kernel_feature_missing(format!(
+                        "controller {} is required by the resources, but is not available in {:?}. \
+                        It has not been enabled or delegated by the parent cgroup",
+                        controller,
+                        current_path
+                    )));
+                }
 
-            // last component cannot have subtree_control enabled due to internal process constraint
-            // if this were set, writing to the cgroups.procs file will fail with Erno 16 (device or resource busy)
-            if components.peek().is_some() {
-                Self::write_controllers(&current_path, &controllers)?;
+                common::write_cgroup_file_str(
+                    current_path.join(CGROUP_SUBTREE_CONTROL),
+                    &format!("+{}", controller),
+                )
+                .with_context(|| {
+                    format!(
+                        "failed to enable controller {} in {:?}",
+                        controller, current_path
+                    )
+                })?;
             }
+
+            current_path = current_path.join(component);
         }
 
-        common::write_cgroup_file(&self.full_path.join(CGROUP_PROCS), pid)?;
         Ok(())
     }
 
+    fn read_controllers(path: &Path) -> Result<Vec<String>> {
+        let controllers = common::read_cgroup_file(path.join(CGROUP_CONTROLLERS))?;
+        Ok(controllers.split_whitespace().map(str::to_owned).collect())
+    }
+
     fn get_available_controllers(&self) -> Result<Vec<ControllerType>> {
         let controllers_path = self.root_path.join(CGROUP_CONTROLLERS);
         if !controllers_path.exists() {
@@ -100,6 +178,7 @@ impl Manager {
                 "memory" => controllers.push(ControllerType::Memory),
                 "pids" => controllers.push(ControllerType::Pids),
                 "freezer" => controllers.push(ControllerType::Freezer),
+                "misc" => controllers.push(ControllerType::Misc),
                 tpe => log::warn!("Controller {} is not yet implemented.", tpe),
             }
         }
@@ -107,42 +186,67 @@ impl Manager {
         Ok(controllers)
     }
 
-    fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
+    fn write_controllers(path: &Path, controllers: &[String]) {
         for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+            let controller = format!("+{}", controller);
+            if let Err(err) =
+                common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), &controller)
+            {
+                log::debug!("could not enable {} in {:?}: {:?}", controller, path, err);
+            }
         }
-
-        Ok(())
     }
 }
 
 impl CgroupManager for Manager {
-    fn add_task(&self, pid: Pid) -> Result<()> {
+    fn add_task(&self, pid: Pid) -> Result<(), CgroupError> {
         self.create_unified_cgroup(pid)?;
         Ok(())
     }
 
-    fn apply(&self, linux_resources: &LinuxResources) -> Result<()> {
+    fn open_cgroup_dir(&self) -> Result<Option<File>, CgroupError> {
+        self.create_cgroup_dirs()?;
+        let dir = File::open(&self.full_path)
+            .with_context(|| format!("failed to open cgroup {:?}", self.full_path))?;
+        Ok(Some(dir))
+    }
+
+    fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError> {
+        self.enable_controllers(&util::required_controllers(linux_resources))?;
+
         for controller in CONTROLLER_TYPES {
-            match controller {
-                ControllerType::Cpu => Cpu::apply(linux_resources, &self.full_path)?,
-                ControllerType::CpuSet => CpuSet::apply(linux_resources, &self.full_path)?,
-                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &self.full_path)?,
-                ControllerType::Io => Io::apply(linux_resources, &self.full_path)?,
-                ControllerType::Memory => Memory::apply(linux_resources, &self.full_path)?,
-                ControllerType::Pids => Pids::apply(linux_resources, &self.full_path)?,
-                ControllerType::Freezer => Freezer::apply(linux_resources, &self.full_path)?,
-            }
+            let result = match controller {
+                ControllerType::Cpu => Cpu::apply(linux_resources, &self.full_path),
+                ControllerType::CpuSet => CpuSet::apply(linux_resources, &self.full_path),
+                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &self.full_path),
+                ControllerType::Io => Io::apply(linux_resources, &self.full_path),
+                ControllerType::Memory => Memory::apply(linux_resources, &self.full_path),
+                ControllerType::Pids => Pids::apply(linux_resources, &self.full_path),
+                ControllerType::Freezer => Freezer::apply(linux_resources, &self.full_path),
+                ControllerType::Misc => Misc::apply(linux_resources, &self.full_path),
+            };
+            common::check_controller_result(result, self.strict)?;
         }
 
+        common::check_controller_result(
+            Io::apply_qos(linux_resources, &self.full_path),
+            self.strict,
+        )?;
+
         #[cfg(feature = "cgroupsv2_devices")]
-        Devices::apply(linux_resources, &self.cgroup_path)?;
+        if !self.skip_devices {
+            Devices::apply(linux_resources, &self.full_path)?;
+        }
+        #[cfg(not(feature = "cgroupsv2_devices"))]
+        if linux_resources.devices.is_some() && !self.skip_devices {
+            log::warn!("youki is built without cgroupsv2_devices, device rules are not enforced");
+        }
 
         for pseudoctlr in PSEUDO_CONTROLLER_TYPES {
             match pseudoctlr {
                 PseudoControllerType::Unified => Unified::apply(
                     linux_resources,
-                    &self.cgroup_path,
+                    &self.full_path,
                     self.get_available_controllers()?,
                 )?,
                 _ => {}
@@ -152,39 +256,115 @@ impl CgroupManager for Manager {
         Ok(())
     }
 
-    fn remove(&self) -> Result<()> {
+    fn remove(&self) -> Result<(), CgroupError> {
         log::debug!("remove cgroup {:?}", self.full_path);
-        fs::remove_dir(&self.full_path)?;
-
-        Ok(())
+        #[cfg(feature = "cgroupsv2_devices")]
+        if self.full_path.exists() {
+            Devices::remove(&self.full_path)?;
+        }
+        Ok(common::remove_cgroup_dir(&self.full_path)?)
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<()> {
+    fn freeze(&self, state: FreezerState) -> Result<(), CgroupError> {
         let linux_resources = LinuxResources {
             freezer: Some(state),
             ..Default::default()
         };
-        Freezer::apply(&linux_resources, &self.full_path)
+        Ok(Freezer::apply(&linux_resources, &self.full_path)?)
     }
 
-    fn stats(&self) -> Result<Stats> {
-        let mut stats = Stats::default();
+    fn ramp_down_cpu(&self, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<(), CgroupError> {
+        Ok(Cpu::ramp_down(&self.full_path, cpu, rampdown)?)
+    }
 
-        for subsystem in CONTROLLER_TYPES {
-            match subsystem {
-                ControllerType::Cpu => stats.cpu.usage = Cpu::stats(&self.full_path)?,
-                ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(&self.full_path)?,
-                ControllerType::Pids => stats.pids = Pids::stats(&self.full_path)?,
-                ControllerType::Memory => stats.memory = Memory::stats(&self.full_path)?,
-                ControllerType::Io => stats.blkio = Io::stats(&self.full_path)?,
-                _ => continue,
-            }
-        }
+    fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError> {
+        Ok(vec![CgroupHierarchy {
+            name: None,
+            path: self.full_path.clone(),
+        }])
+    }
+
+    fn stats(&self) -> Result<Stats, CgroupError> {
+        Ok(util::get_stats(&self.full_path)?)
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError> {
+        Ok(common::get_all_pids(&self.full_path)?)
+    }
 
-        Ok(stats)
+    fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError> {
+        Ok(common::get_all_threads(
+            &self.full_path,
+            common::CGROUP_THREADS,
+        )?)
     }
 
-    fn get_all_pids(&self) -> Result<Vec<Pid>> {
-        common::get_all_pids(&self.full_path)
+    fn kill(&self) -> Result<bool, CgroupError> {
+        Ok(common::write_cgroup_kill(&self.full_path)?)
+    }
+
+    fn exists(&self) -> bool {
+        self.full_path.exists()
+    }
+
+    fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError> {
+        Ok(Memory::notify_on_oom(&self.full_path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+
+    #[test]
+    fn test_enable_unavailable_controller() {
+        let tmp = create_temp_dir("test_enable_unavailable_controller")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CONTROLLERS, "cpu memory pids").unwrap();
+        set_fixture(&tmp, CGROUP_SUBTREE_CONTROL, "memory pids").unwrap();
+        let parent = tmp.join("youki");
+        fs::create_dir(&parent).unwrap();
+        set_fixture(&parent, CGROUP_CONTROLLERS, "memory pids").unwrap();
+        set_fixture(&parent, CGROUP_SUBTREE_CONTROL, "").unwrap();
+
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki/container"))
+            .expect("create manager");
+        manager
+            .enable_controllers(&["memory".to_owned()])
+            .expect("enable memory");
+        assert_eq!(
+            fs::read_to_string(parent.join(CGROUP_SUBTREE_CONTROL)).unwrap(),
+            "+memory"
+        );
+
+        // cpu can be enabled in the root, but it has not been delegated to youki
+        set_fixture(&tmp, CGROUP_SUBTREE_CONTROL, "cpu memory pids").unwrap();
+        let err = manager
+            .enable_controllers(&["cpu".to_owned()])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("controller cpu is required by the resources, but is not available")
+        );
+        assert!(err.contains("youki"));
+    }
+
+    #[test]
+    fn test_exists_and_is_empty() -> Result<()> {
+        let tmp = create_temp_dir("test_exists_and_is_empty").expect("create temp directory");
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki/container"))?;
+        assert!(!manager.exists());
+        assert!(manager.is_empty()?);
+
+        let cgroup = tmp.join("youki/container");
+        fs::create_dir_all(&cgroup)?;
+        set_fixture(&cgroup, CGROUP_PROCS, "")?;
+        assert!(manager.exists());
+        assert!(manager.is_empty()?);
+
+        set_fixture(&cgroup, CGROUP_PROCS, "1234\n")?;
+        assert!(!manager.is_empty()?);
+        Ok(())
     }
 }
@@ -1,10 +1,16 @@
 use anyhow::{bail, Context, Result};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::unistd;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 use oci_spec::{LinuxMemory, LinuxResources};
 
 use crate::{
-    common,
+    common::{self, UnsupportedSettings},
+    error::CgroupError,
     stats::{self, MemoryData, MemoryStats, StatsProvider},
 };
 
@@ -13,18 +19,45 @@ use super::controller::Controller;
 const CGROUP_MEMORY_SWAP: &str = "memory.swap.max";
 const CGROUP_MEMORY_MAX: &str = "memory.max";
 const CGROUP_MEMORY_LOW: &str = "memory.low";
+const CGROUP_MEMORY_MIN: &str = "memory.min";
+const CGROUP_MEMORY_HIGH: &str = "memory.high";
+const CGROUP_MEMORY_ZSWAP: &str = "memory.zswap.max";
+const CGROUP_MEMORY_OOM_GROUP: &str = "memory.oom.group";
 const MEMORY_STAT: &str = "memory.stat";
+const MEMORY_EVENTS: &str = "memory.events";
+const CGROUP_EVENTS: &str = "cgroup.events";
+
+/// Memory files without a field in the spec that can be set through the unified
+/// map. They are validated and applied by the memory controller.
+pub const MEMORY_UNIFIED_FILES: &[&str] = &[
+    CGROUP_MEMORY_MIN,
+    CGROUP_MEMORY_HIGH,
+    CGROUP_MEMORY_ZSWAP,
+    CGROUP_MEMORY_OOM_GROUP,
+];
 
 pub struct Memory {}
 
 impl Controller for Memory {
     fn apply(linux_resources: &LinuxResources, cgroup_path: &Path) -> Result<()> {
+        let mut unsupported = UnsupportedSettings::default();
         if let Some(memory) = &linux_resources.memory {
             Self::apply(cgroup_path, memory)
                 .context("failed to apply memory resource restrictions")?;
+            if memory.disable_oom_killer == Some(true) {
+                unsupported.add("disabling the oom killer (not available on cgroup v2)");
+            }
         }
 
-        Ok(())
+        if let Some(unified) = &linux_resources.unified {
+            for &file in MEMORY_UNIFIED_FILES {
+                if let Some(value) = unified.get(file) {
+                    Self::apply_unified(cgroup_path, file, value, &mut unsupported)?;
+                }
+            }
+        }
+
+        unsupported.check()
     }
 }
 
@@ -32,15 +65,43 @@ impl StatsProvider for Memory {
     type Stats = MemoryStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        let stats = MemoryStats {
-            memory: Self::get_memory_data(cgroup_path, "memory", "oom")?,
-            memswap: Self::get_memory_data(cgroup_path, "memory.swap", "fail")?,
-            hierarchy: true,
-            stats: stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))?,
-            ..Default::default()
+        let memory = Self::get_memory_data(cgroup_path, "memory", "oom")?;
+        // cgroup v2 accounts the swap separately, but memswap reports the usage
+        // of memory and swap like cgroup v1 does. The swap files do not exist
+        // if swap accounting is disabled.
+        let memswap = if cgroup_path.join(CGROUP_MEMORY_SWAP).exists() {
+            let swap = Self::get_memory_data(cgroup_path, "memory.swap", "fail")?;
+            MemoryData {
+                usage: memory.usage + swap.usage,
+                fail_count: swap.fail_count,
+                limit: memory.limit.saturating_add(swap.limit),
+                ..Default::default()
+            }
+        } else {
+            MemoryData {
+                usage: memory.usage,
+                limit: memory.limit,
+                ..Default::default()
+            }
         };
 
-        Ok(stats)
+        // oom_kill is reported since linux 4.13
+        let oom_kill = Self::oom_kill_count(&cgroup_path.join(MEMORY_EVENTS))?;
+        let stats = stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))?;
+        let stat = |name: &str| stats.get(name).copied().unwrap_or_default();
+        Ok(MemoryStats {
+            memory,
+            memswap,
+            cache: stat("file"),
+            rss: stat("anon"),
+            mapped_file: stat("file_mapped"),
+            page_faults: stat("pgfault"),
+            major_page_faults: stat("pgmajfault"),
+            hierarchy: true,
+            oom_kill,
+            stats,
+            ..Default::default()
+        })
     }
 }
 
@@ -54,6 +115,13 @@ impl Memory {
             stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "current")))?;
         let limit =
             stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "max")))?;
+        // the peak is only reported since linux 5.19
+        let peak = cgroup_path.join(format!("{}.{}", file_prefix, "peak"));
+        let max_usage = if peak.exists() {
+            stats::parse_single_value(&peak)?
+        } else {
+            Default::default()
+        };
 
         let events = stats::parse_flat_keyed_data(
             &cgroup_path.join(format!("{}.{}", file_prefix, "events")),
@@ -66,12 +134,60 @@ impl Memory {
 
         Ok(MemoryData {
             usage,
+            max_usage,
             fail_count,
             limit,
-            ..Default::default()
         })
     }
 
+    /// Watches memory.events with inotify and sends a message whenever the
+    /// oom_kill counter has increased, same as runc. The notifications end
+    /// when the cgroup has no processes anymore or has been removed.
+    pub fn notify_on_oom(cgroup_path: &Path) -> Result<Receiver<()>> {
+        let memory_events = cgroup_path.join(MEMORY_EVENTS);
+        let cgroup_events = cgroup_path.join(CGROUP_EVENTS);
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC).context("failed to init inotify")?;
+        let watched = inotify
+            .add_watch(&memory_events, AddWatchFlags::IN_MODIFY)
+            .and_then(|_| inotify.add_watch(&cgroup_events, AddWatchFlags::IN_MODIFY));
+        if let Err(err) = watched {
+            let _ = unistd::close(inotify.as_raw_fd());
+            return Err(err).with_context(|| format!("failed to watch {:?}", cgroup_path));
+        }
+        let mut oom_kills = Self::oom_kill_count(&memory_events)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while inotify.read_events().is_ok() {
+                match Self::oom_kill_count(&memory_events) {
+                    Ok(count) if count > oom_kills => {
+                        oom_kills = count;
+                        if sender.send(()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+
+                let populated = stats::parse_flat_keyed_data(&cgroup_events)
+                    .map_or(false, |events| events.get("populated") == Some(&1));
+                if !populated {
+                    break;
+                }
+            }
+
+            let _ = unistd::close(inotify.as_raw_fd());
+        });
+
+        Ok(receiver)
+    }
+
+    fn oom_kill_count(memory_events: &Path) -> Result<u64> {
+        let events = stats::parse_flat_keyed_data(memory_events)?;
+        Ok(events.get("oom_kill").copied().unwrap_or_default())
+    }
+
     fn set<P: AsRef<Path>>(path: P, val: i64) -> Result<()> {
         if val == 0 {
             Ok(())
@@ -88,41 +204,103 @@ impl Memory {
             return Ok(());
         }
 
-        match memory.limit {
-            Some(limit) if limit < -1 => {
-                bail!("invalid memory value: {}", limit);
-            }
-            Some(limit) => match memory.swap {
-                Some(swap) if swap < -1 => {
-                    bail!("invalid swap value: {}", swap);
-                }
-                Some(swap) => {
-                    Memory::set(path.join(CGROUP_MEMORY_SWAP), swap)?;
-                    Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
-                }
-                None => {
-                    if limit == -1 {
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), -1)?;
-                    }
-                    Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
-                }
-            },
-            None => {
-                if memory.swap.is_some() {
-                    bail!("unable to set swap limit without memory limit");
-                }
+        let limit = memory.limit.unwrap_or(0);
+        if limit < -1 {
+            bail!(CgroupError::spec(format!(
+                "invalid memory value: {}",
+                limit
+            )));
+        }
+        let swap = memory.swap.unwrap_or(0);
+        if swap < -1 {
+            bail!(CgroupError::spec(format!("invalid swap value: {}", swap)));
+        }
+
+        let swap = Self::convert_swap(limit, swap)?;
+        if path.join(CGROUP_MEMORY_SWAP).exists() {
+            match swap {
+                // Memory::set treats 0 as unset, but here it disables the swap
+                Some(0) => common::write_cgroup_file(path.join(CGROUP_MEMORY_SWAP), 0)?,
+                Some(swap) => Memory::set(path.join(CGROUP_MEMORY_SWAP), swap)?,
+                None => {}
             }
-        };
+        } else if swap.map_or(false, |swap| swap > 0) {
+            bail!("swap limit is set, but swap accounting is not enabled");
+        }
+        Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
 
         if let Some(reservation) = memory.reservation {
             if reservation < -1 {
-                bail!("invalid memory reservation value: {}", reservation);
+                bail!(CgroupError::spec(format!(
+                    "invalid memory reservation value: {}",
+                    reservation
+                )));
             }
             Memory::set(path.join(CGROUP_MEMORY_LOW), reservation)?;
         }
 
         Ok(())
     }
+
+    // The swap of the spec is the limit of memory and swap together, as in
+    // memory.memsw.limit_in_bytes of cgroup v1, while memory.swap.max only
+    // limits the swap. Same as runc, an unlimited memory without a swap limit
+    // makes the swap unlimited as well. None leaves the swap limit as it is.
+    fn convert_swap(limit: i64, swap: i64) -> Result<Option<i64>> {
+        if limit == -1 && swap == 0 {
+            return Ok(Some(-1));
+        }
+
+        if swap == 0 {
+            return Ok(None);
+        }
+
+        if swap == -1 {
+            return Ok(Some(-1));
+        }
+
+        if limit == 0 || limit == -1 {
+            bail!("unable to set swap limit without memory limit");
+        }
+
+        if swap < limit {
+            bail!(
+                "memory+swap limit {} should be greater than or equal to the memory limit {}",
+                swap,
+                limit
+            );
+        }
+
+        Ok(Some(swap - limit))
+    }
+
+    fn apply_unified(
+        path: &Path,
+        file: &str,
+        value: &str,
+        unsupported: &mut UnsupportedSettings,
+    ) -> Result<()> {
+        let value = value.trim();
+        let valid = match file {
+            CGROUP_MEMORY_OOM_GROUP => value == "0" || value == "1",
+            _ => value == "max" || value.parse::<u64>().is_ok(),
+        };
+        if !valid {
+            bail!(CgroupError::spec(format!(
+                "invalid value {:?} for {}",
+                value, file
+            )));
+        }
+        // the whole cgroup can only be killed on oom since linux 4.19
+        if file == CGROUP_MEMORY_OOM_GROUP && !path.join(file).exists() {
+            unsupported.add(format!("{} (not supported by the kernel)", file));
+            return Ok(());
+        }
+
+        log::debug!("Apply {} {}", file, value);
+        common::write_cgroup_file_str(path.join(file), value)
+            .with_context(|| format!("failed to set {} to {}", file, value))
+    }
 }
 
 #[cfg(test)]
@@ -130,7 +308,8 @@ mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
     use oci_spec::LinuxMemory;
-    use std::fs::read_to_string;
+    use std::fs::{self, read_to_string};
+    use std::time::Duration;
 
     #[test]
     fn test_set_memory() {
@@ -157,14 +336,40 @@ mod tests {
         let limit_content = read_to_string(tmp.join(CGROUP_MEMORY_MAX)).expect("read memory limit");
         assert_eq!(limit_content, limit.to_string());
 
+        // memory.swap.max only limits the swap without the memory
         let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
-        assert_eq!(swap_content, swap.to_string());
+        assert_eq!(swap_content, (swap - limit).to_string());
 
         let reservation_content =
             read_to_string(tmp.join(CGROUP_MEMORY_LOW)).expect("read memory reservation");
         assert_eq!(reservation_content, reservation.to_string());
     }
 
+    #[test]
+    fn test_set_memory_without_swap() {
+        let tmp = create_temp_dir("test_set_memory_without_swap_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+        set_fixture(&tmp, CGROUP_MEMORY_LOW, "0").expect("set fixture for memory reservation");
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "max").expect("set fixture for swap limit");
+
+        // a memory+swap limit equal to the memory limit allows no swap at all
+        let memory_limits = &LinuxMemory {
+            limit: Some(1024),
+            reservation: None,
+            swap: Some(1024),
+            kernel: None,
+            kernel_tcp: None,
+            swappiness: None,
+            disable_oom_killer: None,
+            use_hierarchy: None,
+        };
+        Memory::apply(&tmp, memory_limits).expect("apply memory limits");
+
+        let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, "0");
+    }
+
     #[test]
     fn test_set_memory_unlimited() {
         let tmp = create_temp_dir("test_set_memory_unlimited_v2")
@@ -283,7 +488,7 @@ mod tests {
                 if swap < -1 {
                     return result.is_err();
                 }
-                if linux_memory.limit.is_none() {
+                if Memory::convert_swap(linux_memory.limit.unwrap_or(0), swap).is_err() {
                     return result.is_err();
                 }
             }
@@ -304,15 +509,11 @@ mod tests {
 
             // check the swap file is set as expected
             let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit to string");
-            let swap_check = match linux_memory.swap {
-                Some(swap) if swap == -1 => swap_content == "max",
-                Some(swap) => swap_content == swap.to_string(),
-                None => {
-                    match linux_memory.limit {
-                        Some(limit) if limit == -1 => swap_content == "max",
-                        _ => swap_content == "0",
-                    }
-                }
+            let swap_check = match (linux_memory.swap, linux_memory.limit) {
+                (Some(-1), _) | (None, Some(-1)) | (Some(0), Some(-1)) => swap_content == "max",
+                (Some(0), _) | (None, _) => swap_content == "0",
+                (Some(swap), Some(limit)) => swap_content == (swap - limit).to_string(),
+                (Some(_), None) => false,
             };
 
 
@@ -349,4 +550,117 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_stat_memory() {
+        let tmp = create_temp_dir("test_stat_memory_swap").expect("create test directory");
+        set_fixture(&tmp, "memory.current", "12500\n").unwrap();
+        set_fixture(&tmp, "memory.max", "25000\n").unwrap();
+        set_fixture(&tmp, MEMORY_EVENTS, "oom 3\noom_kill 2").unwrap();
+        set_fixture(&tmp, MEMORY_STAT, "anon 8192\nfile 4096").unwrap();
+
+        let actual = Memory::stats(&tmp).expect("get cgroup stats");
+        assert_eq!(actual.cache, 4096);
+        assert_eq!(actual.oom_kill, 2);
+        assert_eq!(actual.memswap.usage, 12500);
+
+        set_fixture(&tmp, "memory.swap.current", "2500\n").unwrap();
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "max\n").unwrap();
+        set_fixture(&tmp, "memory.swap.events", "fail 1").unwrap();
+
+        let actual = Memory::stats(&tmp).expect("get cgroup stats");
+        let expected = MemoryData {
+            usage: 15000,
+            fail_count: 1,
+            limit: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(actual.memswap, expected);
+    }
+
+    #[test]
+    fn test_notify_on_oom() {
+        let tmp = create_temp_dir("test_notify_on_oom").expect("create test directory");
+        set_fixture(&tmp, MEMORY_EVENTS, "oom 0\noom_kill 0").unwrap();
+        set_fixture(&tmp, CGROUP_EVENTS, "populated 1\nfrozen 0").unwrap();
+        let receiver = Memory::notify_on_oom(&tmp).expect("watch oom events");
+
+        // the limit has been reached, but no process has been killed
+        set_fixture(&tmp, MEMORY_EVENTS, "oom 1\noom_kill 0").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+        set_fixture(&tmp, MEMORY_EVENTS, "oom 1\noom_kill 1").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        set_fixture(&tmp, CGROUP_EVENTS, "populated 0\nfrozen 0").unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_convert_swap() {
+        assert_eq!(Memory::convert_swap(-1, 0).unwrap(), Some(-1));
+        assert_eq!(Memory::convert_swap(1024, -1).unwrap(), Some(-1));
+        assert_eq!(Memory::convert_swap(1024, 0).unwrap(), None);
+        assert_eq!(Memory::convert_swap(1024, 1024).unwrap(), Some(0));
+        assert_eq!(Memory::convert_swap(1024, 3072).unwrap(), Some(2048));
+
+        assert!(Memory::convert_swap(0, 1024).is_err());
+        assert!(Memory::convert_swap(-1, 1024).is_err());
+        assert!(Memory::convert_swap(2048, 1024).is_err());
+    }
+
+    #[test]
+    fn test_set_memory_unified() {
+        let tmp =
+            create_temp_dir("test_set_memory_unified_v2").expect("create temp directory for test");
+        for file in MEMORY_UNIFIED_FILES {
+            set_fixture(&tmp, file, "").expect("set fixture for memory file");
+        }
+
+        let resources = LinuxResources {
+            unified: Some(
+                [
+                    (CGROUP_MEMORY_MIN.to_owned(), "1048576".to_owned()),
+                    (CGROUP_MEMORY_HIGH.to_owned(), "max".to_owned()),
+                    (CGROUP_MEMORY_ZSWAP.to_owned(), "0".to_owned()),
+                    (CGROUP_MEMORY_OOM_GROUP.to_owned(), "1".to_owned()),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            ..Default::default()
+        };
+        <Memory as Controller>::apply(&resources, &tmp).expect("apply memory");
+
+        for (file, expected) in &[
+            (CGROUP_MEMORY_MIN, "1048576"),
+            (CGROUP_MEMORY_HIGH, "max"),
+            (CGROUP_MEMORY_ZSWAP, "0"),
+            (CGROUP_MEMORY_OOM_GROUP, "1"),
+        ] {
+            assert_eq!(
+                &read_to_string(tmp.join(file)).expect("read memory file"),
+                expected
+            );
+        }
+
+        let mut unsupported = UnsupportedSettings::default();
+        assert!(
+            Memory::apply_unified(&tmp, CGROUP_MEMORY_OOM_GROUP, "2", &mut unsupported).is_err()
+        );
+        assert!(Memory::apply_unified(&tmp, CGROUP_MEMORY_HIGH, "-5", &mut unsupported).is_err());
+        assert!(unsupported.settings().is_empty());
+
+        fs::remove_file(tmp.join(CGROUP_MEMORY_OOM_GROUP)).expect("remove oom group file");
+        let err = <Memory as Controller>::apply(&resources, &tmp).unwrap_err();
+        let unsupported = err
+            .downcast_ref::<UnsupportedSettings>()
+            .expect("oom group is not supported");
+        assert_eq!(unsupported.settings().len(), 1);
+        assert!(unsupported.settings()[0].contains("not supported by the kernel"));
+    }
 }
@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use oci_spec::LinuxResources;
+
+use crate::common;
+
+use super::controller::Controller;
+use crate::error::CgroupError;
+
+/// Limits of the miscellaneous resources like sgx_epc. The spec has no field
+/// for them, so they are passed through the unified map and applied by the
+/// misc controller, which writes the limit of one resource at a time.
+pub const CGROUP_MISC_MAX: &str = "misc.max";
+
+pub struct Misc {}
+
+impl Controller for Misc {
+    fn apply(linux_resources: &LinuxResources, cgroup_root: &Path) -> Result<()> {
+        if let Some(limits) = linux_resources
+            .unified
+            .as_ref()
+            .and_then(|unified| unified.get(CGROUP_MISC_MAX))
+        {
+            log::debug!("Apply misc cgroup v2 config");
+            Self::apply(cgroup_root, limits)
+                .context("failed to apply misc resource restrictions")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Misc {
+    fn apply(root_path: &Path, limits: &str) -> Result<()> {
+        let limits = Self::parse_limits(limits)?;
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let path = root_path.join(CGROUP_MISC_MAX);
+        if !path.exists() {
+            bail!(CgroupError::kernel_feature_missing(
+                "misc limits are set, but the misc controller is not available"
+            ));
+        }
+
+        // the kernel only takes the limit of a single resource per write
+        for (resource, limit) in limits {
+            common::write_cgroup_file_str(&path, &format!("{} {}", resource, limit))
+                .with_context(|| format!("failed to set misc limit of {}", resource))?;
+        }
+
+        Ok(())
+    }
+
+    // The limits of several resources are separated by newlines, a limit is
+    // either a number or "max"
+    fn parse_limits(limits: &str) -> Result<Vec<(&str, &str)>> {
+        let mut parsed = Vec::new();
+        for line in limits.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(resource), Some(limit), None)
+                    if limit == "max" || limit.parse::<u64>().is_ok() =>
+                {
+                    parsed.push((resource, limit))
+                }
+                _ => bail!(
+                    "invalid misc limit {:?}, expected '<resource> <limit>'",
+                    line
+                ),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use super::*;
+    use crate::test::{create_temp_dir, setup};
+
+    #[test]
+    fn test_set_misc_max() {
+        let (tmp, max) = setup("test_set_misc_max", CGROUP_MISC_MAX);
+        let mut unified = HashMap::new();
+        unified.insert(CGROUP_MISC_MAX.to_owned(), "sgx_epc 65536\n".to_owned());
+        let resources = LinuxResources {
+            unified: Some(unified),
+            ..Default::default()
+        };
+
+        <Misc as Controller>::apply(&resources, &tmp).expect("apply misc");
+
+        let content = fs::read_to_string(&max)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_MISC_MAX));
+        assert_eq!(content, "sgx_epc 65536");
+    }
+
+    #[test]
+    fn test_misc_not_available() {
+        let tmp = create_temp_dir("test_misc_not_available").expect("create temp directory");
+        assert!(Misc::apply(&tmp, "sgx_epc max").is_err());
+        // nothing to apply
+        assert!(Misc::apply(&tmp, "\n").is_ok());
+    }
+
+    #[test]
+    fn test_parse_misc_limits() -> Result<()> {
+        assert_eq!(
+            Misc::parse_limits("sgx_epc 65536\nres_b max")?,
+            vec![("sgx_epc", "65536"), ("res_b", "max")]
+        );
+        assert!(Misc::parse_limits("sgx_epc").is_err());
+        assert!(Misc::parse_limits("sgx_epc -1").is_err());
+        Ok(())
+    }
+}
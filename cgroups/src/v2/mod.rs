@@ -7,6 +7,7 @@ mod hugetlb;
 mod io;
 pub mod manager;
 mod memory;
+mod misc;
 mod pids;
 pub mod systemd_manager;
 mod unified;
@@ -1,25 +1,25 @@
-use std::{
-    fs::{self},
-    os::unix::fs::PermissionsExt,
-};
+use std::fs;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use nix::unistd::Pid;
-use oci_spec::{FreezerState, LinuxResources};
+use oci_spec::{FreezerState, LinuxCpu, LinuxResources};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
 
 #[cfg(feature = "cgroupsv2_devices")]
 use super::devices::Devices;
 use super::{
     controller::Controller, controller_type::ControllerType, cpu::Cpu, cpuset::CpuSet,
-    freezer::Freezer, hugetlb::HugeTlb, io::Io, memory::Memory, pids::Pids,
+    freezer::Freezer, hugetlb::HugeTlb, io::Io, memory::Memory, misc::Misc, pids::Pids,
+    unified::Unified, util,
 };
-use crate::common::{self, CgroupManager, PathBufExt};
+use crate::common::{self, CgroupHierarchy, CgroupManager, CpuRampdown, PathBufExt};
+use crate::error::CgroupError;
 use crate::stats::Stats;
+use crate::systemd_unit::{self, PropertyValue, TransientUnit, UnitProperty};
 
 const CGROUP_PROCS: &str = "cgroup.procs";
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
-const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
 
 // v2 systemd only supports cpu, io, memory and pids.
 const CONTROLLER_TYPES: &[ControllerType] = &[
@@ -34,151 +34,73 @@ pub struct SystemDCGroupManager {
     root_path: PathBuf,
     cgroups_path: PathBuf,
     full_path: PathBuf,
-}
-
-/// Represents the systemd cgroups path:
-/// It should be of the form [slice]:[scope_prefix]:[name].
-/// The slice is the "parent" and should be expanded properly,
-/// see expand_slice below.
-struct CgroupsPath {
-    parent: String,
-    scope: String,
-    name: String,
+    unit: TransientUnit,
+    strict: bool,
+    skip_devices: bool,
 }
 
 impl SystemDCGroupManager {
     pub fn new(root_path: PathBuf, cgroups_path: PathBuf) -> Result<Self> {
-        // TODO: create the systemd unit using a dbus client.
-        let destructured_path = Self::destructure_cgroups_path(cgroups_path)?;
-        let cgroups_path = Self::construct_cgroups_path(destructured_path)?;
+        let unit = TransientUnit::new(cgroups_path)?;
+        // the user instance of systemd creates the units of rootless
+        // containers below its own cgroup, which is delegated to the user
+        let cgroups_path = if nix::unistd::geteuid().is_root() {
+            unit.cgroups_path().to_path_buf()
+        } else {
+            systemd_unit::user_cgroup()?.join_safely(unit.cgroups_path())?
+        };
         let full_path = root_path.join_safely(&cgroups_path)?;
 
         Ok(SystemDCGroupManager {
             root_path,
             cgroups_path,
             full_path,
+            unit,
+            strict: false,
+            skip_devices: false,
         })
     }
 
-    fn destructure_cgroups_path(cgroups_path: PathBuf) -> Result<CgroupsPath> {
-        // cgroups path may never be empty as it is defaulted to `/youki`
-        // see 'get_cgroup_path' under utils.rs.
-        // if cgroups_path was provided it should be of the form [slice]:[scope_prefix]:[name],
-        // for example: "system.slice:docker:1234".
-        let mut parent = "";
-        let scope;
-        let name;
-        if cgroups_path.starts_with("/youki") {
-            scope = "youki";
-            name = cgroups_path
-                .strip_prefix("/youki/")?
-                .to_str()
-                .ok_or_else(|| anyhow!("Failed to parse cgroupsPath field."))?;
-        } else {
-            let parts = cgroups_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Failed to parse cgroupsPath field."))?
-                .split(':')
-                .collect::<Vec<&str>>();
-            parent = parts[0];
-            scope = parts[1];
-            name = parts[2];
-        }
-
-        Ok(CgroupsPath {
-            parent: parent.to_owned(),
-            scope: scope.to_owned(),
-            name: name.to_owned(),
-        })
+    /// Fails to apply the resources if a setting is not supported by the host,
+    /// instead of skipping it with a warning
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
-    /// get_unit_name returns the unit (scope) name from the path provided by the user
-    /// for example: foo:docker:bar returns in '/docker-bar.scope'
-    fn get_unit_name(cgroups_path: CgroupsPath) -> String {
-        // By default we create a scope unless specified explicitly.
-        if !cgroups_path.name.ends_with(".slice") {
-            return format!("{}-{}.scope", cgroups_path.scope, cgroups_path.name);
-        }
-        cgroups_path.name
+    /// Does not apply any device rules, e.g. if the devices controller is
+    /// managed by an outer container
+    pub fn with_skip_devices(mut self, skip_devices: bool) -> Self {
+        self.skip_devices = skip_devices;
+        self
     }
 
-    // systemd represents slice hierarchy using `-`, so we need to follow suit when
-    // generating the path of slice. For example, 'test-a-b.slice' becomes
-    // '/test.slice/test-a.slice/test-a-b.slice'.
-    fn expand_slice(slice: &str) -> Result<PathBuf> {
-        let suffix = ".slice";
-        if slice.len() <= suffix.len() || !slice.ends_with(suffix) {
-            bail!("invalid slice name: {}", slice);
-        }
-        if slice.contains('/') {
-            bail!("invalid slice name: {}", slice);
-        }
-        let mut path = "".to_owned();
-        let mut prefix = "".to_owned();
-        let slice_name = slice.trim_end_matches(suffix);
-        // if input was -.slice, we should just return root now
-        if slice_name == "-" {
-            return Ok(Path::new("/").to_path_buf());
-        }
-        for component in slice_name.split('-') {
-            if component.is_empty() {
-                anyhow!("Invalid slice name: {}", slice);
-            }
-            // Append the component to the path and to the prefix.
-            path = format!("{}/{}{}{}", path, prefix, component, suffix);
-            prefix = format!("{}{}-", prefix, component);
-        }
-        Ok(Path::new(&path).to_path_buf())
+    /// Sets additional properties on the systemd unit of the container
+    pub fn with_unit_properties(mut self, properties: Vec<UnitProperty>) -> Self {
+        self.unit = self.unit.with_properties(properties);
+        self
     }
 
-    // get_cgroups_path generates a cgroups path from the one provided by the user via cgroupsPath.
-    // an example of the final path: "/machine.slice/docker-foo.scope"
-    fn construct_cgroups_path(cgroups_path: CgroupsPath) -> Result<PathBuf> {
-        // the root slice is under 'machine.slice'.
-        let mut slice = Path::new("/machine.slice").to_path_buf();
-        // if the user provided a '.slice' (as in a branch of a tree)
-        // we need to "unpack it".
-        if !cgroups_path.parent.is_empty() {
-            slice = Self::expand_slice(&cgroups_path.parent)?;
+    // The unit gets the same limits as the cgroup, see update_resources
+    fn set_unit_resources(&self, linux_resources: &LinuxResources) {
+        let mut properties = systemd_unit::resource_properties(linux_resources);
+        if let Some(limit) = linux_resources.memory.as_ref().and_then(|m| m.limit) {
+            properties.push(UnitProperty {
+                name: "MemoryMax".to_owned(),
+                value: PropertyValue::U64(systemd_unit::unit_limit(limit)),
+            });
         }
-        let unit_name = Self::get_unit_name(cgroups_path);
-        let cgroups_path = slice.join(unit_name);
-        Ok(cgroups_path)
-    }
-
-    /// create_unified_cgroup verifies sure that *each level* in the downward path from the root cgroup
-    /// down to the cgroup_path provided by the user is a valid cgroup hierarchy,
-    /// containing the attached controllers and that it contains the container pid.
-    fn create_unified_cgroup(&self, pid: Pid) -> Result<()> {
-        let controllers: Vec<String> = self
-            .get_available_controllers(&self.root_path)?
-            .into_iter()
-            .map(|c| format!("{}{}", "+", c.to_string()))
-            .collect();
-
-        // Write the controllers to the root_path.
-        Self::write_controllers(&self.root_path, &controllers)?;
-
-        let mut current_path = self.root_path.clone();
-        let mut components = self.cgroups_path.components().skip(1).peekable();
-        // Verify that *each level* in the downward path from the root cgroup
-        // down to the cgroup_path provided by the user is a valid cgroup hierarchy.
-        // containing the attached controllers.
-        while let Some(component) = components.next() {
-            current_path = current_path.join(component);
-            if !current_path.exists() {
-                fs::create_dir(&current_path)?;
-                fs::metadata(&current_path)?.permissions().set_mode(0o755);
-            }
-
-            // last component cannot have subtree_control enabled due to internal process constraint
-            // if this were set, writing to the cgroups.procs file will fail with Erno 16 (device or resource busy)
-            if components.peek().is_some() {
-                Self::write_controllers(&current_path, &controllers)?;
+        if let Some(shares) = linux_resources.cpu.as_ref().and_then(|cpu| cpu.shares) {
+            let weight = Cpu::convert_shares_to_cgroup2(shares);
+            if weight != 0 {
+                properties.push(UnitProperty {
+                    name: "CPUWeight".to_owned(),
+                    value: PropertyValue::U64(weight),
+                });
             }
         }
 
-        common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)
+        self.unit.update_resources(&properties);
     }
 
     fn get_available_controllers<P: AsRef<Path>>(
@@ -206,120 +128,103 @@ impl SystemDCGroupManager {
 
         Ok(controllers)
     }
-
-    fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
-        for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
-        }
-
-        Ok(())
-    }
 }
 
 impl CgroupManager for SystemDCGroupManager {
-    fn add_task(&self, pid: Pid) -> Result<()> {
+    fn add_task(&self, pid: Pid) -> Result<(), CgroupError> {
         // Dont attach any pid to the cgroup if -1 is specified as a pid
         if pid.as_raw() == -1 {
             return Ok(());
         }
 
-        self.create_unified_cgroup(pid)?;
+        self.unit.start(pid)?;
+        if !self.unit.is_scope() {
+            common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)?;
+        }
+
         Ok(())
     }
 
-    fn apply(&self, linux_resources: &LinuxResources) -> Result<()> {
+    fn apply(&self, linux_resources: &LinuxResources) -> Result<(), CgroupError> {
         for controller in CONTROLLER_TYPES {
-            match controller {
-                ControllerType::Cpu => Cpu::apply(linux_resources, &self.full_path)?,
-                ControllerType::CpuSet => CpuSet::apply(linux_resources, &self.full_path)?,
-                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &self.full_path)?,
-                ControllerType::Io => Io::apply(linux_resources, &self.full_path)?,
-                ControllerType::Memory => Memory::apply(linux_resources, &self.full_path)?,
-                ControllerType::Pids => Pids::apply(linux_resources, &self.full_path)?,
-                ControllerType::Freezer => Freezer::apply(linux_resources, &self.full_path)?,
-            }
+            let result = match controller {
+                ControllerType::Cpu => Cpu::apply(linux_resources, &self.full_path),
+                ControllerType::CpuSet => CpuSet::apply(linux_resources, &self.full_path),
+                ControllerType::HugeTlb => HugeTlb::apply(linux_resources, &self.full_path),
+                ControllerType::Io => Io::apply(linux_resources, &self.full_path),
+                ControllerType::Memory => Memory::apply(linux_resources, &self.full_path),
+                ControllerType::Pids => Pids::apply(linux_resources, &self.full_path),
+                ControllerType::Freezer => Freezer::apply(linux_resources, &self.full_path),
+                ControllerType::Misc => Misc::apply(linux_resources, &self.full_path),
+            };
+            common::check_controller_result(result, self.strict)?;
         }
 
         #[cfg(feature = "cgroupsv2_devices")]
-        Devices::apply(linux_resources, &self.full_path)?;
+        if !self.skip_devices {
+            Devices::apply(linux_resources, &self.full_path)?;
+        }
+
+        Unified::apply(
+            linux_resources,
+            &self.full_path,
+            self.get_available_controllers(&self.cgroups_path)?,
+        )?;
+
+        self.set_unit_resources(linux_resources);
         Ok(())
     }
 
-    fn remove(&self) -> Result<()> {
-        Ok(())
+    fn remove(&self) -> Result<(), CgroupError> {
+        self.unit.stop()?;
+        // systemd does not remove the cgroups which have been created in the
+        // delegated cgroup of a scope
+        Ok(common::remove_cgroup_dir(&self.full_path)?)
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<()> {
+    fn freeze(&self, state: FreezerState) -> Result<(), CgroupError> {
         let linux_resources = LinuxResources {
             freezer: Some(state),
             ..Default::default()
         };
-        Freezer::apply(&linux_resources, &self.full_path)
+        Ok(Freezer::apply(&linux_resources, &self.full_path)?)
     }
 
-    fn stats(&self) -> Result<Stats> {
-        Ok(Stats::default())
+    fn ramp_down_cpu(&self, cpu: &LinuxCpu, rampdown: &CpuRampdown) -> Result<(), CgroupError> {
+        Ok(Cpu::ramp_down(&self.full_path, cpu, rampdown)?)
     }
 
-    fn get_all_pids(&self) -> Result<Vec<Pid>> {
-        common::get_all_pids(&self.full_path)
+    fn hierarchies(&self) -> Result<Vec<CgroupHierarchy>, CgroupError> {
+        Ok(vec![CgroupHierarchy {
+            name: None,
+            path: self.full_path.clone(),
+        }])
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn expand_slice_works() -> Result<()> {
-        assert_eq!(
-            SystemDCGroupManager::expand_slice("test-a-b.slice")?,
-            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice"),
-        );
-
-        Ok(())
+    fn stats(&self) -> Result<Stats, CgroupError> {
+        Ok(util::get_stats(&self.full_path)?)
     }
 
-    #[test]
-    fn get_cgroups_path_works_with_a_complex_slice() -> Result<()> {
-        let cgroups_path = SystemDCGroupManager::destructure_cgroups_path(PathBuf::from(
-            "test-a-b.slice:docker:foo",
-        ))
-        .expect("");
-
-        assert_eq!(
-            SystemDCGroupManager::construct_cgroups_path(cgroups_path)?,
-            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice/docker-foo.scope"),
-        );
-
-        Ok(())
+    fn get_all_pids(&self) -> Result<Vec<Pid>, CgroupError> {
+        Ok(common::get_all_pids(&self.full_path)?)
     }
 
-    #[test]
-    fn get_cgroups_path_works_with_a_simple_slice() -> Result<()> {
-        let cgroups_path = SystemDCGroupManager::destructure_cgroups_path(PathBuf::from(
-            "machine.slice:libpod:foo",
-        ))
-        .expect("");
-
-        assert_eq!(
-            SystemDCGroupManager::construct_cgroups_path(cgroups_path)?,
-            PathBuf::from("/machine.slice/libpod-foo.scope"),
-        );
-
-        Ok(())
+    fn get_all_threads(&self) -> Result<Vec<Pid>, CgroupError> {
+        Ok(common::get_all_threads(
+            &self.full_path,
+            common::CGROUP_THREADS,
+        )?)
     }
 
-    #[test]
-    fn get_cgroups_path_works_with_scope() -> Result<()> {
-        let cgroups_path =
-            SystemDCGroupManager::destructure_cgroups_path(PathBuf::from(":docker:foo")).expect("");
+    fn kill(&self) -> Result<bool, CgroupError> {
+        Ok(common::write_cgroup_kill(&self.full_path)?)
+    }
 
-        assert_eq!(
-            SystemDCGroupManager::construct_cgroups_path(cgroups_path)?,
-            PathBuf::from("/machine.slice/docker-foo.scope"),
-        );
+    fn exists(&self) -> bool {
+        self.full_path.exists()
+    }
 
-        Ok(())
+    fn notify_on_oom(&self) -> Result<Receiver<()>, CgroupError> {
+        Ok(Memory::notify_on_oom(&self.full_path)?)
     }
 }
@@ -1,47 +1,175 @@
 use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use oci_spec::LinuxResources;
 
-use super::controller_type::ControllerType;
+use super::{
+    controller_type::ControllerType, cpu::CPU_UNIFIED_FILES, cpuset::CGROUP_CPUSET_PARTITION,
+    hugetlb::HugeTlb, io::IO_QOS_FILES, memory::MEMORY_UNIFIED_FILES, misc::CGROUP_MISC_MAX,
+};
 use crate::common;
+use crate::error::CgroupError;
+
+// Files of the cgroup core which would move processes or change the hierarchy
+const FORBIDDEN_FILES: &[&str] = &[
+    "cgroup.procs",
+    "cgroup.threads",
+    "cgroup.subtree_control",
+    "cgroup.type",
+    "cgroup.kill",
+];
 
 pub struct Unified {}
 
 impl Unified {
+    /// Applies the unified map of the spec, which sets arbitrary files of the
+    /// cgroup. It is applied after the other resources, but a file must not be
+    /// set by both.
     pub fn apply(
         linux_resources: &LinuxResources,
         cgroup_path: &Path,
         controllers: Vec<ControllerType>,
     ) -> Result<()> {
-        if let Some(unified) = &linux_resources.unified {
-            log::debug!("Apply unified cgroup config");
-            for (cgroup_file, value) in unified {
-                common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value).map_err(
-                    |e| {
-                        let (subsystem, _) = cgroup_file
-                            .split_once(".")
-                            .with_context(|| {
-                                format!("failed to split {} with {}", cgroup_file, ".")
-                            })
-                            .unwrap();
-                        let context = if !controllers.iter().any(|c| c.to_string() == subsystem) {
-                            format!(
-                                "failed to set {} to {}: subsystem {} is not available",
-                                cgroup_file, value, subsystem
-                            )
-                        } else {
-                            format!("failed to set {} to {}: {}", cgroup_file, value, e)
-                        };
-
-                        e.context(context)
-                    },
-                )?;
+        let unified = match &linux_resources.unified {
+            Some(unified) => unified,
+            None => return Ok(()),
+        };
+
+        log::debug!("Apply unified cgroup config");
+        let structured = Self::structured_files(linux_resources);
+        // sorted, so problems are reported in a stable order
+        let mut entries: Vec<_> = unified.iter().collect();
+        entries.sort();
+        for (cgroup_file, value) in entries {
+            let subsystem = Self::validate_file(cgroup_file)?;
+            if let Some((_, field)) = structured.iter().find(|(file, _)| file == cgroup_file) {
+                bail!(
+                    "{} is set in the unified map and through linux.resources.{}",
+                    cgroup_file,
+                    field
+                );
+            }
+
+            // io qos files, the cpuset partition, the misc limits and cpu and
+            // memory files without a field in the spec are validated and
+            // applied by their controllers
+            if IO_QOS_FILES.contains(&cgroup_file.as_str())
+                || CPU_UNIFIED_FILES.contains(&cgroup_file.as_str())
+                || MEMORY_UNIFIED_FILES.contains(&cgroup_file.as_str())
+                || cgroup_file == CGROUP_CPUSET_PARTITION
+                || cgroup_file == CGROUP_MISC_MAX
+            {
+                continue;
+            }
+
+            let path = cgroup_path.join(cgroup_file);
+            if !path.exists() {
+                if subsystem != "cgroup" && !controllers.iter().any(|c| c.to_string() == subsystem)
+                {
+                    bail!(
+                        "failed to set {} to {}: subsystem {} is not available",
+                        cgroup_file,
+                        value,
+                        subsystem
+                    );
+                }
+                bail!(
+                    "failed to set {} to {}: unknown cgroup file",
+                    cgroup_file,
+                    value
+                );
             }
+
+            common::write_cgroup_file_str(&path, value)
+                .with_context(|| format!("failed to set {} to {}", cgroup_file, value))?;
         }
 
         Ok(())
     }
+
+    // Returns the subsystem of a file, files are named <subsystem>.<name>
+    fn validate_file(cgroup_file: &str) -> Result<&str> {
+        match cgroup_file.split_once('.') {
+            Some((subsystem, name))
+                if !subsystem.is_empty() && !name.is_empty() && !cgroup_file.contains('/') =>
+            {
+                if FORBIDDEN_FILES.contains(&cgroup_file) {
+                    bail!(CgroupError::spec(format!(
+                        "{} can not be set through the unified map",
+                        cgroup_file
+                    )));
+                }
+                Ok(subsystem)
+            }
+            _ => bail!(CgroupError::spec(format!(
+                "invalid cgroup file {:?} in the unified map",
+                cgroup_file
+            ))),
+        }
+    }
+
+    // Files which are written for the structured resources of the spec and the
+    // field they are set through
+    fn structured_files(linux_resources: &LinuxResources) -> Vec<(String, &'static str)> {
+        let mut files = Vec::new();
+        let mut add = |file: &str, field: &'static str| files.push((file.to_owned(), field));
+
+        if let Some(cpu) = &linux_resources.cpu {
+            if cpu.shares.is_some() {
+                add("cpu.weight", "cpu.shares");
+            }
+            if cpu.quota.is_some() || cpu.period.is_some() {
+                add("cpu.max", "cpu.quota");
+            }
+            if cpu.cpus.is_some() {
+                add("cpuset.cpus", "cpu.cpus");
+            }
+            if cpu.mems.is_some() {
+                add("cpuset.mems", "cpu.mems");
+            }
+        }
+
+        if let Some(memory) = &linux_resources.memory {
+            if memory.limit.is_some() {
+                add("memory.max", "memory.limit");
+            }
+            if memory.reservation.is_some() {
+                add("memory.low", "memory.reservation");
+            }
+            if memory.swap.is_some() {
+                add("memory.swap.max", "memory.swap");
+            }
+        }
+
+        if linux_resources.pids.is_some() {
+            add("pids.max", "pids.limit");
+        }
+
+        if let Some(block_io) = &linux_resources.block_io {
+            if block_io.weight.is_some() || block_io.weight_device.is_some() {
+                add("io.weight", "blockIO.weight");
+                add("io.bfq.weight", "blockIO.weight");
+            }
+            if block_io.throttle_read_bps_device.is_some()
+                || block_io.throttle_write_bps_device.is_some()
+                || block_io.throttle_read_iops_device.is_some()
+                || block_io.throttle_write_iops_device.is_some()
+            {
+                add("io.max", "blockIO.throttle");
+            }
+        }
+
+        // the hugetlb controller writes the files of the normalized page size,
+        // an invalid page size is reported by the controller
+        for limit in linux_resources.hugepage_limits.iter().flatten() {
+            let page_size = HugeTlb::normalize_page_size(&limit.page_size)
+                .unwrap_or_else(|_| limit.page_size.clone());
+            add(&format!("hugetlb.{}.max", page_size), "hugepageLimits");
+            add(&format!("hugetlb.{}.rsvd.max", page_size), "hugepageLimits");
+        }
+
+        files
+    }
 }
 
 #[cfg(test)]
@@ -53,6 +181,7 @@ mod tests {
 
     use crate::test::{create_temp_dir, set_fixture};
     use crate::v2::controller_type::ControllerType;
+    use oci_spec::{LinuxHugepageLimit, LinuxPids};
 
     use super::*;
 
@@ -134,4 +263,73 @@ mod tests {
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_set_unified_conflicts() {
+        let tmp = create_temp_dir("test_set_unified_conflicts").unwrap();
+        set_fixture(&tmp, "pids.max", "").unwrap();
+
+        let resources = LinuxResources {
+            pids: Some(LinuxPids { limit: 10 }),
+            unified: Some(HashMap::<_, _>::from_iter(IntoIter::new([(
+                "pids.max".to_owned(),
+                "20".to_owned(),
+            )]))),
+            ..Default::default()
+        };
+
+        let err = Unified::apply(&resources, &tmp, vec![ControllerType::Pids]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pids.max is set in the unified map and through linux.resources.pids.limit"
+        );
+    }
+
+    #[test]
+    fn test_set_unified_hugetlb_conflicts() {
+        let tmp = create_temp_dir("test_set_unified_hugetlb_conflicts").unwrap();
+        set_fixture(&tmp, "hugetlb.2MB.max", "").unwrap();
+
+        let resources = LinuxResources {
+            hugepage_limits: Some(vec![LinuxHugepageLimit {
+                page_size: "2048kB".to_owned(),
+                limit: 16384,
+            }]),
+            unified: Some(HashMap::<_, _>::from_iter(IntoIter::new([(
+                "hugetlb.2MB.max".to_owned(),
+                "8192".to_owned(),
+            )]))),
+            ..Default::default()
+        };
+
+        let err = Unified::apply(&resources, &tmp, vec![ControllerType::HugeTlb]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "hugetlb.2MB.max is set in the unified map and through linux.resources.hugepageLimits"
+        );
+    }
+
+    #[test]
+    fn test_set_unified_invalid_files() {
+        let tmp = create_temp_dir("test_set_unified_invalid_files").unwrap();
+
+        for file in &[
+            "cgroup.procs",
+            "nodot",
+            "../memory.max",
+            "memory.",
+            "memory.unknown",
+        ] {
+            let resources = LinuxResources {
+                unified: Some(HashMap::<_, _>::from_iter(IntoIter::new([(
+                    file.to_string(),
+                    "1".to_owned(),
+                )]))),
+                ..Default::default()
+            };
+
+            let result = Unified::apply(&resources, &tmp, vec![ControllerType::Memory]);
+            assert!(result.is_err(), "{} should not be accepted", file);
+        }
+    }
 }
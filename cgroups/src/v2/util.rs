@@ -1,13 +1,313 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{anyhow, Result};
-use procfs::process::Process;
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{self, AccessFlags};
+use oci_spec::LinuxResources;
+
+use super::{
+    controller_type::{ControllerType, CONTROLLER_TYPES},
+    cpu::Cpu,
+    hugetlb::HugeTlb,
+    io::Io,
+    memory::Memory,
+    pids::Pids,
+};
+use crate::{
+    common, mounts,
+    stats::{self, PressureStats, Stats, StatsProvider},
+};
+
+const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+const CPU_PRESSURE: &str = "cpu.pressure";
+const MEMORY_PRESSURE: &str = "memory.pressure";
+const IO_PRESSURE: &str = "io.pressure";
 
 pub fn get_unified_mount_point() -> Result<PathBuf> {
-    Process::myself()?
-        .mountinfo()?
-        .into_iter()
-        .find(|m| m.fs_type == "cgroup2")
-        .map(|m| m.mount_point)
+    mounts::unified_mount(None)?
+        .map(|m| m.mount_point.clone())
         .ok_or_else(|| anyhow!("could not find mountpoint for unified"))
 }
+
+/// Collects the stats of a cgroup. Only the controllers which are enabled for
+/// the cgroup are read, because the files of the others do not exist.
+pub fn get_stats(cgroup_path: &Path) -> Result<Stats> {
+    let controllers = common::read_cgroup_file(cgroup_path.join(CGROUP_CONTROLLERS))?;
+    let enabled: Vec<&str> = controllers.split_whitespace().collect();
+    let mut stats = Stats::default();
+
+    // cpu.stat exists without the cpu controller, but lacks the throttling
+    stats.cpu = Cpu::stats(cgroup_path)?;
+    for subsystem in CONTROLLER_TYPES {
+        if !enabled.contains(&subsystem.to_string().as_str()) {
+            continue;
+        }
+
+        match subsystem {
+            ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(cgroup_path)?,
+            ControllerType::Pids => stats.pids = Pids::stats(cgroup_path)?,
+            ControllerType::Memory => stats.memory = Memory::stats(cgroup_path)?,
+            ControllerType::Io => stats.blkio = Io::stats(cgroup_path)?,
+            _ => continue,
+        }
+    }
+
+    // the pressure is accounted for every cgroup, independent of the controllers
+    stats.pressure = PressureStats {
+        cpu: stats::psi_stats(&cgroup_path.join(CPU_PRESSURE))?,
+        memory: stats::psi_stats(&cgroup_path.join(MEMORY_PRESSURE))?,
+        io: stats::psi_stats(&cgroup_path.join(IO_PRESSURE))?,
+    };
+
+    Ok(stats)
+}
+
+/// Controllers which have to be enabled to apply the resources
+pub fn required_controllers(linux_resources: &LinuxResources) -> Vec<String> {
+    let mut controllers = Vec::new();
+    if let Some(cpu) = &linux_resources.cpu {
+        if cpu.shares.is_some() || cpu.quota.is_some() || cpu.period.is_some() {
+            controllers.push(ControllerType::Cpu.to_string());
+        }
+        if cpu.cpus.is_some() || cpu.mems.is_some() {
+            controllers.push(ControllerType::CpuSet.to_string());
+        }
+    }
+    if linux_resources.memory.is_some() {
+        controllers.push(ControllerType::Memory.to_string());
+    }
+    if linux_resources.pids.is_some() {
+        controllers.push(ControllerType::Pids.to_string());
+    }
+    if linux_resources.block_io.is_some() {
+        controllers.push(ControllerType::Io.to_string());
+    }
+    if linux_resources
+        .hugepage_limits
+        .as_ref()
+        .map_or(false, |limits| !limits.is_empty())
+    {
+        controllers.push(ControllerType::HugeTlb.to_string());
+    }
+    // files of the cgroup core do not belong to a controller
+    for file in linux_resources.unified.iter().flat_map(|u| u.keys()) {
+        if let Some((subsystem, _)) = file.split_once('.') {
+            if subsystem != "cgroup" {
+                controllers.push(subsystem.to_owned());
+            }
+        }
+    }
+
+    controllers.sort();
+    controllers.dedup();
+    controllers
+}
+
+/// Returns the controllers which are delegated to the current user for the
+/// cgroup, or None if the user is not allowed to manage it. The cgroup may not
+/// exist yet, in which case the nearest existing parent has to be delegated,
+/// i.e. the user needs write access to the directory and its cgroup.procs.
+pub fn delegated_controllers(cgroup_path: &Path) -> Result<Option<Vec<String>>> {
+    let existing = cgroup_path
+        .ancestors()
+        .find(|path| path.exists())
+        .with_context(|| format!("no parent of cgroup {:?} exists", cgroup_path))?;
+
+    let writable = |path: &Path| unistd::access(path, AccessFlags::W_OK).is_ok();
+    if !writable(existing) || !writable(&existing.join(common::CGROUP_PROCS)) {
+        return Ok(None);
+    }
+
+    let controllers = common::read_cgroup_file(existing.join(CGROUP_CONTROLLERS))?;
+    Ok(Some(
+        controllers.split_whitespace().map(str::to_owned).collect(),
+    ))
+}
+
+/// Removes the resources which need a controller that is not available. The
+/// resources which can be applied are returned together with a description of
+/// the ones which have been removed.
+pub fn restrict_resources(
+    linux_resources: &LinuxResources,
+    available: &[String],
+) -> (LinuxResources, Vec<String>) {
+    let mut restricted = linux_resources.clone();
+    let mut skipped = Vec::new();
+    let mut is_skipped = |name: &str, resources: LinuxResources| {
+        let missing: Vec<String> = required_controllers(&resources)
+            .into_iter()
+            .filter(|controller| !available.contains(controller))
+            .collect();
+        if missing.is_empty() {
+            return false;
+        }
+
+        skipped.push(format!("{} (requires {})", name, missing.join(", ")));
+        true
+    };
+
+    if let Some(cpu) = &linux_resources.cpu {
+        let resources = LinuxResources {
+            cpu: Some(cpu.clone()),
+            ..Default::default()
+        };
+        if is_skipped("cpu", resources) {
+            restricted.cpu = None;
+        }
+    }
+    if let Some(memory) = &linux_resources.memory {
+        let resources = LinuxResources {
+            memory: Some(memory.clone()),
+            ..Default::default()
+        };
+        if is_skipped("memory", resources) {
+            restricted.memory = None;
+        }
+    }
+    if let Some(pids) = &linux_resources.pids {
+        let resources = LinuxResources {
+            pids: Some(pids.clone()),
+            ..Default::default()
+        };
+        if is_skipped("pids", resources) {
+            restricted.pids = None;
+        }
+    }
+    if let Some(block_io) = &linux_resources.block_io {
+        let resources = LinuxResources {
+            block_io: Some(block_io.clone()),
+            ..Default::default()
+        };
+        if is_skipped("blockIO", resources) {
+            restricted.block_io = None;
+        }
+    }
+    if let Some(hugepage_limits) = &linux_resources.hugepage_limits {
+        let resources = LinuxResources {
+            hugepage_limits: Some(hugepage_limits.clone()),
+            ..Default::default()
+        };
+        if is_skipped("hugepageLimits", resources) {
+            restricted.hugepage_limits = None;
+        }
+    }
+    if let Some(unified) = &mut restricted.unified {
+        unified.retain(|file, value| {
+            let mut single = HashMap::new();
+            single.insert(file.clone(), value.clone());
+            let resources = LinuxResources {
+                unified: Some(single),
+                ..Default::default()
+            };
+            !is_skipped(file, resources)
+        });
+    }
+
+    (restricted, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture, LinuxCpuBuilder};
+    use oci_spec::LinuxPids;
+
+    #[test]
+    fn test_get_stats() {
+        let tmp = create_temp_dir("test_get_stats").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CONTROLLERS, "cpu memory pids").unwrap();
+        set_fixture(&tmp, "cpu.stat", "usage_usec 10\nnr_throttled 2").unwrap();
+        set_fixture(&tmp, "pids.current", "5\n").unwrap();
+        set_fixture(&tmp, "pids.max", "max\n").unwrap();
+        set_fixture(&tmp, "memory.current", "4096\n").unwrap();
+        set_fixture(&tmp, "memory.max", "8192\n").unwrap();
+        set_fixture(&tmp, "memory.events", "oom 0").unwrap();
+        set_fixture(&tmp, "memory.stat", "file 1024").unwrap();
+        set_fixture(
+            &tmp,
+            MEMORY_PRESSURE,
+            "some avg10=0.00 avg60=0.00 avg300=0.00 total=10\nfull avg10=0.00 avg60=0.00 avg300=0.00 total=5",
+        )
+        .unwrap();
+
+        let stats = get_stats(&tmp).expect("get cgroup stats");
+        assert_eq!(stats.cpu.usage.usage_total, 10000);
+        assert_eq!(stats.cpu.throttling.throttled_periods, 2);
+        assert_eq!(stats.pids.current, 5);
+        assert_eq!(stats.memory.memory.limit, 8192);
+        assert_eq!(stats.memory.cache, 1024);
+        // hugetlb and io are not enabled, so their files are not read
+        assert!(stats.hugetlb.is_empty());
+        assert_eq!(stats.pressure.cpu, None);
+        assert_eq!(stats.pressure.memory.map(|psi| psi.full.total), Some(5));
+    }
+
+    #[test]
+    fn test_required_controllers() {
+        let mut unified = HashMap::new();
+        unified.insert("misc.max".to_owned(), "sgx_epc 1024".to_owned());
+        unified.insert("memory.high".to_owned(), "max".to_owned());
+        unified.insert("cgroup.freeze".to_owned(), "0".to_owned());
+        let resources = LinuxResources {
+            pids: Some(LinuxPids { limit: 10 }),
+            unified: Some(unified),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            required_controllers(&resources),
+            vec!["memory", "misc", "pids"]
+        );
+    }
+
+    #[test]
+    fn test_restrict_resources() {
+        let mut unified = HashMap::new();
+        unified.insert("misc.max".to_owned(), "sgx_epc 1024".to_owned());
+        unified.insert("memory.high".to_owned(), "max".to_owned());
+        let resources = LinuxResources {
+            pids: Some(LinuxPids { limit: 10 }),
+            cpu: Some(
+                LinuxCpuBuilder::new()
+                    .with_shares(1024)
+                    .with_cpus("0".to_owned())
+                    .build(),
+            ),
+            unified: Some(unified),
+            ..Default::default()
+        };
+
+        let available = vec!["cpu".to_owned(), "memory".to_owned(), "pids".to_owned()];
+        let (restricted, skipped) = restrict_resources(&resources, &available);
+        assert!(restricted.cpu.is_none());
+        assert_eq!(restricted.pids.map(|pids| pids.limit), Some(10));
+        assert_eq!(
+            restricted.unified.unwrap().keys().collect::<Vec<_>>(),
+            vec!["memory.high"]
+        );
+        assert_eq!(
+            skipped,
+            vec!["cpu (requires cpuset)", "misc.max (requires misc)"]
+        );
+
+        let (restricted, skipped) = restrict_resources(&resources, &available[..0]);
+        assert!(restricted.pids.is_none());
+        assert_eq!(skipped.len(), 4);
+    }
+
+    #[test]
+    fn test_delegated_controllers() {
+        let tmp = create_temp_dir("test_delegated_controllers").expect("create temp directory");
+        set_fixture(&tmp, CGROUP_CONTROLLERS, "cpu memory").unwrap();
+        set_fixture(&tmp, common::CGROUP_PROCS, "").unwrap();
+
+        // the cgroup of the container does not exist yet
+        let controllers = delegated_controllers(&tmp.join("user.slice/youki")).unwrap();
+        assert_eq!(
+            controllers,
+            Some(vec!["cpu".to_owned(), "memory".to_owned()])
+        );
+    }
+}
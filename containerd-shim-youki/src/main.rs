@@ -0,0 +1,17 @@
+//! containerd shim v2 of youki. containerd starts a shim for every container,
+//! which creates and manages the container through libcontainer and serves
+//! the task service of containerd over ttrpc, so containerd does not have to
+//! go through the runc compatible command line of youki.
+mod metrics;
+mod reaper;
+mod service;
+mod stdio;
+
+use service::Service;
+
+/// Name under which the shim is configured as runtime of containerd
+const RUNTIME_ID: &str = "io.containerd.youki.v2";
+
+fn main() {
+    containerd_shim::run::<Service>(RUNTIME_ID, None)
+}
@@ -0,0 +1,149 @@
+//! Conversion of the statistics of the cgroup manager into the cgroup metrics
+//! of containerd, which are reported in the stats of a task
+use containerd_shim::protos::cgroups::metrics::{
+    BlkIOEntry, BlkIOStat, CPUStat, CPUUsage, HugetlbStat, MemoryEntry, MemoryStat, Metrics,
+    PidsStat, Throttle,
+};
+use protobuf::well_known_types::Any;
+use protobuf::{Message, ProtobufResult};
+
+use cgroups::stats::{BlkioDeviceStat, MemoryData, Stats};
+
+/// Type of the metrics, by which containerd decodes them
+const METRICS_TYPE_URL: &str = "io.containerd.cgroups.v1.Metrics";
+
+/// Encodes the statistics of a container
+pub fn to_any(stats: &Stats) -> ProtobufResult<Any> {
+    let mut any = Any::new();
+    any.set_type_url(METRICS_TYPE_URL.to_owned());
+    any.set_value(metrics(stats).write_to_bytes()?);
+    Ok(any)
+}
+
+fn metrics(stats: &Stats) -> Metrics {
+    let mut metrics = Metrics::new();
+
+    let mut pids = PidsStat::new();
+    pids.set_current(stats.pids.current);
+    pids.set_limit(stats.pids.limit);
+    metrics.set_pids(pids);
+
+    let mut usage = CPUUsage::new();
+    usage.set_total(stats.cpu.usage.usage_total);
+    usage.set_user(stats.cpu.usage.usage_user);
+    usage.set_kernel(stats.cpu.usage.usage_kernel);
+    usage.set_per_cpu(stats.cpu.usage.per_core_usage_total.clone());
+    let mut throttling = Throttle::new();
+    throttling.set_periods(stats.cpu.throttling.periods);
+    throttling.set_throttled_periods(stats.cpu.throttling.throttled_periods);
+    throttling.set_throttled_time(stats.cpu.throttling.throttled_time);
+    let mut cpu = CPUStat::new();
+    cpu.set_usage(usage);
+    cpu.set_throttling(throttling);
+    metrics.set_cpu(cpu);
+
+    let mut memory = MemoryStat::new();
+    memory.set_cache(stats.memory.cache);
+    memory.set_rss(stats.memory.rss);
+    memory.set_mapped_file(stats.memory.mapped_file);
+    memory.set_pg_fault(stats.memory.page_faults);
+    memory.set_pg_maj_fault(stats.memory.major_page_faults);
+    memory.set_hierarchical_memory_limit(stats.memory.hierarchical_memory_limit);
+    memory.set_hierarchical_swap_limit(stats.memory.hierarchical_memsw_limit);
+    memory.set_usage(memory_entry(&stats.memory.memory));
+    memory.set_swap(memory_entry(&stats.memory.memswap));
+    memory.set_kernel(memory_entry(&stats.memory.kernel));
+    memory.set_kernel_tcp(memory_entry(&stats.memory.kernel_tcp));
+    metrics.set_memory(memory);
+
+    let mut hugetlb: Vec<HugetlbStat> = stats
+        .hugetlb
+        .iter()
+        .map(|(pagesize, stats)| {
+            let mut hugetlb = HugetlbStat::new();
+            hugetlb.set_pagesize(pagesize.clone());
+            hugetlb.set_usage(stats.usage);
+            hugetlb.set_max(stats.max_usage);
+            hugetlb.set_failcnt(stats.fail_count);
+            hugetlb
+        })
+        .collect();
+    hugetlb.sort_by(|a, b| a.get_pagesize().cmp(b.get_pagesize()));
+    metrics.set_hugetlb(hugetlb.into());
+
+    let mut blkio = BlkIOStat::new();
+    blkio.set_io_service_bytes_recursive(blkio_entries(&stats.blkio.service_bytes).into());
+    blkio.set_io_serviced_recursive(blkio_entries(&stats.blkio.serviced).into());
+    blkio.set_io_time_recursive(blkio_entries(&stats.blkio.time).into());
+    blkio.set_sectors_recursive(blkio_entries(&stats.blkio.sectors).into());
+    blkio.set_io_service_time_recursive(blkio_entries(&stats.blkio.service_time).into());
+    blkio.set_io_wait_time_recursive(blkio_entries(&stats.blkio.wait_time).into());
+    blkio.set_io_queued_recursive(blkio_entries(&stats.blkio.queued).into());
+    blkio.set_io_merged_recursive(blkio_entries(&stats.blkio.merged).into());
+    metrics.set_blkio(blkio);
+
+    metrics
+}
+
+fn memory_entry(data: &MemoryData) -> MemoryEntry {
+    let mut entry = MemoryEntry::new();
+    entry.set_usage(data.usage);
+    entry.set_max(data.max_usage);
+    entry.set_failcnt(data.fail_count);
+    entry.set_limit(data.limit);
+    entry
+}
+
+fn blkio_entries(stats: &[BlkioDeviceStat]) -> Vec<BlkIOEntry> {
+    stats
+        .iter()
+        .map(|stat| {
+            let mut entry = BlkIOEntry::new();
+            entry.set_major(stat.major);
+            entry.set_minor(stat.minor);
+            if let Some(op) = &stat.op_type {
+                entry.set_op(op.clone());
+            }
+            entry.set_value(stat.value);
+            entry
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgroups::stats::HugeTlbStats;
+
+    #[test]
+    fn test_metrics() {
+        let mut stats = Stats::default();
+        stats.pids.current = 3;
+        stats.pids.limit = 10;
+        stats.cpu.usage.usage_total = 1200;
+        stats.cpu.usage.per_core_usage_total = vec![500, 700];
+        stats.hugetlb.insert(
+            "2MB".to_owned(),
+            HugeTlbStats {
+                usage: 2097152,
+                ..Default::default()
+            },
+        );
+
+        let metrics = metrics(&stats);
+        assert_eq!(metrics.get_pids().get_current(), 3);
+        assert_eq!(metrics.get_pids().get_limit(), 10);
+        assert_eq!(metrics.get_cpu().get_usage().get_total(), 1200);
+        assert_eq!(metrics.get_cpu().get_usage().get_per_cpu(), &[500, 700]);
+        assert_eq!(metrics.get_hugetlb().len(), 1);
+        assert_eq!(metrics.get_hugetlb()[0].get_pagesize(), "2MB");
+        assert_eq!(metrics.get_hugetlb()[0].get_usage(), 2097152);
+    }
+
+    #[test]
+    fn test_to_any() {
+        let any = to_any(&Stats::default()).unwrap();
+        assert_eq!(any.get_type_url(), METRICS_TYPE_URL);
+        assert!(Metrics::parse_from_bytes(any.get_value()).is_ok());
+    }
+}
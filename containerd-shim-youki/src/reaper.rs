@@ -0,0 +1,154 @@
+//! Reaping of the container processes. The shim is the subreaper of the
+//! containers, so their processes are reparented to it once the intermediate
+//! process of libcontainer exits, and the shim learns their exit statuses.
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use libcontainer::supervisor;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exit of a container process
+#[derive(Clone, Copy, Debug)]
+pub struct Exit {
+    pub status: u32,
+    pub at: SystemTime,
+}
+
+/// Reaps all children of the shim. The container processes are reaped as soon
+/// as they exit. Any other child is only reaped while reaping is not paused,
+/// which libcontainer needs while it runs hooks or other helper programs, as
+/// the statuses of these programs belong to libcontainer.
+#[derive(Default)]
+pub struct Reaper {
+    paused: Mutex<()>,
+}
+
+impl Reaper {
+    /// Makes the shim the subreaper of its descendants and reaps them in a
+    /// thread. `processes` returns the pids of the container processes which
+    /// have not exited yet, the callback receives the exits of all reaped
+    /// processes.
+    pub fn spawn<P, F>(self: &Arc<Self>, processes: P, on_exit: F) -> Result<()>
+    where
+        P: Fn() -> Vec<Pid> + Send + 'static,
+        F: Fn(Pid, Exit) + Send + 'static,
+    {
+        supervisor::set_subreaper()?;
+        let reaper = Arc::clone(self);
+        thread::spawn(move || loop {
+            let mut exits = reap_processes(&processes());
+            if let Ok(_paused) = reaper.paused.try_lock() {
+                exits.extend(reap());
+            }
+            for (pid, exit) in exits {
+                on_exit(pid, exit);
+            }
+            thread::sleep(POLL_INTERVAL);
+        });
+        Ok(())
+    }
+
+    /// Pauses reaping the children which are not container processes until
+    /// the returned guard is dropped
+    pub fn pause(&self) -> MutexGuard<'_, ()> {
+        self.paused.lock().unwrap()
+    }
+}
+
+// Reaps the container processes which have exited. They only become children
+// of the shim once the intermediate process of libcontainer has exited.
+fn reap_processes(pids: &[Pid]) -> Vec<(Pid, Exit)> {
+    let mut exits = Vec::new();
+    for pid in pids {
+        loop {
+            match wait::waitpid(*pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(status) => {
+                    if let Some((pid, status)) = exit_status(status) {
+                        let at = SystemTime::now();
+                        exits.push((pid, Exit { status, at }));
+                    }
+                }
+                Err(Errno::EINTR) => continue,
+                Err(_) => {}
+            }
+            break;
+        }
+    }
+    exits
+}
+
+fn reap() -> Vec<(Pid, Exit)> {
+    let mut exits = Vec::new();
+    loop {
+        match wait::waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+            Ok(status) => {
+                if let Some((pid, status)) = exit_status(status) {
+                    let at = SystemTime::now();
+                    exits.push((pid, Exit { status, at }));
+                }
+            }
+            Err(Errno::EINTR) => {}
+            Err(err) => {
+                log::warn!("failed to reap processes: {}", err);
+                break;
+            }
+        }
+    }
+    exits
+}
+
+// The exit status of a process which is killed by a signal is reported like
+// by a shell, as 128 plus the number of the signal
+fn exit_status(status: WaitStatus) -> Option<(Pid, u32)> {
+    match status {
+        WaitStatus::Exited(pid, code) => Some((pid, code as u32)),
+        WaitStatus::Signaled(pid, signal, _) => Some((pid, 128 + signal as u32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::signal::Signal;
+
+    #[test]
+    fn test_reap_processes() -> Result<()> {
+        let child = match unsafe { nix::unistd::fork()? } {
+            nix::unistd::ForkResult::Child => std::process::exit(3),
+            nix::unistd::ForkResult::Parent { child } => child,
+        };
+        // processes which are not children are skipped
+        let other = Pid::from_raw(1);
+        let exits = loop {
+            let exits = reap_processes(&[other, child]);
+            if !exits.is_empty() {
+                break exits;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].0, child);
+        assert_eq!(exits[0].1.status, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(exit_status(WaitStatus::Exited(pid, 3)), Some((pid, 3)));
+        assert_eq!(
+            exit_status(WaitStatus::Signaled(pid, Signal::SIGKILL, false)),
+            Some((pid, 137))
+        );
+        assert_eq!(exit_status(WaitStatus::Stopped(pid, Signal::SIGSTOP)), None);
+    }
+}
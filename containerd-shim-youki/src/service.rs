@@ -0,0 +1,1000 @@
+//! The shim and its task service, through which containerd creates and
+//! manages the container of the shim and the processes which are executed in
+//! it.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use containerd_shim::protos::events::task::TaskExit;
+use containerd_shim::protos::shim::oci::Options;
+use containerd_shim::protos::types::mount::Mount as RootfsMount;
+use containerd_shim::protos::types::task::{ProcessInfo, Status};
+use containerd_shim::publisher::RemotePublisher;
+use containerd_shim::{
+    api, Config, Error, ExitSignal, Shim, StartOpts, Task, TtrpcContext, TtrpcResult,
+};
+use nix::mount::{self, MntFlags};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{self, Pid};
+use oci_spec::Mount;
+use protobuf::well_known_types::Timestamp;
+use protobuf::Message;
+use ttrpc::context::Context as TtrpcClientContext;
+use ttrpc::Code;
+
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, ContainerStatus};
+use libcontainer::error::ContainerError;
+use libcontainer::tty::ConsoleReceiver;
+use libcontainer::{rootfs, utils};
+
+use crate::metrics;
+use crate::reaper::{Exit, Reaper};
+use crate::stdio::Stdio;
+
+/// Directory in which the states of the containers are kept, below a
+/// directory of the namespace of containerd
+const STATE_ROOT: &str = "/run/containerd/youki";
+/// Topic of the event which tells containerd that a process has exited
+const TASK_EXIT_TOPIC: &str = "/tasks/exit";
+
+/// Publishes the events of the task service to containerd
+trait EventPublisher: Send + Sync {
+    fn publish_exit(&self, namespace: &str, event: TaskExit);
+}
+
+impl EventPublisher for RemotePublisher {
+    fn publish_exit(&self, namespace: &str, event: TaskExit) {
+        let pid = event.get_pid();
+        if let Err(err) = self.publish(
+            TtrpcClientContext::default(),
+            TASK_EXIT_TOPIC,
+            namespace,
+            event,
+        ) {
+            log::warn!("failed to publish exit of process {}: {}", pid, err);
+        }
+    }
+}
+
+pub struct Service {
+    id: String,
+    namespace: String,
+    publisher: Arc<RemotePublisher>,
+    exit: Arc<ExitSignal>,
+}
+
+impl Shim for Service {
+    type T = TaskService;
+
+    fn new(
+        _runtime_id: &str,
+        id: &str,
+        namespace: &str,
+        publisher: RemotePublisher,
+        config: &mut Config,
+    ) -> Self {
+        // the task service reaps the children itself, as it has to know the
+        // exits of the container processes
+        config.no_reaper = true;
+        Service {
+            id: id.to_owned(),
+            namespace: namespace.to_owned(),
+            publisher: Arc::new(publisher),
+            exit: Arc::new(ExitSignal::default()),
+        }
+    }
+
+    fn start_shim(&mut self, opts: StartOpts) -> Result<String, Error> {
+        let grouping = opts.id.clone();
+        let address = containerd_shim::spawn(opts, &grouping, Vec::new())?;
+        Ok(address)
+    }
+
+    // containerd deletes the container this way if the shim has died
+    fn delete_shim(&mut self) -> Result<api::DeleteResponse, Error> {
+        let container_root = state_root(&self.namespace).join(&self.id);
+        if container_root.exists() {
+            if let Err(err) = Container::load(container_root).and_then(|c| c.delete(true)) {
+                log::warn!("failed to delete container {}: {:#}", self.id, err);
+            }
+        }
+
+        let mut resp = api::DeleteResponse::new();
+        resp.set_exit_status(128 + Signal::SIGKILL as u32);
+        resp.set_exited_at(timestamp(SystemTime::now()));
+        Ok(resp)
+    }
+
+    fn wait(&mut self) {
+        self.exit.wait();
+    }
+
+    fn create_task_service(&self) -> Self::T {
+        let service = TaskService::new(
+            state_root(&self.namespace),
+            self.namespace.clone(),
+            Arc::clone(&self.publisher) as Arc<dyn EventPublisher>,
+            Arc::clone(&self.exit),
+        );
+        if let Err(err) = service.reap() {
+            log::error!("failed to reap the container processes: {:#}", err);
+        }
+        service
+    }
+}
+
+fn state_root(namespace: &str) -> PathBuf {
+    Path::new(STATE_ROOT).join(namespace)
+}
+
+pub struct TaskService {
+    root: PathBuf,
+    namespace: String,
+    publisher: Arc<dyn EventPublisher>,
+    exit: Arc<ExitSignal>,
+    containers: Arc<Mutex<HashMap<String, ShimContainer>>>,
+    // notified whenever a process of a container has exited
+    exited: Arc<Condvar>,
+    reaper: Arc<Reaper>,
+}
+
+struct ShimContainer {
+    bundle: PathBuf,
+    // whether the shim has mounted the rootfs of the bundle
+    rootfs_mounted: bool,
+    init: Process,
+    execs: HashMap<String, Process>,
+}
+
+impl ShimContainer {
+    fn process(&self, exec_id: &str) -> TtrpcResult<&Process> {
+        if exec_id.is_empty() {
+            return Ok(&self.init);
+        }
+        self.execs
+            .get(exec_id)
+            .ok_or_else(|| status(Code::NOT_FOUND, format!("exec {} not found", exec_id)))
+    }
+}
+
+struct Process {
+    pid: Option<Pid>,
+    stdio: Stdio,
+    exit: Option<Exit>,
+    // the process spec of an exec, from which the process is created on start
+    spec: Option<PathBuf>,
+}
+
+impl Process {
+    fn new(stdio: Stdio) -> Self {
+        Self {
+            pid: None,
+            stdio,
+            exit: None,
+            spec: None,
+        }
+    }
+}
+
+impl TaskService {
+    fn new(
+        root: PathBuf,
+        namespace: String,
+        publisher: Arc<dyn EventPublisher>,
+        exit: Arc<ExitSignal>,
+    ) -> Self {
+        Self {
+            root,
+            namespace,
+            publisher,
+            exit,
+            containers: Arc::default(),
+            exited: Arc::default(),
+            reaper: Arc::default(),
+        }
+    }
+
+    // The exits are recorded in the processes of the containers and
+    // published to containerd
+    fn reap(&self) -> Result<()> {
+        let processes = {
+            let containers = Arc::clone(&self.containers);
+            move || running_processes(&containers.lock().unwrap())
+        };
+        let containers = Arc::clone(&self.containers);
+        let exited = Arc::clone(&self.exited);
+        let publisher = Arc::clone(&self.publisher);
+        let namespace = self.namespace.clone();
+        self.reaper.spawn(processes, move |pid, exit| {
+            let mut containers = containers.lock().unwrap();
+            for (container_id, container) in containers.iter_mut() {
+                let processes = std::iter::once((container_id, &mut container.init))
+                    .chain(container.execs.iter_mut());
+                for (id, process) in processes {
+                    if process.pid != Some(pid) {
+                        continue;
+                    }
+                    process.exit = Some(exit);
+
+                    let mut event = TaskExit::new();
+                    event.set_container_id(container_id.clone());
+                    event.set_id(id.clone());
+                    event.set_pid(pid.as_raw() as u32);
+                    event.set_exit_status(exit.status);
+                    event.set_exited_at(timestamp(exit.at));
+                    publisher.publish_exit(&namespace, event);
+                }
+            }
+            exited.notify_all();
+        })
+    }
+
+    fn container_root(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    fn create_container(
+        &self,
+        id: &str,
+        bundle: &Path,
+        stdio: &Stdio,
+        use_systemd: bool,
+    ) -> Result<Pid, ContainerError> {
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("failed to create state directory {:?}", self.root))?;
+        let console = if stdio.terminal {
+            Some(ConsoleReceiver::new()?)
+        } else {
+            None
+        };
+        let console_socket = console.as_ref().map(|c| c.path());
+
+        let files = if stdio.terminal {
+            None
+        } else {
+            Some(stdio.open()?)
+        };
+        let container = ContainerBuilder::new(id.to_owned())
+            .with_root_path(self.root.clone())
+            .with_console_socket(console_socket.as_ref())
+            .with_stdio(files.as_ref().map(|files| files.fds()))
+            .as_init(bundle)
+            .with_systemd(use_systemd)
+            .build()?;
+        let pid = container.pid().context("no pid in container state")?;
+
+        if let Some(console) = console {
+            let master = console.receive(pid)?;
+            stdio.copy_console(master)?;
+        }
+        Ok(pid)
+    }
+
+    fn exec_process(
+        &self,
+        id: &str,
+        stdio: &Stdio,
+        spec: Option<&PathBuf>,
+    ) -> Result<Pid, ContainerError> {
+        let console = if stdio.terminal {
+            Some(ConsoleReceiver::new()?)
+        } else {
+            None
+        };
+        let console_socket = console.as_ref().map(|c| c.path());
+
+        let files = if stdio.terminal {
+            None
+        } else {
+            Some(stdio.open()?)
+        };
+        let pid = ContainerBuilder::new(id.to_owned())
+            .with_root_path(self.root.clone())
+            .with_console_socket(console_socket.as_ref())
+            .with_stdio(files.as_ref().map(|files| files.fds()))
+            .as_tenant()
+            .with_process(spec)
+            .with_tty(stdio.terminal)
+            .build()?;
+
+        if let Some(console) = console {
+            let master = console.receive(pid)?;
+            stdio.copy_console(master)?;
+        }
+        Ok(pid)
+    }
+
+    fn cgroup_manager(&self, id: &str) -> Result<Box<dyn cgroups::common::CgroupManager>> {
+        let container = Container::load(self.container_root(id))?;
+        let spec = container.spec()?;
+        let use_systemd = container.systemd().unwrap_or(false);
+        let cgroups_path = utils::get_cgroup_path(
+            &spec.linux.context("no linux in spec")?.cgroups_path,
+            id,
+            use_systemd,
+        );
+        Ok(cgroups::common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            use_systemd,
+        )?)
+    }
+}
+
+// The lifecycle of the processes, which does not depend on the ttrpc context.
+// The containers are not locked while libcontainer creates, starts or deletes
+// a container, as hooks are run and the readiness of the container may be
+// awaited meanwhile. Reaping is paused instead, which leaves the container
+// processes alone.
+impl TaskService {
+    fn create_task(&self, req: api::CreateTaskRequest) -> TtrpcResult<api::CreateTaskResponse> {
+        let id = req.get_id();
+        let bundle = PathBuf::from(req.get_bundle());
+        log::debug!("create container {} from bundle {:?}", id, bundle);
+
+        if self.containers.lock().unwrap().contains_key(id) {
+            return Err(status(
+                Code::ALREADY_EXISTS,
+                format!("container {} already exists", id),
+            ));
+        }
+
+        let options = if req.has_options() {
+            Options::parse_from_bytes(req.get_options().get_value())
+                .map_err(|err| status(Code::INVALID_ARGUMENT, err))?
+        } else {
+            Options::new()
+        };
+        let stdio = Stdio {
+            stdin: req.get_stdin().to_owned(),
+            stdout: req.get_stdout().to_owned(),
+            stderr: req.get_stderr().to_owned(),
+            terminal: req.get_terminal(),
+        };
+
+        mount_rootfs(&bundle, req.get_rootfs()).map_err(to_status)?;
+        let rootfs_mounted = !req.get_rootfs().is_empty();
+        let mut init = Process::new(stdio);
+        // the state of a container which exists already must be left alone
+        // when the create fails
+        let container_root = self.container_root(id);
+        let existed = container_root.exists();
+        // the init process must not be reaped before its pid is known
+        let reaping = self.reaper.pause();
+        let created = self.create_container(id, &bundle, &init.stdio, options.get_systemd_cgroup());
+        let pid = match created {
+            Ok(pid) => pid,
+            Err(err) => {
+                if !existed && container_root.exists() {
+                    if let Err(err) = fs::remove_dir_all(&container_root) {
+                        log::warn!("failed to remove {:?}: {}", container_root, err);
+                    }
+                }
+                if rootfs_mounted {
+                    unmount_rootfs(&bundle);
+                }
+                return Err(to_status(err));
+            }
+        };
+        init.pid = Some(pid);
+        self.containers.lock().unwrap().insert(
+            id.to_owned(),
+            ShimContainer {
+                bundle,
+                rootfs_mounted,
+                init,
+                execs: HashMap::new(),
+            },
+        );
+        drop(reaping);
+
+        let mut resp = api::CreateTaskResponse::new();
+        resp.set_pid(pid.as_raw() as u32);
+        Ok(resp)
+    }
+
+    fn start_process(&self, req: api::StartRequest) -> TtrpcResult<api::StartResponse> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+        log::debug!("start process {:?} of container {}", exec_id, id);
+
+        let (init_pid, exec) = {
+            let containers = self.containers.lock().unwrap();
+            let container = containers.get(id).ok_or_else(|| not_found(id))?;
+            if exec_id.is_empty() {
+                (container.init.pid, None)
+            } else {
+                let process = container.process(exec_id)?;
+                (None, Some((process.stdio.clone(), process.spec.clone())))
+            }
+        };
+
+        let pid = match exec {
+            None => {
+                // hooks are run while starting, their exits belong to libcontainer
+                let _reaping = self.reaper.pause();
+                Container::load(self.container_root(id))
+                    .and_then(|c| c.start())
+                    .map_err(to_status)?;
+                init_pid
+            }
+            Some((stdio, spec)) => {
+                let _reaping = self.reaper.pause();
+                let pid = self
+                    .exec_process(id, &stdio, spec.as_ref())
+                    .map_err(to_status)?;
+                // recorded before reaping continues, as the process may have
+                // exited already
+                let mut containers = self.containers.lock().unwrap();
+                if let Some(process) = containers
+                    .get_mut(id)
+                    .and_then(|c| c.execs.get_mut(exec_id))
+                {
+                    process.pid = Some(pid);
+                    // the spec is not needed anymore once the process exists
+                    if let Some(spec) = process.spec.take() {
+                        if let Err(err) = fs::remove_file(&spec) {
+                            log::warn!("failed to remove process spec {:?}: {}", spec, err);
+                        }
+                    }
+                }
+                Some(pid)
+            }
+        };
+
+        let mut resp = api::StartResponse::new();
+        resp.set_pid(pid.map(|pid| pid.as_raw() as u32).unwrap_or_default());
+        Ok(resp)
+    }
+
+    fn delete_process(&self, req: api::DeleteRequest) -> TtrpcResult<api::DeleteResponse> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+        log::debug!("delete process {:?} of container {}", exec_id, id);
+
+        let process = if exec_id.is_empty() {
+            if !self.containers.lock().unwrap().contains_key(id) {
+                return Err(not_found(id));
+            }
+            {
+                // poststop hooks are run while deleting
+                let _reaping = self.reaper.pause();
+                Container::load(self.container_root(id))
+                    .and_then(|c| c.delete(false))
+                    .map_err(to_status)?;
+            }
+            let container = self
+                .containers
+                .lock()
+                .unwrap()
+                .remove(id)
+                .ok_or_else(|| not_found(id))?;
+            if container.rootfs_mounted {
+                unmount_rootfs(&container.bundle);
+            }
+            container.init
+        } else {
+            let mut containers = self.containers.lock().unwrap();
+            let container = containers.get_mut(id).ok_or_else(|| not_found(id))?;
+            let process = container
+                .execs
+                .remove(exec_id)
+                .ok_or_else(|| status(Code::NOT_FOUND, format!("exec {} not found", exec_id)))?;
+            if let Some(spec) = process.spec.as_ref() {
+                let _ = fs::remove_file(spec);
+            }
+            process
+        };
+
+        let mut resp = api::DeleteResponse::new();
+        if let Some(pid) = process.pid {
+            resp.set_pid(pid.as_raw() as u32);
+        }
+        if let Some(exit) = process.exit {
+            resp.set_exit_status(exit.status);
+            resp.set_exited_at(timestamp(exit.at));
+        }
+        Ok(resp)
+    }
+
+    fn kill_process(&self, req: api::KillRequest) -> TtrpcResult<api::Empty> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+        let sig = Signal::try_from(req.get_signal() as i32)
+            .map_err(|err| status(Code::INVALID_ARGUMENT, err))?;
+        log::debug!(
+            "kill process {:?} of container {} with {}",
+            exec_id,
+            id,
+            sig
+        );
+
+        let containers = self.containers.lock().unwrap();
+        let container = containers.get(id).ok_or_else(|| not_found(id))?;
+        let process = container.process(exec_id)?;
+        if process.exit.is_some() {
+            return Err(status(
+                Code::NOT_FOUND,
+                format!(
+                    "process {:?} of container {} has already exited",
+                    exec_id, id
+                ),
+            ));
+        }
+
+        if exec_id.is_empty() {
+            Container::load(self.container_root(id))
+                .and_then(|c| {
+                    if req.get_all() {
+                        c.kill_all(sig)
+                    } else {
+                        c.kill(sig)
+                    }
+                })
+                .map_err(to_status)?;
+        } else {
+            let pid = process.pid.ok_or_else(|| {
+                status(
+                    Code::FAILED_PRECONDITION,
+                    format!("exec {} has not been started", exec_id),
+                )
+            })?;
+            signal::kill(pid, sig).map_err(to_status)?;
+        }
+        Ok(api::Empty::new())
+    }
+
+    fn wait_process(&self, req: api::WaitRequest) -> TtrpcResult<api::WaitResponse> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+
+        let mut containers = self.containers.lock().unwrap();
+        let exit = loop {
+            let container = containers.get(id).ok_or_else(|| not_found(id))?;
+            if let Some(exit) = container.process(exec_id)?.exit {
+                break exit;
+            }
+            containers = self.exited.wait(containers).unwrap();
+        };
+
+        let mut resp = api::WaitResponse::new();
+        resp.set_exit_status(exit.status);
+        resp.set_exited_at(timestamp(exit.at));
+        Ok(resp)
+    }
+}
+
+impl Task for TaskService {
+    fn create(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::CreateTaskRequest,
+    ) -> TtrpcResult<api::CreateTaskResponse> {
+        self.create_task(req)
+    }
+
+    fn start(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::StartRequest,
+    ) -> TtrpcResult<api::StartResponse> {
+        self.start_process(req)
+    }
+
+    fn delete(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::DeleteRequest,
+    ) -> TtrpcResult<api::DeleteResponse> {
+        self.delete_process(req)
+    }
+
+    fn kill(&self, _ctx: &TtrpcContext, req: api::KillRequest) -> TtrpcResult<api::Empty> {
+        self.kill_process(req)
+    }
+
+    fn exec(&self, _ctx: &TtrpcContext, req: api::ExecProcessRequest) -> TtrpcResult<api::Empty> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+        log::debug!("exec process {} in container {}", exec_id, id);
+
+        // the exec id names the file of the process spec in the bundle
+        if !valid_exec_id(exec_id) {
+            return Err(status(
+                Code::INVALID_ARGUMENT,
+                format!("invalid exec id {:?}", exec_id),
+            ));
+        }
+
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(id).ok_or_else(|| not_found(id))?;
+        if container.execs.contains_key(exec_id) {
+            return Err(status(
+                Code::ALREADY_EXISTS,
+                format!("exec {} already exists", exec_id),
+            ));
+        }
+
+        // the process spec is read by libcontainer when the process is started
+        let spec = container.bundle.join(format!("exec-{}.json", exec_id));
+        fs::write(&spec, req.get_spec().get_value())
+            .with_context(|| format!("failed to write process spec {:?}", spec))
+            .map_err(to_status)?;
+        let mut process = Process::new(Stdio {
+            stdin: req.get_stdin().to_owned(),
+            stdout: req.get_stdout().to_owned(),
+            stderr: req.get_stderr().to_owned(),
+            terminal: req.get_terminal(),
+        });
+        process.spec = Some(spec);
+        container.execs.insert(exec_id.to_owned(), process);
+        Ok(api::Empty::new())
+    }
+
+    fn pids(&self, _ctx: &TtrpcContext, req: api::PidsRequest) -> TtrpcResult<api::PidsResponse> {
+        let id = req.get_id();
+        if !self.containers.lock().unwrap().contains_key(id) {
+            return Err(not_found(id));
+        }
+
+        let pids = self
+            .cgroup_manager(id)
+            .and_then(|cmanager| Ok(cmanager.get_all_pids()?))
+            .map_err(to_status)?;
+        let processes: Vec<ProcessInfo> = pids
+            .into_iter()
+            .map(|pid| {
+                let mut info = ProcessInfo::new();
+                info.set_pid(pid.as_raw() as u32);
+                info
+            })
+            .collect();
+
+        let mut resp = api::PidsResponse::new();
+        resp.set_processes(processes.into());
+        Ok(resp)
+    }
+
+    fn stats(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::StatsRequest,
+    ) -> TtrpcResult<api::StatsResponse> {
+        let id = req.get_id();
+        if !self.containers.lock().unwrap().contains_key(id) {
+            return Err(not_found(id));
+        }
+
+        let stats = self
+            .cgroup_manager(id)
+            .and_then(|cmanager| Ok(cmanager.stats()?))
+            .map_err(to_status)?;
+        let stats = metrics::to_any(&stats).map_err(|err| status(Code::INTERNAL, err))?;
+
+        let mut resp = api::StatsResponse::new();
+        resp.set_stats(stats);
+        Ok(resp)
+    }
+
+    fn wait(&self, _ctx: &TtrpcContext, req: api::WaitRequest) -> TtrpcResult<api::WaitResponse> {
+        self.wait_process(req)
+    }
+
+    fn state(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::StateRequest,
+    ) -> TtrpcResult<api::StateResponse> {
+        let id = req.get_id();
+        let exec_id = req.get_exec_id();
+
+        let containers = self.containers.lock().unwrap();
+        let container = containers.get(id).ok_or_else(|| not_found(id))?;
+        let process = container.process(exec_id)?;
+
+        let mut resp = api::StateResponse::new();
+        resp.set_id(id.to_owned());
+        resp.set_exec_id(exec_id.to_owned());
+        resp.set_bundle(container.bundle.to_string_lossy().into_owned());
+        resp.set_stdin(process.stdio.stdin.clone());
+        resp.set_stdout(process.stdio.stdout.clone());
+        resp.set_stderr(process.stdio.stderr.clone());
+        resp.set_terminal(process.stdio.terminal);
+        if let Some(pid) = process.pid {
+            resp.set_pid(pid.as_raw() as u32);
+        }
+
+        let status = match (process.exit, process.pid) {
+            (Some(exit), _) => {
+                resp.set_exit_status(exit.status);
+                resp.set_exited_at(timestamp(exit.at));
+                Status::STOPPED
+            }
+            (None, None) => Status::CREATED,
+            (None, Some(_)) if exec_id.is_empty() => {
+                let container = Container::load(self.container_root(id))
+                    .and_then(|c| c.refresh_status())
+                    .map_err(to_status)?;
+                match container.status() {
+                    ContainerStatus::Creating | ContainerStatus::Created => Status::CREATED,
+                    ContainerStatus::Running => Status::RUNNING,
+                    ContainerStatus::Paused => Status::PAUSED,
+                    ContainerStatus::Stopped => Status::STOPPED,
+                }
+            }
+            (None, Some(_)) => Status::RUNNING,
+        };
+        resp.set_status(status);
+        Ok(resp)
+    }
+
+    fn connect(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::ConnectRequest,
+    ) -> TtrpcResult<api::ConnectResponse> {
+        let containers = self.containers.lock().unwrap();
+        let container = containers.get(req.get_id());
+
+        let mut resp = api::ConnectResponse::new();
+        resp.set_shim_pid(unistd::getpid().as_raw() as u32);
+        if let Some(pid) = container.and_then(|c| c.init.pid) {
+            resp.set_task_pid(pid.as_raw() as u32);
+        }
+        Ok(resp)
+    }
+
+    // The shim exits once all of its containers have been deleted
+    fn shutdown(&self, _ctx: &TtrpcContext, _req: api::ShutdownRequest) -> TtrpcResult<api::Empty> {
+        if self.containers.lock().unwrap().is_empty() {
+            self.exit.signal();
+        }
+        Ok(api::Empty::new())
+    }
+}
+
+// Pids of the container processes which have not exited yet
+fn running_processes(containers: &HashMap<String, ShimContainer>) -> Vec<Pid> {
+    containers
+        .values()
+        .flat_map(|c| std::iter::once(&c.init).chain(c.execs.values()))
+        .filter(|p| p.exit.is_none())
+        .filter_map(|p| p.pid)
+        .collect()
+}
+
+// Mounts the rootfs which containerd has prepared for the container, usually
+// an overlay of the layers of the image, onto the rootfs of the bundle. The
+// mounts are stacked, so the mounts which were made before one fails are
+// unmounted again, the last one first.
+fn mount_rootfs(bundle: &Path, mounts: &[RootfsMount]) -> Result<()> {
+    let rootfs = bundle.join("rootfs");
+    for (mounted, m) in mounts.iter().enumerate() {
+        let (flags, data) = rootfs::parse_mount(&Mount {
+            destination: rootfs.clone(),
+            typ: Some(m.get_field_type().to_owned()),
+            source: Some(PathBuf::from(m.get_source())),
+            options: Some(m.get_options().to_vec()),
+        });
+        let result = mount::mount(
+            Some(m.get_source()),
+            &rootfs,
+            Some(m.get_field_type()),
+            flags,
+            Some(data.as_str()),
+        );
+        if let Err(err) = result {
+            for _ in 0..mounted {
+                unmount_rootfs(bundle);
+            }
+            return Err(err)
+                .with_context(|| format!("failed to mount {} onto {:?}", m.get_source(), rootfs));
+        }
+    }
+    Ok(())
+}
+
+fn unmount_rootfs(bundle: &Path) {
+    let rootfs = bundle.join("rootfs");
+    if let Err(err) = mount::umount2(&rootfs, MntFlags::MNT_DETACH) {
+        log::warn!("failed to unmount {:?}: {}", rootfs, err);
+    }
+}
+
+// Exec ids are restricted to characters which are safe in a file name
+fn valid_exec_id(exec_id: &str) -> bool {
+    !exec_id.is_empty()
+        && exec_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn timestamp(at: SystemTime) -> Timestamp {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut timestamp = Timestamp::new();
+    timestamp.set_seconds(since_epoch.as_secs() as i64);
+    timestamp.set_nanos(since_epoch.subsec_nanos() as i32);
+    timestamp
+}
+
+fn status<M: ToString>(code: Code, message: M) -> ttrpc::Error {
+    ttrpc::Error::RpcStatus(ttrpc::get_status(code, message.to_string()))
+}
+
+fn not_found(id: &str) -> ttrpc::Error {
+    status(Code::NOT_FOUND, format!("container {} not found", id))
+}
+
+/// Reports a failure of libcontainer with the code which matches its kind
+fn to_status<E: Into<ContainerError>>(err: E) -> ttrpc::Error {
+    let err = err.into();
+    let code = match err {
+        ContainerError::Spec(_) => Code::INVALID_ARGUMENT,
+        ContainerError::Permission(_) => Code::PERMISSION_DENIED,
+        ContainerError::KernelFeatureMissing(_) => Code::UNIMPLEMENTED,
+        ContainerError::State(_) => Code::FAILED_PRECONDITION,
+        ContainerError::System(_) => Code::INTERNAL,
+    };
+    status(code, format!("{:#}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcontainer::utils::create_temp_dir;
+    use oci_spec::Spec;
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    // Records the exits instead of publishing them to containerd
+    #[derive(Default)]
+    struct RecordingPublisher {
+        exits: Mutex<Vec<TaskExit>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish_exit(&self, _namespace: &str, event: TaskExit) {
+            self.exits.lock().unwrap().push(event);
+        }
+    }
+
+    // A bundle whose rootfs contains the binaries of the host
+    fn create_bundle(bundle: &Path, args: &[&str]) -> Result<()> {
+        let rootfs = bundle.join("rootfs");
+        fs::create_dir_all(&rootfs)?;
+        let mut mounts = Vec::new();
+        for dir in &["bin", "sbin", "lib", "lib64", "usr"] {
+            let host = Path::new("/").join(dir);
+            if let Ok(target) = fs::read_link(&host) {
+                symlink(target, rootfs.join(dir))?;
+            } else if host.is_dir() {
+                fs::create_dir(rootfs.join(dir))?;
+                mounts.push(Mount {
+                    destination: PathBuf::from("/").join(dir),
+                    typ: Some("bind".to_owned()),
+                    source: Some(host),
+                    options: Some(vec!["rbind".to_owned(), "ro".to_owned()]),
+                });
+            }
+        }
+
+        let mut spec = Spec::default();
+        let process = spec.process.as_mut().context("no process in spec")?;
+        process.terminal = Some(false);
+        process.args = Some(args.iter().map(|arg| arg.to_string()).collect());
+        spec.root.as_mut().context("no root in spec")?.path = PathBuf::from("rootfs");
+        spec.mounts.get_or_insert_with(Vec::new).extend(mounts);
+        spec.save(bundle.join("config.json"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_lifecycle() -> Result<()> {
+        // creating a container requires root
+        if !unistd::geteuid().is_root() {
+            return Ok(());
+        }
+        let tmp = create_temp_dir("test_shim_task_lifecycle")?;
+        let bundle = tmp.join("bundle");
+        create_bundle(&bundle, &["sleep", "1000"])?;
+        let publisher = Arc::new(RecordingPublisher::default());
+        let service = TaskService::new(
+            tmp.join("state"),
+            "default".to_owned(),
+            Arc::clone(&publisher) as Arc<dyn EventPublisher>,
+            Arc::new(ExitSignal::default()),
+        );
+        service.reap()?;
+        let id = "test_shim_task_lifecycle";
+
+        let mut req = api::CreateTaskRequest::new();
+        req.set_id(id.to_owned());
+        req.set_bundle(bundle.to_string_lossy().into_owned());
+        let pid = service.create_task(req).unwrap().get_pid();
+        assert!(pid > 0);
+
+        let mut req = api::StartRequest::new();
+        req.set_id(id.to_owned());
+        assert_eq!(service.start_process(req).unwrap().get_pid(), pid);
+
+        let mut req = api::KillRequest::new();
+        req.set_id(id.to_owned());
+        req.set_signal(Signal::SIGKILL as u32);
+        req.set_all(true);
+        service.kill_process(req).unwrap();
+
+        let mut req = api::WaitRequest::new();
+        req.set_id(id.to_owned());
+        assert_eq!(service.wait_process(req).unwrap().get_exit_status(), 137);
+
+        let mut req = api::DeleteRequest::new();
+        req.set_id(id.to_owned());
+        let resp = service.delete_process(req).unwrap();
+        assert_eq!(resp.get_pid(), pid);
+        assert_eq!(resp.get_exit_status(), 137);
+        assert!(!service.containers.lock().unwrap().contains_key(id));
+
+        let exits = publisher.exits.lock().unwrap();
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].get_container_id(), id);
+        assert_eq!(exits[0].get_pid(), pid);
+        assert_eq!(exits[0].get_exit_status(), 137);
+        Ok(())
+    }
+
+    #[test]
+    fn test_valid_exec_id() {
+        assert!(valid_exec_id("exec-1.a_b"));
+        assert!(!valid_exec_id(""));
+        assert!(!valid_exec_id("../config"));
+        assert!(!valid_exec_id("a/b"));
+        assert!(!valid_exec_id("exec id"));
+    }
+
+    #[test]
+    fn test_mount_rootfs_rollback() -> Result<()> {
+        // mounting requires root
+        if !unistd::geteuid().is_root() {
+            return Ok(());
+        }
+        let bundle = create_temp_dir("test_shim_mount_rootfs_rollback")?;
+        let rootfs = bundle.join("rootfs");
+        fs::create_dir(&rootfs)?;
+
+        let mut tmpfs = RootfsMount::new();
+        tmpfs.set_field_type("tmpfs".to_owned());
+        tmpfs.set_source("tmpfs".to_owned());
+        let mut invalid = RootfsMount::new();
+        invalid.set_field_type("youki-invalid".to_owned());
+        invalid.set_source("none".to_owned());
+        assert!(mount_rootfs(&bundle, &[tmpfs, invalid]).is_err());
+
+        // the tmpfs has been unmounted again
+        assert_eq!(fs::metadata(&rootfs)?.dev(), fs::metadata(&*bundle)?.dev());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let at = UNIX_EPOCH + std::time::Duration::new(1634383200, 500);
+        let timestamp = timestamp(at);
+        assert_eq!(timestamp.get_seconds(), 1634383200);
+        assert_eq!(timestamp.get_nanos(), 500);
+    }
+
+    #[test]
+    fn test_to_status() {
+        let err = to_status(ContainerError::spec("no root in spec"));
+        match err {
+            ttrpc::Error::RpcStatus(status) => {
+                assert_eq!(status.get_code(), Code::INVALID_ARGUMENT);
+                assert_eq!(status.get_message(), "no root in spec");
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+}
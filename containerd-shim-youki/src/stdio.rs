@@ -0,0 +1,101 @@
+//! Standard streams of the container processes. containerd passes the paths
+//! of fifos, which either become the standard streams of the process, or are
+//! connected to the pty of the process if it has a terminal.
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::thread;
+
+use anyhow::{Context, Result};
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::unistd;
+
+const DEV_NULL: &str = "/dev/null";
+
+#[derive(Clone, Debug, Default)]
+pub struct Stdio {
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub terminal: bool,
+}
+
+impl Stdio {
+    /// Opens the fifos, which are passed to libcontainer as the standard
+    /// streams of the process. The standard streams of the shim are left
+    /// alone.
+    pub fn open(&self) -> Result<StdioFiles> {
+        Ok(StdioFiles {
+            files: [
+                open_input(&self.stdin)?,
+                open_output(&self.stdout)?,
+                open_output(&self.stderr)?,
+            ],
+        })
+    }
+
+    /// Copies between the fifos and the pty master of a process until the
+    /// process closes its terminal
+    pub fn copy_console(&self, master: RawFd) -> Result<()> {
+        if !self.stdin.is_empty() {
+            let mut input = open_input(&self.stdin)?;
+            let mut to_master = unsafe { File::from_raw_fd(unistd::dup(master)?) };
+            thread::spawn(move || {
+                let _ = io::copy(&mut input, &mut to_master);
+            });
+        }
+
+        let mut from_master = unsafe { File::from_raw_fd(master) };
+        let mut output = open_output(&self.stdout)?;
+        thread::spawn(move || {
+            // reading from the master fails with EIO once the terminal is closed
+            let _ = io::copy(&mut from_master, &mut output);
+        });
+        Ok(())
+    }
+}
+
+/// Opened fifos of a process, which have to stay open until the process has
+/// been created
+pub struct StdioFiles {
+    files: [File; 3],
+}
+
+impl StdioFiles {
+    /// Descriptors of stdin, stdout and stderr
+    pub fn fds(&self) -> [RawFd; 3] {
+        [
+            self.files[0].as_raw_fd(),
+            self.files[1].as_raw_fd(),
+            self.files[2].as_raw_fd(),
+        ]
+    }
+}
+
+// Opening the reading end of a fifo blocks until there is a writer, so the
+// fifo is opened non blocking and switched to blocking reads afterwards
+fn open_input(path: &str) -> Result<File> {
+    if path.is_empty() {
+        return File::open(DEV_NULL).context("failed to open /dev/null");
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(OFlag::O_NONBLOCK.bits())
+        .open(path)
+        .with_context(|| format!("failed to open stdin {:?}", path))?;
+    fcntl::fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+    Ok(file)
+}
+
+// The writing end is opened for reading as well, so it neither blocks nor
+// fails if containerd has not opened the fifo yet
+fn open_output(path: &str) -> Result<File> {
+    let path = if path.is_empty() { DEV_NULL } else { path };
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {:?}", path))
+}
@@ -0,0 +1,16 @@
+use std::env;
+use std::path::PathBuf;
+
+// The header is generated into the output directory, so the build does not
+// modify the source tree. CI checks that the committed header in include/,
+// which integrators use without building the library, is the same.
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    match cbindgen::generate(&crate_dir) {
+        Ok(bindings) => {
+            bindings.write_to_file(out_dir.join("youki.h"));
+        }
+        Err(err) => println!("cargo:warning=failed to generate the C header: {}", err),
+    }
+}
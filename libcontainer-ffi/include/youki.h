@@ -0,0 +1,198 @@
+#ifndef YOUKI_H
+#define YOUKI_H
+
+/* Generated by cbindgen from libcontainer-ffi, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of a function of the C API, which tells the kind of failure like
+ * the errors of libcontainer
+ */
+typedef enum {
+  YOUKI_ERROR_OK = 0,
+  /**
+   * A null pointer or invalid UTF-8 has been passed, or a value is out of
+   * range
+   */
+  YOUKI_ERROR_INVALID_ARGUMENT,
+  /**
+   * The spec of the bundle is invalid or can not be fulfilled
+   */
+  YOUKI_ERROR_SPEC,
+  /**
+   * The caller is not allowed to perform the operation
+   */
+  YOUKI_ERROR_PERMISSION,
+  /**
+   * The kernel does not provide a feature which is needed by the container
+   */
+  YOUKI_ERROR_KERNEL_FEATURE_MISSING,
+  /**
+   * Any other failure, which may not happen again when retrying
+   */
+  YOUKI_ERROR_SYSTEM,
+  /**
+   * The status of the container does not allow the operation, e.g. the
+   * container is not running
+   */
+  YOUKI_ERROR_STATE,
+} YoukiError;
+
+/**
+ * Status of a container as defined by the OCI runtime spec
+ */
+typedef enum {
+  YOUKI_STATUS_CREATING,
+  YOUKI_STATUS_CREATED,
+  YOUKI_STATUS_RUNNING,
+  YOUKI_STATUS_STOPPED,
+  YOUKI_STATUS_PAUSED,
+} YoukiStatus;
+
+/**
+ * Handle of a container
+ */
+typedef struct YoukiContainer YoukiContainer;
+
+/**
+ * Options of the creation of a container. The paths are optional unless
+ * stated otherwise, NULL leaves them unset.
+ */
+typedef struct {
+  /**
+   * Directory of the state of all containers, e.g. /run/youki (required)
+   */
+  const char *root;
+  /**
+   * Id of the container (required)
+   */
+  const char *id;
+  /**
+   * Bundle which contains the config.json of the container (required)
+   */
+  const char *bundle;
+  /**
+   * File the pid of the container init process is written to
+   */
+  const char *pid_file;
+  /**
+   * Socket the pseudo terminal master is sent to, if the spec requests a
+   * terminal
+   */
+  const char *console_socket;
+  /**
+   * Manage the cgroup of the container through systemd
+   */
+  bool systemd;
+} YoukiCreateOptions;
+
+/**
+ * State of a container. The strings are only valid during the callback.
+ */
+typedef struct {
+  const char *id;
+  YoukiStatus status;
+  /**
+   * Pid of the container init process, 0 if it has not been created yet
+   */
+  int pid;
+  const char *bundle;
+} YoukiState;
+
+/**
+ * Receives the state of a container together with the data of the caller
+ */
+typedef void (*YoukiStateCallback)(const YoukiState *state, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a container from a bundle. The container process waits for the
+ * start of the container once it has been created.
+ *
+ * # Safety
+ *
+ * The options have to point to a valid struct whose strings are nul
+ * terminated, the handle is written to container.
+ */
+YoukiError youki_container_create(const YoukiCreateOptions *options, YoukiContainer **container);
+
+/**
+ * Loads a container which has been created before, e.g. by youki
+ *
+ * # Safety
+ *
+ * The root and the id have to be nul terminated strings, the handle is
+ * written to container.
+ */
+YoukiError youki_container_load(const char *root, const char *id, YoukiContainer **container);
+
+/**
+ * Starts a created container, which executes the process of the spec
+ *
+ * # Safety
+ *
+ * The container has to be a valid handle.
+ */
+YoukiError youki_container_start(YoukiContainer *container);
+
+/**
+ * Sends a signal to the container init process, or to all processes of the
+ * container if all is set
+ *
+ * # Safety
+ *
+ * The container has to be a valid handle.
+ */
+YoukiError youki_container_kill(YoukiContainer *container, int signal, bool all);
+
+/**
+ * Deletes a stopped container, or kills and deletes a running container if
+ * force is set. The handle has to be released afterwards.
+ *
+ * # Safety
+ *
+ * The container has to be a valid handle.
+ */
+YoukiError youki_container_delete(YoukiContainer *container, bool force);
+
+/**
+ * Passes the current state of the container to the callback, before the
+ * function returns
+ *
+ * # Safety
+ *
+ * The container has to be a valid handle.
+ */
+YoukiError youki_container_state(YoukiContainer *container,
+                                 YoukiStateCallback callback,
+                                 void *user_data);
+
+/**
+ * Releases the handle of a container. The container itself is not affected.
+ *
+ * # Safety
+ *
+ * The container has to be a valid handle or NULL, it must not be used
+ * afterwards.
+ */
+void youki_container_free(YoukiContainer *container);
+
+/**
+ * Returns the message of the last error which has happened on the calling
+ * thread, or NULL if no call has failed yet. The message is valid until the
+ * next call of the thread fails.
+ */
+const char *youki_last_error(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* YOUKI_H */
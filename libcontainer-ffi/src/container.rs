@@ -0,0 +1,367 @@
+//! Lifecycle operations of containers. A container is referred to by a handle,
+//! which is returned by [youki_container_create] or [youki_container_load] and
+//! has to be released with [youki_container_free].
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::PathBuf;
+use std::ptr;
+
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::{Container, ContainerStatus};
+use libcontainer::utils;
+use nix::sys::signal::Signal;
+
+use crate::error::{call, CallError, YoukiError};
+
+/// Handle of a container
+pub struct YoukiContainer {
+    container: Container,
+}
+
+/// Options of the creation of a container. The paths are optional unless
+/// stated otherwise, NULL leaves them unset.
+#[repr(C)]
+pub struct YoukiCreateOptions {
+    /// Directory of the state of all containers, e.g. /run/youki (required)
+    pub root: *const c_char,
+    /// Id of the container (required)
+    pub id: *const c_char,
+    /// Bundle which contains the config.json of the container (required)
+    pub bundle: *const c_char,
+    /// File the pid of the container init process is written to
+    pub pid_file: *const c_char,
+    /// Socket the pseudo terminal master is sent to, if the spec requests a
+    /// terminal
+    pub console_socket: *const c_char,
+    /// Manage the cgroup of the container through systemd
+    pub systemd: bool,
+}
+
+/// Status of a container as defined by the OCI runtime spec
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YoukiStatus {
+    Creating,
+    Created,
+    Running,
+    Stopped,
+    Paused,
+}
+
+impl From<ContainerStatus> for YoukiStatus {
+    fn from(status: ContainerStatus) -> Self {
+        match status {
+            ContainerStatus::Creating => Self::Creating,
+            ContainerStatus::Created => Self::Created,
+            ContainerStatus::Running => Self::Running,
+            ContainerStatus::Stopped => Self::Stopped,
+            ContainerStatus::Paused => Self::Paused,
+        }
+    }
+}
+
+/// State of a container. The strings are only valid during the callback.
+#[repr(C)]
+pub struct YoukiState {
+    pub id: *const c_char,
+    pub status: YoukiStatus,
+    /// Pid of the container init process, 0 if it has not been created yet
+    pub pid: c_int,
+    pub bundle: *const c_char,
+}
+
+/// Receives the state of a container together with the data of the caller
+pub type YoukiStateCallback =
+    Option<extern "C" fn(state: *const YoukiState, user_data: *mut c_void)>;
+
+/// Creates a container from a bundle. The container process waits for the
+/// start of the container once it has been created.
+///
+/// # Safety
+///
+/// The options have to point to a valid struct whose strings are nul
+/// terminated, the handle is written to container.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_create(
+    options: *const YoukiCreateOptions,
+    container: *mut *mut YoukiContainer,
+) -> YoukiError {
+    call(|| {
+        let options = options
+            .as_ref()
+            .ok_or_else(|| invalid_argument("options must not be NULL"))?;
+        let out = output(container)?;
+        let builder = ContainerBuilder::new(to_string(options.id, "id")?)
+            .with_root_path(to_path(options.root, "root")?)
+            .with_pid_file(to_optional_path(options.pid_file, "pid_file")?)
+            .with_console_socket(to_optional_path(options.console_socket, "console_socket")?)
+            .as_init(to_path(options.bundle, "bundle")?)
+            .with_systemd(options.systemd);
+        let created = builder.build()?;
+        *out = into_handle(created);
+        Ok(())
+    })
+}
+
+/// Loads a container which has been created before, e.g. by youki
+///
+/// # Safety
+///
+/// The root and the id have to be nul terminated strings, the handle is
+/// written to container.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_load(
+    root: *const c_char,
+    id: *const c_char,
+    container: *mut *mut YoukiContainer,
+) -> YoukiError {
+    call(|| {
+        let out = output(container)?;
+        let id = to_string(id, "id")?;
+        utils::validate_container_id(&id).map_err(|err| invalid_argument(err.to_string()))?;
+        let container_root = to_path(root, "root")?.join(id);
+        if !container_root.exists() {
+            return Err(invalid_argument(format!(
+                "container {} does not exist",
+                container_root.display()
+            )));
+        }
+
+        let loaded = Container::load(container_root)?;
+        *out = into_handle(loaded);
+        Ok(())
+    })
+}
+
+/// Starts a created container, which executes the process of the spec
+///
+/// # Safety
+///
+/// The container has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_start(container: *mut YoukiContainer) -> YoukiError {
+    call(|| {
+        let handle = handle(container)?;
+        handle.container = handle.container.start()?;
+        Ok(())
+    })
+}
+
+/// Sends a signal to the container init process, or to all processes of the
+/// container if all is set
+///
+/// # Safety
+///
+/// The container has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_kill(
+    container: *mut YoukiContainer,
+    signal: c_int,
+    all: bool,
+) -> YoukiError {
+    call(|| {
+        let handle = handle(container)?;
+        let signal = Signal::try_from(signal)
+            .map_err(|_| invalid_argument(format!("invalid signal {}", signal)))?;
+        handle.container = if all {
+            handle.container.kill_all(signal)?
+        } else {
+            handle.container.kill(signal)?
+        };
+        Ok(())
+    })
+}
+
+/// Deletes a stopped container, or kills and deletes a running container if
+/// force is set. The handle has to be released afterwards.
+///
+/// # Safety
+///
+/// The container has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_delete(
+    container: *mut YoukiContainer,
+    force: bool,
+) -> YoukiError {
+    call(|| {
+        let handle = handle(container)?;
+        handle.container.delete(force)?;
+        Ok(())
+    })
+}
+
+/// Passes the current state of the container to the callback, before the
+/// function returns
+///
+/// # Safety
+///
+/// The container has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_state(
+    container: *mut YoukiContainer,
+    callback: YoukiStateCallback,
+    user_data: *mut c_void,
+) -> YoukiError {
+    call(|| {
+        let handle = handle(container)?;
+        let callback = callback.ok_or_else(|| invalid_argument("callback must not be NULL"))?;
+        handle.container = Container::load(handle.container.root.clone())?.refresh_status()?;
+
+        let id = to_c_string(handle.container.id());
+        let bundle = to_c_string(&handle.container.bundle().to_string_lossy());
+        let state = YoukiState {
+            id: id.as_ptr(),
+            status: handle.container.status().into(),
+            pid: handle.container.pid().map_or(0, |pid| pid.as_raw()),
+            bundle: bundle.as_ptr(),
+        };
+        callback(&state, user_data);
+        Ok(())
+    })
+}
+
+/// Releases the handle of a container. The container itself is not affected.
+///
+/// # Safety
+///
+/// The container has to be a valid handle or NULL, it must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn youki_container_free(container: *mut YoukiContainer) {
+    if !container.is_null() {
+        drop(Box::from_raw(container));
+    }
+}
+
+fn into_handle(container: Container) -> *mut YoukiContainer {
+    Box::into_raw(Box::new(YoukiContainer { container }))
+}
+
+unsafe fn handle<'a>(container: *mut YoukiContainer) -> Result<&'a mut YoukiContainer, CallError> {
+    container
+        .as_mut()
+        .ok_or_else(|| invalid_argument("container must not be NULL"))
+}
+
+unsafe fn output<'a>(
+    container: *mut *mut YoukiContainer,
+) -> Result<&'a mut *mut YoukiContainer, CallError> {
+    let out = container
+        .as_mut()
+        .ok_or_else(|| invalid_argument("container must not be NULL"))?;
+    *out = ptr::null_mut();
+    Ok(out)
+}
+
+unsafe fn to_string(s: *const c_char, name: &str) -> Result<String, CallError> {
+    if s.is_null() {
+        return Err(invalid_argument(format!("{} must not be NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(str::to_owned)
+        .map_err(|_| invalid_argument(format!("{} is not valid UTF-8", name)))
+}
+
+unsafe fn to_path(s: *const c_char, name: &str) -> Result<PathBuf, CallError> {
+    to_string(s, name).map(PathBuf::from)
+}
+
+unsafe fn to_optional_path(s: *const c_char, name: &str) -> Result<Option<PathBuf>, CallError> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    to_path(s, name).map(Some)
+}
+
+// Strings of the container do not contain nul bytes, as they are paths or
+// have been validated
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+fn invalid_argument<M: Into<String>>(message: M) -> CallError {
+    CallError::InvalidArgument(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcontainer::utils::create_temp_dir;
+    use std::fs;
+
+    extern "C" fn record_state(state: *const YoukiState, user_data: *mut c_void) {
+        let state = unsafe { &*state };
+        let recorded = unsafe { &mut *(user_data as *mut Option<(String, YoukiStatus, c_int)>) };
+        let id = unsafe { CStr::from_ptr(state.id) };
+        *recorded = Some((id.to_str().unwrap().to_owned(), state.status, state.pid));
+    }
+
+    #[test]
+    fn test_load_and_state() {
+        let root = create_temp_dir("test_youki_ffi_load_and_state").unwrap();
+        fs::create_dir_all(root.join("test")).unwrap();
+        Container::new(
+            "test",
+            ContainerStatus::Created,
+            None,
+            &root,
+            &root.join("test"),
+        )
+        .unwrap()
+        .save()
+        .unwrap();
+
+        let c_root = CString::new(root.to_str().unwrap()).unwrap();
+        let id = CString::new("test").unwrap();
+        let mut container = ptr::null_mut();
+        unsafe {
+            let missing = CString::new("missing").unwrap();
+            assert_eq!(
+                youki_container_load(c_root.as_ptr(), missing.as_ptr(), &mut container),
+                YoukiError::InvalidArgument
+            );
+            assert!(container.is_null());
+
+            // the id must not be a path, even if it leads to a container
+            let outside = CString::new("../test_youki_ffi_load_and_state/test").unwrap();
+            assert_eq!(
+                youki_container_load(c_root.as_ptr(), outside.as_ptr(), &mut container),
+                YoukiError::InvalidArgument
+            );
+            assert!(container.is_null());
+
+            assert_eq!(
+                youki_container_load(c_root.as_ptr(), id.as_ptr(), &mut container),
+                YoukiError::Ok
+            );
+            let mut recorded: Option<(String, YoukiStatus, c_int)> = None;
+            assert_eq!(
+                youki_container_state(
+                    container,
+                    Some(record_state),
+                    &mut recorded as *mut _ as *mut c_void
+                ),
+                YoukiError::Ok
+            );
+            // the container has no process, so it is considered to be stopped
+            assert_eq!(recorded, Some(("test".to_owned(), YoukiStatus::Stopped, 0)));
+            youki_container_free(container);
+        }
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        unsafe {
+            assert_eq!(
+                youki_container_start(ptr::null_mut()),
+                YoukiError::InvalidArgument
+            );
+            assert_eq!(
+                youki_container_load(ptr::null(), ptr::null(), ptr::null_mut()),
+                YoukiError::InvalidArgument
+            );
+        }
+    }
+}
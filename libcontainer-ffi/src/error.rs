@@ -0,0 +1,111 @@
+//! Errors of the C API. Every function returns a [YoukiError], the message of
+//! the last error of the calling thread is kept until the next call fails, so
+//! callers can report it with [youki_last_error].
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use anyhow::anyhow;
+use libcontainer::error::ContainerError;
+
+/// Result of a function of the C API, which tells the kind of failure like
+/// the errors of libcontainer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YoukiError {
+    Ok = 0,
+    /// A null pointer or invalid UTF-8 has been passed, or a value is out of
+    /// range
+    InvalidArgument,
+    /// The spec of the bundle is invalid or can not be fulfilled
+    Spec,
+    /// The caller is not allowed to perform the operation
+    Permission,
+    /// The kernel does not provide a feature which is needed by the container
+    KernelFeatureMissing,
+    /// Any other failure, which may not happen again when retrying
+    System,
+    /// The status of the container does not allow the operation, e.g. the
+    /// container is not running
+    State,
+}
+
+// Failure of a call, which includes the misuse of the C API by the caller
+pub(crate) enum CallError {
+    InvalidArgument(String),
+    Container(ContainerError),
+}
+
+impl From<ContainerError> for CallError {
+    fn from(err: ContainerError) -> Self {
+        Self::Container(err)
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Returns the message of the last error which has happened on the calling
+/// thread, or NULL if no call has failed yet. The message is valid until the
+/// next call of the thread fails.
+#[no_mangle]
+pub extern "C" fn youki_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Runs a function of the C API. Panics must not unwind into C, they are
+/// reported as a failure of the system.
+pub(crate) fn call<F>(f: F) -> YoukiError
+where
+    F: FnOnce() -> Result<(), CallError>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        Err(CallError::Container(ContainerError::System(anyhow!(
+            "youki panicked"
+        ))))
+    });
+
+    let (code, message) = match result {
+        Ok(()) => return YoukiError::Ok,
+        Err(CallError::InvalidArgument(message)) => (YoukiError::InvalidArgument, message),
+        Err(CallError::Container(err)) => {
+            let code = match err {
+                ContainerError::Spec(_) => YoukiError::Spec,
+                ContainerError::Permission(_) => YoukiError::Permission,
+                ContainerError::KernelFeatureMissing(_) => YoukiError::KernelFeatureMissing,
+                ContainerError::System(_) => YoukiError::System,
+                ContainerError::State(_) => YoukiError::State,
+            };
+            (code, format!("{:#}", err))
+        }
+    };
+    // a message can not contain a nul byte, as it ends the string in C
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_call() {
+        assert_eq!(call(|| Ok(())), YoukiError::Ok);
+
+        let code = call(|| Err(ContainerError::spec("no root in spec").into()));
+        assert_eq!(code, YoukiError::Spec);
+        let message = unsafe { CStr::from_ptr(youki_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "no root in spec");
+
+        assert_eq!(call(|| panic!("bug")), YoukiError::System);
+    }
+}
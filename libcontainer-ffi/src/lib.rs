@@ -0,0 +1,28 @@
+//! C API of libcontainer, so programs which are not written in Rust can embed
+//! the container engine of youki instead of spawning the youki binary. The
+//! header is generated into include/youki.h by cbindgen.
+//!
+//! # Example
+//!
+//! ```c
+//! #include <signal.h>
+//! #include <stdio.h>
+//! #include "youki.h"
+//!
+//! YoukiCreateOptions options = {
+//!     .root = "/run/youki",
+//!     .id = "74f1a4cb3801",
+//!     .bundle = "/var/run/docker/bundle",
+//! };
+//! YoukiContainer *container;
+//! if (youki_container_create(&options, &container) != YOUKI_ERROR_OK) {
+//!     fprintf(stderr, "failed to create container: %s\n", youki_last_error());
+//!     return 1;
+//! }
+//! youki_container_start(container);
+//! youki_container_kill(container, SIGTERM, false);
+//! youki_container_delete(container, true);
+//! youki_container_free(container);
+//! ```
+pub mod container;
+pub mod error;
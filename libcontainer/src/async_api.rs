@@ -0,0 +1,390 @@
+//! Async variants of the lifecycle operations for embedders which run on
+//! tokio. The creation and the start of a container wait for the container
+//! processes, so they are run on the blocking pool of the runtime. Waiting for
+//! the exit of a container and for lifecycle events does not occupy a thread:
+//! the exit of a child is noticed by SIGCHLD, the exit of any other container
+//! and new events are noticed by inotify.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use libcontainer::async_api;
+//! use libcontainer::container::builder::ContainerBuilder;
+//!
+//! # async fn run() -> Result<(), libcontainer::error::ContainerError> {
+//! let builder = ContainerBuilder::new("74f1a4cb3801".to_owned())
+//!     .with_root_path("/run/youki")
+//!     .as_init("/var/run/docker/bundle");
+//! let container = async_api::create(builder).await?;
+//! let container = async_api::start(&container).await?;
+//! let exit_code = async_api::wait(&container).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::HashSet;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use cgroups::common;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::{self, Pid};
+use procfs::process::{ProcState, Process};
+use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task;
+use tokio::time;
+
+use crate::container::history::{HistoryEntry, LifecycleEvent};
+use crate::container::{init_builder::InitContainerBuilder, Container, ContainerLock};
+use crate::error::ContainerError;
+use crate::event_stream::{ContainerEvent, EventStream};
+use crate::supervisor;
+
+// Interval in which is checked if something happened that is not reported by
+// inotify, e.g. the exit of the init process while other processes are left
+// in the cgroup or an OOM kill, which is only recorded by the event stream
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Creates the container on the blocking pool of the runtime
+pub async fn create(builder: InitContainerBuilder) -> Result<Container, ContainerError> {
+    blocking(move || builder.build()).await
+}
+
+/// Starts the created container on the blocking pool of the runtime
+pub async fn start(container: &Container) -> Result<Container, ContainerError> {
+    let container = container.clone();
+    blocking(move || container.start()).await
+}
+
+/// Waits until the init process of the container has exited and saves that
+/// the container has stopped. The exit code is only known if the init process
+/// is a child of the caller, e.g. because the caller has become the subreaper
+/// with [supervisor::set_subreaper] before the container was created. The
+/// init process is reaped by this function in that case.
+pub async fn wait(container: &Container) -> Result<Option<i32>, ContainerError> {
+    let pid = container.pid().ok_or_else(|| {
+        ContainerError::state(format!("container {} is not running", container.id()))
+    })?;
+
+    let reaped = wait_child(pid).await?;
+    match reaped {
+        Some(exit_code) => {
+            // the exit code can not be read anymore once the process has been
+            // reaped, so the stop is recorded here
+            container.record_event(
+                HistoryEntry::new(LifecycleEvent::Stopped)
+                    .with_pid(Some(pid))
+                    .with_exit_code(Some(exit_code)),
+            );
+        }
+        None => wait_exit(container, pid).await?,
+    }
+
+    // records the stop, if it has not been recorded, and reads the exit code
+    // of a zombie
+    let root = container.root.clone();
+    let stopped = blocking(move || {
+        let _lock = ContainerLock::acquire(&root)?;
+        let stopped = Container::load(root.clone())?.refresh_status()?;
+        stopped.save()?;
+        Ok(stopped)
+    })
+    .await?;
+    let exit_code = reaped.or_else(|| {
+        stopped
+            .history()
+            .ok()
+            .and_then(|history| history.last().and_then(|entry| entry.exit_code))
+    });
+    Ok(exit_code)
+}
+
+// Reaps the process if it is a child of the caller, returns None otherwise
+async fn wait_child(pid: Pid) -> Result<Option<i32>> {
+    // registered before the first check, so an exit in between is not missed
+    let mut sigchld = signal(SignalKind::child()).context("failed to handle SIGCHLD")?;
+    loop {
+        match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+            Ok(status) => {
+                if let Some((_, exit_code)) = supervisor::exit_code(status) {
+                    log::debug!("container init process exited with {}", exit_code);
+                    return Ok(Some(exit_code));
+                }
+            }
+            Err(Errno::ECHILD) => return Ok(None),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to wait for {}", pid)),
+        }
+        sigchld.recv().await;
+    }
+}
+
+// Waits for the exit of a process which is not a child, so it can not be
+// reaped. The cgroup of the container notifies when its last process has
+// exited on cgroup v2.
+async fn wait_exit(container: &Container, pid: Pid) -> Result<()> {
+    let watcher = match cgroup_events(container, pid) {
+        Some(cgroup_events) => {
+            let watcher = Watcher::new()?;
+            watcher.add_watch(&cgroup_events, AddWatchFlags::IN_MODIFY)?;
+            Some(watcher)
+        }
+        None => None,
+    };
+
+    while is_alive(pid) {
+        match &watcher {
+            Some(watcher) => {
+                let _ = time::timeout(POLL_INTERVAL, watcher.changed()).await;
+            }
+            None => time::sleep(POLL_INTERVAL).await,
+        }
+    }
+
+    Ok(())
+}
+
+fn is_alive(pid: Pid) -> bool {
+    match Process::new(pid.as_raw()) {
+        Ok(process) => !matches!(
+            process.stat.state(),
+            Ok(ProcState::Zombie) | Ok(ProcState::Dead)
+        ),
+        Err(_) => false,
+    }
+}
+
+// The cgroup.events file of the cgroup v2 which contains the process
+fn cgroup_events(container: &Container, pid: Pid) -> Option<PathBuf> {
+    let cgroup_root = match container.cgroup_root() {
+        Some(cgroup_root) => cgroup_root.to_path_buf(),
+        None => common::detect_cgroup_root().ok()?,
+    };
+    let cgroups = Process::new(pid.as_raw()).ok()?.cgroups().ok()?;
+    let cgroup = cgroups.iter().find(|cgroup| cgroup.hierarchy == 0)?;
+    let path = cgroup_root
+        .join(cgroup.pathname.trim_start_matches('/'))
+        .join("cgroup.events");
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Stream of the lifecycle events of all containers in the root directory,
+/// like [EventStream], which waits for new events instead of being polled
+pub struct AsyncEventStream {
+    root_path: PathBuf,
+    stream: EventStream,
+    watcher: Watcher,
+    // Container directories which are watched for changes of the history
+    watched: HashSet<PathBuf>,
+    pending: Vec<ContainerEvent>,
+}
+
+impl AsyncEventStream {
+    /// Starts to watch the containers in the root directory. Only events which
+    /// happen from now on are reported.
+    pub fn new<P: Into<PathBuf>>(root_path: P) -> Result<Self> {
+        let root_path = root_path.into();
+        let watcher = Watcher::new()?;
+        watcher.add_watch(
+            &root_path,
+            AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_TO,
+        )?;
+        let mut stream = Self {
+            stream: EventStream::new(&root_path)?,
+            root_path,
+            watcher,
+            watched: HashSet::new(),
+            pending: Vec::new(),
+        };
+        stream.watch_containers()?;
+
+        Ok(stream)
+    }
+
+    /// Waits for the next event of any container
+    pub async fn next(&mut self) -> Result<ContainerEvent> {
+        loop {
+            if !self.pending.is_empty() {
+                return Ok(self.pending.remove(0));
+            }
+
+            self.watch_containers()?;
+            self.pending = self.stream.poll()?;
+            if self.pending.is_empty() {
+                let _ = time::timeout(POLL_INTERVAL, self.watcher.changed()).await;
+            }
+        }
+    }
+
+    // Watches the directories of containers which have been created since
+    // the last call. The watch of a removed directory is removed by inotify.
+    fn watch_containers(&mut self) -> Result<()> {
+        self.watched.retain(|dir| dir.exists());
+        for entry in std::fs::read_dir(&self.root_path)
+            .with_context(|| format!("failed to read root directory {:?}", self.root_path))?
+        {
+            let container_root = entry?.path();
+            if !container_root.is_dir() || self.watched.contains(&container_root) {
+                continue;
+            }
+            // the container may be deleted in the meantime
+            if self
+                .watcher
+                .add_watch(
+                    &container_root,
+                    AddWatchFlags::IN_MODIFY
+                        | AddWatchFlags::IN_CREATE
+                        | AddWatchFlags::IN_MOVED_TO,
+                )
+                .is_ok()
+            {
+                self.watched.insert(container_root);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Inotify instance whose events are awaited on the runtime
+struct Watcher {
+    inotify: AsyncFd<OwnedInotify>,
+}
+
+impl Watcher {
+    fn new() -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)
+            .context("failed to initialize inotify")?;
+        let inotify = AsyncFd::new(OwnedInotify(inotify)).context("failed to register inotify")?;
+        Ok(Self { inotify })
+    }
+
+    fn add_watch(&self, path: &Path, flags: AddWatchFlags) -> Result<()> {
+        self.inotify
+            .get_ref()
+            .0
+            .add_watch(path, flags)
+            .with_context(|| format!("failed to watch {:?}", path))?;
+        Ok(())
+    }
+
+    // Waits until a watched file has changed
+    async fn changed(&self) -> Result<()> {
+        loop {
+            let mut guard = self.inotify.readable().await?;
+            match guard.try_io(|inotify| Ok(inotify.get_ref().0.read_events()?)) {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(err)) => return Err(err).context("failed to read inotify events"),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// The inotify instance of nix does not close its descriptor. It is closed when
+// AsyncFd drops its inner value, after the descriptor has been deregistered.
+struct OwnedInotify(Inotify);
+
+impl AsRawFd for OwnedInotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Drop for OwnedInotify {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.0.as_raw_fd());
+    }
+}
+
+async fn blocking<F, T>(f: F) -> Result<T, ContainerError>
+where
+    F: FnOnce() -> Result<T, ContainerError> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(f)
+        .await
+        .map_err(|err| ContainerError::System(err.into()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::history;
+    use crate::container::{state::State, ContainerStatus};
+    use crate::utils::create_temp_dir;
+    use nix::unistd::ForkResult;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_wait_child() -> Result<()> {
+        let root = create_temp_dir("test_async_wait_child")?;
+        let child = match unsafe { unistd::fork()? } {
+            ForkResult::Child => {
+                std::thread::sleep(Duration::from_millis(100));
+                unsafe { libc::_exit(3) }
+            }
+            ForkResult::Parent { child } => child,
+        };
+        let container = Container::new(
+            "test",
+            ContainerStatus::Running,
+            Some(child.as_raw()),
+            &root,
+            &root,
+        )?;
+        container.save()?;
+
+        let exit_code = time::timeout(Duration::from_secs(5), wait(&container)).await??;
+        assert_eq!(exit_code, Some(3));
+        let last = history::load(&root)?.pop().unwrap();
+        assert_eq!(last.event, LifecycleEvent::Stopped);
+        assert_eq!(last.exit_code, Some(3));
+        assert_eq!(State::load(&root)?.status, ContainerStatus::Stopped);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_no_pid() -> Result<()> {
+        let root = create_temp_dir("test_async_wait_no_pid")?;
+        let container = Container::new("test", ContainerStatus::Created, None, &root, &root)?;
+        let err = wait(&container).await.unwrap_err();
+        assert!(matches!(err, ContainerError::State(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_event_stream() -> Result<()> {
+        let root = create_temp_dir("test_async_event_stream")?;
+        let mut stream = AsyncEventStream::new(&*root)?;
+
+        let container_root = root.join("new");
+        fs::create_dir(&container_root)?;
+        fs::write(State::file_path(&container_root), "")?;
+        let created = HistoryEntry::new(LifecycleEvent::Created);
+        history::record(&container_root, &created)?;
+
+        let event = time::timeout(Duration::from_millis(500), stream.next()).await??;
+        assert_eq!(
+            event,
+            ContainerEvent {
+                id: "new".to_owned(),
+                entry: created,
+            }
+        );
+
+        let started = HistoryEntry::new(LifecycleEvent::Started);
+        history::record(&container_root, &started)?;
+        let event = time::timeout(Duration::from_millis(500), stream.next()).await??;
+        assert_eq!(event.entry, started);
+        Ok(())
+    }
+}
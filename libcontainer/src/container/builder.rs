@@ -1,4 +1,6 @@
 use crate::syscall::linux::LinuxSyscall;
+use std::env;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 use super::{init_builder::InitContainerBuilder, tenant_builder::TenantContainerBuilder};
@@ -16,6 +18,8 @@ pub struct ContainerBuilder {
     pub(super) console_socket: Option<PathBuf>,
     /// File descriptors to be passed into the container process
     pub(super) preserve_fds: i32,
+    /// Files which become stdin, stdout and stderr of the container process
+    pub(super) stdio: Option<[RawFd; 3]>,
 }
 
 /// Builder that can be used to configure the common properties of
@@ -24,7 +28,7 @@ pub struct ContainerBuilder {
 /// # Example
 ///
 /// ```no_run
-/// use youki::container::builder::ContainerBuilder;
+/// use libcontainer::container::builder::ContainerBuilder;
 ///
 /// ContainerBuilder::new("74f1a4cb3801".to_owned())
 /// .with_root_path("/run/containers/youki")
@@ -40,7 +44,7 @@ impl ContainerBuilder {
     /// # Example
     ///
     /// ```no_run
-    /// use youki::container::builder::ContainerBuilder;
+    /// use libcontainer::container::builder::ContainerBuilder;
     ///
     /// let builder = ContainerBuilder::new("74f1a4cb3801".to_owned());
     /// ```
@@ -54,6 +58,7 @@ impl ContainerBuilder {
             pid_file: None,
             console_socket: None,
             preserve_fds: 0,
+            stdio: None,
         }
     }
 
@@ -61,7 +66,7 @@ impl ContainerBuilder {
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .as_tenant()
@@ -77,7 +82,7 @@ impl ContainerBuilder {
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .as_init("/var/run/docker/bundle")
@@ -93,7 +98,7 @@ impl ContainerBuilder {
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .with_root_path("/run/containers/youki");
@@ -104,32 +109,33 @@ impl ContainerBuilder {
     }
 
     /// Sets the pid file which will be used to write the pid of the container
-    /// process
+    /// process. A relative path is resolved against the current directory.
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .with_pid_file(Some("/var/run/docker.pid"));
     /// ```
     pub fn with_pid_file<P: Into<PathBuf>>(mut self, path: Option<P>) -> Self {
-        self.pid_file = path.map(|p| p.into());
+        self.pid_file = path.map(|p| absolute(p.into()));
         self
     }
 
     /// Sets the console socket, which will be used to send the file descriptor
-    /// of the pseudoterminal
+    /// of the pseudoterminal. A relative path is resolved against the current
+    /// directory.
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .with_console_socket(Some("/var/run/docker/sock.tty"));
     /// ```
     pub fn with_console_socket<P: Into<PathBuf>>(mut self, path: Option<P>) -> Self {
-        self.console_socket = path.map(|p| p.into());
+        self.console_socket = path.map(|p| absolute(p.into()));
         self
     }
 
@@ -138,7 +144,7 @@ impl ContainerBuilder {
     /// # Example
     ///
     /// ```no_run
-    /// # use youki::container::builder::ContainerBuilder;
+    /// # use libcontainer::container::builder::ContainerBuilder;
     ///
     /// ContainerBuilder::new("74f1a4cb3801".to_owned())
     /// .with_preserved_fds(5);
@@ -147,4 +153,57 @@ impl ContainerBuilder {
         self.preserve_fds = preserved_fds;
         self
     }
+
+    /// Sets the files which become stdin, stdout and stderr of the container
+    /// process, so the standard streams of the caller are left alone. They
+    /// have to stay open until the container has been built. Without them the
+    /// container process inherits the standard streams of the caller.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use std::fs::File;
+    /// # use std::os::unix::io::AsRawFd;
+    ///
+    /// let null = File::open("/dev/null")?;
+    /// let log = File::create("/var/log/74f1a4cb3801.log")?;
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned())
+    /// .with_stdio(Some([null.as_raw_fd(), log.as_raw_fd(), log.as_raw_fd()]));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn with_stdio(mut self, stdio: Option<[RawFd; 3]>) -> Self {
+        self.stdio = stdio;
+        self
+    }
+}
+
+// The builders change into the container directory before the paths which
+// have been passed by the caller are used
+fn absolute(path: PathBuf) -> PathBuf {
+    if path.is_relative() {
+        if let Ok(cwd) = env::current_dir() {
+            return cwd.join(path);
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_paths() {
+        let builder = ContainerBuilder::new("74f1a4cb3801".to_owned())
+            .with_pid_file(Some("container.pid"))
+            .with_console_socket(Some("/run/conmon/console.sock"));
+        assert_eq!(
+            builder.pid_file,
+            Some(env::current_dir().unwrap().join("container.pid"))
+        );
+        assert_eq!(
+            builder.console_socket,
+            Some(PathBuf::from("/run/conmon/console.sock"))
+        );
+    }
 }
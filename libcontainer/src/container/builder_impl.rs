@@ -0,0 +1,396 @@
+use crate::{
+    exec_fifo::ExecFifo,
+    hooks,
+    idmap::{IdMappedMounts, MountIdMappings},
+    intel_rdt::IntelRdt,
+    nested::{self, NestedEnvironment},
+    network_setup::NetworkSetup,
+    notify_socket::NotifyListener,
+    process::{
+        channel, cpu_affinity::ExecCpuAffinity, fork, init, io_priority::IoPriority,
+        scheduler::Scheduler, user::UserIds,
+    },
+    rootless::{self, Rootless},
+    syscall::linux::LinuxSyscall,
+    trace, utils,
+};
+use anyhow::{Context, Result};
+use cgroups::{self, common::ManagerOptions, systemd_unit::UnitProperty};
+use nix::unistd::Pid;
+use oci_spec::Spec;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    io::Write,
+    os::unix::prelude::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use super::history::{HistoryEntry, LifecycleEvent};
+use super::{Container, ContainerStatus};
+
+pub(super) struct ContainerBuilderImpl<'a> {
+    /// Flag indicating if an init or a tenant container should be created
+    pub init: bool,
+    /// Interface to operating system primitives
+    pub syscall: LinuxSyscall,
+    /// Flag indicating if systemd should be used for cgroup management
+    pub use_systemd: bool,
+    /// Mount point of the cgroup root, detected if not set
+    pub cgroup_root: Option<PathBuf>,
+    /// Id of the container
+    pub container_id: String,
+    /// OCI complient runtime spec
+    pub spec: &'a Spec,
+    /// Root filesystem of the container
+    pub rootfs: PathBuf,
+    /// File which will be used to communicate the pid of the
+    /// container process to the higher level runtime
+    pub pid_file: Option<PathBuf>,
+    /// Socket to communicate the file descriptor of the ptty
+    pub console_socket: Option<RawFd>,
+    /// Options for rootless containers
+    pub rootless: Option<Rootless<'a>>,
+    /// Path to the Unix Domain Socket to communicate the start of a tenant process
+    pub notify_path: Option<PathBuf>,
+    /// Fifo which blocks the init process until the container is started
+    pub exec_fifo: Option<PathBuf>,
+    /// Container state
+    pub container: Option<Container>,
+    /// File descriptos preserved/passed to the container init process.
+    pub preserve_fds: i32,
+    /// Files which become stdin, stdout and stderr of the container process
+    pub stdio: Option<[RawFd; 3]>,
+    /// Use MS_MOVE and chroot instead of pivot_root to enter the rootfs
+    pub no_pivot: bool,
+    /// Fail if resources are not supported by the host or can not be applied
+    /// to a rootless container
+    pub strict_resources: bool,
+    /// Scheduling policy of the container process
+    pub scheduler: Option<Scheduler>,
+    /// IO priority of the container process
+    pub io_priority: Option<IoPriority>,
+    /// CPU affinity of the container process before and after exec
+    pub exec_cpu_affinity: Option<ExecCpuAffinity>,
+    /// Ids of the user of the container process as given in the spec
+    pub user_ids: UserIds,
+    /// Intel RDT configuration of the container
+    pub intel_rdt: Option<IntelRdt>,
+    /// Id mappings of the mounts, keyed by their destination
+    pub mount_id_mappings: HashMap<PathBuf, MountIdMappings>,
+    /// Binary which sets up the network of the container
+    pub network_setup: Option<NetworkSetup>,
+}
+
+impl<'a> ContainerBuilderImpl<'a> {
+    /// Creates the container process and returns its pid
+    pub(super) fn create(&mut self) -> Result<Pid> {
+        let init_pid = self.run_container()?;
+
+        Ok(init_pid)
+    }
+
+    fn run_container(&mut self) -> Result<Pid> {
+        let linux = self.spec.linux.as_ref().context("no linux in spec")?;
+        let cgroups_path =
+            utils::get_cgroup_path(&linux.cgroups_path, &self.container_id, self.use_systemd);
+        // the properties are only of interest to systemd
+        let unit_properties = if self.use_systemd {
+            UnitProperty::from_annotations(self.spec.annotations.as_ref())?
+        } else {
+            Vec::new()
+        };
+        let options = ManagerOptions {
+            unit_properties,
+            strict_resources: self.strict_resources,
+            skip_devices: nested::skip_devices(self.spec.annotations.as_ref())?,
+        };
+        let cmanager = cgroups::common::create_cgroup_manager_with_options(
+            self.cgroup_root.as_deref(),
+            &cgroups_path,
+            self.use_systemd,
+            options,
+        )?;
+        let process = self.spec.process.as_ref().context("No process in spec")?;
+        // Detect the environment before any namespace is entered, so the
+        // container processes inherit what is true for the runtime process.
+        let nested = NestedEnvironment::detect();
+        if nested.is_nested() {
+            log::info!(
+                "youki is running inside of a {} container",
+                nested.container.as_deref().unwrap_or_default()
+            );
+        }
+
+        if self.init {
+            if let Some(hooks) = self.spec.hooks.as_ref() {
+                hooks::run_hooks(hooks.create_runtime.as_ref(), self.container.as_ref())?
+            }
+        }
+
+        // We use a set of channels to communicate between parent and child process. Each channel is uni-directional.
+        let (sender_to_intermediate, receiver_from_main) = &mut channel::main_to_intermediate()?;
+        let (sender_to_main, receiver_from_intermediate) = &mut channel::intermediate_to_main()?;
+
+        // Need to create the notify socket and open the exec fifo before we
+        // pivot root, since they are outside of the rootfs of container. During
+        // exec, need to create the socket before we enter into existing mount
+        // namespace.
+        let notify_socket = self
+            .notify_path
+            .as_ref()
+            .map(|path| NotifyListener::new(path))
+            .transpose()?;
+        let exec_fifo = self
+            .exec_fifo
+            .as_ref()
+            .map(|path| ExecFifo::open(path))
+            .transpose()?;
+
+        // If Out-of-memory score adjustment is set in specification.  set the score
+        // value for the current process check
+        // https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for some more
+        // information.
+        //
+        // This has to be done before !dumpable because /proc/self/oom_score_adj
+        // is not writeable unless you're an privileged user (if !dumpable is
+        // set). All children inherit their parent's oom_score_adj value on
+        // fork(2) so this will always be propagated properly.
+        if let Some(oom_score_adj) = process.oom_score_adj {
+            log::debug!("Set OOM score to {}", oom_score_adj);
+            let mut f = fs::File::create("/proc/self/oom_score_adj")?;
+            f.write_all(oom_score_adj.to_string().as_bytes())?;
+        }
+
+        // Make the process non-dumpable, to avoid various race conditions that
+        // could cause processes in namespaces we're joining to access host
+        // resources (or potentially execute code).
+        //
+        // However, if the number of namespaces we are joining is 0, we are not
+        // going to be switching to a different security context. Thus setting
+        // ourselves to be non-dumpable only breaks things (like rootless
+        // containers), which is the recommendation from the kernel folks.
+        if linux.namespaces.is_some() {
+            prctl::set_dumpable(false).unwrap();
+        }
+
+        // Id-mapped mounts have to be created by the runtime process, since
+        // the container process is not privileged over the mount sources.
+        let mut idmapped_mounts = if self.init {
+            IdMappedMounts::prepare(self.spec, self.rootless.as_ref(), &self.mount_id_mappings)?
+        } else {
+            IdMappedMounts::default()
+        };
+
+        // Root of a user namespace can only use the cgroups which have been
+        // delegated to it, just like an unprivileged user
+        let rootless_cgroup = self.rootless.is_some() || rootless::in_user_namespace();
+        // The cgroup is bound into the rootfs for mounts of type cgroup, so it
+        // has to exist before the container process is moved into it
+        let has_cgroup_mount = self.spec.mounts.as_ref().map_or(false, |mounts| {
+            mounts.iter().any(|m| m.typ.as_deref() == Some("cgroup"))
+        });
+        let cgroup_hierarchies = if self.init && has_cgroup_mount && !rootless_cgroup {
+            let hierarchies = cmanager
+                .hierarchies()
+                .context("Failed to get cgroup hierarchies")?;
+            for hierarchy in &hierarchies {
+                utils::create_dir_all(&hierarchy.path)?;
+            }
+            hierarchies
+        } else {
+            Vec::new()
+        };
+
+        // A container manager commonly mounts the cgroup filesystem read-only
+        // for its containers, in which case the resource limits can only be
+        // enforced by the outer container.
+        let cgroup_readonly =
+            nested.is_nested() && nested.cgroup_readonly(self.cgroup_root.as_deref());
+        let join_cgroup = !cgroup_readonly && linux.resources.is_some() && self.init;
+        // The init process is created in its cgroup if possible, otherwise it
+        // is moved into the cgroup once it has been created
+        let cgroup_dir = if join_cgroup && !rootless_cgroup {
+            cmanager.open_cgroup_dir().unwrap_or_else(|err| {
+                log::debug!("failed to open the cgroup of the container: {:?}", err);
+                None
+            })
+        } else {
+            None
+        };
+
+        // This init_args will be passed to the container init process. The
+        // spec, rootfs and container state are only borrowed, since fork
+        // duplicates the address space of the parent anyway, which avoids
+        // copying them for every container process.
+        let init_args = init::ContainerInitArgs {
+            init: self.init,
+            syscall: self.syscall.clone(),
+            spec: self.spec,
+            rootfs: &self.rootfs,
+            console_socket: self.console_socket,
+            notify_socket,
+            exec_fifo,
+            preserve_fds: self.preserve_fds,
+            stdio: self.stdio,
+            container: self.container.as_ref(),
+            rootless: self.rootless.clone(),
+            no_pivot: self.no_pivot,
+            scheduler: self.scheduler.clone(),
+            io_priority: self.io_priority,
+            exec_cpu_affinity: self.exec_cpu_affinity.clone(),
+            user_ids: self.user_ids,
+            idmapped_mounts: idmapped_mounts.clone(),
+            cgroup_hierarchies,
+            cgroup_fd: cgroup_dir.as_ref().map(|dir| dir.as_raw_fd()),
+        };
+        let phase = trace::phase("fork");
+        let intermediate_pid = fork::container_fork(|| {
+            // The fds in the pipe is duplicated during fork, so we first close
+            // the unused fds. Note, this already runs in the child process.
+            sender_to_intermediate
+                .close()
+                .context("Failed to close unused sender")?;
+            receiver_from_intermediate
+                .close()
+                .context("Failed to close unused receiver")?;
+
+            init::container_intermediate(init_args, receiver_from_main, sender_to_main)
+        })?;
+        drop(phase);
+        // Close down unused fds. The corresponding fds are duplicated to the
+        // child process during fork.
+        receiver_from_main
+            .close()
+            .context("Failed to close parent to child receiver")?;
+        sender_to_main
+            .close()
+            .context("Failed to close child to parent sender")?;
+        // The pty master is sent over the connection by the container process,
+        // the caller which listens on the console socket sees the connection
+        // closed once the container process has sent it
+        if let Some(console_socket) = self.console_socket {
+            nix::unistd::close(console_socket).context("Failed to close console socket")?;
+        }
+        // The detached mount trees have been inherited by the container
+        // process and are not needed in the main process anymore.
+        idmapped_mounts
+            .close()
+            .context("Failed to close idmapped mounts")?;
+        drop(cgroup_dir);
+
+        // If creating a rootless container, the intermediate process will ask
+        // the main process to set up uid and gid mapping, once the intermediate
+        // process enters into a new user namespace.
+        if self.rootless.is_some() {
+            receiver_from_intermediate.wait_for_mapping_request()?;
+            log::debug!("write mapping for pid {:?}", intermediate_pid);
+            let rootless = self.rootless.as_ref().unwrap();
+            if !rootless.privileged {
+                // The main process is running as an unprivileged user and cannot write the mapping
+                // until "deny" has been written to setgroups. See CVE-2014-8989.
+                utils::write_file(format!("/proc/{}/setgroups", intermediate_pid), "deny")?;
+            }
+            rootless::write_uid_mapping(intermediate_pid, self.rootless.as_ref())?;
+            rootless::write_gid_mapping(intermediate_pid, self.rootless.as_ref())?;
+            sender_to_intermediate.mapping_written()?;
+        }
+
+        let init_pid = receiver_from_intermediate.wait_for_intermediate_ready()?;
+        log::debug!("init pid is {:?}", init_pid);
+
+        if cgroup_readonly && linux.resources.is_some() && self.init {
+            log::warn!("cgroup filesystem is read-only in the nested container, resource limits are not applied");
+        }
+
+        if join_cgroup {
+            let _phase = trace::phase("cgroups");
+            let resources = linux.resources.as_ref().unwrap();
+            if rootless_cgroup {
+                rootless::apply_cgroup(
+                    cmanager.as_ref(),
+                    resources,
+                    init_pid,
+                    self.strict_resources,
+                )?;
+            } else {
+                // moving a process into the cgroup it is already in is a no-op
+                cmanager
+                    .add_task(init_pid)
+                    .context("Failed to add tasks to cgroup manager")?;
+
+                cmanager
+                    .apply(resources)
+                    .context("Failed to apply resource limits through cgroup")?;
+            }
+        }
+
+        // the resctrl group is joined by exec'd processes as well, so they
+        // are subject to the same cache and memory bandwidth allocation
+        if let Some(intel_rdt) = &self.intel_rdt {
+            intel_rdt
+                .apply(&self.container_id, init_pid)
+                .context("Failed to apply intel rdt")?;
+        }
+
+        // the network is ready before the container is started
+        if let Some(network_setup) = &self.network_setup {
+            network_setup.setup(&self.container_id, init_pid, self.spec)?;
+        }
+
+        if let Some(container) = &self.container {
+            // update status and pid of the container process
+            container
+                .transition(ContainerStatus::Created)?
+                .set_creator(nix::unistd::geteuid().as_raw())
+                .set_pid(init_pid.as_raw())
+                .save()
+                .context("Failed to save container state")?;
+            container
+                .record_event(HistoryEntry::new(LifecycleEvent::Created).with_pid(Some(init_pid)));
+        }
+
+        // The pid file is written last, as callers like conmon take it as the
+        // sign that the container has been created and query its state then
+        if let Some(pid_file) = &self.pid_file {
+            write_pid_file(pid_file, init_pid).context("Failed to write pid file")?;
+        }
+
+        Ok(init_pid)
+    }
+}
+
+// The pid file is replaced atomically, so a caller which watches it never
+// reads a partially written pid
+fn write_pid_file(path: &Path, pid: Pid) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("invalid pid file {:?}", path))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    let temp_path = path.with_file_name(temp_name);
+    fs::write(&temp_path, pid.to_string())
+        .with_context(|| format!("failed to write {:?}", temp_path))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("failed to rename {:?} to {:?}", temp_path, path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_write_pid_file() -> Result<()> {
+        let tmp = create_temp_dir("test_write_pid_file")?;
+        let pid_file = tmp.path().join("container.pid");
+        fs::write(&pid_file, "1")?;
+
+        write_pid_file(&pid_file, Pid::from_raw(4242))?;
+        assert_eq!(fs::read_to_string(&pid_file)?, "4242");
+        assert!(!tmp.path().join(".container.pid").exists());
+        Ok(())
+    }
+}
@@ -8,12 +8,17 @@ use chrono::DateTime;
 use nix::unistd::Pid;
 
 use chrono::Utc;
-use oci_spec::Spec;
+use oci_spec::{LinuxResources, Spec};
 use procfs::process::Process;
 
+use crate::intel_rdt::IntelRdt;
+use crate::process::user::UserIds;
 use crate::syscall::syscall::create_syscall;
+use crate::utils;
 
-use crate::container::{ContainerStatus, State};
+use crate::container::history::{self, HistoryEntry, LifecycleEvent};
+use crate::container::{ContainerAction, ContainerLock, ContainerStatus, State};
+use crate::error::ContainerError;
 
 /// Structure representing the container data
 #[derive(Debug, Clone)]
@@ -57,7 +62,9 @@ impl Container {
         self.state.status
     }
 
-    pub fn refresh_status(&mut self) -> Result<Self> {
+    pub fn refresh_status(&mut self) -> Result<Self, ContainerError> {
+        // the exit code can only be read as long as the process is a zombie
+        let mut exit_code = None;
         let new_status = match self.pid() {
             Some(pid) => {
                 // Note that Process::new does not spawn a new process
@@ -66,7 +73,10 @@ impl Container {
                 if let Ok(proc) = Process::new(pid.as_raw()) {
                     use procfs::process::ProcState;
                     match proc.stat.state().unwrap() {
-                        ProcState::Zombie | ProcState::Dead => ContainerStatus::Stopped,
+                        ProcState::Zombie | ProcState::Dead => {
+                            exit_code = proc.stat.exit_code.map(exit_status_code);
+                            ContainerStatus::Stopped
+                        }
                         _ => match self.status() {
                             ContainerStatus::Creating
                             | ContainerStatus::Created
@@ -80,9 +90,52 @@ impl Container {
             }
             None => ContainerStatus::Stopped,
         };
+
+        if new_status == ContainerStatus::Stopped && self.status() != ContainerStatus::Stopped {
+            self.record_stopped(exit_code);
+        }
         Ok(self.update_status(new_status))
     }
 
+    // The state is not saved when the status is refreshed, so every command
+    // would notice that the container has stopped
+    fn record_stopped(&self, exit_code: Option<i32>) {
+        // hold the lock between the check and the append, so the event is
+        // recorded once when several commands notice the stop concurrently
+        let _lock = match ContainerLock::acquire(&self.root) {
+            Ok(lock) => lock,
+            Err(err) => {
+                log::warn!("failed to record Stopped event: {:?}", err);
+                return;
+            }
+        };
+        let already_recorded = history::load(&self.root)
+            .map(|history| matches!(history.last(), Some(e) if e.event == LifecycleEvent::Stopped))
+            .unwrap_or(false);
+        if !already_recorded {
+            self.record_event(
+                HistoryEntry::new(LifecycleEvent::Stopped)
+                    .with_pid(self.pid())
+                    .with_exit_code(exit_code),
+            );
+        }
+    }
+
+    /// Appends the event to the history of the container. The history is
+    /// only kept for debugging, so a failure is not fatal.
+    pub fn record_event(&self, entry: HistoryEntry) {
+        let result = ContainerLock::acquire(&self.root)
+            .and_then(|_lock| history::record(&self.root, &entry));
+        if let Err(err) = result {
+            log::warn!("failed to record {:?} event: {:?}", entry.event, err);
+        }
+    }
+
+    /// Returns the lifecycle events of the container, oldest event first
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        history::load(&self.root)
+    }
+
     pub fn refresh_state(&self) -> Result<Self> {
         let state = State::load(&self.root)?;
         Ok(Self {
@@ -109,7 +162,7 @@ impl Container {
     }
 
     pub fn can_exec(&self) -> bool {
-        self.state.status == ContainerStatus::Running
+        self.state.status.can_exec()
     }
 
     pub fn can_pause(&self) -> bool {
@@ -120,6 +173,10 @@ impl Container {
         self.state.status.can_resume()
     }
 
+    pub fn can_update(&self) -> bool {
+        self.state.status.can_update()
+    }
+
     pub fn pid(&self) -> Option<Pid> {
         self.state.pid.map(Pid::from_raw)
     }
@@ -164,6 +221,36 @@ impl Container {
         self
     }
 
+    pub fn set_cgroup_root(mut self, cgroup_root: Option<PathBuf>) -> Self {
+        self.state.cgroup_root = cgroup_root;
+        self
+    }
+
+    pub fn set_notify_socket(mut self, notify_socket: Option<PathBuf>) -> Self {
+        self.state.notify_socket = notify_socket;
+        self
+    }
+
+    pub fn set_intel_rdt(mut self, intel_rdt: Option<IntelRdt>) -> Self {
+        self.state.intel_rdt = intel_rdt;
+        self
+    }
+
+    pub fn set_network_setup(mut self, network_setup: Option<PathBuf>) -> Self {
+        self.state.network_setup = network_setup;
+        self
+    }
+
+    pub fn set_user_ids(mut self, user_ids: Option<UserIds>) -> Self {
+        self.state.user_ids = user_ids;
+        self
+    }
+
+    pub fn set_resources(mut self, resources: Option<LinuxResources>) -> Self {
+        self.state.resources = resources;
+        self
+    }
+
     pub fn set_annotations(mut self, annotations: Option<HashMap<String, String>>) -> Self {
         self.state.annotations = annotations;
         self
@@ -173,6 +260,41 @@ impl Container {
         self.state.use_systemd
     }
 
+    pub fn cgroup_root(&self) -> Option<&Path> {
+        self.state.cgroup_root.as_deref()
+    }
+
+    pub fn notify_socket(&self) -> Option<&Path> {
+        self.state.notify_socket.as_deref()
+    }
+
+    pub fn intel_rdt(&self) -> Option<&IntelRdt> {
+        self.state.intel_rdt.as_ref()
+    }
+
+    pub fn network_setup(&self) -> Option<&Path> {
+        self.state.network_setup.as_deref()
+    }
+
+    pub fn user_ids(&self) -> Option<UserIds> {
+        self.state.user_ids
+    }
+
+    pub fn resources(&self) -> Option<&LinuxResources> {
+        self.state.resources.as_ref()
+    }
+
+    /// Fails if the action may not be performed in the current status
+    pub fn check(&self, action: ContainerAction) -> Result<()> {
+        self.state.status.check(action)
+    }
+
+    /// Changes the status, if the lifecycle of a container allows it
+    pub fn transition(&self, status: ContainerStatus) -> Result<Self> {
+        self.state.status.transition(status)?;
+        Ok(self.update_status(status))
+    }
+
     pub fn update_status(&self, status: ContainerStatus) -> Self {
         let created = match (status, self.state.created) {
             (ContainerStatus::Created, None) => Some(Utc::now()),
@@ -189,7 +311,7 @@ impl Container {
         }
     }
 
-    pub fn load(container_root: PathBuf) -> Result<Self> {
+    pub fn load(container_root: PathBuf) -> Result<Self, ContainerError> {
         let state = State::load(&container_root)?;
         Ok(Self {
             state,
@@ -198,7 +320,17 @@ impl Container {
     }
 
     pub fn spec(&self) -> Result<Spec> {
-        Spec::load(self.root.join("config.json"))
+        utils::load_spec(self.root.join("config.json"))
+    }
+}
+
+// Converts a wait status into an exit code like a shell does, so a process
+// killed by a signal exits with 128 + the signal number
+fn exit_status_code(status: i32) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
     }
 }
 
@@ -232,4 +364,11 @@ mod tests {
         assert_eq!(container.root, fs::canonicalize(PathBuf::from("."))?);
         Ok(())
     }
+
+    #[test]
+    fn test_exit_status_code() {
+        assert_eq!(exit_status_code(0), 0);
+        assert_eq!(exit_status_code(3 << 8), 3);
+        assert_eq!(exit_status_code(libc::SIGKILL), 137);
+    }
 }
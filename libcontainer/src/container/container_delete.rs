@@ -0,0 +1,97 @@
+//! Removal of a container together with the resources of the host which
+//! have been set up for it
+use std::fs;
+
+use anyhow::Context;
+use nix::sys::signal::{self, Signal};
+
+use crate::container::{Container, ContainerAction, ContainerLock, ContainerStatus};
+use crate::error::ContainerError;
+use crate::hooks;
+use crate::network_setup::NetworkSetup;
+use crate::utils;
+
+impl Container {
+    /// Deletes a stopped container. The state, the cgroup, the intel rdt
+    /// group and the network of the container are removed and the poststop
+    /// hooks are run. A container which is still running is killed first if
+    /// `force` is set.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::Container;
+    ///
+    /// let container = Container::load("/run/youki/74f1a4cb3801".into())?;
+    /// container.delete(true)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn delete(&self, force: bool) -> Result<(), ContainerError> {
+        log::debug!("load the container from {:?}", self.root);
+        let _lock = ContainerLock::acquire(&self.root)?;
+        // it might be possible that delete is invoked on a running container.
+        let mut container = Container::load(self.root.clone())?.refresh_status()?;
+        if container.can_kill() && force {
+            let sig = Signal::SIGKILL;
+            log::debug!("kill signal {} to {}", sig, container.pid().unwrap());
+            signal::kill(container.pid().unwrap(), sig)?;
+            container = container.transition(ContainerStatus::Stopped)?;
+            container.save()?;
+        }
+        log::debug!("container status: {:?}", container.status());
+        container.check(ContainerAction::Delete)?;
+        if !container.root.exists() {
+            return Ok(());
+        }
+
+        let config_absolute_path = container.root.join("config.json");
+        log::debug!("load spec from {:?}", config_absolute_path);
+        let spec = utils::load_spec(config_absolute_path)?;
+        log::debug!("spec: {:?}", spec);
+
+        // remove the directory storing container state
+        log::debug!("remove dir {:?}", container.root);
+        fs::remove_dir_all(&container.root)?;
+
+        let use_systemd = container.systemd().unwrap_or(false);
+        let cgroups_path = utils::get_cgroup_path(
+            &spec.linux.context("no linux in spec")?.cgroups_path,
+            container.id(),
+            use_systemd,
+        );
+
+        // remove the cgroup created for the container
+        // check https://man7.org/linux/man-pages/man7/cgroups.7.html
+        // creating and removing cgroups section for more information on cgroups
+        let cmanager = cgroups::common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            use_systemd,
+        )?;
+        if cmanager.exists() {
+            cmanager.remove()?;
+        } else {
+            log::debug!(
+                "cgroup of container {} has already been removed",
+                container.id()
+            );
+        }
+
+        if let Some(intel_rdt) = container.intel_rdt() {
+            intel_rdt.remove(container.id())?;
+        }
+
+        // the container is deleted even if its network can not be cleaned up
+        if let Some(binary) = container.network_setup() {
+            if let Err(err) = NetworkSetup::new(binary).cleanup(container.id(), container.pid()) {
+                log::warn!("{:?}", err);
+            }
+        }
+
+        if let Some(hooks) = spec.hooks.as_ref() {
+            hooks::run_hooks(hooks.poststop.as_ref(), Some(&container))
+                .with_context(|| "Failed to run post stop hooks")?;
+        }
+        Ok(())
+    }
+}
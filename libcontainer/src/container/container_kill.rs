@@ -0,0 +1,112 @@
+//! Sending signals to the init process of a container
+use anyhow::Context;
+use nix::sys::signal::{self, Signal};
+
+use crate::container::history::{HistoryEntry, LifecycleEvent};
+use crate::container::{Container, ContainerAction, ContainerLock, ContainerStatus};
+use crate::error::ContainerError;
+use crate::features::FeatureGate;
+use crate::utils;
+
+impl Container {
+    /// Sends the signal to the init process of the container, which is
+    /// regarded as stopped afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::Container;
+    /// use nix::sys::signal::Signal;
+    ///
+    /// let container = Container::load("/run/youki/74f1a4cb3801".into())?;
+    /// container.kill(Signal::SIGTERM)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn kill(&self, sig: Signal) -> Result<Container, ContainerError> {
+        let _lock = ContainerLock::acquire(&self.root)?;
+        // it might be possible that kill is invoked on a already stopped container etc.
+        let container = Container::load(self.root.clone())?.refresh_status()?;
+        container.check(ContainerAction::Kill)?;
+        let pid = container.pid().unwrap();
+        log::debug!("kill signal {} to {}", sig, pid);
+        signal::kill(pid, sig)?;
+        let container = container.transition(ContainerStatus::Stopped)?;
+        container.save()?;
+        container.record_event(
+            HistoryEntry::new(LifecycleEvent::Killed)
+                .with_pid(Some(pid))
+                .with_signal(sig.to_string()),
+        );
+        Ok(container)
+    }
+
+    /// Sends the signal to the init process and to all other processes in
+    /// the cgroup of the container, e.g. the processes which have been
+    /// executed in it. SIGKILL is sent through cgroup.kill if the host
+    /// supports it, which also reaches processes forked in the meantime.
+    pub fn kill_all(&self, sig: Signal) -> Result<Container, ContainerError> {
+        let container = self.kill_init(sig)?;
+
+        let spec = container.spec()?;
+        let use_systemd = container.systemd().unwrap_or(false);
+        let cgroups_path = utils::get_cgroup_path(
+            &spec.linux.context("no linux in spec")?.cgroups_path,
+            container.id(),
+            use_systemd,
+        );
+        let cmanager = cgroups::common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            use_systemd,
+        )?;
+        if sig == Signal::SIGKILL && FeatureGate::CgroupKill.is_supported() && cmanager.kill()? {
+            log::debug!(
+                "killed the processes of {} through cgroup.kill",
+                container.id()
+            );
+            return Ok(container);
+        }
+
+        for pid in cmanager.get_all_pids()? {
+            if Some(pid) == container.pid() {
+                continue;
+            }
+            log::debug!("kill signal {} to {}", sig, pid);
+            // the process may have exited in the meantime
+            if let Err(err) = signal::kill(pid, sig) {
+                log::debug!("failed to kill {}: {}", pid, err);
+            }
+        }
+        Ok(container)
+    }
+
+    // The other processes in the cgroup may outlive the init process, so they
+    // are still signalled when the container has stopped already
+    fn kill_init(&self, sig: Signal) -> Result<Container, ContainerError> {
+        let container = Container::load(self.root.clone())?.refresh_status()?;
+        if container.status() == ContainerStatus::Stopped {
+            log::debug!("init process of {} has stopped already", container.id());
+            return Ok(container);
+        }
+        self.kill(sig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use anyhow::Result;
+
+    #[test]
+    fn test_kill_init_stopped() -> Result<()> {
+        let dir = create_temp_dir("test_kill_init_stopped")?;
+        let container = Container::new("test", ContainerStatus::Stopped, None, &dir, &dir)?;
+        container.save()?;
+
+        assert!(container.kill(Signal::SIGKILL).is_err());
+        let container = container.kill_init(Signal::SIGKILL)?;
+        assert_eq!(container.status(), ContainerStatus::Stopped);
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+//! Start of a container which has been created
+use anyhow::{Context, Result};
+
+use crate::container::history::{HistoryEntry, LifecycleEvent};
+use crate::container::{Container, ContainerAction, ContainerLock, ContainerStatus};
+use crate::error::ContainerError;
+use crate::exec_fifo;
+use crate::hooks;
+use crate::notify_socket::NotifyProxy;
+use crate::utils;
+
+impl Container {
+    /// Starts the process of a created container, which executes the user
+    /// specified program from then on. The state is loaded again while the
+    /// container is locked, so a stale state of the caller does no harm.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::Container;
+    ///
+    /// let container = Container::load("/run/youki/74f1a4cb3801".into())?;
+    /// container.start()?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn start(&self) -> Result<Container, ContainerError> {
+        let lock = ContainerLock::acquire(&self.root)?;
+        let container = Container::load(self.root.clone())?.refresh_status()?;
+        container.check(ContainerAction::Start)?;
+
+        let spec_path = container.root.join("config.json");
+        let spec = utils::load_spec(spec_path).context("failed to load spec")?;
+        if let Some(hooks) = spec.hooks.as_ref() {
+            // While prestart is marked as deprecated in the OCI spec, the docker and integration test still
+            // uses it.
+            #[allow(deprecated)]
+            hooks::run_hooks(hooks.prestart.as_ref(), Some(&container))
+                .with_context(|| "Failed to run pre start hooks")?;
+        }
+
+        let _cwd = utils::ChdirGuard::new(&container.root)?;
+
+        // the proxy has to listen before the container process can notify
+        let notify_proxy = container
+            .notify_socket()
+            .map(|host_socket| NotifyProxy::new(host_socket, &container.root));
+        let proxy_socket = notify_proxy.as_ref().map(|p| p.bind()).transpose()?;
+
+        let pid = container.pid().context("no pid in container state")?;
+        exec_fifo::start_container(&container.root, pid)?;
+        let container = container.transition(ContainerStatus::Running)?;
+        container.save()?;
+        container.record_event(HistoryEntry::new(LifecycleEvent::Started).with_pid(Some(pid)));
+        // the hooks and the notify proxy may take a while, other commands
+        // should not have to wait for them
+        drop(lock);
+
+        // Run post start hooks. It runs after the container process is started.
+        // It is called in the Runtime Namespace.
+        if let Some(hooks) = spec.hooks.as_ref() {
+            hooks::run_hooks(hooks.poststart.as_ref(), Some(&container))
+                .with_context(|| "Failed to run post start hooks")?;
+        }
+
+        if let (Some(proxy), Some(socket)) = (notify_proxy, proxy_socket) {
+            proxy
+                .forward(&socket, pid)
+                .context("failed to forward readiness of the container")?;
+        }
+
+        Ok(container)
+    }
+}
@@ -0,0 +1,136 @@
+//! Journal of the lifecycle events of a container, e.g. when it has been
+//! started or paused, which is kept in the container directory for debugging
+//! after the fact. Every event is appended as a line of JSON with a single
+//! write, so concurrent commands do not interleave their events.
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+const HISTORY_FILE: &str = "history.json";
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LifecycleEvent {
+    Created,
+    Started,
+    Exec,
+    Paused,
+    Resumed,
+    Killed,
+    Oom,
+    Stopped,
+    /// The container directory has been removed, which is only reported by the
+    /// event stream
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    pub event: LifecycleEvent,
+    /// Process the event is about, e.g. the process of an exec
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    /// Signal which has been sent to the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    /// Exit code of the container process, if it is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl HistoryEntry {
+    pub fn new(event: LifecycleEvent) -> Self {
+        Self {
+            time: Utc::now(),
+            event,
+            pid: None,
+            signal: None,
+            exit_code: None,
+        }
+    }
+
+    pub fn with_pid(mut self, pid: Option<Pid>) -> Self {
+        self.pid = pid.map(|pid| pid.as_raw());
+        self
+    }
+
+    pub fn with_signal(mut self, signal: String) -> Self {
+        self.signal = Some(signal);
+        self
+    }
+
+    pub fn with_exit_code(mut self, exit_code: Option<i32>) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+}
+
+pub fn file_path(container_root: &Path) -> PathBuf {
+    container_root.join(HISTORY_FILE)
+}
+
+/// Appends the entry to the history of the container
+pub fn record(container_root: &Path, entry: &HistoryEntry) -> Result<()> {
+    let path = file_path(container_root);
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("failed to write history {:?}", path))
+}
+
+/// Returns the history of the container, oldest event first
+pub fn load(container_root: &Path) -> Result<Vec<HistoryEntry>> {
+    let path = file_path(container_root);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        // nothing has happened yet
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+    };
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid history entry {:?} in {:?}", line, path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_record_and_load() -> Result<()> {
+        let dir = create_temp_dir("test_record_and_load_history")?;
+        assert!(load(&dir)?.is_empty());
+
+        let created = HistoryEntry::new(LifecycleEvent::Created).with_pid(Some(Pid::from_raw(42)));
+        let killed = HistoryEntry::new(LifecycleEvent::Killed).with_signal("SIGTERM".to_owned());
+        record(&dir, &created)?;
+        record(&dir, &killed)?;
+        assert_eq!(load(&dir)?, vec![created, killed]);
+
+        let content = fs::read_to_string(file_path(&dir))?;
+        assert!(content
+            .lines()
+            .last()
+            .unwrap()
+            .contains(r#""event":"killed","signal":"SIGTERM""#));
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{bail, Context, Result};
+use oci_spec::Spec;
+use rootless::Rootless;
+use serde_json::Value;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::ContainerError,
+    exec_fifo::create_exec_fifo,
+    idmap::MountIdMappings,
+    intel_rdt::IntelRdt,
+    lsm,
+    network_setup::NetworkSetup,
+    notify_socket::NotifyProxy,
+    nri::ContainerAdjustment,
+    process::{
+        cpu_affinity::ExecCpuAffinity, io_priority::IoPriority, scheduler::Scheduler, user::UserIds,
+    },
+    rootless, spec_compat, trace, tty, utils,
+};
+
+use super::{
+    builder::ContainerBuilder, builder_impl::ContainerBuilderImpl, Container, ContainerStatus,
+};
+
+// Builder that can be used to configure the properties of a new container
+pub struct InitContainerBuilder {
+    base: ContainerBuilder,
+    bundle: PathBuf,
+    use_systemd: bool,
+    cgroup_root: Option<PathBuf>,
+    no_pivot: bool,
+    strict_resources: bool,
+    rootless_fixups: bool,
+    subid_mappings: bool,
+    network_setup: Option<PathBuf>,
+}
+
+impl InitContainerBuilder {
+    /// Generates the base configuration for a new container from which
+    /// configuration methods can be chained
+    pub(super) fn new(builder: ContainerBuilder, bundle: PathBuf) -> Self {
+        Self {
+            base: builder,
+            bundle,
+            use_systemd: true,
+            cgroup_root: None,
+            no_pivot: false,
+            strict_resources: false,
+            rootless_fixups: false,
+            subid_mappings: true,
+            network_setup: None,
+        }
+    }
+
+    /// Sets if systemd should be used for managing cgroups
+    pub fn with_systemd(mut self, should_use: bool) -> Self {
+        self.use_systemd = should_use;
+        self
+    }
+
+    /// Sets the mount point of the cgroup root, if it is not mounted at the
+    /// default location
+    pub fn with_cgroup_root(mut self, cgroup_root: Option<PathBuf>) -> Self {
+        self.cgroup_root = cgroup_root;
+        self
+    }
+
+    /// Sets if the rootfs should be entered with MS_MOVE and chroot instead
+    /// of pivot_root, e.g. when running from a ramdisk
+    pub fn with_no_pivot(mut self, no_pivot: bool) -> Self {
+        self.no_pivot = no_pivot;
+        self
+    }
+
+    /// Sets if the creation should fail when resources are not supported by
+    /// the host or can not be applied to a rootless container, instead of
+    /// skipping them with a warning
+    pub fn with_strict_resources(mut self, strict_resources: bool) -> Self {
+        self.strict_resources = strict_resources;
+        self
+    }
+
+    /// Sets if the parts of the spec which can not be set up for a rootless
+    /// container should be changed instead of failing the creation, see
+    /// rootless::fix_up
+    pub fn with_rootless_fixups(mut self, rootless_fixups: bool) -> Self {
+        self.rootless_fixups = rootless_fixups;
+        self
+    }
+
+    /// Sets if the subordinate ids of the user should be mapped into a
+    /// rootless container whose spec has no mappings, see
+    /// rootless::apply_subid_mappings
+    pub fn with_subid_mappings(mut self, subid_mappings: bool) -> Self {
+        self.subid_mappings = subid_mappings;
+        self
+    }
+
+    /// Sets a binary which sets up the network of the container before it is
+    /// started and cleans it up when the container is deleted, see
+    /// network_setup::NetworkSetup
+    pub fn with_network_setup(mut self, network_setup: Option<PathBuf>) -> Self {
+        self.network_setup = network_setup;
+        self
+    }
+
+    /// Creates a new container, which waits to be started
+    pub fn build(self) -> Result<Container, ContainerError> {
+        utils::validate_container_id(&self.base.container_id).map_err(ContainerError::Spec)?;
+        let phase = trace::phase("spec");
+        let (mut spec, config) = self.load_spec().map_err(ContainerError::Spec)?;
+        drop(phase);
+        let container_dir = self.create_container_dir()?;
+
+        // when youki runs as a systemd service of Type=notify, the readiness
+        // of the container is forwarded to systemd through a proxy socket
+        let notify_socket = NotifyProxy::host_socket_from_env();
+        if let Some(host_socket) = notify_socket.as_ref() {
+            NotifyProxy::new(host_socket, &container_dir)
+                .setup_spec(&mut spec)
+                .context("failed to set up notify proxy")?;
+        }
+        self.save_spec(&spec, &container_dir)?;
+
+        let intel_rdt = IntelRdt::from_config(&config).context("failed to load intel rdt")?;
+        let user_ids = UserIds::from_config(&config).context("failed to load user")?;
+        let mount_id_mappings = MountIdMappings::from_config(&config)
+            .context("failed to load id mappings of mounts")?;
+
+        let container_state = self
+            .create_container_state(&container_dir)?
+            .set_systemd(self.use_systemd)
+            .set_cgroup_root(self.cgroup_root.clone())
+            .set_notify_socket(notify_socket)
+            .set_intel_rdt(intel_rdt.clone())
+            .set_user_ids(Some(user_ids))
+            .set_network_setup(self.network_setup.clone())
+            .set_annotations(spec.annotations.clone());
+
+        // the container directory may have been given relative to the current
+        // directory, which is changed until the container has been created
+        let container_root = container_state.root.clone();
+        let _cwd = utils::ChdirGuard::new(&container_dir)?;
+        let exec_fifo = create_exec_fifo(&container_dir)?;
+        // convert path of root file system of the container to absolute path
+        let rootfs = fs::canonicalize(&spec.root.as_ref().context("no root in spec")?.path)?;
+
+        // if socket file path is given in commandline options,
+        // get file descriptors of console socket. Like runc, a terminal is
+        // only allocated if the process asks for one.
+        let terminal = spec
+            .process
+            .as_ref()
+            .and_then(|p| p.terminal)
+            .unwrap_or(false);
+        let csocketfd = match &self.base.console_socket {
+            Some(console_socket) if terminal => Some(tty::setup_console_socket(
+                &container_dir,
+                console_socket,
+                "console-socket",
+            )?),
+            Some(console_socket) => {
+                log::warn!(
+                    "ignoring console socket {:?}, as the process does not ask for a terminal",
+                    console_socket
+                );
+                None
+            }
+            None => None,
+        };
+
+        let rootless = Rootless::new(&spec)?;
+        let scheduler = Scheduler::from_config(&config).context("failed to load scheduler")?;
+        let io_priority = IoPriority::from_config(&config).context("failed to load io priority")?;
+        let exec_cpu_affinity =
+            ExecCpuAffinity::from_config(&config).context("failed to load exec cpu affinity")?;
+        let mut builder_impl = ContainerBuilderImpl {
+            init: true,
+            syscall: self.base.syscall,
+            container_id: self.base.container_id,
+            pid_file: self.base.pid_file,
+            console_socket: csocketfd,
+            use_systemd: self.use_systemd,
+            cgroup_root: self.cgroup_root,
+            spec: &spec,
+            rootfs,
+            rootless,
+            notify_path: None,
+            exec_fifo: Some(exec_fifo),
+            container: Some(container_state),
+            preserve_fds: self.base.preserve_fds,
+            stdio: self.base.stdio,
+            no_pivot: self.no_pivot,
+            strict_resources: self.strict_resources,
+            scheduler,
+            io_priority,
+            exec_cpu_affinity,
+            user_ids,
+            intel_rdt,
+            mount_id_mappings,
+            network_setup: self.network_setup.map(NetworkSetup::new),
+        };
+
+        builder_impl.create()?;
+        Container::load(container_root)
+    }
+
+    fn create_container_dir(&self) -> Result<PathBuf> {
+        let container_dir = self.base.root_path.join(&self.base.container_id);
+        log::debug!("container directory will be {:?}", container_dir);
+
+        utils::create_dir_all(&self.base.root_path)?;
+        // creating the directory fails if it exists, so two concurrent creates
+        // of the same container can not both succeed
+        match fs::create_dir(&container_dir) {
+            Ok(()) => Ok(container_dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                bail!("container {} already exists", self.base.container_id)
+            }
+            Err(err) => Err(err).with_context(|| {
+                format!("failed to create container directory {:?}", container_dir)
+            }),
+        }
+    }
+
+    fn load_spec(&self) -> Result<(Spec, Value)> {
+        let source_spec_path = self.bundle.join("config.json");
+        // problems of the spec are reported before anything has been set up
+        let mut config = spec_compat::load_bundle_config(&source_spec_path)?;
+        // node agents can adjust the container before it is created, the
+        // adjusted config is validated and fixed up like any other
+        if let Some(adjustment) = ContainerAdjustment::from_config(&config)? {
+            log::debug!("apply nri adjustment {:?}", adjustment);
+            adjustment
+                .apply(&mut config)
+                .context("failed to apply nri adjustment")?;
+        }
+        let mut spec = spec_compat::spec_from_config(&config, &source_spec_path)?;
+        lsm::check_spec(&mut spec)?;
+        spec.canonicalize_rootfs(&self.bundle)?;
+
+        if rootless::rootless_required() {
+            if self.subid_mappings {
+                rootless::apply_subid_mappings(&mut spec)
+                    .context("failed to map subordinate ids")?;
+            }
+            if self.rootless_fixups {
+                for fix in rootless::fix_up(&mut spec).context("failed to fix up rootless spec")? {
+                    log::warn!("changed spec for rootless container: {}", fix);
+                }
+            }
+            rootless::fall_back_to_single_mapping(&mut spec)?;
+        }
+
+        Ok((spec, config))
+    }
+
+    fn save_spec(&self, spec: &oci_spec::Spec, container_dir: &Path) -> Result<()> {
+        let target_spec_path = container_dir.join("config.json");
+        spec.save(target_spec_path)?;
+        Ok(())
+    }
+
+    fn create_container_state(&self, container_dir: &Path) -> Result<Container> {
+        let container = Container::new(
+            &self.base.container_id,
+            ContainerStatus::Creating,
+            None,
+            self.bundle.as_path(),
+            container_dir,
+        )?;
+        container.save()?;
+        Ok(container)
+    }
+}
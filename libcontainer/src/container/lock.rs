@@ -0,0 +1,97 @@
+//! Advisory locking of a container directory. Orchestrators commonly invoke
+//! several commands on the same container concurrently, e.g. kill and delete.
+//! Commands which change the state of a container hold an exclusive lock from
+//! loading the state until it has been saved, so they are serialized. Reading
+//! the state does not require the lock, because it is replaced atomically.
+//! The history is appended under the lock as well.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nix::fcntl::{flock, FlockArg};
+
+thread_local! {
+    // Number of locks the thread holds on a directory, keyed by its device and
+    // inode. A flock is bound to the open file, so the thread would block on
+    // itself if it locked the directory again through another file.
+    static HELD_LOCKS: RefCell<HashMap<(u64, u64), usize>> = RefCell::new(HashMap::new());
+}
+
+/// Exclusive lock of a container directory, which is released when it is
+/// dropped. A thread which already holds the lock can acquire it again.
+pub struct ContainerLock {
+    _dir: File,
+    key: (u64, u64),
+}
+
+impl ContainerLock {
+    /// Blocks until the lock of the container directory has been acquired
+    pub fn acquire(container_root: &Path) -> Result<Self> {
+        let dir = File::open(container_root)
+            .with_context(|| format!("failed to open container directory {:?}", container_root))?;
+        let metadata = dir.metadata()?;
+        let key = (metadata.dev(), metadata.ino());
+        let held = HELD_LOCKS.with(|held| held.borrow().contains_key(&key));
+        if !held {
+            flock(dir.as_raw_fd(), FlockArg::LockExclusive).with_context(|| {
+                format!("failed to lock container directory {:?}", container_root)
+            })?;
+        }
+        HELD_LOCKS.with(|held| *held.borrow_mut().entry(key).or_insert(0) += 1);
+        Ok(Self { _dir: dir, key })
+    }
+}
+
+impl Drop for ContainerLock {
+    fn drop(&mut self) {
+        HELD_LOCKS.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(count) = held.get_mut(&self.key) {
+                *count -= 1;
+                if *count == 0 {
+                    held.remove(&self.key);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_container_lock() -> Result<()> {
+        let dir = create_temp_dir("test_container_lock")?;
+        let lock = ContainerLock::acquire(&dir)?;
+
+        // a second open file description can not take the lock while it is held
+        let other = File::open(&*dir)?;
+        assert!(flock(other.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err());
+
+        drop(lock);
+        assert!(flock(other.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_lock_reentrant() -> Result<()> {
+        let dir = create_temp_dir("test_container_lock_reentrant")?;
+        let lock = ContainerLock::acquire(&dir)?;
+        let nested = ContainerLock::acquire(&dir)?;
+        drop(nested);
+
+        // the outer lock is still held
+        let other = File::open(&*dir)?;
+        assert!(flock(other.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err());
+
+        drop(lock);
+        assert!(flock(other.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_ok());
+        Ok(())
+    }
+}
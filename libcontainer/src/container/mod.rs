@@ -8,8 +8,14 @@ pub mod builder;
 mod builder_impl;
 #[allow(clippy::module_inception)]
 mod container;
+mod container_delete;
+mod container_kill;
+mod container_start;
+pub mod history;
 pub mod init_builder;
+pub mod lock;
 pub mod state;
 pub mod tenant_builder;
 pub use container::Container;
-pub use state::{ContainerStatus, State};
+pub use lock::ContainerLock;
+pub use state::{ContainerAction, ContainerStatus, State};
@@ -0,0 +1,417 @@
+//! Information about status and state of the container
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::{fs::File, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use oci_spec::LinuxResources;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ContainerError;
+use crate::intel_rdt::IntelRdt;
+use crate::process::user::UserIds;
+
+/// Indicates status of the container
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ContainerStatus {
+    // The container is being created
+    Creating,
+    // The runtime has finished the create operation
+    Created,
+    // The container process has executed the user-specified program but has not exited
+    Running,
+    // The container process has exited
+    Stopped,
+    // The container process has paused
+    Paused,
+}
+
+impl Default for ContainerStatus {
+    fn default() -> Self {
+        ContainerStatus::Creating
+    }
+}
+
+/// Operations of the runtime which depend on the status of the container
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContainerAction {
+    Start,
+    Kill,
+    Delete,
+    Exec,
+    Pause,
+    Resume,
+    Update,
+}
+
+impl Display for ContainerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match *self {
+            Self::Start => "start",
+            Self::Kill => "kill",
+            Self::Delete => "delete",
+            Self::Exec => "exec into",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Update => "update",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+impl ContainerStatus {
+    /// Returns if the action may be performed on a container in this status
+    pub fn allows(&self, action: ContainerAction) -> bool {
+        use ContainerAction::*;
+        use ContainerStatus::*;
+        match action {
+            Start => matches!(self, Created),
+            Kill | Update => matches!(self, Created | Running | Paused),
+            Delete => matches!(self, Stopped),
+            Exec | Pause => matches!(self, Running),
+            Resume => matches!(self, Paused),
+        }
+    }
+
+    /// Fails with a descriptive error if the action may not be performed on a
+    /// container in this status
+    pub fn check(&self, action: ContainerAction) -> Result<()> {
+        if !self.allows(action) {
+            return Err(ContainerError::state(format!(
+                "cannot {} a {} container",
+                action,
+                self.to_string().to_lowercase()
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns if a container in this status may change to the next status.
+    /// A container can stop in every status, but never leaves the stopped
+    /// status again.
+    pub fn can_transition_to(&self, next: ContainerStatus) -> bool {
+        use ContainerStatus::*;
+        match (self, next) {
+            (Stopped, _) => false,
+            (_, Stopped) => true,
+            (Creating, Created) | (Created, Running) => true,
+            (Running, Paused) | (Paused, Running) => true,
+            _ => false,
+        }
+    }
+
+    /// Validates the transition to the next status
+    pub fn transition(&self, next: ContainerStatus) -> Result<ContainerStatus> {
+        if !self.can_transition_to(next) {
+            return Err(ContainerError::state(format!(
+                "cannot change the status of a {} container to {}",
+                self.to_string().to_lowercase(),
+                next.to_string().to_lowercase()
+            ))
+            .into());
+        }
+
+        Ok(next)
+    }
+
+    pub fn can_start(&self) -> bool {
+        self.allows(ContainerAction::Start)
+    }
+
+    pub fn can_kill(&self) -> bool {
+        self.allows(ContainerAction::Kill)
+    }
+
+    pub fn can_delete(&self) -> bool {
+        self.allows(ContainerAction::Delete)
+    }
+
+    pub fn can_exec(&self) -> bool {
+        self.allows(ContainerAction::Exec)
+    }
+
+    pub fn can_pause(&self) -> bool {
+        self.allows(ContainerAction::Pause)
+    }
+
+    pub fn can_resume(&self) -> bool {
+        self.allows(ContainerAction::Resume)
+    }
+
+    pub fn can_update(&self) -> bool {
+        self.allows(ContainerAction::Update)
+    }
+}
+
+impl Display for ContainerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match *self {
+            Self::Creating => "Creating",
+            Self::Created => "Created",
+            Self::Running => "Running",
+            Self::Stopped => "Stopped",
+            Self::Paused => "Paused",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+/// Stores the state information of the container
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct State {
+    // Version is the version of the specification that is supported.
+    pub oci_version: String,
+    // ID is the container ID
+    pub id: String,
+    // Status is the runtime status of the container.
+    pub status: ContainerStatus,
+    // Pid is the process ID for the container process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    // Bundle is the path to the container's bundle directory.
+    pub bundle: PathBuf,
+    // Annotations are key values associated with the container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+    // Creation time of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    // User that created the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<u32>,
+    // Specifies if systemd should be used to manage cgroups
+    pub use_systemd: Option<bool>,
+    // Mount point of the cgroup root, if it has been set explicitly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_root: Option<PathBuf>,
+    // NOTIFY_SOCKET of the service manager, to which the sd_notify messages
+    // of the container are forwarded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_socket: Option<PathBuf>,
+    // Intel RDT configuration of the container, which is needed to join and
+    // remove its resctrl group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intel_rdt: Option<IntelRdt>,
+    // Binary which has set up the network of the container and cleans it up
+    // when the container is deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_setup: Option<PathBuf>,
+    // Ids of the user as given in the spec, which the saved config can not
+    // tell apart from root if they are not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_ids: Option<UserIds>,
+    // Resource restrictions of the container, if they have been updated
+    // since it has been created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<LinuxResources>,
+}
+
+impl State {
+    const STATE_FILE_PATH: &'static str = "state.json";
+
+    pub fn new(
+        container_id: &str,
+        status: ContainerStatus,
+        pid: Option<i32>,
+        bundle: PathBuf,
+    ) -> Self {
+        Self {
+            oci_version: "v1.0.2".to_string(),
+            id: container_id.to_string(),
+            status,
+            pid,
+            bundle,
+            annotations: Some(HashMap::default()),
+            created: None,
+            creator: None,
+            use_systemd: None,
+            cgroup_root: None,
+            notify_socket: None,
+            intel_rdt: None,
+            network_setup: None,
+            user_ids: None,
+            resources: None,
+        }
+    }
+
+    pub fn save(&self, container_root: &Path) -> Result<()> {
+        let state_file_path = Self::file_path(container_root);
+        // the state is written to a temporary file which atomically replaces
+        // the state file, so a concurrent reader never sees a partial state
+        let tmp_file_path =
+            container_root.join(format!(".{}.{}", Self::STATE_FILE_PATH, std::process::id()));
+        let file = File::create(&tmp_file_path)
+            .with_context(|| format!("failed to create {:?}", tmp_file_path))?;
+        serde_json::to_writer(&file, self)?;
+        file.sync_all()
+            .with_context(|| format!("failed to sync {:?}", tmp_file_path))?;
+        fs::rename(&tmp_file_path, &state_file_path).with_context(|| {
+            format!(
+                "failed to replace container state file {:?}",
+                state_file_path
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn load(container_root: &Path) -> Result<Self> {
+        let state_file_path = Self::file_path(container_root);
+        let file = File::open(&state_file_path).with_context(|| {
+            format!("failed to open container state file {:?}", state_file_path)
+        })?;
+        let state: Self = serde_json::from_reader(&file)?;
+        Ok(state)
+    }
+
+    /// Returns the path to the state JSON file for the provided `container_root`.
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use libcontainer::container::State;
+    ///
+    /// let container_root = Path::new("/var/run/containers/container");
+    /// let state_file = State::file_path(&container_root);
+    /// assert_eq!(state_file.to_str(), Some("/var/run/containers/container/state.json"));
+    /// ```
+    pub fn file_path(container_root: &Path) -> PathBuf {
+        container_root.join(Self::STATE_FILE_PATH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creating_status() {
+        let cstatus = ContainerStatus::default();
+        assert!(!cstatus.can_start());
+        assert!(!cstatus.can_delete());
+        assert!(!cstatus.can_kill());
+        assert!(!cstatus.can_pause());
+        assert!(!cstatus.can_resume());
+        assert!(!cstatus.can_update());
+    }
+
+    #[test]
+    fn test_create_status() {
+        let cstatus = ContainerStatus::Created;
+        assert!(cstatus.can_start());
+        assert!(!cstatus.can_delete());
+        assert!(cstatus.can_kill());
+        assert!(!cstatus.can_pause());
+        assert!(!cstatus.can_resume());
+        assert!(cstatus.can_update());
+    }
+
+    #[test]
+    fn test_running_status() {
+        let cstatus = ContainerStatus::Running;
+        assert!(!cstatus.can_start());
+        assert!(!cstatus.can_delete());
+        assert!(cstatus.can_kill());
+        assert!(cstatus.can_pause());
+        assert!(!cstatus.can_resume());
+        assert!(cstatus.can_update());
+    }
+
+    #[test]
+    fn test_stopped_status() {
+        let cstatus = ContainerStatus::Stopped;
+        assert!(!cstatus.can_start());
+        assert!(cstatus.can_delete());
+        assert!(!cstatus.can_kill());
+        assert!(!cstatus.can_pause());
+        assert!(!cstatus.can_resume());
+        assert!(!cstatus.can_update());
+    }
+
+    #[test]
+    fn test_paused_status() {
+        let cstatus = ContainerStatus::Paused;
+        assert!(!cstatus.can_start());
+        assert!(!cstatus.can_delete());
+        assert!(cstatus.can_kill());
+        assert!(!cstatus.can_pause());
+        assert!(cstatus.can_resume());
+        assert!(cstatus.can_update());
+    }
+
+    #[test]
+    fn test_check_action() {
+        assert!(ContainerStatus::Created
+            .check(ContainerAction::Start)
+            .is_ok());
+        let err = ContainerStatus::Paused
+            .check(ContainerAction::Start)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot start a paused container");
+        let err = ContainerStatus::Stopped
+            .check(ContainerAction::Exec)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot exec into a stopped container");
+        assert!(matches!(
+            ContainerError::from(err),
+            ContainerError::State(_)
+        ));
+    }
+
+    #[test]
+    fn test_transition() {
+        use ContainerStatus::*;
+        let allowed = [
+            (Creating, Created),
+            (Creating, Stopped),
+            (Created, Running),
+            (Created, Stopped),
+            (Running, Paused),
+            (Running, Stopped),
+            (Paused, Running),
+            (Paused, Stopped),
+        ];
+        for from in [Creating, Created, Running, Paused, Stopped] {
+            for to in [Creating, Created, Running, Paused, Stopped] {
+                assert_eq!(
+                    from.transition(to).is_ok(),
+                    allowed.contains(&(from, to)),
+                    "{} -> {}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_state() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_save_state")?;
+        let state = State::new(
+            "test",
+            ContainerStatus::Created,
+            Some(1),
+            PathBuf::from("/"),
+        );
+        state.save(&dir)?;
+        State::new(
+            "test",
+            ContainerStatus::Running,
+            Some(1),
+            PathBuf::from("/"),
+        )
+        .save(&dir)?;
+
+        assert_eq!(State::load(&dir)?.status, ContainerStatus::Running);
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(&*dir)?.count(), 1);
+        Ok(())
+    }
+}
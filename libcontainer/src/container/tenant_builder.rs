@@ -1,7 +1,8 @@
 use anyhow::{bail, Context, Result};
 use caps::Capability;
-use nix::unistd;
+use nix::unistd::Pid;
 use oci_spec::{LinuxCapabilities, LinuxNamespace, LinuxNamespaceType, Process, Spec};
+use serde_json::Value;
 
 use std::{
     collections::HashMap,
@@ -13,9 +14,22 @@ use std::{
     str::FromStr,
 };
 
-use crate::{notify_socket::NotifySocket, rootless::Rootless, tty, utils};
+use crate::{
+    error::ContainerError,
+    notify_socket::NotifySocket,
+    process::{
+        cpu_affinity::ExecCpuAffinity, io_priority::IoPriority, scheduler::Scheduler, user::UserIds,
+    },
+    rootless::Rootless,
+    tty, utils,
+};
 
-use super::{builder::ContainerBuilder, builder_impl::ContainerBuilderImpl, Container};
+use super::{
+    builder::ContainerBuilder,
+    builder_impl::ContainerBuilderImpl,
+    history::{HistoryEntry, LifecycleEvent},
+    Container, ContainerAction,
+};
 
 const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup"];
 const TENANT_NOTIFY: &str = "tenant-notify-";
@@ -31,6 +45,7 @@ pub struct TenantContainerBuilder {
     no_new_privs: Option<bool>,
     capabilities: Vec<String>,
     process: Option<PathBuf>,
+    tty: bool,
 }
 
 impl TenantContainerBuilder {
@@ -46,6 +61,7 @@ impl TenantContainerBuilder {
             no_new_privs: None,
             capabilities: Vec::new(),
             process: None,
+            tty: false,
         }
     }
 
@@ -82,15 +98,25 @@ impl TenantContainerBuilder {
         self
     }
 
-    /// Joins an existing container
-    pub fn build(self) -> Result<()> {
+    /// Allocates a pseudo terminal for the process, which is sent to the
+    /// console socket
+    pub fn with_tty(mut self, tty: bool) -> Self {
+        self.tty = tty;
+        self
+    }
+
+    /// Joins an existing container and returns the pid of the process
+    pub fn build(self) -> Result<Pid, ContainerError> {
+        utils::validate_container_id(&self.base.container_id).map_err(ContainerError::Spec)?;
         let container_dir = self.lookup_container_dir()?;
         let container = self.load_container_state(container_dir.clone())?;
         let mut spec = self.load_init_spec(&container_dir)?;
-        self.adapt_spec_for_tenant(&mut spec, &container)?;
+        let process = self.load_process().map_err(ContainerError::Spec)?;
+        self.adapt_spec_for_tenant(&mut spec, &container, process.as_ref())
+            .map_err(ContainerError::Spec)?;
         log::debug!("{:#?}", spec);
 
-        unistd::chdir(&*container_dir)?;
+        let _cwd = utils::ChdirGuard::new(&container_dir)?;
         let notify_path = Self::setup_notify_listener(&container_dir)?;
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(&spec.root.as_ref().context("no root in spec")?.path)?;
@@ -101,6 +127,30 @@ impl TenantContainerBuilder {
 
         let use_systemd = self.should_use_systemd(&container);
         let rootless = Rootless::new(&spec)?;
+        // only a process file can describe the scheduling policy, io
+        // priority and cpu affinity of the process which joins the container
+        let (scheduler, io_priority, exec_cpu_affinity) = match process.as_ref() {
+            Some(process) => (
+                Scheduler::from_process(process).context("failed to load scheduler")?,
+                IoPriority::from_process(process).context("failed to load io priority")?,
+                ExecCpuAffinity::from_process(process)
+                    .context("failed to load exec cpu affinity")?,
+            ),
+            None => (None, None, None),
+        };
+        let user_ids = match process.as_ref() {
+            Some(process) => UserIds::from_process(process).context("failed to load user")?,
+            // the process joins with the user of the container, containers
+            // which have been created by an older version only know the ids
+            // of the saved spec
+            None => container.user_ids().unwrap_or_else(|| {
+                let user = spec.process.as_ref().map(|p| &p.user);
+                UserIds {
+                    uid: user.map(|user| user.uid),
+                    gid: user.map(|user| user.gid),
+                }
+            }),
+        };
 
         let mut builder_impl = ContainerBuilderImpl {
             init: false,
@@ -109,19 +159,32 @@ impl TenantContainerBuilder {
             pid_file: self.base.pid_file,
             console_socket: csocketfd,
             use_systemd,
+            cgroup_root: container.cgroup_root().map(|p| p.to_path_buf()),
             spec: &spec,
             rootfs,
             rootless,
-            notify_path: notify_path.clone(),
+            notify_path: Some(notify_path.clone()),
+            exec_fifo: None,
             container: None,
             preserve_fds: self.base.preserve_fds,
+            stdio: self.base.stdio,
+            no_pivot: false,
+            strict_resources: false,
+            scheduler,
+            io_priority,
+            exec_cpu_affinity,
+            user_ids,
+            intel_rdt: container.intel_rdt().cloned(),
+            mount_id_mappings: HashMap::new(),
+            network_setup: None,
         };
 
-        builder_impl.create()?;
+        let pid = builder_impl.create()?;
+        container.record_event(HistoryEntry::new(LifecycleEvent::Exec).with_pid(Some(pid)));
 
         let mut notify_socket = NotifySocket::new(notify_path);
         notify_socket.notify_container_start()?;
-        Ok(())
+        Ok(pid)
     }
 
     fn lookup_container_dir(&self) -> Result<PathBuf> {
@@ -136,25 +199,27 @@ impl TenantContainerBuilder {
     fn load_init_spec(&self, container_dir: &Path) -> Result<Spec> {
         let spec_path = container_dir.join("config.json");
 
-        let spec = oci_spec::Spec::load(spec_path).context("failed to load spec")?;
+        let spec = utils::load_spec(spec_path).context("failed to load spec")?;
         Ok(spec)
     }
 
     fn load_container_state(&self, container_dir: PathBuf) -> Result<Container> {
         let container = Container::load(container_dir)?.refresh_status()?;
-        if !container.can_exec() {
-            bail!(
-                "Cannot exec as container is in state {}",
-                container.status()
-            );
-        }
+        container.check(ContainerAction::Exec)?;
 
         Ok(container)
     }
 
-    fn adapt_spec_for_tenant(&self, spec: &mut Spec, container: &Container) -> Result<()> {
-        if let Some(ref process) = self.process {
-            self.set_process(spec, process)?;
+    fn adapt_spec_for_tenant(
+        &self,
+        spec: &mut Spec,
+        container: &Container,
+        process: Option<&Value>,
+    ) -> Result<()> {
+        if let Some(process) = process {
+            let process: Process = serde_json::from_value(process.clone())
+                .context("failed to deserialize process file")?;
+            spec.process = Some(process);
         } else {
             self.set_working_dir(spec)?;
             self.set_args(spec)?;
@@ -163,6 +228,17 @@ impl TenantContainerBuilder {
             self.set_capabilities(spec)?;
         }
 
+        if self.tty {
+            if self.base.console_socket.is_none() {
+                bail!("a console socket is required to allocate a terminal");
+            }
+
+            spec.process
+                .as_mut()
+                .context("no process in spec")?
+                .terminal = Some(true);
+        }
+
         if container.pid().is_none() {
             bail!("Could not retrieve container init pid");
         }
@@ -173,7 +249,13 @@ impl TenantContainerBuilder {
         Ok(())
     }
 
-    fn set_process(&self, spec: &mut Spec, process: &Path) -> Result<()> {
+    // The process file is kept as json, because it may hold fields which the
+    // oci-spec crate does not know about
+    fn load_process(&self) -> Result<Option<Value>> {
+        let process = match self.process.as_ref() {
+            Some(process) => process,
+            None => return Ok(None),
+        };
         if !process.exists() {
             bail!(
                 "Process.json file does not exist at specified path {}",
@@ -182,9 +264,8 @@ impl TenantContainerBuilder {
         }
 
         let process = utils::open(process)?;
-        let process_spec: Process = serde_json::from_reader(process)?;
-        spec.process = Some(process_spec);
-        Ok(())
+        let process = serde_json::from_reader(process)?;
+        Ok(Some(process))
     }
 
     fn set_working_dir(&self, spec: &mut Spec) -> Result<()> {
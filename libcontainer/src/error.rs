@@ -0,0 +1,134 @@
+//! Errors which are returned by the creation and management of containers.
+//! They tell callers what kind of failure happened, e.g. so a runtime can
+//! retry a failure of the system, but not an invalid spec. The internals
+//! report failures with anyhow, the errors are classified when they are
+//! returned to the caller.
+use std::fmt::{Debug, Display};
+use std::io;
+
+use cgroups::error::CgroupError;
+use nix::errno::Errno;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ContainerError {
+    /// The spec of the bundle is invalid or can not be fulfilled
+    #[error(transparent)]
+    Spec(anyhow::Error),
+    /// The caller is not allowed to perform the operation
+    #[error(transparent)]
+    Permission(anyhow::Error),
+    /// The kernel does not provide a feature which is needed by the container
+    #[error(transparent)]
+    KernelFeatureMissing(anyhow::Error),
+    /// The status of the container does not allow the operation, e.g. the
+    /// container is not running
+    #[error(transparent)]
+    State(anyhow::Error),
+    /// Any other failure, which may not happen again when retrying
+    #[error(transparent)]
+    System(anyhow::Error),
+}
+
+impl ContainerError {
+    pub fn spec<M>(message: M) -> Self
+    where
+        M: Display + Debug + Send + Sync + 'static,
+    {
+        Self::Spec(anyhow::Error::msg(message))
+    }
+
+    pub fn state<M>(message: M) -> Self
+    where
+        M: Display + Debug + Send + Sync + 'static,
+    {
+        Self::State(anyhow::Error::msg(message))
+    }
+
+    // Constructor of the variant of the error, so another error can be
+    // classified the same way
+    fn variant(&self) -> Variant {
+        match self {
+            Self::Spec(_) => Self::Spec,
+            Self::Permission(_) => Self::Permission,
+            Self::KernelFeatureMissing(_) => Self::KernelFeatureMissing,
+            Self::State(_) => Self::State,
+            Self::System(_) => Self::System,
+        }
+    }
+}
+
+type Variant = fn(anyhow::Error) -> ContainerError;
+
+/// Classifies an error by a container error which has been raised deeper
+/// down, otherwise like a cgroup error
+impl From<anyhow::Error> for ContainerError {
+    fn from(err: anyhow::Error) -> Self {
+        let variant = err.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<ContainerError>()
+                .map(ContainerError::variant)
+        });
+        match variant {
+            Some(variant) => variant(err),
+            None => CgroupError::from(err).into(),
+        }
+    }
+}
+
+impl From<CgroupError> for ContainerError {
+    fn from(err: CgroupError) -> Self {
+        match err {
+            CgroupError::Spec(err) => Self::Spec(err),
+            CgroupError::Permission(err) => Self::Permission(err),
+            CgroupError::KernelFeatureMissing(err) => Self::KernelFeatureMissing(err),
+            CgroupError::System(err) => Self::System(err),
+        }
+    }
+}
+
+impl From<io::Error> for ContainerError {
+    fn from(err: io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<Errno> for ContainerError {
+    fn from(err: Errno) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify_cgroup_error() {
+        let err: ContainerError = Err::<(), _>(CgroupError::kernel_feature_missing(
+            "controller cpu is required by the resources, but is not available",
+        ))
+        .context("failed to apply resource limits through cgroup")
+        .unwrap_err()
+        .into();
+        assert!(matches!(err, ContainerError::KernelFeatureMissing(_)));
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to apply resource limits through cgroup: \
+             controller cpu is required by the resources, but is not available"
+        );
+
+        let err: ContainerError = Errno::EPERM.into();
+        assert!(matches!(err, ContainerError::Permission(_)));
+    }
+
+    #[test]
+    fn test_classify_container_error() {
+        let err: ContainerError = Err::<(), _>(ContainerError::spec("no root in spec"))
+            .context("failed to load spec")
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, ContainerError::Spec(_)));
+    }
+}
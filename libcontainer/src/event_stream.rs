@@ -0,0 +1,271 @@
+//! Stream of the lifecycle events of all containers in the root directory.
+//! The events are taken from the histories of the containers as they are
+//! appended, so events recorded by any youki command are reported. OOM kills
+//! are watched in the cgroups of the running containers and recorded in their
+//! histories as well.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use anyhow::{Context, Result};
+use cgroups::common;
+use serde::Serialize;
+
+use crate::container::history::{self, HistoryEntry, LifecycleEvent};
+use crate::container::{state::State, Container, ContainerStatus};
+use crate::utils;
+
+/// Lifecycle event of a container, serialized as the history entry together
+/// with the id of the container
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEvent {
+    pub id: String,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+}
+
+// What is known about a container which is watched
+struct Watched {
+    // Length of the history which has been reported already
+    offset: u64,
+    oom_events: Option<Receiver<()>>,
+}
+
+pub struct EventStream {
+    root_path: PathBuf,
+    containers: HashMap<String, Watched>,
+}
+
+impl EventStream {
+    /// Starts to watch the containers in the root directory. Only events which
+    /// happen from now on are reported.
+    pub fn new<P: Into<PathBuf>>(root_path: P) -> Result<Self> {
+        let mut stream = Self {
+            root_path: root_path.into(),
+            containers: HashMap::new(),
+        };
+        for (id, container_root) in stream.container_dirs()? {
+            let offset = fs::metadata(history::file_path(&container_root))
+                .map(|m| m.len())
+                .unwrap_or(0);
+            stream.watch(id, &container_root, offset);
+        }
+
+        Ok(stream)
+    }
+
+    /// Returns the events which have happened since the last poll
+    pub fn poll(&mut self) -> Result<Vec<ContainerEvent>> {
+        let mut events = Vec::new();
+        let container_dirs = self.container_dirs()?;
+        // the directory has been removed by the delete command, which does
+        // not leave a history behind
+        let deleted: Vec<String> = self
+            .containers
+            .keys()
+            .filter(|id| !container_dirs.iter().any(|(other, _)| other == *id))
+            .cloned()
+            .collect();
+        for id in deleted {
+            self.containers.remove(&id);
+            events.push(ContainerEvent {
+                id,
+                entry: HistoryEntry::new(LifecycleEvent::Deleted),
+            });
+        }
+
+        for (id, container_root) in container_dirs {
+            if !self.containers.contains_key(&id) {
+                self.watch(id.clone(), &container_root, 0);
+            }
+
+            let watched = self.containers.get_mut(&id).unwrap();
+            if let Some(oom_events) = &watched.oom_events {
+                match oom_events.try_recv() {
+                    Ok(()) => {
+                        if let Err(err) = history::record(
+                            &container_root,
+                            &HistoryEntry::new(LifecycleEvent::Oom),
+                        ) {
+                            log::warn!("failed to record OOM of {}: {:?}", id, err);
+                        }
+                    }
+                    Err(TryRecvError::Empty) => {}
+                    // the cgroup has been removed
+                    Err(TryRecvError::Disconnected) => watched.oom_events = None,
+                }
+            }
+
+            let (entries, offset) = read_history(&container_root, watched.offset)?;
+            watched.offset = offset;
+            events.extend(entries.into_iter().map(|entry| ContainerEvent {
+                id: id.clone(),
+                entry,
+            }));
+        }
+
+        Ok(events)
+    }
+
+    fn watch(&mut self, id: String, container_root: &Path, offset: u64) {
+        let oom_events = match watch_oom(container_root) {
+            Ok(oom_events) => oom_events,
+            Err(err) => {
+                log::debug!("OOM events of {} are not watched: {:?}", id, err);
+                None
+            }
+        };
+        self.containers.insert(id, Watched { offset, oom_events });
+    }
+
+    fn container_dirs(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut container_dirs = Vec::new();
+        for entry in fs::read_dir(&self.root_path)
+            .with_context(|| format!("failed to read root directory {:?}", self.root_path))?
+        {
+            let container_root = entry?.path();
+            if !State::file_path(&container_root).exists() {
+                continue;
+            }
+            if let Some(id) = container_root.file_name().and_then(|n| n.to_str()) {
+                container_dirs.push((id.to_owned(), container_root.clone()));
+            }
+        }
+
+        Ok(container_dirs)
+    }
+}
+
+// OOM kills can only be watched while the container has a cgroup
+fn watch_oom(container_root: &Path) -> Result<Option<Receiver<()>>> {
+    let container = Container::load(container_root.to_path_buf())?.refresh_status()?;
+    if !matches!(
+        container.status(),
+        ContainerStatus::Created | ContainerStatus::Running | ContainerStatus::Paused
+    ) {
+        return Ok(None);
+    }
+
+    let use_systemd = container
+        .systemd()
+        .context("could not determine cgroup manager")?;
+    let cgroups_path = utils::get_cgroup_path(
+        &container
+            .spec()?
+            .linux
+            .context("no linux in spec")?
+            .cgroups_path,
+        container.id(),
+        use_systemd,
+    );
+    let cgroup_manager = common::create_cgroup_manager_with_root(
+        container.cgroup_root(),
+        cgroups_path,
+        use_systemd,
+    )?;
+    Ok(Some(cgroup_manager.notify_on_oom()?))
+}
+
+// Reads the entries which have been appended to the history after the offset
+// and returns them with the new offset. A line which is still being written
+// is read with the next poll.
+fn read_history(container_root: &Path, offset: u64) -> Result<(Vec<HistoryEntry>, u64)> {
+    let path = history::file_path(container_root);
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(_) => return Ok((Vec::new(), offset)),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .with_context(|| format!("failed to read {:?}", path))?;
+
+    let complete = match content.rfind('\n') {
+        Some(end) => &content[..=end],
+        None => return Ok((Vec::new(), offset)),
+    };
+    let entries = complete
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                log::warn!("invalid history entry {:?} in {:?}: {}", line, path, err);
+                None
+            }
+        })
+        .collect();
+
+    Ok((entries, offset + complete.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use nix::unistd::Pid;
+    use std::io::Write;
+
+    #[test]
+    fn test_event_stream() -> Result<()> {
+        let root = create_temp_dir("test_event_stream")?;
+        let old = root.join("old");
+        fs::create_dir(&old)?;
+        fs::write(State::file_path(&old), "")?;
+        history::record(&old, &HistoryEntry::new(LifecycleEvent::Created))?;
+
+        // events which happened before are not reported
+        let mut stream = EventStream::new(&*root)?;
+        assert!(stream.poll()?.is_empty());
+
+        let started = HistoryEntry::new(LifecycleEvent::Started).with_pid(Some(Pid::from_raw(42)));
+        history::record(&old, &started)?;
+        let new = root.join("new");
+        fs::create_dir(&new)?;
+        fs::write(State::file_path(&new), "")?;
+        let created = HistoryEntry::new(LifecycleEvent::Created);
+        history::record(&new, &created)?;
+
+        let mut events = stream.poll()?;
+        events.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(
+            events,
+            vec![
+                ContainerEvent {
+                    id: "new".to_owned(),
+                    entry: created,
+                },
+                ContainerEvent {
+                    id: "old".to_owned(),
+                    entry: started,
+                },
+            ]
+        );
+        assert!(stream.poll()?.is_empty());
+
+        fs::remove_dir_all(&old)?;
+        let events = stream.poll()?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, "old");
+        assert_eq!(events[0].entry.event, LifecycleEvent::Deleted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_history_skips_partial_lines() -> Result<()> {
+        let dir = create_temp_dir("test_read_history_skips_partial_lines")?;
+        history::record(&dir, &HistoryEntry::new(LifecycleEvent::Paused))?;
+        let complete = fs::metadata(history::file_path(&dir))?.len();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(history::file_path(&dir))?
+            .write_all(br#"{"time":"#)?;
+
+        let (entries, offset) = read_history(&dir, 0)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(offset, complete);
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Synchronization of the create and start commands through a fifo in the
+//! container directory, which follows the protocol of runc. The container
+//! init process blocks on opening the fifo for writing once the container has
+//! been created, until the start command opens it for reading. The fifo is
+//! removed by the first start command which read from it, so starting a
+//! container twice fails instead of racing.
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal;
+use nix::sys::stat::{self, Mode};
+use nix::unistd::{self, Pid};
+
+pub const EXEC_FIFO: &str = "exec.fifo";
+
+// Interval in which start checks if the init process is still alive
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates the fifo in the container directory. The fifo is writable for
+/// everyone, so the init process can open it after it changed its user.
+pub fn create_exec_fifo(container_dir: &Path) -> Result<PathBuf> {
+    let path = container_dir.join(EXEC_FIFO);
+    let old_umask = stat::umask(Mode::empty());
+    let res = unistd::mkfifo(&path, Mode::from_bits_truncate(0o622));
+    stat::umask(old_umask);
+    res.with_context(|| format!("failed to create exec fifo {:?}", path))?;
+    Ok(path)
+}
+
+/// Fifo as seen by the container init process. It is opened with O_PATH
+/// before the rootfs is entered, because the container directory is not
+/// reachable afterwards.
+pub struct ExecFifo {
+    fd: RawFd,
+}
+
+impl ExecFifo {
+    pub fn open(path: &Path) -> Result<Self> {
+        let fd = fcntl::open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())
+            .with_context(|| format!("failed to open exec fifo {:?}", path))?;
+        Ok(Self { fd })
+    }
+
+    /// Blocks until the container is started
+    pub fn wait_for_start(self) -> Result<()> {
+        // reopening through procfs works from within the rootfs of the
+        // container, the open blocks until there is a reader
+        let path = format!("/proc/self/fd/{}", self.fd);
+        let fifo = fcntl::open(
+            path.as_str(),
+            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .context("failed to open exec fifo for writing")?;
+        let res = unistd::write(fifo, b"0");
+        let _ = unistd::close(fifo);
+        let _ = unistd::close(self.fd);
+        res.context("failed to write to exec fifo")?;
+        Ok(())
+    }
+}
+
+/// Unblocks the container init process with the given pid. Fails if the
+/// container has already been started or the init process exited before.
+pub fn start_container(container_dir: &Path, pid: Pid) -> Result<()> {
+    let path = container_dir.join(EXEC_FIFO);
+    // the fifo is opened without blocking, so an init process which has
+    // exited in the meantime does not block start forever
+    let fifo = match fcntl::open(
+        &path,
+        OFlag::O_RDONLY | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    ) {
+        Ok(fifo) => fifo,
+        Err(Errno::ENOENT) => bail!("container has already been started"),
+        Err(err) => return Err(err).with_context(|| format!("failed to open {:?}", path)),
+    };
+
+    let res = wait_for_init(fifo, pid);
+    let _ = unistd::close(fifo);
+    res?;
+
+    // only one start command reads from the fifo, so it is removed once
+    fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+    Ok(())
+}
+
+fn wait_for_init(fifo: RawFd, pid: Pid) -> Result<()> {
+    let mut fds = [PollFd::new(fifo, PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, POLL_INTERVAL.as_millis() as i32) {
+            Ok(0) | Err(Errno::EINTR) => {
+                if signal::kill(pid, None).is_err() {
+                    bail!("container init process {} has exited", pid);
+                }
+            }
+            Ok(_) => {
+                let mut buf = [0u8; 1];
+                match unistd::read(fifo, &mut buf) {
+                    Ok(1) => return Ok(()),
+                    // the writer has gone away, another start command was faster
+                    Ok(_) => bail!("container has already been started"),
+                    Err(Errno::EAGAIN) => continue,
+                    Err(err) => return Err(err).context("failed to read from exec fifo"),
+                }
+            }
+            Err(err) => return Err(err).context("failed to wait for exec fifo"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use nix::unistd::ForkResult;
+
+    #[test]
+    fn test_exec_fifo() -> Result<()> {
+        let dir = create_temp_dir("test_exec_fifo")?;
+        let path = create_exec_fifo(&dir)?;
+        let fifo = ExecFifo::open(&path)?;
+
+        let child = match unsafe { unistd::fork()? } {
+            ForkResult::Child => {
+                let code = match fifo.wait_for_start() {
+                    Ok(_) => 0,
+                    Err(_) => 1,
+                };
+                unsafe { libc::_exit(code) };
+            }
+            ForkResult::Parent { child } => child,
+        };
+
+        start_container(&dir, child)?;
+        assert!(!path.exists());
+        assert!(start_container(&dir, child).is_err());
+        assert_eq!(
+            nix::sys::wait::waitpid(child, None)?,
+            nix::sys::wait::WaitStatus::Exited(child, 0)
+        );
+        Ok(())
+    }
+}
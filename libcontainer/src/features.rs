@@ -0,0 +1,131 @@
+//! Optional kernel features that youki can make use of. Every feature is
+//! detected once per process and the result is shared by all callers, so
+//! the runtime and the `features` command agree on what the host supports. When a feature is missing, youki degrades as documented
+//! by [FeatureGate::degradation].
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use nix::errno::Errno;
+use once_cell::sync::OnceCell;
+
+static DETECTED_FEATURES: OnceCell<HashMap<FeatureGate, bool>> = OnceCell::new();
+
+// Syscall numbers which are not exported by libc yet. They are shared by all
+// architectures.
+pub(crate) const SYS_CLONE3: libc::c_long = 435;
+const SYS_MOUNT_SETATTR: libc::c_long = 442;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeatureGate {
+    /// Id-mapped mounts through mount_setattr (linux 5.12)
+    IdMappedMounts,
+    /// Killing all processes of a cgroup through cgroup.kill (linux 5.14)
+    CgroupKill,
+    /// The clone3 syscall (linux 5.3)
+    Clone3,
+}
+
+pub const FEATURE_GATES: &[FeatureGate] = &[
+    FeatureGate::IdMappedMounts,
+    FeatureGate::CgroupKill,
+    FeatureGate::Clone3,
+];
+
+impl FeatureGate {
+    /// Checks if the feature is supported by the host. The detection only
+    /// happens on the first call.
+    pub fn is_supported(&self) -> bool {
+        let detected = DETECTED_FEATURES.get_or_init(|| {
+            FEATURE_GATES
+                .iter()
+                .map(|gate| (*gate, gate.detect()))
+                .collect()
+        });
+
+        detected.get(self).copied().unwrap_or(false)
+    }
+
+    /// Describes how youki behaves if the feature is not supported
+    pub fn degradation(&self) -> &'static str {
+        match self {
+            Self::IdMappedMounts => "id-mapped mounts are rejected",
+            Self::CgroupKill => "kill --all sends SIGKILL to the processes of a cgroup one by one",
+            Self::Clone3 => "processes are moved into the cgroup after they have been created",
+        }
+    }
+
+    fn detect(&self) -> bool {
+        let supported = match self {
+            // mount_setattr fails with EBADF for an invalid fd, if the syscall exists
+            Self::IdMappedMounts => syscall_exists(unsafe {
+                libc::syscall(
+                    SYS_MOUNT_SETATTR,
+                    -1,
+                    std::ptr::null::<libc::c_char>(),
+                    0,
+                    0,
+                    0,
+                )
+            }),
+            Self::CgroupKill => detect_cgroup_kill(),
+            // clone3 fails with EINVAL for a zero sized argument, if the syscall exists
+            Self::Clone3 => syscall_exists(unsafe {
+                libc::syscall(SYS_CLONE3, std::ptr::null::<libc::c_void>(), 0)
+            }),
+        };
+
+        log::debug!("feature {} supported: {}", self, supported);
+        supported
+    }
+}
+
+impl Display for FeatureGate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::IdMappedMounts => "idmapped-mounts",
+            Self::CgroupKill => "cgroup.kill",
+            Self::Clone3 => "clone3",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+fn syscall_exists(res: libc::c_long) -> bool {
+    !(res == -1 && Errno::last() == Errno::ENOSYS)
+}
+
+// cgroup.kill exists in every non root cgroup of a unified hierarchy, so we
+// look for it in the cgroup of the current process.
+fn detect_cgroup_kill() -> bool {
+    let content = match std::fs::read_to_string("/proc/self/cgroup") {
+        Ok(content) => content,
+        Err(_) => return false,
+    };
+
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .and_then(|cgroup| {
+            cgroups::common::detect_cgroup_root()
+                .map(|root| {
+                    root.join(cgroup.trim_start_matches('/'))
+                        .join("cgroup.kill")
+                        .exists()
+                })
+                .ok()
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detection_is_stable() {
+        for gate in FEATURE_GATES {
+            assert_eq!(gate.is_supported(), gate.is_supported());
+        }
+    }
+}
@@ -1,9 +1,23 @@
 use anyhow::{bail, Context, Result};
 use nix::{sys::signal, unistd::Pid};
 use oci_spec::Hook;
-use std::{collections::HashMap, fmt, os::unix::prelude::CommandExt, process, thread, time};
+use std::{
+    collections::HashMap,
+    fmt,
+    os::unix::prelude::CommandExt,
+    path::{Path, PathBuf},
+    process, thread, time,
+};
 
-use crate::{container::Container, utils};
+use crate::{
+    container::{Container, State},
+    utils,
+};
+
+// Prefix of the variables to which the annotations of the container are
+// exported, e.g. io.kubernetes.cri.sandbox-id becomes
+// OCI_ANNOTATION_IO_KUBERNETES_CRI_SANDBOX_ID
+const ANNOTATION_ENV_PREFIX: &str = "OCI_ANNOTATION_";
 // A special error used to signal a timeout. We want to differenciate between a
 // timeout vs. other error.
 #[derive(Debug)]
@@ -24,7 +38,8 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
 
     if let Some(hooks) = hooks {
         for hook in hooks {
-            let mut hook_command = process::Command::new(&hook.path);
+            let path = hook_path(&hook.path, &state.bundle);
+            let mut hook_command = process::Command::new(&path);
             if let Some((arg0, args)) = hook.args.as_ref().map(|a| a.split_first()).flatten() {
                 log::debug!("run_hooks arg0: {:?}, args: {:?}", arg0, args);
                 hook_command.arg0(arg0).args(args)
@@ -32,11 +47,11 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
                 hook_command.arg0(&hook.path.as_path().display().to_string())
             };
 
-            let envs: HashMap<String, String> = if let Some(env) = hook.env.as_ref() {
-                utils::parse_env(env)
-            } else {
-                HashMap::new()
-            };
+            // the environment of the hook takes precedence over the state
+            let mut envs = state_env(state);
+            if let Some(env) = hook.env.as_ref() {
+                envs.extend(utils::parse_env(env));
+            }
             log::debug!("run_hooks envs: {:?}", envs);
 
             let mut hook_process = hook_command
@@ -107,9 +122,68 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
     Ok(())
 }
 
+/// Hooks with a relative path are resolved against the bundle, so they can
+/// be shipped together with the config. Paths which do not exist in the
+/// bundle are looked up in PATH as before.
+fn hook_path(path: &Path, bundle: &Path) -> PathBuf {
+    if path.is_relative() {
+        let in_bundle = bundle.join(path);
+        if in_bundle.exists() {
+            return in_bundle;
+        }
+    }
+
+    path.to_path_buf()
+}
+
+/// Exports the state of the container to the environment of a hook, so hooks
+/// do not need to parse the state from stdin for the common fields
+fn state_env(state: &State) -> HashMap<String, String> {
+    let mut envs = HashMap::new();
+    envs.insert("OCI_CONTAINER_ID".to_owned(), state.id.clone());
+    // same spelling as in the state
+    envs.insert(
+        "OCI_STATUS".to_owned(),
+        state.status.to_string().to_lowercase(),
+    );
+    envs.insert(
+        "OCI_BUNDLE".to_owned(),
+        state.bundle.to_string_lossy().into_owned(),
+    );
+    if let Some(pid) = state.pid {
+        envs.insert("OCI_PID".to_owned(), pid.to_string());
+    }
+
+    for (key, value) in state.annotations.iter().flatten() {
+        // variables can not contain a nul byte
+        if value.contains('\0') {
+            log::warn!("annotation {} is not exported to hooks", key);
+            continue;
+        }
+        envs.insert(annotation_env_name(key), value.clone());
+    }
+
+    envs
+}
+
+fn annotation_env_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}{}", ANNOTATION_ENV_PREFIX, name)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::container::ContainerStatus;
     use anyhow::{bail, Result};
     use std::path::PathBuf;
 
@@ -148,6 +222,43 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_state_env() {
+        let mut state = State::new(
+            "youki",
+            ContainerStatus::Running,
+            Some(42),
+            PathBuf::from("/bundle"),
+        );
+        let mut annotations = HashMap::new();
+        annotations.insert("io.kubernetes.cri.sandbox-id".to_owned(), "abc".to_owned());
+        state.annotations = Some(annotations);
+
+        let envs = state_env(&state);
+        assert_eq!(envs["OCI_CONTAINER_ID"], "youki");
+        assert_eq!(envs["OCI_STATUS"], "running");
+        assert_eq!(envs["OCI_BUNDLE"], "/bundle");
+        assert_eq!(envs["OCI_PID"], "42");
+        assert_eq!(envs["OCI_ANNOTATION_IO_KUBERNETES_CRI_SANDBOX_ID"], "abc");
+    }
+
+    #[test]
+    fn test_hook_path() -> Result<()> {
+        let bundle = crate::utils::create_temp_dir("test_hook_path")?;
+        std::fs::write(bundle.join("setup-network"), "")?;
+
+        assert_eq!(
+            hook_path(Path::new("setup-network"), &bundle),
+            bundle.join("setup-network")
+        );
+        assert_eq!(hook_path(Path::new("tail"), &bundle), PathBuf::from("tail"));
+        assert_eq!(
+            hook_path(Path::new("/bin/true"), &bundle),
+            PathBuf::from("/bin/true")
+        );
+        Ok(())
+    }
+
     #[test]
     #[ignore]
     // This will test executing hook with a timeout. Since the timeout is set in
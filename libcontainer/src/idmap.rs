@@ -0,0 +1,404 @@
+//! Id-mapped mounts through the new mount API (open_tree, mount_setattr and
+//! move_mount). Bind mounts with the `uidMappings` and `gidMappings` of the
+//! runtime spec are cloned and id-mapped by the runtime process with these
+//! mappings, bind mounts with the `idmap` option with the id mappings of the
+//! container. Files owned by the host ids show up with the mapped ids inside
+//! of the container and volumes can be shared without chowning them. The
+//! detached mount trees are inherited by the container init process which
+//! attaches them into the rootfs. Requires linux 5.12 or later and a
+//! filesystem supporting id-mapped mounts.
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::errno::Errno;
+use nix::mount::MsFlags;
+use nix::sched::{self, CloneFlags};
+use nix::sys::wait::waitpid;
+use nix::unistd::{self, ForkResult};
+use oci_spec::{LinuxIdMapping, Mount, Spec};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::features::FeatureGate;
+use crate::rootfs::parse_mount;
+use crate::rootless::{self, Rootless};
+use crate::utils;
+
+/// Mount option that requests an id-mapped bind mount
+pub const IDMAP_OPTION: &str = "idmap";
+
+// The new mount api is not exported by libc yet. These syscall numbers are
+// shared by all architectures.
+const SYS_OPEN_TREE: libc::c_long = 428;
+const SYS_MOVE_MOUNT: libc::c_long = 429;
+const SYS_MOUNT_SETATTR: libc::c_long = 442;
+
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+const MOUNT_ATTR_NOSUID: u64 = 0x2;
+const MOUNT_ATTR_NODEV: u64 = 0x4;
+const MOUNT_ATTR_NOEXEC: u64 = 0x8;
+const MOUNT_ATTR_IDMAP: u64 = 0x100000;
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Checks if a mount requests to be id-mapped
+pub fn is_idmapped(mount: &Mount) -> bool {
+    mount
+        .options
+        .as_ref()
+        .map(|options| options.iter().any(|o| o == IDMAP_OPTION))
+        .unwrap_or(false)
+}
+
+/// Id mappings of a single mount, which oci-spec does not know about
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MountIdMappings {
+    #[serde(default)]
+    pub uid_mappings: Vec<LinuxIdMapping>,
+    #[serde(default)]
+    pub gid_mappings: Vec<LinuxIdMapping>,
+}
+
+impl MountIdMappings {
+    /// Reads the id mappings of the mounts in the spec, keyed by the
+    /// destination of the mount
+    pub fn from_config(config: &Value) -> Result<HashMap<PathBuf, Self>> {
+        let mut mappings = HashMap::new();
+        let mounts = match config.get("mounts").and_then(Value::as_array) {
+            Some(mounts) => mounts,
+            None => return Ok(mappings),
+        };
+
+        for mount in mounts {
+            if mount.get("uidMappings").is_none() && mount.get("gidMappings").is_none() {
+                continue;
+            }
+            let destination = mount
+                .get("destination")
+                .and_then(Value::as_str)
+                .context("no destination in mount spec")?;
+            let mount_mappings: Self = serde_json::from_value(mount.clone())
+                .with_context(|| format!("invalid id mappings of mount {}", destination))?;
+            if mount_mappings.uid_mappings.is_empty() || mount_mappings.gid_mappings.is_empty() {
+                bail!(
+                    "mount {} needs both uidMappings and gidMappings to be id-mapped",
+                    destination
+                );
+            }
+            mappings.insert(PathBuf::from(destination), mount_mappings);
+        }
+
+        Ok(mappings)
+    }
+}
+
+/// Detached id-mapped mount trees, keyed by the destination of the mount
+#[derive(Debug, Default, Clone)]
+pub struct IdMappedMounts {
+    mounts: HashMap<PathBuf, RawFd>,
+}
+
+impl IdMappedMounts {
+    /// Creates a detached id-mapped mount tree for every mount in the spec
+    /// which has id mappings or the idmap option. This has to run in the
+    /// runtime process, because the caller needs to be privileged over the
+    /// filesystem of the mount source.
+    pub fn prepare(
+        spec: &Spec,
+        rootless: Option<&Rootless>,
+        mount_mappings: &HashMap<PathBuf, MountIdMappings>,
+    ) -> Result<Self> {
+        let mut idmapped = Self::default();
+        let mounts: Vec<&Mount> = match spec.mounts.as_ref() {
+            Some(mounts) => mounts
+                .iter()
+                .filter(|m| is_idmapped(m) || mount_mappings.contains_key(&m.destination))
+                .collect(),
+            None => return Ok(idmapped),
+        };
+
+        if mounts.is_empty() {
+            return Ok(idmapped);
+        }
+
+        if !FeatureGate::IdMappedMounts.is_supported() {
+            bail!("idmapped mounts are not supported by the kernel");
+        }
+
+        // the user namespace of the container is shared by all mounts which
+        // only have the idmap option
+        let mut container_userns = None;
+        for mount in mounts {
+            let mount_userns;
+            let userns = match mount_mappings.get(&mount.destination) {
+                Some(mappings) => {
+                    mount_userns = create_mapped_userns(
+                        &mappings.uid_mappings,
+                        &mappings.gid_mappings,
+                        rootless,
+                    )
+                    .with_context(|| {
+                        format!(
+                            "failed to create user namespace for {:?}",
+                            mount.destination
+                        )
+                    })?;
+                    &mount_userns
+                }
+                None => {
+                    if container_userns.is_none() {
+                        container_userns = Some(create_container_userns(rootless)?);
+                    }
+                    container_userns.as_ref().unwrap()
+                }
+            };
+            let fd = create_idmapped_tree(mount, userns)
+                .with_context(|| format!("failed to create idmapped mount {:?}", mount))?;
+            log::debug!("created idmapped mount for {:?}", mount.destination);
+            idmapped.mounts.insert(mount.destination.clone(), fd);
+        }
+
+        Ok(idmapped)
+    }
+
+    pub fn get(&self, destination: &Path) -> Option<RawFd> {
+        self.mounts.get(destination).copied()
+    }
+
+    /// Closes the detached mount trees. Must be called by the runtime process
+    /// once the container process has been forked.
+    pub fn close(&mut self) -> Result<()> {
+        for (_, fd) in self.mounts.drain() {
+            unistd::close(fd)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Attaches a detached mount tree to the given destination
+pub fn attach(fd: RawFd, dest: &Path) -> Result<()> {
+    let empty = CString::new("")?;
+    let dest = CString::new(dest.as_os_str().as_bytes())?;
+    let res = unsafe {
+        libc::syscall(
+            SYS_MOVE_MOUNT,
+            fd,
+            empty.as_ptr(),
+            libc::AT_FDCWD,
+            dest.as_ptr(),
+            MOVE_MOUNT_F_EMPTY_PATH,
+        )
+    };
+    Errno::result(res).context("move_mount failed")?;
+    Ok(())
+}
+
+fn create_idmapped_tree(mount: &Mount, userns: &File) -> Result<RawFd> {
+    let source = mount.source.as_ref().context("no source in mount spec")?;
+    let source = fs::canonicalize(source)?;
+    let (flags, _) = parse_mount(mount);
+
+    let mut open_flags = OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint;
+    if flags.contains(MsFlags::MS_REC) {
+        open_flags |= AT_RECURSIVE;
+    }
+
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let res = unsafe { libc::syscall(SYS_OPEN_TREE, libc::AT_FDCWD, source.as_ptr(), open_flags) };
+    let fd = Errno::result(res).context("open_tree failed")? as RawFd;
+
+    let mut attr = MountAttr {
+        attr_set: MOUNT_ATTR_IDMAP,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: userns.as_raw_fd() as u64,
+    };
+    for (flag, mount_attr) in [
+        (MsFlags::MS_RDONLY, MOUNT_ATTR_RDONLY),
+        (MsFlags::MS_NOSUID, MOUNT_ATTR_NOSUID),
+        (MsFlags::MS_NODEV, MOUNT_ATTR_NODEV),
+        (MsFlags::MS_NOEXEC, MOUNT_ATTR_NOEXEC),
+    ]
+    .iter()
+    {
+        if flags.contains(*flag) {
+            attr.attr_set |= mount_attr;
+        }
+    }
+
+    let mut setattr_flags = libc::AT_EMPTY_PATH as libc::c_uint;
+    if flags.contains(MsFlags::MS_REC) {
+        setattr_flags |= AT_RECURSIVE;
+    }
+
+    let empty = CString::new("")?;
+    let res = unsafe {
+        libc::syscall(
+            SYS_MOUNT_SETATTR,
+            fd,
+            empty.as_ptr(),
+            setattr_flags,
+            &mut attr as *mut MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    };
+    if let Err(err) = Errno::result(res) {
+        let _ = unistd::close(fd);
+        bail!("mount_setattr failed: {}", err);
+    }
+
+    Ok(fd)
+}
+
+fn create_container_userns(rootless: Option<&Rootless>) -> Result<File> {
+    let rootless = rootless.context(
+        "idmapped mounts require the container to create a new user namespace with id mappings",
+    )?;
+    let uid_mappings = rootless.uid_mappings.context("no uid mappings in spec")?;
+    let gid_mappings = rootless.gid_mappings.context("no gid mappings in spec")?;
+    create_mapped_userns(uid_mappings, gid_mappings, Some(rootless))
+        .context("failed to create user namespace for idmapped mounts")
+}
+
+// Creates a user namespace with the given id mappings. This is done through
+// a short lived child process, which unshares the user namespace and keeps it
+// alive until we have opened it.
+fn create_mapped_userns(
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+    rootless: Option<&Rootless>,
+) -> Result<File> {
+    let (ready_read, ready_write) = unistd::pipe()?;
+    let (done_read, done_write) = unistd::pipe()?;
+
+    match unsafe { unistd::fork()? } {
+        ForkResult::Child => {
+            let _ = unistd::close(ready_read);
+            let _ = unistd::close(done_write);
+            let ret = match sched::unshare(CloneFlags::CLONE_NEWUSER) {
+                Ok(_) => {
+                    let _ = unistd::write(ready_write, &[0]);
+                    0
+                }
+                Err(_) => -1,
+            };
+            let _ = unistd::close(ready_write);
+            // wait until the parent is done with the namespace
+            let mut buf = [0u8; 1];
+            let _ = unistd::read(done_read, &mut buf);
+            std::process::exit(ret);
+        }
+        ForkResult::Parent { child } => {
+            unistd::close(ready_write)?;
+            unistd::close(done_read)?;
+
+            let mut buf = [0u8; 1];
+            let ready = unistd::read(ready_read, &mut buf);
+            let _ = unistd::close(ready_read);
+
+            let privileged = rootless.map_or_else(|| unistd::geteuid().is_root(), |r| r.privileged);
+            let userns = match ready {
+                Ok(1) => (|| -> Result<File> {
+                    if !privileged {
+                        utils::write_file(format!("/proc/{}/setgroups", child), "deny")?;
+                    }
+                    rootless::write_id_mapping(
+                        child,
+                        "uid_map",
+                        uid_mappings,
+                        rootless.and_then(|r| r.newuidmap.as_deref()),
+                    )?;
+                    rootless::write_id_mapping(
+                        child,
+                        "gid_map",
+                        gid_mappings,
+                        rootless.and_then(|r| r.newgidmap.as_deref()),
+                    )?;
+                    let userns = File::open(format!("/proc/{}/ns/user", child))?;
+                    Ok(userns)
+                })(),
+                _ => Err(anyhow!("child failed to unshare user namespace")),
+            };
+
+            let _ = unistd::close(done_write);
+            waitpid(child, None)?;
+            userns
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_idmapped() {
+        let mut mount = Mount {
+            destination: PathBuf::from("/data"),
+            typ: Some("bind".to_string()),
+            source: Some(PathBuf::from("/var/lib/data")),
+            options: Some(vec!["rbind".to_string(), "ro".to_string()]),
+        };
+        assert!(!is_idmapped(&mount));
+
+        mount
+            .options
+            .as_mut()
+            .unwrap()
+            .push(IDMAP_OPTION.to_string());
+        assert!(is_idmapped(&mount));
+    }
+
+    #[test]
+    fn test_mount_id_mappings_from_config() -> Result<()> {
+        let config = serde_json::json!({
+            "mounts": [
+                {"destination": "/proc", "type": "proc", "source": "proc"},
+                {
+                    "destination": "/data",
+                    "type": "bind",
+                    "source": "/var/lib/data",
+                    "options": ["rbind"],
+                    "uidMappings": [{"containerID": 0, "hostID": 1000, "size": 1}],
+                    "gidMappings": [{"containerID": 0, "hostID": 1000, "size": 1}]
+                }
+            ]
+        });
+        let mappings = MountIdMappings::from_config(&config)?;
+        assert_eq!(mappings.len(), 1);
+        let data = &mappings[Path::new("/data")];
+        assert_eq!(data.uid_mappings[0].container_id, 0);
+        assert_eq!(data.uid_mappings[0].host_id, 1000);
+        assert_eq!(data.gid_mappings[0].size, 1);
+
+        // the mappings of a mount are only complete with both kinds of ids
+        let config = serde_json::json!({
+            "mounts": [{
+                "destination": "/data",
+                "type": "bind",
+                "source": "/var/lib/data",
+                "uidMappings": [{"containerID": 0, "hostID": 1000, "size": 1}]
+            }]
+        });
+        assert!(MountIdMappings::from_config(&config).is_err());
+        assert!(MountIdMappings::from_config(&serde_json::json!({}))?.is_empty());
+        Ok(())
+    }
+}
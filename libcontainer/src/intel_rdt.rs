@@ -0,0 +1,170 @@
+//! Intel Resource Director Technology as described by the `linux.intelRdt`
+//! object of the runtime spec. The cache allocation (CAT) and memory bandwidth
+//! allocation (MBA) of a container are controlled through a group in the
+//! resctrl filesystem, which is configured by writing the schemata of the spec
+//! and joined by writing the pid of the container process to its tasks file.
+//! The oci-spec crate only knows about the l3 cache schema, so the object is
+//! read from the json of the spec directly.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::Pid;
+use procfs::process::Process;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils;
+
+const RESCTRL_FS_TYPE: &str = "resctrl";
+const SCHEMATA_FILE: &str = "schemata";
+const TASKS_FILE: &str = "tasks";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntelRdt {
+    /// Name of the resctrl group, the id of the container is used if it is
+    /// not set. A group with an explicit name may be shared by containers.
+    #[serde(rename = "closID", skip_serializing_if = "Option::is_none")]
+    pub clos_id: Option<String>,
+    /// Schema of the l3 cache allocation, e.g. "L3:0=ffff0;1=3ff"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub l3_cache_schema: Option<String>,
+    /// Schema of the memory bandwidth allocation, e.g. "MB:0=20;1=70"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_bw_schema: Option<String>,
+}
+
+impl IntelRdt {
+    /// Reads the intel rdt configuration from the spec
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        let intel_rdt: Option<Self> = utils::spec_field(config, "/linux/intelRdt")?;
+        if let Some(intel_rdt) = intel_rdt.as_ref() {
+            intel_rdt.validate()?;
+        }
+
+        Ok(intel_rdt)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(clos_id) = self.clos_id.as_deref() {
+            if clos_id.is_empty() || clos_id == "." || clos_id == ".." || clos_id.contains('/') {
+                bail!("invalid intel rdt closID {:?}", clos_id);
+            }
+        }
+
+        validate_schema(self.l3_cache_schema.as_deref(), "L3")?;
+        validate_schema(self.mem_bw_schema.as_deref(), "MB")?;
+        Ok(())
+    }
+
+    /// Moves the process into the resctrl group of the container. The group
+    /// is created and configured with the schemata of the spec if necessary.
+    pub fn apply(&self, container_id: &str, pid: Pid) -> Result<()> {
+        let group = self.group_path(container_id)?;
+        log::debug!("apply intel rdt group {:?} to {}", group, pid);
+        utils::create_dir_all(&group).context("failed to create resctrl group")?;
+
+        let schemata = self.schemata();
+        if !schemata.is_empty() {
+            utils::write_file(group.join(SCHEMATA_FILE), schemata)
+                .context("failed to write intel rdt schemata")?;
+        }
+
+        utils::write_file(group.join(TASKS_FILE), pid.to_string())
+            .context("failed to join resctrl group")?;
+        Ok(())
+    }
+
+    /// Removes the resctrl group of the container. Groups with an explicit
+    /// closID are left alone, because other containers may still use them.
+    pub fn remove(&self, container_id: &str) -> Result<()> {
+        if self.clos_id.is_some() {
+            return Ok(());
+        }
+
+        let group = self.group_path(container_id)?;
+        log::debug!("remove intel rdt group {:?}", group);
+        match fs::remove_dir(&group) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to remove resctrl group {:?}", group))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn group_path(&self, container_id: &str) -> Result<PathBuf> {
+        let name = self.clos_id.as_deref().unwrap_or(container_id);
+        Ok(find_resctrl_mount()?.join(name))
+    }
+
+    // Every line of a schema configures one resource, the lines of both
+    // schemas are written at once
+    fn schemata(&self) -> String {
+        self.l3_cache_schema
+            .iter()
+            .chain(self.mem_bw_schema.iter())
+            .flat_map(|schema| schema.lines())
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+fn validate_schema(schema: Option<&str>, resource: &str) -> Result<()> {
+    if let Some(schema) = schema {
+        for line in schema.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            // the l3 cache may be split into code and data with CDP enabled
+            if !line.starts_with(resource) || !line.contains(':') {
+                bail!("invalid intel rdt schema {:?}, expected {}", line, resource);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the mount point of the resctrl filesystem
+pub fn find_resctrl_mount() -> Result<PathBuf> {
+    Process::myself()?
+        .mountinfo()?
+        .into_iter()
+        .find(|m| m.fs_type == RESCTRL_FS_TYPE)
+        .map(|m| m.mount_point)
+        .context("intel rdt is not supported, the resctrl filesystem is not mounted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel_rdt() {
+        let intel_rdt: IntelRdt = serde_json::from_str(
+            r#"{"closID": "guaranteed", "l3CacheSchema": "L3:0=ffff0;1=3ff", "memBwSchema": "MB:0=20;1=70"}"#,
+        )
+        .unwrap();
+        assert!(intel_rdt.validate().is_ok());
+        assert_eq!(intel_rdt.schemata(), "L3:0=ffff0;1=3ff\nMB:0=20;1=70\n");
+
+        let intel_rdt = IntelRdt {
+            l3_cache_schema: Some("L3CODE:0=ff\nL3DATA:0=f0".to_owned()),
+            ..Default::default()
+        };
+        assert!(intel_rdt.validate().is_ok());
+
+        let intel_rdt = IntelRdt {
+            mem_bw_schema: Some("L3:0=ff".to_owned()),
+            ..Default::default()
+        };
+        assert!(intel_rdt.validate().is_err());
+
+        let intel_rdt = IntelRdt {
+            clos_id: Some("../escape".to_owned()),
+            ..Default::default()
+        };
+        assert!(intel_rdt.validate().is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! Creation and management of containers according to the OCI runtime
+//! specification. This is the engine of youki, which can also be used to
+//! drive containers without spawning the youki binary.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use libcontainer::container::builder::ContainerBuilder;
+//!
+//! let container = ContainerBuilder::new("74f1a4cb3801".to_owned())
+//!     .with_root_path("/run/youki")
+//!     .as_init("/var/run/docker/bundle")
+//!     .with_systemd(false)
+//!     .build()?;
+//! container.start()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! With the `tokio` feature, [async_api] offers the lifecycle operations to
+//! async embedders.
+#[cfg(feature = "tokio")]
+pub mod async_api;
+pub mod capabilities;
+pub mod container;
+pub mod error;
+pub mod event_stream;
+pub mod exec_fifo;
+pub mod features;
+pub mod hooks;
+pub mod idmap;
+pub mod intel_rdt;
+pub mod lsm;
+pub mod metrics;
+pub mod namespaces;
+pub mod nested;
+pub mod network_setup;
+pub mod notify_socket;
+pub mod nri;
+pub mod process;
+pub mod rootfs;
+pub mod rootless;
+pub mod securejoin;
+pub mod signal;
+pub mod spec_compat;
+pub mod supervisor;
+pub mod syscall;
+pub mod trace;
+pub mod tty;
+pub mod utils;
+pub mod validation;
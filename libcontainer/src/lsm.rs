@@ -0,0 +1,176 @@
+//! Linux security modules which label the container process. The spec can ask
+//! for an AppArmor profile and SELinux labels, which only work if the module is
+//! enabled in the kernel. Otherwise writing the label fails with an error that
+//! does not point to the cause, so the availability is checked when the spec
+//! is loaded. Hosts which run the same bundles with and without the module can
+//! set an annotation to create the container without the labels instead.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use oci_spec::Spec;
+
+/// Creates the container without the labels of a module which is not
+/// enabled when set to "true"
+pub const TOLERATE_ANNOTATION: &str = "youki.lsm.tolerate_missing";
+
+// Comma separated list of the active modules, requires securityfs
+const ACTIVE_LSMS_PATH: &str = "/sys/kernel/security/lsm";
+const APPARMOR_ENABLED_PATH: &str = "/sys/module/apparmor/parameters/enabled";
+const SELINUXFS_PATH: &str = "/sys/fs/selinux";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lsm {
+    AppArmor,
+    SELinux,
+}
+
+impl Lsm {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::AppArmor => "apparmor",
+            Self::SELinux => "selinux",
+        }
+    }
+
+    /// Returns if the module is enabled in the running kernel
+    pub fn is_enabled(&self) -> bool {
+        if let Ok(active) = fs::read_to_string(ACTIVE_LSMS_PATH) {
+            return parse_active_lsms(&active).contains(&self.name());
+        }
+
+        // securityfs is not mounted, fall back to the interfaces of the modules
+        match self {
+            Self::AppArmor => fs::read_to_string(APPARMOR_ENABLED_PATH)
+                .map(|enabled| enabled.trim() == "Y")
+                .unwrap_or(false),
+            Self::SELinux => Path::new(SELINUXFS_PATH).join("enforce").exists(),
+        }
+    }
+}
+
+impl Display for Lsm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let print = match self {
+            Self::AppArmor => "AppArmor",
+            Self::SELinux => "SELinux",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+fn parse_active_lsms(active: &str) -> Vec<&str> {
+    active.trim().split(',').map(str::trim).collect()
+}
+
+/// Checks that the modules which the spec relies on are enabled. If they are
+/// not and the annotation tolerates it, the labels are removed from the spec.
+pub fn check_spec(spec: &mut Spec) -> Result<()> {
+    let tolerate = tolerates_missing(spec.annotations.as_ref())?;
+    check_spec_with(spec, tolerate, Lsm::is_enabled)
+}
+
+fn check_spec_with<F: Fn(&Lsm) -> bool>(spec: &mut Spec, tolerate: bool, enabled: F) -> Result<()> {
+    let mut labels: Vec<(Lsm, &str, &mut Option<String>)> = Vec::new();
+    if let Some(process) = spec.process.as_mut() {
+        labels.push((
+            Lsm::AppArmor,
+            "process.apparmorProfile",
+            &mut process.apparmor_profile,
+        ));
+        labels.push((
+            Lsm::SELinux,
+            "process.selinuxLabel",
+            &mut process.selinux_label,
+        ));
+    }
+    if let Some(linux) = spec.linux.as_mut() {
+        labels.push((Lsm::SELinux, "linux.mountLabel", &mut linux.mount_label));
+    }
+
+    for (lsm, field, label) in labels {
+        if label.as_deref().map_or(true, str::is_empty) || enabled(&lsm) {
+            continue;
+        }
+
+        if !tolerate {
+            bail!(
+                "{} is set, but {} is not enabled in the kernel. Remove the label from the spec \
+                or set the annotation {}=true to create the container without it",
+                field,
+                lsm,
+                TOLERATE_ANNOTATION
+            );
+        }
+
+        log::warn!("{} is not enabled in the kernel, ignoring {}", lsm, field);
+        *label = None;
+    }
+
+    Ok(())
+}
+
+fn tolerates_missing(annotations: Option<&HashMap<String, String>>) -> Result<bool> {
+    match annotations
+        .and_then(|a| a.get(TOLERATE_ANNOTATION))
+        .map(|v| v.trim())
+    {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(value) => bail!(
+            "invalid value {:?} for annotation {}, expected true or false",
+            value,
+            TOLERATE_ANNOTATION
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> Spec {
+        let mut spec = Spec::default();
+        spec.process.as_mut().unwrap().apparmor_profile = Some("youki-default".to_owned());
+        spec.linux.as_mut().unwrap().mount_label =
+            Some("system_u:object_r:container_file_t:s0".to_owned());
+        spec
+    }
+
+    #[test]
+    fn test_parse_active_lsms() {
+        assert_eq!(
+            parse_active_lsms("lockdown,capability,yama,apparmor\n"),
+            vec!["lockdown", "capability", "yama", "apparmor"]
+        );
+    }
+
+    #[test]
+    fn test_check_spec() -> Result<()> {
+        let mut spec = spec();
+        check_spec_with(&mut spec, false, |_| true)?;
+        assert!(spec.process.as_ref().unwrap().apparmor_profile.is_some());
+
+        let err = check_spec_with(&mut spec, false, |lsm| *lsm == Lsm::AppArmor).unwrap_err();
+        assert!(err.to_string().starts_with("linux.mountLabel is set"));
+
+        check_spec_with(&mut spec, true, |lsm| *lsm == Lsm::AppArmor)?;
+        assert!(spec.process.as_ref().unwrap().apparmor_profile.is_some());
+        assert_eq!(spec.linux.as_ref().unwrap().mount_label, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerates_missing() -> Result<()> {
+        let mut annotations = HashMap::new();
+        assert!(!tolerates_missing(Some(&annotations))?);
+        annotations.insert(TOLERATE_ANNOTATION.to_owned(), "true".to_owned());
+        assert!(tolerates_missing(Some(&annotations))?);
+        annotations.insert(TOLERATE_ANNOTATION.to_owned(), "yes".to_owned());
+        assert!(tolerates_missing(Some(&annotations)).is_err());
+        Ok(())
+    }
+}
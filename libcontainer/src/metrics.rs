@@ -0,0 +1,274 @@
+//! Resource usage of the containers in the text exposition format of
+//! Prometheus, see https://prometheus.io/docs/instrumenting/exposition_formats/
+//! The metrics are read from the cgroups of the containers whenever they are
+//! collected, so nothing has to run in the background.
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use cgroups::{common, stats::Stats};
+
+use crate::container::{state::State, Container, ContainerStatus};
+use crate::utils;
+
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        }
+    }
+}
+
+// A metric with a single value per container
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    typ: MetricType,
+    value: fn(&Stats) -> f64,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "youki_container_cpu_usage_seconds_total",
+        help: "Cpu time consumed by the container",
+        typ: MetricType::Counter,
+        value: |s| s.cpu.usage.usage_total as f64 / NANOS_PER_SEC,
+    },
+    Metric {
+        name: "youki_container_cpu_user_seconds_total",
+        help: "Cpu time consumed by the container in user mode",
+        typ: MetricType::Counter,
+        value: |s| s.cpu.usage.usage_user as f64 / NANOS_PER_SEC,
+    },
+    Metric {
+        name: "youki_container_cpu_system_seconds_total",
+        help: "Cpu time consumed by the container in kernel mode",
+        typ: MetricType::Counter,
+        value: |s| s.cpu.usage.usage_kernel as f64 / NANOS_PER_SEC,
+    },
+    Metric {
+        name: "youki_container_cpu_throttled_periods_total",
+        help: "Number of periods in which the container has been throttled",
+        typ: MetricType::Counter,
+        value: |s| s.cpu.throttling.throttled_periods as f64,
+    },
+    Metric {
+        name: "youki_container_cpu_throttled_seconds_total",
+        help: "Time for which the container has been throttled",
+        typ: MetricType::Counter,
+        value: |s| s.cpu.throttling.throttled_time as f64 / NANOS_PER_SEC,
+    },
+    Metric {
+        name: "youki_container_memory_usage_bytes",
+        help: "Memory used by the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.memory.usage as f64,
+    },
+    Metric {
+        name: "youki_container_memory_max_usage_bytes",
+        help: "Maximum memory used by the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.memory.max_usage as f64,
+    },
+    Metric {
+        name: "youki_container_memory_limit_bytes",
+        help: "Memory limit of the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.memory.limit as f64,
+    },
+    Metric {
+        name: "youki_container_memory_cache_bytes",
+        help: "Page cache used by the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.cache as f64,
+    },
+    Metric {
+        name: "youki_container_memory_rss_bytes",
+        help: "Anonymous memory used by the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.rss as f64,
+    },
+    Metric {
+        name: "youki_container_memory_swap_usage_bytes",
+        help: "Memory and swap used by the container",
+        typ: MetricType::Gauge,
+        value: |s| s.memory.memswap.usage as f64,
+    },
+    Metric {
+        name: "youki_container_oom_kills_total",
+        help: "Number of processes of the container killed by the OOM killer",
+        typ: MetricType::Counter,
+        value: |s| s.memory.oom_kill as f64,
+    },
+    Metric {
+        name: "youki_container_pids_current",
+        help: "Number of processes in the container",
+        typ: MetricType::Gauge,
+        value: |s| s.pids.current as f64,
+    },
+    Metric {
+        name: "youki_container_pids_limit",
+        help: "Maximum number of processes in the container, 0 if unlimited",
+        typ: MetricType::Gauge,
+        value: |s| s.pids.limit as f64,
+    },
+];
+
+/// Collects the metrics of all running and paused containers in the root
+/// directory. Containers whose stats can not be read, e.g. because they have
+/// been deleted in the meantime, are skipped.
+pub fn collect(root_path: &Path) -> Result<String> {
+    let mut containers = Vec::new();
+    for container_dir in fs::read_dir(root_path)
+        .with_context(|| format!("failed to read root directory {:?}", root_path))?
+    {
+        let container_dir = container_dir?.path();
+        if !State::file_path(&container_dir).exists() {
+            continue;
+        }
+
+        match container_stats(&container_dir) {
+            Ok(Some((id, stats))) => containers.push((id, stats)),
+            Ok(None) => {}
+            Err(err) => log::warn!("failed to get stats of {:?}: {:?}", container_dir, err),
+        }
+    }
+
+    containers.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(render(&containers))
+}
+
+// Returns the stats of the container, if it has processes
+fn container_stats(container_dir: &Path) -> Result<Option<(String, Stats)>> {
+    let container = Container::load(container_dir.to_path_buf())?.refresh_status()?;
+    if !matches!(
+        container.status(),
+        ContainerStatus::Running | ContainerStatus::Paused
+    ) {
+        return Ok(None);
+    }
+
+    let use_systemd = container
+        .systemd()
+        .context("could not determine cgroup manager")?;
+    let cgroups_path = utils::get_cgroup_path(
+        &container
+            .spec()?
+            .linux
+            .context("no linux in spec")?
+            .cgroups_path,
+        container.id(),
+        use_systemd,
+    );
+    let cgroup_manager = common::create_cgroup_manager_with_root(
+        container.cgroup_root(),
+        cgroups_path,
+        use_systemd,
+    )?;
+
+    Ok(Some((container.id().to_owned(), cgroup_manager.stats()?)))
+}
+
+/// Renders the stats of the containers, which are identified by the id label
+pub fn render(containers: &[(String, Stats)]) -> String {
+    let mut out = String::new();
+    for metric in METRICS {
+        write_header(&mut out, metric.name, metric.help, metric.typ);
+        for (id, stats) in containers {
+            let _ = writeln!(
+                out,
+                "{}{{id=\"{}\"}} {}",
+                metric.name,
+                escape(id),
+                (metric.value)(stats)
+            );
+        }
+    }
+
+    // block io is reported per device and operation
+    let name = "youki_container_blkio_service_bytes_total";
+    write_header(
+        &mut out,
+        name,
+        "Bytes transferred from and to the block devices by the container",
+        MetricType::Counter,
+    );
+    for (id, stats) in containers {
+        for device in &stats.blkio.service_bytes {
+            let _ = writeln!(
+                out,
+                "{}{{id=\"{}\",device=\"{}:{}\",operation=\"{}\"}} {}",
+                name,
+                escape(id),
+                device.major,
+                device.minor,
+                escape(device.op_type.as_deref().unwrap_or_default()),
+                device.value
+            );
+        }
+    }
+
+    out
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, typ: MetricType) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ.as_str());
+}
+
+// Label values are quoted, so backslashes, quotes and newlines are escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgroups::stats::BlkioDeviceStat;
+
+    #[test]
+    fn test_render() {
+        let mut stats = Stats::default();
+        stats.cpu.usage.usage_total = 1_500_000_000;
+        stats.memory.memory.usage = 4096;
+        stats.pids.current = 3;
+        stats.blkio.service_bytes = vec![BlkioDeviceStat {
+            major: 8,
+            minor: 0,
+            op_type: Some("Read".to_owned()),
+            value: 512,
+        }];
+
+        let metrics = render(&[("test".to_owned(), stats)]);
+        assert!(metrics.contains(
+            "# HELP youki_container_cpu_usage_seconds_total Cpu time consumed by the container\n\
+             # TYPE youki_container_cpu_usage_seconds_total counter\n\
+             youki_container_cpu_usage_seconds_total{id=\"test\"} 1.5\n"
+        ));
+        assert!(metrics.contains("youki_container_memory_usage_bytes{id=\"test\"} 4096\n"));
+        assert!(metrics.contains("# TYPE youki_container_pids_current gauge\n"));
+        assert!(metrics.contains("youki_container_pids_current{id=\"test\"} 3\n"));
+        assert!(metrics.contains(
+            "youki_container_blkio_service_bytes_total{id=\"test\",device=\"8:0\",operation=\"Read\"} 512\n"
+        ));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -67,7 +67,7 @@ impl Namespaces {
             self.command.unshare(get_clone_flag(namespace.typ))?;
         } else {
             let ns_path = namespace.path.as_ref().unwrap();
-            let fd = fcntl::open(ns_path, fcntl::OFlag::empty(), stat::Mode::empty())
+            let fd = fcntl::open(ns_path, fcntl::OFlag::O_CLOEXEC, stat::Mode::empty())
                 .with_context(|| format!("Failed to open namespace fd: {:?}", ns_path))?;
             self.command
                 .set_ns(fd, get_clone_flag(namespace.typ))
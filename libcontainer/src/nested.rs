@@ -0,0 +1,213 @@
+//! Detection of youki running inside of another container, e.g. docker in
+//! docker or a Kubernetes CI job. The outer runtime usually takes away some
+//! privileges which youki would otherwise rely on: device nodes cannot be
+//! created, the cgroup filesystem is mounted read-only and parts of /proc are
+//! masked. Knowing about this allows youki to fall back to alternatives where
+//! possible and to explain failures where it is not.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use caps::{CapSet, Capability};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use once_cell::sync::OnceCell;
+use procfs::process::Process;
+
+/// Does not apply the device rules of the container when set to "true". The
+/// devices controller can not be managed from within an unprivileged container,
+/// the outer runtime restricts the devices instead.
+pub const SKIP_DEVICES_ANNOTATION: &str = "youki.cgroup.skip_devices";
+/// Same as SKIP_DEVICES_ANNOTATION under the name runc uses, so bundles of
+/// outer runtimes which set it for runc work with youki as well
+pub const RUNC_SKIP_DEVICES_ANNOTATION: &str = "org.opencontainers.runc.skip-devices";
+
+static NESTED_ENVIRONMENT: OnceCell<NestedEnvironment> = OnceCell::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NestedEnvironment {
+    /// Name of the container manager youki is running under, if any
+    pub container: Option<String>,
+    /// Device nodes can be created with mknod
+    pub mknod_permitted: bool,
+    /// Some entries below /proc are masked by mounts over them, in which case
+    /// the kernel refuses to mount a new procfs for the container
+    pub proc_masked: bool,
+}
+
+impl NestedEnvironment {
+    /// Detects the environment youki is running in. The detection only happens
+    /// on the first call, so it should be done by the runtime process before
+    /// any namespace is entered. The forked container processes inherit the
+    /// result.
+    pub fn detect() -> &'static Self {
+        NESTED_ENVIRONMENT.get_or_init(|| {
+            let env = Self {
+                container: detect_container(),
+                mknod_permitted: caps::has_cap(None, CapSet::Effective, Capability::CAP_MKNOD)
+                    .unwrap_or(false),
+                proc_masked: detect_proc_masked(),
+            };
+            log::debug!("detected environment: {:?}", env);
+            env
+        })
+    }
+
+    pub fn is_nested(&self) -> bool {
+        self.container.is_some()
+    }
+
+    /// Checks if the cgroup filesystem is mounted read-only. It is checked at
+    /// the given cgroup root, or at the detected one if there is none, on
+    /// every call, because the root can differ between containers.
+    pub fn cgroup_readonly(&self, cgroup_root: Option<&Path>) -> bool {
+        let cgroup_root = match cgroup_root {
+            Some(cgroup_root) => Some(cgroup_root.to_path_buf()),
+            None => cgroups::common::detect_cgroup_root().ok(),
+        };
+        cgroup_root
+            .and_then(|root| statvfs(&root).ok())
+            .map(|stat| stat.flags().contains(FsFlags::ST_RDONLY))
+            .unwrap_or(false)
+    }
+
+    /// Describes how youki is limited by the environment and what it does
+    /// about it, with the cgroups mounted at the given cgroup root
+    pub fn limitations(&self, cgroup_root: Option<&Path>) -> Vec<(&'static str, &'static str)> {
+        let mut limitations = Vec::new();
+        if !self.mknod_permitted {
+            limitations.push(("mknod", "not permitted, devices are bind mounted"));
+        }
+        if self.cgroup_readonly(cgroup_root) {
+            limitations.push(("cgroup", "read-only, resource limits are not applied"));
+        }
+        if self.proc_masked {
+            limitations.push(("/proc", "masked, mounting procfs may be refused"));
+        }
+
+        limitations
+    }
+}
+
+/// Returns if the device rules of the container are skipped, see
+/// SKIP_DEVICES_ANNOTATION. The annotation of youki takes precedence over the
+/// one of runc.
+pub fn skip_devices(annotations: Option<&HashMap<String, String>>) -> Result<bool> {
+    let annotation = annotations.and_then(|a| {
+        [SKIP_DEVICES_ANNOTATION, RUNC_SKIP_DEVICES_ANNOTATION]
+            .iter()
+            .find_map(|key| a.get(*key).map(|value| (*key, value.trim())))
+    });
+    match annotation {
+        None | Some((_, "false")) => Ok(false),
+        Some((_, "true")) => Ok(true),
+        Some((key, value)) => bail!(
+            "invalid value {:?} for annotation {}, expected true or false",
+            value,
+            key
+        ),
+    }
+}
+
+fn detect_container() -> Option<String> {
+    // systemd and most container managers set the container environment
+    // variable for the init process of the container
+    if let Ok(environ) = fs::read("/proc/1/environ") {
+        let container = environ
+            .split(|b| *b == 0)
+            .find_map(|var| var.strip_prefix(b"container="))
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+        if let Some(container) = container.filter(|c| !c.is_empty()) {
+            return Some(container);
+        }
+    }
+
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker".to_owned());
+    }
+
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman".to_owned());
+    }
+
+    if let Ok(content) = fs::read_to_string("/proc/1/cgroup") {
+        if let Some(container) = container_from_cgroup(&content) {
+            return Some(container.to_owned());
+        }
+    }
+
+    if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+        return Some("kubernetes".to_owned());
+    }
+
+    None
+}
+
+// On cgroup v1 hosts the cgroup of a containerized process still reveals the
+// container manager. With cgroup namespaces this is hidden behind "/".
+fn container_from_cgroup(content: &str) -> Option<&'static str> {
+    content
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .find_map(|path| {
+            if path.contains("kubepods") {
+                Some("kubernetes")
+            } else if path.contains("docker") {
+                Some("docker")
+            } else if path.contains("libpod") {
+                Some("podman")
+            } else if path.starts_with("/lxc") {
+                Some("lxc")
+            } else {
+                None
+            }
+        })
+}
+
+fn detect_proc_masked() -> bool {
+    Process::myself()
+        .and_then(|p| p.mountinfo())
+        .map(|mounts| {
+            mounts
+                .iter()
+                .any(|m| m.mount_point.starts_with("/proc") && m.mount_point != Path::new("/proc"))
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_from_cgroup() {
+        let docker = "12:pids:/docker/9f3ae54d\n0::/docker/9f3ae54d\n";
+        assert_eq!(container_from_cgroup(docker), Some("docker"));
+
+        let kubernetes = "11:memory:/kubepods/besteffort/pod1234/abcd\n";
+        assert_eq!(container_from_cgroup(kubernetes), Some("kubernetes"));
+
+        let host = "0::/init.scope\n";
+        assert_eq!(container_from_cgroup(host), None);
+    }
+
+    #[test]
+    fn test_skip_devices() -> Result<()> {
+        assert!(!skip_devices(None)?);
+
+        let mut annotations = HashMap::new();
+        annotations.insert(SKIP_DEVICES_ANNOTATION.to_owned(), "true".to_owned());
+        assert!(skip_devices(Some(&annotations))?);
+
+        annotations.insert(SKIP_DEVICES_ANNOTATION.to_owned(), "yes".to_owned());
+        assert!(skip_devices(Some(&annotations)).is_err());
+
+        let mut annotations = HashMap::new();
+        annotations.insert(RUNC_SKIP_DEVICES_ANNOTATION.to_owned(), "true".to_owned());
+        assert!(skip_devices(Some(&annotations))?);
+
+        annotations.insert(SKIP_DEVICES_ANNOTATION.to_owned(), "false".to_owned());
+        assert!(!skip_devices(Some(&annotations))?);
+        Ok(())
+    }
+}
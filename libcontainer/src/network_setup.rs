@@ -0,0 +1,193 @@
+//! Network setup of a container through a binary of the user. A rootless
+//! container can not be connected to the network of the host by youki, tools
+//! like slirp4netns or pasta do that from outside of the container instead.
+//! The binary is run once the network namespace of the container exists and
+//! before the container is started:
+//!
+//! ```text
+//! <binary> setup <container id> <pid> <path of the network namespace>
+//! ```
+//!
+//! and when the container is deleted:
+//!
+//! ```text
+//! <binary> cleanup <container id> [<pid>]
+//! ```
+//!
+//! The pid is left out if the container process is not known anymore. The
+//! binary has to return once the network is set up, so a long running helper
+//! like slirp4netns has to be started in the background and stopped again by
+//! the cleanup. Only the exit status of the binary is waited for, so the
+//! helper may keep its stdout and stderr open.
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use nix::sys::memfd::{self, MemFdCreateFlag};
+use nix::unistd::Pid;
+use oci_spec::{LinuxNamespaceType, Spec};
+
+use crate::namespaces::Namespaces;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkSetup {
+    binary: PathBuf,
+}
+
+impl NetworkSetup {
+    pub fn new<P: Into<PathBuf>>(binary: P) -> Self {
+        Self {
+            binary: binary.into(),
+        }
+    }
+
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    /// Sets up the network namespace of the container process. Nothing is
+    /// done if the container shares the network namespace of the host or
+    /// joins an existing one.
+    pub fn setup(&self, container_id: &str, pid: Pid, spec: &Spec) -> Result<()> {
+        let linux = spec.linux.as_ref().context("no linux in spec")?;
+        let namespaces = Namespaces::from(linux.namespaces.as_ref());
+        match namespaces.get(LinuxNamespaceType::Network) {
+            Some(netns) if netns.path.is_none() => {}
+            _ => {
+                log::warn!(
+                    "network of container {} is not set up by {:?}, it has no network namespace of its own",
+                    container_id,
+                    self.binary
+                );
+                return Ok(());
+            }
+        }
+
+        let netns = format!("/proc/{}/ns/net", pid);
+        self.run(&["setup", container_id, &pid.to_string(), &netns])
+            .with_context(|| format!("failed to set up network of container {}", container_id))
+    }
+
+    /// Releases what has been set up for the container, the container process
+    /// may have exited already
+    pub fn cleanup(&self, container_id: &str, pid: Option<Pid>) -> Result<()> {
+        let pid = pid.map(|pid| pid.to_string());
+        let mut args = vec!["cleanup", container_id];
+        args.extend(pid.as_deref());
+        self.run(&args)
+            .with_context(|| format!("failed to clean up network of container {}", container_id))
+    }
+
+    fn run(&self, args: &[&str]) -> Result<()> {
+        log::debug!("run network setup {:?} {:?}", self.binary, args);
+        // A helper which is left running in the background inherits stdout
+        // and stderr, so they must not be pipes which are read until the end.
+        // stderr is kept in a file for the error message instead.
+        let mut stderr = stderr_file()?;
+        let status = Command::new(&self.binary)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(stderr.try_clone()?)
+            .status()
+            .with_context(|| format!("failed to execute {:?}", self.binary))?;
+        if !status.success() {
+            let mut message = String::new();
+            stderr.seek(SeekFrom::Start(0))?;
+            let _ = stderr.read_to_string(&mut message);
+            bail!(
+                "{:?} exited with {}: {}",
+                self.binary,
+                status,
+                message.trim()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn stderr_file() -> Result<File> {
+    let fd = memfd::memfd_create(
+        &CString::new("youki-network-setup")?,
+        MemFdCreateFlag::MFD_CLOEXEC,
+    )
+    .context("failed to create file for the stderr of network setup")?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use oci_spec::LinuxNamespace;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn setup_binary(dir: &Path, script: &str) -> Result<PathBuf> {
+        let binary = dir.join("network-setup");
+        fs::write(&binary, format!("#!/bin/sh\n{}\n", script))?;
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755))?;
+        Ok(binary)
+    }
+
+    #[test]
+    fn test_setup_and_cleanup() -> Result<()> {
+        let tmp = create_temp_dir("test_network_setup_and_cleanup")?;
+        let log = tmp.join("log");
+        let binary = setup_binary(&tmp, &format!("echo \"$@\" >> {}", log.display()))?;
+        let network_setup = NetworkSetup::new(binary);
+
+        let mut spec = Spec::default();
+        spec.linux.as_mut().unwrap().namespaces = Some(vec![LinuxNamespace {
+            typ: LinuxNamespaceType::Network,
+            path: None,
+        }]);
+        network_setup.setup("test", Pid::from_raw(42), &spec)?;
+        network_setup.cleanup("test", Some(Pid::from_raw(42)))?;
+        assert_eq!(
+            fs::read_to_string(&log)?,
+            "setup test 42 /proc/42/ns/net\ncleanup test 42\n"
+        );
+
+        // without a network namespace of its own there is nothing to set up
+        spec.linux.as_mut().unwrap().namespaces = Some(Vec::new());
+        network_setup.setup("other", Pid::from_raw(43), &spec)?;
+        assert!(!fs::read_to_string(&log)?.contains("other"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_failure() -> Result<()> {
+        let tmp = create_temp_dir("test_network_setup_failure")?;
+        let binary = setup_binary(&tmp, "echo 'no slirp4netns' >&2; exit 1")?;
+        let err = NetworkSetup::new(binary).cleanup("test", None).unwrap_err();
+        assert!(format!("{:#}", err).contains("no slirp4netns"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleanup_without_pid() -> Result<()> {
+        let tmp = create_temp_dir("test_network_cleanup_without_pid")?;
+        let log = tmp.join("log");
+        let binary = setup_binary(&tmp, &format!("echo \"$#\" >> {}", log.display()))?;
+        NetworkSetup::new(binary).cleanup("test", None)?;
+        assert_eq!(fs::read_to_string(&log)?, "2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_background_helper() -> Result<()> {
+        let tmp = create_temp_dir("test_network_setup_background_helper")?;
+        // the helper keeps stdout and stderr open after the binary returned
+        let binary = setup_binary(&tmp, "sleep 10 &")?;
+        let start = std::time::Instant::now();
+        NetworkSetup::new(binary).cleanup("test", Some(Pid::from_raw(42)))?;
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::{bail, Context, Result};
+use nix::sys::signal;
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr};
+use nix::unistd::{self, close, Pid};
+use oci_spec::{Mount, Spec};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Directory of the container directory which contains the socket that is
+/// exposed to the container as NOTIFY_SOCKET
+const NOTIFY_PROXY_DIR: &str = "notify";
+const NOTIFY_PROXY_SOCKET: &str = "notify.sock";
+const NOTIFY_PROXY_MOUNT: &str = "/run/notify";
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+/// Time the container has to notify its readiness, which is the default start
+/// timeout of systemd services
+const NOTIFY_PROXY_TIMEOUT: Duration = Duration::from_secs(90);
+// Interval in which is checked if the container is still alive
+const NOTIFY_PROXY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct NotifyListener {
+    socket: UnixListener,
+}
+
+impl NotifyListener {
+    pub fn new(socket_path: &Path) -> Result<Self> {
+        // unix domain socket has a maximum length of 108, different from
+        // normal path length of 255. due to how docker create the path name
+        // to the container working directory, there is a high chance that
+        // the full absolute path is over the limit. to work around this
+        // limitation, we chdir first into the workdir where the socket is,
+        // and chdir back after the socket is created.
+        let workdir = socket_path.parent().unwrap();
+        let socket_name = socket_path.file_name().unwrap();
+        let cwd = unistd::getcwd().context("Failed to get cwd")?;
+        unistd::chdir(workdir).context(format!(
+            "Failed to chdir into {}",
+            workdir.to_str().unwrap()
+        ))?;
+        let stream = UnixListener::bind(socket_name)
+            .context(format!("Failed to bind {}", socket_name.to_str().unwrap()))?;
+        unistd::chdir(&cwd)
+            .context(format!("Failed to chdir back to {}", cwd.to_str().unwrap()))?;
+
+        Ok(Self { socket: stream })
+    }
+
+    pub fn wait_for_container_start(&self) -> Result<()> {
+        match self.socket.accept() {
+            Ok((mut socket, _)) => {
+                let mut response = String::new();
+                socket.read_to_string(&mut response)?;
+                log::debug!("received: {}", response);
+            }
+            Err(e) => bail!("accept function failed: {:?}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        close(self.socket.as_raw_fd())?;
+        Ok(())
+    }
+}
+
+pub struct NotifySocket {
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    pub fn new<P: Into<PathBuf>>(socket_path: P) -> Self {
+        Self {
+            path: socket_path.into(),
+        }
+    }
+
+    pub fn notify_container_start(&mut self) -> Result<()> {
+        log::debug!("notify container start");
+        let cwd = env::current_dir()?;
+        unistd::chdir(&*self.path.parent().unwrap())?;
+        let mut stream = UnixStream::connect(&self.path.file_name().unwrap())?;
+        stream.write_all(b"start container")?;
+        log::debug!("notify finished");
+        unistd::chdir(&*cwd)?;
+        Ok(())
+    }
+
+    pub fn notify_container_finish(&mut self) -> Result<()> {
+        // self.socket.write_all(b"finish container")?;
+        Ok(())
+    }
+}
+
+/// Proxies the sd_notify messages of the container to the service manager
+/// which started youki. This allows to run containers as systemd services of
+/// Type=notify. The container gets its own socket, because the socket of the
+/// service manager is not reachable from the mount namespace of the container
+/// and the pid in MAINPID has to be translated to the pid namespace of the
+/// host.
+pub struct NotifyProxy {
+    /// NOTIFY_SOCKET of the service manager
+    host_socket: PathBuf,
+    /// Directory containing the socket, which is bind mounted into the container
+    proxy_dir: PathBuf,
+    /// Time the container has to notify its readiness
+    timeout: Duration,
+}
+
+impl NotifyProxy {
+    pub fn new<P: Into<PathBuf>>(host_socket: P, container_dir: &Path) -> Self {
+        Self {
+            host_socket: host_socket.into(),
+            proxy_dir: container_dir.join(NOTIFY_PROXY_DIR),
+            timeout: NOTIFY_PROXY_TIMEOUT,
+        }
+    }
+
+    /// Sets the time the container has to notify its readiness, after which
+    /// the start of the container fails
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the NOTIFY_SOCKET youki has been started with, if any
+    pub fn host_socket_from_env() -> Option<PathBuf> {
+        env::var_os(NOTIFY_SOCKET_ENV)
+            .filter(|socket| !socket.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Mounts the directory of the proxy socket into the container and points
+    /// NOTIFY_SOCKET of the container process to it
+    pub fn setup_spec(&self, spec: &mut Spec) -> Result<()> {
+        fs::create_dir_all(&self.proxy_dir)
+            .with_context(|| format!("failed to create {:?}", self.proxy_dir))?;
+
+        spec.mounts.get_or_insert_with(Vec::new).push(Mount {
+            destination: PathBuf::from(NOTIFY_PROXY_MOUNT),
+            typ: Some("bind".to_owned()),
+            source: Some(self.proxy_dir.clone()),
+            options: Some(
+                vec!["bind", "nosuid", "noexec", "nodev"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ),
+        });
+
+        let process = spec.process.as_mut().context("no process in spec")?;
+        let env = process.env.get_or_insert_with(Vec::new);
+        env.retain(|e| e.split('=').next() != Some(NOTIFY_SOCKET_ENV));
+        env.push(format!(
+            "{}={}/{}",
+            NOTIFY_SOCKET_ENV, NOTIFY_PROXY_MOUNT, NOTIFY_PROXY_SOCKET
+        ));
+        Ok(())
+    }
+
+    /// Binds the proxy socket. This has to happen before the container is
+    /// started, so no message of the container process is lost.
+    pub fn bind(&self) -> Result<UnixDatagram> {
+        // the socket path may be longer than a unix socket address allows,
+        // so it is bound through the short path of a descriptor of the proxy
+        // directory
+        let proxy_dir = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(&self.proxy_dir)
+            .with_context(|| format!("failed to open {:?}", self.proxy_dir))?;
+        let _ = fs::remove_file(self.proxy_dir.join(NOTIFY_PROXY_SOCKET));
+        let socket_path = Path::new("/proc/self/fd")
+            .join(proxy_dir.as_raw_fd().to_string())
+            .join(NOTIFY_PROXY_SOCKET);
+
+        UnixDatagram::bind(&socket_path)
+            .with_context(|| format!("failed to bind notify proxy in {:?}", self.proxy_dir))
+    }
+
+    /// Forwards the messages of the container to the service manager until
+    /// the container signals its readiness, exits or the timeout expires. The
+    /// MAINPID reported to the service manager is the pid of the container
+    /// process on the host.
+    pub fn forward(&self, socket: &UnixDatagram, pid: Pid) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                bail!(
+                    "container did not notify its readiness within {:?}",
+                    self.timeout
+                );
+            }
+            socket.set_read_timeout(Some(remaining.min(NOTIFY_PROXY_POLL_INTERVAL)))?;
+
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if signal::kill(pid, None).is_err() {
+                        bail!("container exited before it notified its readiness");
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err).context("failed to receive from notify proxy"),
+            };
+
+            let message = String::from_utf8_lossy(&buf[..len]);
+            log::debug!("received notify message {:?}", message);
+            let (message, ready) = translate_message(&message, pid);
+            self.send_to_host(message.as_bytes())?;
+            if ready {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send_to_host(&self, message: &[u8]) -> Result<()> {
+        let path = self.host_socket.as_os_str().as_bytes();
+        // a leading @ denotes a socket in the abstract namespace
+        let addr = match path.strip_prefix(b"@") {
+            Some(name) => UnixAddr::new_abstract(name)?,
+            None => UnixAddr::new(OsStr::from_bytes(path))?,
+        };
+
+        let fd = socket::socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let res = socket::sendto(fd, message, &SockAddr::Unix(addr), MsgFlags::empty());
+        let _ = close(fd);
+        res.with_context(|| format!("failed to notify {:?}", self.host_socket))?;
+        Ok(())
+    }
+}
+
+// The pid the container reports in MAINPID is only meaningful in its own pid
+// namespace, so it is replaced with the pid of the container on the host
+fn translate_message(message: &str, pid: Pid) -> (String, bool) {
+    let mut ready = false;
+    let mut lines: Vec<String> = message
+        .lines()
+        .filter(|line| !line.starts_with("MAINPID="))
+        .inspect(|line| ready |= *line == "READY=1")
+        .map(String::from)
+        .collect();
+    if ready {
+        lines.push(format!("MAINPID={}", pid));
+    }
+
+    (lines.join("\n"), ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_message() {
+        let pid = Pid::from_raw(4242);
+        assert_eq!(
+            translate_message("STATUS=starting", pid),
+            ("STATUS=starting".to_owned(), false)
+        );
+        assert_eq!(
+            translate_message("READY=1\nMAINPID=1\nSTATUS=up", pid),
+            ("READY=1\nSTATUS=up\nMAINPID=4242".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn test_forward_to_host() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_notify_forward_to_host")?;
+        let host = UnixDatagram::bind(dir.join("host.sock"))?;
+        let proxy = NotifyProxy::new(dir.join("host.sock"), &dir);
+        fs::create_dir_all(dir.join(NOTIFY_PROXY_DIR))?;
+        let socket = proxy.bind()?;
+
+        let container = UnixDatagram::unbound()?;
+        container.send_to(
+            b"READY=1",
+            dir.join(NOTIFY_PROXY_DIR).join(NOTIFY_PROXY_SOCKET),
+        )?;
+        proxy.forward(&socket, unistd::getpid())?;
+
+        let mut buf = [0u8; 64];
+        let len = host.recv(&mut buf)?;
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!("READY=1\nMAINPID={}", unistd::getpid())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_long_path() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_notify_bind_long_path")?;
+        let container_dir = dir.join("c".repeat(120));
+        fs::create_dir_all(container_dir.join(NOTIFY_PROXY_DIR))?;
+        let proxy = NotifyProxy::new(dir.join("host.sock"), &container_dir);
+        let _socket = proxy.bind()?;
+
+        assert!(container_dir
+            .join(NOTIFY_PROXY_DIR)
+            .join(NOTIFY_PROXY_SOCKET)
+            .exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_timeout() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_notify_forward_timeout")?;
+        let proxy =
+            NotifyProxy::new(dir.join("host.sock"), &dir).with_timeout(Duration::from_millis(100));
+        fs::create_dir_all(dir.join(NOTIFY_PROXY_DIR))?;
+        let socket = proxy.bind()?;
+
+        assert!(proxy.forward(&socket, unistd::getpid()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Container adjustments in the format of the Node Resource Interface (NRI).
+//! Node agents which use NRI with other runtimes can pass the adjustment for
+//! a container as json through an annotation, which youki applies to the
+//! config of the bundle before it is validated, so the adjusted config is
+//! checked like any other. Like in NRI, entries whose key is prefixed with "-"
+//! remove the corresponding entry from the config instead of adding it.
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use oci_spec::{LinuxResources, Mount};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Annotation containing the json encoded adjustment of the container
+pub const ADJUSTMENT_ANNOTATION: &str = "youki.nri.adjustment";
+
+const REMOVAL_PREFIX: char = '-';
+
+// Resources of the spec which are replaced by the adjusted ones
+const REPLACED_RESOURCES: &[&str] = &[
+    "memory",
+    "cpu",
+    "pids",
+    "blockIO",
+    "hugepageLimits",
+    "network",
+    "unified",
+];
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxContainerAdjustment {
+    /// Resources which replace the corresponding resources of the spec
+    pub resources: Option<LinuxResources>,
+    pub cgroups_path: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerAdjustment {
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    #[serde(default)]
+    pub env: Vec<KeyValue>,
+    pub linux: Option<LinuxContainerAdjustment>,
+}
+
+impl ContainerAdjustment {
+    /// Reads the adjustment from the annotations of the config, if there is one
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        let adjustment = match config
+            .get("annotations")
+            .and_then(|a| a.get(ADJUSTMENT_ANNOTATION))
+            .and_then(Value::as_str)
+        {
+            Some(adjustment) => adjustment,
+            None => return Ok(None),
+        };
+
+        let adjustment = serde_json::from_str(adjustment)
+            .with_context(|| format!("failed to parse annotation {}", ADJUSTMENT_ANNOTATION))?;
+        Ok(Some(adjustment))
+    }
+
+    /// Applies the adjustment to the config of a bundle, which has not been
+    /// validated yet
+    pub fn apply(&self, config: &mut Value) -> Result<()> {
+        let config = config.as_object_mut().context("spec is not an object")?;
+        if !self.annotations.is_empty() {
+            let annotations = object_entry(config, "annotations")?;
+            for (key, value) in &self.annotations {
+                match key.strip_prefix(REMOVAL_PREFIX) {
+                    Some(key) => annotations.remove(key),
+                    None => annotations.insert(key.clone(), Value::from(value.as_str())),
+                };
+            }
+        }
+
+        if !self.mounts.is_empty() {
+            let mounts = array_entry(config, "mounts")?;
+            for mount in &self.mounts {
+                let destination = mount.destination.to_string_lossy();
+                let removed = destination
+                    .strip_prefix(REMOVAL_PREFIX)
+                    .unwrap_or(&destination);
+                // a mount replaces any mount with the same destination
+                mounts.retain(|m| m.get("destination").and_then(Value::as_str) != Some(removed));
+                if !destination.starts_with(REMOVAL_PREFIX) {
+                    mounts.push(serde_json::to_value(mount)?);
+                }
+            }
+        }
+
+        if !self.env.is_empty() {
+            let process = config
+                .get_mut("process")
+                .and_then(Value::as_object_mut)
+                .context("no process in spec")?;
+            let env = array_entry(process, "env")?;
+            for kv in &self.env {
+                let key = kv.key.strip_prefix(REMOVAL_PREFIX).unwrap_or(&kv.key);
+                env.retain(|e| e.as_str().and_then(|e| e.split('=').next()) != Some(key));
+                if !kv.key.starts_with(REMOVAL_PREFIX) {
+                    env.push(Value::from(format!("{}={}", kv.key, kv.value)));
+                }
+            }
+        }
+
+        if let Some(adjustment) = self.linux.as_ref() {
+            let linux = config
+                .get_mut("linux")
+                .and_then(Value::as_object_mut)
+                .context("no linux in spec")?;
+            if let Some(cgroups_path) = adjustment.cgroups_path.as_ref() {
+                linux.insert("cgroupsPath".to_owned(), Value::from(cgroups_path.as_str()));
+            }
+
+            if let Some(adjusted) = adjustment.resources.as_ref() {
+                let resources = object_entry(linux, "resources")?;
+                merge_resources(resources, serde_json::to_value(adjusted)?)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Object of the key, which is created if it does not exist yet
+fn object_entry<'a>(
+    object: &'a mut Map<String, Value>,
+    key: &str,
+) -> Result<&'a mut Map<String, Value>> {
+    let value = object.entry(key).or_insert(Value::Null);
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+    value
+        .as_object_mut()
+        .with_context(|| format!("{} in spec is not an object", key))
+}
+
+// Array of the key, which is created if it does not exist yet
+fn array_entry<'a>(object: &'a mut Map<String, Value>, key: &str) -> Result<&'a mut Vec<Value>> {
+    let value = object.entry(key).or_insert(Value::Null);
+    if value.is_null() {
+        *value = Value::Array(Vec::new());
+    }
+    value
+        .as_array_mut()
+        .with_context(|| format!("{} in spec is not an array", key))
+}
+
+// The adjusted resources replace the corresponding resources of the spec,
+// except for the device rules. These are evaluated in order, so the adjusted
+// rules are appended.
+fn merge_resources(resources: &mut Map<String, Value>, adjusted: Value) -> Result<()> {
+    let adjusted = match adjusted {
+        Value::Object(adjusted) => adjusted,
+        _ => return Ok(()),
+    };
+
+    for (key, value) in adjusted {
+        if value.is_null() {
+            continue;
+        }
+        if key == "devices" {
+            if let Value::Array(devices) = value {
+                array_entry(resources, "devices")?.extend(devices);
+            }
+        } else if REPLACED_RESOURCES.contains(&key.as_str()) {
+            resources.insert(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_adjustment() -> Result<()> {
+        let mut config = json!({
+            "process": {"env": ["PATH=/bin", "REMOVED=1"]},
+            "mounts": [{"destination": "/proc", "type": "proc", "source": "proc"}],
+            "linux": {"resources": {"devices": [{"allow": false, "access": "rwm"}]}}
+        });
+
+        let adjustment: ContainerAdjustment = serde_json::from_str(
+            r#"{
+                "annotations": {"added": "true"},
+                "mounts": [{"destination": "/data", "type": "bind", "source": "/var/data", "options": ["rbind"]}],
+                "env": [{"key": "ADDED", "value": "1"}, {"key": "-REMOVED", "value": ""}],
+                "linux": {"resources": {"pids": {"limit": 10}}, "cgroupsPath": "/adjusted"}
+            }"#,
+        )?;
+        adjustment.apply(&mut config)?;
+
+        assert_eq!(config["annotations"]["added"], "true");
+        let destinations: Vec<&str> = config["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|m| m["destination"].as_str())
+            .collect();
+        assert_eq!(destinations, vec!["/proc", "/data"]);
+        assert_eq!(config["process"]["env"], json!(["PATH=/bin", "ADDED=1"]));
+        assert_eq!(config["linux"]["cgroupsPath"], "/adjusted");
+        assert_eq!(config["linux"]["resources"]["pids"]["limit"], 10);
+        assert_eq!(
+            config["linux"]["resources"]["devices"]
+                .as_array()
+                .map(Vec::len),
+            Some(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_config() -> Result<()> {
+        assert!(ContainerAdjustment::from_config(&json!({}))?.is_none());
+
+        let config = json!({"annotations": {ADJUSTMENT_ANNOTATION: r#"{"env": []}"#}});
+        assert!(ContainerAdjustment::from_config(&config)?.is_some());
+
+        let config = json!({"annotations": {ADJUSTMENT_ANNOTATION: "{"}});
+        assert!(ContainerAdjustment::from_config(&config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! CPU affinity of the container process around the exec transition as
+//! described by the `process.execCPUAffinity` object of the runtime spec. The
+//! initial mask applies to the youki processes while they set up the
+//! container, the final mask is applied right before the payload is executed.
+//! This allows latency sensitive deployments to keep the setup phase away
+//! from the cpus which are reserved for the workload.
+use anyhow::{bail, Context, Result};
+use nix::sched::{self, CpuSet};
+use nix::unistd::Pid;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::utils;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct ExecCpuAffinity {
+    /// Cpus the youki processes run on until the payload is executed
+    pub initial: Option<String>,
+    /// Cpus the payload runs on
+    #[serde(rename = "final")]
+    pub final_: Option<String>,
+}
+
+impl ExecCpuAffinity {
+    /// Reads the affinity from the process object of the spec
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        Self::load(config, "/process/execCPUAffinity")
+    }
+
+    /// Reads the affinity from the process file passed to exec
+    pub fn from_process(process: &Value) -> Result<Option<Self>> {
+        Self::load(process, "/execCPUAffinity")
+    }
+
+    fn load(config: &Value, pointer: &str) -> Result<Option<Self>> {
+        let affinity: Option<Self> = utils::spec_field(config, pointer)?;
+        if let Some(affinity) = affinity.as_ref() {
+            affinity.validate()?;
+        }
+
+        Ok(affinity)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for cpus in self.initial.iter().chain(self.final_.iter()) {
+            parse_cpus(cpus)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the initial mask to the calling process
+    pub fn apply_initial(&self) -> Result<()> {
+        match self.initial.as_ref() {
+            Some(cpus) => set_affinity(cpus).context("failed to set initial cpu affinity"),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the final mask to the calling process. This has to happen
+    /// after the process has joined the cgroup of the container, because
+    /// joining a cpuset cgroup resets the affinity.
+    pub fn apply_final(&self) -> Result<()> {
+        match self.final_.as_ref() {
+            Some(cpus) => set_affinity(cpus).context("failed to set final cpu affinity"),
+            None => Ok(()),
+        }
+    }
+}
+
+fn set_affinity(cpus: &str) -> Result<()> {
+    log::debug!("set cpu affinity to {}", cpus);
+    let cpu_set = parse_cpus(cpus)?;
+    sched::sched_setaffinity(Pid::from_raw(0), &cpu_set)
+        .with_context(|| format!("failed to set cpu affinity to {}", cpus))?;
+    Ok(())
+}
+
+/// Parses a list of cpus in the format used by cpuset.cpus, e.g. "0-3,6"
+fn parse_cpus(cpus: &str) -> Result<CpuSet> {
+    let mut cpu_set = CpuSet::new();
+    let mut empty = true;
+    for range in cpus.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (range, range),
+        };
+        let start: usize = start
+            .parse()
+            .with_context(|| format!("invalid cpu {} in {}", start, cpus))?;
+        let end: usize = end
+            .parse()
+            .with_context(|| format!("invalid cpu {} in {}", end, cpus))?;
+        if start > end {
+            bail!("invalid cpu range {} in {}", range, cpus);
+        }
+
+        for cpu in start..=end {
+            cpu_set
+                .set(cpu)
+                .with_context(|| format!("cpu {} in {} is out of range", cpu, cpus))?;
+            empty = false;
+        }
+    }
+
+    if empty {
+        bail!("no cpus in cpu affinity {:?}", cpus);
+    }
+
+    Ok(cpu_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpus() -> Result<()> {
+        let cpu_set = parse_cpus("0-2,5")?;
+        for cpu in 0..8 {
+            assert_eq!(cpu_set.is_set(cpu)?, [0, 1, 2, 5].contains(&cpu));
+        }
+
+        assert!(parse_cpus("").is_err());
+        assert!(parse_cpus("3-1").is_err());
+        assert!(parse_cpus("a").is_err());
+        assert!(parse_cpus("0-100000").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::os::unix::io::RawFd;
+
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::unistd;
+use nix::unistd::Pid;
+use procfs::process::Process;
+
+use crate::features::{FeatureGate, SYS_CLONE3};
+
+// Creates the child in the cgroup of the directory fd (linux 5.7)
+const CLONE_INTO_CGROUP: u64 = 0x2_0000_0000;
+
+// Arguments of clone3 up to the cgroup, see clone(2)
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+// Execute the cb in another process. Make the fork works more like thread_spawn
+// or clone, so it is easier to reason. Compared to clone call, fork is easier
+// to use since fork will magically take care of all the variable copying. If
+// using clone, we would have to manually make sure all the variables are
+// correctly send to the new process, especially Rust borrow checker will be a
+// lot of hassel to deal with every details.
+pub fn container_fork<F: FnOnce() -> Result<()>>(cb: F) -> Result<Pid> {
+    match unsafe { unistd::fork()? } {
+        unistd::ForkResult::Parent { child } => Ok(child),
+        unistd::ForkResult::Child => run_child(cb),
+    }
+}
+
+/// Executes the cb in another process like container_fork, but creates the
+/// process directly in the cgroup of the directory fd. Otherwise the process
+/// would run outside of the cgroup until it is moved into it. Falls back to
+/// container_fork if the kernel can not create processes in a cgroup, which
+/// requires clone3 with CLONE_INTO_CGROUP and a cgroup v2 directory. On
+/// cgroup v1 there is no such directory and the process is always forked.
+///
+/// clone3 is called directly, because libc has no wrapper for it, so the fork
+/// handlers of libc do not run in the child. Like after fork in a
+/// multi-threaded process, the child could only do async-signal-safe work, as
+/// locks may be held by threads which do not exist in the child. clone3 is
+/// therefore only used if the calling process is single-threaded, like the
+/// intermediate process, in which case cb may do anything it could do after
+/// fork. Multi-threaded callers fall back to container_fork. The child must
+/// not raise(3) signals though, since libc still has the thread id of the
+/// parent.
+pub fn container_fork_into_cgroup<F: FnOnce() -> Result<()>>(
+    cgroup_fd: Option<RawFd>,
+    cb: F,
+) -> Result<Pid> {
+    let cgroup_fd = match cgroup_fd {
+        Some(fd) if FeatureGate::Clone3.is_supported() && is_single_threaded() => fd,
+        _ => return container_fork(cb),
+    };
+
+    let mut args = CloneArgs {
+        flags: CLONE_INTO_CGROUP,
+        exit_signal: libc::SIGCHLD as u64,
+        cgroup: cgroup_fd as u64,
+        ..Default::default()
+    };
+    // Without a stack the child continues on a copy of the stack of the
+    // parent, just like with fork
+    let res = unsafe {
+        libc::syscall(
+            SYS_CLONE3,
+            &mut args as *mut CloneArgs,
+            std::mem::size_of::<CloneArgs>(),
+        )
+    };
+    match Errno::result(res) {
+        Ok(0) => run_child(cb),
+        Ok(child) => Ok(Pid::from_raw(child as i32)),
+        Err(err) => {
+            // e.g. EINVAL before linux 5.7 or EACCES if the cgroup can not be
+            // joined from the namespaces of the process
+            log::debug!("failed to clone into cgroup, fall back to fork: {}", err);
+            container_fork(cb)
+        }
+    }
+}
+
+fn is_single_threaded() -> bool {
+    match Process::myself() {
+        Ok(process) => process.stat.num_threads == 1,
+        Err(err) => {
+            log::debug!("failed to count the threads of the process: {}", err);
+            false
+        }
+    }
+}
+
+fn run_child<F: FnOnce() -> Result<()>>(cb: F) -> ! {
+    let ret = if let Err(error) = cb() {
+        log::debug!("failed to run fork: {:?}", error);
+        -1
+    } else {
+        0
+    };
+    std::process::exit(ret);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Context};
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+    use std::path::PathBuf;
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+    // The path of the cgroup v2 of the process, if the unified hierarchy is
+    // mounted at its usual place
+    fn unified_cgroup() -> Option<String> {
+        if !PathBuf::from(CGROUP_ROOT)
+            .join("cgroup.controllers")
+            .exists()
+        {
+            return None;
+        }
+        fs::read_to_string("/proc/self/cgroup")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .map(|path| path.to_owned())
+    }
+
+    // Runs the check in a forked process, which is single-threaded unlike the
+    // test harness
+    fn single_threaded<F: FnOnce() -> Result<()>>(check: F) -> Result<()> {
+        let child = container_fork(|| {
+            assert!(is_single_threaded());
+            check()
+        })?;
+        match waitpid(child, None)? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            status => bail!("check failed with {:?}", status),
+        }
+    }
+
+    fn wait_success(pid: Pid) -> Result<()> {
+        match waitpid(pid, None)? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            status => bail!("child failed with {:?}", status),
+        }
+    }
+
+    #[test]
+    fn test_fork_into_cgroup() -> Result<()> {
+        let parent = match unified_cgroup() {
+            Some(parent) if FeatureGate::Clone3.is_supported() => parent,
+            _ => return Ok(()),
+        };
+        let cgroup = format!(
+            "{}/youki_test_fork_{}",
+            parent.trim_end_matches('/'),
+            std::process::id()
+        );
+        let cgroup_path = PathBuf::from(CGROUP_ROOT).join(cgroup.trim_start_matches('/'));
+        // the cgroup can only be created by root or in a delegated cgroup
+        if fs::create_dir(&cgroup_path).is_err() {
+            return Ok(());
+        }
+
+        let result = single_threaded(|| {
+            let dir = File::open(&cgroup_path)?;
+            let pid = container_fork_into_cgroup(Some(dir.as_raw_fd()), || {
+                let own = fs::read_to_string("/proc/self/cgroup")?;
+                if !own.lines().any(|line| line == format!("0::{}", cgroup)) {
+                    bail!("child was created in {}", own);
+                }
+                Ok(())
+            })?;
+            wait_success(pid)
+        });
+        fs::remove_dir(&cgroup_path).context("failed to remove test cgroup")?;
+        result
+    }
+
+    #[test]
+    fn test_fork_into_cgroup_fallback() -> Result<()> {
+        // no cgroup directory, e.g. on cgroup v1
+        wait_success(container_fork_into_cgroup(None, || Ok(()))?)?;
+        // the test harness is multi-threaded, so clone3 must not be used
+        let dir = File::open("/")?;
+        wait_success(container_fork_into_cgroup(
+            Some(dir.as_raw_fd()),
+            || Ok(()),
+        )?)?;
+        // clone3 fails for an invalid directory fd
+        single_threaded(|| wait_success(container_fork_into_cgroup(Some(-1), || Ok(()))?))?;
+        // a failing child is reported by its exit status
+        let pid = container_fork_into_cgroup(None, || bail!("expected error"))?;
+        assert!(wait_success(pid).is_err());
+        Ok(())
+    }
+}
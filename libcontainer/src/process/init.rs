@@ -0,0 +1,922 @@
+use anyhow::{bail, Context, Result};
+use cgroups::common::CgroupHierarchy;
+use nix::errno::Errno;
+use nix::mount::mount as nix_mount;
+use nix::mount::MsFlags;
+use nix::sched::CloneFlags;
+use nix::{
+    fcntl,
+    sys::statfs,
+    unistd::{self, Gid, Uid},
+};
+use oci_spec::{LinuxNamespaceType, Spec};
+use std::collections::HashMap;
+use std::{
+    env,
+    io::Write,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd, prelude::RawFd},
+};
+use std::{fs, path::Path, path::PathBuf};
+
+use crate::rootless::{self, Rootless};
+use crate::{
+    capabilities,
+    container::Container,
+    exec_fifo::ExecFifo,
+    hooks,
+    idmap::IdMappedMounts,
+    namespaces::Namespaces,
+    nested::NestedEnvironment,
+    notify_socket::NotifyListener,
+    process::channel,
+    process::cpu_affinity::ExecCpuAffinity,
+    process::fork,
+    process::io_priority::IoPriority,
+    process::memory_hints::MemoryHints,
+    process::readahead,
+    process::scheduler::Scheduler,
+    process::user::{self as process_user, ResolvedUser, UserIds},
+    rootfs,
+    securejoin::{self, RootfsPath},
+    syscall::{linux::LinuxSyscall, Syscall},
+    trace, tty, utils,
+};
+
+// Make sure a given path is on procfs. This is to avoid the security risk that
+// /proc path is mounted over. Ref: CVE-2019-16884
+fn ensure_procfs(path: &Path) -> Result<()> {
+    let procfs_fd = fs::File::open(path)?;
+    ensure_procfs_fd(procfs_fd.as_raw_fd(), path)
+}
+
+fn ensure_procfs_fd(fd: RawFd, path: &Path) -> Result<()> {
+    let fstat_info = statfs::fstatfs(&fd)?;
+
+    if fstat_info.filesystem_type() != statfs::PROC_SUPER_MAGIC {
+        bail!(format!("{:?} is not on the procfs", path));
+    }
+
+    Ok(())
+}
+
+// Make sure an entry below /proc, which the runtime is about to mount over, is
+// located on procfs and has been opened without following a symlink. A
+// malicious image could otherwise redirect the masks to arbitrary files. The
+// opened entry is checked, so it can not be swapped out before it is used.
+fn ensure_procfs_entry(target: &RootfsPath, path: &str) -> Result<()> {
+    let path = Path::new(path);
+    if !path.starts_with("/proc") {
+        return Ok(());
+    }
+
+    ensure_procfs_fd(target.as_raw_fd(), path)?;
+    let resolved = fs::read_link(target.proc_path())
+        .with_context(|| format!("failed to resolve {:?}", path))?;
+    if resolved != path {
+        bail!(
+            "{:?} resolves to {:?} through a symlink, refusing to use it",
+            path,
+            resolved
+        );
+    }
+
+    Ok(())
+}
+
+// Get a list of open fds for the calling process.
+fn get_open_fds() -> Result<Vec<i32>> {
+    const PROCFS_FD_PATH: &str = "/proc/self/fd";
+    ensure_procfs(Path::new(PROCFS_FD_PATH))
+        .with_context(|| format!("{} is not the actual procfs", PROCFS_FD_PATH))?;
+
+    let fds: Vec<i32> = fs::read_dir(PROCFS_FD_PATH)?
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry.path()),
+            Err(_) => None,
+        })
+        .filter_map(|path| path.file_name().map(|file_name| file_name.to_owned()))
+        .filter_map(|file_name| file_name.to_str().map(String::from))
+        .filter_map(|file_name| -> Option<i32> {
+            // Convert the file name from string into i32. Since we are looking
+            // at /proc/<pid>/fd, anything that's not a number (i32) can be
+            // ignored. We are only interested in opened fds.
+            match file_name.parse() {
+                Ok(fd) => Some(fd),
+                Err(_) => None,
+            }
+        })
+        .collect();
+
+    Ok(fds)
+}
+
+// Cleanup any extra file descriptors, so the new container process will not
+// leak a file descriptor from before execve gets executed. The first 3 fd will
+// stay open: stdio, stdout, and stderr. We would further preserve the next
+// "preserve_fds" number of fds. youki opens all of its own fds with CLOEXEC, so
+// any other fd without the flag has been inherited from the caller and is
+// closed right away. youki's own fds are still needed until the payload is
+// executed, e.g. the pipe used to wait on starting the container, so they are
+// left to be closed by execve.
+fn cleanup_file_descriptors(preserve_fds: i32) -> Result<()> {
+    // Include stdin, stdout, and stderr for fd 0, 1, and 2 respectively.
+    let min_fd = preserve_fds + 3;
+    let open_fds = match get_open_fds() {
+        Ok(open_fds) => open_fds,
+        Err(err) => {
+            // without procfs the open fds cannot be told apart, so they are
+            // all marked with CLOEXEC at once
+            log::warn!("failed to obtain opened fds, use close_range: {:?}", err);
+            return close_range_cloexec(min_fd);
+        }
+    };
+
+    for fd in open_fds.into_iter().filter(|&fd| fd >= min_fd) {
+        // Intentionally ignore errors here -- the cases where this might fail
+        // are basically file descriptors that have already been closed, like
+        // the one which was used to read the open fds.
+        if let Ok(flags) = fcntl::fcntl(fd, fcntl::F_GETFD) {
+            if !fcntl::FdFlag::from_bits_truncate(flags).contains(fcntl::FdFlag::FD_CLOEXEC) {
+                log::debug!("close inherited fd {}", fd);
+                let _ = unistd::close(fd);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Sets CLOEXEC on all fds starting from min_fd with a single syscall, which is
+// available since linux 5.11
+fn close_range_cloexec(min_fd: i32) -> Result<()> {
+    const SYS_CLOSE_RANGE: libc::c_long = 436;
+    const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+    let res = unsafe {
+        libc::syscall(
+            SYS_CLOSE_RANGE,
+            min_fd as libc::c_uint,
+            libc::c_uint::MAX,
+            CLOSE_RANGE_CLOEXEC,
+        )
+    };
+    nix::errno::Errno::result(res).context("failed to set CLOEXEC with close_range")?;
+    Ok(())
+}
+
+fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
+    let sys = PathBuf::from("/proc/sys");
+    ensure_procfs(&sys).context("/proc/sys is not the actual procfs")?;
+    for (kernel_param, value) in kernel_params {
+        let path = sys.join(kernel_param.replace(".", "/"));
+        log::debug!(
+            "apply value {} to kernel parameter {}.",
+            value,
+            kernel_param
+        );
+        // the opened file is verified, so it can not be swapped out after the check
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)
+            .with_context(|| format!("failed to open {:?}", path))?;
+        ensure_procfs_fd(file.as_raw_fd(), &path)?;
+        file.write_all(value.as_bytes())
+            .with_context(|| format!("failed to set sysctl {}={}", kernel_param, value))?;
+    }
+
+    Ok(())
+}
+
+// make a read only path
+// The first time we bind mount, other flags are ignored,
+// so we need to mount it once and then remount it with the necessary flags specified.
+// https://man7.org/linux/man-pages/man2/mount.2.html
+fn readonly_path(path: &str) -> Result<()> {
+    let target = match open_in_root(path)? {
+        Some(target) => target,
+        None => {
+            log::warn!("readonly path {:?} not exist", path);
+            return Ok(());
+        }
+    };
+    ensure_procfs_entry(&target, path)?;
+
+    nix_mount(
+        Some(&target.proc_path()),
+        &target.proc_path(),
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .with_context(|| format!("failed to bind mount readonly path {:?}", path))?;
+
+    // the magic link of the previous fd still points below the new mount
+    let target = securejoin::open_in_root(Path::new("/"), path)?;
+    ensure_procfs_entry(&target, path)?;
+    nix_mount(
+        Some(&target.proc_path()),
+        &target.proc_path(),
+        None::<&str>,
+        MsFlags::MS_NOSUID
+            | MsFlags::MS_NODEV
+            | MsFlags::MS_NOEXEC
+            | MsFlags::MS_BIND
+            | MsFlags::MS_REMOUNT
+            | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )?;
+    log::debug!("readonly path {:?} mounted", path);
+    Ok(())
+}
+
+// mask a path by mounting an empty read only tmpfs over directories and
+// /dev/null over files, so the container can not access its content.
+fn masked_path(path: &str, mount_label: Option<&String>) -> Result<()> {
+    let target = match open_in_root(path)? {
+        Some(target) => target,
+        None => {
+            log::warn!("masked path {:?} not exist", path);
+            return Ok(());
+        }
+    };
+    ensure_procfs_entry(&target, path)?;
+
+    match nix_mount(
+        Some("/dev/null"),
+        &target.proc_path(),
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    ) {
+        Err(nix::errno::Errno::ENOTDIR) => {
+            let label = mount_label.map(|l| format!("context=\"{}\"", l));
+            nix_mount(
+                Some("tmpfs"),
+                &target.proc_path(),
+                Some("tmpfs"),
+                MsFlags::MS_RDONLY,
+                label.as_deref(),
+            )?;
+        }
+        Err(err) => bail!(err),
+        Ok(_) => {}
+    }
+
+    log::debug!("masked path {:?}", path);
+    Ok(())
+}
+
+// The initramfs is mounted as rootfs and can not be pivoted away from
+fn is_initramfs_root() -> bool {
+    fs::read_to_string("/proc/self/mountinfo")
+        .map(|mountinfo| root_is_rootfs(&mountinfo))
+        .unwrap_or(false)
+}
+
+fn root_is_rootfs(mountinfo: &str) -> bool {
+    mountinfo.lines().any(|line| match line.split_once(" - ") {
+        Some((mount, fs)) => {
+            mount.split_whitespace().nth(4) == Some("/") && fs.starts_with("rootfs ")
+        }
+        None => false,
+    })
+}
+
+// Opens a path of the container after the pivot, where the rootfs is the root
+// of the filesystem. A symlink in the path can not lead out of the container.
+fn open_in_root(path: &str) -> Result<Option<RootfsPath>> {
+    match securejoin::open_in_root(Path::new("/"), path) {
+        Ok(target) => Ok(Some(target)),
+        Err(err) if err.downcast_ref::<Errno>() == Some(&Errno::ENOENT) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub struct ContainerInitArgs<'a> {
+    /// Flag indicating if an init or a tenant container should be created
+    pub init: bool,
+    /// Interface to operating system primitives
+    pub syscall: LinuxSyscall,
+    /// OCI complient runtime spec
+    pub spec: &'a Spec,
+    /// Root filesystem of the container
+    pub rootfs: &'a Path,
+    /// Socket to communicate the file descriptor of the ptty
+    pub console_socket: Option<RawFd>,
+    /// The Unix Domain Socket to communicate the start of a tenant process
+    pub notify_socket: Option<NotifyListener>,
+    /// Fifo which blocks the init process until the container is started
+    pub exec_fifo: Option<ExecFifo>,
+    /// File descriptos preserved/passed to the container init process.
+    pub preserve_fds: i32,
+    /// Files which become stdin, stdout and stderr of the container process
+    pub stdio: Option<[RawFd; 3]>,
+    /// Container state
+    pub container: Option<&'a Container>,
+    /// Options for rootless containers
+    pub rootless: Option<Rootless<'a>>,
+    /// Use MS_MOVE and chroot instead of pivot_root to enter the rootfs
+    pub no_pivot: bool,
+    /// Scheduling policy of the container process
+    pub scheduler: Option<Scheduler>,
+    /// IO priority of the container process
+    pub io_priority: Option<IoPriority>,
+    /// CPU affinity of the container process before and after exec
+    pub exec_cpu_affinity: Option<ExecCpuAffinity>,
+    /// Ids of the user of the container process as given in the spec
+    pub user_ids: UserIds,
+    /// Detached id-mapped mount trees to attach into the rootfs
+    pub idmapped_mounts: IdMappedMounts,
+    /// Cgroup of the container which is bound for mounts of type cgroup
+    pub cgroup_hierarchies: Vec<CgroupHierarchy>,
+    /// Directory of the cgroup the init process is created in, if the cgroup
+    /// supports it
+    pub cgroup_fd: Option<RawFd>,
+}
+
+pub fn container_intermediate(
+    args: ContainerInitArgs,
+    receiver_from_main: &mut channel::ReceiverFromMain,
+    sender_to_main: &mut channel::SenderIntermediateToMain,
+) -> Result<()> {
+    let command = &args.syscall;
+    let spec = args.spec;
+    let linux = spec.linux.as_ref().context("no linux in spec")?;
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+
+    // the init process is forked from here, so it inherits the initial affinity
+    if let Some(affinity) = args.exec_cpu_affinity.as_ref() {
+        affinity.apply_initial()?;
+    }
+
+    let phase = trace::phase("setns");
+    // if new user is specified in specification, this will be true and new
+    // namespace will be created, check
+    // https://man7.org/linux/man-pages/man7/user_namespaces.7.html for more
+    // information
+    if let Some(user_namespace) = namespaces.get(LinuxNamespaceType::User) {
+        namespaces
+            .unshare_or_setns(user_namespace)
+            .with_context(|| format!("Failed to enter pid namespace: {:?}", user_namespace))?;
+        if user_namespace.path.is_none() {
+            log::debug!("creating new user namespace");
+            // child needs to be dumpable, otherwise the non root parent is not
+            // allowed to write the uid/gid maps
+            prctl::set_dumpable(true).unwrap();
+            sender_to_main.identifier_mapping_request()?;
+            receiver_from_main.wait_for_mapping_ack()?;
+            prctl::set_dumpable(false).unwrap();
+        }
+
+        // After UID and GID mapping is configured correctly in the Youki main
+        // process, We want to make sure continue as the root user inside the
+        // new user namespace. This is required because the process of
+        // configuring the container process will require root, even though the
+        // root in the user namespace likely is mapped to an non-priviliged user
+        // on the parent user namespace.
+        command.set_id(Uid::from_raw(0), Gid::from_raw(0)).context(
+            "Failed to configure uid and gid root in the beginning of a new user namespace",
+        )?;
+    }
+
+    // set limits and namespaces to the process
+    let proc = spec.process.as_ref().context("no process in spec")?;
+    if let Some(rlimits) = proc.rlimits.as_ref() {
+        for rlimit in rlimits.iter() {
+            command.set_rlimit(rlimit).context("failed to set rlimit")?;
+        }
+    }
+
+    // Pid namespace requires an extra fork to enter, so we enter pid namespace now.
+    if let Some(pid_namespace) = namespaces.get(LinuxNamespaceType::Pid) {
+        namespaces
+            .unshare_or_setns(pid_namespace)
+            .with_context(|| format!("Failed to enter pid namespace: {:?}", pid_namespace))?;
+    }
+    drop(phase);
+
+    // We only need for init process to send us the ChildReady.
+    let (sender_to_intermediate, receiver_from_init) = &mut channel::init_to_intermediate()?;
+
+    // We resued the args passed in, but replace with a new set of channels.
+    let cgroup_fd = args.cgroup_fd;
+    let init_args = ContainerInitArgs { ..args };
+    // We have to record the pid of the child (container init process), since
+    // the child will be inside the pid namespace. We can't rely on child_ready
+    // to send us the correct pid. The init process is created in its cgroup
+    // right away where possible, so it never runs outside of it.
+    let phase = trace::phase("fork");
+    let pid = fork::container_fork_into_cgroup(cgroup_fd, || {
+        // First thing in the child process to close the unused fds in the channel/pipe.
+        receiver_from_init
+            .close()
+            .context("Failed to close receiver in init process")?;
+        container_init(init_args, sender_to_intermediate)
+    })?;
+    drop(phase);
+    // Close unused fds in the parent process.
+    sender_to_intermediate
+        .close()
+        .context("Failed to close sender in the intermediate process")?;
+    // There is no point using the pid returned here, since the child will be
+    // inside the pid namespace already.
+    receiver_from_init
+        .wait_for_init_ready()
+        .context("Failed to wait for the child")?;
+    // After the child (the container init process) becomes ready, we can signal
+    // the parent (the main process) that we are ready.
+    sender_to_main
+        .intermediate_ready(pid)
+        .context("Failed to send child ready from intermediate process")?;
+
+    Ok(())
+}
+
+pub fn container_init(
+    args: ContainerInitArgs,
+    sender_to_intermediate: &mut channel::SenderInitToIntermediate,
+) -> Result<()> {
+    let command = &args.syscall;
+    let spec = args.spec;
+    let linux = spec.linux.as_ref().context("no linux in spec")?;
+    let proc = spec.process.as_ref().context("no process in spec")?;
+    // environment variables which are set in addition to the ones from the spec
+    let mut extra_envs: Vec<String> = Vec::new();
+    let rootfs = args.rootfs;
+    let hooks = spec.hooks.as_ref();
+    let container = args.container;
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+
+    // connect the standard streams which have been passed by the caller, a
+    // terminal replaces them
+    if let Some(stdio) = args.stdio {
+        for (target, fd) in stdio.iter().enumerate() {
+            unistd::dup2(*fd, target as RawFd)
+                .with_context(|| format!("Failed to connect fd {} to {}", fd, target))?;
+        }
+    }
+
+    // set up tty if specified
+    if let Some(csocketfd) = args.console_socket {
+        tty::setup_console(&csocketfd).with_context(|| "Failed to set up tty")?;
+    }
+
+    let phase = trace::phase("setns");
+    // Enter into rest of namespace. Note, we already entered into user and pid
+    // namespace. We also have to enter into mount namespace last since
+    // namespace may be bind to /proc path. The /proc path will need to be
+    // accessed before pivot_root.
+    namespaces
+        .apply_namespaces(|ns_type| -> bool {
+            ns_type != CloneFlags::CLONE_NEWUSER
+                && ns_type != CloneFlags::CLONE_NEWPID
+                && ns_type != CloneFlags::CLONE_NEWNS
+        })
+        .with_context(|| "Failed to apply namespaces")?;
+    if let Some(mount_namespace) = namespaces.get(LinuxNamespaceType::Mount) {
+        namespaces
+            .unshare_or_setns(mount_namespace)
+            .with_context(|| format!("Failed to enter mount namespace: {:?}", mount_namespace))?;
+    }
+
+    // Only set the host name if entering into a new uts namespace
+    if let Some(uts_namespace) = namespaces.get(LinuxNamespaceType::Uts) {
+        if uts_namespace.path.is_none() {
+            if let Some(hostname) = spec.hostname.as_ref() {
+                command.set_hostname(hostname)?;
+            }
+        }
+    }
+    drop(phase);
+
+    if let Some(true) = proc.no_new_privileges {
+        let _ = prctl::set_no_new_privileges(true);
+    }
+
+    MemoryHints::from_annotations(spec.annotations.as_ref())
+        .and_then(|hints| hints.apply())
+        .context("Failed to apply memory hints")?;
+
+    if let Some(io_priority) = args.io_priority.as_ref() {
+        io_priority.apply()?;
+    }
+
+    if args.init {
+        // create_container hook needs to be called after the namespace setup, but
+        // before pivot_root is called. This runs in the container namespaces.
+        if let Some(hooks) = hooks {
+            hooks::run_hooks(hooks.create_container.as_ref(), container)
+                .context("Failed to run create container hooks")?;
+        }
+
+        let _phase = trace::phase("mounts");
+        // Device nodes can neither be created in a user namespace nor when
+        // youki itself runs in a container which does not permit mknod
+        let bind_service = namespaces.get(LinuxNamespaceType::User).is_some()
+            || rootless::in_user_namespace()
+            || !NestedEnvironment::detect().mknod_permitted;
+        rootfs::prepare_rootfs(
+            spec,
+            rootfs,
+            bind_service,
+            &args.idmapped_mounts,
+            &args.cgroup_hierarchies,
+        )
+        .with_context(|| "Failed to prepare rootfs")?;
+
+        // the pseudo terminal has already been connected to stdio during the tty setup
+        if args.console_socket.is_some() {
+            rootfs::setup_console(rootfs).context("Failed to setup /dev/console")?;
+        }
+
+        // Entering into the rootfs jail. If mount namespace is specified, then
+        // we use pivot_root, but if we are on the host mount namespace, we will
+        // use simple chroot. Scary things will happen if you try to pivot_root
+        // in the host mount namespace...
+        if namespaces.get(LinuxNamespaceType::Mount).is_some() {
+            if args.no_pivot {
+                command
+                    .move_rootfs(rootfs)
+                    .with_context(|| format!("Failed to move root to {:?}", rootfs))?;
+            } else {
+                // change the root of filesystem of the process to the rootfs
+                match command.pivot_rootfs(rootfs) {
+                    Ok(_) => {}
+                    // pivot_root is not possible if the current root is not a
+                    // mount point, e.g. youki running from an initramfs. An
+                    // invalid rootfs fails with EINVAL as well, so the fallback
+                    // is only taken for the initramfs.
+                    Err(err)
+                        if err.downcast_ref::<nix::Error>() == Some(&nix::Error::EINVAL)
+                            && is_initramfs_root() =>
+                    {
+                        log::warn!("pivot_root is not supported, fall back to chroot");
+                        command
+                            .move_rootfs(rootfs)
+                            .with_context(|| format!("Failed to move root to {:?}", rootfs))?;
+                    }
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("Failed to pivot root to {:?}", rootfs))
+                    }
+                }
+            }
+        } else {
+            command
+                .chroot(rootfs)
+                .with_context(|| format!("Failed to chroot to {:?}", rootfs))?;
+        }
+
+        if let Some(kernel_params) = &linux.sysctl {
+            sysctl(kernel_params)
+                .with_context(|| format!("Failed to sysctl: {:?}", kernel_params))?;
+        }
+
+        // all mounts have been set up, processes which join the container
+        // later find the rootfs read-only already
+        if spec.root.as_ref().and_then(|r| r.readonly).unwrap_or(false) {
+            rootfs::make_rootfs_readonly()?;
+        }
+    }
+
+    if let Some(paths) = &linux.readonly_paths {
+        // mount readonly path
+        for path in paths {
+            readonly_path(path).context("Failed to set read only path")?;
+        }
+    }
+
+    if let Some(paths) = &linux.masked_paths {
+        // mount masked path
+        for path in paths {
+            masked_path(path, linux.mount_label.as_ref()).context("Failed to set masked path")?;
+        }
+    }
+
+    let phase = trace::phase("exec");
+    let do_chdir = if proc.cwd.is_empty() {
+        false
+    } else {
+        // This chdir must run before setting up the user.
+        // This may allow the user running youki to access directories
+        // that the container user cannot access.
+        match unistd::chdir(&*proc.cwd) {
+            Ok(_) => false,
+            Err(nix::Error::EPERM) => true,
+            Err(e) => bail!("Failed to chdir: {}", e),
+        }
+    };
+
+    // the passwd file of the container is read after the rootfs has been entered
+    let user = process_user::resolve_user(
+        process_user::PASSWD_PATH,
+        args.user_ids,
+        proc.user.username.as_deref(),
+    )
+    .context("failed to resolve the user of the container process")?;
+    log::debug!("container process runs as {:?}", user);
+    let home_in_env = proc
+        .env
+        .as_ref()
+        .map_or(false, |env| env.iter().any(|e| e.starts_with("HOME=")));
+    if !home_in_env {
+        extra_envs.push(format!("HOME={}", user.home.display()));
+    }
+
+    set_supplementary_gids(proc.user.additional_gids.as_deref(), &user, &args.rootless)
+        .context("failed to set supplementary gids")?;
+
+    command
+        .set_id(Uid::from_raw(user.uid), Gid::from_raw(user.gid))
+        .context("Failed to configure uid and gid")?;
+
+    capabilities::reset_effective(command).context("Failed to reset effective capabilities")?;
+    if let Some(caps) = &proc.capabilities {
+        capabilities::drop_privileges(caps, command).context("Failed to drop capabilities")?;
+    }
+
+    // Take care of LISTEN_FDS used for systemd-active-socket. If the value is
+    // not 0, then we have to preserve those fds as well, and set up the correct
+    // environment variables.
+    let preserve_fds: i32 = match env::var("LISTEN_FDS") {
+        Ok(listen_fds_str) => {
+            let listen_fds = match listen_fds_str.parse::<i32>() {
+                Ok(v) => v,
+                Err(error) => {
+                    log::warn!(
+                        "LISTEN_FDS entered is not a fd. Ignore the value. {:?}",
+                        error
+                    );
+
+                    0
+                }
+            };
+
+            // The LISTEN_FDS will have to be passed to container init process.
+            // The LISTEN_PID will be set to PID 1. Based on the spec, if
+            // LISTEN_FDS is 0, the variable should be unset, so we just ignore
+            // it here, if it is 0.
+            if listen_fds > 0 {
+                extra_envs.append(&mut vec![
+                    format!("LISTEN_FDS={}", listen_fds),
+                    "LISTEN_PID=1".to_string(),
+                ]);
+            }
+
+            args.preserve_fds + listen_fds
+        }
+        Err(env::VarError::NotPresent) => args.preserve_fds,
+        Err(env::VarError::NotUnicode(value)) => {
+            log::warn!(
+                "LISTEN_FDS entered is malformed: {:?}. Ignore the value.",
+                &value
+            );
+            args.preserve_fds
+        }
+    };
+
+    // clean up and handle perserved fds.
+    cleanup_file_descriptors(preserve_fds).with_context(|| "Failed to clean up extra fds")?;
+
+    // change directory to process.cwd if process.cwd is not empty
+    if do_chdir {
+        unistd::chdir(&*proc.cwd).with_context(|| format!("Failed to chdir {}", proc.cwd))?;
+    }
+
+    // Reset the process env based on oci spec.
+    env::vars().for_each(|(key, _value)| std::env::remove_var(key));
+    utils::parse_env(proc.env.as_deref().unwrap_or_default())
+        .iter()
+        .chain(utils::parse_env(&extra_envs).iter())
+        .for_each(|(key, value)| env::set_var(key, value));
+
+    drop(phase);
+
+    // Warm the page cache with the payload while the container is created,
+    // so it is not delayed by disk io once the container is started. This is
+    // only an optimization, so failures are not fatal.
+    if readahead::is_enabled(spec.annotations.as_ref()) {
+        if let Some(entrypoint) = proc.args.as_ref().and_then(|args| args.first()) {
+            let _phase = trace::phase("readahead");
+            if let Err(err) = readahead::readahead_entrypoint(entrypoint) {
+                log::warn!("failed to readahead {}: {:?}", entrypoint, err);
+            }
+        }
+    }
+
+    // notify parents that the init process is ready to execute the payload.
+    // Note, we pass -1 here because we are already inside the pid namespace.
+    // The pid outside the pid namespace should be recorded by the intermediate
+    // process.
+    sender_to_intermediate.init_ready()?;
+
+    // wait for the start command, which opens the exec fifo for reading
+    if let Some(exec_fifo) = args.exec_fifo {
+        exec_fifo.wait_for_start()?;
+    }
+
+    // listing on the notify socket for the start of a tenant process
+    if let Some(notify_socket) = args.notify_socket {
+        notify_socket.wait_for_container_start()?;
+    }
+
+    // create_container hook needs to be called after the namespace setup, but
+    // before pivot_root is called. This runs in the container namespaces.
+    if args.init {
+        if let Some(hooks) = hooks {
+            hooks::run_hooks(hooks.start_container.as_ref(), container)?
+        }
+    }
+
+    if let Some(affinity) = args.exec_cpu_affinity.as_ref() {
+        affinity.apply_final()?;
+    }
+
+    // applied after the hooks, which are forked and would inherit the policy
+    if let Some(scheduler) = args.scheduler.as_ref() {
+        scheduler.apply()?;
+    }
+
+    if let Some(args) = proc.args.as_ref() {
+        trace::mark("execve");
+        utils::do_exec(&args[0], args)?;
+    } else {
+        bail!("On non-Windows, at least one process arg entry is required.")
+    }
+
+    // After do_exec is called, the process is replaced with the container
+    // payload through execvp, so it should never reach here.
+    unreachable!();
+}
+
+// Before 3.19 it was possible for an unprivileged user to enter an user namespace,
+// become root and then call setgroups in order to drop membership in supplementary
+// groups. This allowed access to files which blocked access based on being a member
+// of these groups (see CVE-2014-8989)
+//
+// This leaves us with three scenarios:
+//
+// Unprivileged user starting a rootless container: The main process is running as an
+// unprivileged user and therefore cannot write the mapping until "deny" has been written
+// to /proc/{pid}/setgroups. Once written /proc/{pid}/setgroups cannot be reset and the
+// setgroups system call will be disabled for all processes in this user namespace. This
+// also means that we should detect if the user is unprivileged and additional gids have
+// been specified and bail out early as this can never work. This is not handled here,
+// but during the validation for rootless containers.
+//
+// Privileged user starting a rootless container: It is not necessary to write "deny" to
+// /proc/setgroups in order to create the gid mapping and therefore we don't. This means
+// that setgroups could be used to drop groups, but this is fine as the user is privileged
+// and could do so anyway.
+// We already have checked during validation if the specified supplemental groups fall into
+// the range that are specified in the gid mapping and bail out early if they do not.
+//
+// Privileged user starting a normal container: Just add the supplementary groups.
+//
+// Like runc, the groups the user is a member of according to the /etc/group file of
+// the container are added to the additional gids of the spec. The supplementary groups
+// are set even if there are none, so the groups of the runtime are not inherited.
+fn set_supplementary_gids(
+    additional_gids: Option<&[u32]>,
+    user: &ResolvedUser,
+    rootless: &Option<Rootless>,
+) -> Result<()> {
+    let mut gids: Vec<u32> = additional_gids.map(|g| g.to_vec()).unwrap_or_default();
+    let mut member_gids = Vec::new();
+    if let Some(username) = user.name.as_deref() {
+        member_gids = process_user::lookup_groups(process_user::GROUP_PATH, username)?;
+        member_gids.retain(|gid| *gid != user.gid && !gids.contains(gid));
+    }
+
+    let setgroups =
+        fs::read_to_string("/proc/self/setgroups").context("failed to read setgroups")?;
+    if setgroups.trim() == "deny" {
+        if !gids.is_empty() {
+            bail!("cannot set supplementary gids, setgroup is disabled");
+        }
+        if !member_gids.is_empty() {
+            log::warn!(
+                "setgroup is disabled, groups {:?} of /etc/group are not applied",
+                member_gids
+            );
+        }
+        return Ok(());
+    }
+
+    match rootless {
+        Some(r) if !r.privileged && !gids.is_empty() => {
+            // this should have been detected during validation
+            unreachable!("unprivileged users cannot set supplementary gids in rootless container")
+        }
+        _ => {
+            gids.extend(member_gids);
+            let gids: Vec<Gid> = gids.into_iter().map(Gid::from_raw).collect();
+            log::debug!("set supplementary gids {:?}", gids);
+            nix::unistd::setgroups(&gids).context("failed to set supplementary gids")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+    use nix::{fcntl, sys, unistd};
+    use std::fs;
+
+    #[test]
+    fn test_get_open_fds() -> Result<()> {
+        let file = fs::File::open("/dev/null")?;
+        let fd = file.as_raw_fd();
+        let open_fds = super::get_open_fds()?;
+
+        if !open_fds.iter().any(|&v| v == fd) {
+            bail!("Failed to find the opened dev null fds: {:?}", open_fds);
+        }
+
+        // explicitly close the file before the test case returns.
+        drop(file);
+
+        // The stdio fds should also be contained in the list of opened fds.
+        if !vec![0, 1, 2]
+            .iter()
+            .all(|&stdio_fd| open_fds.iter().any(|&open_fd| open_fd == stdio_fd))
+        {
+            bail!("Failed to find the stdio fds: {:?}", open_fds);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleanup_file_descriptors() -> Result<()> {
+        // The cleanup closes fds of the whole process, so it runs in a child
+        // process to leave the fds of other tests alone.
+        let child = match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Child => {
+                let check = || -> Result<()> {
+                    // Open fds without the CLOEXEC flag. Rust automatically
+                    // adds the flag, so we use fcntl::open here for more control.
+                    let preserved =
+                        fcntl::open("/dev/null", fcntl::OFlag::O_RDWR, sys::stat::Mode::empty())?;
+                    let inherited =
+                        fcntl::open("/dev/null", fcntl::OFlag::O_RDWR, sys::stat::Mode::empty())?;
+                    let internal = fs::File::open("/dev/null")?;
+                    cleanup_file_descriptors(preserved - 2)
+                        .with_context(|| "Failed to clean up the fds")?;
+
+                    let fd_flag = fcntl::fcntl(preserved, fcntl::F_GETFD)?;
+                    if (fd_flag & fcntl::FdFlag::FD_CLOEXEC.bits()) != 0 {
+                        bail!("CLOEXEC flag is not set correctly");
+                    }
+                    if fcntl::fcntl(inherited, fcntl::F_GETFD).is_ok() {
+                        bail!("inherited fd has not been closed");
+                    }
+                    fcntl::fcntl(internal.as_raw_fd(), fcntl::F_GETFD)?;
+                    Ok(())
+                };
+                let code = match check() {
+                    Ok(_) => 0,
+                    Err(err) => {
+                        eprintln!("{:?}", err);
+                        1
+                    }
+                };
+                unsafe { libc::_exit(code) };
+            }
+            unistd::ForkResult::Parent { child } => child,
+        };
+
+        assert_eq!(
+            sys::wait::waitpid(child, None)?,
+            sys::wait::WaitStatus::Exited(child, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_root_is_rootfs() {
+        assert!(root_is_rootfs(
+            "1 1 0:2 / / rw shared:1 - rootfs rootfs rw,size=4063648k\n"
+        ));
+        assert!(!root_is_rootfs(
+            "21 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+             22 21 0:5 / /proc rw shared:2 - proc proc rw\n"
+        ));
+    }
+
+    #[test]
+    fn test_ensure_procfs_entry() -> Result<()> {
+        let target = open_in_root("/proc/sys")?.unwrap();
+        ensure_procfs_entry(&target, "/proc/sys")?;
+        // /proc/self is a symlink to the directory of the current process
+        let target = open_in_root("/proc/self")?.unwrap();
+        assert!(ensure_procfs_entry(&target, "/proc/self").is_err());
+        // entries outside of /proc are not checked
+        let target = open_in_root("/tmp")?.unwrap();
+        ensure_procfs_entry(&target, "/tmp")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! IO priority of the container process as described by the
+//! `process.ioPriority` object of the runtime spec. Like the scheduler, it is
+//! read from the json of the spec or the process file directly, because the
+//! oci-spec crate does not know about it yet. The priority is set with
+//! ioprio_set and inherited by the container payload.
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::utils;
+
+// see ioprio_set(2)
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_CLASS_RT: u32 = 1;
+const IOPRIO_CLASS_BE: u32 = 2;
+const IOPRIO_CLASS_IDLE: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum IoPriorityClass {
+    #[serde(rename = "IOPRIO_CLASS_RT")]
+    RealTime,
+    #[serde(rename = "IOPRIO_CLASS_BE")]
+    BestEffort,
+    #[serde(rename = "IOPRIO_CLASS_IDLE")]
+    Idle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct IoPriority {
+    pub class: IoPriorityClass,
+    /// Priority within the class, from 0 (highest) to 7 (lowest)
+    #[serde(default)]
+    pub priority: i64,
+}
+
+impl IoPriority {
+    /// Reads the io priority from the process object of the spec
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        Self::load(config, "/process/ioPriority")
+    }
+
+    /// Reads the io priority from the process file passed to exec
+    pub fn from_process(process: &Value) -> Result<Option<Self>> {
+        Self::load(process, "/ioPriority")
+    }
+
+    fn load(config: &Value, pointer: &str) -> Result<Option<Self>> {
+        let io_priority: Option<Self> = utils::spec_field(config, pointer)?;
+        if let Some(io_priority) = io_priority.as_ref() {
+            io_priority.validate()?;
+        }
+
+        Ok(io_priority)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(0..=7).contains(&self.priority) {
+            bail!("io priority {} is not in range 0..7", self.priority);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the io priority of the calling process. The realtime class
+    /// requires CAP_SYS_ADMIN, so this has to happen before the capabilities
+    /// are dropped.
+    pub fn apply(&self) -> Result<()> {
+        log::debug!("set io priority {:?}", self);
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                self.as_raw() as libc::c_int,
+            )
+        };
+        if let Err(err) = Errno::result(res) {
+            bail!("failed to set io priority {:?}: {}", self, err);
+        }
+
+        Ok(())
+    }
+
+    fn as_raw(&self) -> u32 {
+        let class = match self.class {
+            IoPriorityClass::RealTime => IOPRIO_CLASS_RT,
+            IoPriorityClass::BestEffort => IOPRIO_CLASS_BE,
+            IoPriorityClass::Idle => IOPRIO_CLASS_IDLE,
+        };
+
+        (class << IOPRIO_CLASS_SHIFT) | self.priority as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_priority() {
+        let io_priority: IoPriority =
+            serde_json::from_str(r#"{"class": "IOPRIO_CLASS_BE", "priority": 4}"#).unwrap();
+        assert!(io_priority.validate().is_ok());
+        assert_eq!(io_priority.as_raw(), (2 << 13) | 4);
+
+        let io_priority: IoPriority =
+            serde_json::from_str(r#"{"class": "IOPRIO_CLASS_IDLE"}"#).unwrap();
+        assert_eq!(io_priority.as_raw(), 3 << 13);
+
+        let io_priority: IoPriority =
+            serde_json::from_str(r#"{"class": "IOPRIO_CLASS_RT", "priority": 8}"#).unwrap();
+        assert!(io_priority.validate().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Per container memory management hints, such as transparent hugepage (THP)
+//! and kernel samepage merging (KSM) policy. These are configured through
+//! annotations, because the OCI runtime spec has no field for them, and are
+//! applied to the container init process. Both settings are inherited by the
+//! process that is executed as the container payload.
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+
+/// Disables transparent hugepages for the container process when set to "true"
+pub const THP_DISABLE_ANNOTATION: &str = "youki.memory.thp_disable";
+/// Marks all anonymous memory of the container process as mergeable by KSM when set to "true"
+pub const KSM_MERGE_ANNOTATION: &str = "youki.memory.ksm_merge";
+
+// PR_SET_MEMORY_MERGE was introduced with linux 6.4 and is not yet exported by libc
+const PR_SET_MEMORY_MERGE: libc::c_int = 67;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryHints {
+    pub thp_disable: Option<bool>,
+    pub ksm_merge: Option<bool>,
+}
+
+impl MemoryHints {
+    pub fn from_annotations(annotations: Option<&HashMap<String, String>>) -> Result<Self> {
+        let mut hints = Self::default();
+        if let Some(annotations) = annotations {
+            hints.thp_disable = parse_bool(annotations, THP_DISABLE_ANNOTATION)?;
+            hints.ksm_merge = parse_bool(annotations, KSM_MERGE_ANNOTATION)?;
+        }
+
+        Ok(hints)
+    }
+
+    pub fn apply(&self) -> Result<()> {
+        if let Some(disable) = self.thp_disable {
+            log::debug!("set transparent hugepage disabled to {}", disable);
+            if let Err(err) = set_prctl(libc::PR_SET_THP_DISABLE, disable) {
+                bail!("failed to set transparent hugepage policy: {}", err);
+            }
+        }
+
+        if let Some(merge) = self.ksm_merge {
+            log::debug!("set ksm memory merge to {}", merge);
+            match set_prctl(PR_SET_MEMORY_MERGE, merge) {
+                Ok(_) => {}
+                // kernels older than 6.4 or without CONFIG_KSM do not know about this option
+                Err(Errno::EINVAL) => {
+                    log::warn!("ksm memory merge is not supported by the kernel, ignoring it")
+                }
+                Err(err) => bail!("failed to set ksm memory merge: {}", err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_bool(annotations: &HashMap<String, String>, key: &str) -> Result<Option<bool>> {
+    match annotations.get(key).map(|v| v.trim()) {
+        None => Ok(None),
+        Some("true") => Ok(Some(true)),
+        Some("false") => Ok(Some(false)),
+        Some(value) => bail!(
+            "invalid value {:?} for annotation {}, expected true or false",
+            value,
+            key
+        ),
+    }
+}
+
+fn set_prctl(option: libc::c_int, enable: bool) -> std::result::Result<(), Errno> {
+    let res = unsafe { libc::prctl(option, enable as libc::c_ulong, 0, 0, 0) };
+    Errno::result(res).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_annotations() -> Result<()> {
+        let hints = MemoryHints::from_annotations(None)?;
+        assert_eq!(hints, MemoryHints::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_annotations() -> Result<()> {
+        let mut annotations = HashMap::new();
+        annotations.insert(THP_DISABLE_ANNOTATION.to_owned(), "true".to_owned());
+        annotations.insert(KSM_MERGE_ANNOTATION.to_owned(), "false".to_owned());
+
+        let hints = MemoryHints::from_annotations(Some(&annotations))?;
+        assert_eq!(hints.thp_disable, Some(true));
+        assert_eq!(hints.ksm_merge, Some(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_annotation() {
+        let mut annotations = HashMap::new();
+        annotations.insert(THP_DISABLE_ANNOTATION.to_owned(), "yes".to_owned());
+
+        assert!(MemoryHints::from_annotations(Some(&annotations)).is_err());
+    }
+}
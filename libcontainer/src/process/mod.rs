@@ -2,6 +2,12 @@
 //! with enums and functions specific to youki implemented
 
 pub mod channel;
+pub mod cpu_affinity;
 pub mod fork;
 pub mod init;
+pub mod io_priority;
+pub mod memory_hints;
 pub mod message;
+pub mod readahead;
+pub mod scheduler;
+pub mod user;
@@ -0,0 +1,346 @@
+//! Warms the page cache with the container entrypoint before it is executed.
+//! The binary, its program interpreter and the shared libraries it links
+//! against are read ahead while the container is being created, so the first
+//! instructions of the payload do not have to wait for disk io once the
+//! container is started. This is opt-in through an annotation, because it
+//! costs io bandwidth which is wasted if the files are already cached.
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+
+/// Reads ahead the entrypoint and its libraries when set to "true"
+pub const READAHEAD_ANNOTATION: &str = "youki.readahead";
+
+// Directories the dynamic linker searches by default
+const LIBRARY_PATHS: &[&str] = &[
+    "/lib",
+    "/usr/lib",
+    "/lib64",
+    "/usr/lib64",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+];
+
+// Upper bound for the number of files which are read ahead, so a broken
+// dependency graph can not stall the container creation
+const MAX_FILES: usize = 64;
+
+// Size of a program header and of an entry of the dynamic section of a 64
+// bit ELF object
+const PHDR_SIZE: u64 = 56;
+const DYN_SIZE: u64 = 16;
+// Upper bound for the entries of a dynamic section which are read
+const MAX_DYN_ENTRIES: u64 = 4096;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const DT_NULL: i64 = 0;
+const DT_NEEDED: i64 = 1;
+const DT_STRTAB: i64 = 5;
+
+/// Checks if readahead has been requested through the annotations
+pub fn is_enabled(annotations: Option<&HashMap<String, String>>) -> bool {
+    annotations
+        .and_then(|a| a.get(READAHEAD_ANNOTATION))
+        .map(|v| v.trim() == "true")
+        .unwrap_or(false)
+}
+
+/// Reads ahead the entrypoint and all shared libraries it depends on. Has to
+/// be called after the rootfs has been entered and the environment of the
+/// container process has been set up, because the entrypoint is looked up in
+/// the PATH of the container.
+pub fn readahead_entrypoint(entrypoint: &str) -> Result<()> {
+    let start = Instant::now();
+    let binary = find_executable(entrypoint)
+        .with_context(|| format!("failed to find entrypoint {}", entrypoint))?;
+
+    let mut visited = HashSet::new();
+    let mut pending = vec![binary];
+    let mut bytes = 0;
+    while let Some(path) = pending.pop() {
+        if visited.len() >= MAX_FILES || !visited.insert(path.clone()) {
+            continue;
+        }
+
+        let file = File::open(&path).with_context(|| format!("failed to open {:?}", path))?;
+        bytes += readahead(&file).with_context(|| format!("failed to readahead {:?}", path))?;
+
+        // files which are not ELF objects, like scripts, have no dependencies
+        let deps = match ElfDependencies::parse(&file) {
+            Ok(deps) => deps,
+            Err(err) => {
+                log::debug!("no dependencies of {:?} are read ahead: {}", path, err);
+                continue;
+            }
+        };
+
+        pending.extend(deps.interpreter);
+        pending.extend(deps.needed.iter().filter_map(|lib| find_library(lib)));
+    }
+
+    log::debug!(
+        "read ahead {} bytes of {} files for {} in {:?}",
+        bytes,
+        visited.len(),
+        entrypoint,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+fn readahead(file: &File) -> Result<u64> {
+    let len = file.metadata()?.len();
+    let res = unsafe { libc::readahead(file.as_raw_fd(), 0, len as libc::size_t) };
+    Errno::result(res)?;
+    Ok(len)
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    env::var("PATH")
+        .ok()?
+        .split(':')
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+}
+
+fn find_library(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    let ld_library_path = env::var("LD_LIBRARY_PATH").unwrap_or_default();
+    ld_library_path
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .chain(LIBRARY_PATHS.iter().copied())
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+}
+
+/// Program interpreter and shared libraries of a 64 bit little endian ELF
+/// object. The offsets are taken from the file, which may have been crafted,
+/// so they are checked before they are used.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ElfDependencies {
+    interpreter: Option<PathBuf>,
+    needed: Vec<String>,
+}
+
+impl ElfDependencies {
+    fn parse(file: &File) -> Result<Self> {
+        let mut ident = [0u8; 64];
+        file.read_exact_at(&mut ident, 0)?;
+        if &ident[0..4] != b"\x7fELF" || ident[4] != 2 || ident[5] != 1 {
+            bail!("not a 64 bit little endian ELF object");
+        }
+
+        let phoff = read_u64(&ident, 0x20);
+        let phentsize = read_u16(&ident, 0x36) as u64;
+        let phnum = read_u16(&ident, 0x38) as u64;
+        if phentsize < PHDR_SIZE {
+            bail!("program header size {} is too small", phentsize);
+        }
+
+        let mut deps = Self::default();
+        let mut loads = Vec::new();
+        let mut dynamic = None;
+        for i in 0..phnum {
+            let mut phdr = [0u8; PHDR_SIZE as usize];
+            file.read_exact_at(&mut phdr, table_offset(phoff, i, phentsize)?)?;
+            let typ = read_u32(&phdr, 0);
+            let offset = read_u64(&phdr, 8);
+            let vaddr = read_u64(&phdr, 16);
+            let filesz = read_u64(&phdr, 32);
+            match typ {
+                PT_LOAD => loads.push((vaddr, offset, filesz)),
+                PT_DYNAMIC => dynamic = Some((offset, filesz)),
+                PT_INTERP => {
+                    // the size is taken from the file, so it is capped
+                    let max_len = (filesz as usize).min(libc::PATH_MAX as usize);
+                    let interpreter = read_string(file, offset, max_len)?;
+                    deps.interpreter = Some(PathBuf::from(interpreter));
+                }
+                _ => {}
+            }
+        }
+
+        // statically linked binaries have no dynamic section
+        let (offset, size) = match dynamic {
+            Some(dynamic) => dynamic,
+            None => return Ok(deps),
+        };
+
+        let mut needed = Vec::new();
+        let mut strtab = None;
+        for i in 0..(size / DYN_SIZE).min(MAX_DYN_ENTRIES) {
+            let mut entry = [0u8; DYN_SIZE as usize];
+            file.read_exact_at(&mut entry, table_offset(offset, i, DYN_SIZE)?)?;
+            let tag = read_u64(&entry, 0) as i64;
+            let val = read_u64(&entry, 8);
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => needed.push(val),
+                DT_STRTAB => strtab = Some(val),
+                _ => {}
+            }
+        }
+
+        // the string table is referenced by its virtual address
+        let strtab = match strtab.and_then(|addr| {
+            loads.iter().find_map(|(vaddr, offset, filesz)| {
+                addr.checked_sub(*vaddr)
+                    .filter(|relative| relative < filesz)
+                    .and_then(|relative| relative.checked_add(*offset))
+            })
+        }) {
+            Some(strtab) => strtab,
+            None => bail!("no string table in dynamic section"),
+        };
+
+        for name in needed {
+            let offset = strtab
+                .checked_add(name)
+                .context("library name is out of range")?;
+            deps.needed.push(read_string(file, offset, 256)?);
+        }
+
+        Ok(deps)
+    }
+}
+
+// Offset of the entry with the index in a table of the file
+fn table_offset(base: u64, index: u64, entry_size: u64) -> Result<u64> {
+    index
+        .checked_mul(entry_size)
+        .and_then(|relative| base.checked_add(relative))
+        .context("table entry is out of range")
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    let mut bytes = [0u8; 2];
+    bytes.copy_from_slice(&buf[offset..offset + 2]);
+    u16::from_le_bytes(bytes)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+// Reads a nul terminated string of at most max_len bytes
+fn read_string(file: &File, offset: u64, max_len: usize) -> Result<String> {
+    let mut buf = vec![0u8; max_len];
+    let read = file.read_at(&mut buf, offset)?;
+    buf.truncate(read);
+    if let Some(end) = buf.iter().position(|b| *b == 0) {
+        buf.truncate(end);
+    }
+
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_is_enabled() {
+        assert!(!is_enabled(None));
+
+        let mut annotations = HashMap::new();
+        annotations.insert(READAHEAD_ANNOTATION.to_owned(), "true".to_owned());
+        assert!(is_enabled(Some(&annotations)));
+    }
+
+    #[test]
+    #[cfg(all(target_pointer_width = "64", target_endian = "little"))]
+    fn test_parse_elf_dependencies() -> Result<()> {
+        // the test binary itself is a dynamically linked executable
+        let file = File::open("/proc/self/exe")?;
+        let deps = ElfDependencies::parse(&file)?;
+        assert!(deps.interpreter.is_some());
+        assert!(deps.needed.iter().any(|lib| lib.starts_with("libc.so")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_oversized_interpreter() -> Result<()> {
+        let tmp = create_temp_dir("test_parse_oversized_interpreter")?;
+        let path = tmp.join("binary");
+        let mut elf = elf_with_header(PT_INTERP, 120, u64::MAX);
+        elf.resize(elf.len() + 2 * libc::PATH_MAX as usize, b'a');
+        std::fs::write(&path, &elf)?;
+
+        let deps = ElfDependencies::parse(&File::open(&path)?)?;
+        let interpreter = deps.interpreter.unwrap();
+        assert_eq!(interpreter.as_os_str().len(), libc::PATH_MAX as usize);
+        Ok(())
+    }
+
+    // ELF header followed by a single program header
+    fn elf_with_header(typ: u32, offset: u64, filesz: u64) -> Vec<u8> {
+        let mut elf = vec![0u8; 64 + 56];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+        elf[64..68].copy_from_slice(&typ.to_le_bytes());
+        elf[72..80].copy_from_slice(&offset.to_le_bytes());
+        elf[96..104].copy_from_slice(&filesz.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn test_parse_invalid_offsets() -> Result<()> {
+        let tmp = create_temp_dir("test_parse_invalid_offsets")?;
+        let path = tmp.join("binary");
+
+        let mut elf = elf_with_header(PT_LOAD, 0, 0);
+        elf[54..56].copy_from_slice(&32u16.to_le_bytes());
+        std::fs::write(&path, &elf)?;
+        assert!(ElfDependencies::parse(&File::open(&path)?).is_err());
+
+        let mut elf = elf_with_header(PT_LOAD, 0, 0);
+        elf[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        elf[56..58].copy_from_slice(&2u16.to_le_bytes());
+        std::fs::write(&path, &elf)?;
+        assert!(ElfDependencies::parse(&File::open(&path)?).is_err());
+
+        let elf = elf_with_header(PT_DYNAMIC, u64::MAX - 8, 64);
+        std::fs::write(&path, &elf)?;
+        assert!(ElfDependencies::parse(&File::open(&path)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_non_elf() -> Result<()> {
+        let file = File::open("/proc/self/status")?;
+        assert!(ElfDependencies::parse(&file).is_err());
+        Ok(())
+    }
+}
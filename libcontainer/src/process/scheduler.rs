@@ -0,0 +1,284 @@
+//! Scheduling policy of the container process as described by the
+//! `process.scheduler` object of the runtime spec. The oci-spec crate does
+//! not know about this object yet, so it is read from the json of the spec
+//! or the process file directly. The policy is applied to the container init
+//! process with sched_setattr and is inherited by the container payload, so
+//! realtime and deadline scheduled workloads do not need a privileged wrapper.
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::utils;
+
+// sched_setattr is not wrapped by libc, so the attributes of the kernel
+// interface are defined here, see sched_setattr(2)
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+const SCHED_OTHER: u32 = 0;
+const SCHED_FIFO: u32 = 1;
+const SCHED_RR: u32 = 2;
+const SCHED_BATCH: u32 = 3;
+const SCHED_ISO: u32 = 4;
+const SCHED_IDLE: u32 = 5;
+const SCHED_DEADLINE: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SchedulerPolicy {
+    #[serde(rename = "SCHED_OTHER")]
+    Other,
+    #[serde(rename = "SCHED_FIFO")]
+    Fifo,
+    #[serde(rename = "SCHED_RR")]
+    RoundRobin,
+    #[serde(rename = "SCHED_BATCH")]
+    Batch,
+    #[serde(rename = "SCHED_ISO")]
+    Iso,
+    #[serde(rename = "SCHED_IDLE")]
+    Idle,
+    #[serde(rename = "SCHED_DEADLINE")]
+    Deadline,
+}
+
+impl SchedulerPolicy {
+    fn as_raw(&self) -> u32 {
+        match self {
+            Self::Other => SCHED_OTHER,
+            Self::Fifo => SCHED_FIFO,
+            Self::RoundRobin => SCHED_RR,
+            Self::Batch => SCHED_BATCH,
+            Self::Iso => SCHED_ISO,
+            Self::Idle => SCHED_IDLE,
+            Self::Deadline => SCHED_DEADLINE,
+        }
+    }
+
+    fn is_realtime(&self) -> bool {
+        matches!(self, Self::Fifo | Self::RoundRobin)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SchedulerFlag {
+    #[serde(rename = "SCHED_FLAG_RESET_ON_FORK")]
+    ResetOnFork,
+    #[serde(rename = "SCHED_FLAG_RECLAIM")]
+    Reclaim,
+    #[serde(rename = "SCHED_FLAG_DL_OVERRUN")]
+    DlOverrun,
+    #[serde(rename = "SCHED_FLAG_KEEP_POLICY")]
+    KeepPolicy,
+    #[serde(rename = "SCHED_FLAG_KEEP_PARAMS")]
+    KeepParams,
+    #[serde(rename = "SCHED_FLAG_UTIL_CLAMP_MIN")]
+    UtilClampMin,
+    #[serde(rename = "SCHED_FLAG_UTIL_CLAMP_MAX")]
+    UtilClampMax,
+}
+
+impl SchedulerFlag {
+    fn as_raw(&self) -> u64 {
+        match self {
+            Self::ResetOnFork => 0x01,
+            Self::Reclaim => 0x02,
+            Self::DlOverrun => 0x04,
+            Self::KeepPolicy => 0x08,
+            Self::KeepParams => 0x10,
+            Self::UtilClampMin => 0x20,
+            Self::UtilClampMax => 0x40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Scheduler {
+    pub policy: SchedulerPolicy,
+    /// Nice value for SCHED_OTHER and SCHED_BATCH
+    pub nice: Option<i32>,
+    /// Static priority for SCHED_FIFO and SCHED_RR
+    pub priority: Option<i32>,
+    #[serde(default)]
+    pub flags: Vec<SchedulerFlag>,
+    /// Runtime, deadline and period of SCHED_DEADLINE in nanoseconds
+    pub runtime: Option<u64>,
+    pub deadline: Option<u64>,
+    pub period: Option<u64>,
+}
+
+impl Scheduler {
+    /// Reads the scheduler from the process object of the spec
+    pub fn from_config(config: &Value) -> Result<Option<Self>> {
+        Self::load(config, "/process/scheduler")
+    }
+
+    /// Reads the scheduler from the process file passed to exec
+    pub fn from_process(process: &Value) -> Result<Option<Self>> {
+        Self::load(process, "/scheduler")
+    }
+
+    fn load(config: &Value, pointer: &str) -> Result<Option<Self>> {
+        let scheduler: Option<Self> = utils::spec_field(config, pointer)?;
+        if let Some(scheduler) = scheduler.as_ref() {
+            scheduler.validate()?;
+        }
+
+        Ok(scheduler)
+    }
+
+    /// Checks that only the attributes which are meaningful for the policy
+    /// are set, so a misconfiguration is reported when the container is
+    /// created instead of silently being ignored
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("scheduler nice value {} is not in range -20..19", nice);
+            }
+        }
+
+        if self.policy.is_realtime() {
+            match self.priority {
+                Some(priority) if (1..=99).contains(&priority) => {}
+                Some(priority) => bail!(
+                    "scheduler priority {} is not in range 1..99 for {:?}",
+                    priority,
+                    self.policy
+                ),
+                None => bail!("scheduler priority is required for {:?}", self.policy),
+            }
+        } else if self.priority.unwrap_or(0) != 0 {
+            bail!("scheduler priority must be 0 for {:?}", self.policy);
+        }
+
+        if self.policy == SchedulerPolicy::Deadline {
+            let (runtime, deadline) = match (self.runtime, self.deadline) {
+                (Some(runtime), Some(deadline)) => (runtime, deadline),
+                _ => bail!("scheduler runtime and deadline are required for SCHED_DEADLINE"),
+            };
+            let period = self.period.unwrap_or(deadline);
+            if runtime > deadline || deadline > period {
+                bail!(
+                    "scheduler requires runtime ({}) <= deadline ({}) <= period ({})",
+                    runtime,
+                    deadline,
+                    period
+                );
+            }
+        } else if self.runtime.is_some() || self.deadline.is_some() || self.period.is_some() {
+            bail!(
+                "scheduler runtime, deadline and period are only valid for SCHED_DEADLINE, not {:?}",
+                self.policy
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Applies the scheduling policy to the calling process. It is applied
+    /// right before the payload is executed, so hooks are not run with the
+    /// policy and a deadline task does not have to fork. Realtime and
+    /// deadline policies therefore require CAP_SYS_NICE in the capabilities
+    /// of the container process.
+    pub fn apply(&self) -> Result<()> {
+        let attr = self.to_attr();
+        log::debug!("set scheduler {:?}", attr);
+        let res =
+            unsafe { libc::syscall(libc::SYS_sched_setattr, 0, &attr as *const SchedAttr, 0) };
+        if let Err(err) = Errno::result(res) {
+            bail!("failed to set scheduler {:?}: {}", self.policy, err);
+        }
+
+        Ok(())
+    }
+
+    fn to_attr(&self) -> SchedAttr {
+        SchedAttr {
+            size: std::mem::size_of::<SchedAttr>() as u32,
+            sched_policy: self.policy.as_raw(),
+            sched_flags: self.flags.iter().fold(0, |flags, f| flags | f.as_raw()),
+            sched_nice: self.nice.unwrap_or(0),
+            sched_priority: self.priority.unwrap_or(0) as u32,
+            sched_runtime: self.runtime.unwrap_or(0),
+            sched_deadline: self.deadline.unwrap_or(0),
+            sched_period: self.period.unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(json: &str) -> Scheduler {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_scheduler_to_attr() {
+        let scheduler = parse(
+            r#"{"policy": "SCHED_FIFO", "priority": 50, "flags": ["SCHED_FLAG_RESET_ON_FORK", "SCHED_FLAG_RECLAIM"]}"#,
+        );
+        assert!(scheduler.validate().is_ok());
+
+        let attr = scheduler.to_attr();
+        assert_eq!(attr.size, 48);
+        assert_eq!(attr.sched_policy, SCHED_FIFO);
+        assert_eq!(attr.sched_priority, 50);
+        assert_eq!(attr.sched_flags, 0x03);
+    }
+
+    #[test]
+    fn test_validate_scheduler() {
+        assert!(parse(r#"{"policy": "SCHED_OTHER", "nice": -5}"#)
+            .validate()
+            .is_ok());
+        assert!(parse(r#"{"policy": "SCHED_OTHER", "nice": 20}"#)
+            .validate()
+            .is_err());
+        assert!(parse(r#"{"policy": "SCHED_RR"}"#).validate().is_err());
+        assert!(parse(r#"{"policy": "SCHED_BATCH", "priority": 10}"#)
+            .validate()
+            .is_err());
+        assert!(parse(r#"{"policy": "SCHED_OTHER", "runtime": 1000}"#)
+            .validate()
+            .is_err());
+        assert!(parse(
+            r#"{"policy": "SCHED_DEADLINE", "runtime": 10000, "deadline": 20000, "period": 40000}"#
+        )
+        .validate()
+        .is_ok());
+        assert!(
+            parse(r#"{"policy": "SCHED_DEADLINE", "runtime": 30000, "deadline": 20000}"#)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_scheduler_from_config() -> Result<()> {
+        let config = json!({"process": {"args": ["sh"]}});
+        assert_eq!(Scheduler::from_config(&config)?, None);
+
+        let config = json!({"process": {"scheduler": {"policy": "SCHED_IDLE"}}});
+        assert_eq!(
+            Scheduler::from_config(&config)?.map(|s| s.policy),
+            Some(SchedulerPolicy::Idle)
+        );
+
+        let config = json!({"process": {"scheduler": {"policy": "SCHED_FIFO"}}});
+        assert!(Scheduler::from_config(&config).is_err());
+        Ok(())
+    }
+}
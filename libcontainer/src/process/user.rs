@@ -0,0 +1,288 @@
+//! Lookup of users and groups in the /etc/passwd and /etc/group files of the
+//! container, which is how runc resolves the user of the container process.
+//! The files are read after the rootfs has been entered, so the databases of
+//! the host are never consulted.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils;
+
+pub const PASSWD_PATH: &str = "/etc/passwd";
+pub const GROUP_PATH: &str = "/etc/group";
+// Home directory of users without an entry in the passwd file
+const DEFAULT_HOME: &str = "/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// Identity of the container process after the user of the spec has been
+/// resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub name: Option<String>,
+    pub home: PathBuf,
+}
+
+/// Ids of the user of the container process as they are given in the spec.
+/// The oci-spec crate deserializes a missing id to 0, so the json of the spec
+/// is consulted to tell root apart from an id which is not set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserIds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+impl UserIds {
+    /// Reads the ids from the process object of the spec
+    pub fn from_config(config: &Value) -> Result<Self> {
+        Self::load(config, "/process/user")
+    }
+
+    /// Reads the ids from the process file passed to exec
+    pub fn from_process(process: &Value) -> Result<Self> {
+        Self::load(process, "/user")
+    }
+
+    fn load(config: &Value, pointer: &str) -> Result<Self> {
+        Ok(utils::spec_field(config, pointer)?.unwrap_or_default())
+    }
+}
+
+/// Resolves the user of the container process against the passwd file like
+/// runc does. A username is looked up by name and has to exist, otherwise
+/// the entry of the uid is used if there is one. The entry provides the uid
+/// and the primary gid which are not set in the spec.
+pub fn resolve_user<P: AsRef<Path>>(
+    passwd: P,
+    ids: UserIds,
+    username: Option<&str>,
+) -> Result<ResolvedUser> {
+    let entries = read_passwd(passwd.as_ref())?;
+    let entry = match username.filter(|name| !name.is_empty()) {
+        Some(name) => match entries.into_iter().find(|entry| entry.name == name) {
+            Some(entry) => Some(entry),
+            None => bail!("user {} does not exist in {:?}", name, passwd.as_ref()),
+        },
+        None => {
+            let uid = ids.uid.unwrap_or(0);
+            entries.into_iter().find(|entry| entry.uid == uid)
+        }
+    };
+
+    Ok(match entry {
+        Some(entry) => ResolvedUser {
+            uid: ids.uid.unwrap_or(entry.uid),
+            gid: ids.gid.unwrap_or(entry.gid),
+            name: Some(entry.name),
+            home: entry.home,
+        },
+        None => ResolvedUser {
+            uid: ids.uid.unwrap_or(0),
+            gid: ids.gid.unwrap_or(0),
+            name: None,
+            home: PathBuf::from(DEFAULT_HOME),
+        },
+    })
+}
+
+/// Looks up a user by its uid. A missing passwd file is not an error,
+/// because many images do not have one.
+pub fn lookup_user<P: AsRef<Path>>(passwd: P, uid: u32) -> Result<Option<PasswdEntry>> {
+    Ok(read_passwd(passwd.as_ref())?
+        .into_iter()
+        .find(|entry| entry.uid == uid))
+}
+
+/// Returns the gids of all groups the user is a member of
+pub fn lookup_groups<P: AsRef<Path>>(group: P, username: &str) -> Result<Vec<u32>> {
+    Ok(read_group(group.as_ref())?
+        .into_iter()
+        .filter(|entry| entry.members.iter().any(|m| m == username))
+        .map(|entry| entry.gid)
+        .collect())
+}
+
+fn read_passwd(path: &Path) -> Result<Vec<PasswdEntry>> {
+    match read_database(path)? {
+        Some(content) => Ok(parse_passwd(&content)),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn read_group(path: &Path) -> Result<Vec<GroupEntry>> {
+    match read_database(path)? {
+        Some(content) => Ok(parse_group(&content)),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn read_database(path: &Path) -> Result<Option<String>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(String::from_utf8_lossy(&content).into_owned())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read {:?}", path)),
+    }
+}
+
+// name:password:uid:gid:gecos:home:shell, malformed lines are skipped like
+// the libc implementation does
+fn parse_passwd(content: &str) -> Vec<PasswdEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+
+            Some(PasswdEntry {
+                name: fields[0].to_owned(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: PathBuf::from(fields[5]),
+            })
+        })
+        .collect()
+}
+
+// name:password:gid:member,member
+fn parse_group(content: &str) -> Vec<GroupEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+
+            Some(GroupEntry {
+                name: fields[0].to_owned(),
+                gid: fields[2].parse().ok()?,
+                members: fields[3]
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|m| !m.is_empty())
+                    .map(String::from)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      # comment\n\
+                      broken:x:abc:0::/:/bin/false\n\
+                      app:x:1000:1000:App:/home/app:/bin/sh\n";
+        let entries = parse_passwd(passwd);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            PasswdEntry {
+                name: "app".to_owned(),
+                uid: 1000,
+                gid: 1000,
+                home: PathBuf::from("/home/app"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_user() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_resolve_user")?;
+        let passwd = dir.join("passwd");
+        fs::write(
+            &passwd,
+            "root:x:0:0:root:/root:/bin/sh
+app:x:1000:100:App:/home/app:/bin/sh
+",
+        )?;
+
+        let ids = |uid, gid| UserIds { uid, gid };
+
+        let user = resolve_user(&passwd, ids(None, None), Some("app"))?;
+        assert_eq!((user.uid, user.gid), (1000, 100));
+        assert_eq!(user.home, PathBuf::from("/home/app"));
+
+        let user = resolve_user(&passwd, ids(Some(1000), None), None)?;
+        assert_eq!((user.uid, user.gid), (1000, 100));
+        assert_eq!(user.name.as_deref(), Some("app"));
+
+        let user = resolve_user(&passwd, ids(Some(1000), Some(1000)), None)?;
+        assert_eq!(user.gid, 1000);
+
+        // explicit ids of root are not replaced by the entry of the user
+        let user = resolve_user(&passwd, ids(Some(0), Some(0)), Some("app"))?;
+        assert_eq!((user.uid, user.gid), (0, 0));
+        assert_eq!(user.home, PathBuf::from("/home/app"));
+
+        let user = resolve_user(&passwd, ids(Some(2000), Some(2000)), None)?;
+        assert_eq!(user.name, None);
+        assert_eq!(user.home, PathBuf::from("/"));
+
+        assert!(resolve_user(&passwd, ids(None, None), Some("nobody")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_ids_from_config() -> Result<()> {
+        let config = serde_json::json!({"process": {"user": {"uid": 0, "username": "app"}}});
+        assert_eq!(
+            UserIds::from_config(&config)?,
+            UserIds {
+                uid: Some(0),
+                gid: None
+            }
+        );
+        assert_eq!(
+            UserIds::from_config(&serde_json::json!({}))?,
+            UserIds::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_groups() -> Result<()> {
+        let dir = crate::utils::create_temp_dir("test_lookup_groups")?;
+        let group = dir.join("group");
+        fs::write(
+            &group,
+            "root:x:0:\nwheel:x:10:root,app\naudio:x:29:app\nvideo:x:44:other\n",
+        )?;
+
+        assert_eq!(lookup_groups(&group, "app")?, vec![10, 29]);
+        assert_eq!(lookup_groups(&group, "nobody")?, Vec::<u32>::new());
+        assert_eq!(
+            lookup_groups(dir.join("missing"), "app")?,
+            Vec::<u32>::new()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,696 @@
+//! During kernel initialization, a minimal replica of the ramfs filesystem is loaded, called rootfs.
+//! Most systems mount another filesystem over it
+
+use crate::idmap::{self, IdMappedMounts};
+use crate::nested::NestedEnvironment;
+use crate::securejoin::{self, RootfsPath};
+use anyhow::{bail, Context, Result};
+use cgroups::common::CgroupHierarchy;
+use nix::errno::Errno;
+use nix::mount::mount as nix_mount;
+use nix::mount::MsFlags;
+use nix::sys::stat::Mode;
+use nix::sys::stat::{mknod, umask};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{fchownat, ttyname, FchownatFlags};
+use nix::unistd::{Gid, Uid};
+use oci_spec::{LinuxDevice, LinuxDeviceType, Mount, Spec};
+use std::fs::OpenOptions;
+use std::fs::{canonicalize, create_dir_all, remove_file};
+use std::io::ErrorKind;
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+const STDIN_FILENO: RawFd = 0;
+// Size of /dev/shm if the spec does not set one, which is the default of docker
+const DEFAULT_SHM_SIZE: &str = "64m";
+
+pub fn prepare_rootfs(
+    spec: &Spec,
+    rootfs: &Path,
+    bind_devices: bool,
+    idmapped_mounts: &IdMappedMounts,
+    cgroup_hierarchies: &[CgroupHierarchy],
+) -> Result<()> {
+    log::debug!("Prepare rootfs: {:?}", rootfs);
+    let mut flags = MsFlags::MS_REC;
+    let linux = spec.linux.as_ref().context("no linux in spec")?;
+    if let Some(roofs_propagation) = linux.rootfs_propagation.as_ref() {
+        match roofs_propagation.as_str() {
+            "shared" => flags |= MsFlags::MS_SHARED,
+            "private" => flags |= MsFlags::MS_PRIVATE,
+            "slave" => flags |= MsFlags::MS_SLAVE,
+            uknown => bail!("unknown rootfs_propagation: {}", uknown),
+        }
+    } else {
+        flags |= MsFlags::MS_SLAVE;
+    }
+
+    nix_mount(None::<&str>, "/", None::<&str>, flags, None::<&str>)
+        .context("Failed to mount rootfs")?;
+
+    log::debug!("mount root fs {:?}", rootfs);
+    nix_mount::<Path, Path, str, str>(
+        Some(rootfs),
+        rootfs,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )?;
+
+    if let Some(mounts) = spec.mounts.as_ref() {
+        for mount in mounts.iter() {
+            log::debug!("Mount... {:?}", mount);
+            let (flags, data) = parse_mount(mount);
+            let mount_label = linux.mount_label.as_ref();
+            if mount.typ == Some("cgroup".to_string()) {
+                mount_cgroup(mount, rootfs, flags, cgroup_hierarchies)
+                    .with_context(|| format!("Failed to mount cgroup: {:?}", mount))?;
+            } else if let Some(fd) = idmapped_mounts.get(&mount.destination) {
+                mount_idmapped(mount, rootfs, fd)
+                    .with_context(|| format!("Failed to mount idmapped: {:?}", mount))?;
+            } else if mount.destination == PathBuf::from("/dev") {
+                mount_to_container(
+                    mount,
+                    rootfs,
+                    flags & !MsFlags::MS_RDONLY,
+                    &data,
+                    mount_label,
+                )
+                .with_context(|| format!("Failed to mount /dev: {:?}", mount))?;
+            } else if mount.typ.as_deref() == Some("mqueue") {
+                mount_mqueue(mount, rootfs, flags, &data, mount_label)
+                    .with_context(|| format!("Failed to mount mqueue: {:?}", mount))?;
+            } else if mount.destination == PathBuf::from("/dev/shm")
+                && mount.typ.as_deref() == Some("tmpfs")
+            {
+                let data = shm_data(&data)?;
+                mount_to_container(mount, rootfs, flags, &data, mount_label)
+                    .with_context(|| format!("Failed to mount /dev/shm: {:?}", mount))?;
+            } else {
+                mount_to_container(mount, rootfs, flags, &data, mount_label)
+                    .with_context(|| format!("Failed to mount: {:?}", mount))?;
+            }
+        }
+    }
+
+    setup_default_symlinks(rootfs).context("Failed to setup default symlinks")?;
+    if let Some(added_devices) = linux.devices.as_ref() {
+        // devices from the spec take precedence over the default devices
+        let defaults: Vec<LinuxDevice> = default_devices()
+            .into_iter()
+            .filter(|d| !added_devices.iter().any(|a| a.path == d.path))
+            .collect();
+        create_devices(rootfs, defaults.iter().chain(added_devices), bind_devices)
+    } else {
+        create_devices(rootfs, default_devices().iter(), bind_devices)
+    }
+    .context("Failed to create devices")?;
+
+    setup_ptmx(rootfs)?;
+    Ok(())
+}
+
+/// Remounts the root of the container read-only. Has to be called after the
+/// rootfs has been entered. The remount is not recursive, so /dev, /proc and
+/// the tmpfs mounts of the spec are separate mount points which stay writable.
+pub fn make_rootfs_readonly() -> Result<()> {
+    // flags of a mount which has been created in another user namespace are
+    // locked, so the remount fails with EPERM unless they are kept
+    let locked = locked_flags(statvfs("/").context("failed to stat rootfs")?.flags());
+    log::debug!("remount rootfs read-only, keeping {:?}", locked);
+    nix_mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | locked,
+        None::<&str>,
+    )
+    .context("failed to remount rootfs read-only")?;
+    Ok(())
+}
+
+fn locked_flags(current: FsFlags) -> MsFlags {
+    [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ]
+    .iter()
+    .filter(|(st, _)| current.contains(*st))
+    .fold(MsFlags::empty(), |flags, (_, ms)| flags | *ms)
+}
+
+/// Bind mounts the pseudo terminal, which has been connected to the stdio of
+/// the container process, to /dev/console of the container
+pub fn setup_console(rootfs: &Path) -> Result<()> {
+    let pty = ttyname(STDIN_FILENO).context("Failed to get the name of the pseudo terminal")?;
+    let console = rootfs.join("dev/console");
+    log::debug!("bind mount {:?} to {:?}", pty, console);
+
+    if !console.exists() {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .mode(0o666)
+            .open(&console)
+            .with_context(|| format!("Failed to create {:?}", console))?;
+    }
+
+    nix_mount(
+        Some(&pty),
+        &console,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .with_context(|| format!("Failed to bind mount {:?} to {:?}", pty, console))?;
+    Ok(())
+}
+
+fn setup_ptmx(rootfs: &Path) -> Result<()> {
+    if let Err(e) = remove_file(rootfs.join("dev/ptmx")) {
+        if e.kind() != ::std::io::ErrorKind::NotFound {
+            bail!("could not delete /dev/ptmx")
+        }
+    }
+
+    symlink("pts/ptmx", rootfs.join("dev/ptmx")).context("failed to symlink ptmx")?;
+    Ok(())
+}
+
+fn setup_default_symlinks(rootfs: &Path) -> Result<()> {
+    if Path::new("/proc/kcore").exists() {
+        symlink("/proc/kcore", rootfs.join("dev/kcore")).context("Failed to symlink kcore")?;
+    }
+
+    let defaults = [
+        ("/proc/self/fd", "dev/fd"),
+        ("/proc/self/fd/0", "dev/stdin"),
+        ("/proc/self/fd/1", "dev/stdout"),
+        ("/proc/self/fd/2", "dev/stderr"),
+    ];
+    for &(src, dst) in defaults.iter() {
+        let link = rootfs.join(dst);
+        // the image may already provide these links
+        if link.symlink_metadata().is_ok() {
+            continue;
+        }
+        symlink(src, &link).with_context(|| format!("Fail to symlink {:?}", link))?;
+    }
+
+    Ok(())
+}
+
+pub fn default_devices() -> Vec<LinuxDevice> {
+    vec![
+        LinuxDevice {
+            path: PathBuf::from("/dev/null"),
+            typ: LinuxDeviceType::C,
+            major: 1,
+            minor: 3,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+        LinuxDevice {
+            path: PathBuf::from("/dev/zero"),
+            typ: LinuxDeviceType::C,
+            major: 1,
+            minor: 5,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+        LinuxDevice {
+            path: PathBuf::from("/dev/full"),
+            typ: LinuxDeviceType::C,
+            major: 1,
+            minor: 7,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+        LinuxDevice {
+            path: PathBuf::from("/dev/tty"),
+            typ: LinuxDeviceType::C,
+            major: 5,
+            minor: 0,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+        LinuxDevice {
+            path: PathBuf::from("/dev/urandom"),
+            typ: LinuxDeviceType::C,
+            major: 1,
+            minor: 9,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+        LinuxDevice {
+            path: PathBuf::from("/dev/random"),
+            typ: LinuxDeviceType::C,
+            major: 1,
+            minor: 8,
+            file_mode: Some(0o066),
+            uid: None,
+            gid: None,
+        },
+    ]
+}
+
+fn create_devices<'a, I>(rootfs: &Path, devices: I, bind: bool) -> Result<()>
+where
+    I: Iterator<Item = &'a LinuxDevice>,
+{
+    let old_mode = umask(Mode::from_bits_truncate(0o000));
+    let result = devices
+        .map(|dev| {
+            if !dev.path.starts_with("/dev") {
+                bail!("{} is not a valid device path", dev.path.display());
+            }
+
+            if bind {
+                bind_dev(rootfs, dev)
+            } else {
+                mknod_dev(rootfs, dev)
+            }
+            .with_context(|| format!("Failed to create device {:?}", dev.path))
+        })
+        .collect::<Result<Vec<_>>>();
+    umask(old_mode);
+
+    result.map(|_| ())
+}
+
+// Bind mounts the device node of the host into the container. This is used
+// inside of user namespaces, where creating device nodes is not permitted.
+fn bind_dev(rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
+    let dest = securejoin::create_file_in_root(rootfs, &dev.path)?;
+    nix_mount(
+        Some(&dev.path),
+        &dest.proc_path(),
+        Some("bind"),
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )?;
+
+    Ok(())
+}
+
+fn mknod_dev(rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
+    fn makedev(major: i64, minor: i64) -> u64 {
+        ((minor & 0xff)
+            | ((major & 0xfff) << 8)
+            | ((minor & !0xff) << 12)
+            | ((major & !0xfff) << 32)) as u64
+    }
+
+    // the node is created relative to its parent, which has been resolved
+    // within the rootfs, and mknod does not follow a symlink in its place
+    let (parent, name) = securejoin::split_parent(&dev.path)?;
+    let parent = securejoin::mkdir_all_in_root(rootfs, parent)?;
+    let full_container_path = parent.proc_path().join(name);
+    if let Err(errno) = mknod(
+        &full_container_path,
+        dev.typ.to_sflag()?,
+        Mode::from_bits_truncate(dev.file_mode.unwrap_or(0o666)),
+        makedev(dev.major, dev.minor),
+    ) {
+        // mknod is not permitted in a user namespace, if we are not privileged
+        // over the initial user namespace. Same as runc, we fall back to bind
+        // mounting the device from the host in this case.
+        if errno == Errno::EPERM {
+            log::warn!(
+                "not permitted to create device {:?}, falling back to bind mount",
+                dev.path
+            );
+            return bind_dev(rootfs, dev);
+        }
+
+        bail!("mknod of {:?} failed: {}", dev.path, errno);
+    }
+
+    fchownat(
+        Some(parent.as_raw_fd()),
+        name,
+        dev.uid.map(Uid::from_raw),
+        dev.gid.map(Gid::from_raw),
+        FchownatFlags::NoFollowSymlink,
+    )?;
+
+    Ok(())
+}
+
+fn mount_to_container(
+    m: &Mount,
+    rootfs: &Path,
+    flags: MsFlags,
+    data: &str,
+    label: Option<&String>,
+) -> Result<()> {
+    let typ = m.typ.as_deref();
+    let d = if let Some(l) = label {
+        if typ != Some("proc") && typ != Some("sysfs") {
+            if data.is_empty() {
+                format!("context=\"{}\"", l)
+            } else {
+                format!("{},context=\"{}\"", data, l)
+            }
+        } else {
+            data.to_string()
+        }
+    } else {
+        data.to_string()
+    };
+    let source = m.source.as_ref().context("no source in mount spec")?;
+    let (src, is_file) = if typ == Some("bind") {
+        let src = canonicalize(source)?;
+        let is_file = src.is_file();
+        (src, is_file)
+    } else {
+        (PathBuf::from(source), false)
+    };
+    let dest = create_mount_destination(rootfs, &m.destination, is_file)?.proc_path();
+
+    if let Err(errno) = nix_mount(Some(&*src), &dest, typ, flags, Some(&*d)) {
+        if typ == Some("proc") && errno == Errno::EPERM && NestedEnvironment::detect().proc_masked {
+            bail!(
+                "mount of procfs on {:?} was refused, because parts of /proc are masked in the container youki runs in. \
+                The outer container has to be started with an unmasked /proc",
+                m.destination
+            );
+        }
+        if !matches!(errno, Errno::EINVAL) {
+            bail!("mount of {:?} failed: {}", m.destination, errno);
+        }
+        nix_mount(Some(&*src), &dest, typ, flags, Some(data))?;
+    }
+
+    if flags.contains(MsFlags::MS_BIND)
+        && flags.intersects(
+            !(MsFlags::MS_REC
+                | MsFlags::MS_REMOUNT
+                | MsFlags::MS_BIND
+                | MsFlags::MS_PRIVATE
+                | MsFlags::MS_SHARED
+                | MsFlags::MS_SLAVE),
+        )
+    {
+        // the file descriptor of the destination still refers to the
+        // directory below the new mount, which has to be resolved again
+        let dest = securejoin::open_in_root(rootfs, &m.destination)?.proc_path();
+        nix_mount(
+            Some(&dest),
+            &dest,
+            None::<&str>,
+            flags | MsFlags::MS_REMOUNT,
+            None::<&str>,
+        )?;
+    }
+    Ok(())
+}
+
+/// Mounts the cgroup of the container read-only, so processes in the container
+/// only see their own subtree. The unified hierarchy is bound directly, the
+/// hierarchies of cgroup v1 are bound below a tmpfs in the layout of the host.
+fn mount_cgroup(
+    m: &Mount,
+    rootfs: &Path,
+    flags: MsFlags,
+    hierarchies: &[CgroupHierarchy],
+) -> Result<()> {
+    if hierarchies.is_empty() {
+        log::warn!(
+            "cgroup of the container is not available, skip mount of {:?}",
+            m.destination
+        );
+        return Ok(());
+    }
+
+    create_mount_destination(rootfs, &m.destination, false)?;
+    if let [CgroupHierarchy { name: None, path }] = hierarchies {
+        return bind_readonly(path, rootfs, &m.destination, flags);
+    }
+
+    let dest = securejoin::open_in_root(rootfs, &m.destination)?.proc_path();
+    nix_mount(
+        Some("tmpfs"),
+        &dest,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some("mode=755"),
+    )
+    .context("Failed to mount tmpfs for cgroup v1 hierarchies")?;
+
+    // the tmpfs has been created by youki, so its content can be trusted
+    let tmpfs = securejoin::open_in_root(rootfs, &m.destination)?;
+    let dest = tmpfs.proc_path();
+    for hierarchy in hierarchies {
+        let name = match hierarchy.name.as_deref() {
+            Some(name) => name,
+            None => continue,
+        };
+        create_dir_all(dest.join(name))?;
+        bind_readonly(&hierarchy.path, rootfs, &m.destination.join(name), flags)?;
+
+        // co-mounted controllers are reachable by each of their names
+        if name.contains(',') {
+            for controller in name.split(',') {
+                match symlink(name, dest.join(controller)) {
+                    Err(err) if err.kind() != ErrorKind::AlreadyExists => {
+                        return Err(err).with_context(|| {
+                            format!("Failed to create symlink for cgroup {}", controller)
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    nix_mount(
+        None::<&str>,
+        &dest,
+        None::<&str>,
+        MsFlags::MS_REMOUNT
+            | MsFlags::MS_RDONLY
+            | MsFlags::MS_NOSUID
+            | MsFlags::MS_NODEV
+            | MsFlags::MS_NOEXEC,
+        Some("mode=755"),
+    )
+    .context("Failed to remount tmpfs for cgroup v1 hierarchies read-only")?;
+    Ok(())
+}
+
+fn bind_readonly(source: &Path, rootfs: &Path, destination: &Path, flags: MsFlags) -> Result<()> {
+    let target = securejoin::open_in_root(rootfs, destination)?.proc_path();
+    nix_mount(
+        Some(source),
+        &target,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .with_context(|| format!("Failed to bind {:?} to {:?}", source, destination))?;
+
+    // resolved again to get to the new mount
+    let target = securejoin::open_in_root(rootfs, destination)?.proc_path();
+    let kept = flags & (MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC);
+    nix_mount(
+        None::<&str>,
+        &target,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | kept,
+        None::<&str>,
+    )
+    .with_context(|| format!("Failed to remount {:?} read-only", destination))?;
+    Ok(())
+}
+
+/// Creates the destination of a mount if it does not exist in the image. The
+/// destination is resolved within the rootfs, so symlinks in the image can
+/// not redirect the mount to the host. Bind mounts of files need a file as
+/// destination, all other mounts a directory.
+pub fn create_mount_destination(
+    rootfs: &Path,
+    destination: &Path,
+    is_file: bool,
+) -> Result<RootfsPath> {
+    if is_file {
+        securejoin::create_file_in_root(rootfs, destination)
+    } else {
+        securejoin::mkdir_all_in_root(rootfs, destination)
+    }
+    .with_context(|| format!("Failed to create mount destination {:?}", destination))
+}
+
+fn mount_mqueue(
+    m: &Mount,
+    rootfs: &Path,
+    flags: MsFlags,
+    data: &str,
+    label: Option<&String>,
+) -> Result<()> {
+    let err = match mount_to_container(m, rootfs, flags, data, label) {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+
+    // older kernels do not support labels on mqueue filesystems
+    if label.is_some() && mount_to_container(m, rootfs, flags, data, None).is_ok() {
+        log::warn!(
+            "mqueue does not support labels, mounted {:?} without",
+            m.destination
+        );
+        return Ok(());
+    }
+
+    // an unprivileged user can only mount the mqueue of an ipc namespace it
+    // owns, which is not the case if the container shares the ipc namespace
+    // of the host
+    let host_mqueue = Path::new("/dev/mqueue");
+    if !host_mqueue.exists() {
+        return Err(err);
+    }
+    log::warn!(
+        "failed to mount mqueue ({}), bind the mqueue of the host instead",
+        err
+    );
+    let dest = create_mount_destination(rootfs, &m.destination, false)?;
+    nix_mount(
+        Some(host_mqueue),
+        &dest.proc_path(),
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .context("Failed to bind mqueue of the host")?;
+    Ok(())
+}
+
+// Adds the default size to the options of the /dev/shm tmpfs, the size of a
+// tmpfs is half of the memory of the host otherwise
+fn shm_data(data: &str) -> Result<String> {
+    let size = data
+        .split(',')
+        .find_map(|option| option.strip_prefix("size="));
+    match size {
+        Some(size) => {
+            validate_tmpfs_size(size)?;
+            Ok(data.to_owned())
+        }
+        None if data.is_empty() => Ok(format!("size={}", DEFAULT_SHM_SIZE)),
+        None => Ok(format!("{},size={}", data, DEFAULT_SHM_SIZE)),
+    }
+}
+
+// A size is given in bytes with an optional k, m or g suffix, or as
+// percentage of the memory of the host
+fn validate_tmpfs_size(size: &str) -> Result<()> {
+    let number = size
+        .strip_suffix(|c| matches!(c, 'k' | 'K' | 'm' | 'M' | 'g' | 'G' | '%'))
+        .unwrap_or(size);
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        bail!("invalid size {:?} for /dev/shm", size);
+    }
+
+    Ok(())
+}
+
+fn mount_idmapped(m: &Mount, rootfs: &Path, fd: RawFd) -> Result<()> {
+    let source = m.source.as_ref().context("no source in mount spec")?;
+    let dest = create_mount_destination(rootfs, &m.destination, canonicalize(source)?.is_file())?;
+
+    idmap::attach(fd, &dest.proc_path())
+}
+
+/// Splits the options of a mount into the flags of mount(2) and the data,
+/// which is passed on to the filesystem
+pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
+    let mut flags = MsFlags::empty();
+    let mut data = Vec::new();
+    if let Some(options) = &m.options {
+        for s in options {
+            if let Some((is_clear, flag)) = match s.as_str() {
+                "defaults" => Some((false, MsFlags::empty())),
+                "ro" => Some((false, MsFlags::MS_RDONLY)),
+                "rw" => Some((true, MsFlags::MS_RDONLY)),
+                "suid" => Some((true, MsFlags::MS_NOSUID)),
+                "nosuid" => Some((false, MsFlags::MS_NOSUID)),
+                "dev" => Some((true, MsFlags::MS_NODEV)),
+                "nodev" => Some((false, MsFlags::MS_NODEV)),
+                "exec" => Some((true, MsFlags::MS_NOEXEC)),
+                "noexec" => Some((false, MsFlags::MS_NOEXEC)),
+                "sync" => Some((false, MsFlags::MS_SYNCHRONOUS)),
+                "async" => Some((true, MsFlags::MS_SYNCHRONOUS)),
+                "dirsync" => Some((false, MsFlags::MS_DIRSYNC)),
+                "remount" => Some((false, MsFlags::MS_REMOUNT)),
+                "mand" => Some((false, MsFlags::MS_MANDLOCK)),
+                "nomand" => Some((true, MsFlags::MS_MANDLOCK)),
+                "atime" => Some((true, MsFlags::MS_NOATIME)),
+                "noatime" => Some((false, MsFlags::MS_NOATIME)),
+                "diratime" => Some((true, MsFlags::MS_NODIRATIME)),
+                "nodiratime" => Some((false, MsFlags::MS_NODIRATIME)),
+                "bind" => Some((false, MsFlags::MS_BIND)),
+                "rbind" => Some((false, MsFlags::MS_BIND | MsFlags::MS_REC)),
+                "unbindable" => Some((false, MsFlags::MS_UNBINDABLE)),
+                "runbindable" => Some((false, MsFlags::MS_UNBINDABLE | MsFlags::MS_REC)),
+                "private" => Some((true, MsFlags::MS_PRIVATE)),
+                "rprivate" => Some((true, MsFlags::MS_PRIVATE | MsFlags::MS_REC)),
+                "shared" => Some((true, MsFlags::MS_SHARED)),
+                "rshared" => Some((true, MsFlags::MS_SHARED | MsFlags::MS_REC)),
+                "slave" => Some((true, MsFlags::MS_SLAVE)),
+                "rslave" => Some((true, MsFlags::MS_SLAVE | MsFlags::MS_REC)),
+                "relatime" => Some((true, MsFlags::MS_RELATIME)),
+                "norelatime" => Some((true, MsFlags::MS_RELATIME)),
+                "strictatime" => Some((true, MsFlags::MS_STRICTATIME)),
+                "nostrictatime" => Some((true, MsFlags::MS_STRICTATIME)),
+                _ => None,
+            } {
+                if is_clear {
+                    flags &= !flag;
+                } else {
+                    flags |= flag;
+                }
+            } else {
+                data.push(s.as_str());
+            };
+        }
+    }
+    (flags, data.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_flags() {
+        assert_eq!(locked_flags(FsFlags::empty()), MsFlags::empty());
+        assert_eq!(
+            locked_flags(
+                FsFlags::ST_RDONLY | FsFlags::ST_NOSUID | FsFlags::ST_NODEV | FsFlags::ST_RELATIME
+            ),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
+        );
+    }
+
+    #[test]
+    fn test_shm_data() -> Result<()> {
+        assert_eq!(shm_data("")?, "size=64m");
+        assert_eq!(shm_data("mode=1777")?, "mode=1777,size=64m");
+        assert_eq!(shm_data("mode=1777,size=65536k")?, "mode=1777,size=65536k");
+        assert_eq!(shm_data("size=50%")?, "size=50%");
+        assert!(shm_data("size=64mb").is_err());
+        assert!(shm_data("size=m").is_err());
+        Ok(())
+    }
+}
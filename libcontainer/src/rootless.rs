@@ -0,0 +1,966 @@
+use crate::{namespaces::Namespaces, syscall::syscall::create_syscall, utils};
+use anyhow::{bail, Context, Result};
+pub use cgroups::common::in_user_namespace;
+use cgroups::common::CgroupManager;
+use nix::unistd::Pid;
+use oci_spec::{
+    Linux, LinuxDeviceCgroup, LinuxDeviceType, LinuxIdMapping, LinuxNamespace, LinuxNamespaceType,
+    LinuxResources, Mount, Spec,
+};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::{env, fs, path::PathBuf};
+
+const SUBUID_PATH: &str = "/etc/subuid";
+const SUBGID_PATH: &str = "/etc/subgid";
+const FILE_CAPS_XATTR: &[u8] = b"security.capability\0";
+
+#[derive(Debug, Clone)]
+pub struct Rootless<'a> {
+    /// Location of the newuidmap binary
+    pub newuidmap: Option<PathBuf>,
+    /// Location of the newgidmap binary
+    pub newgidmap: Option<PathBuf>,
+    /// Mappings for user ids
+    pub uid_mappings: Option<&'a Vec<LinuxIdMapping>>,
+    /// Mappings for group ids
+    pub gid_mappings: Option<&'a Vec<LinuxIdMapping>>,
+    /// Info on the user namespaces
+    user_namespace: Option<LinuxNamespace>,
+    /// Is rootless container requested by a privileged
+    /// user
+    pub privileged: bool,
+}
+
+impl<'a> Rootless<'a> {
+    pub fn new(spec: &'a Spec) -> Result<Option<Rootless<'a>>> {
+        let linux = spec.linux.as_ref().context("no linux in spec")?;
+        let namespaces = Namespaces::from(linux.namespaces.as_ref());
+        let user_namespace = namespaces.get(LinuxNamespaceType::User);
+
+        // If conditions requires us to use rootless, we must either create a new
+        // user namespace or enter an exsiting. When youki already runs as root
+        // of a user namespace, e.g. under podman unshare, the container can
+        // share the user namespace of youki instead.
+        if rootless_required() && user_namespace.is_none() {
+            if in_user_namespace() && nix::unistd::geteuid().is_root() {
+                log::debug!("container shares the user namespace of youki");
+                return Ok(None);
+            }
+            bail!("rootless container requires valid user namespace definition");
+        }
+
+        if user_namespace.is_some() && user_namespace.unwrap().path.is_none() {
+            log::debug!("rootless container should be created");
+            log::warn!(
+                "resource constraints and multi id mapping is unimplemented for rootless containers"
+            );
+
+            validate(spec).context("The spec failed to comply to rootless requirement")?;
+            let mut rootless = Rootless::from(linux);
+            // a privileged user writes all mappings itself
+            if !rootless.privileged {
+                if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
+                    rootless.newuidmap = Some(uid_binary);
+                    rootless.newgidmap = Some(gid_binary);
+                }
+            }
+
+            Ok(Some(rootless))
+        } else {
+            log::debug!("This is NOT a rootless container");
+            Ok(None)
+        }
+    }
+}
+
+impl<'a> From<&'a Linux> for Rootless<'a> {
+    fn from(linux: &'a Linux) -> Self {
+        let namespaces = Namespaces::from(linux.namespaces.as_ref());
+        let user_namespace = namespaces.get(LinuxNamespaceType::User);
+        Self {
+            newuidmap: None,
+            newgidmap: None,
+            uid_mappings: linux.uid_mappings.as_ref(),
+            gid_mappings: linux.gid_mappings.as_ref(),
+            user_namespace: user_namespace.cloned(),
+            privileged: nix::unistd::geteuid().is_root(),
+        }
+    }
+}
+
+/// Checks if rootless mode should be used. This is also the case for root of
+/// a user namespace other than the initial one, which lacks privileges over
+/// the host just like an unprivileged user.
+pub fn rootless_required() -> bool {
+    if !nix::unistd::geteuid().is_root() || in_user_namespace() {
+        return true;
+    }
+
+    if let Ok("true") = std::env::var("YOUKI_USE_ROOTLESS").as_deref() {
+        return true;
+    }
+
+    false
+}
+
+/// Places the container process of a rootless container in its cgroup and
+/// applies the resources. This is only possible if the cgroup has been
+/// delegated to the user, e.g. by systemd on a host with cgroup v2. On cgroup
+/// v1 only the hierarchies which have been made writable for the user are
+/// used. Resources which need a controller that is not delegated are skipped
+/// with a warning, unless strict is set, in which case the creation fails.
+pub fn apply_cgroup(
+    cmanager: &dyn CgroupManager,
+    resources: &LinuxResources,
+    pid: Pid,
+    strict: bool,
+) -> Result<()> {
+    let hierarchies = cmanager.hierarchies()?;
+    let cgroup_path = match hierarchies.as_slice() {
+        [hierarchy] if hierarchy.name.is_none() => &hierarchy.path,
+        // the v1 manager skips the hierarchies which are not writable
+        _ => {
+            cmanager
+                .add_task(pid)
+                .context("failed to add task to the writable cgroups")?;
+            return cmanager
+                .apply(resources)
+                .context("failed to apply resource limits through the writable cgroups");
+        }
+    };
+
+    let available = match cgroups::v2::util::delegated_controllers(cgroup_path)? {
+        Some(available) => available,
+        None => {
+            let reason = format!("cgroup {:?} is not delegated to the user", cgroup_path);
+            return skip_cgroup(&reason, strict);
+        }
+    };
+
+    let (resources, skipped) = cgroups::v2::util::restrict_resources(resources, &available);
+    if !skipped.is_empty() {
+        let skipped = skipped.join(", ");
+        if strict {
+            bail!(
+                "resources {} can not be applied, their controllers are not delegated to the user",
+                skipped
+            );
+        }
+        log::warn!(
+            "resources {} are not applied, their controllers are not delegated to the user",
+            skipped
+        );
+    }
+
+    cmanager
+        .add_task(pid)
+        .context("failed to add task to the delegated cgroup")?;
+    cmanager
+        .apply(&resources)
+        .context("failed to apply resource limits through the delegated cgroup")
+}
+
+fn skip_cgroup(reason: &str, strict: bool) -> Result<()> {
+    if strict {
+        bail!("resource limits can not be applied: {}", reason);
+    }
+
+    log::warn!("resource limits are not applied: {}", reason);
+    Ok(())
+}
+
+/// Validates that the spec contains the required information for
+/// running in rootless mode. All problems are reported at once, so that the
+/// spec does not have to be fixed one problem at a time.
+fn validate(spec: &Spec) -> Result<()> {
+    let problems = problems(spec)?;
+    if !problems.is_empty() {
+        bail!(
+            "the spec can not be used for a rootless container:\n  - {}",
+            problems.join("\n  - ")
+        );
+    }
+
+    Ok(())
+}
+
+// Collects the parts of the spec which can not be set up by the user
+fn problems(spec: &Spec) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let linux = spec.linux.as_ref().context("no linux in spec")?;
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+    if namespaces.get(LinuxNamespaceType::User).is_none() {
+        problems
+            .push("rootless containers require the specification of a user namespace".to_owned());
+    }
+
+    let uid_mappings = linux.uid_mappings.as_deref().unwrap_or_default();
+    let gid_mappings = linux.gid_mappings.as_deref().unwrap_or_default();
+    if uid_mappings.is_empty() {
+        problems.push("rootless containers require at least one uid mapping".to_owned());
+    }
+    if gid_mappings.is_empty() {
+        problems.push("rootless containers require at least one gid mapping".to_owned());
+    }
+
+    if let Some(devices) = linux.resources.as_ref().and_then(|r| r.devices.as_ref()) {
+        for device in devices.iter().filter(|d| is_unsupported_device(d)) {
+            problems.push(format!(
+                "device cgroup rule {:?} can not be applied by an unprivileged user",
+                device
+            ));
+        }
+    }
+
+    let mounts = spec.mounts.as_ref().context("no mounts in spec")?;
+    problems.extend(validate_mounts(mounts, uid_mappings, gid_mappings));
+    if !has_network_namespace(linux) {
+        for mount in mounts.iter().filter(|m| is_sysfs(m)) {
+            problems.push(format!(
+                "sysfs can not be mounted at {:?} without a new network namespace",
+                mount.destination
+            ));
+        }
+    }
+
+    if let Some(process) = &spec.process {
+        if let Some(additional_gids) = &process.user.additional_gids {
+            let privileged = nix::unistd::geteuid().is_root();
+
+            match (privileged, additional_gids.is_empty()) {
+                (true, false) => {
+                    for gid in additional_gids {
+                        if !is_id_mapped(*gid, gid_mappings) {
+                            problems.push(format!("gid {} is specified as supplementary group, but is not mapped in the user namespace", gid));
+                        }
+                    }
+                }
+                (false, false) => problems.push(format!(
+                    "user is {} (unprivileged). Supplementary groups cannot be set in \
+                    a rootless container for this user due to CVE-2014-8989",
+                    nix::unistd::geteuid()
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+fn validate_mounts(
+    mounts: &[Mount],
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for mount in mounts {
+        for opt in mount.options.iter().flatten() {
+            match is_option_mapped(opt, uid_mappings, gid_mappings) {
+                Ok(true) => {}
+                Ok(false) => problems.push(format!(
+                    "mount {:?} specifies option {} which is not mapped inside the rootless container",
+                    mount.destination, opt
+                )),
+                Err(err) => problems.push(format!(
+                    "mount {:?} specifies invalid option {}: {}",
+                    mount.destination, opt, err
+                )),
+            }
+        }
+    }
+
+    problems
+}
+
+/// Changes the parts of the spec which an unprivileged user can not set up,
+/// like `runc spec --rootless` does, and returns a description of each
+/// change:
+/// - a user namespace is added which maps the user to root, if the spec has
+///   no user namespace or mappings
+/// - device cgroup rules are removed, except for the rule denying all devices
+/// - uid= and gid= mount options are removed if the id is not mapped
+/// - sysfs is bind mounted from the host if there is no network namespace
+///
+/// Problems which can not be fixed, e.g. supplementary groups, are left for
+/// the validation to report.
+pub fn fix_up(spec: &mut Spec) -> Result<Vec<String>> {
+    let mut fixes = Vec::new();
+    let linux = spec.linux.as_mut().context("no linux in spec")?;
+    let namespaces = linux.namespaces.get_or_insert_with(Vec::new);
+    let joins_userns = namespaces
+        .iter()
+        .find(|ns| ns.typ == LinuxNamespaceType::User)
+        .map(|userns| userns.path.is_some());
+    match joins_userns {
+        // the mappings belong to the user namespace which is joined
+        Some(true) => return Ok(fixes),
+        Some(false) => {}
+        None => {
+            namespaces.push(LinuxNamespace {
+                typ: LinuxNamespaceType::User,
+                path: None,
+            });
+            fixes.push("added a user namespace".to_owned());
+        }
+    }
+
+    if linux.uid_mappings.as_ref().map_or(true, Vec::is_empty) {
+        let uid = nix::unistd::geteuid().as_raw();
+        linux.uid_mappings = Some(vec![LinuxIdMapping {
+            container_id: 0,
+            host_id: uid,
+            size: 1,
+        }]);
+        fixes.push(format!("mapped uid {} to root of the container", uid));
+    }
+    if linux.gid_mappings.as_ref().map_or(true, Vec::is_empty) {
+        let gid = nix::unistd::getegid().as_raw();
+        linux.gid_mappings = Some(vec![LinuxIdMapping {
+            container_id: 0,
+            host_id: gid,
+            size: 1,
+        }]);
+        fixes.push(format!("mapped gid {} to root of the container", gid));
+    }
+
+    if let Some(devices) = linux.resources.as_mut().and_then(|r| r.devices.as_mut()) {
+        let count = devices.len();
+        devices.retain(|d| !is_unsupported_device(d));
+        if devices.len() < count {
+            fixes.push(format!(
+                "removed {} device cgroup rules",
+                count - devices.len()
+            ));
+        }
+    }
+
+    let has_netns = has_network_namespace(linux);
+    let uid_mappings = linux.uid_mappings.as_deref().unwrap_or_default();
+    let gid_mappings = linux.gid_mappings.as_deref().unwrap_or_default();
+    for mount in spec.mounts.iter_mut().flatten() {
+        if let Some(options) = mount.options.as_mut() {
+            let count = options.len();
+            // invalid options are kept for the validation to report
+            options.retain(|o| is_option_mapped(o, uid_mappings, gid_mappings).unwrap_or(true));
+            if options.len() < count {
+                fixes.push(format!(
+                    "removed unmapped uid and gid options of mount {:?}",
+                    mount.destination
+                ));
+            }
+        }
+
+        if !has_netns && is_sysfs(mount) {
+            mount.typ = Some("none".to_owned());
+            mount.source = Some(PathBuf::from("/sys"));
+            mount.options = Some(
+                vec!["rbind", "nosuid", "noexec", "nodev", "ro"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            );
+            fixes.push(format!(
+                "bind mounted sysfs of the host at {:?}",
+                mount.destination
+            ));
+        }
+    }
+
+    Ok(fixes)
+}
+
+// The devices of a rootless container are bind mounted from the host and can
+// only be restricted by the permissions of the user. The rule denying all
+// devices is part of every default spec and is accepted for that reason.
+fn is_unsupported_device(device: &LinuxDeviceCgroup) -> bool {
+    let deny_all = !device.allow
+        && device.typ.clone().unwrap_or_default() == LinuxDeviceType::A
+        && device.major.is_none()
+        && device.minor.is_none();
+    !deny_all
+}
+
+// Mounting sysfs requires privileges over the network namespace
+fn has_network_namespace(linux: &Linux) -> bool {
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+    matches!(
+        namespaces.get(LinuxNamespaceType::Network),
+        Some(netns) if netns.path.is_none()
+    )
+}
+
+fn is_sysfs(mount: &Mount) -> bool {
+    mount.typ.as_deref() == Some("sysfs")
+}
+
+// Returns if the id of a uid= or gid= option is mapped, other options are
+// always considered to be mapped
+fn is_option_mapped(
+    option: &str,
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Result<bool> {
+    if let Some(uid) = option.strip_prefix("uid=") {
+        return Ok(is_id_mapped(uid.parse()?, uid_mappings));
+    }
+    if let Some(gid) = option.strip_prefix("gid=") {
+        return Ok(is_id_mapped(gid.parse()?, gid_mappings));
+    }
+
+    Ok(true)
+}
+
+fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
+    mappings
+        .iter()
+        .any(|m| id >= m.container_id && id - m.container_id < m.size)
+}
+
+/// Range of subordinate ids which is delegated to a user, see subuid(5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubIdRange {
+    /// First id of the range on the host
+    pub start: u32,
+    /// Number of ids in the range
+    pub count: u32,
+}
+
+/// Maps the user to root of the container and the subordinate ids of the user
+/// from /etc/subuid and /etc/subgid to the following ids of the container, if
+/// the spec has a new user namespace but no mappings or only a single identity
+/// mapping. Bundles which are created for a privileged runtime can then be run
+/// by an unprivileged user without changes. Returns if the mappings have been
+/// changed, which requires the user to have subordinate ids and the
+/// newuidmap/newgidmap binaries to be installed.
+pub fn apply_subid_mappings(spec: &mut Spec) -> Result<bool> {
+    let linux = spec.linux.as_mut().context("no linux in spec")?;
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+    match namespaces.get(LinuxNamespaceType::User) {
+        Some(userns) if userns.path.is_none() => {}
+        _ => return Ok(false),
+    }
+
+    // root of a user namespace can only map the ids of its own namespace
+    if nix::unistd::geteuid().is_root() {
+        return Ok(false);
+    }
+
+    if !needs_mappings(linux.uid_mappings.as_deref())
+        || !needs_mappings(linux.gid_mappings.as_deref())
+    {
+        return Ok(false);
+    }
+
+    let uid = nix::unistd::geteuid().as_raw();
+    let gid = nix::unistd::getegid().as_raw();
+    let user_name = create_syscall()
+        .get_pwuid(uid)
+        .map(|name| name.to_string_lossy().into_owned());
+    let subuids = read_subid_ranges(SUBUID_PATH, user_name.as_deref(), uid)?;
+    let subgids = read_subid_ranges(SUBGID_PATH, user_name.as_deref(), uid)?;
+    if subuids.is_empty() || subgids.is_empty() {
+        log::debug!(
+            "user {} has no subordinate ids, the mappings of the spec are used",
+            uid
+        );
+        return Ok(false);
+    }
+
+    if lookup_map_binary("newuidmap")?.is_none() || lookup_map_binary("newgidmap")?.is_none() {
+        log::warn!(
+            "subordinate ids of user {} are not mapped, newuidmap/newgidmap binaries could not be found in path",
+            uid
+        );
+        return Ok(false);
+    }
+
+    linux.uid_mappings = Some(subid_mappings(uid, &subuids));
+    linux.gid_mappings = Some(subid_mappings(gid, &subgids));
+    log::debug!(
+        "mapped subordinate ids: uid mappings {:?}, gid mappings {:?}",
+        linux.uid_mappings,
+        linux.gid_mappings
+    );
+    Ok(true)
+}
+
+// Mappings which are missing or only map an id to itself are considered to
+// be a placeholder of a spec which was not written for a rootless container
+fn needs_mappings(mappings: Option<&[LinuxIdMapping]>) -> bool {
+    match mappings {
+        None | Some([]) => true,
+        Some([mapping]) => mapping.container_id == mapping.host_id,
+        Some(_) => false,
+    }
+}
+
+fn read_subid_ranges(path: &str, user_name: Option<&str>, uid: u32) -> Result<Vec<SubIdRange>> {
+    match fs::read_to_string(path) {
+        Ok(content) => parse_subid_ranges(&content, user_name, uid)
+            .with_context(|| format!("failed to parse {}", path)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path)),
+    }
+}
+
+/// Parses the ranges of a user from the content of /etc/subuid or
+/// /etc/subgid. Entries are of the form user:start:count, where the user is
+/// given either by name or by uid.
+pub fn parse_subid_ranges(
+    content: &str,
+    user_name: Option<&str>,
+    uid: u32,
+) -> Result<Vec<SubIdRange>> {
+    let uid = uid.to_string();
+    let mut ranges = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 3 {
+            bail!("invalid entry {:?}", line);
+        }
+        if fields[0] != uid && Some(fields[0]) != user_name {
+            continue;
+        }
+
+        let start = fields[1]
+            .parse()
+            .with_context(|| format!("invalid start of entry {:?}", line))?;
+        let count = fields[2]
+            .parse()
+            .with_context(|| format!("invalid count of entry {:?}", line))?;
+        if count > 0 {
+            ranges.push(SubIdRange { start, count });
+        }
+    }
+
+    Ok(ranges)
+}
+
+// The id of the user becomes root, the subordinate ids follow in the order
+// of the file
+fn subid_mappings(id: u32, ranges: &[SubIdRange]) -> Vec<LinuxIdMapping> {
+    let mut mappings = vec![LinuxIdMapping {
+        container_id: 0,
+        host_id: id,
+        size: 1,
+    }];
+    let mut next = 1u32;
+    for range in ranges {
+        mappings.push(LinuxIdMapping {
+            container_id: next,
+            host_id: range.start,
+            size: range.count,
+        });
+        next = next.saturating_add(range.count);
+    }
+
+    mappings
+}
+
+/// Replaces multiple id mappings by a single mapping of the user, if the
+/// newuidmap and newgidmap binaries which are required to write them are not
+/// installed or can not be run with privileges. The kernel allows an
+/// unprivileged user to map its own ids once setgroups has been denied, so the
+/// container still runs, but only with the ids of the user. Returns if the
+/// mappings have been replaced.
+pub fn fall_back_to_single_mapping(spec: &mut Spec) -> Result<bool> {
+    let linux = spec.linux.as_mut().context("no linux in spec")?;
+    let multiple =
+        |mappings: &Option<Vec<LinuxIdMapping>>| mappings.as_ref().map_or(false, |m| m.len() > 1);
+    if nix::unistd::geteuid().is_root()
+        || !(multiple(&linux.uid_mappings) || multiple(&linux.gid_mappings))
+    {
+        return Ok(false);
+    }
+
+    let namespaces = Namespaces::from(linux.namespaces.as_ref());
+    match namespaces.get(LinuxNamespaceType::User) {
+        Some(userns) if userns.path.is_none() => {}
+        _ => return Ok(false),
+    }
+
+    if lookup_map_binary("newuidmap")?.is_some() && lookup_map_binary("newgidmap")?.is_some() {
+        return Ok(false);
+    }
+
+    let uid = nix::unistd::geteuid().as_raw();
+    let gid = nix::unistd::getegid().as_raw();
+    linux.uid_mappings = Some(vec![single_mapping(
+        uid,
+        linux.uid_mappings.as_deref().unwrap_or_default(),
+    )]);
+    linux.gid_mappings = Some(vec![single_mapping(
+        gid,
+        linux.gid_mappings.as_deref().unwrap_or_default(),
+    )]);
+    log::warn!(
+        "newuidmap/newgidmap are not installed or neither setuid nor have file capabilities, \
+        only uid {} and gid {} are mapped into the container. Files owned by other ids are \
+        inaccessible and supplementary groups can not be used",
+        uid,
+        gid
+    );
+    Ok(true)
+}
+
+// Keeps the container id the user was mapped to, which is root unless the
+// mappings say otherwise
+fn single_mapping(id: u32, mappings: &[LinuxIdMapping]) -> LinuxIdMapping {
+    let container_id = mappings
+        .iter()
+        .find(|m| id >= m.host_id && id - m.host_id < m.size)
+        .map_or(0, |m| m.container_id + (id - m.host_id));
+    LinuxIdMapping {
+        container_id,
+        host_id: id,
+        size: 1,
+    }
+}
+
+/// Looks up the location of the newuidmap and newgidmap binaries which
+/// are required to write multiple user/group mappings
+pub fn lookup_map_binaries(spec: &Linux) -> Result<Option<(PathBuf, PathBuf)>> {
+    let single =
+        |mappings: &Option<Vec<LinuxIdMapping>>| mappings.as_ref().map_or(true, |m| m.len() <= 1);
+    if single(&spec.uid_mappings) && single(&spec.gid_mappings) {
+        return Ok(None);
+    }
+
+    let uidmap = lookup_map_binary("newuidmap")?;
+    let gidmap = lookup_map_binary("newgidmap")?;
+
+    match (uidmap, gidmap) {
+        (Some(newuidmap), Some(newgidmap)) => Ok(Some((newuidmap, newgidmap))),
+        _ => bail!("newuidmap/newgidmap binaries could not be found in path or are neither setuid nor have file capabilities. This is required if multiple id mappings are specified"),
+    }
+}
+
+// Returns the location of a binary in the path, if it can write the mappings
+// of the user
+fn lookup_map_binary(binary: &str) -> Result<Option<PathBuf>> {
+    let paths = env::var("PATH")?;
+    let path = match paths
+        .split_terminator(':')
+        .map(|p| PathBuf::from(p).join(binary))
+        .find(|p| p.exists())
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    if nix::unistd::geteuid().is_root() || is_privileged_binary(&path)? {
+        Ok(Some(path))
+    } else {
+        log::debug!("{:?} is neither setuid nor has file capabilities", path);
+        Ok(None)
+    }
+}
+
+// Distributions install newuidmap either setuid root or with the setuid
+// capability as a file capability
+fn is_privileged_binary(path: &Path) -> Result<bool> {
+    let metadata = fs::metadata(path).with_context(|| format!("failed to stat {:?}", path))?;
+    if metadata.permissions().mode() & libc::S_ISUID != 0 {
+        return Ok(true);
+    }
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            FILE_CAPS_XATTR.as_ptr() as *const libc::c_char,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    Ok(size > 0)
+}
+
+pub fn write_uid_mapping(target_pid: Pid, rootless: Option<&Rootless>) -> Result<()> {
+    log::debug!("Write UID mapping for {:?}", target_pid);
+    if let Some(rootless) = rootless {
+        if let Some(uid_mappings) = rootless.uid_mappings {
+            return write_id_mapping(
+                target_pid,
+                "uid_map",
+                uid_mappings,
+                rootless.newuidmap.as_deref(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_gid_mapping(target_pid: Pid, rootless: Option<&Rootless>) -> Result<()> {
+    log::debug!("Write GID mapping for {:?}", target_pid);
+    if let Some(rootless) = rootless {
+        if let Some(gid_mappings) = rootless.gid_mappings {
+            return write_id_mapping(
+                target_pid,
+                "gid_map",
+                gid_mappings,
+                rootless.newgidmap.as_deref(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_id_mapping(
+    target_pid: Pid,
+    map_file: &str,
+    mappings: &[oci_spec::LinuxIdMapping],
+    map_binary: Option<&Path>,
+) -> Result<()> {
+    log::debug!("Write ID mapping: {:?}", mappings);
+    // a privileged user and the kernel can write the mappings directly, an
+    // unprivileged user can only map its own ids that way
+    let map_binary = match map_binary {
+        Some(map_binary) if mappings.len() > 1 => map_binary,
+        _ => {
+            let content: Vec<String> = mappings
+                .iter()
+                .map(|m| format!("{} {} {}", m.container_id, m.host_id, m.size))
+                .collect();
+            utils::write_file(
+                format!("/proc/{}/{}", target_pid, map_file),
+                content.join("\n"),
+            )?;
+            return Ok(());
+        }
+    };
+
+    // e.g. newuidmap <pid> 0 1000 1 1 100000 65536
+    let args = mappings
+        .iter()
+        .flat_map(|m| vec![m.container_id, m.host_id, m.size])
+        .map(|id| id.to_string());
+    let output = Command::new(map_binary)
+        .arg(target_pid.to_string())
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to execute {:?}", map_binary))?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed to write {} of pid {}: {}",
+            map_binary,
+            map_file,
+            target_pid,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rootless_spec() -> Spec {
+        let mut spec = Spec::default();
+        let linux = spec.linux.as_mut().unwrap();
+        linux.namespaces = Some(vec![
+            LinuxNamespace {
+                typ: LinuxNamespaceType::Mount,
+                path: None,
+            },
+            LinuxNamespace {
+                typ: LinuxNamespaceType::Pid,
+                path: None,
+            },
+        ]);
+        linux.uid_mappings = None;
+        linux.gid_mappings = None;
+        linux.resources = Some(LinuxResources {
+            devices: Some(vec![
+                LinuxDeviceCgroup {
+                    allow: false,
+                    typ: None,
+                    major: None,
+                    minor: None,
+                    access: "rwm".to_string().into(),
+                },
+                LinuxDeviceCgroup {
+                    allow: true,
+                    typ: Some(LinuxDeviceType::C),
+                    major: Some(1),
+                    minor: Some(3),
+                    access: "rwm".to_string().into(),
+                },
+            ]),
+            ..Default::default()
+        });
+        spec.mounts = Some(vec![
+            Mount {
+                destination: PathBuf::from("/dev/pts"),
+                typ: Some("devpts".to_string()),
+                source: Some(PathBuf::from("devpts")),
+                options: Some(vec!["nosuid".to_string(), "gid=5".to_string()]),
+            },
+            Mount {
+                destination: PathBuf::from("/sys"),
+                typ: Some("sysfs".to_string()),
+                source: Some(PathBuf::from("sysfs")),
+                options: Some(vec!["ro".to_string()]),
+            },
+        ]);
+        spec.process.as_mut().unwrap().user.additional_gids = None;
+        spec
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() -> Result<()> {
+        let problems = problems(&rootless_spec())?;
+        // user namespace, uid and gid mappings, device rule, gid option, sysfs
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert!(validate(&rootless_spec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_up() -> Result<()> {
+        let mut spec = rootless_spec();
+        let fixes = fix_up(&mut spec)?;
+        assert_eq!(fixes.len(), 6, "{:?}", fixes);
+        assert!(problems(&spec)?.is_empty());
+
+        let linux = spec.linux.as_ref().unwrap();
+        let devices = linux.resources.as_ref().unwrap().devices.as_ref().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(!devices[0].allow);
+        let mounts = spec.mounts.as_ref().unwrap();
+        assert_eq!(mounts[0].options, Some(vec!["nosuid".to_string()]));
+        assert_eq!(mounts[1].source, Some(PathBuf::from("/sys")));
+        assert_eq!(mounts[1].typ, Some("none".to_string()));
+
+        // nothing is left to change
+        assert!(fix_up(&mut spec)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_subid_ranges() -> Result<()> {
+        let content = "\
+# comment
+alice:100000:65536
+bob:165536:65536
+1000:231072:1000
+alice:300000:0
+";
+        let ranges = parse_subid_ranges(content, Some("alice"), 1000)?;
+        assert_eq!(
+            ranges,
+            vec![
+                SubIdRange {
+                    start: 100000,
+                    count: 65536
+                },
+                SubIdRange {
+                    start: 231072,
+                    count: 1000
+                },
+            ]
+        );
+        assert!(parse_subid_ranges(content, Some("carol"), 1001)?.is_empty());
+        assert!(parse_subid_ranges("alice:100000", Some("alice"), 1000).is_err());
+        assert!(parse_subid_ranges("alice:x:65536", Some("alice"), 1000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_subid_mappings() {
+        let ranges = vec![
+            SubIdRange {
+                start: 100000,
+                count: 65536,
+            },
+            SubIdRange {
+                start: 231072,
+                count: 1000,
+            },
+        ];
+        let mappings = subid_mappings(1000, &ranges);
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(
+            (
+                mappings[0].container_id,
+                mappings[0].host_id,
+                mappings[0].size
+            ),
+            (0, 1000, 1)
+        );
+        assert_eq!(
+            (
+                mappings[1].container_id,
+                mappings[1].host_id,
+                mappings[1].size
+            ),
+            (1, 100000, 65536)
+        );
+        assert_eq!(
+            (
+                mappings[2].container_id,
+                mappings[2].host_id,
+                mappings[2].size
+            ),
+            (65537, 231072, 1000)
+        );
+    }
+
+    #[test]
+    fn test_needs_mappings() {
+        let mapping = |container_id, host_id| LinuxIdMapping {
+            container_id,
+            host_id,
+            size: 1,
+        };
+        assert!(needs_mappings(None));
+        assert!(needs_mappings(Some(&[])));
+        assert!(needs_mappings(Some(&[mapping(0, 0)])));
+        assert!(!needs_mappings(Some(&[mapping(0, 1000)])));
+        assert!(!needs_mappings(Some(&[mapping(0, 0), mapping(1, 1000)])));
+    }
+
+    #[test]
+    fn test_single_mapping() {
+        let mappings = vec![
+            LinuxIdMapping {
+                container_id: 0,
+                host_id: 100000,
+                size: 1000,
+            },
+            LinuxIdMapping {
+                container_id: 1000,
+                host_id: 1000,
+                size: 1,
+            },
+        ];
+        assert_eq!(single_mapping(1000, &mappings).container_id, 1000);
+        assert_eq!(single_mapping(100010, &mappings).container_id, 10);
+        assert_eq!(single_mapping(2000, &mappings).container_id, 0);
+        assert_eq!(single_mapping(2000, &[]).host_id, 2000);
+    }
+
+    #[test]
+    fn test_is_id_mapped() {
+        let mappings = vec![LinuxIdMapping {
+            container_id: 0,
+            host_id: 1000,
+            size: 1,
+        }];
+        assert!(is_id_mapped(0, &mappings));
+        assert!(!is_id_mapped(1, &mappings));
+    }
+}
@@ -0,0 +1,315 @@
+//! Resolution of paths in the rootfs of a container. The rootfs comes from an
+//! image which is not trusted, so a symlink in it must not lead a mount or a
+//! created directory out of the rootfs. Symlinks are resolved as if the rootfs
+//! was the root of the filesystem, like chroot would do, which keeps absolute
+//! links and any amount of ".." within the rootfs.
+//!
+//! Paths are opened with openat2 and RESOLVE_IN_ROOT, which lets the kernel
+//! confine the resolution to the rootfs. RESOLVE_BENEATH would refuse the
+//! absolute symlinks which are common in images instead of resolving them.
+//! The resulting file descriptor is used through its magic link in /proc, so
+//! a path can not be swapped for a symlink between its resolution and its use.
+//! Kernels before 5.6 fall back to resolving the path component by component.
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::{self, Mode};
+use nix::unistd;
+
+// Same limit as the kernel applies to the resolution of a path
+const MAX_SYMLINKS: usize = 40;
+
+const SYS_OPENAT2: libc::c_long = 437;
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+const RESOLVE_IN_ROOT: u64 = 0x10;
+
+// struct open_how of openat2(2)
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// File descriptor of a path in the rootfs, opened with O_PATH
+#[derive(Debug)]
+pub struct RootfsPath {
+    fd: RawFd,
+}
+
+impl RootfsPath {
+    /// Magic link to the file descriptor, which can be passed to syscalls
+    /// that only take paths like mount
+    pub fn proc_path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.fd))
+    }
+}
+
+impl AsRawFd for RootfsPath {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for RootfsPath {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+/// Opens a path of the container in the rootfs. Symlinks are resolved within
+/// the rootfs and magic links of procfs are refused.
+pub fn open_in_root<P: AsRef<Path>>(rootfs: &Path, unsafe_path: P) -> Result<RootfsPath> {
+    let unsafe_path = unsafe_path.as_ref();
+    let fd = match openat2_in_root(rootfs, unsafe_path) {
+        // openat2 is not available or filtered by seccomp
+        Err(Errno::ENOSYS) | Err(Errno::EPERM) => open_fallback(rootfs, unsafe_path)?,
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to open {:?} in rootfs", unsafe_path))
+        }
+        Ok(fd) => fd,
+    };
+
+    Ok(RootfsPath { fd })
+}
+
+fn openat2_in_root(rootfs: &Path, unsafe_path: &Path) -> nix::Result<RawFd> {
+    let root = fcntl::open(
+        rootfs,
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let relative = match unsafe_path.strip_prefix("/") {
+        Ok(relative) if relative.as_os_str().is_empty() => Path::new("."),
+        Ok(relative) => relative,
+        Err(_) => unsafe_path,
+    };
+
+    let result = CString::new(relative.as_os_str().as_bytes())
+        .map_err(|_| Errno::EINVAL)
+        .and_then(|path| {
+            let how = OpenHow {
+                flags: (OFlag::O_PATH | OFlag::O_CLOEXEC).bits() as u64,
+                mode: 0,
+                resolve: RESOLVE_IN_ROOT | RESOLVE_NO_MAGICLINKS,
+            };
+            let res = unsafe {
+                libc::syscall(
+                    SYS_OPENAT2,
+                    root,
+                    path.as_ptr(),
+                    &how as *const OpenHow,
+                    std::mem::size_of::<OpenHow>(),
+                )
+            };
+            Errno::result(res).map(|fd| fd as RawFd)
+        });
+    let _ = unistd::close(root);
+    result
+}
+
+// Resolves the path in userspace and checks that the opened file is still in
+// the rootfs, in case the path has been changed in the meantime
+fn open_fallback(rootfs: &Path, unsafe_path: &Path) -> Result<RawFd> {
+    let path = secure_join(rootfs, unsafe_path)?;
+    let fd = fcntl::open(
+        &path,
+        OFlag::O_PATH | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("failed to open {:?} in rootfs", unsafe_path))?;
+
+    let opened = fs::read_link(format!("/proc/self/fd/{}", fd));
+    match opened {
+        Ok(opened) if opened.starts_with(rootfs) => Ok(fd),
+        _ => {
+            let _ = unistd::close(fd);
+            bail!("{:?} has been moved out of the rootfs", unsafe_path)
+        }
+    }
+}
+
+/// Creates a directory and its missing parents in the rootfs. Every
+/// directory is created relative to the file descriptor of its parent.
+pub fn mkdir_all_in_root<P: AsRef<Path>>(rootfs: &Path, unsafe_path: P) -> Result<RootfsPath> {
+    // symlinks are resolved up front, so the targets of dangling links are
+    // created instead of failing on the link
+    let resolved = secure_join(rootfs, unsafe_path)?;
+    let resolved = resolved.strip_prefix(rootfs)?;
+    let mut current = PathBuf::from("/");
+    let mut dir = open_in_root(rootfs, &current)?;
+    for component in components(resolved).into_iter().rev() {
+        current.push(&component);
+        dir = match open_in_root(rootfs, &current) {
+            Ok(dir) => dir,
+            Err(err) if err.downcast_ref::<Errno>() == Some(&Errno::ENOENT) => {
+                match stat::mkdirat(
+                    dir.as_raw_fd(),
+                    component.as_os_str(),
+                    Mode::from_bits_truncate(0o755),
+                ) {
+                    Ok(_) | Err(Errno::EEXIST) => {}
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to create {:?} in rootfs", current))
+                    }
+                }
+                open_in_root(rootfs, &current)?
+            }
+            Err(err) => return Err(err),
+        };
+    }
+
+    Ok(dir)
+}
+
+/// Creates an empty file and its missing parent directories in the rootfs,
+/// if the file does not exist yet
+pub fn create_file_in_root<P: AsRef<Path>>(rootfs: &Path, unsafe_path: P) -> Result<RootfsPath> {
+    let unsafe_path = unsafe_path.as_ref();
+    let (parent, name) = split_parent(unsafe_path)?;
+    let parent = mkdir_all_in_root(rootfs, parent)?;
+    match fcntl::openat(
+        parent.as_raw_fd(),
+        name,
+        OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::from_bits_truncate(0o644),
+    ) {
+        Ok(fd) => {
+            let _ = unistd::close(fd);
+        }
+        // the file exists and may be a symlink, which is resolved below
+        Err(Errno::ELOOP) | Err(Errno::EISDIR) => {}
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to create {:?} in rootfs", unsafe_path))
+        }
+    }
+
+    open_in_root(rootfs, unsafe_path)
+}
+
+/// Splits a path into its parent and its last component, which must be a
+/// normal file name
+pub fn split_parent(path: &Path) -> Result<(&Path, &OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => bail!("{:?} does not have a file name", path),
+    }
+}
+
+/// Joins a path of the container to the rootfs with all symlinks resolved
+/// within the rootfs. Components which do not exist are taken as they are, so
+/// the result can be used to create them.
+pub fn secure_join<P: AsRef<Path>>(rootfs: &Path, unsafe_path: P) -> Result<PathBuf> {
+    let unsafe_path = unsafe_path.as_ref();
+    // components which are still to be resolved, in reverse order
+    let mut pending = components(unsafe_path);
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+
+    while let Some(component) = pending.pop() {
+        if component == ".." {
+            // the parent of the root is the root itself
+            resolved.pop();
+            continue;
+        }
+
+        let candidate = resolved.join(&component);
+        let full_path = rootfs.join(&candidate);
+        match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    bail!("too many levels of symbolic links in {:?}", unsafe_path);
+                }
+
+                let target = fs::read_link(&full_path)
+                    .with_context(|| format!("failed to read link {:?}", full_path))?;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                pending.extend(components(&target));
+            }
+            _ => resolved = candidate,
+        }
+    }
+
+    Ok(rootfs.join(resolved))
+}
+
+// Returns the normal and parent components of a path in reverse order
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsStr::new("..").to_os_string()),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_secure_join() -> Result<()> {
+        let rootfs = create_temp_dir("test_secure_join")?;
+        fs::create_dir_all(rootfs.join("etc/app"))?;
+        symlink("/etc", rootfs.join("absolute"))?;
+        symlink("../../../../etc", rootfs.join("etc/app/escape"))?;
+        symlink("loop", rootfs.join("loop"))?;
+
+        assert_eq!(
+            secure_join(&rootfs, "/etc/app/config")?,
+            rootfs.join("etc/app/config")
+        );
+        assert_eq!(
+            secure_join(&rootfs, "/absolute/passwd")?,
+            rootfs.join("etc/passwd")
+        );
+        assert_eq!(
+            secure_join(&rootfs, "/etc/app/escape/passwd")?,
+            rootfs.join("etc/passwd")
+        );
+        assert_eq!(secure_join(&rootfs, "/../../tmp")?, rootfs.join("tmp"));
+        assert!(secure_join(&rootfs, "/loop/file").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mkdir_all_in_root() -> Result<()> {
+        let rootfs = create_temp_dir("test_mkdir_all_in_root")?;
+        let outside = create_temp_dir("test_mkdir_all_in_root_outside")?;
+        symlink(&*outside, rootfs.join("escape"))?;
+
+        let dir = mkdir_all_in_root(&rootfs, "/escape/app/data")?;
+        assert!(!outside.join("app").exists());
+        assert_eq!(
+            fs::read_link(dir.proc_path())?,
+            fs::canonicalize(&*rootfs)?
+                .join(outside.strip_prefix("/")?)
+                .join("app/data")
+        );
+
+        let file = create_file_in_root(&rootfs, "/escape/app/config")?;
+        assert!(!outside.join("app/config").exists());
+        assert_eq!(
+            fs::read_link(file.proc_path())?,
+            fs::canonicalize(&*rootfs)?
+                .join(outside.strip_prefix("/")?)
+                .join("app/config")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Compatibility with the different versions of the runtime spec. Bundles
+//! from 1.0.0 up to the latest 1.1.x are accepted, including the release
+//! candidates of 1.0.0 which older versions of docker still produce. The
+//! config is translated into the current layout before it is deserialized, so
+//! the rest of youki only deals with a single version. Fields which have been
+//! added by newer versions keep their defaults when they are missing.
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use oci_spec::Spec;
+use serde_json::{json, Value};
+
+use crate::validation;
+
+// Newest minor version of the spec youki knows about
+const LATEST_MINOR: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Pre-release suffix, e.g. "rc5" or "dev"
+    pub pre: Option<String>,
+}
+
+impl OciVersion {
+    /// Layout of the release candidates before 1.0.0, which differs in a few
+    /// places from the final release
+    fn is_pre_release_1_0(&self) -> bool {
+        (self.major, self.minor, self.patch) == (1, 0, 0)
+            && self
+                .pre
+                .as_deref()
+                .map_or(false, |pre| pre.starts_with("rc"))
+    }
+}
+
+impl FromStr for OciVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(version: &str) -> Result<Self> {
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre.to_owned())),
+            None => (version, None),
+        };
+
+        let parts = release
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid oci version {:?}", version))?;
+        match parts.as_slice() {
+            [major, minor, patch] => Ok(Self {
+                major: *major,
+                minor: *minor,
+                patch: *patch,
+                pre,
+            }),
+            _ => bail!("invalid oci version {:?}", version),
+        }
+    }
+}
+
+impl Display for OciVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = self.pre.as_deref() {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks if a config of the version can be run. Configs of a newer minor
+/// version are rejected, because the fields it adds would silently be
+/// ignored.
+pub fn check_version(version: &str) -> Result<OciVersion> {
+    let parsed: OciVersion = version.parse()?;
+    if parsed.major != 1 || parsed.minor > LATEST_MINOR {
+        bail!(
+            "runtime spec has unsupported version '{}', only 1.0.0 through 1.{}.x are supported",
+            version,
+            LATEST_MINOR
+        );
+    }
+
+    Ok(parsed)
+}
+
+/// Loads the config.json of a bundle. The config is checked for its version,
+/// translated into the current layout and validated before it is deserialized.
+/// The translated json is returned along with the spec, because it holds the
+/// fields which the oci-spec crate does not know about.
+pub fn load_bundle_spec<P: AsRef<Path>>(path: P) -> Result<(Spec, Value)> {
+    let path = path.as_ref();
+    let config = load_bundle_config(path)?;
+    let spec = spec_from_config(&config, path)?;
+    Ok((spec, config))
+}
+
+/// Loads the config.json of a bundle, which is checked for its version and
+/// translated into the current layout, but not validated yet
+pub fn load_bundle_config<P: AsRef<Path>>(path: P) -> Result<Value> {
+    let path = path.as_ref();
+    let content = fs::read(path).with_context(|| format!("failed to read spec {:?}", path))?;
+    let mut config: Value = serde_json::from_slice(&content)
+        .with_context(|| format!("spec {:?} is not valid json", path))?;
+
+    // a missing version is reported by the validation
+    if let Some(version) = config.get("ociVersion").and_then(Value::as_str) {
+        let version = check_version(version)?;
+        translate(&mut config, &version);
+    }
+    if let Some(resources) = config.pointer_mut("/linux/resources") {
+        translate_resources(resources);
+    }
+
+    Ok(config)
+}
+
+/// Validates the config loaded from the path and deserializes it
+pub fn spec_from_config<P: AsRef<Path>>(config: &Value, path: P) -> Result<Spec> {
+    let path = path.as_ref();
+    validation::validate_spec(config).with_context(|| format!("invalid spec {:?}", path))?;
+    let spec = serde_json::from_value(config.clone())
+        .with_context(|| format!("failed to deserialize spec {:?}", path))?;
+    Ok(spec)
+}
+
+/// Translates the config of an older version into the current layout
+pub fn translate(config: &mut Value, version: &OciVersion) {
+    if !version.is_pre_release_1_0() {
+        return;
+    }
+
+    log::debug!("translate config of version {}", version);
+    // the capabilities were a single list, which applied to all sets
+    if let Some(Value::Array(caps)) = config.pointer("/process/capabilities").cloned() {
+        config["process"]["capabilities"] = json!({
+            "bounding": caps,
+            "effective": caps,
+            "inheritable": caps,
+            "permitted": caps,
+        });
+    }
+
+    // seccomp rules named a single syscall
+    if let Some(Value::Array(syscalls)) = config.pointer_mut("/linux/seccomp/syscalls") {
+        for syscall in syscalls.iter_mut().filter_map(Value::as_object_mut) {
+            if let Some(name) = syscall.remove("name") {
+                syscall.insert("names".to_owned(), json!([name]));
+            }
+        }
+    }
+}
+
+/// Moves resources of newer versions, which oci-spec does not know about, into
+/// the unified map of cgroup v2. Values which are already set in the unified
+/// map take precedence. The limits of the misc controller can be set as an
+/// object of resource names and limits, e.g. `"misc": {"sgx_epc": 65536}`.
+pub fn translate_resources(resources: &mut Value) {
+    let cpu_fields = [("burst", "cpu.max.burst"), ("idle", "cpu.idle")];
+    let mut unified = Vec::new();
+    if let Some(Value::Object(cpu)) = resources.get_mut("cpu") {
+        for (field, file) in cpu_fields.iter() {
+            if let Some(value) = cpu.remove(*field).filter(|v| !v.is_null()) {
+                let value = match value {
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                unified.push((file.to_string(), Value::String(value)));
+            }
+        }
+    }
+
+    if let Some(Value::Object(misc)) = resources.as_object_mut().and_then(|r| r.remove("misc")) {
+        let limits: Vec<String> = misc
+            .into_iter()
+            .filter(|(_, limit)| !limit.is_null())
+            .map(|(resource, limit)| match limit {
+                Value::String(limit) => format!("{} {}", resource, limit),
+                limit => format!("{} {}", resource, limit),
+            })
+            .collect();
+        if !limits.is_empty() {
+            unified.push(("misc.max".to_owned(), Value::String(limits.join("\n"))));
+        }
+    }
+
+    if unified.is_empty() {
+        return;
+    }
+
+    if !resources.get("unified").map_or(false, Value::is_object) {
+        resources["unified"] = json!({});
+    }
+    if let Some(Value::Object(map)) = resources.get_mut("unified") {
+        for (file, value) in unified {
+            map.entry(file).or_insert(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_version() -> Result<()> {
+        for version in &["1.0.0", "1.0.2-dev", "1.1.0", "1.0.0-rc5"] {
+            check_version(version)?;
+        }
+        assert!(check_version("1.2.0").is_err());
+        assert!(check_version("2.0.0").is_err());
+        assert!(check_version("0.6.0").is_err());
+        assert!(check_version("1.0").is_err());
+
+        let version: OciVersion = "1.0.0-rc5".parse()?;
+        assert_eq!(version.pre.as_deref(), Some("rc5"));
+        assert_eq!(version.to_string(), "1.0.0-rc5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_pre_release() -> Result<()> {
+        let mut config = json!({
+            "ociVersion": "1.0.0-rc2",
+            "process": { "capabilities": ["CAP_CHOWN", "CAP_KILL"] },
+            "linux": {
+                "seccomp": {
+                    "defaultAction": "SCMP_ACT_ALLOW",
+                    "syscalls": [{ "name": "mount", "action": "SCMP_ACT_ERRNO" }]
+                }
+            }
+        });
+        translate(&mut config, &"1.0.0-rc2".parse()?);
+
+        assert_eq!(
+            config["process"]["capabilities"]["effective"],
+            json!(["CAP_CHOWN", "CAP_KILL"])
+        );
+        assert_eq!(
+            config["linux"]["seccomp"]["syscalls"][0],
+            json!({ "names": ["mount"], "action": "SCMP_ACT_ERRNO" })
+        );
+
+        // configs of a release are left alone
+        let mut config = json!({ "process": { "capabilities": ["CAP_CHOWN"] } });
+        let original = config.clone();
+        translate(&mut config, &"1.0.2".parse()?);
+        assert_eq!(config, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_resources() {
+        let mut resources = json!({
+            "cpu": { "shares": 1024, "burst": 50000, "idle": 1 },
+            "unified": { "cpu.idle": "0" }
+        });
+        translate_resources(&mut resources);
+
+        assert_eq!(resources["cpu"], json!({ "shares": 1024 }));
+        assert_eq!(
+            resources["unified"],
+            json!({ "cpu.max.burst": "50000", "cpu.idle": "0" })
+        );
+
+        let mut resources = json!({ "cpu": { "shares": 1024 } });
+        translate_resources(&mut resources);
+        assert_eq!(resources, json!({ "cpu": { "shares": 1024 } }));
+
+        let mut resources = json!({ "misc": { "sgx_epc": 65536, "res_b": "max" } });
+        translate_resources(&mut resources);
+        assert_eq!(
+            resources,
+            json!({ "unified": { "misc.max": "res_b max\nsgx_epc 65536" } })
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Supervision of a container by a youki process which stays in the
+//! foreground. youki becomes a child subreaper, so the container init process
+//! is reparented to it once the intermediate process exits, and reaps every
+//! process which is reparented to it. This keeps double forking workloads from
+//! leaving zombies on the host and allows to report the exit status of the
+//! container init process.
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{self, SigSet, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+// Signals which are forwarded to the container init process
+const FORWARDED_SIGNALS: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+/// Makes the calling process the subreaper of its descendants. Has to be
+/// called before the container processes are forked.
+pub fn set_subreaper() -> Result<()> {
+    let res = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    Errno::result(res).context("failed to become child subreaper")?;
+    Ok(())
+}
+
+/// Blocks SIGCHLD and the forwarded signals, so they are only received
+/// through the reaping loop. Is called once the container has been started,
+/// so the mask is not inherited by the processes which are forked for the
+/// start.
+pub fn block_signals() -> Result<SigSet> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+    for signal in FORWARDED_SIGNALS {
+        mask.add(*signal);
+    }
+
+    mask.thread_block().context("failed to block signals")?;
+    Ok(mask)
+}
+
+pub struct Supervisor {
+    init_pid: Pid,
+    mask: SigSet,
+}
+
+impl Supervisor {
+    pub fn new(init_pid: Pid, mask: SigSet) -> Self {
+        Self { init_pid, mask }
+    }
+
+    /// Reaps children until the container init process exits and returns
+    /// its exit code. A process killed by a signal exits with 128 + signal,
+    /// like in a shell.
+    pub fn wait(&self) -> Result<i32> {
+        loop {
+            // children which exited before the signals were blocked have to
+            // be reaped without waiting for SIGCHLD
+            if let Some(code) = self.reap()? {
+                // the other processes of the pid namespace are killed along
+                // with the init process and are reparented to youki as well
+                self.reap()?;
+                return Ok(code);
+            }
+
+            let signal = self.mask.wait().context("failed to wait for signals")?;
+            if signal != Signal::SIGCHLD {
+                log::debug!("forward signal {} to {}", signal, self.init_pid);
+                match signal::kill(self.init_pid, signal) {
+                    Ok(_) | Err(Errno::ESRCH) => {}
+                    Err(err) => log::warn!("failed to forward signal {}: {}", signal, err),
+                }
+            }
+        }
+    }
+
+    // Reaps all children which have exited, returns the exit code of the
+    // container init process if it is among them
+    fn reap(&self) -> Result<Option<i32>> {
+        let mut init_code = None;
+        loop {
+            let (pid, code) = match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+                Ok(status) => match exit_code(status) {
+                    Some(exit) => exit,
+                    None => continue,
+                },
+                Err(err) => return Err(err).context("failed to reap children"),
+            };
+
+            if pid == self.init_pid {
+                log::debug!("container init process exited with {}", code);
+                init_code = Some(code);
+            } else {
+                log::debug!("reaped process {} with exit code {}", pid, code);
+            }
+        }
+
+        Ok(init_code)
+    }
+}
+
+pub(crate) fn exit_code(status: WaitStatus) -> Option<(Pid, i32)> {
+    match status {
+        WaitStatus::Exited(pid, code) => Some((pid, code)),
+        WaitStatus::Signaled(pid, signal, _) => Some((pid, 128 + signal as i32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{self, ForkResult};
+
+    #[test]
+    fn test_exit_code() -> Result<()> {
+        let child = match unsafe { unistd::fork()? } {
+            ForkResult::Child => unsafe { libc::_exit(3) },
+            ForkResult::Parent { child } => child,
+        };
+        assert_eq!(exit_code(waitpid(child, None)?), Some((child, 3)));
+
+        let child = match unsafe { unistd::fork()? } {
+            ForkResult::Child => loop {
+                unistd::pause();
+            },
+            ForkResult::Parent { child } => child,
+        };
+        signal::kill(child, Signal::SIGKILL)?;
+        assert_eq!(exit_code(waitpid(child, None)?), Some((child, 137)));
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 //! Implements Command trait for Linux systems
 use std::ffi::{CStr, OsStr};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
 use std::sync::Arc;
 use std::{any::Any, mem, path::Path, ptr};
 
@@ -17,7 +18,7 @@ use nix::{
     unistd::{Gid, Uid},
 };
 use nix::{
-    mount::{umount2, MntFlags},
+    mount::{mount, umount2, MntFlags, MsFlags},
     unistd,
 };
 use nix::{sched::unshare, sys::stat::Mode};
@@ -32,6 +33,41 @@ use crate::capabilities;
 pub struct LinuxSyscall;
 
 impl LinuxSyscall {
+    fn pivot_to(newroot: RawFd) -> Result<()> {
+        // Change directory to the new root, so it can be referred to as "."
+        fchdir(newroot)?;
+
+        // make the given path as the root directory for the container
+        // see https://man7.org/linux/man-pages/man2/pivot_root.2.html, specially the notes
+        // pivot root usually changes the root directory to first argument, and then mounts the original root
+        // directory at second argument. Giving same path for both stacks mapping of the original root directory
+        // above the new directory at the same path, then the call to umount unmounts the original root directory from
+        // this path. This is done, as otherwise, we will need to create a separate temporary directory under the new root path
+        // so we can move the original root there, and then unmount that. This way saves the creation of the temporary
+        // directory to put original root directory. The errno is returned as is, so the caller
+        // can fall back to chroot if pivot_root is not possible.
+        pivot_root(".", ".")?;
+
+        // Make the original root directory a slave, so unmounting it does not
+        // propagate to the mount namespace of the host
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_SLAVE | MsFlags::MS_REC,
+            None::<&str>,
+        )?;
+
+        // Unmount the original root directory which was stacked on top of new root directory
+        // MNT_DETACH makes the mount point unavailable to new accesses, but waits till the original mount point
+        // to be free of activity to actually unmount
+        // see https://man7.org/linux/man-pages/man2/umount2.2.html for more information
+        umount2("/", MntFlags::MNT_DETACH)?;
+        // Change directory to root
+        unistd::chdir("/")?;
+        Ok(())
+    }
+
     unsafe fn from_raw_buf<'a, T>(p: *const c_char) -> T
     where
         T: From<&'a OsStr>,
@@ -56,25 +92,30 @@ impl Syscall for LinuxSyscall {
     /// Function to set given path as root path inside process
     fn pivot_rootfs(&self, path: &Path) -> Result<()> {
         // open the path as directory and read only
-        let newroot = open(path, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
-
-        // make the given path as the root directory for the container
-        // see https://man7.org/linux/man-pages/man2/pivot_root.2.html, specially the notes
-        // pivot root usually changes the root directory to first argument, and then mounts the original root
-        // directory at second argument. Giving same path for both stacks mapping of the original root directory
-        // above the new directory at the same path, then the call to umount unmounts the original root directory from
-        // this path. This is done, as otherwise, we will need to create a separate temporary directory under the new root path
-        // so we can move the original root there, and then unmount that. This way saves the creation of the temporary
-        // directory to put original root directory.
-        pivot_root(path, path)?;
+        let newroot = open(
+            path,
+            OFlag::O_DIRECTORY | OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        let result = Self::pivot_to(newroot);
+        let _ = unistd::close(newroot);
+        result
+    }
 
-        // Unmount the original root directory which was stacked on top of new root directory
-        // MNT_DETACH makes the mount point unavailable to new accesses, but waits till the original mount point
-        // to be free of activity to actually unmount
-        // see https://man7.org/linux/man-pages/man2/umount2.2.html for more information
-        umount2("/", MntFlags::MNT_DETACH)?;
-        // Change directory to root
-        fchdir(newroot)?;
+    fn move_rootfs(&self, path: &Path) -> Result<()> {
+        // Move the rootfs on top of the original root directory and chroot
+        // into it. Unlike pivot_root, the original root directory stays
+        // reachable below the new one, which is why this is only a fallback.
+        unistd::chdir(path)?;
+        mount(
+            Some(path),
+            "/",
+            None::<&str>,
+            MsFlags::MS_MOVE,
+            None::<&str>,
+        )?;
+        unistd::chroot(".")?;
+        unistd::chdir("/")?;
         Ok(())
     }
 
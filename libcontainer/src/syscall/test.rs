@@ -65,6 +65,10 @@ impl Syscall for TestHelperSyscall {
         todo!()
     }
 
+    fn move_rootfs(&self, _: &std::path::Path) -> anyhow::Result<()> {
+        todo!()
+    }
+
     fn chroot(&self, _: &std::path::Path) -> anyhow::Result<()> {
         todo!()
     }
@@ -0,0 +1,402 @@
+//! Timing of the phases of the container creation, e.g. the setup of the
+//! namespaces or the mounts of the rootfs. The phases are measured for a
+//! timing report of the startup with --profile-start. With the trace feature
+//! they are also recorded as tracing spans and can be written to a file in
+//! the Chrome trace event format, which can be opened with chrome://tracing
+//! or Perfetto.
+//!
+//! The container processes are forked from youki and record their phases in
+//! the same file, so every event is appended with a single write. The trace is
+//! a JSON array without the closing bracket, which the viewers accept for
+//! traces of processes that may not exit cleanly.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::sys::memfd::{self, MemFdCreateFlag};
+use once_cell::sync::OnceCell;
+
+// Anonymous file the processes of the container append their phases to, if
+// the startup is profiled
+static PROFILE: OnceCell<File> = OnceCell::new();
+
+/// A phase of the container creation, which lasts until the value is dropped
+pub struct Phase {
+    name: &'static str,
+    start: SystemTime,
+    started: Instant,
+    #[cfg(feature = "trace")]
+    _span: tracing::span::EnteredSpan,
+}
+
+/// Starts a phase in the current process
+pub fn phase(name: &'static str) -> Phase {
+    Phase {
+        name,
+        start: SystemTime::now(),
+        started: Instant::now(),
+        #[cfg(feature = "trace")]
+        _span: tracing::info_span!("phase", phase = name).entered(),
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        if let Some(mut profile) = PROFILE.get() {
+            let _ = profile.write_all(
+                format!(
+                    "{} {} {} {}\n",
+                    self.name,
+                    std::process::id(),
+                    micros_since_epoch(self.start),
+                    self.started.elapsed().as_micros()
+                )
+                .as_bytes(),
+            );
+        }
+    }
+}
+
+/// Marks a point in time of the current process, e.g. the execution of the
+/// payload, which ends the process before a phase could be closed
+pub fn mark(name: &'static str) {
+    #[cfg(feature = "trace")]
+    tracing::info!(phase = name);
+    #[cfg(not(feature = "trace"))]
+    let _ = name;
+}
+
+/// Writes the phases of this process and the processes forked from it to the
+/// trace file. A new file is created if it does not exist yet, otherwise the
+/// events are appended to it.
+#[cfg(feature = "trace")]
+pub fn init(trace_file: &Path) -> Result<()> {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let layer = chrome::ChromeLayer::open(trace_file)
+        .with_context(|| format!("failed to open trace file {:?}", trace_file))?;
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .context("failed to set the tracing subscriber")
+}
+
+#[cfg(not(feature = "trace"))]
+pub fn init(trace_file: &Path) -> Result<()> {
+    bail!(
+        "can not write trace to {:?}, youki has been built without the trace feature",
+        trace_file
+    )
+}
+
+/// Measures the phases of this process and the processes forked from it, until
+/// they execute the payload
+pub fn enable_profile() -> Result<()> {
+    PROFILE.get_or_try_init(|| -> Result<File> {
+        let fd = memfd::memfd_create(
+            &std::ffi::CString::new("youki-profile")?,
+            MemFdCreateFlag::MFD_CLOEXEC,
+        )
+        .context("failed to create profile file")?;
+        let profile = unsafe { File::from_raw_fd(fd) };
+        // the processes share the offset of the file, so they have to append
+        fcntl::fcntl(fd, FcntlArg::F_SETFL(OFlag::O_APPEND))?;
+        Ok(profile)
+    })?;
+
+    Ok(())
+}
+
+/// Time a process spent in a phase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub pid: u32,
+    /// Microseconds since the epoch
+    pub start: u64,
+    pub duration: Duration,
+}
+
+/// Returns the phases which have been measured since the profile was enabled
+pub fn profile() -> Result<Vec<PhaseTiming>> {
+    let mut profile = match PROFILE.get() {
+        Some(profile) => profile,
+        None => bail!("the startup is not profiled"),
+    };
+
+    let mut content = String::new();
+    profile.seek(SeekFrom::Start(0))?;
+    profile
+        .read_to_string(&mut content)
+        .context("failed to read profile")?;
+    parse_profile(&content)
+}
+
+fn parse_profile(content: &str) -> Result<Vec<PhaseTiming>> {
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [name, pid, start, duration] => Ok(PhaseTiming {
+                    name: name.to_owned(),
+                    pid: pid.parse()?,
+                    start: start.parse()?,
+                    duration: Duration::from_micros(duration.parse()?),
+                }),
+                _ => bail!("invalid profile entry {:?}", line),
+            }
+        })
+        .collect()
+}
+
+/// Writes a report of the time spent in every phase, in the order the phases
+/// have been started. Phases which ran in several processes are summed up.
+pub fn write_profile<W: Write>(writer: W, timings: &[PhaseTiming], total: Duration) -> Result<()> {
+    let mut timings = timings.to_vec();
+    timings.sort_by_key(|t| t.start);
+
+    let mut tab_writer = tabwriter::TabWriter::new(writer);
+    writeln!(&mut tab_writer, "PHASE\tPROCESSES\tTIME")?;
+    let mut reported = HashSet::new();
+    for timing in &timings {
+        if !reported.insert(&timing.name) {
+            continue;
+        }
+
+        let phase: Vec<&PhaseTiming> = timings.iter().filter(|t| t.name == timing.name).collect();
+        let pids: HashSet<u32> = phase.iter().map(|t| t.pid).collect();
+        let duration: Duration = phase.iter().map(|t| t.duration).sum();
+        writeln!(
+            &mut tab_writer,
+            "{}\t{}\t{}",
+            timing.name,
+            pids.len(),
+            format_duration(duration)
+        )?;
+    }
+    writeln!(&mut tab_writer, "total\t\t{}", format_duration(total))?;
+    tab_writer.flush()?;
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+// The processes of a container are measured with the same clock, so their
+// phases line up
+fn micros_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "trace")]
+mod chrome {
+    use std::fmt;
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::{Instant, SystemTime};
+
+    use anyhow::Result;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::micros_since_epoch;
+
+    // Category of the events in the trace
+    const CATEGORY: &str = "youki";
+
+    pub struct ChromeLayer {
+        file: File,
+    }
+
+    // Start of a span, which is kept in the extensions of the span until it
+    // is closed
+    struct Timing {
+        name: String,
+        start: SystemTime,
+        started: Instant,
+    }
+
+    // Uses the phase field of a span or event as its name in the trace
+    #[derive(Default)]
+    struct NameVisitor {
+        name: Option<String>,
+    }
+
+    impl Visit for NameVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "phase" {
+                self.name = Some(value.to_owned());
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "phase" {
+                self.name = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl ChromeLayer {
+        pub fn open(path: &Path) -> Result<Self> {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            if file.metadata()?.len() == 0 {
+                file.write_all(b"[\n")?;
+            }
+
+            Ok(Self { file })
+        }
+
+        fn write(&self, event: serde_json::Value) {
+            // a failed write must not fail the container creation
+            let _ = (&self.file).write_all(format!("{},\n", event).as_bytes());
+        }
+    }
+
+    impl<S> Layer<S> for ChromeLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut visitor = NameVisitor::default();
+            attrs.record(&mut visitor);
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(Timing {
+                    name: visitor
+                        .name
+                        .unwrap_or_else(|| attrs.metadata().name().to_owned()),
+                    start: SystemTime::now(),
+                    started: Instant::now(),
+                });
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut visitor = NameVisitor::default();
+            event.record(&mut visitor);
+            self.write(serde_json::json!({
+                "name": visitor.name.unwrap_or_else(|| event.metadata().name().to_owned()),
+                "cat": CATEGORY,
+                "ph": "i",
+                "s": "p",
+                "ts": micros_since_epoch(SystemTime::now()),
+                "pid": std::process::id(),
+                "tid": nix::unistd::gettid().as_raw(),
+            }));
+        }
+
+        fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+            let span = match ctx.span(&id) {
+                Some(span) => span,
+                None => return,
+            };
+            let extensions = span.extensions();
+            if let Some(timing) = extensions.get::<Timing>() {
+                self.write(serde_json::json!({
+                    "name": timing.name,
+                    "cat": CATEGORY,
+                    "ph": "X",
+                    "ts": micros_since_epoch(timing.start),
+                    "dur": timing.started.elapsed().as_micros() as u64,
+                    "pid": std::process::id(),
+                    "tid": nix::unistd::gettid().as_raw(),
+                }));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::utils::create_temp_dir;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        #[test]
+        fn test_chrome_layer() -> Result<()> {
+            let tmp = create_temp_dir("test_chrome_layer")?;
+            let trace_file = tmp.join("trace.json");
+            let layer = ChromeLayer::open(&trace_file)?;
+            tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+                let _phase = crate::trace::phase("rootfs");
+                crate::trace::mark("execve");
+            });
+
+            let trace = std::fs::read_to_string(&trace_file)?;
+            // the viewers accept a trailing comma without the closing bracket
+            let events: Vec<serde_json::Value> = serde_json::from_str(&format!("{}{{}}]", trace))?;
+            assert_eq!(events[0]["name"], "execve");
+            assert_eq!(events[0]["ph"], "i");
+            assert_eq!(events[1]["name"], "rootfs");
+            assert_eq!(events[1]["ph"], "X");
+            assert_eq!(events[1]["pid"], std::process::id());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(name: &str, pid: u32, start: u64, duration: u64) -> PhaseTiming {
+        PhaseTiming {
+            name: name.to_owned(),
+            pid,
+            start,
+            duration: Duration::from_micros(duration),
+        }
+    }
+
+    #[test]
+    fn test_parse_profile() -> Result<()> {
+        assert_eq!(
+            parse_profile("spec 10 1000 250\nsetns 11 2000 1500\n")?,
+            vec![
+                timing("spec", 10, 1000, 250),
+                timing("setns", 11, 2000, 1500)
+            ]
+        );
+        assert!(parse_profile("spec 10\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_profile() -> Result<()> {
+        let timings = vec![
+            timing("setns", 12, 3000, 500),
+            timing("spec", 10, 1000, 250),
+            timing("setns", 11, 2000, 1500),
+        ];
+        let mut report = Vec::new();
+        write_profile(&mut report, &timings, Duration::from_micros(4000))?;
+        assert_eq!(
+            String::from_utf8(report)?,
+            "PHASE  PROCESSES  TIME\n\
+             spec   1          0.250ms\n\
+             setns  2          2.000ms\n\
+             total             4.000ms\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile() -> Result<()> {
+        enable_profile()?;
+        drop(phase("spec"));
+        let timings = profile()?;
+        assert!(timings
+            .iter()
+            .any(|t| t.name == "spec" && t.pid == std::process::id()));
+        Ok(())
+    }
+}
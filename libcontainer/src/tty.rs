@@ -0,0 +1,366 @@
+//! tty (teletype) for user-system interaction
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::symlink;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::prelude::RawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::socket;
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::sys::uio;
+use nix::unistd::{self, dup2, Pid};
+use nix::unistd::{close, setsid};
+
+use crate::utils::TempDir;
+
+const STDIN: i32 = 0;
+const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+
+const CONSOLE_SOCKET_NAME: &str = "console.sock";
+// Interval in which the receiver checks if the process is still alive
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Connects to the console socket of the caller, e.g. of conmon, before the
+/// container process is created. The caller has to listen on the socket
+/// already, so a missing socket fails the creation instead of the container
+/// process, which would leave the caller waiting for the terminal.
+pub fn setup_console_socket(
+    container_dir: &Path,
+    console_socket_path: &Path,
+    socket_name: &str,
+) -> Result<RawFd> {
+    // the path of the socket may be too long for a socket address, so it is
+    // connected through a link in the container directory
+    let linked = container_dir.join(socket_name);
+    symlink(console_socket_path, &linked)?;
+
+    let csocketfd = socket::socket(
+        socket::AddressFamily::Unix,
+        socket::SockType::Stream,
+        socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    if let Err(errno) = socket::connect(
+        csocketfd,
+        &socket::SockAddr::Unix(socket::UnixAddr::new(&*socket_name)?),
+    ) {
+        let _ = close(csocketfd);
+        return Err(errno).with_context(|| {
+            format!(
+                "failed to connect to console socket {:?}",
+                console_socket_path
+            )
+        });
+    }
+    Ok(csocketfd)
+}
+
+pub fn setup_console(console_fd: &RawFd) -> Result<()> {
+    // You can also access pty master, but it is better to use the API.
+    // ref. https://github.com/containerd/containerd/blob/261c107ffc4ff681bc73988f64e3f60c32233b37/vendor/github.com/containerd/go-runc/console.go#L139-L154
+    let openpty_result =
+        nix::pty::openpty(None, None).context("could not create pseudo terminal")?;
+    let pty_name: &[u8] = b"/dev/ptmx";
+    let iov = [uio::IoVec::from_slice(pty_name)];
+    let fds = [openpty_result.master];
+    let cmsg = socket::ControlMessage::ScmRights(&fds);
+    socket::sendmsg(
+        console_fd.as_raw_fd(),
+        &iov,
+        &[cmsg],
+        socket::MsgFlags::empty(),
+        None,
+    )
+    .context("failed to send pty master")?;
+
+    setsid()?;
+    if unsafe { libc::ioctl(openpty_result.slave, libc::TIOCSCTTY) } < 0 {
+        log::warn!("could not TIOCSCTTY");
+    };
+    let slave = openpty_result.slave;
+    connect_stdio(&slave, &slave, &slave).context("could not dup tty to stderr")?;
+    close(console_fd.as_raw_fd()).context("could not close console socket")?;
+    Ok(())
+}
+
+fn connect_stdio(stdin: &RawFd, stdout: &RawFd, stderr: &RawFd) -> Result<()> {
+    dup2(stdin.as_raw_fd(), STDIN)?;
+    dup2(stdout.as_raw_fd(), STDOUT)?;
+    // FIXME: Rarely does it fail.
+    // error message: `Error: Resource temporarily unavailable (os error 11)`
+    dup2(stderr.as_raw_fd(), STDERR)?;
+    Ok(())
+}
+
+/// Console socket of youki itself. It receives the pty master of a process
+/// which is attached to the terminal youki runs in, if no console socket
+/// has been passed on the command line.
+pub struct ConsoleReceiver {
+    dir: TempDir,
+    listener: UnixListener,
+}
+
+impl ConsoleReceiver {
+    pub fn new() -> Result<Self> {
+        // the directory gets an unpredictable name and is only accessible by
+        // its owner from the start, so nobody else can connect and send a
+        // terminal. Every receiver has its own directory, so concurrent execs
+        // into the same container do not collide.
+        let dir = TempDir::new(mkdtemp(&std::env::temp_dir().join("youki-console-"))?)?;
+        let listener = UnixListener::bind(dir.join(CONSOLE_SOCKET_NAME))
+            .context("failed to create console socket")?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self { dir, listener })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(CONSOLE_SOCKET_NAME)
+    }
+
+    /// Waits for the pty master which the process with the given pid sends.
+    /// Fails if the process exits before.
+    pub fn receive(self, pid: Pid) -> Result<RawFd> {
+        let mut fds = [PollFd::new(self.listener.as_raw_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, POLL_INTERVAL.as_millis() as i32) {
+                Ok(0) | Err(Errno::EINTR) => {
+                    // the process is a child of youki, so it stays a zombie
+                    // until it is reaped
+                    let alive = procfs::process::Process::new(pid.as_raw())
+                        .map(|p| p.stat.state != 'Z')
+                        .unwrap_or(false);
+                    if !alive {
+                        bail!("process {} exited before it sent its terminal", pid);
+                    }
+                }
+                Ok(_) => break,
+                Err(err) => return Err(err).context("failed to wait for console socket"),
+            }
+        }
+
+        let (stream, _) = self
+            .listener
+            .accept()
+            .context("failed to accept console socket connection")?;
+        let mut buf = [0u8; 4096];
+        let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg(
+            stream.as_raw_fd(),
+            &iov,
+            Some(&mut cmsg),
+            socket::MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .context("failed to receive pty master")?;
+
+        for cmsg in msg.cmsgs() {
+            if let socket::ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(fd) = fds.first() {
+                    return Ok(*fd);
+                }
+            }
+        }
+
+        bail!("no pty master has been sent to the console socket")
+    }
+}
+
+// Creates a directory with a random suffix, which only the owner can access
+fn mkdtemp(prefix: &Path) -> Result<PathBuf> {
+    let mut template = prefix.as_os_str().as_bytes().to_vec();
+    template.extend_from_slice(b"XXXXXX\0");
+    let res = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if res.is_null() {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("failed to create directory {:?}XXXXXX", prefix));
+    }
+
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+/// Connects the terminal youki runs in to the pty master of a process until
+/// the process closes the pty. The window size of the pty follows the size of
+/// the terminal. Must be called before other threads are spawned, so SIGWINCH
+/// is blocked in all of them and only received by the resize thread.
+pub fn proxy_console(master: RawFd) -> Result<()> {
+    let _raw_mode = RawMode::enable(STDIN)?;
+    if let Err(err) = copy_window_size(STDIN, master) {
+        log::warn!("failed to set window size of the terminal: {}", err);
+    }
+
+    let mut winch = SigSet::empty();
+    winch.add(Signal::SIGWINCH);
+    winch.thread_block().context("failed to block SIGWINCH")?;
+    thread::spawn(move || {
+        while winch.wait().is_ok() {
+            if let Err(err) = copy_window_size(STDIN, master) {
+                log::warn!("failed to resize terminal: {}", err);
+            }
+        }
+    });
+
+    // std::io::stdout is line buffered, which would hold back prompts
+    let mut input = unsafe { File::from_raw_fd(unistd::dup(STDIN)?) };
+    let mut to_master = unsafe { File::from_raw_fd(unistd::dup(master)?) };
+    thread::spawn(move || {
+        let _ = io::copy(&mut input, &mut to_master);
+    });
+
+    let mut from_master = unsafe { File::from_raw_fd(master) };
+    let mut output = unsafe { File::from_raw_fd(unistd::dup(STDOUT)?) };
+    match io::copy(&mut from_master, &mut output) {
+        Ok(_) => Ok(()),
+        // reading from the master fails once the last fd of the slave is closed
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(()),
+        Err(err) => Err(err).context("failed to copy output of the process"),
+    }
+}
+
+fn copy_window_size(from: RawFd, to: RawFd) -> Result<()> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    Errno::result(unsafe { libc::ioctl(from, libc::TIOCGWINSZ, &mut size) })?;
+    Errno::result(unsafe { libc::ioctl(to, libc::TIOCSWINSZ, &size) })?;
+    Ok(())
+}
+
+// Passes every key press to the process, including the ones which would
+// generate signals, and restores the terminal when dropped
+struct RawMode {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Result<Option<Self>> {
+        if !unistd::isatty(fd)? {
+            return Ok(None);
+        }
+
+        let original = termios::tcgetattr(fd).context("failed to get terminal attributes")?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw).context("failed to set raw mode")?;
+        Ok(Some(Self { fd, original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(self.fd, SetArg::TCSANOW, &self.original);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use serial_test::serial;
+
+    use crate::utils::create_temp_dir;
+
+    const CONSOLE_SOCKET: &str = "console-socket";
+
+    fn setup(testname: &str) -> Result<(TempDir, PathBuf, PathBuf)> {
+        let testdir = create_temp_dir(testname)?;
+        let rundir_path = Path::join(&testdir, "run");
+        let _ = fs::create_dir(&rundir_path)?;
+        let socket_path = Path::new(&rundir_path).join("socket");
+        let _ = File::create(&socket_path);
+        env::set_current_dir(&testdir)?;
+        Ok((testdir, rundir_path, socket_path))
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_socket() {
+        let init = setup("test_setup_console_socket");
+        assert!(init.is_ok());
+        let (testdir, rundir_path, socket_path) = init.unwrap();
+        let lis = UnixListener::bind(Path::join(&testdir, "console-socket"));
+        assert!(lis.is_ok());
+        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
+        assert!(fd.is_ok());
+        assert_ne!(fd.unwrap().as_raw_fd(), -1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_socket_empty() {
+        let init = setup("test_setup_console_socket_empty");
+        assert!(init.is_ok());
+        let (_testdir, rundir_path, socket_path) = init.unwrap();
+        // nobody listens on the socket, who could receive the terminal
+        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
+        assert!(fd.is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console_socket_invalid() {
+        let init = setup("test_setup_console_socket_invalid");
+        assert!(init.is_ok());
+        let (testdir, rundir_path, socket_path) = init.unwrap();
+        let _socket = File::create(Path::join(&testdir, "console-socket"));
+        assert!(_socket.is_ok());
+        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
+        assert!(fd.is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_console_receiver() -> Result<()> {
+        let receiver = ConsoleReceiver::new()?;
+        let path = receiver.path();
+        let mode = fs::metadata(path.parent().unwrap())?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let pty = nix::pty::openpty(None, None)?;
+
+        let stream = std::os::unix::net::UnixStream::connect(&path)?;
+        let iov = [uio::IoVec::from_slice(b"/dev/ptmx")];
+        let fds = [pty.master];
+        socket::sendmsg(
+            stream.as_raw_fd(),
+            &iov,
+            &[socket::ControlMessage::ScmRights(&fds)],
+            socket::MsgFlags::empty(),
+            None,
+        )?;
+
+        let master = receiver.receive(unistd::getpid())?;
+        assert!(unistd::isatty(master)?);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_console() {
+        let init = setup("test_setup_console");
+        assert!(init.is_ok());
+        let (testdir, rundir_path, socket_path) = init.unwrap();
+        let lis = UnixListener::bind(Path::join(&testdir, "console-socket"));
+        assert!(lis.is_ok());
+        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
+        let status = setup_console(&fd.unwrap());
+        assert!(status.is_ok());
+    }
+}
@@ -2,10 +2,11 @@
 
 use std::collections::HashMap;
 use std::ffi::CString;
-use std::fs::{self, DirBuilder, File};
+use std::fs::{self, DirBuilder, File, OpenOptions};
 use std::ops::Deref;
 use std::os::linux::fs::MetadataExt;
-use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
@@ -13,6 +14,8 @@ use anyhow::Context;
 use anyhow::{bail, Result};
 use nix::sys::stat::Mode;
 use nix::unistd;
+use oci_spec::Spec;
+use serde::de::DeserializeOwned;
 
 pub trait PathBufExt {
     fn as_in_container(&self) -> Result<PathBuf>;
@@ -42,15 +45,9 @@ impl PathBufExt for PathBuf {
 
 pub fn parse_env(envs: &[String]) -> HashMap<String, String> {
     envs.iter()
-        .filter_map(|e| {
-            let mut split = e.split('=');
-
-            if let Some(key) = split.next() {
-                let value: String = split.collect::<Vec<&str>>().join("=");
-                Some((String::from(key), value))
-            } else {
-                None
-            }
+        .map(|e| match e.split_once('=') {
+            Some((key, value)) => (key.to_owned(), value.to_owned()),
+            None => (e.to_owned(), String::new()),
         })
         .collect()
 }
@@ -70,14 +67,70 @@ pub fn set_name(_name: &str) -> Result<()> {
     Ok(())
 }
 
-/// If None, it will generate a default path for cgroups.
-pub fn get_cgroup_path(cgroups_path: &Option<PathBuf>, container_id: &str) -> PathBuf {
+/// Loads the runtime spec from the given path. The file is read into memory
+/// at once, because deserializing from an unbuffered reader is considerably
+/// slower and spec loading is on the hot path of every container creation.
+pub fn load_spec<P: AsRef<Path>>(path: P) -> Result<Spec> {
+    let path = path.as_ref();
+    let content = fs::read(path).with_context(|| format!("failed to read spec {:?}", path))?;
+    let spec = serde_json::from_slice(&content)
+        .with_context(|| format!("failed to deserialize spec {:?}", path))?;
+    Ok(spec)
+}
+
+/// Reads a field of a loaded spec which is not known to the oci-spec crate.
+/// The field is addressed by a json pointer, e.g. "/process/scheduler".
+pub fn spec_field<T: DeserializeOwned>(
+    config: &serde_json::Value,
+    pointer: &str,
+) -> Result<Option<T>> {
+    match config.pointer(pointer) {
+        Some(field) if !field.is_null() => {
+            let field = serde_json::from_value(field.clone())
+                .with_context(|| format!("invalid {} in spec", pointer))?;
+            Ok(Some(field))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parent of the cgroups of the containers, if the spec does not specify an
+/// absolute cgroups path
+const DEFAULT_CGROUP_PARENT: &str = "youki";
+
+/// If None, it will generate a default path for cgroups. A relative path is
+/// placed below the default parent, a path for the systemd driver is always
+/// of the form [slice]:[scope_prefix]:[name].
+pub fn get_cgroup_path(
+    cgroups_path: &Option<PathBuf>,
+    container_id: &str,
+    use_systemd: bool,
+) -> PathBuf {
     match cgroups_path {
-        Some(cpath) => cpath.clone(),
-        None => PathBuf::from(format!("/youki/{}", container_id)),
+        Some(cpath) if use_systemd || cpath.is_absolute() => cpath.clone(),
+        Some(cpath) => Path::new("/").join(DEFAULT_CGROUP_PARENT).join(cpath),
+        None if use_systemd => {
+            PathBuf::from(format!(":{}:{}", DEFAULT_CGROUP_PARENT, container_id))
+        }
+        None => Path::new("/")
+            .join(DEFAULT_CGROUP_PARENT)
+            .join(container_id),
     }
 }
 
+/// Validates the id of a container, which names its directory below the root
+/// directory. Like runc, ids consist of letters, digits and the characters
+/// `_+-.`, but must not be `.` or `..`.
+pub fn validate_container_id(id: &str) -> Result<()> {
+    let valid = id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.'));
+    if id.is_empty() || id == "." || id == ".." || !valid {
+        bail!("invalid container id {:?}", id);
+    }
+    Ok(())
+}
+
 pub fn delete_with_retry<P: AsRef<Path>>(path: P) -> Result<()> {
     let mut attempts = 0;
     let mut delay = Duration::from_millis(10);
@@ -112,12 +165,42 @@ pub fn open<P: AsRef<Path>>(path: P) -> Result<File> {
     File::open(path).with_context(|| format!("failed to open {:?}", path))
 }
 
+/// Changes into a directory until the guard is dropped, which changes back into
+/// the previous directory. The builders need the container directory as the
+/// current directory, but an embedding program should not notice that.
+pub struct ChdirGuard {
+    previous: File,
+}
+
+impl ChdirGuard {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        // O_PATH is enough for fchdir and works for directories which can
+        // not be read
+        let previous = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(".")
+            .context("failed to open current directory")?;
+        unistd::chdir(dir).with_context(|| format!("failed to chdir into {:?}", dir))?;
+        Ok(Self { previous })
+    }
+}
+
+impl Drop for ChdirGuard {
+    fn drop(&mut self) {
+        if let Err(err) = unistd::fchdir(self.previous.as_raw_fd()) {
+            log::warn!("failed to change back into the previous directory: {}", err);
+        }
+    }
+}
+
 /// Creates the specified directory and all parent directories with the specified mode. Ensures
 /// that the directory has been created with the correct mode and that the owner of the directory
 /// is the owner that has been specified
 /// # Example
 /// ``` no_run
-/// use youki::utils::create_dir_all_with_mode;
+/// use libcontainer::utils::create_dir_all_with_mode;
 /// use nix::sys::stat::Mode;
 /// use std::path::Path;
 ///
@@ -206,6 +289,7 @@ pub fn create_temp_dir(test_name: &str) -> Result<TempDir> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
 
     #[test]
     fn test_join_absolute_path() {
@@ -224,17 +308,38 @@ mod tests {
             .is_err(),);
     }
 
+    #[test]
+    fn test_validate_container_id() {
+        assert!(validate_container_id("74f1a4cb3801").is_ok());
+        assert!(validate_container_id("my_container-1.0+a").is_ok());
+        for id in &["", ".", "..", "../x", "/abs", "a/b", "a b"] {
+            assert!(validate_container_id(id).is_err(), "{:?}", id);
+        }
+    }
+
     #[test]
     fn test_get_cgroup_path() {
         let cid = "sample_container_id";
         assert_eq!(
-            get_cgroup_path(&None, cid),
+            get_cgroup_path(&None, cid, false),
             PathBuf::from("/youki/sample_container_id")
         );
         assert_eq!(
-            get_cgroup_path(&Some(PathBuf::from("/youki")), cid),
+            get_cgroup_path(&Some(PathBuf::from("/youki")), cid, false),
             PathBuf::from("/youki")
         );
+        assert_eq!(
+            get_cgroup_path(&Some(PathBuf::from("pod/abc")), cid, false),
+            PathBuf::from("/youki/pod/abc")
+        );
+        assert_eq!(
+            get_cgroup_path(&None, cid, true),
+            PathBuf::from(":youki:sample_container_id")
+        );
+        assert_eq!(
+            get_cgroup_path(&Some(PathBuf::from("machine.slice:docker:abc")), cid, true),
+            PathBuf::from("machine.slice:docker:abc")
+        );
     }
     #[test]
     fn test_parse_env() -> Result<()> {
@@ -251,4 +356,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_chdir_guard() -> Result<()> {
+        let tmp = create_temp_dir("test_chdir_guard")?;
+        let cwd = fs::canonicalize(&*tmp)?;
+        let dir = cwd.join("dir");
+        fs::create_dir(&dir)?;
+        // other tests may leave a removed directory as the current one
+        std::env::set_current_dir(&cwd)?;
+        {
+            let _guard = ChdirGuard::new(&dir)?;
+            assert_eq!(std::env::current_dir()?, dir);
+        }
+        assert_eq!(std::env::current_dir()?, cwd);
+        assert!(ChdirGuard::new(cwd.join("missing")).is_err());
+        assert_eq!(std::env::current_dir()?, cwd);
+        Ok(())
+    }
 }
@@ -0,0 +1,372 @@
+//! Validation of a config.json against the runtime spec before any namespace
+//! or cgroup is set up. The config is checked as plain json, so problems
+//! which the oci-spec crate would only report as a deserialization error, or
+//! which would fail midway through the creation with an obscure syscall error,
+//! are all reported at once together with the json pointer of their location.
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+const SUPPORTED_NAMESPACES: &[&str] = &["pid", "network", "mount", "ipc", "uts", "user", "cgroup"];
+const HOOK_KINDS: &[&str] = &[
+    "prestart",
+    "createRuntime",
+    "createContainer",
+    "startContainer",
+    "poststart",
+    "poststop",
+];
+const DEVICE_TYPES: &[&str] = &["c", "b", "u", "p"];
+
+/// Problem in the config and its location as json pointer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.message)
+    }
+}
+
+/// Validates a config and fails with a list of all problems which have been
+/// found
+pub fn validate_spec(config: &Value) -> Result<()> {
+    let errors = validate(config);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|e| format!("  {}", e)).collect();
+        bail!("{} problems found:\n{}", errors.len(), errors.join("\n"));
+    }
+
+    Ok(())
+}
+
+/// Returns all problems of the config
+pub fn validate(config: &Value) -> Vec<ValidationError> {
+    let mut v = Validator::default();
+    v.validate_version(config);
+    v.validate_root(config);
+    v.validate_process(config);
+    v.validate_mounts(config);
+    v.validate_hooks(config);
+    v.validate_linux(config);
+    v.errors
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    fn error<S: Into<String>>(&mut self, pointer: &str, message: S) {
+        self.errors.push(ValidationError {
+            pointer: pointer.to_owned(),
+            message: message.into(),
+        });
+    }
+
+    fn validate_version(&mut self, config: &Value) {
+        match config.pointer("/ociVersion") {
+            Some(Value::String(version)) if !version.is_empty() => {}
+            Some(_) => self.error("/ociVersion", "must be a non-empty string"),
+            None => self.error("/ociVersion", "is required"),
+        }
+    }
+
+    fn validate_root(&mut self, config: &Value) {
+        match config.pointer("/root/path") {
+            Some(Value::String(path)) if !path.is_empty() => {}
+            Some(_) => self.error("/root/path", "must be a non-empty string"),
+            None => self.error("/root/path", "is required"),
+        }
+    }
+
+    fn validate_process(&mut self, config: &Value) {
+        let process = match config.pointer("/process") {
+            Some(process) => process,
+            None => return self.error("/process", "is required"),
+        };
+
+        match process.get("args").and_then(Value::as_array) {
+            Some(args) if !args.is_empty() => {
+                for (i, arg) in args.iter().enumerate() {
+                    if !arg.is_string() {
+                        self.error(&format!("/process/args/{}", i), "must be a string");
+                    }
+                }
+            }
+            Some(_) => self.error("/process/args", "must contain at least the executable"),
+            None => self.error("/process/args", "is required"),
+        }
+
+        self.absolute_path(process.get("cwd"), "/process/cwd", true);
+
+        if let Some(env) = process.get("env").and_then(Value::as_array) {
+            for (i, var) in env.iter().enumerate() {
+                if !var.as_str().map_or(false, |var| var.contains('=')) {
+                    self.error(
+                        &format!("/process/env/{}", i),
+                        "must have the form KEY=value",
+                    );
+                }
+            }
+        }
+
+        for id in &["uid", "gid"] {
+            if let Some(value) = process.pointer(&format!("/user/{}", id)) {
+                if value.as_u64().map_or(true, |id| id > u32::MAX as u64) {
+                    self.error(&format!("/process/user/{}", id), "must be a valid id");
+                }
+            }
+        }
+
+        if let Some(rlimits) = process.get("rlimits").and_then(Value::as_array) {
+            let mut types = HashSet::new();
+            for (i, rlimit) in rlimits.iter().enumerate() {
+                let pointer = format!("/process/rlimits/{}", i);
+                match rlimit.get("type").and_then(Value::as_str) {
+                    Some(typ) if typ.starts_with("RLIMIT_") => {
+                        if !types.insert(typ) {
+                            self.error(&pointer, format!("{} is set more than once", typ));
+                        }
+                    }
+                    _ => self.error(&format!("{}/type", pointer), "must be an RLIMIT_* name"),
+                }
+
+                let soft = rlimit.get("soft").and_then(Value::as_u64);
+                let hard = rlimit.get("hard").and_then(Value::as_u64);
+                if let (Some(soft), Some(hard)) = (soft, hard) {
+                    if soft > hard {
+                        self.error(
+                            &pointer,
+                            "soft limit must not be greater than the hard limit",
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_mounts(&mut self, config: &Value) {
+        if let Some(mounts) = config.pointer("/mounts").and_then(Value::as_array) {
+            for (i, mount) in mounts.iter().enumerate() {
+                let pointer = format!("/mounts/{}/destination", i);
+                self.absolute_path(mount.get("destination"), &pointer, true);
+            }
+        }
+    }
+
+    fn validate_hooks(&mut self, config: &Value) {
+        let hooks = match config.pointer("/hooks").and_then(Value::as_object) {
+            Some(hooks) => hooks,
+            None => return,
+        };
+
+        for (kind, hooks) in hooks {
+            if !HOOK_KINDS.contains(&kind.as_str()) {
+                self.error(&format!("/hooks/{}", kind), "is not a known hook");
+                continue;
+            }
+
+            // relative paths of hooks are resolved against the bundle
+            for (i, hook) in hooks.as_array().into_iter().flatten().enumerate() {
+                let pointer = format!("/hooks/{}/{}/path", kind, i);
+                match hook.get("path") {
+                    Some(Value::String(path)) if !path.is_empty() => {}
+                    Some(_) => self.error(&pointer, "must be a non-empty string"),
+                    None => self.error(&pointer, "is required"),
+                }
+            }
+        }
+    }
+
+    fn validate_linux(&mut self, config: &Value) {
+        let linux = match config.pointer("/linux") {
+            Some(linux) => linux,
+            None => return self.error("/linux", "is required"),
+        };
+
+        let mut namespaces = HashSet::new();
+        if let Some(entries) = linux.get("namespaces").and_then(Value::as_array) {
+            for (i, namespace) in entries.iter().enumerate() {
+                let pointer = format!("/linux/namespaces/{}", i);
+                match namespace.get("type").and_then(Value::as_str) {
+                    Some(typ) if SUPPORTED_NAMESPACES.contains(&typ) => {
+                        if !namespaces.insert(typ) {
+                            self.error(
+                                &pointer,
+                                format!("{} namespace is set more than once", typ),
+                            );
+                        }
+                    }
+                    Some(typ) => self.error(
+                        &format!("{}/type", pointer),
+                        format!("unsupported namespace type {}", typ),
+                    ),
+                    None => self.error(&format!("{}/type", pointer), "is required"),
+                }
+                self.absolute_path(namespace.get("path"), &format!("{}/path", pointer), false);
+            }
+        }
+
+        let has_mappings = ["uidMappings", "gidMappings"].iter().any(|m| {
+            linux
+                .get(m)
+                .and_then(Value::as_array)
+                .map_or(false, |m| !m.is_empty())
+        });
+        if has_mappings && !namespaces.contains("user") {
+            self.error(
+                "/linux/uidMappings",
+                "id mappings require a user namespace in /linux/namespaces",
+            );
+        }
+        for mappings in &["uidMappings", "gidMappings"] {
+            for (i, mapping) in linux
+                .get(mappings)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                for field in &["containerID", "hostID", "size"] {
+                    if mapping.get(field).and_then(Value::as_u64).is_none() {
+                        self.error(
+                            &format!("/linux/{}/{}/{}", mappings, i, field),
+                            "is required",
+                        );
+                    }
+                }
+                if mapping.get("size").and_then(Value::as_u64) == Some(0) {
+                    self.error(
+                        &format!("/linux/{}/{}/size", mappings, i),
+                        "must be greater than 0",
+                    );
+                }
+            }
+        }
+
+        let hostname = config.get("hostname").and_then(Value::as_str);
+        if hostname.map_or(false, |h| !h.is_empty()) && !namespaces.contains("uts") {
+            self.error("/hostname", "setting the hostname requires a uts namespace");
+        }
+
+        if let Some(sysctl) = linux.get("sysctl").and_then(Value::as_object) {
+            for key in sysctl.keys() {
+                if key.starts_with("net.") && !namespaces.contains("network") {
+                    self.error(
+                        &format!("/linux/sysctl/{}", key),
+                        "network sysctls require a network namespace",
+                    );
+                }
+            }
+        }
+
+        for paths in &["maskedPaths", "readonlyPaths"] {
+            if let Some(entries) = linux.get(paths).and_then(Value::as_array) {
+                for (i, path) in entries.iter().enumerate() {
+                    let pointer = format!("/linux/{}/{}", paths, i);
+                    self.absolute_path(Some(path), &pointer, true);
+                }
+            }
+        }
+
+        if let Some(devices) = linux.get("devices").and_then(Value::as_array) {
+            for (i, device) in devices.iter().enumerate() {
+                let pointer = format!("/linux/devices/{}", i);
+                self.absolute_path(device.get("path"), &format!("{}/path", pointer), true);
+                match device.get("type").and_then(Value::as_str) {
+                    Some(typ) if DEVICE_TYPES.contains(&typ) => {}
+                    _ => self.error(&format!("{}/type", pointer), "must be one of c, b, u or p"),
+                }
+            }
+        }
+    }
+
+    fn absolute_path(&mut self, value: Option<&Value>, pointer: &str, required: bool) {
+        match value {
+            Some(Value::String(path)) if path.starts_with('/') => {}
+            Some(Value::String(_)) => self.error(pointer, "must be an absolute path"),
+            Some(_) => self.error(pointer, "must be a string"),
+            None if required => self.error(pointer, "is required"),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> Value {
+        json!({
+            "ociVersion": "1.0.2",
+            "root": { "path": "rootfs" },
+            "process": {
+                "args": ["sh"],
+                "cwd": "/",
+                "env": ["PATH=/bin"],
+                "user": { "uid": 0, "gid": 0 }
+            },
+            "hostname": "youki",
+            "mounts": [{ "destination": "/proc", "type": "proc", "source": "proc" }],
+            "linux": {
+                "namespaces": [{ "type": "pid" }, { "type": "mount" }, { "type": "uts" }]
+            }
+        })
+    }
+
+    fn pointers(config: &Value) -> Vec<String> {
+        validate(config).into_iter().map(|e| e.pointer).collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert_eq!(validate(&config()), vec![]);
+    }
+
+    #[test]
+    fn test_reports_all_errors() {
+        let mut config = config();
+        config["process"]["cwd"] = json!("relative");
+        config["process"]["env"] = json!(["PATH"]);
+        config["mounts"][0]["destination"] = json!("proc");
+        config["linux"]["namespaces"] = json!([{ "type": "pid" }, { "type": "time" }]);
+
+        assert_eq!(
+            pointers(&config),
+            vec![
+                "/process/cwd",
+                "/process/env/0",
+                "/mounts/0/destination",
+                "/linux/namespaces/1/type",
+                "/hostname",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mutually_exclusive_options() {
+        let mut config = config();
+        config["linux"]["uidMappings"] = json!([{ "containerID": 0, "hostID": 1000, "size": 0 }]);
+        config["linux"]["sysctl"] = json!({ "net.ipv4.ip_forward": "1" });
+        config["process"]["rlimits"] =
+            json!([{ "type": "RLIMIT_NOFILE", "soft": 2048, "hard": 1024 }]);
+
+        assert_eq!(
+            pointers(&config),
+            vec![
+                "/process/rlimits/0",
+                "/linux/uidMappings",
+                "/linux/uidMappings/0/size",
+                "/linux/sysctl/net.ipv4.ip_forward",
+            ]
+        );
+    }
+}
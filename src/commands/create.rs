@@ -1,9 +1,12 @@
 //! Handles the creation of a new container
 use anyhow::Result;
 use clap::Clap;
+use std::io;
 use std::path::PathBuf;
+use std::time::Instant;
 
-use crate::container::builder::ContainerBuilder;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::trace;
 
 /// This is the main structure which stores various commandline options given by
 /// high-level container runtime
@@ -22,6 +25,37 @@ pub struct Create {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     preserve_fds: i32,
+    /// Do not use pivot_root to enter the root filesystem, which is required when running from a ramdisk
+    #[clap(long)]
+    no_pivot: bool,
+    /// Fail instead of skipping the resource limits which can not be applied,
+    /// because the host does not support them or the cgroup of a rootless
+    /// container is not delegated to the user
+    #[clap(long)]
+    strict_resources: bool,
+    /// Change the parts of the spec which an unprivileged user can not set
+    /// up, e.g. device rules or unmapped mount options, instead of failing
+    /// to create a rootless container
+    #[clap(long)]
+    rootless_fixups: bool,
+    /// Do not map the subordinate ids of the user from /etc/subuid and
+    /// /etc/subgid into a rootless container whose spec has no mappings
+    #[clap(long)]
+    no_subid_mappings: bool,
+    /// Binary which sets up the network of a container with a network
+    /// namespace before it is started, e.g. with slirp4netns. It is called
+    /// with "setup <container id> <pid> <netns path>" and with
+    /// "cleanup <container id> <pid>" when the container is deleted.
+    #[clap(long)]
+    network_setup: Option<PathBuf>,
+    /// Print the time spent in the phases of the creation, e.g. in setting
+    /// up the namespaces or the mounts, to stderr
+    #[clap(long)]
+    profile_start: bool,
+    /// Accepted for compatibility with runc, as conmon passes it. youki does
+    /// not create a session keyring for the container anyway.
+    #[clap(long)]
+    no_new_keyring: bool,
     /// name of the container instance to be started
     pub container_id: String,
 }
@@ -39,6 +73,8 @@ impl Create {
         bundle: PathBuf,
         console_socket: Option<PathBuf>,
         preserve_fds: i32,
+        no_pivot: bool,
+        strict_resources: bool,
     ) -> Self {
         Self {
             pid_file,
@@ -46,10 +82,56 @@ impl Create {
             console_socket,
             container_id,
             preserve_fds,
+            no_pivot,
+            strict_resources,
+            rootless_fixups: false,
+            no_subid_mappings: false,
+            network_setup: None,
+            profile_start: false,
+            no_new_keyring: false,
         }
     }
+
+    /// Changes the spec of a rootless container instead of failing when it
+    /// can not be set up by an unprivileged user
+    pub fn with_rootless_fixups(mut self, rootless_fixups: bool) -> Self {
+        self.rootless_fixups = rootless_fixups;
+        self
+    }
+
+    /// Does not map the subordinate ids of the user into a rootless container
+    pub fn with_no_subid_mappings(mut self, no_subid_mappings: bool) -> Self {
+        self.no_subid_mappings = no_subid_mappings;
+        self
+    }
+
+    /// Sets up the network of the container with the binary
+    pub fn with_network_setup(mut self, network_setup: Option<PathBuf>) -> Self {
+        self.network_setup = network_setup;
+        self
+    }
+
+    /// Prints the time spent in the phases of the creation
+    pub fn with_profile_start(mut self, profile_start: bool) -> Self {
+        self.profile_start = profile_start;
+        self
+    }
+
     /// Starts a new container process
-    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    pub fn exec(
+        &self,
+        root_path: PathBuf,
+        systemd_cgroup: bool,
+        cgroup_root: Option<PathBuf>,
+    ) -> Result<()> {
+        let started = Instant::now();
+        if self.profile_start {
+            trace::enable_profile()?;
+        }
+        if self.no_new_keyring {
+            log::debug!("no session keyring is created for the container");
+        }
+
         ContainerBuilder::new(self.container_id.clone())
             .with_pid_file(self.pid_file.as_ref())
             .with_console_socket(self.console_socket.as_ref())
@@ -57,6 +139,18 @@ impl Create {
             .with_preserved_fds(self.preserve_fds)
             .as_init(&self.bundle)
             .with_systemd(systemd_cgroup)
-            .build()
+            .with_cgroup_root(cgroup_root)
+            .with_no_pivot(self.no_pivot)
+            .with_strict_resources(self.strict_resources)
+            .with_rootless_fixups(self.rootless_fixups)
+            .with_subid_mappings(!self.no_subid_mappings)
+            .with_network_setup(self.network_setup.clone())
+            .build()?;
+
+        if self.profile_start {
+            trace::write_profile(io::stderr(), &trace::profile()?, started.elapsed())?;
+        }
+
+        Ok(())
     }
 }
@@ -1,11 +1,26 @@
-use crate::utils;
-use cgroups::common;
+use cgroups::{common, stats::Stats};
 use clap::Clap;
-use std::{path::PathBuf, thread, time::Duration};
+use libcontainer::utils;
+use serde::Serialize;
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc::RecvTimeoutError,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{bail, Context, Result};
 
-use crate::container::{Container, ContainerStatus};
+use libcontainer::container::history::{HistoryEntry, LifecycleEvent};
+use libcontainer::container::{Container, ContainerStatus};
+use libcontainer::event_stream::EventStream;
+
+// Delay between two polls of the histories of the containers
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Clap, Debug)]
 pub struct Events {
@@ -15,48 +30,173 @@ pub struct Events {
     /// Display the container stats only once
     #[clap(long)]
     pub stats: bool,
+    /// Display the lifecycle events of the container which have been recorded
+    /// so far, e.g. when it has been started or stopped, and exit
+    #[clap(long)]
+    pub history: bool,
+    /// Publish the lifecycle events of all containers as lines of JSON to the
+    /// clients which connect to the unix socket, until youki is stopped
+    #[clap(long)]
+    pub socket: Option<PathBuf>,
     /// Name of the container instance
-    pub container_id: String,
+    #[clap(required_unless_present = "socket")]
+    pub container_id: Option<String>,
 }
 
 impl Events {
     pub fn exec(&self, root_path: PathBuf) -> Result<()> {
-        let container_dir = root_path.join(&self.container_id);
+        if let Some(socket) = &self.socket {
+            return publish_events(socket, root_path);
+        }
+
+        let container_id = self
+            .container_id
+            .as_deref()
+            .context("no container id given")?;
+        let container_dir = root_path.join(container_id);
         if !container_dir.exists() {
             log::debug!("{:?}", container_dir);
-            bail!("{} doesn't exist.", self.container_id)
+            bail!("{} doesn't exist.", container_id)
         }
 
         let container = Container::load(container_dir)?.refresh_status()?;
+        if self.history {
+            for entry in container.history()? {
+                println!("{}", serde_json::to_string(&entry)?);
+            }
+            return Ok(());
+        }
+
         if !container.state.status.eq(&ContainerStatus::Running) {
-            bail!("{} is not in running state", self.container_id);
+            bail!("{} is not in running state", container_id);
         }
 
+        let use_systemd = container
+            .systemd()
+            .context("Could not determine cgroup manager")?;
         let cgroups_path = utils::get_cgroup_path(
             &container
                 .spec()?
                 .linux
                 .context("no linux in spec")?
                 .cgroups_path,
-            &self.container_id,
+            container_id,
+            use_systemd,
         );
-        let use_systemd = container
-            .systemd()
-            .context("Could not determine cgroup manager")?;
 
-        let cgroup_manager = common::create_cgroup_manager(cgroups_path, use_systemd)?;
-        match self.stats {
-            true => {
-                let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
+        let cgroup_manager = common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            use_systemd,
+        )?;
+        if self.stats {
+            let stats = cgroup_manager.stats()?;
+            return print_event(Event::stats(container_id, stats));
+        }
+
+        // OOM events can not be watched without the memory controller
+        let mut oom_events = match cgroup_manager.notify_on_oom() {
+            Ok(receiver) => Some(receiver),
+            Err(err) => {
+                log::warn!("OOM events of the container are not reported: {:?}", err);
+                None
             }
-            false => loop {
+        };
+
+        let interval = Duration::from_secs(self.interval as u64);
+        let mut next_stats = Instant::now();
+        loop {
+            if Instant::now() >= next_stats {
                 let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
-                thread::sleep(Duration::from_secs(self.interval as u64));
-            },
+                print_event(Event::stats(container_id, stats))?;
+                next_stats += interval;
+            }
+
+            let timeout = next_stats.saturating_duration_since(Instant::now());
+            let received = oom_events
+                .as_ref()
+                .map(|events| events.recv_timeout(timeout));
+            match received {
+                Some(Ok(())) => {
+                    container.record_event(HistoryEntry::new(LifecycleEvent::Oom));
+                    print_event(Event::oom(container_id))?
+                }
+                Some(Err(RecvTimeoutError::Timeout)) => {}
+                // the cgroup has been removed
+                Some(Err(RecvTimeoutError::Disconnected)) => oom_events = None,
+                None => thread::sleep(timeout),
+            }
         }
+    }
+}
+
+// Clients are served from a single thread, which polls the histories of the
+// containers in between
+fn publish_events(socket: &Path, root_path: PathBuf) -> Result<()> {
+    if socket.exists() {
+        // left behind by a previous publisher
+        fs::remove_file(socket).with_context(|| format!("failed to remove {:?}", socket))?;
+    }
+    let listener =
+        UnixListener::bind(socket).with_context(|| format!("failed to bind {:?}", socket))?;
+    listener.set_nonblocking(true)?;
+
+    let mut stream = EventStream::new(root_path)?;
+    let mut clients: Vec<UnixStream> = Vec::new();
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((client, _)) => {
+                    // a client which does not read its events is dropped
+                    // instead of blocking the others
+                    client.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+                    clients.push(client);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err).context("failed to accept client"),
+            }
+        }
+
+        for event in stream.poll()? {
+            let mut line = serde_json::to_string(&event)?;
+            line.push('\n');
+            clients.retain(|mut client| client.write_all(line.as_bytes()).is_ok());
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn print_event(event: Event) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&event)?);
+    Ok(())
+}
+
+/// Event of a container in the same format as the events of runc, e.g.
+/// `{"type":"oom","id":"<container id>"}`
+#[derive(Serialize)]
+struct Event<'a> {
+    #[serde(rename = "type")]
+    typ: &'a str,
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Stats>,
+}
 
-        Ok(())
+impl<'a> Event<'a> {
+    fn stats(id: &'a str, stats: Stats) -> Self {
+        Self {
+            typ: "stats",
+            id,
+            data: Some(stats),
+        }
+    }
+
+    fn oom(id: &'a str) -> Self {
+        Self {
+            typ: "oom",
+            id,
+            data: None,
+        }
     }
 }
@@ -1,14 +1,17 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Clap;
 use std::{error::Error, path::PathBuf};
 
-use crate::container::builder::ContainerBuilder;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::supervisor::{self, Supervisor};
+use libcontainer::tty::{self, ConsoleReceiver};
 
 #[derive(Clap, Debug)]
 pub struct Exec {
     /// Unix socket (file) path , which will receive file descriptor of the writing end of the pseudoterminal
     #[clap(long)]
     pub console_socket: Option<PathBuf>,
+    /// Allocate a pseudo terminal for the process
     #[clap(short, long)]
     pub tty: bool,
     #[clap(long)]
@@ -38,9 +41,27 @@ pub struct Exec {
 
 impl Exec {
     pub fn exec(&self, root_path: PathBuf) -> Result<()> {
-        ContainerBuilder::new(self.container_id.clone())
+        // without a console socket the pseudo terminal of the process is
+        // connected to the terminal youki runs in
+        let console = if self.tty && self.console_socket.is_none() {
+            if self.detach {
+                bail!("a console socket is required to detach from a process with a terminal");
+            }
+            // the process is reparented to youki once the intermediate
+            // process exits, so youki can wait for it
+            supervisor::set_subreaper()?;
+            Some(ConsoleReceiver::new()?)
+        } else {
+            None
+        };
+        let console_socket = console
+            .as_ref()
+            .map(|c| c.path())
+            .or_else(|| self.console_socket.clone());
+
+        let pid = ContainerBuilder::new(self.container_id.clone())
             .with_root_path(root_path)
-            .with_console_socket(self.console_socket.as_ref())
+            .with_console_socket(console_socket.as_ref())
             .with_pid_file(self.pid_file.as_ref())
             .as_tenant()
             .with_cwd(self.cwd.as_ref())
@@ -49,7 +70,20 @@ impl Exec {
             .with_no_new_privs(self.no_new_privs)
             .with_process(self.process.as_ref())
             .with_container_args(self.command.clone())
-            .build()
+            .with_tty(self.tty)
+            .build()?;
+
+        if let Some(console) = console {
+            let master = console.receive(pid)?;
+            // the signals have to be blocked before the threads of the
+            // console proxy are spawned
+            let mask = supervisor::block_signals()?;
+            tty::proxy_console(master)?;
+            let code = Supervisor::new(pid, mask).wait()?;
+            std::process::exit(code);
+        }
+
+        Ok(())
     }
 }
 
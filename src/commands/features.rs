@@ -0,0 +1,27 @@
+//! Contains functionality of the features command
+use anyhow::Result;
+use clap::Clap;
+
+use libcontainer::features::FEATURE_GATES;
+
+/// Show which optional kernel features are supported by the host
+#[derive(Clap, Debug)]
+pub struct Features {}
+
+impl Features {
+    pub fn exec(&self) -> Result<()> {
+        for gate in FEATURE_GATES {
+            if gate.is_supported() {
+                println!("{:<18}supported", gate.to_string());
+            } else {
+                println!(
+                    "{:<18}unsupported ({})",
+                    gate.to_string(),
+                    gate.degradation()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 //! Contains functions related to printing information about system running Youki
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use clap::Clap;
@@ -7,17 +10,20 @@ use procfs::{CpuInfo, Meminfo};
 
 use cgroups;
 
+use libcontainer::nested::NestedEnvironment;
+
 #[derive(Clap, Debug)]
 pub struct Info {}
 
 impl Info {
-    pub fn exec(&self) -> Result<()> {
+    pub fn exec(&self, cgroup_root: Option<PathBuf>) -> Result<()> {
         print_youki();
         print_kernel();
         print_os();
         print_hardware();
         print_cgroups();
         print_namespaces();
+        print_nested(cgroup_root.as_deref());
 
         Ok(())
     }
@@ -123,6 +129,18 @@ pub fn print_cgroups() {
         }
     }
 
+    if let Ok(named_mounts) = cgroups::v1::util::list_named_mount_points() {
+        let mut named_mounts: Vec<String> = named_mounts
+            .iter()
+            .map(|kv| format!("  {:<16}{}", format!("name={}", kv.0), kv.1.display()))
+            .collect();
+
+        named_mounts.sort();
+        for cgroup_mount in named_mounts {
+            println!("{}", cgroup_mount);
+        }
+    }
+
     let unified = cgroups::v2::util::get_unified_mount_point();
     if let Ok(mount_point) = unified {
         println!("  {:<16}{}", "unified", mount_point.display());
@@ -156,6 +174,20 @@ pub fn print_namespaces() {
     }
 }
 
+/// Print whether youki runs inside of another container and how it is limited
+/// by it, with the cgroups mounted at the given cgroup root
+pub fn print_nested(cgroup_root: Option<&Path>) {
+    let nested = NestedEnvironment::detect();
+    match nested.container.as_ref() {
+        Some(container) => println!("{:<18}{}", "Nested", container),
+        None => println!("{:<18}no", "Nested"),
+    }
+
+    for (name, limitation) in nested.limitations(cgroup_root) {
+        println!("  {:<16}{}", name, limitation);
+    }
+}
+
 fn print_feature_status(config: &str, feature: &str, display: &str) {
     if let Some(status_flag) = find_parameter(config, feature) {
         let status = if status_flag == "y" {
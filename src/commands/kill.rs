@@ -3,16 +3,16 @@ use std::{fs, path::PathBuf};
 
 use anyhow::{bail, Result};
 use clap::Clap;
-use nix::sys::signal as nix_signal;
 
-use crate::{
-    container::{Container, ContainerStatus},
-    signal::ToSignal,
-};
+use libcontainer::{container::Container, signal::ToSignal};
 
 #[derive(Clap, Debug)]
 pub struct Kill {
-    container_id: String,
+    /// Send the signal to all processes of the container, not only to its
+    /// init process
+    #[clap(short, long)]
+    all: bool,
+    pub container_id: String,
     signal: String,
 }
 
@@ -27,21 +27,13 @@ impl Kill {
             bail!("{} doesn't exist.", self.container_id)
         }
 
-        // load container state from json file, and check status of the container
-        // it might be possible that kill is invoked on a already stopped container etc.
-        let container = Container::load(container_root)?.refresh_status()?;
-        if container.can_kill() {
-            let sig = self.signal.to_signal()?;
-            log::debug!("kill signal {} to {}", sig, container.pid().unwrap());
-            nix_signal::kill(container.pid().unwrap(), sig)?;
-            container.update_status(ContainerStatus::Stopped).save()?;
-            std::process::exit(0)
+        let sig = self.signal.to_signal()?;
+        let container = Container::load(container_root)?;
+        if self.all {
+            container.kill_all(sig)?;
         } else {
-            bail!(
-                "{} could not be killed because it was {:?}",
-                container.id(),
-                container.status()
-            )
+            container.kill(sig)?;
         }
+        std::process::exit(0)
     }
 }
@@ -10,7 +10,7 @@ use chrono::{DateTime, Local};
 use clap::Clap;
 use tabwriter::TabWriter;
 
-use crate::container::{state::State, Container};
+use libcontainer::container::{state::State, Container};
 
 /// Empty struct for list command
 #[derive(Clap, Debug)]
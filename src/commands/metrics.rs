@@ -0,0 +1,152 @@
+//! Exposes the resource usage of the containers to Prometheus
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Clap;
+
+use libcontainer::metrics;
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+// Scrapes are served one after the other, so a client which stops sending or
+// reading must not block the others for longer than this
+const STREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Print the metrics of all containers in the Prometheus text format or serve
+/// them over http
+#[derive(Clap, Debug)]
+pub struct Metrics {
+    /// Address to serve the metrics on at /metrics, e.g. 127.0.0.1:9100.
+    /// Without an address the metrics are printed once.
+    #[clap(long)]
+    pub listen: Option<String>,
+}
+
+impl Metrics {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        let listen = match &self.listen {
+            Some(listen) => listen,
+            None => {
+                print!("{}", metrics::collect(&root_path)?);
+                return Ok(());
+            }
+        };
+
+        let listener =
+            TcpListener::bind(listen).with_context(|| format!("failed to listen on {}", listen))?;
+        log::info!("serving metrics on {}", listen);
+        // scrapes are rare, so they are handled one after the other
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| serve(stream, &root_path, STREAM_TIMEOUT));
+            if let Err(err) = result {
+                log::warn!("failed to serve metrics: {:?}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn serve(stream: TcpStream, root_path: &Path, timeout: Duration) -> Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are of no interest, but are read so the client does not
+    // see a reset connection
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => match metrics::collect(root_path) {
+            Ok(body) => response("200 OK", CONTENT_TYPE, &body),
+            Err(err) => response(
+                "500 Internal Server Error",
+                "text/plain",
+                &format!("{:?}\n", err),
+            ),
+        },
+        (Some("GET"), _) => response("404 Not Found", "text/plain", "not found\n"),
+        _ => response(
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        ),
+    };
+
+    write_response(&stream, &response)
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn write_response(mut stream: &TcpStream, response: &str) -> io::Result<()> {
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcontainer::utils::create_temp_dir;
+    use std::io::Read;
+    use std::thread;
+
+    fn request(root_path: PathBuf, request: &str) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = thread::spawn(move || -> Result<()> {
+            let (stream, _) = listener.accept()?;
+            serve(stream, &root_path, STREAM_TIMEOUT)
+        });
+
+        let mut client = TcpStream::connect(addr)?;
+        client.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        client.read_to_string(&mut response)?;
+        server.join().unwrap()?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let root_path = create_temp_dir("test_serve_metrics")?;
+        let response = request(
+            root_path.to_path_buf(),
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE youki_container_cpu_usage_seconds_total counter\n"));
+
+        let response = request(root_path.to_path_buf(), "GET / HTTP/1.1\r\n\r\n")?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_timeout() -> Result<()> {
+        let root_path = create_temp_dir("test_serve_metrics_timeout")?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        // the client never sends a request
+        let _client = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        assert!(serve(stream, &root_path, Duration::from_millis(100)).is_err());
+        Ok(())
+    }
+}
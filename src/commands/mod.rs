@@ -2,13 +2,17 @@ pub mod create;
 pub mod delete;
 pub mod events;
 pub mod exec;
+pub mod features;
 pub mod info;
 pub mod kill;
 pub mod list;
+pub mod metrics;
 pub mod pause;
 pub mod ps;
 pub mod resume;
 pub mod run;
+pub mod self_test;
 pub mod spec_json;
 pub mod start;
 pub mod state;
+pub mod update;
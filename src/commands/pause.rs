@@ -5,10 +5,13 @@ use std::path::PathBuf;
 use anyhow::{bail, Context, Result};
 use clap::Clap;
 
-use crate::container::Container;
-use crate::container::ContainerStatus;
-use crate::utils;
 use cgroups;
+use libcontainer::container::history::{HistoryEntry, LifecycleEvent};
+use libcontainer::container::Container;
+use libcontainer::container::ContainerAction;
+use libcontainer::container::ContainerLock;
+use libcontainer::container::ContainerStatus;
+use libcontainer::utils;
 use oci_spec::FreezerState;
 
 /// Structure to implement pause command
@@ -33,29 +36,30 @@ impl Pause {
         }
 
         // populate data in a container structure from its file
+        let _lock = ContainerLock::acquire(&container_root)?;
         let container = Container::load(container_root)?.refresh_status()?;
         // check if a container is pauseable :
         // for example, a stopped container cannot be paused
-        if !container.can_pause() {
-            bail!(
-                "{} could not be paused because it was {:?}",
-                self.container_id,
-                container.status()
-            );
-        }
+        container.check(ContainerAction::Pause)?;
 
         let spec = container.spec()?;
         let cgroups_path = utils::get_cgroup_path(
             &spec.linux.context("no linux in spec")?.cgroups_path,
             &self.container_id,
+            systemd_cgroup,
         );
         // create cgroup manager structure from the config at the path
-        let cmanager = cgroups::common::create_cgroup_manager(cgroups_path, systemd_cgroup)?;
+        let cmanager = cgroups::common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            systemd_cgroup,
+        )?;
         // freeze the container
         cmanager.freeze(FreezerState::Frozen)?;
 
         log::debug!("saving paused status");
-        container.update_status(ContainerStatus::Paused).save()?;
+        container.transition(ContainerStatus::Paused)?.save()?;
+        container.record_event(HistoryEntry::new(LifecycleEvent::Paused));
 
         log::debug!("container {} paused", self.container_id);
         Ok(())
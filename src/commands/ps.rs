@@ -1,7 +1,7 @@
-use crate::{container::Container, utils};
 use anyhow::{bail, Context, Result};
 use cgroups;
 use clap::{self, Clap};
+use libcontainer::{container::Container, utils};
 use std::{path::PathBuf, process::Command};
 
 /// display the processes inside a container
@@ -10,6 +10,10 @@ pub struct Ps {
     /// format to display processes: table or json (default: "table")
     #[clap(short, long, default_value = "table")]
     format: String,
+    /// display the threads of the processes, the thread ids are matched
+    /// against the LWP column of ps
+    #[clap(short = 'T', long)]
+    threads: bool,
     pub container_id: String,
     /// options will be passed to the ps utility
     #[clap(setting = clap::ArgSettings::Last)]
@@ -25,26 +29,39 @@ impl Ps {
         if container.root.exists() {
             let config_absolute_path = container.root.join("config.json");
             log::debug!("load spec from {:?}", config_absolute_path);
-            let spec = oci_spec::Spec::load(config_absolute_path)?;
+            let spec = utils::load_spec(config_absolute_path)?;
             log::debug!("spec: {:?}", spec);
+            let systemd_cgroup = container
+                .systemd()
+                .context("could not determine cgroup manager")?;
             let cgroups_path = utils::get_cgroup_path(
                 &spec.linux.context("no linux in spec")?.cgroups_path,
                 container.id(),
+                systemd_cgroup,
             );
-            let systemd_cgroup = container
-                .systemd()
-                .context("could not determine cgroup manager")?;
-            let cmanager = cgroups::common::create_cgroup_manager(cgroups_path, systemd_cgroup)?;
-            let pids: Vec<i32> = cmanager
-                .get_all_pids()?
-                .iter()
-                .map(|pid| pid.as_raw())
-                .collect();
+            let cmanager = cgroups::common::create_cgroup_manager_with_root(
+                container.cgroup_root(),
+                cgroups_path,
+                systemd_cgroup,
+            )?;
+            if !cmanager.exists() {
+                bail!("cgroup of container {} does not exist", container.id());
+            }
+            let pids = if self.threads {
+                cmanager.get_all_threads()?
+            } else {
+                cmanager.get_all_pids()?
+            };
+            let pids: Vec<i32> = pids.iter().map(|pid| pid.as_raw()).collect();
 
             if self.format == "json" {
                 println!("{}", serde_json::to_string(&pids)?);
             } else if self.format == "table" {
-                let default_ps_options = vec![String::from("-ef")];
+                let default_ps_options = if self.threads {
+                    vec![String::from("-eLf")]
+                } else {
+                    vec![String::from("-ef")]
+                };
                 let ps_options = if self.ps_options.is_empty() {
                     &default_ps_options
                 } else {
@@ -56,7 +73,8 @@ impl Ps {
                 } else {
                     let lines = std::str::from_utf8(&output.stdout)?;
                     let lines: Vec<&str> = lines.split('\n').collect();
-                    let pid_index = get_pid_index(lines[0])?;
+                    let column = if self.threads { "LWP" } else { "PID" };
+                    let pid_index = get_column_index(lines[0], column)?;
                     println!("{}", &lines[0]);
                     for line in &lines[1..] {
                         if line.is_empty() {
@@ -75,13 +93,13 @@ impl Ps {
     }
 }
 
-fn get_pid_index(title: &str) -> Result<usize> {
+fn get_column_index(title: &str, column: &str) -> Result<usize> {
     let titles = title.split_whitespace();
 
     for (index, name) in titles.enumerate() {
-        if name == "PID" {
+        if name == column {
             return Ok(index);
         }
     }
-    bail!("could't find PID field in ps output");
+    bail!("could't find {} field in ps output", column);
 }
@@ -5,10 +5,13 @@ use std::path::PathBuf;
 use anyhow::{bail, Context, Result};
 use clap::Clap;
 
-use crate::container::Container;
-use crate::container::ContainerStatus;
-use crate::utils;
 use cgroups;
+use libcontainer::container::history::{HistoryEntry, LifecycleEvent};
+use libcontainer::container::Container;
+use libcontainer::container::ContainerAction;
+use libcontainer::container::ContainerLock;
+use libcontainer::container::ContainerStatus;
+use libcontainer::utils;
 use oci_spec::FreezerState;
 
 /// Structure to implement resume command
@@ -31,29 +34,30 @@ impl Resume {
             bail!("{} doesn't exist.", self.container_id)
         }
 
+        let _lock = ContainerLock::acquire(&container_root)?;
         let container = Container::load(container_root)?.refresh_status()?;
         // check if container can be resumed :
         // for example, a running process cannot be resumed
-        if !container.can_resume() {
-            bail!(
-                "{} could not be resumed because it was {:?}",
-                self.container_id,
-                container.status()
-            );
-        }
+        container.check(ContainerAction::Resume)?;
 
         let spec = container.spec()?;
         let cgroups_path = utils::get_cgroup_path(
             &spec.linux.context("no linux in spec")?.cgroups_path,
             &self.container_id,
+            systemd_cgroup,
         );
         // create cgroup manager structure from the config at the path
-        let cmanager = cgroups::common::create_cgroup_manager(cgroups_path, systemd_cgroup)?;
+        let cmanager = cgroups::common::create_cgroup_manager_with_root(
+            container.cgroup_root(),
+            cgroups_path,
+            systemd_cgroup,
+        )?;
         // resume the frozen container
         cmanager.freeze(FreezerState::Thawed)?;
 
         log::debug!("saving running status");
-        container.update_status(ContainerStatus::Running).save()?;
+        container.transition(ContainerStatus::Running)?.save()?;
+        container.record_event(HistoryEntry::new(LifecycleEvent::Resumed));
 
         log::debug!("container {} resumed", self.container_id);
         Ok(())
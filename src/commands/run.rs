@@ -1,9 +1,14 @@
+use std::io;
 use std::path::PathBuf;
+use std::time::Instant;
 
 use crate::commands::create::Create;
 use crate::commands::start::Start;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Clap;
+use libcontainer::container::Container;
+use libcontainer::supervisor::{self, Supervisor};
+use libcontainer::trace;
 /// Create and start a container.
 /// a shortcut for create followed by start.
 #[derive(Clap, Debug)]
@@ -21,22 +26,100 @@ pub struct Run {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     preserve_fds: i32,
+    /// Do not use pivot_root to enter the root filesystem, which is required when running from a ramdisk
+    #[clap(long)]
+    no_pivot: bool,
+    /// Fail instead of skipping the resource limits which can not be applied,
+    /// because the host does not support them or the cgroup of a rootless
+    /// container is not delegated to the user
+    #[clap(long)]
+    strict_resources: bool,
+    /// Change the parts of the spec which an unprivileged user can not set
+    /// up, e.g. device rules or unmapped mount options, instead of failing
+    /// to create a rootless container
+    #[clap(long)]
+    rootless_fixups: bool,
+    /// Do not map the subordinate ids of the user from /etc/subuid and
+    /// /etc/subgid into a rootless container whose spec has no mappings
+    #[clap(long)]
+    no_subid_mappings: bool,
+    /// Binary which sets up the network of a container with a network
+    /// namespace before it is started, e.g. with slirp4netns. It is called
+    /// with "setup <container id> <pid> <netns path>" and with
+    /// "cleanup <container id> <pid>" when the container is deleted.
+    #[clap(long)]
+    network_setup: Option<PathBuf>,
+    /// Print the time spent in the phases of the creation and the start of
+    /// the container, e.g. in setting up the namespaces or the mounts, to
+    /// stderr
+    #[clap(long)]
+    profile_start: bool,
+    /// Stay in the foreground until the container exits, reap the processes
+    /// orphaned by it and exit with the exit code of the container
+    #[clap(short, long)]
+    foreground: bool,
     /// name of the container instance to be started
     pub container_id: String,
 }
 
 impl Run {
-    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    pub fn exec(
+        &self,
+        root_path: PathBuf,
+        systemd_cgroup: bool,
+        cgroup_root: Option<PathBuf>,
+    ) -> Result<()> {
+        // the container init process is reparented to youki when the
+        // intermediate process exits, so this has to happen before it is forked
+        if self.foreground {
+            supervisor::set_subreaper()?;
+        }
+
+        let started = Instant::now();
+        if self.profile_start {
+            trace::enable_profile()?;
+        }
+
         Create::new(
             self.container_id.clone(),
             self.pid_file.clone(),
             self.bundle.clone(),
             self.console_socket.clone(),
             self.preserve_fds,
+            self.no_pivot,
+            self.strict_resources,
         )
-        .exec(root_path.clone(), systemd_cgroup)?;
+        .with_rootless_fixups(self.rootless_fixups)
+        .with_no_subid_mappings(self.no_subid_mappings)
+        .with_network_setup(self.network_setup.clone())
+        .exec(root_path.clone(), systemd_cgroup, cgroup_root)?;
+
+        if !self.foreground {
+            self.start(root_path, started)?;
+            return Ok(());
+        }
 
+        let container = Container::load(root_path.join(&self.container_id))?;
+        let init_pid = container.pid().context("no pid in container state")?;
+        // blocked once the container is started, so neither the container
+        // processes nor the poststart hooks inherit the blocked signals. A
+        // child which exits in the meantime is reaped by the supervisor.
+        self.start(root_path.clone(), started)?;
+        let mask = supervisor::block_signals()?;
+
+        let code = Supervisor::new(init_pid, mask).wait()?;
+        container.delete(false)?;
+        std::process::exit(code);
+    }
+
+    fn start(&self, root_path: PathBuf, started: Instant) -> Result<()> {
+        let phase = trace::phase("start");
         Start::new(self.container_id.clone()).exec(root_path)?;
+        drop(phase);
+
+        if self.profile_start {
+            trace::write_profile(io::stderr(), &trace::profile()?, started.elapsed())?;
+        }
 
         Ok(())
     }
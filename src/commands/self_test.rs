@@ -0,0 +1,374 @@
+//! Contains functionality of the self-test command, which checks if the host
+//! is able to run containers. Besides checking the individual building blocks,
+//! a throwaway container is run end to end. Its root filesystem only contains
+//! a tiny static binary which is generated on the fly, so the test neither
+//! depends on an image nor on the network.
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, ForkResult};
+use oci_spec::{LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Spec};
+
+use libcontainer::rootless::rootless_required;
+
+const SELF_TEST_ID: &str = "youki-self-test";
+const SELF_TEST_BINARY: &str = "self-test";
+// Printed by the generated binary, if it was executed in the container
+const SELF_TEST_OUTPUT: &[u8] = b"youki self-test ok\n";
+
+const SECCOMP_GET_ACTION_AVAIL: libc::c_uint = 2;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+/// Check if the host is set up to run containers and print a readiness report
+#[derive(Clap, Debug)]
+pub struct SelfTest {
+    /// Keep the bundle of the test container for inspection
+    #[clap(long)]
+    keep: bool,
+}
+
+impl SelfTest {
+    pub fn exec(&self, systemd_cgroup: bool) -> Result<()> {
+        let rootless = rootless_required();
+        println!(
+            "{:<18}{}",
+            "Mode",
+            if rootless { "rootless" } else { "root" }
+        );
+
+        let mut checks: Vec<(&str, Result<()>)> = vec![
+            ("namespaces", check_namespaces(rootless)),
+            ("cgroups", check_cgroups(rootless)),
+            ("seccomp", check_seccomp()),
+        ];
+        if rootless {
+            checks.push(("rootless", check_rootless()));
+        }
+        checks.push(("container", self.check_container(rootless, systemd_cgroup)));
+
+        let mut failed = 0;
+        for (name, result) in &checks {
+            match result {
+                Ok(_) => println!("{:<18}pass", name),
+                Err(err) => {
+                    failed += 1;
+                    println!("{:<18}fail ({:#})", name, err);
+                }
+            }
+        }
+
+        if failed > 0 {
+            bail!("{} of {} checks failed", failed, checks.len());
+        }
+
+        println!("youki is ready to run containers on this host");
+        Ok(())
+    }
+
+    fn check_container(&self, rootless: bool, systemd_cgroup: bool) -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("{}-{}", SELF_TEST_ID, unistd::getpid()));
+        let root = dir.join("state");
+        let bundle = dir.join("bundle");
+        create_bundle(&bundle, rootless)?;
+
+        let youki = std::env::current_exe().context("failed to find the youki binary")?;
+        let mut command = Command::new(&youki);
+        command.arg("--root").arg(&root);
+        if systemd_cgroup {
+            command.arg("--systemd-cgroup");
+        }
+        // the container inherits stdout, so this only returns after the
+        // container process has exited
+        let output = command
+            .arg("run")
+            .arg("--bundle")
+            .arg(&bundle)
+            .arg(SELF_TEST_ID)
+            .output()
+            .context("failed to execute youki run")?;
+
+        let _ = Command::new(&youki)
+            .arg("--root")
+            .arg(&root)
+            .arg("delete")
+            .arg(SELF_TEST_ID)
+            .output();
+
+        if self.keep {
+            println!("{:<18}{}", "Bundle", bundle.display());
+        } else {
+            let _ = fs::remove_dir_all(&dir);
+        }
+
+        if !output.status.success() {
+            bail!(
+                "youki run failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        if output.stdout != SELF_TEST_OUTPUT {
+            bail!(
+                "unexpected output of the container: {:?}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn check_namespaces(rootless: bool) -> Result<()> {
+    let mut flags = CloneFlags::CLONE_NEWNS
+        | CloneFlags::CLONE_NEWUTS
+        | CloneFlags::CLONE_NEWIPC
+        | CloneFlags::CLONE_NEWPID
+        | CloneFlags::CLONE_NEWNET;
+    if rootless {
+        flags |= CloneFlags::CLONE_NEWUSER;
+    }
+
+    // the namespaces are created in a child, so youki itself is not affected
+    match unsafe { unistd::fork()? } {
+        ForkResult::Child => {
+            let code = match unshare(flags) {
+                Ok(_) => 0,
+                Err(err) => err as i32,
+            };
+            unsafe { libc::_exit(code) };
+        }
+        ForkResult::Parent { child } => match waitpid(child, None)? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            WaitStatus::Exited(_, errno) => bail!(
+                "failed to unshare namespaces: {}",
+                nix::errno::Errno::from_i32(errno)
+            ),
+            status => bail!("namespace check ended unexpectedly: {:?}", status),
+        },
+    }
+}
+
+fn check_cgroups(rootless: bool) -> Result<()> {
+    let setup = cgroups::common::get_cgroup_setup().context("no cgroup filesystem found")?;
+    let root = cgroups::common::detect_cgroup_root()?;
+    if rootless {
+        // resource limits are not applied for rootless containers, so it is
+        // enough that the cgroup filesystem exists
+        log::debug!("cgroup setup {} found at {:?}", setup, root);
+        return Ok(());
+    }
+
+    unistd::access(&root, unistd::AccessFlags::W_OK)
+        .with_context(|| format!("cgroup root {:?} is not writable", root))?;
+    Ok(())
+}
+
+fn check_seccomp() -> Result<()> {
+    let res = unsafe { libc::prctl(libc::PR_GET_SECCOMP) };
+    if let Err(err) = nix::errno::Errno::result(res) {
+        bail!("kernel does not support seccomp: {}", err);
+    }
+
+    let action = SECCOMP_RET_ERRNO;
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_GET_ACTION_AVAIL,
+            0,
+            &action as *const u32,
+        )
+    };
+    if let Err(err) = nix::errno::Errno::result(res) {
+        bail!("kernel does not support seccomp filters: {}", err);
+    }
+
+    Ok(())
+}
+
+fn check_rootless() -> Result<()> {
+    if let Ok(clone) = fs::read_to_string("/proc/sys/kernel/unprivileged_userns_clone") {
+        if clone.trim() == "0" {
+            bail!("unprivileged user namespaces are disabled by kernel.unprivileged_userns_clone");
+        }
+    }
+
+    if let Ok(max) = fs::read_to_string("/proc/sys/user/max_user_namespaces") {
+        if max.trim() == "0" {
+            bail!("user namespaces are disabled by user.max_user_namespaces");
+        }
+    }
+
+    Ok(())
+}
+
+fn create_bundle(bundle: &Path, rootless: bool) -> Result<()> {
+    let rootfs = bundle.join("rootfs");
+    for dir in &["proc", "dev", "sys", "tmp"] {
+        fs::create_dir_all(rootfs.join(dir))?;
+    }
+
+    let binary = rootfs.join(SELF_TEST_BINARY);
+    fs::write(&binary, generate_binary()?)?;
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o755))?;
+
+    let mut spec = Spec::default();
+    let process = spec.process.as_mut().context("no process in spec")?;
+    process.terminal = Some(false);
+    process.args = Some(vec![format!("/{}", SELF_TEST_BINARY)]);
+    let root = spec.root.as_mut().context("no root in spec")?;
+    root.path = PathBuf::from("rootfs");
+    root.readonly = Some(true);
+    spec.hostname = Some(SELF_TEST_ID.to_owned());
+
+    if rootless {
+        make_rootless(&mut spec)?;
+    }
+
+    spec.save(bundle.join("config.json"))?;
+    Ok(())
+}
+
+// Maps the invoking user to root in a new user namespace and removes what an
+// unprivileged user is not allowed to set up
+fn make_rootless(spec: &mut Spec) -> Result<()> {
+    let linux = spec.linux.as_mut().context("no linux in spec")?;
+    let namespaces = linux.namespaces.get_or_insert_with(Vec::new);
+    namespaces.retain(|ns| ns.typ != LinuxNamespaceType::Network);
+    namespaces.push(LinuxNamespace {
+        typ: LinuxNamespaceType::User,
+        path: None,
+    });
+    linux.uid_mappings = Some(vec![LinuxIdMapping {
+        container_id: 0,
+        host_id: unistd::getuid().as_raw(),
+        size: 1,
+    }]);
+    linux.gid_mappings = Some(vec![LinuxIdMapping {
+        container_id: 0,
+        host_id: unistd::getgid().as_raw(),
+        size: 1,
+    }]);
+    linux.resources = None;
+
+    if let Some(mounts) = spec.mounts.as_mut() {
+        for mount in mounts.iter_mut() {
+            if let Some(options) = mount.options.as_mut() {
+                // the groups are not mapped into the user namespace
+                options.retain(|o| !o.starts_with("gid="));
+            }
+
+            // sysfs can only be mounted together with a new network namespace
+            if mount.typ.as_deref() == Some("sysfs") {
+                mount.typ = Some("none".to_owned());
+                mount.source = Some(PathBuf::from("/sys"));
+                mount.options = Some(
+                    vec!["rbind", "nosuid", "noexec", "nodev", "ro"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a static ELF executable for the host architecture, which
+/// prints the expected output and exits
+fn generate_binary() -> Result<Vec<u8>> {
+    let len = SELF_TEST_OUTPUT.len() as u32;
+    let (machine, code): (u16, Vec<u8>) = match std::env::consts::ARCH {
+        "x86_64" => {
+            let mut code = Vec::new();
+            // mov eax, 1 (write); mov edi, 1 (stdout)
+            code.extend_from_slice(&[0xb8, 0x01, 0x00, 0x00, 0x00]);
+            code.extend_from_slice(&[0xbf, 0x01, 0x00, 0x00, 0x00]);
+            // lea rsi, [rip + 16], the message after the code
+            code.extend_from_slice(&[0x48, 0x8d, 0x35, 0x10, 0x00, 0x00, 0x00]);
+            // mov edx, len; syscall
+            code.push(0xba);
+            code.extend_from_slice(&len.to_le_bytes());
+            code.extend_from_slice(&[0x0f, 0x05]);
+            // mov eax, 60 (exit); xor edi, edi; syscall
+            code.extend_from_slice(&[0xb8, 0x3c, 0x00, 0x00, 0x00]);
+            code.extend_from_slice(&[0x31, 0xff]);
+            code.extend_from_slice(&[0x0f, 0x05]);
+            (62, code)
+        }
+        "aarch64" => {
+            let instructions: [u32; 8] = [
+                0xd280_0020,              // mov x0, #1 (stdout)
+                0x1000_00e1,              // adr x1, #28, the message after the code
+                0xd280_0002 | (len << 5), // mov x2, len
+                0xd280_0808,              // mov x8, #64 (write)
+                0xd400_0001,              // svc #0
+                0xd280_0000,              // mov x0, #0
+                0xd280_0ba8,              // mov x8, #93 (exit)
+                0xd400_0001,              // svc #0
+            ];
+            let code = instructions.iter().flat_map(|i| i.to_le_bytes()).collect();
+            (183, code)
+        }
+        arch => bail!("no self-test binary for architecture {}", arch),
+    };
+
+    const BASE: u64 = 0x40_0000;
+    const HEADER_SIZE: u64 = 64 + 56;
+    let size = HEADER_SIZE + code.len() as u64 + SELF_TEST_OUTPUT.len() as u64;
+
+    let mut elf = Vec::with_capacity(size as usize);
+    // ELF header of a 64 bit little endian executable
+    elf.extend_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    elf.extend_from_slice(&[0u8; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    elf.extend_from_slice(&machine.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&(BASE + HEADER_SIZE).to_le_bytes()); // e_entry
+    elf.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    elf.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    elf.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    elf.extend_from_slice(&[0u8; 6]); // no section headers
+                                      // a single loadable segment containing the whole file
+    elf.extend_from_slice(&1u32.to_le_bytes()); // p_type: PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // p_flags: PF_R | PF_X
+    elf.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    elf.extend_from_slice(&BASE.to_le_bytes()); // p_vaddr
+    elf.extend_from_slice(&BASE.to_le_bytes()); // p_paddr
+    elf.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    elf.extend_from_slice(&size.to_le_bytes()); // p_memsz
+    elf.extend_from_slice(&0x1000u64.to_le_bytes()); // p_align
+    elf.extend_from_slice(&code);
+    elf.extend_from_slice(SELF_TEST_OUTPUT);
+
+    Ok(elf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcontainer::utils::create_temp_dir;
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_generated_binary() -> Result<()> {
+        let dir = create_temp_dir("test_generated_binary")?;
+        let binary = dir.join(SELF_TEST_BINARY);
+        fs::write(&binary, generate_binary()?)?;
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755))?;
+
+        let output = Command::new(&binary).output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, SELF_TEST_OUTPUT);
+        Ok(())
+    }
+}
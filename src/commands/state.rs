@@ -4,7 +4,7 @@ use std::path::PathBuf;
 use anyhow::Result;
 use clap::Clap;
 
-use crate::container::Container;
+use libcontainer::container::Container;
 
 #[derive(Clap, Debug)]
 pub struct State {
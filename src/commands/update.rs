@@ -0,0 +1,300 @@
+//! Updates the resource limits of a running container
+use std::fs::{self, canonicalize};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use oci_spec::{LinuxBlockIo, LinuxCpu, LinuxMemory, LinuxPids, LinuxResources, LinuxWeightDevice};
+use serde_json::Value;
+
+use cgroups::common::{CpuRampdown, ManagerOptions};
+use libcontainer::container::{Container, ContainerAction, ContainerLock};
+use libcontainer::{nested, spec_compat, utils};
+
+/// Structure to implement update command
+#[derive(Clap, Debug)]
+pub struct Update {
+    /// Read the new resource limits from a json file, or from stdin if "-" is given
+    #[clap(short, long)]
+    resources: Option<PathBuf>,
+    /// CPU shares (relative weight vs. other containers)
+    #[clap(long)]
+    cpu_shares: Option<u64>,
+    /// CPU CFS period to be used for hardcapping (in usecs)
+    #[clap(long)]
+    cpu_period: Option<u64>,
+    /// CPU CFS hardcap limit (in usecs). Allowed cpu time in a given period
+    #[clap(long)]
+    cpu_quota: Option<i64>,
+    /// CPU(s) to use
+    #[clap(long)]
+    cpuset_cpus: Option<String>,
+    /// Memory node(s) to use
+    #[clap(long)]
+    cpuset_mems: Option<String>,
+    /// Memory limit (in bytes)
+    #[clap(short, long)]
+    memory: Option<i64>,
+    /// Maximum number of pids allowed in the container
+    #[clap(long)]
+    pids_limit: Option<i64>,
+    /// Block IO weight (relative weight, 10-1000)
+    #[clap(long)]
+    blkio_weight: Option<u16>,
+    /// Block IO weight of a device, e.g. /dev/sda:300 (relative weight, 10-1000)
+    #[clap(long, parse(try_from_str = parse_weight_device), number_of_values = 1)]
+    blkio_weight_device: Vec<(PathBuf, u16)>,
+    /// Lower the cpu quota gradually over the given number of milliseconds
+    /// instead of at once, so the container is not throttled abruptly
+    #[clap(long)]
+    cpu_rampdown: Option<u64>,
+    /// Number of steps in which the cpu quota is lowered
+    #[clap(long, default_value = "10")]
+    cpu_rampdown_steps: u32,
+    pub container_id: String,
+}
+
+impl Update {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        log::debug!("start updating container {}", self.container_id);
+        let root_path = canonicalize(root_path)?;
+        let container_root = root_path.join(&self.container_id);
+        if !container_root.exists() {
+            bail!("{} doesn't exist.", self.container_id)
+        }
+
+        let _lock = ContainerLock::acquire(&container_root)?;
+        let container = Container::load(container_root)?.refresh_status()?;
+        container.check(ContainerAction::Update)?;
+
+        let resources = self.resources()?;
+        let spec = container.spec()?;
+        let linux = spec.linux.as_ref().context("no linux in spec")?;
+        // restrictions which are restored if the update fails
+        let previous = container
+            .resources()
+            .or_else(|| linux.resources.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let use_systemd = container
+            .systemd()
+            .context("could not determine cgroup manager")?;
+        let cgroups_path =
+            utils::get_cgroup_path(&linux.cgroups_path, &self.container_id, use_systemd);
+        let options = ManagerOptions {
+            skip_devices: nested::skip_devices(spec.annotations.as_ref())?,
+            ..Default::default()
+        };
+        let cmanager = cgroups::common::create_cgroup_manager_with_options(
+            container.cgroup_root(),
+            cgroups_path,
+            use_systemd,
+            options,
+        )?;
+
+        if let (Some(rampdown), Some(cpu)) = (self.cpu_rampdown, resources.cpu.as_ref()) {
+            let rampdown = CpuRampdown {
+                duration: Duration::from_millis(rampdown),
+                steps: self.cpu_rampdown_steps,
+            };
+            cmanager
+                .ramp_down_cpu(cpu, &rampdown)
+                .context("failed to ramp down cpu quota")?;
+        }
+
+        cmanager
+            .update(&resources, &previous)
+            .context("failed to apply resource limits")?;
+
+        container
+            .set_resources(Some(merge_resources(&previous, &resources)?))
+            .save()
+            .context("failed to save the updated resource limits")?;
+        Ok(())
+    }
+
+    // Resources from the command line flags take precedence over the ones
+    // from the resources file
+    fn resources(&self) -> Result<LinuxResources> {
+        let mut resources: LinuxResources = match self.resources.as_ref() {
+            Some(path) => {
+                let content = if path.as_os_str() == "-" {
+                    let mut content = String::new();
+                    io::stdin().read_to_string(&mut content)?;
+                    content
+                } else {
+                    fs::read_to_string(path)
+                        .with_context(|| format!("failed to read resources from {:?}", path))?
+                };
+                let mut resources: serde_json::Value =
+                    serde_json::from_str(&content).context("failed to parse resources")?;
+                spec_compat::translate_resources(&mut resources);
+                serde_json::from_value(resources).context("failed to parse resources")?
+            }
+            None => LinuxResources::default(),
+        };
+
+        if self.cpu_shares.is_some()
+            || self.cpu_period.is_some()
+            || self.cpu_quota.is_some()
+            || self.cpuset_cpus.is_some()
+            || self.cpuset_mems.is_some()
+        {
+            let cpu = resources.cpu.get_or_insert_with(|| LinuxCpu {
+                shares: None,
+                quota: None,
+                period: None,
+                realtime_runtime: None,
+                realtime_period: None,
+                cpus: None,
+                mems: None,
+            });
+            cpu.shares = self.cpu_shares.or(cpu.shares);
+            cpu.period = self.cpu_period.or(cpu.period);
+            cpu.quota = self.cpu_quota.or(cpu.quota);
+            cpu.cpus = self.cpuset_cpus.clone().or_else(|| cpu.cpus.take());
+            cpu.mems = self.cpuset_mems.clone().or_else(|| cpu.mems.take());
+        }
+
+        if let Some(limit) = self.memory {
+            let memory = resources.memory.get_or_insert_with(|| LinuxMemory {
+                limit: None,
+                reservation: None,
+                swap: None,
+                kernel: None,
+                kernel_tcp: None,
+                swappiness: None,
+                disable_oom_killer: None,
+                use_hierarchy: None,
+            });
+            memory.limit = Some(limit);
+        }
+
+        if let Some(limit) = self.pids_limit {
+            resources.pids = Some(LinuxPids { limit });
+        }
+
+        if self.blkio_weight.is_some() || !self.blkio_weight_device.is_empty() {
+            let block_io = resources.block_io.get_or_insert_with(|| LinuxBlockIo {
+                weight: None,
+                leaf_weight: None,
+                weight_device: None,
+                throttle_read_bps_device: None,
+                throttle_write_bps_device: None,
+                throttle_read_iops_device: None,
+                throttle_write_iops_device: None,
+            });
+            block_io.weight = self.blkio_weight.or(block_io.weight);
+            // the devices are resolved now, as the cgroup only knows their numbers
+            for (path, weight) in &self.blkio_weight_device {
+                let (major, minor) = cgroups::common::block_device_number(path)?;
+                block_io
+                    .weight_device
+                    .get_or_insert_with(Vec::new)
+                    .push(LinuxWeightDevice {
+                        major,
+                        minor,
+                        weight: Some(*weight),
+                        leaf_weight: None,
+                    });
+            }
+        }
+
+        Ok(resources)
+    }
+}
+
+// Parses the weight of a block device, which is given as <path>:<weight>
+fn parse_weight_device(s: &str) -> Result<(PathBuf, u16), String> {
+    let (path, weight) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("invalid weight device {:?}, expected <path>:<weight>", s))?;
+    let weight = weight
+        .parse()
+        .map_err(|err| format!("invalid weight of device {:?}: {}", path, err))?;
+    Ok((PathBuf::from(path), weight))
+}
+
+// Restrictions of the update replace the previous ones field by field, the
+// previous ones are kept if the update does not set them. Lists, like the
+// device rules, are replaced as a whole.
+fn merge_resources(previous: &LinuxResources, update: &LinuxResources) -> Result<LinuxResources> {
+    let mut merged = serde_json::to_value(previous)?;
+    merge_value(&mut merged, serde_json::to_value(update)?);
+    let mut merged: LinuxResources =
+        serde_json::from_value(merged).context("failed to merge resources")?;
+    merged.disable_oom_killer = previous.disable_oom_killer;
+    // the freezer state is not a restriction, it is changed by pause and resume
+    merged.freezer = None;
+    Ok(merged)
+}
+
+fn merge_value(previous: &mut Value, update: Value) {
+    match (previous, update) {
+        (_, Value::Null) => {}
+        (Value::Object(previous), Value::Object(update)) => {
+            for (key, value) in update {
+                match previous.get_mut(&key) {
+                    Some(previous) => merge_value(previous, value),
+                    None => {
+                        previous.insert(key, value);
+                    }
+                }
+            }
+        }
+        (previous, update) => *previous = update,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::{LinuxDeviceCgroup, LinuxDeviceType};
+
+    fn cpu(shares: Option<u64>, quota: Option<i64>, period: Option<u64>) -> LinuxCpu {
+        LinuxCpu {
+            shares,
+            quota,
+            period,
+            realtime_runtime: None,
+            realtime_period: None,
+            cpus: None,
+            mems: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_resources() -> Result<()> {
+        let mut previous_cpu = cpu(Some(1024), Some(50000), Some(100000));
+        previous_cpu.cpus = Some("0-1".to_owned());
+        let previous = LinuxResources {
+            cpu: Some(previous_cpu),
+            pids: Some(LinuxPids { limit: 100 }),
+            devices: Some(vec![LinuxDeviceCgroup {
+                allow: false,
+                typ: Some(LinuxDeviceType::A),
+                major: None,
+                minor: None,
+                access: Some("rwm".to_owned()),
+            }]),
+            ..Default::default()
+        };
+        let update = LinuxResources {
+            cpu: Some(cpu(None, Some(20000), None)),
+            devices: Some(Vec::new()),
+            ..Default::default()
+        };
+
+        let merged = merge_resources(&previous, &update)?;
+        let merged_cpu = merged.cpu.unwrap();
+        assert_eq!(merged_cpu.quota, Some(20000));
+        assert_eq!(merged_cpu.shares, Some(1024));
+        assert_eq!(merged_cpu.period, Some(100000));
+        assert_eq!(merged_cpu.cpus.as_deref(), Some("0-1"));
+        assert_eq!(merged.pids.map(|pids| pids.limit), Some(100));
+        assert_eq!(merged.devices.map(|devices| devices.len()), Some(0));
+        Ok(())
+    }
+}
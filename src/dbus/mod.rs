@@ -1,2 +0,0 @@
-mod client;
-pub use client::Client;
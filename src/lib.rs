@@ -1,15 +1,3 @@
-pub mod capabilities;
 pub mod commands;
-pub mod container;
-pub mod dbus;
-pub mod hooks;
 pub mod logger;
-pub mod namespaces;
-pub mod notify_socket;
-pub mod process;
-pub mod rootfs;
-pub mod rootless;
-pub mod signal;
-pub mod syscall;
-pub mod tty;
-pub mod utils;
+pub mod state_dir;
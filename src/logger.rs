@@ -2,19 +2,25 @@
 
 use std::env;
 use std::io::{stderr, Write};
-use std::path::PathBuf;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
 use std::{
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     str::FromStr,
 };
 
-use anyhow::Result;
-use log::{LevelFilter, Log, Metadata, Record};
+use anyhow::{bail, Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
 use once_cell::sync::OnceCell;
 
 /// Public global variables to access logger and logfile
 pub static YOUKI_LOGGER: OnceCell<YoukiLogger> = OnceCell::new();
 pub static LOG_FILE: OnceCell<Option<File>> = OnceCell::new();
+/// Operation and container the log messages belong to, which are attached to
+/// every message, so that the messages of different containers can be told
+/// apart in a shared log file
+pub static LOG_CONTEXT: OnceCell<LogContext> = OnceCell::new();
 
 /// If in debug mode, default level is debug to get maximum logging
 #[cfg(debug_assertions)]
@@ -24,10 +30,102 @@ const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
 #[cfg(not(debug_assertions))]
 const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
 
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const SYSLOG_IDENTIFIER: &str = "youki";
+// Facility of the syslog messages, see RFC 5424
+const SYSLOG_FACILITY_USER: u8 = 1;
+
+/// Format of the log messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, as written by runc
+    Json,
+    /// Structured messages sent to journald, or to syslog if journald is not
+    /// running
+    Journald,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "journald" => Ok(Self::Journald),
+            _ => bail!(
+                "unknown log format {:?}, expected text, json or journald",
+                s
+            ),
+        }
+    }
+}
+
+/// Rotation of the log file, so that a log file which is used by all youki
+/// invocations on a host does not grow without bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Size in bytes at which the log file is rotated
+    pub max_size: u64,
+    /// Number of rotated files which are kept as <log>.1 to <log>.<n>, the
+    /// log file is truncated if no rotated file is kept
+    pub max_backups: usize,
+}
+
+/// Operation and container which are added to the log messages
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogContext {
+    pub operation: String,
+    pub container_id: Option<String>,
+}
+
+impl LogContext {
+    // Prefix of the text messages, e.g. "create test: "
+    fn prefix(&self) -> String {
+        match &self.container_id {
+            Some(container_id) => format!("{} {}: ", self.operation, container_id),
+            None => format!("{}: ", self.operation),
+        }
+    }
+}
+
+/// Sets the operation and container of the log messages. It should be called
+/// before the logger is initialized and only has an effect on the first call.
+pub fn set_context(operation: &str, container_id: Option<&str>) {
+    let _ = LOG_CONTEXT.set(LogContext {
+        operation: operation.to_owned(),
+        container_id: container_id.map(str::to_owned),
+    });
+}
+
 /// Initialize the logger, must be called before accessing the logger
 /// Multiple parts might call this at once, but the actual initialization
-/// is done only once due to use of OnceCell
-pub fn init(log_file: Option<PathBuf>) -> Result<()> {
+/// is done only once due to use of OnceCell. Without a format, messages are
+/// sent to the journal if stderr is connected to it, e.g. when youki runs as
+/// part of a systemd service, and written as text otherwise. The log file is
+/// rotated when it is opened, if it has grown beyond the maximum size.
+pub fn init(
+    log_file: Option<PathBuf>,
+    log_format: Option<&str>,
+    rotation: Option<LogRotation>,
+) -> Result<()> {
+    let format = match log_format {
+        Some(format) => format.parse()?,
+        None if log_file.is_none() && stderr_is_journal() => LogFormat::Journald,
+        None => LogFormat::Text,
+    };
+
+    if let (Some(log_file), Some(rotation), None) = (&log_file, rotation, LOG_FILE.get()) {
+        // the logger is not set up yet, but logging into the file is still
+        // possible if it can not be rotated
+        if let Err(err) = rotate(log_file, &rotation) {
+            eprintln!("failed to rotate log file {:?}: {:?}", log_file, err);
+        }
+    }
+
     // If file exists, ignore, else create and open the file
     let _log_file = LOG_FILE.get_or_init(|| -> Option<File> {
         // set the log level if specified in env variable or set to default
@@ -38,18 +136,18 @@ pub fn init(log_file: Option<PathBuf>) -> Result<()> {
         };
 
         // Create a new logger, or get existing if already created
-        let logger = YOUKI_LOGGER.get_or_init(|| YoukiLogger::new(level_filter.to_level()));
+        let logger = YOUKI_LOGGER.get_or_init(|| YoukiLogger::new(level_filter.to_level(), format));
 
         log::set_logger(logger)
             .map(|()| log::set_max_level(level_filter))
             .expect("set logger failed");
 
-        // Create and open log file
+        // Create and open log file, the messages of all invocations of youki
+        // are appended to it
         log_file.as_ref().map(|log_file_path| {
             OpenOptions::new()
                 .create(true)
-                .write(true)
-                .truncate(false)
+                .append(true)
                 .open(log_file_path)
                 .expect("failed opening log file ")
         })
@@ -57,16 +155,136 @@ pub fn init(log_file: Option<PathBuf>) -> Result<()> {
     Ok(())
 }
 
+// Moves the log file to <log>.1 once it has reached the maximum size, after
+// the older rotated files have been moved from <log>.<n> to <log>.<n+1>. The
+// oldest file is overwritten if the maximum number of files is kept already.
+fn rotate(log_file: &Path, rotation: &LogRotation) -> Result<()> {
+    let size = match fs::metadata(log_file) {
+        Ok(metadata) => metadata.len(),
+        // it has been rotated by another invocation of youki in the meantime
+        Err(_) => return Ok(()),
+    };
+    if size < rotation.max_size {
+        return Ok(());
+    }
+
+    if rotation.max_backups == 0 {
+        OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(log_file)
+            .with_context(|| format!("failed to truncate {:?}", log_file))?;
+        return Ok(());
+    }
+
+    for n in (1..rotation.max_backups).rev() {
+        let backup = backup_path(log_file, n);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(log_file, n + 1))
+                .with_context(|| format!("failed to rotate {:?}", backup))?;
+        }
+    }
+    fs::rename(log_file, backup_path(log_file, 1))
+        .with_context(|| format!("failed to rotate {:?}", log_file))
+}
+
+fn backup_path(log_file: &Path, n: usize) -> PathBuf {
+    let mut path = log_file.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+}
+
+/// Parses a size in bytes with an optional k, m or g suffix, e.g. 10M
+pub fn parse_size(size: &str) -> Result<u64> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid size {:?}", size))?;
+    number
+        .checked_mul(unit)
+        .with_context(|| format!("size {:?} is too large", size))
+}
+
+// systemd sets JOURNAL_STREAM to the device and inode of the stream which
+// connects stdout and stderr of a service to the journal
+fn stderr_is_journal() -> bool {
+    let journal_stream = match env::var("JOURNAL_STREAM") {
+        Ok(journal_stream) => journal_stream,
+        Err(_) => return false,
+    };
+
+    match fs::metadata("/proc/self/fd/2") {
+        Ok(stderr) => journal_stream == format!("{}:{}", stderr.dev(), stderr.ino()),
+        Err(_) => false,
+    }
+}
+
 /// Youki's custom Logger
 pub struct YoukiLogger {
     /// Indicates level up to which logs are to be printed
     level: Option<log::Level>,
+    /// Format the logs are written in
+    format: LogFormat,
+    /// Socket for the messages to the journal or to syslog
+    socket: Option<UnixDatagram>,
 }
 
 impl YoukiLogger {
     /// Create new logger
-    pub fn new(level: Option<log::Level>) -> Self {
-        Self { level }
+    pub fn new(level: Option<log::Level>, format: LogFormat) -> Self {
+        let socket = match format {
+            LogFormat::Journald => UnixDatagram::unbound().ok(),
+            _ => None,
+        };
+
+        Self {
+            level,
+            format,
+            socket,
+        }
+    }
+
+    fn text_message(record: &Record) -> String {
+        let time = chrono::Local::now().to_rfc3339();
+        text_message(record, LOG_CONTEXT.get(), &time)
+    }
+
+    fn json_message(record: &Record) -> String {
+        let time = chrono::Local::now().to_rfc3339();
+        json_message(record, LOG_CONTEXT.get(), &time)
+    }
+
+    // Sends the message to the journal, falls back to syslog and then to
+    // stderr if the message can not be sent
+    fn send(&self, record: &Record) {
+        if let Some(socket) = &self.socket {
+            let context = LOG_CONTEXT.get();
+            let message = journal_message(record, context);
+            if socket.send_to(&message, JOURNALD_SOCKET).is_ok() {
+                return;
+            }
+
+            let message = syslog_message(record, context);
+            if socket.send_to(message.as_bytes(), SYSLOG_SOCKET).is_ok() {
+                return;
+            }
+        }
+
+        self.write(&Self::text_message(record));
+    }
+
+    // if log file is set, write to it, else write to stderr
+    fn write(&self, log_msg: &str) {
+        if let Some(mut log_file) = LOG_FILE.get().unwrap().as_ref() {
+            let _ = writeln!(log_file, "{}", log_msg);
+        } else {
+            let _ = writeln!(stderr(), "{}", log_msg);
+        }
     }
 }
 
@@ -84,28 +302,10 @@ impl Log for YoukiLogger {
     /// Function to carry out logging
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let log_msg = match (record.file(), record.line()) {
-                (Some(file), Some(line)) => format!(
-                    "[{} {}:{}] {} {}\r",
-                    record.level(),
-                    file,
-                    line,
-                    chrono::Local::now().to_rfc3339(),
-                    record.args()
-                ),
-                (_, _) => format!(
-                    "[{}] {} {}\r",
-                    record.level(),
-                    chrono::Local::now().to_rfc3339(),
-                    record.args()
-                ),
-            };
-
-            // if log file is set, write to it, else write to stderr
-            if let Some(mut log_file) = LOG_FILE.get().unwrap().as_ref() {
-                let _ = writeln!(log_file, "{}", log_msg);
-            } else {
-                let _ = writeln!(stderr(), "{}", log_msg);
+            match self.format {
+                LogFormat::Text => self.write(&Self::text_message(record)),
+                LogFormat::Json => self.write(&Self::json_message(record)),
+                LogFormat::Journald => self.send(record),
             }
         }
     }
@@ -119,3 +319,263 @@ impl Log for YoukiLogger {
         }
     }
 }
+
+// Severity of syslog and the journal, see syslog(3)
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// The pid is determined for every message, as the processes which are forked
+// for the container log with the same context
+fn text_message(record: &Record, context: Option<&LogContext>, time: &str) -> String {
+    let location = match (record.file(), record.line()) {
+        (Some(file), Some(line)) => format!(" {}:{}", file, line),
+        (_, _) => String::new(),
+    };
+    format!(
+        "[{}{}] {} [{}] {}{}\r",
+        record.level(),
+        location,
+        time,
+        std::process::id(),
+        context.map(LogContext::prefix).unwrap_or_default(),
+        record.args()
+    )
+}
+
+fn json_message(record: &Record, context: Option<&LogContext>, time: &str) -> String {
+    let level = match record.level() {
+        Level::Warn => "warning".to_owned(),
+        level => level.to_string().to_lowercase(),
+    };
+    let mut message = serde_json::json!({
+        "level": level,
+        "msg": record.args().to_string(),
+        "time": time,
+        "pid": std::process::id(),
+    });
+    if let Some(context) = context {
+        message["operation"] = context.operation.clone().into();
+        if let Some(container_id) = &context.container_id {
+            message["container_id"] = container_id.clone().into();
+        }
+    }
+    message.to_string()
+}
+
+// Encodes the message in the native protocol of journald, see
+// https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+fn journal_message(record: &Record, context: Option<&LogContext>) -> Vec<u8> {
+    let mut fields = vec![
+        ("MESSAGE", record.args().to_string()),
+        ("PRIORITY", priority(record.level()).to_string()),
+        ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.to_owned()),
+    ];
+    if let Some(file) = record.file() {
+        fields.push(("CODE_FILE", file.to_owned()));
+    }
+    if let Some(line) = record.line() {
+        fields.push(("CODE_LINE", line.to_string()));
+    }
+    if let Some(context) = context {
+        fields.push(("OPERATION", context.operation.clone()));
+        if let Some(container_id) = &context.container_id {
+            fields.push(("CONTAINER_ID", container_id.clone()));
+        }
+    }
+
+    let mut message = Vec::new();
+    for (name, value) in fields {
+        message.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // values with newlines are prefixed with their length instead
+            message.push(b'\n');
+            message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            message.push(b'=');
+        }
+        message.extend_from_slice(value.as_bytes());
+        message.push(b'\n');
+    }
+
+    message
+}
+
+// Formats the message for the syslog socket, see RFC 3164
+fn syslog_message(record: &Record, context: Option<&LogContext>) -> String {
+    format!(
+        "<{}>{}[{}]: {}{}",
+        SYSLOG_FACILITY_USER * 8 + priority(record.level()),
+        SYSLOG_IDENTIFIER,
+        std::process::id(),
+        context.map(LogContext::prefix).unwrap_or_default(),
+        record.args()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> LogContext {
+        LogContext {
+            operation: "create".to_owned(),
+            container_id: Some("test".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_journal_message() {
+        let message = journal_message(
+            &Record::builder()
+                .args(format_args!("container created"))
+                .level(Level::Info)
+                .build(),
+            Some(&context()),
+        );
+        assert_eq!(
+            String::from_utf8(message).unwrap(),
+            "MESSAGE=container created\nPRIORITY=6\nSYSLOG_IDENTIFIER=youki\n\
+            OPERATION=create\nCONTAINER_ID=test\n"
+        );
+
+        let message = journal_message(
+            &Record::builder()
+                .args(format_args!("a\nb"))
+                .level(Level::Error)
+                .build(),
+            None,
+        );
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\nPRIORITY=3\nSYSLOG_IDENTIFIER=youki\n");
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_syslog_message() {
+        let message = syslog_message(
+            &Record::builder()
+                .args(format_args!("failed"))
+                .level(Level::Warn)
+                .build(),
+            Some(&context()),
+        );
+        assert_eq!(
+            message,
+            format!("<12>youki[{}]: create test: failed", std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_text_message() {
+        let record = Record::builder()
+            .args(format_args!("container created"))
+            .level(Level::Info)
+            .file(Some("src/create.rs"))
+            .line(Some(42))
+            .build();
+        assert_eq!(
+            text_message(&record, Some(&context()), "2021-01-01T00:00:00+00:00"),
+            format!(
+                "[INFO src/create.rs:42] 2021-01-01T00:00:00+00:00 [{}] create test: container created\r",
+                std::process::id()
+            )
+        );
+        assert_eq!(
+            text_message(&record, None, "2021-01-01T00:00:00+00:00"),
+            format!(
+                "[INFO src/create.rs:42] 2021-01-01T00:00:00+00:00 [{}] container created\r",
+                std::process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_message() {
+        let record = Record::builder()
+            .args(format_args!("failed"))
+            .level(Level::Warn)
+            .build();
+        let message: serde_json::Value = serde_json::from_str(&json_message(
+            &record,
+            Some(&context()),
+            "2021-01-01T00:00:00+00:00",
+        ))
+        .unwrap();
+        assert_eq!(
+            message,
+            serde_json::json!({
+                "level": "warning",
+                "msg": "failed",
+                "time": "2021-01-01T00:00:00+00:00",
+                "pid": std::process::id(),
+                "operation": "create",
+                "container_id": "test",
+            })
+        );
+    }
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let tmp = libcontainer::utils::create_temp_dir("test_rotate_log_file")?;
+        let log_file = tmp.join("youki.log");
+        let rotation = LogRotation {
+            max_size: 4,
+            max_backups: 2,
+        };
+
+        // below the maximum size the file is kept
+        fs::write(&log_file, "1")?;
+        rotate(&log_file, &rotation)?;
+        assert_eq!(fs::read_to_string(&log_file)?, "1");
+
+        for content in ["first", "second", "third"] {
+            fs::write(&log_file, content)?;
+            rotate(&log_file, &rotation)?;
+        }
+        assert!(!log_file.exists());
+        assert_eq!(fs::read_to_string(tmp.join("youki.log.1"))?, "third");
+        assert_eq!(fs::read_to_string(tmp.join("youki.log.2"))?, "second");
+        assert!(!tmp.join("youki.log.3").exists());
+
+        // without backups the file is truncated
+        fs::write(&log_file, "fourth")?;
+        rotate(
+            &log_file,
+            &LogRotation {
+                max_size: 4,
+                max_backups: 0,
+            },
+        )?;
+        assert_eq!(fs::read_to_string(&log_file)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("512")?, 512);
+        assert_eq!(parse_size("10k")?, 10 * 1024);
+        assert_eq!(parse_size("10M")?, 10 * 1024 * 1024);
+        assert_eq!(parse_size("1g")?, 1024 * 1024 * 1024);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1T").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!(
+            "journald".parse::<LogFormat>().unwrap(),
+            LogFormat::Journald
+        );
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -2,31 +2,33 @@
 //! Container Runtime written in Rust, inspired by [railcar](https://github.com/oracle/railcar)
 //! This crate provides a container runtime which can be used by a high-level container runtime to run containers.
 
-use std::fs;
 use std::path::PathBuf;
+use std::process;
 
-use anyhow::bail;
 use anyhow::Result;
 use clap::{crate_version, Clap};
+use libcontainer::error::ContainerError;
 
-use nix::sys::stat::Mode;
-use nix::unistd::getuid;
 use youki::commands::create;
 use youki::commands::delete;
 use youki::commands::events;
 use youki::commands::exec;
+use youki::commands::features;
 use youki::commands::info;
 use youki::commands::kill;
 use youki::commands::list;
+use youki::commands::metrics;
 use youki::commands::pause;
 use youki::commands::ps;
 use youki::commands::resume;
 use youki::commands::run;
+use youki::commands::self_test;
 use youki::commands::spec_json;
 use youki::commands::start;
 use youki::commands::state;
-use youki::rootless::rootless_required;
-use youki::utils::{self, create_dir_all_with_mode};
+use youki::commands::update;
+use youki::logger::LogRotation;
+use youki::state_dir;
 
 // High-level commandline option definition
 // This takes global options as well as individual commands as specified in [OCI runtime-spec](https://github.com/opencontainers/runtime-spec/blob/master/runtime.md)
@@ -39,11 +41,27 @@ struct Opts {
     root: Option<PathBuf>,
     #[clap(short, long)]
     log: Option<PathBuf>,
+    /// Format of the logs: text, json or journald. Without a log file the
+    /// logs are sent to the journal if stderr is connected to it.
     #[clap(long)]
     log_format: Option<String>,
+    /// Rotate the log file once it has reached the size, e.g. 10M
+    #[clap(long, parse(try_from_str = youki::logger::parse_size))]
+    log_max_size: Option<u64>,
+    /// Number of rotated log files which are kept
+    #[clap(long, default_value = "1")]
+    log_max_backups: usize,
     /// Enable systemd cgroup manager, rather then use the cgroupfs directly.
     #[clap(short, long)]
     systemd_cgroup: bool,
+    /// Mount point of the cgroup root, if it is not mounted at /sys/fs/cgroup.
+    /// It is recorded in the container state on creation.
+    #[clap(long)]
+    cgroup_root: Option<PathBuf>,
+    /// Append the timing of the phases of the container creation to the file
+    /// in the Chrome trace event format. Requires the trace feature.
+    #[clap(long)]
+    trace: Option<PathBuf>,
     /// command to actually manage container
     #[clap(subcommand)]
     subcmd: SubCommand,
@@ -70,6 +88,8 @@ enum SubCommand {
     #[clap(version = crate_version!(), author = "youki team")]
     Info(info::Info),
     #[clap(version = crate_version!(), author = "youki team")]
+    Features(features::Features),
+    #[clap(version = crate_version!(), author = "youki team")]
     Spec(spec_json::SpecJson),
     #[clap(version = crate_version!(), author = "youki team")]
     List(list::List),
@@ -81,75 +101,96 @@ enum SubCommand {
     Events(events::Events),
     #[clap(version = crate_version!(), author = "youki team", setting=clap::AppSettings::AllowLeadingHyphen)]
     Ps(ps::Ps),
+    #[clap(version = crate_version!(), author = "youki team")]
+    Update(update::Update),
+    #[clap(version = crate_version!(), author = "youki team")]
+    SelfTest(self_test::SelfTest),
+    #[clap(version = crate_version!(), author = "youki team")]
+    Metrics(metrics::Metrics),
 }
 
 /// This is the entry point in the container runtime. The binary is run by a high-level container runtime,
 /// with various flags passed. This parses the flags, creates and manages appropriate resources.
-fn main() -> Result<()> {
-    let opts = Opts::parse();
+fn main() {
+    if let Err(err) = try_main(Opts::parse()) {
+        eprintln!("Error: {:?}", err);
+        process::exit(exit_code(err));
+    }
+}
 
-    if let Err(e) = youki::logger::init(opts.log) {
+fn try_main(opts: Opts) -> Result<()> {
+    let (operation, container_id) = log_context(&opts.subcmd);
+    youki::logger::set_context(operation, container_id);
+    let rotation = opts.log_max_size.map(|max_size| LogRotation {
+        max_size,
+        max_backups: opts.log_max_backups,
+    });
+    if let Err(e) = youki::logger::init(opts.log, opts.log_format.as_deref(), rotation) {
         eprintln!("log init failed: {:?}", e);
     }
+    if let Some(trace_file) = &opts.trace {
+        libcontainer::trace::init(trace_file)?;
+    }
 
-    let root_path = determine_root_path(opts.root)?;
+    let root_path = state_dir::determine_root_path(opts.root)?;
     let systemd_cgroup = opts.systemd_cgroup;
 
     match opts.subcmd {
-        SubCommand::Create(create) => create.exec(root_path, systemd_cgroup),
+        SubCommand::Create(create) => create.exec(root_path, systemd_cgroup, opts.cgroup_root),
         SubCommand::Start(start) => start.exec(root_path),
-        SubCommand::Run(run) => run.exec(root_path, systemd_cgroup),
+        SubCommand::Run(run) => run.exec(root_path, systemd_cgroup, opts.cgroup_root),
         SubCommand::Exec(exec) => exec.exec(root_path),
         SubCommand::Kill(kill) => kill.exec(root_path),
-        SubCommand::Delete(delete) => delete.exec(root_path, systemd_cgroup),
+        SubCommand::Delete(delete) => delete.exec(root_path),
         SubCommand::State(state) => state.exec(root_path),
-        SubCommand::Info(info) => info.exec(),
+        SubCommand::Info(info) => info.exec(opts.cgroup_root),
+        SubCommand::Features(features) => features.exec(),
         SubCommand::List(list) => list.exec(root_path),
         SubCommand::Spec(spec) => spec.exec(),
         SubCommand::Pause(pause) => pause.exec(root_path, systemd_cgroup),
         SubCommand::Resume(resume) => resume.exec(root_path, systemd_cgroup),
         SubCommand::Events(events) => events.exec(root_path),
         SubCommand::Ps(ps) => ps.exec(root_path),
+        SubCommand::Update(update) => update.exec(root_path),
+        SubCommand::SelfTest(self_test) => self_test.exec(systemd_cgroup),
+        SubCommand::Metrics(metrics) => metrics.exec(root_path),
     }
 }
 
-fn determine_root_path(root_path: Option<PathBuf>) -> Result<PathBuf> {
-    if let Some(path) = root_path {
-        return Ok(path);
-    }
-
-    if !rootless_required() {
-        let default = PathBuf::from("/run/youki");
-        utils::create_dir_all(&default)?;
-        return Ok(default);
-    }
-
-    // see https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html
-    if let Ok(path) = std::env::var("XDG_RUNTIME_DIR") {
-        return Ok(PathBuf::from(path));
-    }
-
-    // XDG_RUNTIME_DIR is not set, try the usual location
-    let uid = getuid().as_raw();
-    let runtime_dir = PathBuf::from(format!("/run/user/{}", uid));
-    if create_dir_all_with_mode(&runtime_dir, uid, Mode::S_IRWXU).is_ok() {
-        return Ok(runtime_dir);
-    }
-
-    if let Ok(path) = std::env::var("HOME") {
-        let home = PathBuf::from(path);
-        if let Ok(resolved) = fs::canonicalize(home) {
-            let run_dir = resolved.join(".youki/run");
-            if create_dir_all_with_mode(&run_dir, uid, Mode::S_IRWXU).is_ok() {
-                return Ok(run_dir);
-            }
-        }
+// The exit code tells the caller what kind of failure happened, following
+// sysexits(3), so e.g. an invalid spec is not retried
+fn exit_code(err: anyhow::Error) -> i32 {
+    match ContainerError::from(err) {
+        // EX_CONFIG
+        ContainerError::Spec(_) => 78,
+        // EX_NOPERM
+        ContainerError::Permission(_) => 77,
+        // EX_UNAVAILABLE
+        ContainerError::KernelFeatureMissing(_) => 69,
+        ContainerError::State(_) | ContainerError::System(_) => 1,
     }
+}
 
-    let tmp_dir = PathBuf::from(format!("/tmp/youki/{}", uid));
-    if create_dir_all_with_mode(&tmp_dir, uid, Mode::S_IRWXU).is_ok() {
-        return Ok(tmp_dir);
+// Operation and container which the log messages are about
+fn log_context(subcmd: &SubCommand) -> (&'static str, Option<&str>) {
+    match subcmd {
+        SubCommand::Create(create) => ("create", Some(&create.container_id)),
+        SubCommand::Start(start) => ("start", Some(&start.container_id)),
+        SubCommand::Run(run) => ("run", Some(&run.container_id)),
+        SubCommand::Exec(exec) => ("exec", Some(&exec.container_id)),
+        SubCommand::Kill(kill) => ("kill", Some(&kill.container_id)),
+        SubCommand::Delete(delete) => ("delete", Some(&delete.container_id)),
+        SubCommand::State(state) => ("state", Some(&state.container_id)),
+        SubCommand::Info(_) => ("info", None),
+        SubCommand::Features(_) => ("features", None),
+        SubCommand::Spec(_) => ("spec", None),
+        SubCommand::List(_) => ("list", None),
+        SubCommand::Pause(pause) => ("pause", Some(&pause.container_id)),
+        SubCommand::Resume(resume) => ("resume", Some(&resume.container_id)),
+        SubCommand::Events(events) => ("events", events.container_id.as_deref()),
+        SubCommand::Ps(ps) => ("ps", Some(&ps.container_id)),
+        SubCommand::Update(update) => ("update", Some(&update.container_id)),
+        SubCommand::SelfTest(_) => ("self-test", None),
+        SubCommand::Metrics(_) => ("metrics", None),
     }
-
-    bail!("could not find a storage location with suitable permissions for the current user");
 }
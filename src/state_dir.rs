@@ -0,0 +1,210 @@
+//! Location of the state of the containers. The state directory contains the
+//! state files, sockets and fifos of all containers of a user, so it has to
+//! be private to the user: a directory which others can write to would allow
+//! them to tamper with the containers, e.g. by replacing the exec fifo.
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::geteuid;
+
+use libcontainer::rootless::rootless_required;
+
+const DEFAULT_ROOT_PATH: &str = "/run/youki";
+// Directory which is created below the runtime directory of the user
+const STATE_DIR_NAME: &str = "youki";
+const PRIVATE_MODE: u32 = 0o700;
+
+/// Determines the directory in which the state of the containers is kept. An
+/// explicitly given directory is used as it is, as long as it is owned by the
+/// user and not world-writable. Otherwise a private directory of youki is
+/// created in the first suitable location:
+/// - /run/youki for root
+/// - $XDG_RUNTIME_DIR/youki
+/// - /run/user/<uid>/youki
+/// - $HOME/.youki/run
+/// - /tmp/youki-<uid>
+pub fn determine_root_path(root_path: Option<PathBuf>) -> Result<PathBuf> {
+    let uid = geteuid().as_raw();
+    if let Some(path) = root_path {
+        if path.exists() {
+            audit_dir(&path, uid, false)
+                .with_context(|| format!("refusing to use root directory {:?}", path))?;
+        }
+        return Ok(path);
+    }
+
+    if !rootless_required() {
+        let default = PathBuf::from(DEFAULT_ROOT_PATH);
+        create_state_dir(&default, uid)?;
+        return Ok(default);
+    }
+
+    let mut problems = Vec::new();
+    for (base, state_dir) in candidates(uid) {
+        // the base directory is shared with other applications of the user
+        let result = match &base {
+            Some(base) => audit_dir(base, uid, false),
+            None => Ok(()),
+        }
+        .and_then(|_| create_state_dir(&state_dir, uid));
+        match result {
+            Ok(()) => {
+                log::debug!("use state directory {:?}", state_dir);
+                return Ok(state_dir);
+            }
+            Err(err) => {
+                log::debug!("state directory {:?} is not suitable: {:?}", state_dir, err);
+                problems.push(format!("{:?}: {:#}", state_dir, err));
+            }
+        }
+    }
+
+    bail!(
+        "could not find a storage location with suitable permissions for the current user:\n  - {}",
+        problems.join("\n  - ")
+    );
+}
+
+// Locations of the state directory for an unprivileged user, together with
+// the existing directory of the user it is created in, if any
+fn candidates(uid: u32) -> Vec<(Option<PathBuf>, PathBuf)> {
+    let mut candidates = Vec::new();
+    // see https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        let runtime_dir = PathBuf::from(runtime_dir);
+        candidates.push((Some(runtime_dir.clone()), runtime_dir.join(STATE_DIR_NAME)));
+    }
+
+    // XDG_RUNTIME_DIR is not set, try the usual location
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", uid));
+    if runtime_dir.exists() {
+        candidates.push((Some(runtime_dir.clone()), runtime_dir.join(STATE_DIR_NAME)));
+    }
+
+    if let Some(home) = std::env::var_os("HOME").and_then(|h| fs::canonicalize(h).ok()) {
+        candidates.push((Some(home.clone()), home.join(".youki/run")));
+    }
+
+    // /tmp is world-writable, so the directory itself must belong to the user
+    candidates.push((None, PathBuf::from(format!("/tmp/youki-{}", uid))));
+    candidates
+}
+
+// Creates the state directory if it does not exist yet and makes sure only
+// the user has access to it
+fn create_state_dir(path: &Path, uid: u32) -> Result<()> {
+    if !path.exists() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(PRIVATE_MODE)
+            .create(path)
+            .with_context(|| format!("failed to create directory {:?}", path))?;
+    }
+
+    if let Err(err) = audit_dir(path, uid, true) {
+        // permissions which are only too broad are tightened, e.g. for a
+        // directory created by an older version of youki
+        let metadata = fs::symlink_metadata(path)?;
+        let mode = metadata.mode() & 0o777;
+        if !metadata.is_dir() || metadata.uid() != uid || mode & 0o002 != 0 {
+            return Err(err);
+        }
+
+        log::warn!(
+            "tighten permissions of state directory {:?} from {:o} to {:o}",
+            path,
+            mode,
+            PRIVATE_MODE
+        );
+        fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_MODE))
+            .with_context(|| format!("failed to change permissions of {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the directory is owned by the user and can not be written to
+/// by others. A private directory must not be accessible by others at all
+/// and must not be a symlink, which could be replaced by someone else.
+pub fn audit_dir(path: &Path, uid: u32, private: bool) -> Result<()> {
+    let metadata = if private {
+        fs::symlink_metadata(path)
+    } else {
+        fs::metadata(path)
+    }
+    .with_context(|| format!("failed to get metadata for {:?}", path))?;
+
+    if !metadata.is_dir() {
+        bail!("{:?} is not a directory", path);
+    }
+    if metadata.uid() != uid {
+        bail!(
+            "{:?} is owned by uid {} instead of uid {}",
+            path,
+            metadata.uid(),
+            uid
+        );
+    }
+
+    let mode = metadata.mode() & 0o777;
+    if mode & 0o002 != 0 {
+        bail!("{:?} is world-writable (mode {:o})", path, mode);
+    }
+    if private && mode & 0o077 != 0 {
+        bail!(
+            "{:?} is accessible by other users (mode {:o}, expected {:o})",
+            path,
+            mode,
+            PRIVATE_MODE
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcontainer::utils::create_temp_dir;
+
+    #[test]
+    fn test_audit_dir() -> Result<()> {
+        let tmp = create_temp_dir("test_audit_dir")?;
+        let uid = geteuid().as_raw();
+        let dir = tmp.join("state");
+        fs::create_dir(&dir)?;
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        audit_dir(&dir, uid, true)?;
+        assert!(audit_dir(&dir, uid + 1, false).is_err());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        audit_dir(&dir, uid, false)?;
+        assert!(audit_dir(&dir, uid, true).is_err());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777))?;
+        assert!(audit_dir(&dir, uid, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_state_dir() -> Result<()> {
+        let tmp = create_temp_dir("test_create_state_dir")?;
+        let uid = geteuid().as_raw();
+        let dir = tmp.join("run/youki");
+        create_state_dir(&dir, uid)?;
+        assert_eq!(fs::metadata(&dir)?.mode() & 0o777, PRIVATE_MODE);
+
+        // too broad permissions are tightened
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        create_state_dir(&dir, uid)?;
+        assert_eq!(fs::metadata(&dir)?.mode() & 0o777, PRIVATE_MODE);
+
+        // but a world-writable directory is refused
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777))?;
+        assert!(create_state_dir(&dir, uid).is_err());
+        Ok(())
+    }
+}
@@ -10,6 +10,7 @@ use crate::support::cleanup_test;
 use crate::support::get_project_path;
 use crate::support::initialize_test;
 use crate::support::set_runtime_path;
+use crate::tests::conmon::ConmonCompat;
 use crate::tests::lifecycle::{ContainerCreate, ContainerLifecycle};
 
 #[derive(Clap, Debug)]
@@ -51,9 +52,11 @@ fn main() -> Result<()> {
 
     let cl = ContainerLifecycle::new(&project_path);
     let cc = ContainerCreate::new(&project_path);
+    let conmon = ConmonCompat::new(&project_path);
 
     tm.add_test_group(&cl);
     tm.add_test_group(&cc);
+    tm.add_test_group(&conmon);
 
     if initialize_test(&project_path).is_err() {
         bail!("Can not initilize test.")
@@ -0,0 +1,350 @@
+//! Tests of the conventions of conmon, through which CRI-O runs containers.
+//! conmon listens on a console socket and expects the pty master of a
+//! container with a terminal to be sent before create returns, and reads the
+//! pid file once create has returned. If conmon is installed, it monitors a
+//! container of youki and has to write the exit file and create the attach
+//! socket. CRI-O itself is not run, and the streams of a terminal are not
+//! attached through the attach socket.
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::symlink;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::sys::{socket, uio};
+use nix::unistd::{self, Pid};
+use test_framework::{TestResult, TestableGroup};
+
+use crate::support::{generate_uuid, get_runtime_path};
+
+// Time in which conmon has to react to a change of the container
+const CONMON_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct ConmonCompat {
+    project_path: PathBuf,
+}
+
+impl ConmonCompat {
+    pub fn new(project_path: &Path) -> Self {
+        ConmonCompat {
+            project_path: project_path.to_owned(),
+        }
+    }
+
+    fn workspace(&self) -> PathBuf {
+        self.project_path.join("integration-workspace")
+    }
+
+    fn youki(&self) -> Command {
+        let mut cmd = Command::new(get_runtime_path());
+        cmd.stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .arg("--root")
+            .arg(self.workspace().join("youki"));
+        cmd
+    }
+
+    // conmon passes the pid file relative to its working directory
+    fn pid_file(&self) -> TestResult {
+        let id = generate_uuid().to_string();
+        let pid_file = format!("{}.pid", id);
+        let result = self
+            .youki()
+            .current_dir(self.workspace())
+            .arg("create")
+            .arg("--pid-file")
+            .arg(&pid_file)
+            .arg("--bundle")
+            .arg(self.workspace().join("bundle"))
+            .arg(&id)
+            .output()
+            .map_err(anyhow::Error::from)
+            .and_then(check_success)
+            .and_then(|_| {
+                let pid_file = self.workspace().join(&pid_file);
+                let pid = fs::read_to_string(&pid_file)
+                    .with_context(|| format!("pid file {:?} has not been written", pid_file))?;
+                let state = self.youki().arg("state").arg(&id).output()?;
+                let state = String::from_utf8(check_success(state)?.stdout)?;
+                if !state.contains(&format!(r#""pid": {}"#, pid)) {
+                    bail!("pid {} of pid file is not the pid in {}", pid, state);
+                }
+                Ok(())
+            });
+        let _ = fs::remove_file(self.workspace().join(&pid_file));
+        self.remove(&id);
+        result.into()
+    }
+
+    // conmon receives the terminal once create has returned
+    fn console_socket(&self) -> TestResult {
+        let id = generate_uuid().to_string();
+        let socket_path = self.workspace().join(format!("{}.sock", id));
+        let result = self.terminal_bundle().and_then(|bundle| {
+            let listener = UnixListener::bind(&socket_path)?;
+            listener.set_nonblocking(true)?;
+            let output = self
+                .youki()
+                .arg("create")
+                .arg("--console-socket")
+                .arg(&socket_path)
+                .arg("--bundle")
+                .arg(&bundle)
+                .arg(&id)
+                .output()?;
+            check_success(output)?;
+
+            let (stream, _) = listener.accept().map_err(|err| match err.kind() {
+                ErrorKind::WouldBlock => {
+                    anyhow!("no terminal has been sent before create returned")
+                }
+                _ => err.into(),
+            })?;
+            let master = receive_fd(stream.as_raw_fd())?;
+            if !unistd::isatty(master)? {
+                bail!("received fd {} is not a terminal", master);
+            }
+            unistd::close(master)?;
+            Ok(())
+        });
+        let _ = fs::remove_file(&socket_path);
+        self.remove(&id);
+        result.into()
+    }
+
+    // create fails instead of leaving conmon waiting for the terminal
+    fn missing_console_socket(&self) -> TestResult {
+        let id = generate_uuid().to_string();
+        let result = self.terminal_bundle().and_then(|bundle| {
+            let output = self
+                .youki()
+                .arg("create")
+                .arg("--console-socket")
+                .arg(self.workspace().join("missing.sock"))
+                .arg("--bundle")
+                .arg(&bundle)
+                .arg(&id)
+                .output()?;
+            if output.status.success() {
+                bail!("container has been created without console socket");
+            }
+            Ok(())
+        });
+        self.remove(&id);
+        result.into()
+    }
+
+    // CRI-O stops the processes of a container with kill --all
+    fn kill_all(&self) -> TestResult {
+        let id = generate_uuid().to_string();
+        let result = self
+            .youki()
+            .arg("create")
+            .arg("--no-new-keyring")
+            .arg("--bundle")
+            .arg(self.workspace().join("bundle"))
+            .arg(&id)
+            .output()
+            .map_err(anyhow::Error::from)
+            .and_then(check_success)
+            .and_then(|_| {
+                let output = self.youki().args(["kill", "--all", &id, "9"]).output()?;
+                check_success(output)
+            });
+        self.remove(&id);
+        result.into()
+    }
+
+    // conmon creates the container through youki, CRI-O starts and kills it
+    // and reads the exit code from the exit file written by conmon
+    fn conmon_exit_file(&self) -> TestResult {
+        let conmon = match find_conmon() {
+            Some(conmon) => conmon,
+            None => return TestResult::Skip,
+        };
+        let id = generate_uuid().to_string();
+        let dir = self.workspace().join(format!("conmon-{}", id));
+        let result = fs::create_dir_all(dir.join("exits"))
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                let status = Command::new(&conmon)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .arg("--cid")
+                    .arg(&id)
+                    .arg("--cuuid")
+                    .arg(&id)
+                    .arg("--name")
+                    .arg(&id)
+                    .arg("--runtime")
+                    .arg(get_runtime_path())
+                    .arg("--runtime-arg")
+                    .arg("--root")
+                    .arg("--runtime-arg")
+                    .arg(self.workspace().join("youki"))
+                    .arg("--bundle")
+                    .arg(self.workspace().join("bundle"))
+                    .arg("--container-pidfile")
+                    .arg(dir.join("pid"))
+                    .arg("--conmon-pidfile")
+                    .arg(dir.join("conmon.pid"))
+                    .arg("--exit-dir")
+                    .arg(dir.join("exits"))
+                    .arg("--socket-dir-path")
+                    .arg(&dir)
+                    .arg("--log-path")
+                    .arg(dir.join("container.log"))
+                    .status()
+                    .with_context(|| format!("failed to run {:?}", conmon))?;
+                if !status.success() {
+                    bail!("conmon exited with {}", status);
+                }
+
+                wait_for_file(&dir.join("pid"))?;
+                let attach = dir.join(&id).join("attach");
+                if !attach.exists() {
+                    bail!("conmon has not created the attach socket {:?}", attach);
+                }
+
+                check_success(self.youki().arg("start").arg(&id).output()?)?;
+                check_success(self.youki().args(["kill", &id, "9"]).output()?)?;
+                let exit_file = dir.join("exits").join(&id);
+                wait_for_file(&exit_file)?;
+                // the content of the exit file may not be complete yet
+                let deadline = Instant::now() + CONMON_TIMEOUT;
+                loop {
+                    let exit_code = fs::read_to_string(&exit_file)?;
+                    if exit_code.trim() == "137" {
+                        return Ok(());
+                    }
+                    if Instant::now() > deadline {
+                        bail!("exit file contains {:?} instead of 137", exit_code);
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            });
+        self.remove(&id);
+        // conmon exits with the container, unless the test has failed before
+        if result.is_err() {
+            if let Ok(pid) = fs::read_to_string(dir.join("conmon.pid")) {
+                if let Ok(pid) = pid.trim().parse() {
+                    let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+        result.into()
+    }
+
+    // The bundle of the tests shares the rootfs with the default bundle, but
+    // its process asks for a terminal
+    fn terminal_bundle(&self) -> Result<PathBuf> {
+        let bundle = self.workspace().join("bundle-terminal");
+        if bundle.exists() {
+            return Ok(bundle);
+        }
+
+        fs::create_dir_all(&bundle)?;
+        let config = fs::read_to_string(self.workspace().join("bundle").join("config.json"))?;
+        let config = config.replace(r#""terminal": false"#, r#""terminal": true"#);
+        fs::write(bundle.join("config.json"), config)?;
+        symlink(
+            self.workspace().join("bundle").join("rootfs"),
+            bundle.join("rootfs"),
+        )?;
+        Ok(bundle)
+    }
+
+    fn remove(&self, id: &str) {
+        let _ = self.youki().args(["delete", "--force", id]).output();
+    }
+}
+
+fn check_success(output: Output) -> Result<Output> {
+    if !output.status.success() {
+        bail!(
+            "youki exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output)
+}
+
+// conmon is searched like the shell would, the test is skipped without it
+fn find_conmon() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join("conmon"))
+        .find(|conmon| conmon.is_file())
+}
+
+fn wait_for_file(path: &Path) -> Result<()> {
+    let deadline = Instant::now() + CONMON_TIMEOUT;
+    while !path.exists() {
+        if Instant::now() > deadline {
+            bail!("{:?} has not been written by conmon", path);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+fn receive_fd(socket_fd: RawFd) -> Result<RawFd> {
+    let mut buf = [0u8; 4096];
+    let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+    let msg = socket::recvmsg(socket_fd, &iov, Some(&mut cmsg), socket::MsgFlags::empty())?;
+    for cmsg in msg.cmsgs() {
+        if let socket::ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(fd) = fds.first() {
+                return Ok(*fd);
+            }
+        }
+    }
+    bail!("no fd has been sent over the console socket")
+}
+
+impl TestableGroup for ConmonCompat {
+    fn get_name(&self) -> String {
+        "conmon".to_owned()
+    }
+    fn run_all(&self) -> Vec<(String, TestResult)> {
+        vec![
+            ("pid_file".to_owned(), self.pid_file()),
+            ("console_socket".to_owned(), self.console_socket()),
+            (
+                "missing_console_socket".to_owned(),
+                self.missing_console_socket(),
+            ),
+            ("kill_all".to_owned(), self.kill_all()),
+            ("conmon_exit_file".to_owned(), self.conmon_exit_file()),
+        ]
+    }
+    fn run_selected(&self, selected: &[&str]) -> Vec<(String, TestResult)> {
+        let mut ret = Vec::new();
+        for name in selected {
+            match *name {
+                "pid_file" => ret.push(("pid_file".to_owned(), self.pid_file())),
+                "console_socket" => ret.push(("console_socket".to_owned(), self.console_socket())),
+                "missing_console_socket" => ret.push((
+                    "missing_console_socket".to_owned(),
+                    self.missing_console_socket(),
+                )),
+                "kill_all" => ret.push(("kill_all".to_owned(), self.kill_all())),
+                "conmon_exit_file" => {
+                    ret.push(("conmon_exit_file".to_owned(), self.conmon_exit_file()))
+                }
+                _ => eprintln!("No test named {} in conmon", name),
+            };
+        }
+        ret
+    }
+}
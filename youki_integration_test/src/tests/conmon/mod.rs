@@ -0,0 +1,2 @@
+mod conmon_compat;
+pub use conmon_compat::ConmonCompat;
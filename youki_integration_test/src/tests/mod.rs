@@ -1 +1,2 @@
+pub mod conmon;
 pub mod lifecycle;